@@ -0,0 +1,342 @@
+use {
+    super::{
+        errors::BlockError,
+        header::{BlockHash, BlockHeader},
+        merkle::merkle_root,
+    },
+    crate::{
+        script::{
+            opcodes::{OP_1, OP_16},
+            raw::{decode_num, Instruction},
+        },
+        transaction::tx::{Transaction, WITNESS_SCALE_FACTOR},
+        utils::{
+            encoding::{compact_size, read_compact_size},
+            errors::EncodingError,
+            hash::hash256,
+        },
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        io::Read,
+        str::FromStr,
+    },
+};
+
+/// Largest block weight allowed by BIP141
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// Most signature operations allowed in a block, in weight units
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+/// `OP_RETURN` push of 36 bytes starting with `0xaa21a9ed`, which a witness commitment output
+/// script starts with
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Block of transactions, the first of which is the coinbase
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// Parse a serialised block, whose transactions may have witness data
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let header = BlockHeader::parse(reader)?;
+        let count = read_compact_size(reader)?;
+        let transactions = (0..count)
+            .map(|_| Transaction::parse(reader))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            header,
+            transactions,
+        })
+    }
+
+    /// Parse a block that must span all of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let block = Self::parse(&mut reader)?;
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(block)
+    }
+
+    /// Serialise the block, with the witness data of its transactions
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = self.header.serialise();
+        bytes.extend(compact_size(self.transactions.len() as u64));
+        for tx in &self.transactions {
+            bytes.extend(tx.serialise());
+        }
+
+        bytes
+    }
+
+    /// Serialise the block without witness data, as seen by nodes that predate segwit
+    pub fn serialise_without_witness(&self) -> Vec<u8> {
+        let mut bytes = self.header.serialise();
+        bytes.extend(compact_size(self.transactions.len() as u64));
+        for tx in &self.transactions {
+            bytes.extend(tx.serialise_without_witness());
+        }
+
+        bytes
+    }
+
+    /// Hash identifying the block
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+
+    /// Serialised size in bytes, including witness data
+    pub fn size(&self) -> usize {
+        self.serialise().len()
+    }
+
+    /// Serialised size in bytes without witness data
+    pub fn stripped_size(&self) -> usize {
+        self.serialise_without_witness().len()
+    }
+
+    /// Weight as defined by BIP141, witness bytes counting a quarter of other bytes
+    pub fn weight(&self) -> usize {
+        self.stripped_size() * (WITNESS_SCALE_FACTOR - 1) + self.size()
+    }
+
+    /// Signature operations in the script sigs and output scripts, in weight units
+    ///
+    /// P2SH redeem scripts and witness scripts also count towards `MAX_BLOCK_SIGOPS_COST`, but
+    /// need the outputs being spent
+    pub fn legacy_sigop_cost(&self) -> usize {
+        let sigops = self
+            .transactions
+            .iter()
+            .map(|tx| {
+                let inputs = tx
+                    .inputs
+                    .iter()
+                    .map(|input| input.script_sig.sigop_count(false));
+                let outputs = tx
+                    .outputs
+                    .iter()
+                    .map(|output| output.script_pubkey.sigop_count(false));
+                inputs.chain(outputs).sum::<usize>()
+            })
+            .sum::<usize>();
+
+        sigops * WITNESS_SCALE_FACTOR
+    }
+
+    /// Merkle root of the txids and whether the tree is mutated, see `merkle_root()`
+    pub fn compute_merkle_root(&self) -> ([u8; 32], bool) {
+        let txids = self
+            .transactions
+            .iter()
+            .map(Transaction::txid)
+            .collect::<Vec<_>>();
+        merkle_root(&txids)
+    }
+
+    /// Merkle root of the wtxids, the coinbase counting as zero since its wtxid cannot commit to
+    /// itself
+    pub fn compute_witness_root(&self) -> [u8; 32] {
+        let wtxids = self
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| if i == 0 { [0; 32] } else { tx.wtxid() })
+            .collect::<Vec<_>>();
+        merkle_root(&wtxids).0
+    }
+
+    /// Check the block has exactly one coinbase, first, and the header commits to the transactions
+    pub fn check_merkle_root(&self) -> Result<()> {
+        let mut transactions = self.transactions.iter();
+        if !transactions.next().is_some_and(Transaction::is_coinbase)
+            || transactions.any(Transaction::is_coinbase)
+        {
+            bail!(BlockError::BadCoinbase);
+        }
+
+        let (root, mutated) = self.compute_merkle_root();
+        if root != self.header.merkle_root {
+            bail!(BlockError::BadMerkleRoot);
+        }
+        if mutated {
+            bail!(BlockError::MutatedMerkleTree);
+        }
+
+        Ok(())
+    }
+
+    /// Witness commitment of the coinbase, from the last output starting with
+    /// `WITNESS_COMMITMENT_HEADER`
+    pub fn witness_commitment(&self) -> Option<[u8; 32]> {
+        self.transactions
+            .first()?
+            .outputs
+            .iter()
+            .rev()
+            .map(|output| output.script_pubkey.as_bytes())
+            .find(|script| script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_HEADER))
+            .map(|script| script[6..38].try_into().unwrap())
+    }
+
+    /// Check the witness data as required by BIP141
+    ///
+    /// A block with a witness commitment must commit to the witness root and the reserved value in
+    /// the coinbase witness. A block without one must not have witness data.
+    pub fn check_witness_commitment(&self) -> Result<()> {
+        let Some(commitment) = self.witness_commitment() else {
+            if self.transactions.iter().any(Transaction::has_witness) {
+                bail!(BlockError::UnexpectedWitness);
+            }
+            return Ok(());
+        };
+
+        let reserved_value = match self.transactions[0].inputs.as_slice() {
+            [input] => match input.witness.as_slice() {
+                [value] if value.len() == 32 => value,
+                _ => bail!(BlockError::BadWitnessNonce),
+            },
+            _ => bail!(BlockError::BadWitnessNonce),
+        };
+        if hash256(&[self.compute_witness_root().as_slice(), reserved_value].concat()) != commitment
+        {
+            bail!(BlockError::BadWitnessCommitment);
+        }
+
+        Ok(())
+    }
+
+    /// Height the coinbase script sig starts with, as required by BIP34 from block version 2
+    pub fn bip34_height(&self) -> Result<u32> {
+        let script_sig = self
+            .transactions
+            .first()
+            .and_then(|tx| tx.inputs.first())
+            .map(|input| &input.script_sig);
+        let height = match script_sig.and_then(|script| script.instructions().next()) {
+            Some(Ok(Instruction::Op(op @ OP_1..=OP_16))) => (op - OP_1 + 1) as i64,
+            Some(Ok(Instruction::Push(bytes))) => decode_num(bytes, 5)?,
+            _ => bail!(BlockError::MissingHeight),
+        };
+
+        u32::try_from(height).map_err(|_| BlockError::MissingHeight.into())
+    }
+}
+
+impl Display for Block {
+    /// Format the serialised block as hex
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialise()))
+    }
+}
+
+impl FromStr for Block {
+    type Err = anyhow::Error;
+
+    /// Parse a hex serialised block
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_bytes(&hex::decode(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    /// Mainnet genesis block
+    const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    /// Mainnet block 702861, a segwit block of 1.4 MB
+    const BLOCK_702861: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/mainnet_block_702861.raw"
+    ));
+
+    #[test]
+    fn genesis_block() -> Result<()> {
+        let block: Block = GENESIS.parse()?;
+
+        assert_eq!(block.to_string(), GENESIS);
+        assert_eq!(block.transactions.len(), 1);
+        assert_eq!(block.header.merkle_root, block.transactions[0].txid());
+        block.check_merkle_root()?;
+        block.check_witness_commitment()?;
+        assert_eq!(block.size(), 285);
+        assert_eq!(block.weight(), 285 * 4);
+        assert_eq!(block.legacy_sigop_cost(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_mainnet_block() -> Result<()> {
+        let block = Block::from_bytes(BLOCK_702861)?;
+
+        assert_eq!(block.serialise(), BLOCK_702861);
+        assert_eq!(
+            reversed_hex(&block.hash()),
+            "000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae"
+        );
+        block.header.check_pow()?;
+        block.check_merkle_root()?;
+        block.check_witness_commitment()?;
+        assert!(block.witness_commitment().is_some());
+        assert_eq!(block.bip34_height()?, 702861);
+        assert_eq!(block.size(), BLOCK_702861.len());
+        assert!(block.stripped_size() < block.size());
+        assert!(block.weight() <= MAX_BLOCK_WEIGHT);
+        assert!(block.legacy_sigop_cost() <= MAX_BLOCK_SIGOPS_COST);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_blocks() -> Result<()> {
+        let mut block = Block::from_bytes(BLOCK_702861)?;
+        block.transactions.truncate(3);
+        block.header.merkle_root = block.compute_merkle_root().0;
+        block.check_merkle_root()?;
+
+        // CVE-2012-2459, the duplicated transaction keeps the merkle root
+        let mut mutated = block.clone();
+        mutated.transactions.push(block.transactions[2].clone());
+        assert_eq!(mutated.compute_merkle_root().0, block.header.merkle_root);
+        assert_eq!(
+            mutated.check_merkle_root().unwrap_err().downcast_ref(),
+            Some(&BlockError::MutatedMerkleTree)
+        );
+
+        let mut swapped = block.clone();
+        swapped.transactions.swap(1, 2);
+        assert_eq!(
+            swapped.check_merkle_root().unwrap_err().downcast_ref(),
+            Some(&BlockError::BadMerkleRoot)
+        );
+
+        // the witness root no longer matches the commitment
+        assert_eq!(
+            block.check_witness_commitment().unwrap_err().downcast_ref(),
+            Some(&BlockError::BadWitnessCommitment)
+        );
+        block.transactions[0].inputs[0].witness.clear();
+        assert_eq!(
+            block.check_witness_commitment().unwrap_err().downcast_ref(),
+            Some(&BlockError::BadWitnessNonce)
+        );
+
+        let mut genesis: Block = GENESIS.parse()?;
+        genesis.transactions.push(genesis.transactions[0].clone());
+        assert_eq!(
+            genesis.check_merkle_root().unwrap_err().downcast_ref(),
+            Some(&BlockError::BadCoinbase)
+        );
+        Ok(())
+    }
+}
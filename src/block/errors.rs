@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Blocks and their headers
+pub enum BlockError {
+    #[error("bits {0:#010x} encode a zero, negative or overflowing target")]
+    InvalidTarget(u32),
+    #[error("block hash {0} is above its target")]
+    InsufficientWork(String),
+    #[error("the first transaction of a block must be its only coinbase")]
+    BadCoinbase,
+    #[error("merkle root does not commit to the transactions")]
+    BadMerkleRoot,
+    #[error("merkle tree has duplicate transactions")]
+    MutatedMerkleTree,
+    #[error("coinbase witness must be a single 32 byte reserved value")]
+    BadWitnessNonce,
+    #[error("witness commitment does not commit to the witness data")]
+    BadWitnessCommitment,
+    #[error("witness data in a block without a witness commitment")]
+    UnexpectedWitness,
+    #[error("coinbase script does not start with the block height")]
+    MissingHeight,
+    #[error("invalid partial merkle tree: {0}")]
+    InvalidPartialMerkleTree(&'static str),
+}
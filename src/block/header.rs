@@ -0,0 +1,267 @@
+use {
+    super::errors::BlockError,
+    crate::utils::{encoding::read_array, errors::EncodingError, hash::hash256},
+    anyhow::{bail, Result},
+    ibig::UBig,
+    std::{
+        fmt::{self, Display, Formatter},
+        io::Read,
+        str::FromStr,
+    },
+};
+
+/// Size of a serialised block header in bytes
+pub const HEADER_SIZE: usize = 80;
+
+/// Block hash, the double SHA256 of the serialised header
+///
+/// Stored in the byte order it is hashed and serialised in, and displayed reversed
+pub type BlockHash = [u8; 32];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Header of a block, committing to its transactions and the block before it
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_blockhash: BlockHash,
+    pub merkle_root: [u8; 32],
+    /// Block time in seconds since the Unix epoch
+    pub time: u32,
+    /// Target the block hash must not exceed, in the compact format
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+/// Decode a target from the compact format, a base 256 exponent byte followed by a 3 byte mantissa
+/// whose top bit is a sign
+///
+/// Returns `None` for zero, negative and overflowing targets
+pub fn bits_to_target(bits: u32) -> Option<UBig> {
+    let size = (bits >> 24) as usize;
+    let word = bits & 0x007fffff;
+    let negative = bits & 0x00800000 != 0;
+
+    // the mantissa must fit in 256 bits once shifted into place
+    let overflow =
+        word > 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32));
+    if negative && word != 0 || overflow {
+        return None;
+    }
+
+    let target = match size {
+        0..=3 => UBig::from(word >> (8 * (3 - size))),
+        _ => UBig::from(word) << (8 * (size - 3)),
+    };
+    (target != UBig::from(0u8)).then_some(target)
+}
+
+/// Encode a target in the compact format, losing all but its 3 most significant bytes
+pub fn target_to_bits(target: &UBig) -> u32 {
+    let mut size = target.bit_len().div_ceil(8);
+    let mut word = match size {
+        0..=3 => u32::try_from(target << (8 * (3 - size))).unwrap_or(0),
+        _ => u32::try_from(target >> (8 * (size - 3))).unwrap_or(0),
+    };
+
+    // the top bit of the mantissa is its sign, so shift it out of the way
+    if word & 0x00800000 != 0 {
+        word >>= 8;
+        size += 1;
+    }
+
+    word | (size as u32) << 24
+}
+
+impl BlockHeader {
+    /// Parse the 80 byte serialisation of a header
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            version: i32::from_le_bytes(read_array(reader)?),
+            prev_blockhash: read_array(reader)?,
+            merkle_root: read_array(reader)?,
+            time: u32::from_le_bytes(read_array(reader)?),
+            bits: u32::from_le_bytes(read_array(reader)?),
+            nonce: u32::from_le_bytes(read_array(reader)?),
+        })
+    }
+
+    /// Parse a header that must span all of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let header = Self::parse(&mut reader)?;
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(header)
+    }
+
+    /// Serialise the header to 80 bytes
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.version.to_le_bytes().as_slice(),
+            &self.prev_blockhash,
+            &self.merkle_root,
+            &self.time.to_le_bytes(),
+            &self.bits.to_le_bytes(),
+            &self.nonce.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Hash identifying the block
+    pub fn hash(&self) -> BlockHash {
+        hash256(&self.serialise())
+    }
+
+    /// Target the block hash must not exceed, `None` if the bits are invalid
+    pub fn target(&self) -> Option<UBig> {
+        bits_to_target(self.bits)
+    }
+
+    /// Expected number of hashes needed to find a block at the header's target, 2^256 / (target + 1)
+    ///
+    /// Chainwork is the sum of the work of every header in a chain. Headers with invalid bits have
+    /// no work.
+    pub fn work(&self) -> UBig {
+        match self.target() {
+            Some(target) => (UBig::from(1u8) << 256) / (target + UBig::from(1u8)),
+            None => UBig::from(0u8),
+        }
+    }
+
+    /// Difficulty relative to the highest target of the main network, `0x1d00ffff`
+    pub fn difficulty(&self) -> f64 {
+        let mut shift = (self.bits >> 24) & 0xff;
+        let mut difficulty = 0x0000ffff as f64 / (self.bits & 0x00ffffff) as f64;
+
+        while shift < 29 {
+            difficulty *= 256.0;
+            shift += 1;
+        }
+        while shift > 29 {
+            difficulty /= 256.0;
+            shift -= 1;
+        }
+
+        difficulty
+    }
+
+    /// Check the bits encode a valid target and the block hash does not exceed it
+    ///
+    /// The network's proof of work limit is checked along with the chain the header belongs to
+    pub fn check_pow(&self) -> Result<()> {
+        let Some(target) = self.target() else {
+            bail!(BlockError::InvalidTarget(self.bits));
+        };
+
+        // the hash is compared as a little endian number
+        let mut hash = self.hash();
+        hash.reverse();
+        if UBig::from_be_bytes(&hash) > target {
+            bail!(BlockError::InsufficientWork(hex::encode(hash)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for BlockHeader {
+    /// Format the serialised header as hex
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialise()))
+    }
+}
+
+impl FromStr for BlockHeader {
+    type Err = anyhow::Error;
+
+    /// Parse a hex serialised header
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_bytes(&hex::decode(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    /// Header of the mainnet genesis block
+    const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+
+    /// Header of mainnet block 125552
+    const BLOCK_125552: &str = "0100000081cd02ab7e569e8bcd9317e2fe99f2de44d49ab2b8851ba4a308000000000000e320b6c2fffc8d750423db8b1eb942ae710e951ed797f7affc8892b0f1fc122bc7f5d74df2b9441a42a14695";
+
+    #[test]
+    fn genesis_header() -> Result<()> {
+        let header: BlockHeader = GENESIS.parse()?;
+
+        assert_eq!(header.to_string(), GENESIS);
+        assert_eq!(header.serialise().len(), HEADER_SIZE);
+        assert_eq!(
+            reversed_hex(&header.hash()),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(header.bits, 0x1d00ffff);
+        assert_eq!(header.difficulty(), 1.0);
+        assert_eq!(header.work(), UBig::from(0x100010001u64));
+        header.check_pow()
+    }
+
+    #[test]
+    fn mined_header() -> Result<()> {
+        let mut header: BlockHeader = BLOCK_125552.parse()?;
+
+        assert_eq!(
+            reversed_hex(&header.hash()),
+            "00000000000000001e8d6829a8a21adc5d38d0a473b144b6765798e61f98bd1d"
+        );
+        assert_eq!(header.time, 1305998791);
+        assert_eq!(header.nonce, 2504433986);
+        assert!((header.difficulty() - 244_112.487_774_89).abs() < 1e-6);
+        header.check_pow()?;
+
+        header.nonce += 1;
+        assert!(header
+            .check_pow()
+            .unwrap_err()
+            .downcast_ref::<BlockError>()
+            .is_some_and(|err| matches!(err, BlockError::InsufficientWork(_))));
+
+        header.bits = 0x04923456;
+        assert_eq!(
+            header.check_pow().unwrap_err().downcast_ref(),
+            Some(&BlockError::InvalidTarget(0x04923456))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compact_targets() {
+        for (bits, target, compact) in [
+            (0x01003456, None, 0),
+            (0x01123456, Some("12"), 0x01120000),
+            (0x02008000, Some("80"), 0x02008000),
+            (0x05009234, Some("92340000"), 0x05009234),
+            (0x04123456, Some("12345600"), 0x04123456),
+            (
+                0x20123456,
+                Some("1234560000000000000000000000000000000000000000000000000000000000"),
+                0x20123456,
+            ),
+            (
+                0x1d00ffff,
+                Some("ffff0000000000000000000000000000000000000000000000000000"),
+                0x1d00ffff,
+            ),
+            (0x04923456, None, 0),
+            (0x00923456, None, 0),
+            (0xff123456, None, 0),
+        ] {
+            let target = target.map(|hex| UBig::from_str_radix(hex, 16).unwrap());
+            assert_eq!(bits_to_target(bits), target);
+            if let Some(target) = target {
+                assert_eq!(target_to_bits(&target), compact);
+            }
+        }
+    }
+}
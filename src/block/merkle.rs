@@ -0,0 +1,528 @@
+use {
+    super::{
+        body::{Block, MAX_BLOCK_WEIGHT},
+        errors::BlockError,
+        header::BlockHeader,
+    },
+    crate::{
+        transaction::tx::{Transaction, Txid},
+        utils::{
+            encoding::{compact_size, read_array, read_bytes, read_compact_size},
+            errors::EncodingError,
+            hash::hash256,
+        },
+    },
+    anyhow::{bail, Result},
+    std::io::Read,
+};
+
+/// Weight of the smallest possible transaction, bounding the transactions a block can hold
+const MIN_TRANSACTION_WEIGHT: usize = 240;
+
+/// Most transactions a block can hold
+const MAX_TRANSACTIONS: u32 = (MAX_BLOCK_WEIGHT / MIN_TRANSACTION_WEIGHT) as u32;
+
+/// Txid of a transaction matched by a partial merkle tree, with its position in the block
+pub type TxMatch = (Txid, u32);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Merkle tree of a block pruned to the branches of some of its transactions, as specified by
+/// BIP37
+///
+/// The tree is traversed depth first. Each node visited has a flag bit, set if it is a matched
+/// transaction or one is below it. The hash of a node is included when its bit is unset or it is a
+/// leaf, and the traversal only continues below nodes with a set bit.
+pub struct PartialMerkleTree {
+    total_transactions: u32,
+    hashes: Vec<[u8; 32]>,
+    bits: Vec<bool>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Block header with a partial merkle tree proving some of its transactions are in the block
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    pub tree: PartialMerkleTree,
+}
+
+/// State of a partial merkle tree traversal extracting the matched transactions
+struct Extraction {
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<TxMatch>,
+}
+
+/// Merkle root of `hashes`, pairing the last hash of a level with itself when the level is odd
+///
+/// Also returns if two identical hashes were paired at any level. Such a tree has the same root
+/// as one with the duplicated hashes removed, which CVE-2012-2459 used to make valid blocks look
+/// invalid, so blocks with a mutated tree must be rejected without marking their hash invalid.
+/// The root of no hashes is zero.
+pub fn merkle_root(hashes: &[[u8; 32]]) -> ([u8; 32], bool) {
+    if hashes.is_empty() {
+        return ([0; 32], false);
+    }
+
+    let mut level = hashes.to_vec();
+    let mut mutated = false;
+    while level.len() > 1 {
+        mutated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        if level.len() % 2 == 1 {
+            level.push(*level.last().unwrap());
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], &pair[1]))
+            .collect();
+    }
+
+    (level[0], mutated)
+}
+
+/// Hash of two sibling nodes of a merkle tree
+pub fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hash256(&[left.as_slice(), right].concat())
+}
+
+impl PartialMerkleTree {
+    /// Build the tree of `txids` keeping the branches of the transactions `matches` flags
+    pub fn from_txids(txids: &[Txid], matches: &[bool]) -> Self {
+        let mut tree = Self {
+            total_transactions: txids.len() as u32,
+            hashes: vec![],
+            bits: vec![],
+        };
+
+        let mut height = 0;
+        while tree.width(height) > 1 {
+            height += 1;
+        }
+        tree.build(height, 0, txids, matches);
+
+        tree
+    }
+
+    /// Number of transactions in the block
+    pub fn total_transactions(&self) -> u32 {
+        self.total_transactions
+    }
+
+    /// Hashes of the pruned nodes and matched leaves, in depth first order
+    pub fn hashes(&self) -> &[[u8; 32]] {
+        &self.hashes
+    }
+
+    /// Flag bits of the nodes visited, in depth first order
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+
+    /// Number of nodes at `height`, leaves being at height 0
+    fn width(&self, height: u32) -> u32 {
+        // widen to avoid overflowing when the height reaches 32
+        ((self.total_transactions as u64 + (1 << height) - 1) >> height) as u32
+    }
+
+    /// Hash of the node at `height` and `pos` of the full tree of `txids`
+    fn hash(&self, height: u32, pos: u32, txids: &[Txid]) -> [u8; 32] {
+        if height == 0 {
+            return txids[pos as usize];
+        }
+
+        let left = self.hash(height - 1, pos * 2, txids);
+        let right = match pos * 2 + 1 < self.width(height - 1) {
+            true => self.hash(height - 1, pos * 2 + 1, txids),
+            false => left,
+        };
+        merkle_parent(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, pos: u32, txids: &[Txid], matches: &[bool]) {
+        // if any leaf below the node is matched
+        let start = (pos as usize) << height;
+        let end = (((pos + 1) as usize) << height).min(txids.len());
+        let parent_of_match = matches[start..end].iter().any(|matched| *matched);
+        self.bits.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            let hash = self.hash(height, pos, txids);
+            self.hashes.push(hash);
+        } else {
+            self.build(height - 1, pos * 2, txids, matches);
+            if pos * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, pos * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    fn extract(&self, height: u32, pos: u32, state: &mut Extraction) -> Result<[u8; 32]> {
+        let Some(&parent_of_match) = self.bits.get(state.bits_used) else {
+            bail!(BlockError::InvalidPartialMerkleTree("too few flag bits"));
+        };
+        state.bits_used += 1;
+
+        if height == 0 || !parent_of_match {
+            let Some(&hash) = self.hashes.get(state.hashes_used) else {
+                bail!(BlockError::InvalidPartialMerkleTree("too few hashes"));
+            };
+            state.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                state.matches.push((hash, pos));
+            }
+            return Ok(hash);
+        }
+
+        let left = self.extract(height - 1, pos * 2, state)?;
+        let right = match pos * 2 + 1 < self.width(height - 1) {
+            true => {
+                let right = self.extract(height - 1, pos * 2 + 1, state)?;
+                // identical siblings would allow the CVE-2012-2459 mutation
+                if right == left {
+                    bail!(BlockError::InvalidPartialMerkleTree(
+                        "identical sibling hashes"
+                    ));
+                }
+                right
+            }
+            false => left,
+        };
+
+        Ok(merkle_parent(&left, &right))
+    }
+
+    /// Merkle root the tree commits to, with the matched txids and their positions in the block
+    ///
+    /// Fails unless every hash and flag bit, but the padding of the last flag byte, is used
+    pub fn extract_matches(&self) -> Result<([u8; 32], Vec<TxMatch>)> {
+        if self.total_transactions == 0 || self.total_transactions > MAX_TRANSACTIONS {
+            bail!(BlockError::InvalidPartialMerkleTree(
+                "invalid transaction count"
+            ));
+        }
+        if self.hashes.len() > self.total_transactions as usize
+            || self.bits.len() < self.hashes.len()
+        {
+            bail!(BlockError::InvalidPartialMerkleTree("too many hashes"));
+        }
+
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        let mut state = Extraction {
+            bits_used: 0,
+            hashes_used: 0,
+            matches: vec![],
+        };
+        let root = self.extract(height, 0, &mut state)?;
+
+        if state.bits_used.div_ceil(8) != self.bits.len().div_ceil(8) {
+            bail!(BlockError::InvalidPartialMerkleTree("unused flag bits"));
+        }
+        if state.hashes_used != self.hashes.len() {
+            bail!(BlockError::InvalidPartialMerkleTree("unused hashes"));
+        }
+
+        Ok((root, state.matches))
+    }
+
+    /// Parse a serialised tree, its flag bits packed least significant bit first
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let total_transactions = u32::from_le_bytes(read_array(reader)?);
+        if total_transactions > MAX_TRANSACTIONS {
+            bail!(BlockError::InvalidPartialMerkleTree(
+                "invalid transaction count"
+            ));
+        }
+
+        let hash_count = read_compact_size(reader)?;
+        if hash_count > total_transactions as u64 {
+            bail!(BlockError::InvalidPartialMerkleTree("too many hashes"));
+        }
+        let hashes = (0..hash_count)
+            .map(|_| read_array(reader))
+            .collect::<Result<Vec<_>>>()?;
+
+        let flag_count = read_compact_size(reader)?;
+        let bits = read_bytes(reader, flag_count)?
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| byte >> i & 1 == 1))
+            .collect();
+
+        Ok(Self {
+            total_transactions,
+            hashes,
+            bits,
+        })
+    }
+
+    /// Serialise the tree, padding the flag bits to whole bytes
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = self.total_transactions.to_le_bytes().to_vec();
+        bytes.extend(compact_size(self.hashes.len() as u64));
+        for hash in &self.hashes {
+            bytes.extend(hash);
+        }
+
+        let flags = self
+            .bits
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, bit)| byte | (*bit as u8) << i)
+            })
+            .collect::<Vec<_>>();
+        bytes.extend(compact_size(flags.len() as u64));
+        bytes.extend(flags);
+
+        bytes
+    }
+}
+
+impl MerkleBlock {
+    /// Prove the transactions of `block` whose txids satisfy `matches`
+    pub fn from_block(block: &Block, matches: impl Fn(&Txid) -> bool) -> Self {
+        let txids = block
+            .transactions
+            .iter()
+            .map(Transaction::txid)
+            .collect::<Vec<_>>();
+        let flags = txids.iter().map(matches).collect::<Vec<_>>();
+
+        Self {
+            header: block.header,
+            tree: PartialMerkleTree::from_txids(&txids, &flags),
+        }
+    }
+
+    /// Matched txids with their positions in the block, once the tree is checked against the
+    /// header's merkle root
+    pub fn extract_matches(&self) -> Result<Vec<TxMatch>> {
+        let (root, matches) = self.tree.extract_matches()?;
+        if root != self.header.merkle_root {
+            bail!(BlockError::BadMerkleRoot);
+        }
+
+        Ok(matches)
+    }
+
+    /// Parse a serialised `merkleblock` message
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            header: BlockHeader::parse(reader)?,
+            tree: PartialMerkleTree::parse(reader)?,
+        })
+    }
+
+    /// Parse a merkle block that must span all of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let merkle_block = Self::parse(&mut reader)?;
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(merkle_block)
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [self.header.serialise(), self.tree.serialise()].concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    /// Mainnet block 702861, a segwit block of 1.4 MB
+    const BLOCK_702861: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/mainnet_block_702861.raw"
+    ));
+
+    /// Deterministic xorshift generator for the fuzz tests
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /// Txids 1 to `count` as little endian numbers
+    fn txids(count: usize) -> Vec<Txid> {
+        (1..=count)
+            .map(|i| {
+                let mut txid = [0; 32];
+                txid[..8].copy_from_slice(&(i as u64).to_le_bytes());
+                txid
+            })
+            .collect()
+    }
+
+    #[test]
+    fn odd_levels_and_mutation() {
+        let hashes = [[1; 32], [2; 32], [3; 32]];
+        let (root, mutated) = merkle_root(&hashes);
+
+        assert!(!mutated);
+        assert_eq!(
+            root,
+            merkle_parent(
+                &merkle_parent(&hashes[0], &hashes[1]),
+                &merkle_parent(&hashes[2], &hashes[2])
+            )
+        );
+
+        // duplicating the last hash keeps the root but is detected
+        assert_eq!(
+            merkle_root(&[[1; 32], [2; 32], [3; 32], [3; 32]]),
+            (root, true)
+        );
+        assert_eq!(merkle_root(&[[1; 32]]), ([1; 32], false));
+        assert_eq!(merkle_root(&[]), ([0; 32], false));
+    }
+
+    #[test]
+    fn partial_trees_round_trip() -> Result<()> {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for count in [1, 4, 7, 17, 56, 100, 127, 256, 312, 513, 1000] {
+            let txids = txids(count);
+            let (root, _) = merkle_root(&txids);
+
+            // inclusion chances of 1, 1/2, 1/4 up to 1/128
+            for attempt in 1..15 {
+                let bits = attempt / 2;
+                let matches = (0..count)
+                    .map(|_| bits == 0 || rng.next() >> (64 - bits) == 0)
+                    .collect::<Vec<_>>();
+                let expected = txids
+                    .iter()
+                    .zip(0..)
+                    .zip(&matches)
+                    .filter(|(_, matched)| **matched)
+                    .map(|(matched, _)| (*matched.0, matched.1))
+                    .collect::<Vec<_>>();
+
+                let bytes = PartialMerkleTree::from_txids(&txids, &matches).serialise();
+                let tree = PartialMerkleTree::parse(&mut bytes.as_slice())?;
+                assert_eq!(tree.serialise(), bytes);
+                assert_eq!(tree.extract_matches()?, (root, expected));
+
+                // flipping any bit of a hash breaks the proof
+                for _ in 0..4 {
+                    let mut damaged = tree.clone();
+                    let index = rng.next() as usize % damaged.hashes.len();
+                    let bit = rng.next() as usize % 256;
+                    damaged.hashes[index][bit / 8] ^= 1 << (bit % 8);
+                    assert!(damaged
+                        .extract_matches()
+                        .map_or(true, |(damaged, _)| damaged != root));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_trees() -> Result<()> {
+        let txids = txids(100);
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        let matches = (0..100).map(|i| i % 7 == 3).collect::<Vec<_>>();
+        let tree = PartialMerkleTree::from_txids(&txids, &matches);
+        let (root, _) = merkle_root(&txids);
+
+        // truncated and corrupted serialisations either fail or no longer prove the root
+        let bytes = tree.serialise();
+        for len in 0..bytes.len() {
+            let mut reader = &bytes[..len];
+            if let Ok(tree) = PartialMerkleTree::parse(&mut reader) {
+                assert!(tree
+                    .extract_matches()
+                    .map_or(true, |(damaged, _)| damaged != root));
+            }
+        }
+        for _ in 0..500 {
+            let mut damaged = bytes.clone();
+            let index = rng.next() as usize % damaged.len();
+            damaged[index] ^= 1 << (rng.next() % 8);
+            if let Ok(damaged) = PartialMerkleTree::parse(&mut damaged.as_slice()) {
+                if let Ok((damaged_root, matches)) = damaged.extract_matches() {
+                    assert!(damaged_root != root || damaged == tree || matches.is_empty());
+                }
+            }
+        }
+
+        let mut extra_hash = tree.clone();
+        extra_hash.hashes.push([0; 32]);
+        assert!(extra_hash.extract_matches().is_err());
+        let mut missing_bits = tree.clone();
+        missing_bits.bits.truncate(missing_bits.bits.len() - 9);
+        assert!(missing_bits.extract_matches().is_err());
+        let mut empty = tree;
+        empty.total_transactions = 0;
+        assert!(empty.extract_matches().is_err());
+
+        // txids 9 and 10 repeat, so the tree has identical siblings
+        let mut txids = self::txids(10);
+        txids.extend([txids[8], txids[9]]);
+        let mut matches = vec![false; 12];
+        matches[9] = true;
+        matches[10] = true;
+        assert_eq!(
+            PartialMerkleTree::from_txids(&txids, &matches)
+                .extract_matches()
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&BlockError::InvalidPartialMerkleTree(
+                "identical sibling hashes"
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merkle_block_of_mainnet_block() -> Result<()> {
+        let block = Block::from_bytes(BLOCK_702861)?;
+        let wanted = [block.transactions[1].txid(), block.transactions[500].txid()];
+
+        let merkle_block = MerkleBlock::from_block(&block, |txid| wanted.contains(txid));
+        let bytes = merkle_block.serialise();
+        let parsed = MerkleBlock::from_bytes(&bytes)?;
+        // parsing pads the flag bits to whole bytes
+        assert_eq!(parsed.serialise(), bytes);
+        assert_eq!(parsed.header, merkle_block.header);
+        assert_eq!(
+            parsed.extract_matches()?,
+            vec![(wanted[0], 1), (wanted[1], 500)]
+        );
+        assert!(bytes.len() < 1000);
+
+        let none = MerkleBlock::from_block(&block, |_| false);
+        assert!(none.extract_matches()?.is_empty());
+        assert_eq!(none.tree.hashes(), [block.header.merkle_root]);
+
+        let mut other_header = merkle_block;
+        other_header.header.merkle_root[0] ^= 1;
+        assert_eq!(
+            other_header.extract_matches().unwrap_err().downcast_ref(),
+            Some(&BlockError::BadMerkleRoot)
+        );
+        assert_eq!(
+            reversed_hex(&block.hash()),
+            "000000000000000000000c835b2adcaedc20fdf6ee440009c249452c726dafae"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_merkle_block() {
+        let bytes = hex::decode("000006000000000000000004ee00000004c7f1ccb1000000ffff0000000100000000ffffffffff1f000000000400000000000002000000000500000000000000000000000300000000000003000000000200000000ff00000000c7f1ccb1040700000000000000ccb100c76538b100000004bfa9c251681b1b0004000000002500000004bfaac251681b1b25").unwrap();
+        assert!(MerkleBlock::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+pub mod body;
+pub mod errors;
+pub mod header;
+pub mod merkle;
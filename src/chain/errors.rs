@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to validating a chain of headers
+pub enum ChainError {
+    #[error("parent {0} of the header is unknown")]
+    UnknownParent(String),
+    #[error("target of the header is above the proof of work limit")]
+    HighTarget,
+    #[error("header bits {1:#010x} differ from the required {0:#010x}")]
+    BadDiffBits(u32, u32),
+    #[error("header time {0} is not after the median time past {1}")]
+    TimeTooOld(u32, u32),
+    #[error("header time {0} is more than two hours after {1}")]
+    TimeTooNew(u32, u32),
+}
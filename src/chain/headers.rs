@@ -0,0 +1,500 @@
+use {
+    super::errors::ChainError,
+    crate::{
+        block::header::{bits_to_target, target_to_bits, BlockHash, BlockHeader},
+        network::params::Params,
+        utils::encoding::reversed_hex,
+    },
+    anyhow::{bail, Result},
+    ibig::UBig,
+    std::collections::HashMap,
+};
+
+/// Seconds between blocks the difficulty aims for
+pub const TARGET_SPACING: u32 = 10 * 60;
+
+/// Seconds a difficulty adjustment interval aims to take, two weeks
+pub const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+
+/// Blocks between difficulty adjustments
+pub const DIFFICULTY_ADJUSTMENT_INTERVAL: u32 = TARGET_TIMESPAN / TARGET_SPACING;
+
+/// Seconds a header may be ahead of the network adjusted time
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// Number of previous blocks whose median time a block must be after
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Header accepted into a chain, with its position and the work of the chain up to it
+pub struct ChainEntry {
+    pub header: BlockHeader,
+    pub height: u32,
+    /// Total work of the chain from the genesis block up to and including this header
+    pub chainwork: UBig,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Effect of accepting a header on the chain
+pub enum Accepted {
+    /// The header was already known
+    Duplicate,
+    /// The header extends a chain with less work than the active one
+    SideChain,
+    /// The header extends the active chain
+    Extended,
+    /// The header moved the active chain to a fork with more work, disconnecting the blocks after
+    /// `fork_height`, tip first
+    Reorganised {
+        fork_height: u32,
+        disconnected: Vec<BlockHash>,
+    },
+}
+
+#[derive(Clone, Debug)]
+/// Validated headers of a network held in memory, following the chain with the most work
+///
+/// Every header must connect to a known one, meet the target the difficulty adjustment requires
+/// and have a valid time. Forks are kept so that the active chain can reorganise to them.
+pub struct HeaderChain {
+    params: Params,
+    entries: HashMap<BlockHash, ChainEntry>,
+    /// Hashes of the active chain by height
+    active: Vec<BlockHash>,
+}
+
+/// Target of the block after a difficulty adjustment interval that took `last_time - first_time`
+/// seconds, limited to a quarter or four times the previous target
+pub fn next_retarget_bits(params: &Params, last_bits: u32, first_time: u32, last_time: u32) -> u32 {
+    if params.no_retargeting {
+        return last_bits;
+    }
+
+    let timespan = (last_time as i64 - first_time as i64)
+        .clamp(TARGET_TIMESPAN as i64 / 4, TARGET_TIMESPAN as i64 * 4);
+    let target = bits_to_target(last_bits).unwrap_or_default() * UBig::from(timespan as u64)
+        / UBig::from(TARGET_TIMESPAN);
+
+    target_to_bits(&target.min(params.pow_limit.clone()))
+}
+
+impl HeaderChain {
+    /// Chain of the genesis block of the network described by `params`
+    pub fn new(params: Params) -> Self {
+        let genesis = params.genesis;
+        let hash = genesis.hash();
+        let entry = ChainEntry {
+            header: genesis,
+            height: 0,
+            chainwork: genesis.work(),
+        };
+
+        Self {
+            params,
+            entries: HashMap::from([(hash, entry)]),
+            active: vec![hash],
+        }
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Height of the active chain
+    pub fn height(&self) -> u32 {
+        self.active.len() as u32 - 1
+    }
+
+    /// Last entry of the active chain
+    pub fn tip(&self) -> &ChainEntry {
+        &self.entries[self.active.last().unwrap()]
+    }
+
+    /// Entry of any known header, active or not
+    pub fn get(&self, hash: &BlockHash) -> Option<&ChainEntry> {
+        self.entries.get(hash)
+    }
+
+    /// Hash of the block at `height` of the active chain
+    pub fn hash_at(&self, height: u32) -> Option<BlockHash> {
+        self.active.get(height as usize).copied()
+    }
+
+    /// If the header with `hash` is part of the active chain
+    pub fn is_active(&self, hash: &BlockHash) -> bool {
+        self.entries
+            .get(hash)
+            .is_some_and(|entry| self.hash_at(entry.height) == Some(*hash))
+    }
+
+    /// Hashes of the active chain, dense near the tip and exponentially sparser towards the
+    /// genesis block, for peers to find the last block they have in common
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut height = self.height() as i64;
+        let mut step = 1;
+        while height > 0 {
+            locator.push(self.active[height as usize]);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height -= step;
+        }
+        locator.push(self.active[0]);
+
+        locator
+    }
+
+    /// Ancestor of `entry` at `height`, which must not be above it
+    fn ancestor<'a>(&'a self, mut entry: &'a ChainEntry, height: u32) -> &'a ChainEntry {
+        // walk back until the fork joins the active chain, then look the height up
+        while entry.height > height && !self.is_active(&entry.header.hash()) {
+            entry = &self.entries[&entry.header.prev_blockhash];
+        }
+        match entry.height > height {
+            true => &self.entries[&self.active[height as usize]],
+            false => entry,
+        }
+    }
+
+    /// Previous entry of a header other than the genesis one
+    fn parent(&self, entry: &ChainEntry) -> &ChainEntry {
+        &self.entries[&entry.header.prev_blockhash]
+    }
+
+    /// Median time of `entry` and up to 10 blocks before it
+    pub fn median_time_past(&self, entry: &ChainEntry) -> u32 {
+        let mut times = vec![entry.header.time];
+        let mut entry = entry;
+        while times.len() < MEDIAN_TIME_SPAN && entry.height > 0 {
+            entry = self.parent(entry);
+            times.push(entry.header.time);
+        }
+        times.sort_unstable();
+
+        times[times.len() / 2]
+    }
+
+    /// Median time past of the block at `height` of the active chain
+    pub fn median_time_past_at(&self, height: u32) -> Option<u32> {
+        let entry = self.entries.get(&self.hash_at(height)?)?;
+        Some(self.median_time_past(entry))
+    }
+
+    /// Bits required of a header at time `time` following `parent`
+    pub fn next_work_required(&self, parent: &ChainEntry, time: u32) -> u32 {
+        let height = parent.height + 1;
+        if !height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL) {
+            if !self.params.allow_min_difficulty_blocks {
+                return parent.header.bits;
+            }
+
+            // a block 20 minutes after its parent may use the lowest difficulty
+            let limit = self.params.pow_limit_bits();
+            if time > parent.header.time + TARGET_SPACING * 2 {
+                return limit;
+            }
+
+            // otherwise the difficulty of the last block not using that rule applies
+            let mut entry = parent;
+            while entry.height > 0
+                && !entry.height.is_multiple_of(DIFFICULTY_ADJUSTMENT_INTERVAL)
+                && entry.header.bits == limit
+            {
+                entry = self.parent(entry);
+            }
+            return entry.header.bits;
+        }
+
+        let first = self.ancestor(parent, parent.height + 1 - DIFFICULTY_ADJUSTMENT_INTERVAL);
+        next_retarget_bits(
+            &self.params,
+            parent.header.bits,
+            first.header.time,
+            parent.header.time,
+        )
+    }
+
+    /// Check that `header` follows a known header with the required work and a valid time,
+    /// without adding it to the chain
+    ///
+    /// `adjusted_time` is the network adjusted time in seconds since the Unix epoch
+    pub fn check_header(&self, header: &BlockHeader, adjusted_time: u32) -> Result<()> {
+        let Some(parent) = self.entries.get(&header.prev_blockhash) else {
+            bail!(ChainError::UnknownParent(reversed_hex(
+                &header.prev_blockhash
+            )));
+        };
+
+        header.check_pow()?;
+        if header
+            .target()
+            .is_some_and(|target| target > self.params.pow_limit)
+        {
+            bail!(ChainError::HighTarget);
+        }
+        let required = self.next_work_required(parent, header.time);
+        if header.bits != required {
+            bail!(ChainError::BadDiffBits(required, header.bits));
+        }
+        let median_time_past = self.median_time_past(parent);
+        if header.time <= median_time_past {
+            bail!(ChainError::TimeTooOld(header.time, median_time_past));
+        }
+        if header.time as u64 > adjusted_time as u64 + MAX_FUTURE_BLOCK_TIME as u64 {
+            bail!(ChainError::TimeTooNew(header.time, adjusted_time));
+        }
+
+        Ok(())
+    }
+
+    /// Validate `header` and add it to the chain, reorganising if its chain has the most work
+    ///
+    /// `adjusted_time` is the network adjusted time in seconds since the Unix epoch
+    pub fn accept(&mut self, header: BlockHeader, adjusted_time: u32) -> Result<Accepted> {
+        let hash = header.hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(Accepted::Duplicate);
+        }
+        self.check_header(&header, adjusted_time)?;
+
+        let parent = &self.entries[&header.prev_blockhash];
+        let entry = ChainEntry {
+            header,
+            height: parent.height + 1,
+            chainwork: &parent.chainwork + header.work(),
+        };
+        let more_work = entry.chainwork > self.tip().chainwork;
+        let extends_tip = header.prev_blockhash == *self.active.last().unwrap();
+        self.entries.insert(hash, entry);
+
+        match (more_work, extends_tip) {
+            (false, _) => Ok(Accepted::SideChain),
+            (true, true) => {
+                self.active.push(hash);
+                Ok(Accepted::Extended)
+            }
+            (true, false) => Ok(self.reorganise(hash)),
+        }
+    }
+
+    /// Accept headers in order, stopping at the first invalid one
+    ///
+    /// Returns the effect of each header accepted
+    pub fn accept_all(
+        &mut self,
+        headers: &[BlockHeader],
+        adjusted_time: u32,
+    ) -> Result<Vec<Accepted>> {
+        headers
+            .iter()
+            .map(|header| self.accept(*header, adjusted_time))
+            .collect()
+    }
+
+    /// Make the chain ending at `tip` active
+    fn reorganise(&mut self, tip: BlockHash) -> Accepted {
+        // collect the new branch back to the active chain
+        let mut branch = vec![];
+        let mut hash = tip;
+        while !self.is_active(&hash) {
+            branch.push(hash);
+            hash = self.entries[&hash].header.prev_blockhash;
+        }
+        let fork_height = self.entries[&hash].height;
+
+        let disconnected = self
+            .active
+            .split_off(fork_height as usize + 1)
+            .into_iter()
+            .rev()
+            .collect();
+        self.active.extend(branch.into_iter().rev());
+
+        Accepted::Reorganised {
+            fork_height,
+            disconnected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::network::Network};
+
+    /// Header on `prev` at `time`, with a merkle root of `tag` bytes to tell forks apart
+    fn mine(prev: &BlockHeader, time: u32, bits: u32, tag: u8) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 4,
+            prev_blockhash: prev.hash(),
+            merkle_root: [tag; 32],
+            time,
+            bits,
+            nonce: 0,
+        };
+        while header.check_pow().is_err() {
+            header.nonce += 1;
+        }
+
+        header
+    }
+
+    /// Extend the active chain by `count` headers `spacing` seconds apart at its required bits
+    fn extend(chain: &mut HeaderChain, count: u32, spacing: u32, tag: u8) -> Result<()> {
+        for _ in 0..count {
+            let tip = chain.tip().clone();
+            let time = tip.header.time + spacing;
+            let header = mine(&tip.header, time, chain.next_work_required(&tip, time), tag);
+            assert_eq!(chain.accept(header, time)?, Accepted::Extended);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mainnet_retargets() {
+        let params = Network::Mainnet.params();
+
+        for (last_bits, first_time, last_time, expected) in [
+            (0x1d00ffff, 1261130161, 1262152739, 0x1d00d86a),
+            // the target cannot rise above the limit
+            (0x1d00ffff, 1231006505, 1233061996, 0x1d00ffff),
+            // nor change by more than four times
+            (0x1c05a3f4, 1279008237, 1279297671, 0x1c0168fd),
+            (0x1c387f6f, 1263163443, 1269211443, 0x1d00e1fd),
+        ] {
+            assert_eq!(
+                next_retarget_bits(&params, last_bits, first_time, last_time),
+                expected
+            );
+        }
+
+        let regtest = Network::Regtest.params();
+        assert_eq!(next_retarget_bits(&regtest, 0x207fffff, 0, 1), 0x207fffff);
+    }
+
+    #[test]
+    fn retarget_and_min_difficulty() -> Result<()> {
+        let mut params = Network::Regtest.params();
+        params.no_retargeting = false;
+        let limit = params.pow_limit_bits();
+        let mut chain = HeaderChain::new(params);
+
+        // blocks a minute apart make the first adjustment as hard as allowed
+        extend(&mut chain, DIFFICULTY_ADJUSTMENT_INTERVAL - 1, 60, 0)?;
+        let retarget = target_to_bits(&(chain.params().pow_limit.clone() / UBig::from(4u8)));
+        let tip = chain.tip().header;
+        let easy = mine(&tip, tip.time + 60, limit, 0);
+        assert_eq!(
+            chain.accept(easy, easy.time).unwrap_err().downcast_ref(),
+            Some(&ChainError::BadDiffBits(retarget, limit))
+        );
+        extend(&mut chain, 1, 60, 0)?;
+        assert_eq!(chain.tip().header.bits, retarget);
+
+        // testnet rules allow the lowest difficulty only 20 minutes after the previous block
+        let tip = chain.tip().header;
+        let easy = mine(&tip, tip.time + 60, limit, 0);
+        assert!(chain.accept(easy, easy.time).is_err());
+        let easy = mine(&tip, tip.time + TARGET_SPACING * 2 + 1, limit, 0);
+        assert_eq!(chain.accept(easy, easy.time)?, Accepted::Extended);
+
+        // after which the last difficulty not using the rule applies again
+        let tip = chain.tip().clone();
+        assert_eq!(
+            chain.next_work_required(&tip, tip.header.time + 60),
+            retarget
+        );
+        extend(&mut chain, 1, 60, 0)?;
+        assert_eq!(chain.height(), DIFFICULTY_ADJUSTMENT_INTERVAL + 2);
+
+        let locator = chain.locator();
+        assert_eq!(
+            locator[..10],
+            chain.active[chain.active.len() - 10..]
+                .iter()
+                .rev()
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(locator.last(), Some(&chain.params().genesis.hash()));
+        assert!(locator.len() < 25);
+        Ok(())
+    }
+
+    #[test]
+    fn reorganise_to_most_work() -> Result<()> {
+        let mut chain = HeaderChain::new(Network::Regtest.params());
+        let genesis = chain.tip().header;
+        extend(&mut chain, 2, 600, 1)?;
+        let (a1, a2) = (chain.hash_at(1).unwrap(), chain.hash_at(2).unwrap());
+
+        let b1 = mine(&genesis, genesis.time + 600, genesis.bits, 2);
+        let b2 = mine(&b1, b1.time + 600, genesis.bits, 2);
+        let b3 = mine(&b2, b2.time + 600, genesis.bits, 2);
+        assert_eq!(
+            chain.accept_all(&[b1, b2], b2.time)?,
+            vec![Accepted::SideChain, Accepted::SideChain]
+        );
+        assert_eq!(chain.tip().header.hash(), a2);
+        assert_eq!(
+            chain.accept(b3, b3.time)?,
+            Accepted::Reorganised {
+                fork_height: 0,
+                disconnected: vec![a2, a1],
+            }
+        );
+        assert_eq!(chain.height(), 3);
+        assert!(chain.is_active(&b1.hash()) && !chain.is_active(&a1));
+        assert_eq!(chain.get(&a2).unwrap().height, 2);
+        assert_eq!(chain.tip().chainwork, genesis.work() * UBig::from(4u8));
+        assert_eq!(chain.accept(b3, b3.time)?, Accepted::Duplicate);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_headers() -> Result<()> {
+        let mut chain = HeaderChain::new(Network::Regtest.params());
+        extend(&mut chain, 11, 600, 0)?;
+        let tip = chain.tip().clone();
+        let median_time_past = chain.median_time_past(&tip);
+        assert_eq!(median_time_past, tip.header.time - 5 * 600);
+
+        let old = mine(&tip.header, median_time_past, tip.header.bits, 0);
+        assert_eq!(
+            chain
+                .accept(old, tip.header.time)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&ChainError::TimeTooOld(median_time_past, median_time_past))
+        );
+        let new = mine(
+            &tip.header,
+            tip.header.time + MAX_FUTURE_BLOCK_TIME + 1,
+            tip.header.bits,
+            0,
+        );
+        assert_eq!(
+            chain
+                .accept(new, tip.header.time)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&ChainError::TimeTooNew(new.time, tip.header.time))
+        );
+        assert!(chain.accept(new, tip.header.time + 1).is_ok());
+
+        let orphan = mine(&old, old.time + 600, old.bits, 0);
+        assert!(chain
+            .accept(orphan, orphan.time)
+            .unwrap_err()
+            .downcast_ref::<ChainError>()
+            .is_some_and(|err| matches!(err, ChainError::UnknownParent(_))));
+
+        let mut unmined = mine(&chain.tip().header, new.time + 600, new.bits, 0);
+        while unmined.check_pow().is_ok() {
+            unmined.nonce += 1;
+        }
+        assert!(chain.accept(unmined, unmined.time).is_err());
+        assert_eq!(chain.height(), 12);
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use {
+    super::errors::DescriptorError,
+    anyhow::{bail, Result},
+};
+
+/// Characters allowed in descriptors, ordered so common characters share a group of 32
+const INPUT_CHARSET: &str = concat!(
+    "0123456789()[],'/*abcdefgh@:$%{}",
+    "IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~",
+    "ijklmnopqrstuvwxyzABCDEFGH`#\"\\ "
+);
+
+/// Characters of the checksum itself
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = (c & 0x7ffffffff) << 5 ^ value;
+    for (i, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .iter()
+    .enumerate()
+    {
+        if (c0 >> i) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Compute the 8 character checksum of a descriptor without its `#checksum` suffix
+pub fn checksum(desc: &str) -> Result<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+
+    for ch in desc.chars() {
+        let Some(pos) = INPUT_CHARSET.find(ch) else {
+            bail!(DescriptorError::InvalidCharacter(ch));
+        };
+        // a symbol for the position inside the group of every character
+        c = polymod(c, pos as u64 & 31);
+        // and a symbol for the groups of every 3 characters
+        class = class * 3 + (pos as u64 >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[(c >> (5 * (7 - j)) & 31) as usize] as char)
+        .collect())
+}
+
+/// Strip and verify the `#checksum` suffix of a descriptor, if it has one
+pub fn strip_checksum(desc: &str) -> Result<&str> {
+    let Some((body, given)) = desc.split_once('#') else {
+        return Ok(desc);
+    };
+
+    let expected = checksum(body)?;
+    if given != expected {
+        bail!(DescriptorError::InvalidChecksum(
+            given.to_string(),
+            expected
+        ));
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn raw_checksum() -> Result<()> {
+        assert_eq!(checksum("raw(deadbeef)")?, "89f8spxm");
+        assert_eq!(strip_checksum("raw(deadbeef)#89f8spxm")?, "raw(deadbeef)");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_checksums() -> Result<()> {
+        for desc in [
+            "raw(deadbeef)#",
+            "raw(deadbeef)#89f8spxmx",
+            "raw(deadbeef)#89f8spxn",
+            "raw(dedbeef)#89f8spxm",
+            "raw(deadbeef)##9f8spxm",
+            "raw(Ü)#00000000",
+        ] {
+            assert!(strip_checksum(desc).is_err(), "{desc}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Output Descriptors
+pub enum DescriptorError {
+    #[error("descriptor checksum `{0}` does not match, expected `{1}`")]
+    InvalidChecksum(String, String),
+    #[error("invalid character `{0}` in descriptor")]
+    InvalidCharacter(char),
+    #[error("invalid descriptor expression `{0}`")]
+    InvalidExpression(String),
+    #[error("invalid key expression `{0}`")]
+    InvalidKey(String),
+    #[error("uncompressed key `{0}` used in a segwit context")]
+    UncompressedInSegwit(String),
+    #[error("x-only key `{0}` used outside of taproot")]
+    XOnlyOutsideTaproot(String),
+    #[error("hardened derivation requires a private key in `{0}`")]
+    HardenedFromPublic(String),
+    #[error("threshold {0} out of range for {1} keys")]
+    InvalidThreshold(usize, usize),
+    #[error("{0} keys exceed the limit of {1}")]
+    TooManyKeys(usize, usize),
+    #[error("descriptor `{0}` has no address form")]
+    NoAddress(String),
+}
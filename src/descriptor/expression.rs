@@ -0,0 +1,40 @@
+use {
+    super::errors::DescriptorError,
+    anyhow::{bail, Result},
+};
+
+/// Split `name(args)` into its name and arguments
+pub(crate) fn split_call(s: &str) -> Result<(&str, &str)> {
+    match s.split_once('(') {
+        Some((name, rest)) if rest.ends_with(')') => Ok((name, &rest[..rest.len() - 1])),
+        _ => bail!(DescriptorError::InvalidExpression(s.to_string())),
+    }
+}
+
+/// Split arguments at the commas that are not nested in parentheses or braces
+pub(crate) fn split_args(s: &str) -> Result<Vec<&str>> {
+    let mut args = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| DescriptorError::InvalidExpression(s.to_string()))?
+            }
+            ',' if depth == 0 => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if depth != 0 {
+        bail!(DescriptorError::InvalidExpression(s.to_string()));
+    }
+    args.push(&s[start..]);
+
+    Ok(args)
+}
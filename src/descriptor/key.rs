@@ -0,0 +1,379 @@
+use {
+    super::errors::DescriptorError,
+    crate::{
+        hd::{
+            bip32::{ExtendedPrivateKey, ExtendedPublicKey, Fingerprint},
+            path::{ChildNumber, DerivationPath},
+        },
+        secp256k1::{keys::PrivateKey, point::Point},
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Fingerprint of the master key and path a descriptor key was derived with
+pub struct KeyOrigin {
+    fingerprint: Fingerprint,
+    path: DerivationPath,
+}
+
+impl KeyOrigin {
+    pub fn new(fingerprint: Fingerprint, path: DerivationPath) -> Self {
+        Self { fingerprint, path }
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    pub fn path(&self) -> &DerivationPath {
+        &self.path
+    }
+}
+
+impl Display for KeyOrigin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.fingerprint))?;
+        for child in self.path.children() {
+            write!(f, "/{child}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Final derivation step of a ranged extended key
+pub enum Wildcard {
+    /// The key is not ranged
+    None,
+    /// `/*`, children derived with normal derivation
+    Unhardened,
+    /// `/*'`, children derived with hardened derivation
+    Hardened,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Serialisation of a single public key
+pub enum KeyForm {
+    Compressed,
+    Uncompressed,
+    /// 32 byte BIP340 key, only valid inside `tr()`
+    XOnly,
+}
+
+#[derive(Clone)]
+/// Key material of a descriptor key
+pub enum KeySource {
+    Single { point: Point, form: KeyForm },
+    Public(ExtendedPublicKey),
+    Private(ExtendedPrivateKey),
+}
+
+#[derive(Clone)]
+/// Key expression of a descriptor, such as `[d34db33f/84'/0'/0']xpub.../0/*`
+pub struct DescriptorKey {
+    origin: Option<KeyOrigin>,
+    source: KeySource,
+    path: DerivationPath,
+    wildcard: Wildcard,
+}
+
+impl DescriptorKey {
+    /// Descriptor key of a single public key
+    pub fn single(point: Point, form: KeyForm) -> Self {
+        Self {
+            origin: None,
+            source: KeySource::Single { point, form },
+            path: DerivationPath::master(),
+            wildcard: Wildcard::None,
+        }
+    }
+
+    pub fn origin(&self) -> Option<&KeyOrigin> {
+        self.origin.as_ref()
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    /// Derivation path following the extended key, without the wildcard
+    pub fn path(&self) -> &DerivationPath {
+        &self.path
+    }
+
+    pub fn wildcard(&self) -> Wildcard {
+        self.wildcard
+    }
+
+    /// If the key derives a different public key for every index
+    pub fn is_ranged(&self) -> bool {
+        self.wildcard != Wildcard::None
+    }
+
+    /// If the key is a single uncompressed public key
+    pub fn is_uncompressed(&self) -> bool {
+        matches!(
+            self.source,
+            KeySource::Single {
+                form: KeyForm::Uncompressed,
+                ..
+            }
+        )
+    }
+
+    /// If the key is a single x-only public key
+    pub fn is_x_only(&self) -> bool {
+        matches!(
+            self.source,
+            KeySource::Single {
+                form: KeyForm::XOnly,
+                ..
+            }
+        )
+    }
+
+    /// Path from the extended key to the key at `index`
+    fn full_path(&self, index: u32) -> Result<DerivationPath> {
+        Ok(match self.wildcard {
+            Wildcard::None => self.path.clone(),
+            Wildcard::Unhardened => self.path.child(ChildNumber::normal(index)?),
+            Wildcard::Hardened => self.path.child(ChildNumber::hardened(index)?),
+        })
+    }
+
+    /// Public key at `index`, the index is ignored by keys that are not ranged
+    pub fn derive(&self, index: u32) -> Result<Point> {
+        Ok(match &self.source {
+            KeySource::Single { point, .. } => point.clone(),
+            KeySource::Public(xpub) => xpub.derive_path(&self.full_path(index)?)?.point().clone(),
+            KeySource::Private(xprv) => xprv
+                .derive_path(&self.full_path(index)?)?
+                .private_key()
+                .point()
+                .clone(),
+        })
+    }
+
+    /// Private key at `index`, if the descriptor carries it
+    pub fn derive_private(&self, index: u32) -> Result<Option<PrivateKey>> {
+        Ok(match &self.source {
+            KeySource::Private(xprv) => Some(
+                xprv.derive_path(&self.full_path(index)?)?
+                    .private_key()
+                    .clone(),
+            ),
+            _ => None,
+        })
+    }
+
+    /// Master fingerprint and full path of the key at `index`, as recorded by PSBTs
+    ///
+    /// Extended keys without an origin are treated as master keys
+    pub fn key_source(&self, index: u32) -> Result<Option<(Fingerprint, DerivationPath)>> {
+        let path = self.full_path(index)?;
+        let fingerprint = match (&self.origin, &self.source) {
+            (Some(origin), _) => return Ok(Some((origin.fingerprint, origin.path.extend(&path)))),
+            (None, KeySource::Single { .. }) => return Ok(None),
+            (None, KeySource::Public(xpub)) => xpub.fingerprint(),
+            (None, KeySource::Private(xprv)) => xprv.fingerprint(),
+        };
+
+        Ok(Some((fingerprint, path)))
+    }
+
+    /// Same key with any extended private key replaced by its extended public key
+    ///
+    /// Hardened steps after an extended private key are moved into the origin
+    pub fn to_public(&self) -> Result<Self> {
+        let KeySource::Private(xprv) = &self.source else {
+            return Ok(self.clone());
+        };
+        if self.wildcard == Wildcard::Hardened {
+            bail!(DescriptorError::HardenedFromPublic(self.to_string()));
+        }
+
+        let children = self.path.children();
+        let split = children
+            .iter()
+            .rposition(|child| child.is_hardened())
+            .map_or(0, |i| i + 1);
+        let (hardened, rest) = children.split_at(split);
+        let hardened = DerivationPath::new(hardened.to_vec());
+
+        let origin = match &self.origin {
+            Some(origin) => KeyOrigin::new(origin.fingerprint, origin.path.extend(&hardened)),
+            None => KeyOrigin::new(xprv.fingerprint(), hardened.clone()),
+        };
+
+        Ok(Self {
+            origin: (split > 0 || self.origin.is_some()).then_some(origin),
+            source: KeySource::Public(xprv.derive_path(&hardened)?.extended_public_key()),
+            path: DerivationPath::new(rest.to_vec()),
+            wildcard: self.wildcard,
+        })
+    }
+}
+
+impl Display for DescriptorKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(origin) = &self.origin {
+            write!(f, "[{origin}]")?;
+        }
+        match &self.source {
+            KeySource::Single { point, form } => match form {
+                KeyForm::Compressed => write!(f, "{}", hex::encode(point.sec(true)))?,
+                KeyForm::Uncompressed => write!(f, "{}", hex::encode(point.sec(false)))?,
+                KeyForm::XOnly => write!(f, "{}", hex::encode(point.x_only()))?,
+            },
+            KeySource::Public(xpub) => write!(f, "{xpub}")?,
+            KeySource::Private(xprv) => write!(f, "{xprv}")?,
+        }
+        for child in self.path.children() {
+            write!(f, "/{child}")?;
+        }
+        match self.wildcard {
+            Wildcard::None => Ok(()),
+            Wildcard::Unhardened => write!(f, "/*"),
+            Wildcard::Hardened => write!(f, "/*'"),
+        }
+    }
+}
+
+impl FromStr for DescriptorKey {
+    type Err = anyhow::Error;
+
+    /// Parse a key expression, context dependent restrictions are left to the descriptor
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || DescriptorError::InvalidKey(s.to_string());
+
+        let (origin, key) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, key) = rest.split_once(']').ok_or_else(invalid)?;
+                let (fingerprint, path) = match origin.split_once('/') {
+                    Some((fingerprint, path)) => {
+                        (fingerprint, path.parse().map_err(|_| invalid())?)
+                    }
+                    None => (origin, DerivationPath::master()),
+                };
+                let fingerprint = hex::decode(fingerprint)
+                    .ok()
+                    .and_then(|f| f.try_into().ok())
+                    .ok_or_else(invalid)?;
+                (Some(KeyOrigin::new(fingerprint, path)), key)
+            }
+            None => (None, s),
+        };
+
+        let mut parts = key.split('/');
+        let key = parts.next().unwrap_or_default();
+        let mut steps = parts.collect::<Vec<_>>();
+        let wildcard = match steps.last() {
+            Some(&"*") => Wildcard::Unhardened,
+            Some(&("*'" | "*h" | "*H")) => Wildcard::Hardened,
+            _ => Wildcard::None,
+        };
+        if wildcard != Wildcard::None {
+            steps.pop();
+        }
+        let path = DerivationPath::new(
+            steps
+                .iter()
+                .map(|step| step.parse().map_err(|_| invalid()))
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+
+        let is_hex = key.bytes().all(|b| b.is_ascii_hexdigit());
+        let source = match key.len() {
+            64 if is_hex => KeySource::Single {
+                point: Point::from_x_only(&hex::decode(key)?).map_err(|_| invalid())?,
+                form: KeyForm::XOnly,
+            },
+            66 | 130 if is_hex => KeySource::Single {
+                point: Point::from_sec(&hex::decode(key)?).map_err(|_| invalid())?,
+                form: if key.len() == 66 {
+                    KeyForm::Compressed
+                } else {
+                    KeyForm::Uncompressed
+                },
+            },
+            _ => match key.parse::<ExtendedPublicKey>() {
+                Ok(xpub) => KeySource::Public(xpub),
+                Err(_) => KeySource::Private(key.parse().map_err(|_| invalid())?),
+            },
+        };
+
+        match &source {
+            KeySource::Single { .. } if !path.is_empty() || wildcard != Wildcard::None => {
+                bail!(invalid())
+            }
+            KeySource::Public(_)
+                if wildcard == Wildcard::Hardened
+                    || path.children().iter().any(|child| child.is_hardened()) =>
+            {
+                bail!(DescriptorError::HardenedFromPublic(s.to_string()))
+            }
+            _ => (),
+        }
+
+        Ok(Self {
+            origin,
+            source,
+            path,
+            wildcard,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_key_expressions() -> Result<()> {
+        for s in [
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "[d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*",
+            "xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*'",
+        ] {
+            assert_eq!(s.parse::<DescriptorKey>()?.to_string(), s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn private_key_to_public() -> Result<()> {
+        let key: DescriptorKey = "xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*".parse()?;
+        let public = key.to_public()?;
+
+        assert_eq!(
+            public.to_string(),
+            "[a12b02f4/44'/0'/0']xpub6BzhLAQUDcBUfHRQHZxDF2AbcJqp4Kaeq6bzJpXrjrWuK26ymTFwkEFbxPra2bJ7yeZKbDjfDeFwxe93JMqpo5SsPJH6dZdvV9kMzJkAZ69/0/*"
+        );
+        assert_eq!(public.derive(7)?, key.derive(7)?);
+        assert_eq!(public.key_source(7)?, key.key_source(7)?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_key_expressions() -> Result<()> {
+        for s in [
+            "",
+            "[d34db33f]",
+            "[d34db3/0]0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798/0",
+            "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1'/*",
+            "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/*'",
+            "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/x",
+        ] {
+            assert!(s.parse::<DescriptorKey>().is_err(), "{s}");
+        }
+        Ok(())
+    }
+}
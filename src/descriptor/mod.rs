@@ -0,0 +1,5 @@
+pub mod checksum;
+pub mod errors;
+pub(crate) mod expression;
+pub mod key;
+pub mod output;
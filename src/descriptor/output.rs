@@ -0,0 +1,774 @@
+use {
+    super::{
+        checksum::{checksum, strip_checksum},
+        errors::DescriptorError,
+        expression::{split_args, split_call},
+        key::DescriptorKey,
+    },
+    crate::{
+        miniscript::ast::{Miniscript, ScriptContext},
+        network::Network,
+        script::{
+            address::Address,
+            opcodes::{OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_NUMEQUAL},
+            raw::Script,
+            taproot::{output_key, TapTree},
+        },
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+/// Maximum keys of `multi()` used as a bare output script, above which it is not standard
+const MAX_BARE_MULTISIG_KEYS: usize = 3;
+
+/// Maximum keys of `multi()` in legacy scripts, bounded by the 520 byte redeem script limit
+const MAX_LEGACY_MULTISIG_KEYS: usize = 15;
+
+/// Maximum keys of `multi()` in segwit v0 scripts
+const MAX_SEGWIT_MULTISIG_KEYS: usize = 20;
+
+/// Maximum keys of `multi_a()`, bounded by the tapscript stack size
+const MAX_MULTI_A_KEYS: usize = 999;
+
+/// Maximum depth of a taproot script tree
+const MAX_TAPTREE_DEPTH: usize = 128;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Script context restricting which keys and expressions are valid
+enum Context {
+    /// Bare output scripts
+    Bare,
+    /// P2SH scripts
+    Legacy,
+    /// P2WPKH and P2WSH scripts
+    Segwit,
+    /// Tapscript leaves
+    Tap,
+}
+
+#[derive(Clone)]
+/// Script expression nested inside `sh()`, `wsh()` or used as a bare output script
+pub enum ScriptExpr {
+    /// `pk(KEY)`
+    Pk(DescriptorKey),
+    /// `pkh(KEY)`
+    Pkh(DescriptorKey),
+    /// `multi(k,KEY,...)` and `sortedmulti(k,KEY,...)`
+    Multi {
+        k: usize,
+        keys: Vec<DescriptorKey>,
+        sorted: bool,
+    },
+    /// Any other miniscript, only inside `wsh()`
+    Miniscript(Miniscript),
+}
+
+#[derive(Clone)]
+/// Leaf script of a `tr()` script tree
+pub enum TapLeafExpr {
+    /// `pk(KEY)`
+    Pk(DescriptorKey),
+    /// `multi_a(k,KEY,...)` and `sortedmulti_a(k,KEY,...)`
+    MultiA {
+        k: usize,
+        keys: Vec<DescriptorKey>,
+        sorted: bool,
+    },
+    /// Any other miniscript
+    Miniscript(Miniscript),
+}
+
+#[derive(Clone)]
+/// Script tree of a `tr()` descriptor, `{A,B}` being a branch
+pub enum TapTreeExpr {
+    Leaf(TapLeafExpr),
+    Branch(Box<TapTreeExpr>, Box<TapTreeExpr>),
+}
+
+#[derive(Clone)]
+/// Output script descriptor as specified by BIP380 onwards
+pub enum Descriptor {
+    /// Bare script, `pk()` or `multi()` at the top level
+    Bare(ScriptExpr),
+    /// `pkh(KEY)`
+    Pkh(DescriptorKey),
+    /// `wpkh(KEY)`
+    Wpkh(DescriptorKey),
+    /// `sh(wpkh(KEY))`
+    ShWpkh(DescriptorKey),
+    /// `sh(SCRIPT)`
+    Sh(ScriptExpr),
+    /// `wsh(SCRIPT)`
+    Wsh(ScriptExpr),
+    /// `sh(wsh(SCRIPT))`
+    ShWsh(ScriptExpr),
+    /// `tr(KEY)` or `tr(KEY,TREE)`
+    Tr {
+        internal_key: DescriptorKey,
+        tree: Option<TapTreeExpr>,
+    },
+    /// `addr(ADDRESS)`
+    Addr(Address),
+    /// `raw(HEX)`
+    Raw(Script),
+}
+
+/// Script of a `multi()` expression with the keys at `index`
+fn multi_script(k: usize, keys: &[DescriptorKey], sorted: bool, index: u32) -> Result<Script> {
+    let mut secs = keys
+        .iter()
+        .map(|key| Ok(key.derive(index)?.sec(!key.is_uncompressed())))
+        .collect::<Result<Vec<_>>>()?;
+    if sorted {
+        secs.sort();
+    }
+
+    Ok(secs
+        .iter()
+        .fold(Script::new().push_int(k as i64), |script, sec| {
+            script.push_slice(sec)
+        })
+        .push_int(keys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG))
+}
+
+/// Public versions of `keys`
+fn to_public(keys: &[DescriptorKey]) -> Result<Vec<DescriptorKey>> {
+    keys.iter().map(|key| key.to_public()).collect()
+}
+
+fn write_multi(f: &mut Formatter<'_>, name: &str, k: usize, keys: &[DescriptorKey]) -> fmt::Result {
+    write!(f, "{name}({k}")?;
+    for key in keys {
+        write!(f, ",{key}")?;
+    }
+    write!(f, ")")
+}
+
+impl ScriptExpr {
+    /// Script with the keys at `index`
+    pub fn to_script(&self, index: u32) -> Result<Script> {
+        match self {
+            Self::Pk(key) => Ok(Script::p2pk(&key.derive(index)?, !key.is_uncompressed())),
+            Self::Pkh(key) => Ok(Script::p2pkh(
+                &key.derive(index)?.hash160(!key.is_uncompressed()),
+            )),
+            Self::Multi { k, keys, sorted } => multi_script(*k, keys, *sorted, index),
+            Self::Miniscript(ms) => ms.to_script(ScriptContext::Segwitv0, index),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::Pk(key) | Self::Pkh(key) => vec![key],
+            Self::Multi { keys, .. } => keys.iter().collect(),
+            Self::Miniscript(ms) => ms.keys(),
+        }
+    }
+
+    fn to_public(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Pk(key) => Self::Pk(key.to_public()?),
+            Self::Pkh(key) => Self::Pkh(key.to_public()?),
+            Self::Multi { k, keys, sorted } => Self::Multi {
+                k: *k,
+                keys: to_public(keys)?,
+                sorted: *sorted,
+            },
+            Self::Miniscript(ms) => Self::Miniscript(ms.translate_keys(&|key| key.to_public())?),
+        })
+    }
+}
+
+impl Display for ScriptExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pk(key) => write!(f, "pk({key})"),
+            Self::Pkh(key) => write!(f, "pkh({key})"),
+            Self::Multi { k, keys, sorted } => {
+                write_multi(f, if *sorted { "sortedmulti" } else { "multi" }, *k, keys)
+            }
+            Self::Miniscript(ms) => write!(f, "{ms}"),
+        }
+    }
+}
+
+impl TapLeafExpr {
+    /// Tapscript with the keys at `index`
+    pub fn to_script(&self, index: u32) -> Result<Script> {
+        match self {
+            Self::Pk(key) => Ok(Script::new()
+                .push_slice(&key.derive(index)?.x_only())
+                .push_opcode(OP_CHECKSIG)),
+            Self::MultiA { k, keys, sorted } => {
+                let mut x_only = keys
+                    .iter()
+                    .map(|key| Ok(key.derive(index)?.x_only()))
+                    .collect::<Result<Vec<_>>>()?;
+                if *sorted {
+                    x_only.sort();
+                }
+
+                let mut script = Script::new();
+                for (i, x) in x_only.iter().enumerate() {
+                    script = script.push_slice(x).push_opcode(if i == 0 {
+                        OP_CHECKSIG
+                    } else {
+                        OP_CHECKSIGADD
+                    });
+                }
+                Ok(script.push_int(*k as i64).push_opcode(OP_NUMEQUAL))
+            }
+            Self::Miniscript(ms) => ms.to_script(ScriptContext::Tapscript, index),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::Pk(key) => vec![key],
+            Self::MultiA { keys, .. } => keys.iter().collect(),
+            Self::Miniscript(ms) => ms.keys(),
+        }
+    }
+
+    fn to_public(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Pk(key) => Self::Pk(key.to_public()?),
+            Self::MultiA { k, keys, sorted } => Self::MultiA {
+                k: *k,
+                keys: to_public(keys)?,
+                sorted: *sorted,
+            },
+            Self::Miniscript(ms) => Self::Miniscript(ms.translate_keys(&|key| key.to_public())?),
+        })
+    }
+}
+
+impl Display for TapLeafExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pk(key) => write!(f, "pk({key})"),
+            Self::MultiA { k, keys, sorted } => write_multi(
+                f,
+                if *sorted { "sortedmulti_a" } else { "multi_a" },
+                *k,
+                keys,
+            ),
+            Self::Miniscript(ms) => write!(f, "{ms}"),
+        }
+    }
+}
+
+impl TapTreeExpr {
+    /// Script tree with the keys at `index`
+    pub fn to_tap_tree(&self, index: u32) -> Result<TapTree> {
+        Ok(match self {
+            Self::Leaf(leaf) => TapTree::Leaf(leaf.to_script(index)?),
+            Self::Branch(left, right) => TapTree::Branch(
+                Box::new(left.to_tap_tree(index)?),
+                Box::new(right.to_tap_tree(index)?),
+            ),
+        })
+    }
+
+    /// Leaves of the tree from left to right
+    pub fn leaves(&self) -> Vec<&TapLeafExpr> {
+        match self {
+            Self::Leaf(leaf) => vec![leaf],
+            Self::Branch(left, right) => [left.leaves(), right.leaves()].concat(),
+        }
+    }
+
+    fn to_public(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Leaf(leaf) => Self::Leaf(leaf.to_public()?),
+            Self::Branch(left, right) => {
+                Self::Branch(Box::new(left.to_public()?), Box::new(right.to_public()?))
+            }
+        })
+    }
+}
+
+impl Display for TapTreeExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Leaf(leaf) => write!(f, "{leaf}"),
+            Self::Branch(left, right) => write!(f, "{{{left},{right}}}"),
+        }
+    }
+}
+
+impl Descriptor {
+    /// Output script of the descriptor at `index`
+    pub fn script_pubkey(&self, index: u32) -> Result<Script> {
+        Ok(match self {
+            Self::Bare(expr) => expr.to_script(index)?,
+            Self::Pkh(key) => Script::p2pkh(&key.derive(index)?.hash160(!key.is_uncompressed())),
+            Self::Wpkh(key) => Script::p2wpkh(&key.derive(index)?.hash160(true)),
+            Self::ShWpkh(_) | Self::Sh(_) | Self::ShWsh(_) => self
+                .redeem_script(index)?
+                .map(|redeem| redeem.to_p2sh())
+                .unwrap_or_default(),
+            Self::Wsh(expr) => expr.to_script(index)?.to_p2wsh(),
+            Self::Tr { internal_key, .. } => {
+                let merkle_root = self.tap_tree(index)?.map(|tree| tree.merkle_root());
+                let output = output_key(&internal_key.derive(index)?, merkle_root.as_ref())?;
+                Script::p2tr(&output.x_only())
+            }
+            Self::Addr(address) => address.script_pubkey(),
+            Self::Raw(script) => script.clone(),
+        })
+    }
+
+    /// Redeem script revealed when spending a P2SH output at `index`
+    pub fn redeem_script(&self, index: u32) -> Result<Option<Script>> {
+        Ok(match self {
+            Self::ShWpkh(key) => Some(Script::p2wpkh(&key.derive(index)?.hash160(true))),
+            Self::Sh(expr) => Some(expr.to_script(index)?),
+            Self::ShWsh(expr) => Some(expr.to_script(index)?.to_p2wsh()),
+            _ => None,
+        })
+    }
+
+    /// Witness script revealed when spending a P2WSH output at `index`
+    pub fn witness_script(&self, index: u32) -> Result<Option<Script>> {
+        Ok(match self {
+            Self::Wsh(expr) | Self::ShWsh(expr) => Some(expr.to_script(index)?),
+            _ => None,
+        })
+    }
+
+    /// Taproot script tree at `index`, if the descriptor has one
+    pub fn tap_tree(&self, index: u32) -> Result<Option<TapTree>> {
+        match self {
+            Self::Tr {
+                tree: Some(tree), ..
+            } => Ok(Some(tree.to_tap_tree(index)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Address of the output script at `index`
+    pub fn address(&self, index: u32, network: Network) -> Result<Address> {
+        match self {
+            Self::Bare(_) | Self::Raw(_) => {
+                Address::from_script(&self.script_pubkey(index)?, network)
+                    .map_err(|_| DescriptorError::NoAddress(self.to_string()).into())
+            }
+            Self::Addr(address) => Ok(Address::new(network, address.payload().clone())),
+            _ => Address::from_script(&self.script_pubkey(index)?, network),
+        }
+    }
+
+    /// All key expressions of the descriptor
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::Bare(expr) | Self::Sh(expr) | Self::Wsh(expr) | Self::ShWsh(expr) => expr.keys(),
+            Self::Pkh(key) | Self::Wpkh(key) | Self::ShWpkh(key) => vec![key],
+            Self::Tr { internal_key, tree } => {
+                let mut keys = vec![internal_key];
+                if let Some(tree) = tree {
+                    keys.extend(tree.leaves().into_iter().flat_map(|leaf| leaf.keys()));
+                }
+                keys
+            }
+            Self::Addr(_) | Self::Raw(_) => vec![],
+        }
+    }
+
+    /// If the descriptor describes a different output script for every index
+    pub fn is_ranged(&self) -> bool {
+        self.keys().iter().any(|key| key.is_ranged())
+    }
+
+    /// Same descriptor with extended private keys replaced by extended public keys
+    pub fn to_public(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Bare(expr) => Self::Bare(expr.to_public()?),
+            Self::Pkh(key) => Self::Pkh(key.to_public()?),
+            Self::Wpkh(key) => Self::Wpkh(key.to_public()?),
+            Self::ShWpkh(key) => Self::ShWpkh(key.to_public()?),
+            Self::Sh(expr) => Self::Sh(expr.to_public()?),
+            Self::Wsh(expr) => Self::Wsh(expr.to_public()?),
+            Self::ShWsh(expr) => Self::ShWsh(expr.to_public()?),
+            Self::Tr { internal_key, tree } => Self::Tr {
+                internal_key: internal_key.to_public()?,
+                tree: tree.as_ref().map(|tree| tree.to_public()).transpose()?,
+            },
+            Self::Addr(_) | Self::Raw(_) => self.clone(),
+        })
+    }
+}
+
+impl Display for Descriptor {
+    /// Descriptor with its checksum, the alternate form `{:#}` omits the checksum
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let body = match self {
+            Self::Bare(expr) => expr.to_string(),
+            Self::Pkh(key) => format!("pkh({key})"),
+            Self::Wpkh(key) => format!("wpkh({key})"),
+            Self::ShWpkh(key) => format!("sh(wpkh({key}))"),
+            Self::Sh(expr) => format!("sh({expr})"),
+            Self::Wsh(expr) => format!("wsh({expr})"),
+            Self::ShWsh(expr) => format!("sh(wsh({expr}))"),
+            Self::Tr {
+                internal_key,
+                tree: None,
+            } => format!("tr({internal_key})"),
+            Self::Tr {
+                internal_key,
+                tree: Some(tree),
+            } => format!("tr({internal_key},{tree})"),
+            Self::Addr(address) => format!("addr({address})"),
+            Self::Raw(script) => format!("raw({})", hex::encode(script.as_bytes())),
+        };
+
+        if f.alternate() {
+            write!(f, "{body}")
+        } else {
+            write!(f, "{body}#{}", checksum(&body).map_err(|_| fmt::Error)?)
+        }
+    }
+}
+
+/// Parse a key expression, checking it is allowed in `context`
+fn parse_key(s: &str, context: Context) -> Result<DescriptorKey> {
+    let key: DescriptorKey = s.parse()?;
+    if key.is_uncompressed() && !matches!(context, Context::Bare | Context::Legacy) {
+        bail!(DescriptorError::UncompressedInSegwit(s.to_string()));
+    }
+    if key.is_x_only() && context != Context::Tap {
+        bail!(DescriptorError::XOnlyOutsideTaproot(s.to_string()));
+    }
+
+    Ok(key)
+}
+
+/// Parse the threshold and keys of a multisig expression
+fn parse_multi(
+    args: &str,
+    context: Context,
+    max_keys: usize,
+) -> Result<(usize, Vec<DescriptorKey>)> {
+    let args = split_args(args)?;
+    let k = args[0]
+        .parse()
+        .map_err(|_| DescriptorError::InvalidExpression(args[0].to_string()))?;
+    let keys = args[1..]
+        .iter()
+        .map(|key| parse_key(key, context))
+        .collect::<Result<Vec<_>>>()?;
+
+    if keys.len() > max_keys {
+        bail!(DescriptorError::TooManyKeys(keys.len(), max_keys));
+    }
+    if k == 0 || k > keys.len() {
+        bail!(DescriptorError::InvalidThreshold(k, keys.len()));
+    }
+
+    Ok((k, keys))
+}
+
+impl ScriptExpr {
+    fn parse(s: &str, context: Context) -> Result<Self> {
+        // miniscript fragments such as `1` are not calls
+        let (name, args) = split_call(s).unwrap_or_default();
+        let max_keys = match context {
+            Context::Bare => MAX_BARE_MULTISIG_KEYS,
+            Context::Segwit => MAX_SEGWIT_MULTISIG_KEYS,
+            _ => MAX_LEGACY_MULTISIG_KEYS,
+        };
+
+        Ok(match name {
+            "pk" => Self::Pk(parse_key(args, context)?),
+            "pkh" => Self::Pkh(parse_key(args, context)?),
+            "multi" | "sortedmulti" => {
+                let (k, keys) = parse_multi(args, context, max_keys)?;
+                Self::Multi {
+                    k,
+                    keys,
+                    sorted: name == "sortedmulti",
+                }
+            }
+            _ if context == Context::Segwit => {
+                Self::Miniscript(Miniscript::parse_top_level(s, ScriptContext::Segwitv0)?)
+            }
+            _ => bail!(DescriptorError::InvalidExpression(s.to_string())),
+        })
+    }
+}
+
+impl TapTreeExpr {
+    fn parse(s: &str, depth: usize) -> Result<Self> {
+        if depth > MAX_TAPTREE_DEPTH {
+            bail!(DescriptorError::InvalidExpression(s.to_string()));
+        }
+
+        if let Some(inner) = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            let [left, right] = split_args(inner)?[..] else {
+                bail!(DescriptorError::InvalidExpression(s.to_string()));
+            };
+            return Ok(Self::Branch(
+                Box::new(Self::parse(left, depth + 1)?),
+                Box::new(Self::parse(right, depth + 1)?),
+            ));
+        }
+
+        let (name, args) = split_call(s).unwrap_or_default();
+        Ok(Self::Leaf(match name {
+            "pk" => TapLeafExpr::Pk(parse_key(args, Context::Tap)?),
+            "multi_a" | "sortedmulti_a" => {
+                let (k, keys) = parse_multi(args, Context::Tap, MAX_MULTI_A_KEYS)?;
+                TapLeafExpr::MultiA {
+                    k,
+                    keys,
+                    sorted: name == "sortedmulti_a",
+                }
+            }
+            _ => TapLeafExpr::Miniscript(Miniscript::parse_top_level(s, ScriptContext::Tapscript)?),
+        }))
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = anyhow::Error;
+
+    /// Parse a descriptor, verifying its checksum if one is given
+    fn from_str(s: &str) -> Result<Self> {
+        let s = strip_checksum(s)?;
+        let (name, args) = split_call(s)?;
+
+        Ok(match name {
+            "pk" | "multi" | "sortedmulti" => Self::Bare(ScriptExpr::parse(s, Context::Bare)?),
+            "pkh" => Self::Pkh(parse_key(args, Context::Legacy)?),
+            "wpkh" => Self::Wpkh(parse_key(args, Context::Segwit)?),
+            "sh" => match split_call(args)? {
+                ("wpkh", key) => Self::ShWpkh(parse_key(key, Context::Segwit)?),
+                ("wsh", expr) => Self::ShWsh(ScriptExpr::parse(expr, Context::Segwit)?),
+                _ => Self::Sh(ScriptExpr::parse(args, Context::Legacy)?),
+            },
+            "wsh" => Self::Wsh(ScriptExpr::parse(args, Context::Segwit)?),
+            "tr" => {
+                let (key, tree) = match split_args(args)?[..] {
+                    [key] => (key, None),
+                    [key, tree] => (key, Some(TapTreeExpr::parse(tree, 0)?)),
+                    _ => bail!(DescriptorError::InvalidExpression(s.to_string())),
+                };
+                Self::Tr {
+                    internal_key: parse_key(key, Context::Tap)?,
+                    tree,
+                }
+            }
+            "addr" => Self::Addr(args.parse()?),
+            "raw" => Self::Raw(Script::from_bytes(
+                hex::decode(args).map_err(|_| DescriptorError::InvalidExpression(s.to_string()))?,
+            )),
+            _ => bail!(DescriptorError::InvalidExpression(s.to_string())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::hd::{bip39::Mnemonic, path::DerivationPath},
+    };
+
+    const KEY: &str = "020000000000000000000000000000000000000000000000000000000000000002";
+
+    #[test]
+    fn single_key_checksums() -> Result<()> {
+        for (desc, checksum) in [
+            (format!("pk({KEY})"), "7yxkn84h"),
+            (format!("pkh({KEY})"), "ma7nspkf"),
+            (format!("wpkh({KEY})"), "d3xz2xye"),
+            (format!("sh(wpkh({KEY}))"), "45zpjtet"),
+            (format!("tr({KEY})"), "8hc7wq5h"),
+        ] {
+            let descriptor: Descriptor = desc.parse()?;
+
+            assert_eq!(descriptor.to_string(), format!("{desc}#{checksum}"));
+            assert_eq!(format!("{descriptor:#}"), desc);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sortedmulti_addresses() -> Result<()> {
+        for (one, two, address) in [
+            (
+                "sh(sortedmulti(1,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556,0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352))#uetvewm2",
+                "sh(sortedmulti(1,0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352,03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))#7l8smyg9",
+                "3JZJNxvDKe6Y55ZaF5223XHwfF2eoMNnoV",
+            ),
+            (
+                "wsh(sortedmulti(1,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH))#7etm7zk7",
+                "wsh(sortedmulti(1,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB))#ppmeel9k",
+                "bc1qpq2cfgz5lktxzr5zqv7nrzz46hsvq3492ump9pz8rzcl8wqtwqcspx5y6a",
+            ),
+            (
+                "sh(wsh(sortedmulti(1,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/1/0/*,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH/0/0/*)))#u60cee0u",
+                "sh(wsh(sortedmulti(1,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH/0/0/*,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/1/0/*)))#75dkf44w",
+                "325zcVBN5o2eqqqtGwPjmtDd8dJRyYP82s",
+            ),
+        ] {
+            for desc in [one, two] {
+                let descriptor: Descriptor = desc.parse()?;
+
+                assert_eq!(descriptor.to_string(), desc);
+                assert_eq!(
+                    descriptor.address(5, Network::Mainnet)?.to_string(),
+                    address
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn private_descriptor_to_public() -> Result<()> {
+        let descriptor: Descriptor = "wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/44'/0'/0'/0/*)".parse()?;
+
+        assert!(descriptor.is_ranged());
+        assert_eq!(
+            descriptor.to_public()?.to_string(),
+            "wpkh([2cbe2a6d/44'/0'/0']tpubDCvNhURocXGZsLNqWcqD3syHTqPXrMSTwi8feKVwAcpi29oYKsDD3Vex7x2TDneKMVN23RbLprfxB69v94iYqdaYHsVz3kPR37NQXeqouVz/0/*)#nhdxg96s"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn standard_wallet_addresses() -> Result<()> {
+        let mnemonic: Mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".parse()?;
+        let master = mnemonic.to_master_key(Network::Mainnet, "")?;
+
+        for (wrapper, purpose, address) in [
+            ("pkh", 44, "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA"),
+            ("wpkh", 84, "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"),
+            (
+                "tr",
+                86,
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+            ),
+        ] {
+            let path: DerivationPath = format!("m/{purpose}'/0'/0'").parse()?;
+            let xpub = master.derive_path(&path)?.extended_public_key();
+            let descriptor: Descriptor = format!(
+                "{wrapper}([{}/{purpose}'/0'/0']{xpub}/0/*)",
+                hex::encode(master.fingerprint())
+            )
+            .parse()?;
+
+            assert_eq!(
+                descriptor.address(0, Network::Mainnet)?.to_string(),
+                address
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn taproot_script_tree() -> Result<()> {
+        let descriptor: Descriptor = format!(
+            "tr({KEY},{{pk(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798),sortedmulti_a(1,{KEY},79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)}})"
+        )
+        .parse()?;
+        let tree = descriptor.tap_tree(0)?.unwrap();
+
+        assert_eq!(tree.leaves().len(), 2);
+        assert!(descriptor.script_pubkey(0)?.is_p2tr());
+        assert_eq!(descriptor.keys().len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn miniscript_descriptors() -> Result<()> {
+        assert_eq!(
+            "wsh(1)".parse::<Descriptor>()?.to_string(),
+            "wsh(1)#mrg7xj7p"
+        );
+        assert_eq!(
+            "sh(wsh(1))".parse::<Descriptor>()?.to_string(),
+            "sh(wsh(1))#hcyfl07f"
+        );
+
+        let mnemonic: Mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".parse()?;
+        let xprv = mnemonic.to_master_key(Network::Mainnet, "")?;
+        let descriptor: Descriptor =
+            format!("wsh(or_d(pk({KEY}),and_v(v:pkh({xprv}/0/*),older(144))))").parse()?;
+        let public = descriptor.to_public()?;
+
+        assert!(public.to_string().contains("xpub"));
+        assert_eq!(public.witness_script(3)?, descriptor.witness_script(3)?);
+        assert!(descriptor.is_ranged());
+
+        let tr: Descriptor = format!("tr({KEY},and_v(v:pk({xprv}/1/*),after(700000)))").parse()?;
+        assert_eq!(tr.tap_tree(0)?.unwrap().leaves().len(), 1);
+        assert!(
+            "sh(and_v(v:pk(020000000000000000000000000000000000000000000000000000000000000002),1))"
+                .parse::<Descriptor>()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn raw_and_addr() -> Result<()> {
+        let raw: Descriptor = "raw(deadbeef)#89f8spxm".parse()?;
+        let addr: Descriptor = "addr(3JZJNxvDKe6Y55ZaF5223XHwfF2eoMNnoV)".parse()?;
+
+        assert_eq!(raw.script_pubkey(0)?.as_bytes(), [0xde, 0xad, 0xbe, 0xef]);
+        assert!(raw.address(0, Network::Mainnet).is_err());
+        assert!(addr.script_pubkey(0)?.is_p2sh());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_descriptors() -> Result<()> {
+        let uncompressed = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let x_only = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        for desc in [
+            format!("wpkh({uncompressed})"),
+            format!("wsh(pk({uncompressed}))"),
+            format!("pkh({x_only})"),
+            format!("sh(multi(0,{KEY}))"),
+            format!("sh(multi(2,{KEY}))"),
+            format!("wpkh({KEY})#00000000"),
+            format!("wpkh({KEY}"),
+            format!("wpkh({KEY}))"),
+            format!("tr({KEY},{{pk({KEY})}})"),
+            format!("foo({KEY})"),
+            format!("sh(sh(pk({KEY})))"),
+        ] {
+            assert!(desc.parse::<Descriptor>().is_err(), "{desc}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bare_multi_key_limit() -> Result<()> {
+        let keys = |n: usize| vec![KEY; n].join(",");
+
+        assert!(format!("multi(1,{})", keys(3))
+            .parse::<Descriptor>()
+            .is_ok());
+        assert_eq!(
+            format!("multi(1,{})", keys(4))
+                .parse::<Descriptor>()
+                .err()
+                .unwrap()
+                .downcast::<DescriptorError>()?,
+            DescriptorError::TooManyKeys(4, MAX_BARE_MULTISIG_KEYS)
+        );
+        // the bare limit does not apply inside P2SH
+        assert!(format!("sh(multi(1,{}))", keys(4))
+            .parse::<Descriptor>()
+            .is_ok());
+        Ok(())
+    }
+}
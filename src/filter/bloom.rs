@@ -0,0 +1,318 @@
+use {
+    super::errors::FilterError,
+    crate::{
+        script::raw::{Instruction, Script},
+        transaction::tx::{OutPoint, Transaction},
+        utils::{
+            encoding::{read_array, read_var_bytes, var_bytes},
+            hash::murmur3_32,
+        },
+    },
+    anyhow::{bail, Result},
+    std::{f64::consts::LN_2, io::Read},
+};
+
+/// Do not add the outpoints of matching outputs to the filter
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+
+/// Add the outpoint of every output with a matching data push to the filter
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+
+/// Add the outpoint of matching pay to public key and bare multisig outputs to the filter
+pub const BLOOM_UPDATE_P2PUBKEY_ONLY: u8 = 2;
+
+/// Bits of the flags that select how the filter is updated
+pub const BLOOM_UPDATE_MASK: u8 = 3;
+
+/// Largest filter peers accept, in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+
+/// Largest number of hash functions peers accept
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Largest element that can be added with a filteradd message
+pub const MAX_FILTERADD_SIZE: usize = 520;
+
+/// Multiplier of the hash function number in the seed of each hash function
+const SEED_MULTIPLIER: u32 = 0xfba4c795;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// BIP37 bloom filter, loaded into a peer to receive only the transactions that match it
+pub struct BloomFilter {
+    data: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+    flags: u8,
+}
+
+impl BloomFilter {
+    /// Filter sized to hold `elements` elements with a false positive rate of `fp_rate`, within
+    /// the limits peers accept
+    ///
+    /// `tweak` varies the hash functions, so that filters of different wallets holding the same
+    /// elements set different bits.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Self {
+        let elements = elements.max(1) as f64;
+        let bits = (-1.0 / (LN_2 * LN_2) * elements * fp_rate.ln()) as usize;
+        let size = bits.min(MAX_BLOOM_FILTER_SIZE * 8) / 8;
+        let hash_funcs = ((size * 8) as f64 / elements * LN_2) as u32;
+
+        Self {
+            data: vec![0; size],
+            hash_funcs: hash_funcs.min(MAX_HASH_FUNCS),
+            tweak,
+            flags,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    pub fn tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Bit set by hash function `n` for `data`
+    fn bit(&self, n: u32, data: &[u8]) -> usize {
+        let seed = n.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        murmur3_32(seed, data) as usize % (self.data.len() * 8)
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        // an empty filter has no bits to set
+        if self.data.is_empty() {
+            return;
+        }
+        for n in 0..self.hash_funcs {
+            let bit = self.bit(n, data);
+            self.data[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// If `data` is in the filter, or collides with the elements of it
+    pub fn contains(&self, data: &[u8]) -> bool {
+        // an empty filter matches everything
+        if self.data.is_empty() {
+            return true;
+        }
+        (0..self.hash_funcs).all(|n| {
+            let bit = self.bit(n, data);
+            self.data[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&outpoint.serialise());
+    }
+
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&outpoint.serialise())
+    }
+
+    /// If the filter contains any data pushed by `script`
+    fn contains_push(&self, script: &Script) -> bool {
+        script
+            .instructions()
+            .map_while(Result::ok)
+            .any(|instruction| match instruction {
+                Instruction::Push(data) => !data.is_empty() && self.contains(data),
+                Instruction::Op(_) => false,
+            })
+    }
+
+    /// If `tx` matches the filter, as a peer holding the filter would decide
+    ///
+    /// A transaction matches if the filter contains its txid, data pushed by one of its outputs,
+    /// an outpoint it spends or data pushed by one of its inputs. The outpoints of matching
+    /// outputs are added to the filter according to its flags, so that transactions spending them
+    /// match too.
+    pub fn is_relevant_and_update(&mut self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        let mut found = self.contains(&txid);
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            if !self.contains_push(&output.script_pubkey) {
+                continue;
+            }
+            found = true;
+            let script = &output.script_pubkey;
+            match self.flags & BLOOM_UPDATE_MASK {
+                BLOOM_UPDATE_ALL => self.insert_outpoint(&OutPoint::new(txid, vout as u32)),
+                BLOOM_UPDATE_P2PUBKEY_ONLY if script.is_p2pk() || script.is_multisig() => {
+                    self.insert_outpoint(&OutPoint::new(txid, vout as u32))
+                }
+                _ => {}
+            }
+        }
+        if found {
+            return true;
+        }
+
+        tx.inputs.iter().any(|input| {
+            self.contains_outpoint(&input.previous_output) || self.contains_push(&input.script_sig)
+        })
+    }
+
+    /// Parse the payload of a filterload message, refusing filters larger than peers accept
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let data = read_var_bytes(reader)?;
+        if data.len() > MAX_BLOOM_FILTER_SIZE {
+            bail!(FilterError::OversizedBloomFilter(
+                data.len(),
+                MAX_BLOOM_FILTER_SIZE
+            ));
+        }
+        let hash_funcs = u32::from_le_bytes(read_array(reader)?);
+        if hash_funcs > MAX_HASH_FUNCS {
+            bail!(FilterError::TooManyHashFuncs(hash_funcs, MAX_HASH_FUNCS));
+        }
+        let tweak = u32::from_le_bytes(read_array(reader)?);
+        let [flags] = read_array(reader)?;
+
+        Ok(Self {
+            data,
+            hash_funcs,
+            tweak,
+            flags,
+        })
+    }
+
+    /// Payload of a filterload message
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            var_bytes(&self.data).as_slice(),
+            &self.hash_funcs.to_le_bytes(),
+            &self.tweak.to_le_bytes(),
+            &[self.flags],
+        ]
+        .concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::transaction::tx::{TxIn, TxOut},
+    };
+
+    #[test]
+    fn create_insert_serialise() -> Result<()> {
+        // from Bitcoin Core's bloom tests
+        let mut filter = BloomFilter::new(3, 0.01, 0, BLOOM_UPDATE_ALL);
+        let first = hex::decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8")?;
+        filter.insert(&first);
+        assert!(filter.contains(&first));
+        // one bit different
+        assert!(!filter.contains(&hex::decode("19108ad8ed9bb6274d3980bab5a85c048f0950c8")?));
+
+        filter.insert(&hex::decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee")?);
+        let third = hex::decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5")?;
+        filter.insert(&third);
+        assert!(filter.contains(&third));
+
+        assert_eq!(
+            hex::encode(filter.serialise()),
+            "03614e9b050000000000000001"
+        );
+        assert_eq!(
+            BloomFilter::parse(&mut filter.serialise().as_slice())?,
+            filter
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn create_insert_serialise_with_tweak() -> Result<()> {
+        let mut filter = BloomFilter::new(3, 0.01, 2147483649, BLOOM_UPDATE_ALL);
+        filter.insert(&hex::decode("99108ad8ed9bb6274d3980bab5a85c048f0950c8")?);
+        filter.insert(&hex::decode("b5a2c786d9ef4658287ced5914b37a1b4aa32eee")?);
+        filter.insert(&hex::decode("b9300670b4c5366e95b2699e8b18bc75e5f729c5")?);
+
+        assert_eq!(
+            hex::encode(filter.serialise()),
+            "03ce4299050000000100008001"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_oversized_filters() {
+        let mut payload = var_bytes(&[0xff; MAX_BLOOM_FILTER_SIZE + 1]);
+        payload.extend([1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let err = BloomFilter::parse(&mut payload.as_slice()).unwrap_err();
+        assert_eq!(
+            err.downcast::<FilterError>().unwrap(),
+            FilterError::OversizedBloomFilter(MAX_BLOOM_FILTER_SIZE + 1, MAX_BLOOM_FILTER_SIZE)
+        );
+
+        let payload = hex::decode("01ff330000000000000000").unwrap();
+        let err = BloomFilter::parse(&mut payload.as_slice()).unwrap_err();
+        assert_eq!(
+            err.downcast::<FilterError>().unwrap(),
+            FilterError::TooManyHashFuncs(51, MAX_HASH_FUNCS)
+        );
+
+        // the size is capped rather than refused when creating a filter
+        let filter = BloomFilter::new(1_000_000, 0.0001, 0, BLOOM_UPDATE_NONE);
+        assert_eq!(filter.data().len(), MAX_BLOOM_FILTER_SIZE);
+        assert!(filter.hash_funcs() <= MAX_HASH_FUNCS);
+    }
+
+    #[test]
+    fn matches_transactions() {
+        let key = [2; 33];
+        let mut funding = Transaction::new(2, 0);
+        funding.inputs.push(TxIn::new(OutPoint::new([1; 32], 0)));
+        funding
+            .outputs
+            .push(TxOut::new(1000, Script::p2wpkh(&[3; 20])));
+        funding
+            .outputs
+            .push(TxOut::new(2000, Script::new().push_slice(&key)));
+        let mut spending = Transaction::new(2, 0);
+        spending
+            .inputs
+            .push(TxIn::new(OutPoint::new(funding.txid(), 1)));
+
+        // by txid
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BLOOM_UPDATE_NONE);
+        filter.insert(&funding.txid());
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(!filter.is_relevant_and_update(&spending));
+
+        // by an output data push, adding the outpoint so that its spend matches
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BLOOM_UPDATE_ALL);
+        filter.insert(&key);
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(filter.contains_outpoint(&OutPoint::new(funding.txid(), 1)));
+        assert!(!filter.contains_outpoint(&OutPoint::new(funding.txid(), 0)));
+        assert!(filter.is_relevant_and_update(&spending));
+
+        // a push that is not a public key script does not update a p2pubkey only filter
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BLOOM_UPDATE_P2PUBKEY_ONLY);
+        filter.insert(&key);
+        assert!(filter.is_relevant_and_update(&funding));
+        assert!(!filter.is_relevant_and_update(&spending));
+
+        // by a spent outpoint
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BLOOM_UPDATE_NONE);
+        filter.insert_outpoint(&OutPoint::new([1; 32], 0));
+        assert!(filter.is_relevant_and_update(&funding));
+
+        // by an input data push
+        let mut filter = BloomFilter::new(10, 0.000001, 0, BLOOM_UPDATE_NONE);
+        filter.insert(&[4; 72]);
+        assert!(!filter.is_relevant_and_update(&spending));
+        spending.inputs[0].script_sig = Script::new().push_slice(&[4; 72]);
+        assert!(filter.is_relevant_and_update(&spending));
+    }
+}
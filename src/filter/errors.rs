@@ -0,0 +1,14 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to block filters
+pub enum FilterError {
+    #[error("filter ends in the middle of an element")]
+    Truncated,
+    #[error("filter of {0} elements overflows its hash range")]
+    TooManyElements(u64),
+    #[error("bloom filter of {0} bytes is larger than {1} bytes")]
+    OversizedBloomFilter(usize, usize),
+    #[error("bloom filter with {0} hash functions, more than {1}")]
+    TooManyHashFuncs(u32, u32),
+}
@@ -0,0 +1,235 @@
+use {
+    super::errors::FilterError,
+    crate::utils::{
+        encoding::{compact_size, read_compact_size},
+        hash::siphash24,
+    },
+    anyhow::{bail, Result},
+};
+
+/// Parameters of a Golomb-coded set, as specified by BIP158
+///
+/// Items are hashed with SipHash into the range `[0, N * M)` for a set of `N` items, so that any
+/// other item matches with probability `1 / M`. The sorted hashes are then Golomb-Rice coded as
+/// differences, with the low `P` bits written as they are.
+pub struct Gcs {
+    k0: u64,
+    k1: u64,
+    p: u8,
+    m: u64,
+}
+
+/// Writes bits most significant first
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits of the last byte that are used
+    used: u8,
+}
+
+/// Reads bits most significant first
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Position of the next bit to read
+    position: usize,
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, used: 8 }
+    }
+
+    /// Write the low `count` bits of `value`
+    fn write(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            if self.used == 8 {
+                self.bytes.push(0);
+                self.used = 0;
+            }
+            let bit = (value >> i) as u8 & 1;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used += 1;
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<u64> {
+        let Some(byte) = self.bytes.get(self.position / 8) else {
+            bail!(FilterError::Truncated);
+        };
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+
+        Ok(bit as u64)
+    }
+
+    /// Read `count` bits into the low bits of a number
+    fn read(&mut self, count: u8) -> Result<u64> {
+        (0..count).try_fold(0, |value, _| Ok((value << 1) | self.read_bit()?))
+    }
+}
+
+impl Gcs {
+    /// Set keyed with the first 16 bytes of `key`, with Golomb-Rice parameter `p` and false
+    /// positive rate `1 / m`
+    pub fn new(key: &[u8; 16], p: u8, m: u64) -> Self {
+        Self {
+            k0: u64::from_le_bytes(key[..8].try_into().unwrap()),
+            k1: u64::from_le_bytes(key[8..].try_into().unwrap()),
+            p,
+            m,
+        }
+    }
+
+    /// Hash of `item` mapped into `[0, range)` without a modulo
+    pub fn hash_to_range(&self, item: &[u8], range: u64) -> u64 {
+        ((siphash24(self.k0, self.k1, item) as u128 * range as u128) >> 64) as u64
+    }
+
+    /// Range the items of a set of `count` items are hashed into
+    fn range(&self, count: u64) -> Result<u64> {
+        count
+            .checked_mul(self.m)
+            .ok_or_else(|| FilterError::TooManyElements(count).into())
+    }
+
+    /// Sorted hashes of `items` in the range of a set of `count` items
+    fn hashes<'a>(
+        &self,
+        items: impl IntoIterator<Item = &'a [u8]>,
+        count: u64,
+    ) -> Result<Vec<u64>> {
+        let range = self.range(count)?;
+        let mut hashes = items
+            .into_iter()
+            .map(|item| self.hash_to_range(item, range))
+            .collect::<Vec<_>>();
+        hashes.sort_unstable();
+
+        Ok(hashes)
+    }
+
+    /// Serialised set of `items`, their number followed by the coded hashes
+    ///
+    /// Duplicate items must be removed first, as they would be counted twice.
+    pub fn build(&self, items: &[&[u8]]) -> Result<Vec<u8>> {
+        let count = items.len() as u64;
+        let hashes = self.hashes(items.iter().copied(), count)?;
+
+        let mut writer = BitWriter::new(compact_size(count));
+        let mut last = 0;
+        for hash in hashes {
+            let delta = hash - last;
+            // quotient in unary, then the remainder
+            for _ in 0..delta >> self.p {
+                writer.write(1, 1);
+            }
+            writer.write(0, 1);
+            writer.write(delta, self.p);
+            last = hash;
+        }
+
+        Ok(writer.into_bytes())
+    }
+
+    /// Hashes of the serialised set `filter`, in order
+    pub fn decode(&self, filter: &[u8]) -> Result<Vec<u64>> {
+        let mut reader = filter;
+        let count = read_compact_size(&mut reader)?;
+        self.range(count)?;
+
+        let mut bits = BitReader::new(reader);
+        let mut last = 0u64;
+        let mut hashes = vec![];
+        for _ in 0..count {
+            let mut quotient = 0u64;
+            while bits.read_bit()? == 1 {
+                quotient += 1;
+            }
+            let delta = (quotient << self.p) | bits.read(self.p)?;
+            last = last.wrapping_add(delta);
+            hashes.push(last);
+        }
+
+        Ok(hashes)
+    }
+
+    /// If any of `queries` is in the serialised set `filter`, or collides with an item of it
+    pub fn match_any<'a>(
+        &self,
+        filter: &[u8],
+        queries: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<bool> {
+        let hashes = self.decode(filter)?;
+        let queries = self.hashes(queries, hashes.len() as u64)?;
+
+        // walk both sorted lists together
+        let (mut i, mut j) = (0, 0);
+        while i < hashes.len() && j < queries.len() {
+            match hashes[i].cmp(&queries[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => return Ok(true),
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bits_round_trip() -> Result<()> {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write(0b1, 1);
+        writer.write(0b0110, 4);
+        writer.write(0x1_2345, 19);
+        writer.write(0, 3);
+        let bytes = writer.into_bytes();
+        assert_eq!(bytes.len(), 4);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read(1)?, 0b1);
+        assert_eq!(reader.read(4)?, 0b0110);
+        assert_eq!(reader.read(19)?, 0x1_2345);
+        assert_eq!(reader.read(3)?, 0);
+        assert!(reader.read(8).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn matches_set_items() -> Result<()> {
+        let gcs = Gcs::new(&[7; 16], 19, 784931);
+        let items = (0..16u8).map(|i| [i * 0x11; 3]).collect::<Vec<_>>();
+        let filter = gcs.build(&items.iter().map(|item| item.as_slice()).collect::<Vec<_>>())?;
+        assert_eq!(gcs.decode(&filter)?.len(), 16);
+
+        assert!(gcs.match_any(&filter, [[0xab, 0xcd, 0xef].as_slice(), &[0xee; 3]])?);
+        assert!(!gcs.match_any(
+            &filter,
+            [[0xab, 0xcd, 0xef].as_slice(), &[0x12, 0x34, 0x56]]
+        )?);
+        assert!(!gcs.match_any(&filter, [])?);
+
+        // an empty set matches nothing
+        let empty = gcs.build(&[])?;
+        assert_eq!(empty, [0]);
+        assert!(!gcs.match_any(&empty, [[0; 3].as_slice()])?);
+
+        assert!(gcs
+            .match_any(&filter[..filter.len() - 2], [[0xff; 3].as_slice()])
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod basic;
+pub mod bloom;
+pub mod errors;
+pub mod gcs;
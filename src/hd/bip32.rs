@@ -0,0 +1,654 @@
+use {
+    super::{
+        errors::Bip32Error,
+        path::{ChildNumber, DerivationPath},
+    },
+    crate::{
+        network::Network,
+        secp256k1::{
+            constants::{G, N, N_RING},
+            keys::PrivateKey,
+            point::Point,
+        },
+        utils::{
+            base58::{decode_check, encode_check},
+            encoding::to_32_bytes,
+            hash::hmac_sha512,
+        },
+    },
+    anyhow::{bail, Result},
+    ibig::{modular::IntoModulo, UBig},
+    std::{
+        fmt::{self, Debug, Display, Formatter},
+        str::FromStr,
+    },
+    zeroize::Zeroize,
+};
+
+/// Version bytes of mainnet extended private keys (`xprv`)
+const XPRV_MAINNET: [u8; 4] = [0x04, 0x88, 0xad, 0xe4];
+/// Version bytes of mainnet extended public keys (`xpub`)
+const XPUB_MAINNET: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+/// Version bytes of testnet extended private keys (`tprv`)
+const XPRV_TESTNET: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+/// Version bytes of testnet extended public keys (`tpub`)
+const XPUB_TESTNET: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// Length of a serialised extended key
+const EXTENDED_KEY_LENGTH: usize = 78;
+
+/// First four bytes of the HASH160 of a public key, identifying parent keys
+pub type Fingerprint = [u8; 4];
+
+#[derive(Clone)]
+/// Private key with a chain code, able to derive both hardened and normal children
+pub struct ExtendedPrivateKey {
+    network: Network,
+    depth: u8,
+    parent_fingerprint: Fingerprint,
+    child_number: ChildNumber,
+    chain_code: [u8; 32],
+    private_key: PrivateKey,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Public key with a chain code, able to derive normal children only
+pub struct ExtendedPublicKey {
+    network: Network,
+    depth: u8,
+    parent_fingerprint: Fingerprint,
+    child_number: ChildNumber,
+    chain_code: [u8; 32],
+    point: Point,
+}
+
+impl ExtendedPrivateKey {
+    /// Generate the master key from a seed of 16 to 64 bytes
+    pub fn new_master(network: Network, seed: &[u8]) -> Result<Self> {
+        if !(16..=64).contains(&seed.len()) {
+            bail!(Bip32Error::InvalidSeedLength(seed.len()));
+        }
+
+        let i = hmac_sha512(b"Bitcoin seed", seed);
+        let (il, ir) = i.split_at(32);
+
+        Ok(Self {
+            network,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: ChildNumber::from(0),
+            chain_code: ir.try_into()?,
+            // an out of range master secret makes the seed unusable
+            private_key: PrivateKey::from_bytes(il).map_err(|_| Bip32Error::InvalidChildKey)?,
+        })
+    }
+
+    /// Derive the child key at index `child`
+    pub fn derive_child(&self, child: ChildNumber) -> Result<Self> {
+        let data = if child.is_hardened() {
+            [&[0x00], self.private_key.to_bytes().as_slice()].concat()
+        } else {
+            self.private_key.point().sec(true)
+        };
+        let i = hmac_sha512(
+            &self.chain_code,
+            &[data.as_slice(), &u32::from(child).to_be_bytes()].concat(),
+        );
+        let (il, ir) = i.split_at(32);
+
+        let tweak = UBig::from_be_bytes(il);
+        if N.with(|n| tweak >= *n) {
+            bail!(Bip32Error::InvalidChildKey);
+        }
+        // child secret is the parent secret tweaked by IL
+        let secret = N_RING.with(|r| {
+            (tweak.into_modulo(r)
+                + UBig::from_be_bytes(&self.private_key.to_bytes()).into_modulo(r))
+            .residue()
+        });
+        let private_key = PrivateKey::from_bytes(&to_32_bytes(&secret))
+            .map_err(|_| Bip32Error::InvalidChildKey)?;
+
+        Ok(Self {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(Bip32Error::MaxDepth)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: child,
+            chain_code: ir.try_into()?,
+            private_key,
+        })
+    }
+
+    /// Derive the descendant key at `path` relative to this key
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self> {
+        path.children()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    /// Return the extended public key with the same chain code
+    pub fn extended_public_key(&self) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            network: self.network,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            point: self.private_key.point().clone(),
+        }
+    }
+
+    /// HASH160 of the compressed public key
+    pub fn identifier(&self) -> [u8; 20] {
+        self.private_key.point().hash160(true)
+    }
+
+    /// First four bytes of the identifier
+    pub fn fingerprint(&self) -> Fingerprint {
+        fingerprint(&self.identifier())
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn parent_fingerprint(&self) -> Fingerprint {
+        self.parent_fingerprint
+    }
+
+    pub fn child_number(&self) -> ChildNumber {
+        self.child_number
+    }
+
+    pub fn chain_code(&self) -> [u8; 32] {
+        self.chain_code
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    /// Return the 78 byte BIP32 serialisation of the key
+    pub fn serialise(&self) -> Vec<u8> {
+        let version = if self.network.is_mainnet() {
+            XPRV_MAINNET
+        } else {
+            XPRV_TESTNET
+        };
+        let key = [&[0x00], self.private_key.to_bytes().as_slice()].concat();
+
+        RawExtendedKey {
+            version,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            key: &key,
+        }
+        .serialise()
+    }
+
+    /// Parse the 78 byte BIP32 serialisation of the key
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let raw = RawExtendedKey::parse(data)?;
+        let network = match raw.version {
+            XPRV_MAINNET => Network::Mainnet,
+            XPRV_TESTNET => Network::Testnet,
+            _ => bail!(Bip32Error::UnknownVersion(raw.version)),
+        };
+        if raw.key[0] != 0x00 {
+            bail!(Bip32Error::InvalidKeyData);
+        }
+
+        Ok(Self {
+            network,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            private_key: PrivateKey::from_bytes(&raw.key[1..])
+                .map_err(|_| Bip32Error::InvalidKeyData)?,
+        })
+    }
+}
+
+impl ExtendedPublicKey {
+    /// Derive the non-hardened child key at index `child`
+    pub fn derive_child(&self, child: ChildNumber) -> Result<Self> {
+        if child.is_hardened() {
+            bail!(Bip32Error::HardenedFromPublic(child.index()));
+        }
+
+        let i = hmac_sha512(
+            &self.chain_code,
+            &[
+                self.point.sec(true).as_slice(),
+                &u32::from(child).to_be_bytes(),
+            ]
+            .concat(),
+        );
+        let (il, ir) = i.split_at(32);
+
+        let tweak = UBig::from_be_bytes(il);
+        if N.with(|n| tweak >= *n) {
+            bail!(Bip32Error::InvalidChildKey);
+        }
+        // child point is the parent point tweaked by IL * G
+        let point = G.with(|g| &tweak * g) + &self.point;
+        if point.is_inf() {
+            bail!(Bip32Error::InvalidChildKey);
+        }
+
+        Ok(Self {
+            network: self.network,
+            depth: self.depth.checked_add(1).ok_or(Bip32Error::MaxDepth)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: child,
+            chain_code: ir.try_into()?,
+            point,
+        })
+    }
+
+    /// Derive the descendant key at `path`, which must not contain hardened steps
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self> {
+        path.children()
+            .iter()
+            .try_fold(self.clone(), |key, child| key.derive_child(*child))
+    }
+
+    /// HASH160 of the compressed public key
+    pub fn identifier(&self) -> [u8; 20] {
+        self.point.hash160(true)
+    }
+
+    /// First four bytes of the identifier
+    pub fn fingerprint(&self) -> Fingerprint {
+        fingerprint(&self.identifier())
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn parent_fingerprint(&self) -> Fingerprint {
+        self.parent_fingerprint
+    }
+
+    pub fn child_number(&self) -> ChildNumber {
+        self.child_number
+    }
+
+    pub fn chain_code(&self) -> [u8; 32] {
+        self.chain_code
+    }
+
+    pub fn point(&self) -> &Point {
+        &self.point
+    }
+
+    /// Return the 78 byte BIP32 serialisation of the key
+    pub fn serialise(&self) -> Vec<u8> {
+        let version = if self.network.is_mainnet() {
+            XPUB_MAINNET
+        } else {
+            XPUB_TESTNET
+        };
+
+        RawExtendedKey {
+            version,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            key: &self.point.sec(true),
+        }
+        .serialise()
+    }
+
+    /// Parse the 78 byte BIP32 serialisation of the key
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let raw = RawExtendedKey::parse(data)?;
+        let network = match raw.version {
+            XPUB_MAINNET => Network::Mainnet,
+            XPUB_TESTNET => Network::Testnet,
+            _ => bail!(Bip32Error::UnknownVersion(raw.version)),
+        };
+
+        Ok(Self {
+            network,
+            depth: raw.depth,
+            parent_fingerprint: raw.parent_fingerprint,
+            child_number: raw.child_number,
+            chain_code: raw.chain_code,
+            point: Point::from_sec(raw.key).map_err(|_| Bip32Error::InvalidKeyData)?,
+        })
+    }
+}
+
+impl Drop for ExtendedPrivateKey {
+    fn drop(&mut self) {
+        self.chain_code.zeroize();
+    }
+}
+
+impl Debug for ExtendedPrivateKey {
+    /// Public metadata only, never the chain code or the secret
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtendedPrivateKey")
+            .field("network", &self.network)
+            .field("depth", &self.depth)
+            .field("parent_fingerprint", &self.parent_fingerprint)
+            .field("child_number", &self.child_number)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Display for ExtendedPrivateKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_check(&self.serialise()))
+    }
+}
+
+impl FromStr for ExtendedPrivateKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(&decode_check(s)?)
+    }
+}
+
+impl Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", encode_check(&self.serialise()))
+    }
+}
+
+impl FromStr for ExtendedPublicKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(&decode_check(s)?)
+    }
+}
+
+/// First four bytes of a key identifier
+fn fingerprint(identifier: &[u8; 20]) -> Fingerprint {
+    [identifier[0], identifier[1], identifier[2], identifier[3]]
+}
+
+/// Fields shared by serialised extended private and public keys
+struct RawExtendedKey<'a> {
+    version: [u8; 4],
+    depth: u8,
+    parent_fingerprint: Fingerprint,
+    child_number: ChildNumber,
+    chain_code: [u8; 32],
+    key: &'a [u8],
+}
+
+impl<'a> RawExtendedKey<'a> {
+    fn serialise(&self) -> Vec<u8> {
+        [
+            self.version.as_slice(),
+            &[self.depth],
+            &self.parent_fingerprint,
+            &u32::from(self.child_number).to_be_bytes(),
+            &self.chain_code,
+            self.key,
+        ]
+        .concat()
+    }
+
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() != EXTENDED_KEY_LENGTH {
+            bail!(Bip32Error::InvalidLength(data.len()));
+        }
+
+        let raw = Self {
+            version: data[0..4].try_into()?,
+            depth: data[4],
+            parent_fingerprint: data[5..9].try_into()?,
+            child_number: ChildNumber::from(u32::from_be_bytes(data[9..13].try_into()?)),
+            chain_code: data[13..45].try_into()?,
+            key: &data[45..],
+        };
+
+        // master keys have no parent and no index
+        if raw.depth == 0
+            && (raw.parent_fingerprint != [0; 4] || raw.child_number != ChildNumber::from(0))
+        {
+            bail!(Bip32Error::InvalidKeyData);
+        }
+
+        Ok(raw)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Check the xprv and xpub at each path of a BIP32 test vector
+    fn check_vector(seed: &str, steps: &[(&str, &str, &str)]) -> Result<()> {
+        let master = ExtendedPrivateKey::new_master(Network::Mainnet, &hex::decode(seed)?)?;
+
+        for (path, xprv, xpub) in steps {
+            let key = master.derive_path(&path.parse()?)?;
+
+            assert_eq!(key.to_string(), *xprv, "{path}");
+            assert_eq!(key.extended_public_key().to_string(), *xpub, "{path}");
+            assert_eq!(xprv.parse::<ExtendedPrivateKey>()?.to_string(), *xprv);
+            assert_eq!(xpub.parse::<ExtendedPublicKey>()?.to_string(), *xpub);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_1() -> Result<()> {
+        check_vector(
+            "000102030405060708090a0b0c0d0e0f",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+                    "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+                ),
+                (
+                    "m/0h",
+                    "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+                    "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+                ),
+                (
+                    "m/0h/1",
+                    "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs",
+                    "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+                ),
+                (
+                    "m/0h/1/2h",
+                    "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM",
+                    "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+                ),
+                (
+                    "m/0h/1/2h/2",
+                    "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334",
+                    "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV",
+                ),
+                (
+                    "m/0h/1/2h/2/1000000000",
+                    "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76",
+                    "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_vector_2() -> Result<()> {
+        check_vector(
+            "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U",
+                    "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB",
+                ),
+                (
+                    "m/0",
+                    "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt",
+                    "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+                ),
+                (
+                    "m/0/2147483647h",
+                    "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9",
+                    "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a",
+                ),
+                (
+                    "m/0/2147483647h/1",
+                    "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef",
+                    "xpub6DF8uhdarytz3FWdA8TvFSvvAh8dP3283MY7p2V4SeE2wyWmG5mg5EwVvmdMVCQcoNJxGoWaU9DCWh89LojfZ537wTfunKau47EL2dhHKon",
+                ),
+                (
+                    "m/0/2147483647h/1/2147483646h",
+                    "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc",
+                    "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL",
+                ),
+                (
+                    "m/0/2147483647h/1/2147483646h/2",
+                    "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j",
+                    "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt",
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_vector_3() -> Result<()> {
+        // leading zeros of the private key must be retained
+        check_vector(
+            "4b381541583be4423346c643850da4b320e46a87ae3d2a4e6da11eba819cd4acba45d239319ac14f863b8d5ab5a0d0c64d2e8a1e7d1457df2e5a3c51c73235be",
+            &[
+                (
+                    "m",
+                    "xprv9s21ZrQH143K25QhxbucbDDuQ4naNntJRi4KUfWT7xo4EKsHt2QJDu7KXp1A3u7Bi1j8ph3EGsZ9Xvz9dGuVrtHHs7pXeTzjuxBrCmmhgC6",
+                    "xpub661MyMwAqRbcEZVB4dScxMAdx6d4nFc9nvyvH3v4gJL378CSRZiYmhRoP7mBy6gSPSCYk6SzXPTf3ND1cZAceL7SfJ1Z3GC8vBgp2epUt13",
+                ),
+                (
+                    "m/0h",
+                    "xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L",
+                    "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y",
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn public_derivation_matches_private() -> Result<()> {
+        let master = ExtendedPrivateKey::new_master(
+            Network::Mainnet,
+            &hex::decode("000102030405060708090a0b0c0d0e0f")?,
+        )?;
+        let account = master.derive_path(&"m/0h/1".parse()?)?;
+        let path = "m/2/5".parse()?;
+
+        assert_eq!(
+            account.extended_public_key().derive_path(&path)?,
+            account.derive_path(&path)?.extended_public_key()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hardened_from_public_fails() -> Result<()> {
+        let xpub: ExtendedPublicKey = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8".parse()?;
+
+        assert!(xpub.derive_child(ChildNumber::hardened(0)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn derivation_stops_at_max_depth() -> Result<()> {
+        let mut master = ExtendedPrivateKey::new_master(
+            Network::Mainnet,
+            &hex::decode("000102030405060708090a0b0c0d0e0f")?,
+        )?;
+        master.depth = u8::MAX;
+        let child = ChildNumber::normal(0)?;
+
+        for result in [
+            master.derive_child(child).map(|_| ()),
+            master.extended_public_key().derive_child(child).map(|_| ()),
+        ] {
+            assert_eq!(
+                result.err().unwrap().downcast::<Bip32Error>()?,
+                Bip32Error::MaxDepth
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_of_master() -> Result<()> {
+        let master = ExtendedPrivateKey::new_master(
+            Network::Mainnet,
+            &hex::decode("000102030405060708090a0b0c0d0e0f")?,
+        )?;
+        let child = master.derive_child(ChildNumber::hardened(0)?)?;
+
+        assert_eq!(master.fingerprint(), [0x34, 0x42, 0x19, 0x3e]);
+        assert_eq!(child.parent_fingerprint(), master.fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn testnet_serialisation() -> Result<()> {
+        let master = ExtendedPrivateKey::new_master(
+            Network::Testnet,
+            &hex::decode("000102030405060708090a0b0c0d0e0f")?,
+        )?;
+
+        assert!(master.to_string().starts_with("tprv"));
+        assert!(master.extended_public_key().to_string().starts_with("tpub"));
+        assert_eq!(
+            master.to_string().parse::<ExtendedPrivateKey>()?.network(),
+            Network::Testnet
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_5_invalid_keys() -> Result<()> {
+        for key in [
+            // private key 0 not in 1..n-1
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzF93Y5wvzdUayhgkkFoicQZcP3y52uPPxFnfoLZB21Teqt1VvEHx",
+            // private key n not in 1..n-1
+            "xprv9s21ZrQH143K24Mfq5zL5MhWK9hUhhGbd45hLXo2Pq2oqzMMo63oStZzFAzHGBP2UuGCqWLTAPLcMtD9y5gkZ6Eq3Rjuahrv17fENZ3QzxW",
+            // invalid checksum
+            "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHL",
+        ] {
+            assert!(key.parse::<ExtendedPrivateKey>().is_err(), "{key}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_public_key_data() -> Result<()> {
+        let xpub: ExtendedPublicKey = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8".parse()?;
+        let mut data = xpub.serialise();
+
+        // public key version with private key parsing
+        assert!(ExtendedPrivateKey::parse(&data).is_err());
+
+        // invalid public key prefix
+        data[45] = 0x04;
+        assert!(ExtendedPublicKey::parse(&data).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to BIP32 hierarchical deterministic keys
+pub enum Bip32Error {
+    #[error("seed must be between 16 and 64 bytes, got {0}")]
+    InvalidSeedLength(usize),
+    #[error("derived key is invalid, proceed with the next index")]
+    InvalidChildKey,
+    #[error("cannot derive hardened child {0} from a public key")]
+    HardenedFromPublic(u32),
+    #[error("child index {0} does not fit in 31 bits")]
+    InvalidChildIndex(u32),
+    #[error("cannot derive children of a key at the maximum depth of 255")]
+    MaxDepth,
+    #[error("invalid derivation path `{0}`")]
+    InvalidPath(String),
+    #[error("extended key must be 78 bytes, got {0}")]
+    InvalidLength(usize),
+    #[error("unknown extended key version {0:02x?}")]
+    UnknownVersion([u8; 4]),
+    #[error("invalid extended key data")]
+    InvalidKeyData,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to BIP39 mnemonic sentences
+pub enum Bip39Error {
+    #[error("`{0}` is not in the BIP39 wordlist")]
+    UnknownWord(String),
+    #[error("mnemonic checksum mismatch")]
+    InvalidChecksum,
+    #[error("mnemonic must have 12, 15, 18, 21 or 24 words, got {0}")]
+    InvalidWordCount(usize),
+    #[error("entropy must be 16, 20, 24, 28 or 32 bytes, got {0}")]
+    InvalidEntropyLength(usize),
+}
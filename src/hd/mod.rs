@@ -0,0 +1,5 @@
+pub mod bip32;
+pub mod bip39;
+pub mod errors;
+pub mod path;
+mod wordlist;
@@ -0,0 +1,243 @@
+use {
+    super::errors::Bip32Error,
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+/// Index from which child numbers are hardened
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// Index of a child key derived from a parent extended key
+///
+/// Holds the number as serialised, so hardened children are those from 2^31 onwards. Children
+/// that can be derived from the parent public key are normal, the others hardened.
+pub struct ChildNumber(u32);
+
+impl ChildNumber {
+    /// Create a non-hardened child number
+    pub fn normal(index: u32) -> Result<Self> {
+        if index >= HARDENED_OFFSET {
+            bail!(Bip32Error::InvalidChildIndex(index));
+        }
+
+        Ok(Self(index))
+    }
+
+    /// Create a hardened child number
+    pub fn hardened(index: u32) -> Result<Self> {
+        if index >= HARDENED_OFFSET {
+            bail!(Bip32Error::InvalidChildIndex(index));
+        }
+
+        Ok(Self(index + HARDENED_OFFSET))
+    }
+
+    /// If the child is hardened
+    pub fn is_hardened(&self) -> bool {
+        self.0 >= HARDENED_OFFSET
+    }
+
+    /// Index of the child without the hardened offset
+    pub fn index(&self) -> u32 {
+        self.0 & !HARDENED_OFFSET
+    }
+}
+
+impl From<u32> for ChildNumber {
+    /// Child number as it appears in serialised keys, hardened from 2^31 onwards
+    fn from(number: u32) -> Self {
+        Self(number)
+    }
+}
+
+impl From<ChildNumber> for u32 {
+    fn from(child: ChildNumber) -> Self {
+        child.0
+    }
+}
+
+impl Display for ChildNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_hardened() {
+            write!(f, "{}'", self.index())
+        } else {
+            write!(f, "{}", self.index())
+        }
+    }
+}
+
+impl FromStr for ChildNumber {
+    type Err = anyhow::Error;
+
+    /// Parse a child number, hardened children are suffixed with `'`, `h` or `H`
+    fn from_str(s: &str) -> Result<Self> {
+        let (index, hardened) = match s.strip_suffix(['\'', 'h', 'H']) {
+            Some(index) => (index, true),
+            None => (s, false),
+        };
+
+        // reject signs and whitespace that u32 parsing would accept
+        if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+            bail!(Bip32Error::InvalidPath(s.to_string()));
+        }
+        let index = index
+            .parse()
+            .map_err(|_| Bip32Error::InvalidPath(s.to_string()))?;
+
+        if hardened {
+            Self::hardened(index)
+        } else {
+            Self::normal(index)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+/// Path of child numbers leading from a master key to a descendant key
+pub struct DerivationPath {
+    children: Vec<ChildNumber>,
+}
+
+impl DerivationPath {
+    /// Create a derivation path from its child numbers
+    pub fn new(children: Vec<ChildNumber>) -> Self {
+        Self { children }
+    }
+
+    /// Path pointing at the master key itself
+    pub fn master() -> Self {
+        Self::default()
+    }
+
+    /// Child numbers of the path, from the master key downwards
+    pub fn children(&self) -> &[ChildNumber] {
+        &self.children
+    }
+
+    /// Number of derivation steps in the path
+    pub fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    /// If the path points at the master key
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Return a new path extended by `child`
+    pub fn child(&self, child: ChildNumber) -> Self {
+        let mut children = self.children.clone();
+        children.push(child);
+        Self { children }
+    }
+
+    /// Return a new path extended by all children of `path`
+    pub fn extend(&self, path: &DerivationPath) -> Self {
+        Self {
+            children: [self.children.as_slice(), path.children()].concat(),
+        }
+    }
+}
+
+impl From<Vec<ChildNumber>> for DerivationPath {
+    fn from(children: Vec<ChildNumber>) -> Self {
+        Self::new(children)
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for child in &self.children {
+            write!(f, "/{child}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = anyhow::Error;
+
+    /// Parse a path such as `m/84'/0'/0'/0/5`, the leading `m` is optional
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('/').peekable();
+        if parts.peek() == Some(&"m") {
+            parts.next();
+        }
+
+        let children = parts
+            .map(|part| {
+                part.parse()
+                    .map_err(|_| Bip32Error::InvalidPath(s.to_string()).into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { children })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_path() -> Result<()> {
+        let path: DerivationPath = "m/84'/0'/0'/0/5".parse()?;
+
+        assert_eq!(
+            path.children(),
+            [
+                ChildNumber::hardened(84)?,
+                ChildNumber::hardened(0)?,
+                ChildNumber::hardened(0)?,
+                ChildNumber::normal(0)?,
+                ChildNumber::normal(5)?,
+            ]
+        );
+        assert_eq!(path.to_string(), "m/84'/0'/0'/0/5");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_master_path() -> Result<()> {
+        let path: DerivationPath = "m".parse()?;
+
+        assert!(path.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_hardened_markers() -> Result<()> {
+        let path: DerivationPath = "m/1h/2H/3'".parse()?;
+
+        assert!(path.children().iter().all(|c| c.is_hardened()));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_paths() -> Result<()> {
+        for path in ["m/", "m/x", "m/1''", "m/2147483648", "m/-1", "m/ 1", "n/1"] {
+            assert!(path.parse::<DerivationPath>().is_err(), "{path}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn child_number_u32_round_trip() -> Result<()> {
+        let child = ChildNumber::from(HARDENED_OFFSET + 44);
+
+        assert_eq!(child, ChildNumber::hardened(44)?);
+        assert_eq!(u32::from(child), HARDENED_OFFSET + 44);
+        assert_eq!((child.index(), child.to_string()), (44, "44'".to_string()));
+
+        // indices that would overlap the hardened range are rejected
+        assert!(ChildNumber::hardened(HARDENED_OFFSET).is_err());
+        assert!(ChildNumber::normal(HARDENED_OFFSET).is_err());
+        Ok(())
+    }
+}
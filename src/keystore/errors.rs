@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors of storing and unlocking encrypted keys
+pub enum KeystoreError {
+    #[error("wrong password")]
+    WrongPassword,
+    #[error("key derivation parameters are invalid")]
+    InvalidKdfParams,
+    #[error("file is not a keystore")]
+    NotAKeystore,
+    #[error("keystore version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("keystore entry {0} is corrupted")]
+    CorruptedEntry(String),
+    #[error("keystore entries were tampered with")]
+    TamperedEntries,
+    #[error("a keystore entry is already labelled {0}")]
+    DuplicateLabel(String),
+    #[error("no keystore entry is labelled {0}")]
+    UnknownLabel(String),
+    #[error("keystore entry {0} does not hold a {1}")]
+    WrongKind(String, String),
+}
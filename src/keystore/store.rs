@@ -0,0 +1,699 @@
+use {
+    super::errors::KeystoreError,
+    crate::{
+        secp256k1::keys::PrivateKey,
+        utils::{
+            encoding::{compact_size, read_array, read_compact_size, read_var_bytes, var_bytes},
+            hash::tagged_hash,
+        },
+    },
+    anyhow::{bail, Result},
+    chacha20poly1305::{
+        aead::{Aead, KeyInit, Payload},
+        ChaCha20Poly1305,
+    },
+    hmac::{Hmac, Mac},
+    sha2::Sha256,
+    std::{
+        collections::BTreeMap,
+        fmt::{self, Debug, Display, Formatter},
+        fs::{self, OpenOptions},
+        io::Write,
+        path::Path,
+    },
+    zeroize::Zeroizing,
+};
+
+/// Magic bytes starting a keystore file
+const KEYSTORE_MAGIC: [u8; 4] = *b"kst\0";
+
+/// Version of the keystore file format
+const KEYSTORE_VERSION: u8 = 1;
+
+/// Plaintext sealed under the key of a keystore, to tell a wrong password from a corrupted entry
+const VERIFIER: &[u8] = b"keystore password verifier";
+
+/// Largest scrypt cost accepted, 1 GiB of memory with the default block size
+const MAX_LOG_N: u8 = 20;
+
+/// Largest product of the scrypt block size and parallelisation accepted
+const MAX_R_P: u64 = 64;
+
+/// Size of the MAC ending a keystore file
+const MAC_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Cost parameters of scrypt, which stretches passwords into encryption keys
+pub struct KdfParams {
+    /// Base 2 logarithm of the CPU and memory cost
+    pub log_n: u8,
+    /// Block size
+    pub r: u32,
+    /// Parallelisation
+    pub p: u32,
+}
+
+impl Default for KdfParams {
+    /// Parameters recommended for interactive use, costing 32 MiB of memory
+    fn default() -> Self {
+        Self {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Encryption key stretched from `password` and `salt`
+    ///
+    /// Parameters are read from the file before they can be authenticated, so costs above the
+    /// ceiling are rejected rather than letting a crafted file exhaust memory or time.
+    fn derive_key(&self, password: &str, salt: &[u8; 16]) -> Result<Zeroizing<[u8; 32]>> {
+        if self.log_n > MAX_LOG_N || u64::from(self.r) * u64::from(self.p) > MAX_R_P {
+            bail!(KeystoreError::InvalidKdfParams);
+        }
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, 32)
+            .map_err(|_| KeystoreError::InvalidKdfParams)?;
+        let mut key = Zeroizing::new([0; 32]);
+        scrypt::scrypt(password.as_bytes(), salt, &params, key.as_mut())
+            .map_err(|_| KeystoreError::InvalidKdfParams)?;
+
+        Ok(key)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Kind of secret held by a keystore entry
+pub enum EntryKind {
+    PrivateKey,
+    /// Seed of a BIP32 wallet
+    Seed,
+}
+
+impl EntryKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::PrivateKey => 0,
+            Self::Seed => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::PrivateKey),
+            1 => Some(Self::Seed),
+            _ => None,
+        }
+    }
+}
+
+impl Display for EntryKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PrivateKey => write!(f, "private key"),
+            Self::Seed => write!(f, "seed"),
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Secret encrypted under the key of a keystore
+struct Sealed {
+    nonce: [u8; 12],
+    /// Ciphertext followed by its tag
+    data: Vec<u8>,
+}
+
+impl Sealed {
+    /// Encrypt `plaintext` under `key` with a fresh random nonce
+    fn seal(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Result<Self> {
+        let mut nonce = [0; 12];
+        getrandom::getrandom(&mut nonce)?;
+        let data = ChaCha20Poly1305::new(key.into())
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("secrets are far below the size limit of ChaCha20-Poly1305");
+
+        Ok(Self { nonce, data })
+    }
+
+    /// Decrypt the secret, `None` if it does not authenticate under `key` and `aad`
+    fn open(&self, key: &[u8; 32], aad: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+        ChaCha20Poly1305::new(key.into())
+            .decrypt(
+                &self.nonce.into(),
+                Payload {
+                    msg: &self.data,
+                    aad,
+                },
+            )
+            .ok()
+            .map(Zeroizing::new)
+    }
+
+    fn serialise(&self) -> Vec<u8> {
+        [self.nonce.as_slice(), &var_bytes(&self.data)].concat()
+    }
+
+    fn parse(reader: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            nonce: read_array(reader)?,
+            data: read_var_bytes(reader)?,
+        })
+    }
+}
+
+/// Private keys and seeds encrypted with ChaCha20-Poly1305 under a key derived from a password
+///
+/// Each entry is authenticated together with its label and kind, so entries cannot be swapped,
+/// and the file ends with a MAC over the whole entry list, so entries cannot be dropped or mixed
+/// in from another file. The derived key is held while the keystore is open and wiped when it is dropped.
+pub struct Keystore {
+    params: KdfParams,
+    salt: [u8; 16],
+    key: Zeroizing<[u8; 32]>,
+    verifier: Sealed,
+    entries: BTreeMap<String, (EntryKind, Sealed)>,
+}
+
+impl Keystore {
+    /// Empty keystore locked with `password`
+    pub fn new(password: &str, params: KdfParams) -> Result<Self> {
+        let mut salt = [0; 16];
+        getrandom::getrandom(&mut salt)?;
+        let key = params.derive_key(password, &salt)?;
+        let verifier = Sealed::seal(&key, &header(&params, &salt), VERIFIER)?;
+
+        Ok(Self {
+            params,
+            salt,
+            key,
+            verifier,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    /// Parameters used to derive the key from the password
+    pub fn params(&self) -> KdfParams {
+        self.params
+    }
+
+    /// Labels of the entries, in order
+    pub fn labels(&self) -> Vec<&str> {
+        self.entries.keys().map(String::as_str).collect()
+    }
+
+    /// Kind of the secret labelled `label`, if there is one
+    pub fn kind(&self, label: &str) -> Option<EntryKind> {
+        self.entries.get(label).map(|(kind, _)| *kind)
+    }
+
+    /// Encrypt `key` under `label`
+    pub fn insert_key(&mut self, label: &str, key: &PrivateKey) -> Result<()> {
+        self.insert(
+            label,
+            EntryKind::PrivateKey,
+            &Zeroizing::new(key.to_bytes())[..],
+        )
+    }
+
+    /// Encrypt the wallet seed `seed` under `label`
+    pub fn insert_seed(&mut self, label: &str, seed: &[u8]) -> Result<()> {
+        self.insert(label, EntryKind::Seed, seed)
+    }
+
+    /// Decrypt the private key labelled `label`
+    pub fn private_key(&self, label: &str) -> Result<PrivateKey> {
+        PrivateKey::from_bytes(&self.open_entry(label, EntryKind::PrivateKey)?)
+    }
+
+    /// Decrypt the seed labelled `label`
+    pub fn seed(&self, label: &str) -> Result<Zeroizing<Vec<u8>>> {
+        self.open_entry(label, EntryKind::Seed)
+    }
+
+    /// Delete the entry labelled `label`
+    pub fn remove(&mut self, label: &str) -> Result<()> {
+        if self.entries.remove(label).is_none() {
+            bail!(KeystoreError::UnknownLabel(label.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypt every entry under a new salt and a key derived from `new_password`
+    ///
+    /// Passing the same password for both rotates the key and nonces of every entry.
+    pub fn change_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        params: KdfParams,
+    ) -> Result<()> {
+        // checked against the verifier rather than the key, as tags are compared in constant time
+        let old_key = self.params.derive_key(old_password, &self.salt)?;
+        if self
+            .verifier
+            .open(&old_key, &header(&self.params, &self.salt))
+            .is_none()
+        {
+            bail!(KeystoreError::WrongPassword);
+        }
+
+        let mut rotated = Self::new(new_password, params)?;
+        for (label, (kind, _)) in &self.entries {
+            rotated.insert(label, *kind, &self.open_entry(label, *kind)?)?;
+        }
+        *self = rotated;
+
+        Ok(())
+    }
+
+    /// Serialise the keystore, with every secret encrypted
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = header(&self.params, &self.salt);
+        bytes.extend(self.verifier.serialise());
+        bytes.extend(compact_size(self.entries.len() as u64));
+        for (label, (kind, sealed)) in &self.entries {
+            bytes.extend(var_bytes(label.as_bytes()));
+            bytes.push(kind.to_byte());
+            bytes.extend(sealed.serialise());
+        }
+        let mac = self.entries_mac(&bytes);
+        bytes.extend(mac);
+
+        bytes
+    }
+
+    /// Parse a serialised keystore and unlock it with `password`
+    pub fn parse(bytes: &[u8], password: &str) -> Result<Self> {
+        let reader = &mut &bytes[..];
+        if read_array::<4>(reader)? != KEYSTORE_MAGIC {
+            bail!(KeystoreError::NotAKeystore);
+        }
+        let version = read_array::<1>(reader)?[0];
+        if version != KEYSTORE_VERSION {
+            bail!(KeystoreError::UnsupportedVersion(version));
+        }
+        let params = KdfParams {
+            log_n: read_array::<1>(reader)?[0],
+            r: u32::from_le_bytes(read_array(reader)?),
+            p: u32::from_le_bytes(read_array(reader)?),
+        };
+        let salt = read_array(reader)?;
+
+        let key = params.derive_key(password, &salt)?;
+        let verifier = Sealed::parse(reader)?;
+        // the header is authenticated with the verifier, so tampered parameters look like a
+        // wrong password
+        match verifier.open(&key, &header(&params, &salt)) {
+            Some(plaintext) if *plaintext == VERIFIER => {}
+            _ => bail!(KeystoreError::WrongPassword),
+        }
+
+        let mut keystore = Self {
+            params,
+            salt,
+            key,
+            verifier,
+            entries: BTreeMap::new(),
+        };
+        // the entry list is only read once the MAC over the whole file checks out
+        let Some(body_len) = bytes.len().checked_sub(MAC_SIZE) else {
+            bail!(KeystoreError::TamperedEntries);
+        };
+        let entries_start = bytes.len() - reader.len();
+        let (body, mac) = bytes.split_at(body_len);
+        if body_len < entries_start || !keystore.verify_entries_mac(body, mac) {
+            bail!(KeystoreError::TamperedEntries);
+        }
+
+        let reader = &mut &body[entries_start..];
+        for _ in 0..read_compact_size(reader)? {
+            let label = String::from_utf8(read_var_bytes(reader)?)?;
+            let kind = EntryKind::from_byte(read_array::<1>(reader)?[0])
+                .ok_or_else(|| KeystoreError::CorruptedEntry(label.clone()))?;
+            if keystore.entries.contains_key(&label) {
+                bail!(KeystoreError::DuplicateLabel(label));
+            }
+            keystore
+                .entries
+                .insert(label, (kind, Sealed::parse(reader)?));
+        }
+        if !reader.is_empty() {
+            bail!(KeystoreError::TamperedEntries);
+        }
+
+        Ok(keystore)
+    }
+
+    /// Write the keystore to `path`, replacing the file atomically
+    ///
+    /// On Unix the file is only readable and writable by its owner.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        // the mode only applies to new files, not to a temporary file left by an earlier save
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(&self.serialise())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Keystore saved at `path`, unlocked with `password`
+    pub fn open(path: impl AsRef<Path>, password: &str) -> Result<Self> {
+        Self::parse(&fs::read(path)?, password)
+    }
+
+    /// MAC over the serialised keystore `body`, keyed with a subkey of the encryption key
+    fn entries_mac(&self, body: &[u8]) -> [u8; MAC_SIZE] {
+        self.mac(body).finalize().into_bytes().into()
+    }
+
+    /// Whether `mac` authenticates `body`, compared in constant time
+    fn verify_entries_mac(&self, body: &[u8], mac: &[u8]) -> bool {
+        self.mac(body).verify_slice(mac).is_ok()
+    }
+
+    fn mac(&self, body: &[u8]) -> Hmac<Sha256> {
+        let key = Zeroizing::new(tagged_hash("keystore/entries", self.key.as_slice()));
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_slice())
+            .expect("HMAC accepts keys of any length");
+        mac.update(body);
+        mac
+    }
+
+    /// Encrypt `secret` as a new entry
+    fn insert(&mut self, label: &str, kind: EntryKind, secret: &[u8]) -> Result<()> {
+        if self.entries.contains_key(label) {
+            bail!(KeystoreError::DuplicateLabel(label.to_string()));
+        }
+
+        let sealed = Sealed::seal(&self.key, &entry_aad(label, kind), secret)?;
+        self.entries.insert(label.to_string(), (kind, sealed));
+
+        Ok(())
+    }
+
+    /// Decrypt the entry labelled `label`, which must hold a secret of `kind`
+    fn open_entry(&self, label: &str, kind: EntryKind) -> Result<Zeroizing<Vec<u8>>> {
+        let (stored_kind, sealed) = self
+            .entries
+            .get(label)
+            .ok_or_else(|| KeystoreError::UnknownLabel(label.to_string()))?;
+        if *stored_kind != kind {
+            bail!(KeystoreError::WrongKind(
+                label.to_string(),
+                kind.to_string()
+            ));
+        }
+
+        Ok(sealed
+            .open(&self.key, &entry_aad(label, kind))
+            .ok_or_else(|| KeystoreError::CorruptedEntry(label.to_string()))?)
+    }
+}
+
+impl Debug for Keystore {
+    /// Parameters and labels only, never the key or the secrets
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keystore")
+            .field("params", &self.params)
+            .field("labels", &self.labels())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Magic, version, key derivation parameters and salt starting a keystore file
+fn header(params: &KdfParams, salt: &[u8; 16]) -> Vec<u8> {
+    [
+        KEYSTORE_MAGIC.as_slice(),
+        &[KEYSTORE_VERSION, params.log_n],
+        &params.r.to_le_bytes(),
+        &params.p.to_le_bytes(),
+        salt,
+    ]
+    .concat()
+}
+
+/// Data authenticated with an entry, binding its secret to the format version, label and kind
+fn entry_aad(label: &str, kind: EntryKind) -> Vec<u8> {
+    [&[KEYSTORE_VERSION, kind.to_byte()], label.as_bytes()].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Cheap parameters, so the tests do not spend their time stretching passwords
+    const TEST_PARAMS: KdfParams = KdfParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    fn keystore() -> Result<Keystore> {
+        let mut keystore = Keystore::new("correct horse", TEST_PARAMS)?;
+        keystore.insert_key("hot", &PrivateKey::new("deadbeef", 16)?)?;
+        keystore.insert_seed("wallet", &[7; 64])?;
+        Ok(keystore)
+    }
+
+    fn error(result: Result<impl Sized>) -> KeystoreError {
+        result.err().unwrap().downcast().unwrap()
+    }
+
+    #[test]
+    fn stores_and_unlocks_secrets() -> Result<()> {
+        let keystore = keystore()?;
+        let bytes = keystore.serialise();
+        assert!(!bytes
+            .windows(4)
+            .any(|window| window == [0xde, 0xad, 0xbe, 0xef]));
+        assert!(!bytes.windows(8).any(|window| window == [7; 8]));
+
+        let parsed = Keystore::parse(&bytes, "correct horse")?;
+        assert_eq!(parsed.labels(), ["hot", "wallet"]);
+        assert_eq!(parsed.kind("wallet"), Some(EntryKind::Seed));
+        assert_eq!(
+            parsed.private_key("hot")?.to_bytes(),
+            PrivateKey::new("deadbeef", 16)?.to_bytes()
+        );
+        assert_eq!(*parsed.seed("wallet")?, [7; 64]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_wrong_password() -> Result<()> {
+        let bytes = keystore()?.serialise();
+        assert_eq!(
+            error(Keystore::parse(&bytes, "battery staple")),
+            KeystoreError::WrongPassword
+        );
+
+        // weakening the stored parameters breaks the authenticated header
+        let mut weakened = bytes.clone();
+        weakened[5] = 3;
+        assert_eq!(
+            error(Keystore::parse(&weakened, "correct horse")),
+            KeystoreError::WrongPassword
+        );
+
+        let mut future = bytes;
+        future[4] = 2;
+        assert_eq!(
+            error(Keystore::parse(&future, "correct horse")),
+            KeystoreError::UnsupportedVersion(2)
+        );
+        assert_eq!(
+            error(Keystore::parse(b"wlt\0\x01", "correct horse")),
+            KeystoreError::NotAKeystore
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_costly_kdf_params() -> Result<()> {
+        let bytes = keystore()?.serialise();
+        let mut costly = bytes.clone();
+        costly[5] = 40;
+        assert_eq!(
+            error(Keystore::parse(&costly, "correct horse")),
+            KeystoreError::InvalidKdfParams
+        );
+
+        // r is stored after log_n
+        let mut costly = bytes;
+        costly[6..10].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            error(Keystore::parse(&costly, "correct horse")),
+            KeystoreError::InvalidKdfParams
+        );
+
+        let params = KdfParams {
+            p: 9,
+            ..TEST_PARAMS
+        };
+        assert_eq!(
+            error(Keystore::new("correct horse", params)),
+            KeystoreError::InvalidKdfParams
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn detects_tampered_entries() -> Result<()> {
+        let bytes = keystore()?.serialise();
+        let mut flipped = bytes.clone();
+        flipped[bytes.len() - MAC_SIZE - 1] ^= 1;
+        assert_eq!(
+            error(Keystore::parse(&flipped, "correct horse")),
+            KeystoreError::TamperedEntries
+        );
+        assert_eq!(
+            error(Keystore::parse(&bytes[..bytes.len() - 1], "correct horse")),
+            KeystoreError::TamperedEntries
+        );
+
+        // dropping an entry, or rolling the list back to an older one, breaks the MAC
+        let mut keystore = keystore()?;
+        keystore.remove("hot")?;
+        let mut dropped = keystore.serialise();
+        let len = dropped.len();
+        dropped[len - MAC_SIZE..].copy_from_slice(&bytes[bytes.len() - MAC_SIZE..]);
+        assert_eq!(
+            error(Keystore::parse(&dropped, "correct horse")),
+            KeystoreError::TamperedEntries
+        );
+
+        // a secret moved under another label does not authenticate
+        let moved = keystore.entries["wallet"].clone();
+        keystore.entries.insert("cold".to_string(), moved);
+        assert_eq!(
+            error(keystore.seed("cold")),
+            KeystoreError::CorruptedEntry("cold".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_duplicate_labels() -> Result<()> {
+        let mut keystore = keystore()?;
+        keystore.remove("hot")?;
+        let bytes = keystore.serialise();
+        // the only entry is its label length, label, kind byte and sealed secret
+        let entry_start = bytes.len()
+            - MAC_SIZE
+            - keystore.entries["wallet"].1.serialise().len()
+            - "wallet".len()
+            - 2;
+
+        // the same entry twice, under a valid MAC
+        let mut body = bytes[..entry_start - 1].to_vec();
+        body.push(2);
+        body.extend_from_slice(&bytes[entry_start..bytes.len() - MAC_SIZE]);
+        body.extend_from_slice(&bytes[entry_start..bytes.len() - MAC_SIZE]);
+        let mac = keystore.entries_mac(&body);
+        body.extend(mac);
+        assert_eq!(
+            error(Keystore::parse(&body, "correct horse")),
+            KeystoreError::DuplicateLabel("wallet".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn manages_entries() -> Result<()> {
+        let mut keystore = keystore()?;
+        assert_eq!(
+            error(keystore.insert_seed("hot", &[1; 32])),
+            KeystoreError::DuplicateLabel("hot".to_string())
+        );
+        assert_eq!(
+            error(keystore.seed("hot")),
+            KeystoreError::WrongKind("hot".to_string(), "seed".to_string())
+        );
+
+        keystore.remove("hot")?;
+        assert_eq!(keystore.labels(), ["wallet"]);
+        assert_eq!(
+            error(keystore.private_key("hot")),
+            KeystoreError::UnknownLabel("hot".to_string())
+        );
+        assert_eq!(
+            error(keystore.remove("hot")),
+            KeystoreError::UnknownLabel("hot".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn changes_password() -> Result<()> {
+        let mut keystore = keystore()?;
+        let salt = keystore.salt;
+        assert_eq!(
+            error(keystore.change_password("battery staple", "new", TEST_PARAMS)),
+            KeystoreError::WrongPassword
+        );
+
+        let params = KdfParams {
+            log_n: 5,
+            ..TEST_PARAMS
+        };
+        keystore.change_password("correct horse", "battery staple", params)?;
+        assert_ne!(keystore.salt, salt);
+        assert_eq!(keystore.params(), params);
+
+        let bytes = keystore.serialise();
+        assert_eq!(
+            error(Keystore::parse(&bytes, "correct horse")),
+            KeystoreError::WrongPassword
+        );
+        let parsed = Keystore::parse(&bytes, "battery staple")?;
+        assert_eq!(*parsed.seed("wallet")?, [7; 64]);
+        assert_eq!(
+            parsed.private_key("hot")?.to_bytes(),
+            PrivateKey::new("deadbeef", 16)?.to_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn saves_and_opens() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("keystore-{}.dat", std::process::id()));
+        keystore()?.save(&path)?;
+        let opened = Keystore::open(&path, "correct horse")?;
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path)?.permissions());
+        fs::remove_file(&path)?;
+
+        assert_eq!(*opened.seed("wallet")?, [7; 64]);
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_hides_secrets() -> Result<()> {
+        let keystore = keystore()?;
+        let debug = format!("{keystore:?}");
+
+        assert!(debug.contains("\"hot\"") && debug.contains("\"wallet\""));
+        assert!(!debug.contains(&hex::encode(*keystore.key)));
+        Ok(())
+    }
+}
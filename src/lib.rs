@@ -1,2 +1,21 @@
+pub mod block;
+pub mod chain;
+pub mod descriptor;
+pub mod filter;
+pub mod hd;
+pub mod keystore;
+pub mod mempool;
+pub mod message;
+pub mod mining;
+pub mod miniscript;
+pub mod network;
+pub mod p2p;
+pub mod psbt;
+pub mod script;
 pub mod secp256k1;
+pub mod spv;
+pub mod transaction;
 mod utils;
+pub mod utxo;
+pub mod validation;
+pub mod wallet;
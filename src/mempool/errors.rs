@@ -0,0 +1,52 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors of transactions the mempool does not accept
+pub enum MempoolError {
+    #[error("transaction {0} is already in the mempool")]
+    AlreadyInMempool(String),
+    #[error("fee {0} is below the minimum relay fee of {1} for its size")]
+    MinRelayFee(u64, u64),
+    #[error("fee {0} is below the mempool minimum fee of {1} for its size")]
+    MempoolMinFee(u64, u64),
+    #[error("transaction exceeds the {0} limit of its mempool package")]
+    TooLongChain(&'static str),
+    #[error("transaction spends an output of a transaction it replaces")]
+    SpendsConflictingTx,
+    #[error("transaction conflicts with {0}, which does not signal replaceability")]
+    NotReplaceable(String),
+    #[error("replacement would evict {0} transactions, above the limit of {1}")]
+    TooManyReplacements(usize, usize),
+    #[error("replacement spends unconfirmed outputs the transactions it replaces do not")]
+    ReplacementAddsUnconfirmed,
+    #[error("replacement fee {0} does not pay for the {1} it replaces")]
+    InsufficientFee(u64, u64),
+    #[error("replacement fee rate is not above that of {0}")]
+    InsufficientFeeRate(String),
+    #[error("transaction was evicted to keep the mempool under its size limit")]
+    MempoolFull,
+    #[error("transactions of a package cannot replace mempool transactions")]
+    PackageConflict,
+    #[error("package is not a child with its unconfirmed parents")]
+    NotChildWithParents,
+}
+
+impl MempoolError {
+    /// Reason Bitcoin Core gives for rejecting a transaction with this error
+    pub fn reject_reason(&self) -> &'static str {
+        match self {
+            Self::AlreadyInMempool(_) => "txn-already-in-mempool",
+            Self::MinRelayFee(..) => "min relay fee not met",
+            Self::MempoolMinFee(..) => "mempool min fee not met",
+            Self::TooLongChain(_) => "too-long-mempool-chain",
+            Self::SpendsConflictingTx => "bad-txns-spends-conflicting-tx",
+            Self::NotReplaceable(_) => "txn-mempool-conflict",
+            Self::TooManyReplacements(..) => "too many potential replacements",
+            Self::ReplacementAddsUnconfirmed => "replacement-adds-unconfirmed",
+            Self::InsufficientFee(..) | Self::InsufficientFeeRate(_) => "insufficient fee",
+            Self::MempoolFull => "mempool full",
+            Self::PackageConflict => "package RBF not supported",
+            Self::NotChildWithParents => "package-not-child-with-parents",
+        }
+    }
+}
@@ -0,0 +1,1141 @@
+use {
+    super::errors::MempoolError,
+    crate::{
+        block::body::Block,
+        script::interpreter::MANDATORY_SCRIPT_VERIFY_FLAGS,
+        transaction::tx::{OutPoint, Transaction, Txid, SEQUENCE_ENABLE_LOCKTIME_NO_RBF},
+        utils::encoding::reversed_hex,
+        utxo::set::{Coin, UtxoView},
+        validation::{chainstate::tx_sigop_cost, tx::validate_transaction},
+    },
+    anyhow::{bail, Result},
+    std::{
+        cmp::Ordering,
+        collections::{HashMap, HashSet},
+    },
+};
+
+/// Most virtual bytes the mempool holds by default
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 300_000_000;
+
+/// Seconds after which transactions expire from the mempool by default, two weeks
+pub const DEFAULT_MEMPOOL_EXPIRY: u64 = 14 * 24 * 60 * 60;
+
+/// Lowest fee rate relayed by default, in satoshis per 1000 virtual bytes
+pub const DEFAULT_MIN_RELAY_FEE: u64 = 1000;
+
+/// Fee rate a replacement or the minimum fee after an eviction must add by default, in satoshis
+/// per 1000 virtual bytes
+pub const DEFAULT_INCREMENTAL_RELAY_FEE: u64 = 1000;
+
+/// Most transactions in a package of ancestors or descendants by default, including itself
+pub const DEFAULT_PACKAGE_COUNT_LIMIT: usize = 25;
+
+/// Most virtual bytes of a package of ancestors or descendants by default, including itself
+pub const DEFAULT_PACKAGE_SIZE_LIMIT: usize = 101_000;
+
+/// Most transactions a replacement may evict, as specified by BIP125
+pub const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+/// Seconds for the minimum fee rate raised by an eviction to halve
+const ROLLING_FEE_HALFLIFE: u64 = 12 * 60 * 60;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Policy limits of a mempool
+pub struct MempoolLimits {
+    /// Most virtual bytes of transactions held
+    pub max_size: usize,
+    /// Seconds a transaction is held for
+    pub expiry: u64,
+    /// Lowest fee rate accepted, in satoshis per 1000 virtual bytes
+    pub min_relay_fee: u64,
+    /// Fee rate replacements and the minimum fee after an eviction must add
+    pub incremental_relay_fee: u64,
+    pub ancestor_count: usize,
+    pub ancestor_size: usize,
+    pub descendant_count: usize,
+    pub descendant_size: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Transaction in the mempool, with the totals of its packages of unconfirmed ancestors and
+/// descendants, which include the transaction itself
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: usize,
+    /// Signature operations of the transaction, in weight units
+    pub sigop_cost: usize,
+    /// Seconds since the Unix epoch the transaction was accepted at
+    pub time: u64,
+    /// Height of the block the transaction was validated for
+    pub height: u32,
+    /// Mempool transactions whose outputs the transaction spends
+    pub parents: HashSet<Txid>,
+    /// Mempool transactions spending outputs of the transaction
+    pub children: HashSet<Txid>,
+    pub ancestor_count: usize,
+    pub ancestor_size: usize,
+    pub ancestor_fees: u64,
+    pub descendant_count: usize,
+    pub descendant_size: usize,
+    pub descendant_fees: u64,
+}
+
+/// Unconfirmed transactions waiting to be mined, validated against the coins of the chain tip and
+/// each other
+///
+/// Transactions spending the same output as one in the mempool replace it if they follow the
+/// rules of BIP125. When the mempool grows above its size limit, the packages of descendants with
+/// the lowest fee rate are evicted and the minimum fee rate rises above theirs.
+pub struct Mempool {
+    limits: MempoolLimits,
+    entries: HashMap<Txid, MempoolEntry>,
+    /// Mempool transaction spending each output
+    spends: HashMap<OutPoint, Txid>,
+    /// Total virtual size of the transactions
+    size: usize,
+    /// Minimum fee rate raised by the last eviction, and when it was raised
+    rolling_min_fee: (u64, u64),
+}
+
+/// Transaction validated against the mempool, ready to be added
+struct Candidate {
+    tx: Transaction,
+    txid: Txid,
+    fee: u64,
+    vsize: usize,
+    sigop_cost: usize,
+    parents: HashSet<Txid>,
+    /// Transactions the candidate replaces, the ones it conflicts with and their descendants
+    replaced: HashSet<Txid>,
+}
+
+/// Coins of a view and outputs of the transactions in the mempool
+struct MempoolView<'a, V: UtxoView> {
+    mempool: &'a Mempool,
+    base: &'a V,
+    height: u32,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_MEMPOOL_SIZE,
+            expiry: DEFAULT_MEMPOOL_EXPIRY,
+            min_relay_fee: DEFAULT_MIN_RELAY_FEE,
+            incremental_relay_fee: DEFAULT_INCREMENTAL_RELAY_FEE,
+            ancestor_count: DEFAULT_PACKAGE_COUNT_LIMIT,
+            ancestor_size: DEFAULT_PACKAGE_SIZE_LIMIT,
+            descendant_count: DEFAULT_PACKAGE_COUNT_LIMIT,
+            descendant_size: DEFAULT_PACKAGE_SIZE_LIMIT,
+        }
+    }
+}
+
+/// Fee of `size` virtual bytes at `fee_rate` satoshis per 1000 virtual bytes
+pub fn fee_for_size(fee_rate: u64, size: usize) -> u64 {
+    fee_rate * size as u64 / 1000
+}
+
+/// Compare the fee rates `fee_a / size_a` and `fee_b / size_b` without rounding
+pub(crate) fn compare_fee_rates(fee_a: u64, size_a: usize, fee_b: u64, size_b: usize) -> Ordering {
+    (fee_a as u128 * size_b as u128).cmp(&(fee_b as u128 * size_a as u128))
+}
+
+impl MempoolEntry {
+    /// Fee rate of the transaction alone, in satoshis per 1000 virtual bytes
+    pub fn fee_rate(&self) -> u64 {
+        self.fee * 1000 / self.vsize as u64
+    }
+
+    /// Fee rate of the transaction with its unconfirmed ancestors, which miners include together
+    pub fn ancestor_fee_rate(&self) -> u64 {
+        self.ancestor_fees * 1000 / self.ancestor_size as u64
+    }
+
+    /// Fee rate of the transaction with its descendants, which are evicted together
+    pub fn descendant_fee_rate(&self) -> u64 {
+        self.descendant_fees * 1000 / self.descendant_size as u64
+    }
+
+    /// If the transaction signals that it can be replaced, as specified by BIP125
+    fn signals_rbf(&self) -> bool {
+        self.tx
+            .inputs
+            .iter()
+            .any(|input| input.sequence < SEQUENCE_ENABLE_LOCKTIME_NO_RBF)
+    }
+}
+
+impl<V: UtxoView> UtxoView for MempoolView<'_, V> {
+    fn get_coin(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        let Some(entry) = self.mempool.entries.get(&outpoint.txid) else {
+            return self.base.get_coin(outpoint);
+        };
+
+        // unconfirmed outputs count as created in the next block
+        Ok(entry
+            .tx
+            .outputs
+            .get(outpoint.vout as usize)
+            .map(|txout| Coin {
+                txout: txout.clone(),
+                height: self.height,
+                is_coinbase: false,
+            }))
+    }
+}
+
+impl Mempool {
+    pub fn new(limits: MempoolLimits) -> Self {
+        Self {
+            limits,
+            entries: HashMap::new(),
+            spends: HashMap::new(),
+            size: 0,
+            rolling_min_fee: (0, 0),
+        }
+    }
+
+    pub fn limits(&self) -> &MempoolLimits {
+        &self.limits
+    }
+
+    /// Number of transactions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total virtual size of the transactions
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, txid: &Txid) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    /// Mempool transaction spending `outpoint`
+    pub fn spender(&self, outpoint: &OutPoint) -> Option<&Txid> {
+        self.spends.get(outpoint)
+    }
+
+    /// Lowest fee rate accepted at `time`, in satoshis per 1000 virtual bytes
+    ///
+    /// An eviction raises it above the relay minimum, after which it halves every 12 hours.
+    pub fn min_fee_rate(&self, time: u64) -> u64 {
+        let (rate, since) = self.rolling_min_fee;
+        let halvings = time.saturating_sub(since) / ROLLING_FEE_HALFLIFE;
+        let rate = rate.checked_shr(halvings as u32).unwrap_or_default();
+        // the raised rate is dropped once it is negligible
+        match rate < self.limits.incremental_relay_fee / 2 {
+            true => self.limits.min_relay_fee,
+            false => rate.max(self.limits.min_relay_fee),
+        }
+    }
+
+    /// Transactions ordered by their ancestor fee rate, highest first, the order miners pick
+    /// packages in
+    pub fn by_ancestor_fee_rate(&self) -> Vec<&MempoolEntry> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            compare_fee_rates(
+                b.ancestor_fees,
+                b.ancestor_size,
+                a.ancestor_fees,
+                a.ancestor_size,
+            )
+            .then(a.txid.cmp(&b.txid))
+        });
+        entries
+    }
+
+    /// Transactions reachable from `txid` by following `next`, excluding itself
+    fn closure(&self, txid: &Txid, next: fn(&MempoolEntry) -> &HashSet<Txid>) -> HashSet<Txid> {
+        let mut found = HashSet::new();
+        let mut queue = vec![*txid];
+        while let Some(txid) = queue.pop() {
+            for other in self.entries.get(&txid).map(next).into_iter().flatten() {
+                if found.insert(*other) {
+                    queue.push(*other);
+                }
+            }
+        }
+        found
+    }
+
+    /// Unconfirmed transactions `txid` spends outputs of, directly or not
+    pub fn ancestors(&self, txid: &Txid) -> HashSet<Txid> {
+        self.closure(txid, |entry| &entry.parents)
+    }
+
+    /// Mempool transactions spending outputs of `txid`, directly or not
+    pub fn descendants(&self, txid: &Txid) -> HashSet<Txid> {
+        self.closure(txid, |entry| &entry.children)
+    }
+
+    /// Recompute the package totals of `txid`
+    fn refresh(&mut self, txid: &Txid) {
+        let totals = |txids: HashSet<Txid>| {
+            txids.iter().fold((1, 0, 0), |(count, size, fees), txid| {
+                let entry = &self.entries[txid];
+                (count + 1, size + entry.vsize, fees + entry.fee)
+            })
+        };
+        let ancestors = totals(self.ancestors(txid));
+        let descendants = totals(self.descendants(txid));
+
+        let entry = self.entries.get_mut(txid).unwrap();
+        (
+            entry.ancestor_count,
+            entry.ancestor_size,
+            entry.ancestor_fees,
+        ) = (
+            ancestors.0,
+            ancestors.1 + entry.vsize,
+            ancestors.2 + entry.fee,
+        );
+        (
+            entry.descendant_count,
+            entry.descendant_size,
+            entry.descendant_fees,
+        ) = (
+            descendants.0,
+            descendants.1 + entry.vsize,
+            descendants.2 + entry.fee,
+        );
+    }
+
+    /// Remove the transactions `txids`, keeping their descendants that are not removed, and return
+    /// their entries
+    fn remove(&mut self, txids: &HashSet<Txid>) -> Vec<MempoolEntry> {
+        let mut removed = vec![];
+        let mut affected = HashSet::new();
+        for txid in txids {
+            affected.extend(self.ancestors(txid));
+            affected.extend(self.descendants(txid));
+        }
+
+        for txid in txids {
+            let Some(entry) = self.entries.remove(txid) else {
+                continue;
+            };
+            self.size -= entry.vsize;
+            for input in &entry.tx.inputs {
+                self.spends.remove(&input.previous_output);
+            }
+            for parent in &entry.parents {
+                if let Some(parent) = self.entries.get_mut(parent) {
+                    parent.children.remove(txid);
+                }
+            }
+            for child in &entry.children {
+                if let Some(child) = self.entries.get_mut(child) {
+                    child.parents.remove(txid);
+                }
+            }
+            removed.push(entry);
+        }
+
+        for txid in affected.difference(txids) {
+            self.refresh(txid);
+        }
+        removed
+    }
+
+    /// Remove `txid` with all of its descendants, returning their entries
+    fn remove_with_descendants(&mut self, txid: &Txid) -> Vec<MempoolEntry> {
+        let mut txids = self.descendants(txid);
+        txids.insert(*txid);
+        self.remove(&txids)
+    }
+
+    /// Undo a change that failed, removing the transactions `added` and putting back the
+    /// `removed` entries with the minimum fee rate from before the change
+    fn restore(
+        &mut self,
+        added: &HashSet<Txid>,
+        removed: Vec<MempoolEntry>,
+        rolling_min_fee: (u64, u64),
+    ) {
+        self.remove(added);
+
+        let mut restored = HashSet::new();
+        for entry in removed {
+            if added.contains(&entry.txid) {
+                continue;
+            }
+            for input in &entry.tx.inputs {
+                self.spends.insert(input.previous_output, entry.txid);
+            }
+            self.size += entry.vsize;
+            restored.insert(entry.txid);
+            self.entries.insert(entry.txid, entry);
+        }
+
+        // links to entries removed along with them were dropped, so relink from the spends
+        for txid in &restored {
+            let entry = &self.entries[txid];
+            let parents = entry
+                .tx
+                .inputs
+                .iter()
+                .map(|input| input.previous_output.txid)
+                .filter(|parent| self.entries.contains_key(parent))
+                .collect::<HashSet<_>>();
+            let children = (0..entry.tx.outputs.len())
+                .filter_map(|vout| self.spends.get(&OutPoint::new(*txid, vout as u32)))
+                .copied()
+                .collect::<HashSet<_>>();
+            for parent in &parents {
+                self.entries.get_mut(parent).unwrap().children.insert(*txid);
+            }
+            for child in &children {
+                self.entries.get_mut(child).unwrap().parents.insert(*txid);
+            }
+            let entry = self.entries.get_mut(txid).unwrap();
+            entry.parents = parents;
+            entry.children = children;
+        }
+
+        let mut affected = restored.clone();
+        for txid in &restored {
+            affected.extend(self.ancestors(txid));
+            affected.extend(self.descendants(txid));
+        }
+        for txid in &affected {
+            self.refresh(txid);
+        }
+        self.rolling_min_fee = rolling_min_fee;
+    }
+
+    /// Validate `tx` against the mempool and the coins of `view`, for a block at `height`
+    ///
+    /// The fee rate is checked separately, as packages are checked as a whole.
+    fn prepare(
+        &self,
+        tx: Transaction,
+        view: &impl UtxoView,
+        height: u32,
+        median_time_past: impl Fn(u32) -> u32,
+    ) -> Result<Candidate> {
+        let txid = tx.txid();
+        if self.contains(&txid) {
+            bail!(MempoolError::AlreadyInMempool(reversed_hex(&txid)));
+        }
+
+        let mempool_view = MempoolView {
+            mempool: self,
+            base: view,
+            height,
+        };
+        let fee = validate_transaction(
+            &tx,
+            &mempool_view,
+            height,
+            median_time_past,
+            MANDATORY_SCRIPT_VERIFY_FLAGS,
+        )?;
+        let vsize = tx.vsize();
+        let coins = tx
+            .inputs
+            .iter()
+            .filter_map(|input| mempool_view.get_coin(&input.previous_output).transpose())
+            .collect::<Result<Vec<_>>>()?;
+        let sigop_cost = tx_sigop_cost(&tx, &coins, MANDATORY_SCRIPT_VERIFY_FLAGS);
+
+        let parents = tx
+            .inputs
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .filter(|txid| self.contains(txid))
+            .collect::<HashSet<_>>();
+        let mut ancestors = parents.clone();
+        for parent in &parents {
+            ancestors.extend(self.ancestors(parent));
+        }
+
+        let conflicts = tx
+            .inputs
+            .iter()
+            .filter_map(|input| self.spends.get(&input.previous_output).copied())
+            .collect::<HashSet<_>>();
+        let mut replaced = conflicts.clone();
+        for conflict in &conflicts {
+            replaced.extend(self.descendants(conflict));
+        }
+        if !ancestors.is_disjoint(&replaced) {
+            bail!(MempoolError::SpendsConflictingTx);
+        }
+
+        self.check_package_limits(&ancestors, vsize)?;
+        if !conflicts.is_empty() {
+            self.check_replacement(fee, vsize, &parents, &conflicts, &replaced)?;
+        }
+
+        Ok(Candidate {
+            tx,
+            txid,
+            fee,
+            vsize,
+            sigop_cost,
+            parents,
+            replaced,
+        })
+    }
+
+    /// Check a transaction of `vsize` with `ancestors` keeps every package within the limits
+    fn check_package_limits(&self, ancestors: &HashSet<Txid>, vsize: usize) -> Result<()> {
+        let limits = &self.limits;
+        if ancestors.len() + 1 > limits.ancestor_count {
+            bail!(MempoolError::TooLongChain("ancestor count"));
+        }
+        let ancestor_size = ancestors
+            .iter()
+            .map(|txid| self.entries[txid].vsize)
+            .sum::<usize>();
+        if ancestor_size + vsize > limits.ancestor_size {
+            bail!(MempoolError::TooLongChain("ancestor size"));
+        }
+
+        for ancestor in ancestors.iter().map(|txid| &self.entries[txid]) {
+            if ancestor.descendant_count + 1 > limits.descendant_count {
+                bail!(MempoolError::TooLongChain("descendant count"));
+            }
+            if ancestor.descendant_size + vsize > limits.descendant_size {
+                bail!(MempoolError::TooLongChain("descendant size"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a transaction paying `fee` for `vsize` may replace the transactions `replaced`,
+    /// `conflicts` and their descendants, as specified by BIP125
+    fn check_replacement(
+        &self,
+        fee: u64,
+        vsize: usize,
+        parents: &HashSet<Txid>,
+        conflicts: &HashSet<Txid>,
+        replaced: &HashSet<Txid>,
+    ) -> Result<()> {
+        for conflict in conflicts {
+            // replaceability is inherited from unconfirmed ancestors
+            let signals = self.entries[conflict].signals_rbf()
+                || self
+                    .ancestors(conflict)
+                    .iter()
+                    .any(|txid| self.entries[txid].signals_rbf());
+            if !signals {
+                bail!(MempoolError::NotReplaceable(reversed_hex(conflict)));
+            }
+        }
+
+        if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+            bail!(MempoolError::TooManyReplacements(
+                replaced.len(),
+                MAX_REPLACEMENT_CANDIDATES
+            ));
+        }
+
+        // only outputs the replaced transactions already spend may be unconfirmed
+        let conflict_parents = conflicts
+            .iter()
+            .flat_map(|txid| &self.entries[txid].parents)
+            .collect::<HashSet<_>>();
+        if parents
+            .iter()
+            .any(|parent| !conflict_parents.contains(parent))
+        {
+            bail!(MempoolError::ReplacementAddsUnconfirmed);
+        }
+
+        for conflict in conflicts.iter().map(|txid| &self.entries[txid]) {
+            if compare_fee_rates(fee, vsize, conflict.fee, conflict.vsize) != Ordering::Greater {
+                bail!(MempoolError::InsufficientFeeRate(reversed_hex(
+                    &conflict.txid
+                )));
+            }
+        }
+
+        // the replacement pays for the bandwidth of relaying itself on top of the replaced fees
+        let replaced_fees = replaced
+            .iter()
+            .map(|txid| self.entries[txid].fee)
+            .sum::<u64>();
+        let required = replaced_fees + fee_for_size(self.limits.incremental_relay_fee, vsize);
+        if fee < required {
+            bail!(MempoolError::InsufficientFee(fee, required));
+        }
+
+        Ok(())
+    }
+
+    /// Check `fee` for `vsize` virtual bytes meets the minimum fee rates at `time`
+    fn check_fee_rate(&self, fee: u64, vsize: usize, time: u64) -> Result<()> {
+        let relay = fee_for_size(self.limits.min_relay_fee, vsize);
+        if fee < relay {
+            bail!(MempoolError::MinRelayFee(fee, relay));
+        }
+        let mempool = fee_for_size(self.min_fee_rate(time), vsize);
+        if fee < mempool {
+            bail!(MempoolError::MempoolMinFee(fee, mempool));
+        }
+
+        Ok(())
+    }
+
+    /// Add a validated transaction, removing the ones it replaces and returning their entries
+    fn insert(&mut self, candidate: Candidate, height: u32, time: u64) -> Vec<MempoolEntry> {
+        let replaced = self.remove(&candidate.replaced);
+
+        let txid = candidate.txid;
+        for input in &candidate.tx.inputs {
+            self.spends.insert(input.previous_output, txid);
+        }
+        for parent in &candidate.parents {
+            self.entries.get_mut(parent).unwrap().children.insert(txid);
+        }
+        self.size += candidate.vsize;
+        self.entries.insert(
+            txid,
+            MempoolEntry {
+                tx: candidate.tx,
+                txid,
+                fee: candidate.fee,
+                vsize: candidate.vsize,
+                sigop_cost: candidate.sigop_cost,
+                time,
+                height,
+                parents: candidate.parents,
+                children: HashSet::new(),
+                ancestor_count: 1,
+                ancestor_size: candidate.vsize,
+                ancestor_fees: candidate.fee,
+                descendant_count: 1,
+                descendant_size: candidate.vsize,
+                descendant_fees: candidate.fee,
+            },
+        );
+
+        self.refresh(&txid);
+        for ancestor in self.ancestors(&txid) {
+            self.refresh(&ancestor);
+        }
+        replaced
+    }
+
+    /// Evict the packages of descendants with the lowest fee rates until the mempool is within
+    /// its size limit, raising the minimum fee rate above theirs, and return the evicted entries
+    fn trim(&mut self, time: u64) -> Vec<MempoolEntry> {
+        let mut evicted = vec![];
+        while self.size > self.limits.max_size {
+            let Some(lowest) = self.entries.values().min_by(|a, b| {
+                compare_fee_rates(
+                    a.descendant_fees,
+                    a.descendant_size,
+                    b.descendant_fees,
+                    b.descendant_size,
+                )
+                .then(a.txid.cmp(&b.txid))
+            }) else {
+                break;
+            };
+
+            let rate = lowest.descendant_fee_rate() + self.limits.incremental_relay_fee;
+            let txid = lowest.txid;
+            self.rolling_min_fee = (rate.max(self.min_fee_rate(time)), time);
+            evicted.extend(self.remove_with_descendants(&txid));
+        }
+        evicted
+    }
+
+    /// Validate `tx` against the coins of `view` and the mempool, for a block at `height`, and add
+    /// it at `time`, seconds since the Unix epoch
+    ///
+    /// `median_time_past` gives the median time past of the block at each height below `height`.
+    /// Transactions it conflicts with are replaced if it follows the rules of BIP125.
+    pub fn accept(
+        &mut self,
+        tx: Transaction,
+        view: &impl UtxoView,
+        height: u32,
+        median_time_past: impl Fn(u32) -> u32,
+        time: u64,
+    ) -> Result<Txid> {
+        let candidate = self.prepare(tx, view, height, median_time_past)?;
+        self.check_fee_rate(candidate.fee, candidate.vsize, time)?;
+
+        let txid = candidate.txid;
+        let rolling_min_fee = self.rolling_min_fee;
+        let mut removed = self.insert(candidate, height, time);
+        removed.extend(self.trim(time));
+        if !self.contains(&txid) {
+            // the transactions it replaced and the packages evicted for it come back
+            self.restore(&HashSet::from([txid]), removed, rolling_min_fee);
+            bail!(MempoolError::MempoolFull);
+        }
+
+        Ok(txid)
+    }
+
+    /// Validate and add `txs`, a package of parents sorted so that they come before their
+    /// children, followed by a child spending every one of them, whose fee rate is checked as a
+    /// whole
+    ///
+    /// This lets a child pay for a parent whose fee rate is too low to be accepted alone. Members
+    /// already in the mempool are skipped and do not count towards the fee rate. Either the whole
+    /// package is added or none of it.
+    pub fn accept_package(
+        &mut self,
+        txs: Vec<Transaction>,
+        view: &impl UtxoView,
+        height: u32,
+        median_time_past: impl Fn(u32) -> u32,
+        time: u64,
+    ) -> Result<Vec<Txid>> {
+        let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+        let Some((child, parents)) = txs.split_last().filter(|(_, parents)| !parents.is_empty())
+        else {
+            bail!(MempoolError::NotChildWithParents);
+        };
+        let spent = child
+            .inputs
+            .iter()
+            .map(|input| input.previous_output.txid)
+            .collect::<HashSet<_>>();
+        if !parents.iter().all(|parent| spent.contains(&parent.txid())) {
+            bail!(MempoolError::NotChildWithParents);
+        }
+
+        let mut added = HashSet::new();
+        let (mut fees, mut size) = (0, 0);
+        for tx in txs {
+            if self.contains(&tx.txid()) {
+                continue;
+            }
+            let candidate = match self.prepare(tx, view, height, &median_time_past) {
+                Ok(candidate) if candidate.replaced.is_empty() => candidate,
+                result => {
+                    self.remove(&added);
+                    result?;
+                    bail!(MempoolError::PackageConflict);
+                }
+            };
+            fees += candidate.fee;
+            size += candidate.vsize;
+            added.insert(candidate.txid);
+            self.insert(candidate, height, time);
+        }
+        if let Err(e) = self.check_fee_rate(fees, size, time) {
+            self.remove(&added);
+            return Err(e);
+        }
+
+        let rolling_min_fee = self.rolling_min_fee;
+        let evicted = self.trim(time);
+        if !txids.iter().all(|txid| self.contains(txid)) {
+            self.restore(&added, evicted, rolling_min_fee);
+            bail!(MempoolError::MempoolFull);
+        }
+
+        Ok(txids)
+    }
+
+    /// Remove the transactions accepted more than the expiry before `time`, with their
+    /// descendants, returning how many were removed
+    pub fn expire(&mut self, time: u64) -> usize {
+        let expired = self
+            .entries
+            .values()
+            .filter(|entry| entry.time + self.limits.expiry < time)
+            .map(|entry| entry.txid)
+            .collect::<Vec<_>>();
+
+        let before = self.len();
+        for txid in expired {
+            self.remove_with_descendants(&txid);
+        }
+        before - self.len()
+    }
+
+    /// Remove the transactions confirmed by `block`, and those spending the same outputs with
+    /// their descendants
+    pub fn remove_for_block(&mut self, block: &Block) {
+        for tx in &block.transactions {
+            let txid = tx.txid();
+            if self.contains(&txid) {
+                self.remove(&HashSet::from([txid]));
+                continue;
+            }
+
+            for input in &tx.inputs {
+                if let Some(conflict) = self.spends.get(&input.previous_output).copied() {
+                    self.remove_with_descendants(&conflict);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            network::Network,
+            script::raw::Script,
+            secp256k1::keys::PrivateKey,
+            transaction::{
+                sighash::SIGHASH_ALL,
+                tx::{TxIn, TxOut, SEQUENCE_FINAL},
+            },
+        },
+    };
+
+    fn key() -> PrivateKey {
+        PrivateKey::new("5eed", 16).unwrap()
+    }
+
+    fn script() -> Script {
+        Script::p2wpkh(&key().point().hash160(true))
+    }
+
+    /// View of `count` confirmed coins of 10000 satoshis paying the test key
+    fn view(count: u8) -> HashMap<OutPoint, Coin> {
+        (0..count)
+            .map(|i| {
+                let coin = Coin {
+                    txout: TxOut::new(10_000, script()),
+                    height: 1,
+                    is_coinbase: false,
+                };
+                (OutPoint::new([i; 32], 0), coin)
+            })
+            .collect()
+    }
+
+    /// Transaction spending `inputs` of the test key, with their values, into one output per
+    /// value of `outputs`, signalling replaceability if `rbf`
+    fn spend(inputs: &[(OutPoint, u64)], outputs: &[u64], rbf: bool) -> Transaction {
+        let mut tx = Transaction::new(2, 0);
+        for (outpoint, _) in inputs {
+            let mut input = TxIn::new(*outpoint);
+            input.sequence = if rbf { 0 } else { SEQUENCE_FINAL };
+            tx.inputs.push(input);
+        }
+        for value in outputs {
+            tx.outputs.push(TxOut::new(*value, script()));
+        }
+
+        let code = Script::p2pkh(&key().point().hash160(true));
+        for (index, (_, value)) in inputs.iter().enumerate() {
+            let sighash = tx
+                .segwit_v0_sighash(index, &code, *value, SIGHASH_ALL as u32)
+                .unwrap();
+            let mut sig = key().sign(&hex::encode(sighash)).unwrap().der();
+            sig.push(SIGHASH_ALL);
+            tx.inputs[index].witness = vec![sig, key().point().sec(true)];
+        }
+        tx
+    }
+
+    fn mtp(height: u32) -> u32 {
+        1_600_000_000 + height * 600
+    }
+
+    fn reason(result: Result<impl std::fmt::Debug>) -> &'static str {
+        result
+            .unwrap_err()
+            .downcast::<MempoolError>()
+            .unwrap()
+            .reject_reason()
+    }
+
+    fn coin(n: u8) -> (OutPoint, u64) {
+        (OutPoint::new([n; 32], 0), 10_000)
+    }
+
+    #[test]
+    fn child_pays_for_parent_ordering() -> Result<()> {
+        let view = view(2);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+
+        let parent = spend(&[coin(0)], &[9_800], false);
+        let parent_id = mempool.accept(parent, &view, 100, mtp, 0)?;
+        let child = spend(&[(OutPoint::new(parent_id, 0), 9_800)], &[8_800], false);
+        let child_id = mempool.accept(child.clone(), &view, 100, mtp, 0)?;
+        let other = spend(&[coin(1)], &[9_500], false);
+        let other_id = mempool.accept(other, &view, 100, mtp, 0)?;
+        assert_eq!(
+            reason(mempool.accept(child, &view, 100, mtp, 0)),
+            "txn-already-in-mempool"
+        );
+
+        let parent = mempool.get(&parent_id).unwrap();
+        assert_eq!(parent.descendant_count, 2);
+        assert_eq!(parent.descendant_fees, 1_200);
+        let child = mempool.get(&child_id).unwrap();
+        assert_eq!(child.ancestor_count, 2);
+        assert_eq!(child.ancestor_size, parent.vsize + child.vsize);
+        assert!(child.ancestor_fee_rate() > mempool.get(&other_id).unwrap().fee_rate());
+
+        let order = mempool
+            .by_ancestor_fee_rate()
+            .into_iter()
+            .map(|entry| entry.txid)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![child_id, other_id, parent_id]);
+        assert_eq!(mempool.ancestors(&child_id), HashSet::from([parent_id]));
+        Ok(())
+    }
+
+    #[test]
+    fn replace_by_fee() -> Result<()> {
+        let view = view(2);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+
+        let final_tx = spend(&[coin(0)], &[9_800], false);
+        mempool.accept(final_tx, &view, 100, mtp, 0)?;
+        let replacement = spend(&[coin(0)], &[9_000], true);
+        assert_eq!(
+            reason(mempool.accept(replacement, &view, 100, mtp, 0)),
+            "txn-mempool-conflict"
+        );
+
+        let original = spend(&[coin(1)], &[9_800], true);
+        let original_id = mempool.accept(original, &view, 100, mtp, 0)?;
+        let child = spend(&[(OutPoint::new(original_id, 0), 9_800)], &[9_600], false);
+        let child_id = mempool.accept(child, &view, 100, mtp, 0)?;
+
+        // the replacement must pay for both transactions it evicts and its own relay
+        let cheap = spend(&[coin(1)], &[9_500], false);
+        assert_eq!(
+            reason(mempool.accept(cheap, &view, 100, mtp, 0)),
+            "insufficient fee"
+        );
+        let replacement = spend(&[coin(1)], &[9_000], false);
+        let replacement_id = mempool.accept(replacement, &view, 100, mtp, 0)?;
+        assert!(!mempool.contains(&original_id) && !mempool.contains(&child_id));
+        assert_eq!(mempool.spender(&coin(1).0), Some(&replacement_id));
+        assert_eq!(mempool.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn replacements_cannot_add_unconfirmed_inputs() -> Result<()> {
+        let view = view(2);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let original = spend(&[coin(0)], &[9_800], true);
+        mempool.accept(original, &view, 100, mtp, 0)?;
+        let unconfirmed = spend(&[coin(1)], &[9_800], false);
+        let unconfirmed_id = mempool.accept(unconfirmed, &view, 100, mtp, 0)?;
+
+        let replacement = spend(
+            &[coin(0), (OutPoint::new(unconfirmed_id, 0), 9_800)],
+            &[15_000],
+            false,
+        );
+        assert_eq!(
+            reason(mempool.accept(replacement, &view, 100, mtp, 0)),
+            "replacement-adds-unconfirmed"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn package_limits() -> Result<()> {
+        let view = view(1);
+        let limits = MempoolLimits {
+            ancestor_count: 2,
+            ..Default::default()
+        };
+        let mut mempool = Mempool::new(limits);
+
+        let parent = mempool.accept(spend(&[coin(0)], &[9_800], false), &view, 100, mtp, 0)?;
+        let child = spend(&[(OutPoint::new(parent, 0), 9_800)], &[9_600], false);
+        let child = mempool.accept(child, &view, 100, mtp, 0)?;
+        let grandchild = spend(&[(OutPoint::new(child, 0), 9_600)], &[9_400], false);
+        assert_eq!(
+            reason(mempool.accept(grandchild, &view, 100, mtp, 0)),
+            "too-long-mempool-chain"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn evicts_lowest_fee_rate_and_expires() -> Result<()> {
+        let view = view(3);
+        let low = spend(&[coin(0)], &[9_850], false);
+        let high = spend(&[coin(1)], &[9_000], false);
+        let limits = MempoolLimits {
+            max_size: low.vsize() + high.vsize(),
+            ..Default::default()
+        };
+        let mut mempool = Mempool::new(limits);
+        assert_eq!(
+            reason(mempool.accept(spend(&[coin(2)], &[10_000], false), &view, 100, mtp, 0)),
+            "min relay fee not met"
+        );
+
+        let low_id = mempool.accept(low.clone(), &view, 100, mtp, 0)?;
+        let high_id = mempool.accept(high, &view, 100, mtp, 10)?;
+        let third = spend(&[coin(2)], &[9_500], false);
+        let third_id = mempool.accept(third, &view, 100, mtp, 20)?;
+        assert!(!mempool.contains(&low_id));
+        assert_eq!(mempool.len(), 2);
+
+        // the minimum fee rose above the evicted fee rate, then decays
+        assert!(mempool.min_fee_rate(20) > fee_rate_of(&low));
+        assert_eq!(
+            reason(mempool.accept(low.clone(), &view, 100, mtp, 20)),
+            "mempool min fee not met"
+        );
+        assert_eq!(
+            mempool.min_fee_rate(20 + 8 * ROLLING_FEE_HALFLIFE),
+            DEFAULT_MIN_RELAY_FEE
+        );
+
+        assert_eq!(mempool.expire(DEFAULT_MEMPOOL_EXPIRY + 15), 1);
+        assert!(!mempool.contains(&high_id) && mempool.contains(&third_id));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_acceptance_leaves_the_mempool_unchanged() -> Result<()> {
+        let view = view(5);
+        let original = spend(&[coin(0)], &[9_000], true);
+        let high = spend(&[coin(1)], &[5_000], false);
+        let limits = MempoolLimits {
+            max_size: original.vsize() + high.vsize(),
+            ..Default::default()
+        };
+        let mut mempool = Mempool::new(limits);
+        let original_id = mempool.accept(original, &view, 100, mtp, 0)?;
+        let high_id = mempool.accept(high, &view, 100, mtp, 0)?;
+
+        // a larger replacement paying less than the other transaction is evicted at once, which
+        // brings back the one it replaced
+        let replacement = spend(&[coin(0)], &[3_000, 3_000, 1_000], false);
+        assert_eq!(
+            reason(mempool.accept(replacement, &view, 100, mtp, 0)),
+            "mempool full"
+        );
+        assert_eq!(mempool.spender(&coin(0).0), Some(&original_id));
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.min_fee_rate(0), DEFAULT_MIN_RELAY_FEE);
+
+        // as does a package evicted after a cheaper transaction was evicted for it
+        mempool.remove(&HashSet::from([original_id]));
+        let low = spend(&[coin(2)], &[9_850], false);
+        let low_id = mempool.accept(low, &view, 100, mtp, 0)?;
+        let parent = spend(&[coin(3)], &[10_000], false);
+        let child = spend(
+            &[(OutPoint::new(parent.txid(), 0), 10_000)],
+            &[9_600],
+            false,
+        );
+        assert_eq!(
+            reason(mempool.accept_package(vec![parent, child], &view, 100, mtp, 0)),
+            "mempool full"
+        );
+        assert!(mempool.contains(&low_id) && mempool.contains(&high_id));
+        assert_eq!(mempool.get(&low_id).unwrap().descendant_count, 1);
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.min_fee_rate(0), DEFAULT_MIN_RELAY_FEE);
+        Ok(())
+    }
+
+    /// Fee rate of `tx` spending a coin of the test view
+    fn fee_rate_of(tx: &Transaction) -> u64 {
+        (10_000 - tx.output_value()) * 1000 / tx.vsize() as u64
+    }
+
+    #[test]
+    fn packages_pay_for_parents() -> Result<()> {
+        let view = view(1);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let parent = spend(&[coin(0)], &[10_000], false);
+        let child = spend(
+            &[(OutPoint::new(parent.txid(), 0), 10_000)],
+            &[9_000],
+            false,
+        );
+        assert_eq!(
+            reason(mempool.accept(parent.clone(), &view, 100, mtp, 0)),
+            "min relay fee not met"
+        );
+
+        let txids = mempool.accept_package(vec![parent, child], &view, 100, mtp, 0)?;
+        assert_eq!(txids.len(), 2);
+        assert_eq!(mempool.get(&txids[1]).unwrap().ancestor_fees, 1_000);
+        Ok(())
+    }
+
+    #[test]
+    fn packages_are_a_child_with_its_parents() -> Result<()> {
+        let view = view(3);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let parent = spend(&[coin(0)], &[9_000], false);
+        let unrelated = spend(&[coin(1)], &[9_000], false);
+        assert_eq!(
+            reason(mempool.accept_package(vec![parent.clone(), unrelated], &view, 100, mtp, 0)),
+            "package-not-child-with-parents"
+        );
+        assert_eq!(
+            reason(mempool.accept_package(vec![parent.clone()], &view, 100, mtp, 0)),
+            "package-not-child-with-parents"
+        );
+        assert!(mempool.is_empty());
+
+        // a parent already in the mempool is skipped
+        let parent_id = mempool.accept(parent.clone(), &view, 100, mtp, 0)?;
+        let other = spend(&[coin(2)], &[10_000], false);
+        let child = spend(
+            &[
+                (OutPoint::new(parent_id, 0), 9_000),
+                (OutPoint::new(other.txid(), 0), 10_000),
+            ],
+            &[18_000],
+            false,
+        );
+        let txids = mempool.accept_package(vec![parent, other, child], &view, 100, mtp, 0)?;
+        assert_eq!(txids.len(), 3);
+        assert!(txids.iter().all(|txid| mempool.contains(txid)));
+        Ok(())
+    }
+
+    #[test]
+    fn removes_confirmed_and_conflicting_transactions() -> Result<()> {
+        let view = view(2);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let parent = spend(&[coin(0)], &[9_800], false);
+        let parent_id = mempool.accept(parent.clone(), &view, 100, mtp, 0)?;
+        let child = spend(&[(OutPoint::new(parent_id, 0), 9_800)], &[9_600], false);
+        let child_id = mempool.accept(child, &view, 100, mtp, 0)?;
+        let conflicted = mempool.accept(spend(&[coin(1)], &[9_800], false), &view, 100, mtp, 0)?;
+
+        let block = Block {
+            header: Network::Regtest.params().genesis,
+            transactions: vec![parent, spend(&[coin(1)], &[9_700], false)],
+        };
+        mempool.remove_for_block(&block);
+        assert!(!mempool.contains(&parent_id) && !mempool.contains(&conflicted));
+        let child = mempool.get(&child_id).unwrap();
+        assert!(child.parents.is_empty());
+        assert_eq!(child.ancestor_count, 1);
+        assert_eq!(mempool.size(), child.vsize);
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors of signing and verifying messages
+pub enum MessageError {
+    #[error("invalid message signature length {0}, expected 65")]
+    InvalidSignatureLength(usize),
+    #[error("invalid message signature header {0}")]
+    InvalidHeader(u8),
+}
@@ -0,0 +1,215 @@
+use {
+    super::errors::MessageError,
+    crate::{
+        network::Network,
+        script::{address::Address, raw::Script},
+        secp256k1::{keys::PrivateKey, point::Point, signature::Signature},
+        utils::{
+            base64,
+            encoding::{to_32_bytes, var_bytes},
+            hash::hash256,
+        },
+    },
+    anyhow::{bail, Result},
+    ibig::UBig,
+};
+
+/// Prefix of signed messages, so a message signature cannot be a transaction signature
+pub const MESSAGE_MAGIC: &str = "Bitcoin Signed Message:\n";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Type of the address whose ownership a message signature proves, encoded in its header byte
+pub enum MessageAddressType {
+    /// P2PKH address of the uncompressed public key
+    P2pkhUncompressed,
+    /// P2PKH address of the compressed public key
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+}
+
+impl MessageAddressType {
+    /// Header byte of signatures with recovery id 0
+    fn header_base(self) -> u8 {
+        match self {
+            Self::P2pkhUncompressed => 27,
+            Self::P2pkh => 31,
+            Self::P2shP2wpkh => 35,
+            Self::P2wpkh => 39,
+        }
+    }
+
+    /// Address of this type of the public key `point`
+    fn address(self, point: &Point, network: Network) -> Address {
+        match self {
+            Self::P2pkhUncompressed => Address::p2pkh(point, false, network),
+            Self::P2pkh => Address::p2pkh(point, true, network),
+            Self::P2shP2wpkh => Address::p2sh(&Script::p2wpkh(&point.hash160(true)), network),
+            Self::P2wpkh => Address::p2wpkh(point, network),
+        }
+    }
+}
+
+/// Hash of `message` signed by message signatures
+pub fn message_hash(message: &str) -> [u8; 32] {
+    hash256(
+        &[
+            var_bytes(MESSAGE_MAGIC.as_bytes()),
+            var_bytes(message.as_bytes()),
+        ]
+        .concat(),
+    )
+}
+
+/// Sign `message` with `key` as proof of owning its compressed P2PKH address
+///
+/// Returns the base64 encoded header byte, r and s.
+pub fn sign_message(key: &PrivateKey, message: &str) -> Result<String> {
+    sign_message_with_type(key, message, MessageAddressType::P2pkh)
+}
+
+/// Sign `message` with `key` as proof of owning its address of `address_type`, with the header
+/// byte specified by BIP137
+pub fn sign_message_with_type(
+    key: &PrivateKey,
+    message: &str,
+    address_type: MessageAddressType,
+) -> Result<String> {
+    let (signature, recovery_id) = key.sign_recoverable(&hex::encode(message_hash(message)))?;
+    let bytes = [
+        &[address_type.header_base() + recovery_id][..],
+        &to_32_bytes(&signature.r()),
+        &to_32_bytes(&signature.s()),
+    ]
+    .concat();
+
+    Ok(base64::encode(&bytes))
+}
+
+/// Verify that the base64 encoded `signature` of `message` was made by the key of `address`
+///
+/// A header of a compressed P2PKH key also verifies for segwit addresses of the key, as
+/// wallets signing before BIP137 wrote such headers.
+pub fn verify_message(address: &str, signature: &str, message: &str) -> Result<bool> {
+    let address = address.parse::<Address>()?;
+    let bytes = base64::decode(signature)?;
+    if bytes.len() != 65 {
+        bail!(MessageError::InvalidSignatureLength(bytes.len()));
+    }
+
+    let header = bytes[0];
+    let address_type = match header {
+        27..=30 => MessageAddressType::P2pkhUncompressed,
+        31..=34 => MessageAddressType::P2pkh,
+        35..=38 => MessageAddressType::P2shP2wpkh,
+        39..=42 => MessageAddressType::P2wpkh,
+        _ => bail!(MessageError::InvalidHeader(header)),
+    };
+    let signature = Signature::new(
+        UBig::from_be_bytes(&bytes[1..33]),
+        UBig::from_be_bytes(&bytes[33..]),
+    );
+    let z = UBig::from_be_bytes(&message_hash(message));
+    let Ok(point) = Point::recover(z, &signature, header - address_type.header_base()) else {
+        return Ok(false);
+    };
+
+    let candidates = match address_type {
+        MessageAddressType::P2pkh => vec![
+            MessageAddressType::P2pkh,
+            MessageAddressType::P2shP2wpkh,
+            MessageAddressType::P2wpkh,
+        ],
+        address_type => vec![address_type],
+    };
+
+    Ok(candidates.into_iter().any(|candidate| {
+        candidate.address(&point, address.network()).payload() == address.payload()
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::base58::decode_check};
+
+    /// Private key of a WIF string
+    fn wif(wif: &str) -> Result<PrivateKey> {
+        PrivateKey::from_bytes(&decode_check(wif)?[1..33])
+    }
+
+    #[test]
+    fn signs_and_verifies_core_vector() -> Result<()> {
+        // from the signmessage functional test of Bitcoin Core
+        let key = wif("cUeKHd5orzT3mz8P9pxyREHfsWtVfgsfDjiZZBcjUBAaGk1BTj7N")?;
+        let address = "mpLQjfK79b7CCV4VMJWEWAj5Mpx8Up5zxB";
+        let message = "This is just a test message";
+        let expected = "INbVnW4e6PeRmsv2Qgu8NuopvrVjkcxob+sX8OcZG0SALhWybUjzMLPdAsXI46YZGb0KQTRii+wWIQzRpG/U+S0=";
+
+        assert_eq!(sign_message(&key, message)?, expected);
+        assert!(verify_message(address, expected, message)?);
+        assert!(!verify_message(
+            address,
+            expected,
+            "This is just a test message!"
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn verifies_every_address_type() -> Result<()> {
+        let key = PrivateKey::new("deadbeef", 16)?;
+        let message = "proof of ownership";
+
+        for address_type in [
+            MessageAddressType::P2pkhUncompressed,
+            MessageAddressType::P2pkh,
+            MessageAddressType::P2shP2wpkh,
+            MessageAddressType::P2wpkh,
+        ] {
+            let address = address_type
+                .address(key.point(), Network::Mainnet)
+                .to_string();
+            let signature = sign_message_with_type(&key, message, address_type)?;
+            assert!(verify_message(&address, &signature, message)?);
+
+            let other = PrivateKey::new("cafebabe", 16)?;
+            let other_address = address_type
+                .address(other.point(), Network::Mainnet)
+                .to_string();
+            assert!(!verify_message(&other_address, &signature, message)?);
+        }
+
+        // compressed P2PKH headers also prove segwit addresses of the key
+        let signature = sign_message(&key, message)?;
+        let segwit = Address::p2wpkh(key.point(), Network::Mainnet).to_string();
+        assert!(verify_message(&segwit, &signature, message)?);
+
+        // but the uncompressed key has no segwit address
+        let signature =
+            sign_message_with_type(&key, message, MessageAddressType::P2pkhUncompressed)?;
+        assert!(!verify_message(&segwit, &signature, message)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() -> Result<()> {
+        let key = PrivateKey::new("deadbeef", 16)?;
+        let address = Address::p2pkh(key.point(), true, Network::Mainnet).to_string();
+        let mut bytes = base64::decode(&sign_message(&key, "hi")?)?;
+
+        let error = |bytes: &[u8]| {
+            verify_message(&address, &base64::encode(bytes), "hi")
+                .err()
+                .unwrap()
+                .downcast::<MessageError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error(&bytes[..64]),
+            MessageError::InvalidSignatureLength(64)
+        );
+        bytes[0] = 43;
+        assert_eq!(error(&bytes), MessageError::InvalidHeader(43));
+        Ok(())
+    }
+}
@@ -0,0 +1,563 @@
+use {
+    crate::{
+        block::{
+            body::{Block, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT, WITNESS_COMMITMENT_HEADER},
+            header::BlockHeader,
+        },
+        mempool::pool::{compare_fee_rates, Mempool, MempoolEntry, DEFAULT_MIN_RELAY_FEE},
+        script::raw::Script,
+        transaction::tx::{OutPoint, Transaction, TxIn, TxOut, Txid},
+        utils::hash::hash256,
+        utxo::{set::BlockUndo, store::UtxoStore},
+        validation::{
+            chainstate::{block_subsidy, ChainState},
+            tx::is_final,
+        },
+    },
+    anyhow::Result,
+    std::{
+        cmp::Ordering,
+        collections::{BTreeSet, HashMap, HashSet},
+    },
+};
+
+/// Version of assembled blocks, signalling no BIP9 deployment
+pub const BLOCK_VERSION: i32 = 0x20000000;
+
+/// Weight kept free for the coinbase when selecting transactions
+const COINBASE_RESERVED_WEIGHT: usize = 4000;
+
+/// Signature operation cost kept free for the coinbase when selecting transactions
+const COINBASE_RESERVED_SIGOPS: usize = 400;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Limits of the blocks an assembler builds
+pub struct AssemblerOptions {
+    pub max_weight: usize,
+    pub max_sigop_cost: usize,
+    /// Lowest fee rate of the packages included, in satoshis per 1000 virtual bytes
+    pub min_fee_rate: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Block ready for a nonce search, with the totals of the transactions chosen for it
+pub struct BlockTemplate {
+    pub block: Block,
+    pub height: u32,
+    /// Fees of the transactions, paid to the coinbase with the subsidy
+    pub fees: u64,
+    /// Weight of the block, including the coinbase
+    pub weight: usize,
+    /// Signature operation cost of the transactions other than the coinbase
+    pub sigop_cost: usize,
+    /// Value pushed after the height in the coinbase script, to search more hashes once the
+    /// nonces run out
+    pub extra_nonce: u32,
+}
+
+impl Default for AssemblerOptions {
+    fn default() -> Self {
+        Self {
+            max_weight: MAX_BLOCK_WEIGHT,
+            max_sigop_cost: MAX_BLOCK_SIGOPS_COST,
+            min_fee_rate: DEFAULT_MIN_RELAY_FEE,
+        }
+    }
+}
+
+/// Coinbase script of a block at `height`, the height required by BIP34 then the extra nonce
+fn coinbase_script(height: u32, extra_nonce: u32) -> Script {
+    Script::new()
+        .push_int(height as i64)
+        .push_int(extra_nonce as i64)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Mempool transaction with some of its ancestors already selected, and the totals of its package
+/// without them
+struct ModifiedEntry {
+    txid: Txid,
+    ancestor_fees: u64,
+    ancestor_size: usize,
+}
+
+impl ModifiedEntry {
+    fn new(entry: &MempoolEntry) -> Self {
+        Self {
+            txid: entry.txid,
+            ancestor_fees: entry.ancestor_fees,
+            ancestor_size: entry.ancestor_size,
+        }
+    }
+}
+
+impl Ord for ModifiedEntry {
+    /// Highest ancestor fee rate first, as `Mempool::by_ancestor_fee_rate` orders entries
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_fee_rates(
+            other.ancestor_fees,
+            other.ancestor_size,
+            self.ancestor_fees,
+            self.ancestor_size,
+        )
+        .then(self.txid.cmp(&other.txid))
+    }
+}
+
+impl PartialOrd for ModifiedEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Choose the mempool transactions of a block at `height` whose lock time cutoff is
+/// `lock_time_cutoff`, picking the packages of unconfirmed ancestors with the highest fee rate
+/// that fit in the limits of `options`
+///
+/// Like `addPackageTxs` of Bitcoin Core, the mempool is walked in ancestor fee rate order while
+/// the descendants of selected transactions are kept aside with the fee rate of their remaining
+/// ancestors, so only their packages are recomputed. Returns the transactions in an order where
+/// parents come first, with their fees and signature operation cost.
+fn select_transactions<'a>(
+    mempool: &'a Mempool,
+    options: &AssemblerOptions,
+    height: u32,
+    lock_time_cutoff: u32,
+) -> (Vec<&'a MempoolEntry>, u64, usize) {
+    let mut selected: Vec<&MempoolEntry> = vec![];
+    let mut included = HashSet::new();
+    // transactions whose package did not fit or is not final
+    let mut failed = HashSet::new();
+    // transactions with selected ancestors, ordered by the fee rate of the rest of their package
+    let mut modified = BTreeSet::new();
+    let mut modified_by_txid = HashMap::new();
+    let (mut fees, mut weight, mut sigop_cost) = (0, COINBASE_RESERVED_WEIGHT, 0);
+
+    let entries = mempool.by_ancestor_fee_rate();
+    let mut entries = entries.iter().peekable();
+    loop {
+        while let Some(entry) = entries.peek() {
+            if !included.contains(&entry.txid)
+                && !failed.contains(&entry.txid)
+                && !modified_by_txid.contains_key(&entry.txid)
+            {
+                break;
+            }
+            entries.next();
+        }
+        let candidate = match (
+            entries.peek().map(|entry| ModifiedEntry::new(entry)),
+            modified.first().copied(),
+        ) {
+            (Some(entry), Some(best)) => entry.min(best),
+            (entry, best) => match entry.or(best) {
+                Some(candidate) => candidate,
+                None => break,
+            },
+        };
+        if modified.remove(&candidate) {
+            modified_by_txid.remove(&candidate.txid);
+        } else {
+            entries.next();
+        }
+
+        // every other package pays less
+        if candidate.ancestor_fees * 1000 < options.min_fee_rate * candidate.ancestor_size as u64 {
+            break;
+        }
+        let mut package = mempool
+            .ancestors(&candidate.txid)
+            .iter()
+            .filter(|txid| !included.contains(*txid))
+            .filter_map(|txid| mempool.get(txid))
+            .collect::<Vec<_>>();
+        package.extend(mempool.get(&candidate.txid));
+        let package_weight = package.iter().map(|entry| entry.tx.weight()).sum::<usize>();
+        let package_sigops = package.iter().map(|entry| entry.sigop_cost).sum::<usize>();
+        if weight + package_weight > options.max_weight
+            || COINBASE_RESERVED_SIGOPS + sigop_cost + package_sigops > options.max_sigop_cost
+            || !package
+                .iter()
+                .all(|entry| is_final(&entry.tx, height, lock_time_cutoff))
+        {
+            failed.insert(candidate.txid);
+            continue;
+        }
+
+        // an ancestor always has fewer ancestors than its descendants
+        package.sort_by_key(|entry| entry.ancestor_count);
+        for entry in &package {
+            included.insert(entry.txid);
+            selected.push(entry);
+        }
+        fees += package.iter().map(|entry| entry.fee).sum::<u64>();
+        weight += package_weight;
+        sigop_cost += package_sigops;
+
+        // the packages of the descendants no longer include what was just selected
+        for entry in package {
+            for descendant in mempool.descendants(&entry.txid) {
+                if included.contains(&descendant) || failed.contains(&descendant) {
+                    continue;
+                }
+                let mut updated = match modified_by_txid.remove(&descendant) {
+                    Some(updated) => {
+                        modified.remove(&updated);
+                        updated
+                    }
+                    None => ModifiedEntry::new(mempool.get(&descendant).unwrap()),
+                };
+                updated.ancestor_fees -= entry.fee;
+                updated.ancestor_size -= entry.vsize;
+                modified.insert(updated);
+                modified_by_txid.insert(descendant, updated);
+            }
+        }
+    }
+
+    (selected, fees, sigop_cost)
+}
+
+impl BlockTemplate {
+    /// Template of the block on the tip of `state` with the best mempool transactions, whose
+    /// coinbase pays the subsidy and fees to `payout`
+    ///
+    /// `time` is the current time in seconds since the Unix epoch, raised above the median time
+    /// past of the tip if needed.
+    pub fn new<S: UtxoStore>(
+        state: &ChainState<S>,
+        mempool: &Mempool,
+        payout: &Script,
+        time: u32,
+        options: &AssemblerOptions,
+    ) -> Self {
+        let chain = state.chain();
+        let tip = chain.tip();
+        let height = tip.height + 1;
+        let params = chain.params();
+        let median_time_past = chain.median_time_past(tip);
+        let time = time.max(median_time_past + 1);
+
+        // lock times are checked against the median time past, as specified by BIP113
+        let (entries, fees, sigop_cost) =
+            select_transactions(mempool, options, height, median_time_past);
+        let mut coinbase = Transaction::new(2, 0);
+        let mut input = TxIn::new(OutPoint::null());
+        input.script_sig = coinbase_script(height, 0);
+        coinbase.inputs.push(input);
+        coinbase.outputs.push(TxOut::new(
+            block_subsidy(height, params) + fees,
+            payout.clone(),
+        ));
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                prev_blockhash: tip.header.hash(),
+                merkle_root: [0; 32],
+                time,
+                bits: chain.next_work_required(tip, time),
+                nonce: 0,
+            },
+            transactions: [coinbase]
+                .into_iter()
+                .chain(entries.into_iter().map(|entry| entry.tx.clone()))
+                .collect(),
+        };
+
+        // the commitment is to the wtxids and a reserved value in the coinbase witness
+        if height >= params.segwit_height {
+            let reserved_value = [0; 32];
+            let commitment = hash256(&[block.compute_witness_root(), reserved_value].concat());
+            let script =
+                Script::from_bytes([WITNESS_COMMITMENT_HEADER.as_slice(), &commitment].concat());
+            let coinbase = &mut block.transactions[0];
+            coinbase.outputs.push(TxOut::new(0, script));
+            coinbase.inputs[0].witness = vec![reserved_value.to_vec()];
+        }
+        block.header.merkle_root = block.compute_merkle_root().0;
+
+        Self {
+            weight: block.weight(),
+            block,
+            height,
+            fees,
+            sigop_cost,
+            extra_nonce: 0,
+        }
+    }
+
+    /// Txids of the transactions other than the coinbase
+    pub fn txids(&self) -> Vec<Txid> {
+        self.block.transactions[1..]
+            .iter()
+            .map(Transaction::txid)
+            .collect()
+    }
+
+    /// Change the extra nonce of the coinbase, which changes the merkle root and resets the nonce
+    pub fn set_extra_nonce(&mut self, extra_nonce: u32) {
+        self.extra_nonce = extra_nonce;
+        self.block.transactions[0].inputs[0].script_sig = coinbase_script(self.height, extra_nonce);
+        self.block.header.merkle_root = self.block.compute_merkle_root().0;
+        self.block.header.nonce = 0;
+        self.weight = self.block.weight();
+    }
+}
+
+/// Nonce at which `header` meets its target, searching from its current nonce
+pub fn find_nonce(header: &BlockHeader) -> Option<u32> {
+    let mut header = *header;
+    loop {
+        if header.check_pow().is_ok() {
+            return Some(header.nonce);
+        }
+        header.nonce = header.nonce.checked_add(1)?;
+    }
+}
+
+/// Search the nonces of `template`, moving to the next extra nonce when they run out, and return
+/// the mined block
+///
+/// Only practical at low difficulties, such as regtest's.
+pub fn mine(mut template: BlockTemplate) -> Block {
+    loop {
+        if let Some(nonce) = find_nonce(&template.block.header) {
+            template.block.header.nonce = nonce;
+            return template.block;
+        }
+        template.set_extra_nonce(template.extra_nonce + 1);
+    }
+}
+
+/// Mine `count` blocks paying `payout` on top of `state` with the transactions of `mempool`,
+/// connecting them and removing their transactions from the mempool
+///
+/// Each block is timestamped ten minutes after the previous one, giving a reproducible chain for
+/// tests. Returns the blocks with their undo data.
+pub fn generate<S: UtxoStore>(
+    state: &mut ChainState<S>,
+    mempool: &mut Mempool,
+    payout: &Script,
+    count: u32,
+) -> Result<Vec<(Block, BlockUndo)>> {
+    (0..count)
+        .map(|_| {
+            let time = state.chain().tip().header.time + 600;
+            let template =
+                BlockTemplate::new(state, mempool, payout, time, &AssemblerOptions::default());
+            let block = mine(template);
+            let undo = state.connect_block(&block, time)?;
+            mempool.remove_for_block(&block);
+            Ok((block, undo))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            chain::headers::HeaderChain,
+            mempool::pool::MempoolLimits,
+            network::Network,
+            secp256k1::keys::PrivateKey,
+            transaction::sighash::SIGHASH_ALL,
+            utxo::{set::UtxoSet, store::MemoryStore},
+            validation::tx::COIN,
+        },
+    };
+
+    fn key() -> PrivateKey {
+        PrivateKey::new("b10c", 16).unwrap()
+    }
+
+    fn payout() -> Script {
+        Script::p2wpkh(&key().point().hash160(true))
+    }
+
+    fn regtest() -> ChainState<MemoryStore> {
+        let chain = HeaderChain::new(Network::Regtest.params());
+        ChainState::new(chain, UtxoSet::new(MemoryStore::new()).unwrap())
+    }
+
+    /// Transaction spending `outpoint` of `value` paid to the test key, into `outputs`
+    fn spend(outpoint: OutPoint, value: u64, outputs: &[u64]) -> Transaction {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(outpoint));
+        for output in outputs {
+            tx.outputs.push(TxOut::new(*output, payout()));
+        }
+        sign(tx, value)
+    }
+
+    /// `tx` with its only input, of `value` paid to the test key, signed
+    fn sign(mut tx: Transaction, value: u64) -> Transaction {
+        let code = Script::p2pkh(&key().point().hash160(true));
+        let sighash = tx
+            .segwit_v0_sighash(0, &code, value, SIGHASH_ALL as u32)
+            .unwrap();
+        let mut sig = key().sign(&hex::encode(sighash)).unwrap().der();
+        sig.push(SIGHASH_ALL);
+        tx.inputs[0].witness = vec![sig, key().point().sec(true)];
+        tx
+    }
+
+    fn accept(state: &ChainState<MemoryStore>, mempool: &mut Mempool, tx: Transaction) -> Txid {
+        let chain = state.chain();
+        mempool
+            .accept(
+                tx,
+                state.utxos(),
+                state.height() + 1,
+                |height| chain.median_time_past_at(height).unwrap(),
+                0,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn generates_a_regtest_chain() -> Result<()> {
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let blocks = generate(&mut state, &mut mempool, &payout(), 101)?;
+        assert_eq!(state.height(), 101);
+        let (first, _) = &blocks[0];
+        assert_eq!(first.bip34_height()?, 1);
+        assert!(first.witness_commitment().is_some());
+
+        // the matured first coinbase funds a transaction mined in the next block
+        let coinbase = OutPoint::new(first.transactions[0].txid(), 0);
+        let tx = spend(coinbase, 50 * COIN, &[50 * COIN - 10_000]);
+        let txid = accept(&state, &mut mempool, tx);
+        let (block, undo) = generate(&mut state, &mut mempool, &payout(), 1)?.remove(0);
+        assert_eq!(block.transactions[1].txid(), txid);
+        assert_eq!(
+            block.transactions[0].outputs[0].value,
+            block_subsidy(102, state.chain().params()) + 10_000
+        );
+        assert_eq!(undo.spent.len(), 1);
+        assert!(mempool.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn selects_packages_by_fee_rate() -> Result<()> {
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let blocks = generate(&mut state, &mut mempool, &payout(), 102)?;
+        let coinbase = |i: usize| OutPoint::new(blocks[i].0.transactions[0].txid(), 0);
+
+        // a cheap parent whose child pays enough for both beats a middling transaction
+        let parent = spend(coinbase(0), 50 * COIN, &[50 * COIN - 200]);
+        let parent_id = accept(&state, &mut mempool, parent);
+        let child = spend(
+            OutPoint::new(parent_id, 0),
+            50 * COIN - 200,
+            &[50 * COIN - 5_000],
+        );
+        let child_id = accept(&state, &mut mempool, child);
+        let middling = spend(coinbase(1), 50 * COIN, &[50 * COIN - 1_000]);
+        let middling_id = accept(&state, &mut mempool, middling);
+
+        let options = AssemblerOptions::default();
+        let template = BlockTemplate::new(&state, &mempool, &payout(), 0, &options);
+        assert_eq!(template.txids(), vec![parent_id, child_id, middling_id]);
+        assert_eq!(template.fees, 6_000);
+        assert_eq!(template.height, 103);
+        let tip = state.chain().tip();
+        assert_eq!(
+            template.block.header.time,
+            state.chain().median_time_past(tip) + 1
+        );
+
+        // only the middling transaction fits in a block that cannot hold the package
+        let middling_weight = mempool.get(&middling_id).unwrap().tx.weight();
+        let small = AssemblerOptions {
+            max_weight: COINBASE_RESERVED_WEIGHT + middling_weight,
+            ..Default::default()
+        };
+        let template = BlockTemplate::new(&state, &mempool, &payout(), 0, &small);
+        assert_eq!(template.txids(), vec![middling_id]);
+
+        let mut template = BlockTemplate::new(&state, &mempool, &payout(), 0, &options);
+        let root = template.block.header.merkle_root;
+        template.set_extra_nonce(7);
+        assert_ne!(template.block.header.merkle_root, root);
+        let block = mine(template);
+        let time = block.header.time;
+        state.connect_block(&block, time)?;
+        assert_eq!(state.height(), 103);
+        Ok(())
+    }
+
+    #[test]
+    fn updates_packages_of_descendants() -> Result<()> {
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let blocks = generate(&mut state, &mut mempool, &payout(), 102)?;
+        let coinbase = |i: usize| OutPoint::new(blocks[i].0.transactions[0].txid(), 0);
+
+        // the second child pays less than the other transaction with the parent, but more alone
+        let parent = spend(coinbase(0), 50 * COIN, &[25 * COIN, 25 * COIN - 200]);
+        let parent_id = accept(&state, &mut mempool, parent);
+        let first = spend(
+            OutPoint::new(parent_id, 0),
+            25 * COIN,
+            &[25 * COIN - 20_000],
+        );
+        let first_id = accept(&state, &mut mempool, first);
+        let second = spend(
+            OutPoint::new(parent_id, 1),
+            25 * COIN - 200,
+            &[25 * COIN - 3_200],
+        );
+        let second_id = accept(&state, &mut mempool, second);
+        let other = spend(coinbase(1), 50 * COIN, &[50 * COIN - 1_500]);
+        let other_id = accept(&state, &mut mempool, other);
+        let second_entry = mempool.get(&second_id).unwrap();
+        assert!(second_entry.ancestor_fee_rate() < mempool.get(&other_id).unwrap().fee_rate());
+
+        let options = AssemblerOptions::default();
+        let template = BlockTemplate::new(&state, &mempool, &payout(), 0, &options);
+        assert_eq!(
+            template.txids(),
+            vec![parent_id, first_id, second_id, other_id]
+        );
+        assert_eq!(template.fees, 24_700);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_transactions_that_are_not_final() -> Result<()> {
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let blocks = generate(&mut state, &mut mempool, &payout(), 101)?;
+        let coinbase = OutPoint::new(blocks[0].0.transactions[0].txid(), 0);
+
+        // locked until the block after the next one, as a reorg may leave in the mempool
+        let mut tx = Transaction::new(2, state.height() + 1);
+        let mut input = TxIn::new(coinbase);
+        input.sequence = 0;
+        tx.inputs.push(input);
+        tx.outputs.push(TxOut::new(50 * COIN - 10_000, payout()));
+        let tx = sign(tx, 50 * COIN);
+        let chain = state.chain();
+        let txid = mempool.accept(
+            tx,
+            state.utxos(),
+            state.height() + 2,
+            |_| chain.median_time_past(chain.tip()),
+            0,
+        )?;
+
+        let options = AssemblerOptions::default();
+        let template = BlockTemplate::new(&state, &mempool, &payout(), 0, &options);
+        assert!(template.txids().is_empty());
+        generate(&mut state, &mut mempool, &payout(), 1)?;
+        let template = BlockTemplate::new(&state, &mempool, &payout(), 0, &options);
+        assert_eq!(template.txids(), vec![txid]);
+        Ok(())
+    }
+}
@@ -0,0 +1,902 @@
+use {
+    super::{
+        errors::MiniscriptError,
+        types::{Base, Type},
+    },
+    crate::{
+        descriptor::{
+            errors::DescriptorError,
+            expression::{split_args, split_call},
+            key::DescriptorKey,
+        },
+        script::{
+            opcodes::*,
+            raw::{Instruction, Script, MAX_PUBKEYS_PER_MULTISIG},
+        },
+        secp256k1::signature::MAX_ECDSA_SIG_SIZE,
+        utils::hash::{hash160, hash256, ripemd160, sha256},
+    },
+    anyhow::{bail, Result},
+    std::fmt::{self, Display, Formatter},
+};
+
+/// Largest witness script relayed by nodes
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+
+/// Most keys allowed in `multi_a()`, bounded by the tapscript stack size
+pub const MAX_PUBKEYS_PER_MULTI_A: usize = 999;
+
+/// Size of a Schnorr signature with a non default sighash byte, at most
+pub(crate) const MAX_SCHNORR_SIG_SIZE: usize = 65;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Script version a miniscript is encoded for
+pub enum ScriptContext {
+    /// P2WSH witness scripts
+    Segwitv0,
+    /// BIP342 tapscript leaves
+    Tapscript,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Hash whose preimage must be revealed
+pub enum HashLock {
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+}
+
+impl HashLock {
+    /// If `preimage` is a 32 byte preimage of the hash, the only length miniscript accepts
+    pub fn matches(&self, preimage: &[u8]) -> bool {
+        preimage.len() == 32
+            && match self {
+                Self::Sha256(hash) => sha256(preimage) == *hash,
+                Self::Hash256(hash) => hash256(preimage) == *hash,
+                Self::Ripemd160(hash) => ripemd160(preimage) == *hash,
+                Self::Hash160(hash) => hash160(preimage) == *hash,
+            }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256(_) => "sha256",
+            Self::Hash256(_) => "hash256",
+            Self::Ripemd160(_) => "ripemd160",
+            Self::Hash160(_) => "hash160",
+        }
+    }
+
+    fn hash(&self) -> &[u8] {
+        match self {
+            Self::Sha256(hash) | Self::Hash256(hash) => hash,
+            Self::Ripemd160(hash) | Self::Hash160(hash) => hash,
+        }
+    }
+
+    /// Parse the fragment `name(hex)`, returning None for names that are not hash locks
+    pub(crate) fn parse(name: &str, hash: &str) -> Option<Result<Self>> {
+        let parsed = hex::decode(hash).ok();
+        let invalid = || MiniscriptError::InvalidFragment(format!("{name}({hash})")).into();
+
+        let lock = match name {
+            "sha256" => parsed.and_then(|h| h.try_into().ok()).map(Self::Sha256),
+            "hash256" => parsed.and_then(|h| h.try_into().ok()).map(Self::Hash256),
+            "ripemd160" => parsed.and_then(|h| h.try_into().ok()).map(Self::Ripemd160),
+            "hash160" => parsed.and_then(|h| h.try_into().ok()).map(Self::Hash160),
+            _ => return None,
+        };
+
+        Some(lock.ok_or_else(invalid))
+    }
+}
+
+impl Display for HashLock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({})", self.name(), hex::encode(self.hash()))
+    }
+}
+
+#[derive(Clone)]
+/// Miniscript expression, a structured representation of a subset of Bitcoin Script
+///
+/// The `t:`, `l:`, `u:`, `pk()`, `pkh()` and `and_n()` shorthands are stored expanded
+pub enum Miniscript {
+    /// `0`
+    False,
+    /// `1`
+    True,
+    /// `pk_k(KEY)`
+    PkK(DescriptorKey),
+    /// `pk_h(KEY)`
+    PkH(DescriptorKey),
+    /// `older(n)`, relative timelock
+    Older(u32),
+    /// `after(n)`, absolute timelock
+    After(u32),
+    /// `sha256(h)`, `hash256(h)`, `ripemd160(h)` and `hash160(h)`
+    Hash(HashLock),
+    /// `andor(X,Y,Z)`
+    AndOr(Box<Miniscript>, Box<Miniscript>, Box<Miniscript>),
+    /// `and_v(X,Y)`
+    AndV(Box<Miniscript>, Box<Miniscript>),
+    /// `and_b(X,Y)`
+    AndB(Box<Miniscript>, Box<Miniscript>),
+    /// `or_b(X,Z)`
+    OrB(Box<Miniscript>, Box<Miniscript>),
+    /// `or_c(X,Z)`
+    OrC(Box<Miniscript>, Box<Miniscript>),
+    /// `or_d(X,Z)`
+    OrD(Box<Miniscript>, Box<Miniscript>),
+    /// `or_i(X,Z)`
+    OrI(Box<Miniscript>, Box<Miniscript>),
+    /// `thresh(k,X1,...,Xn)`
+    Thresh(usize, Vec<Miniscript>),
+    /// `multi(k,KEY1,...,KEYn)`, P2WSH only
+    Multi(usize, Vec<DescriptorKey>),
+    /// `multi_a(k,KEY1,...,KEYn)`, tapscript only
+    MultiA(usize, Vec<DescriptorKey>),
+    /// `a:X`
+    Alt(Box<Miniscript>),
+    /// `s:X`
+    Swap(Box<Miniscript>),
+    /// `c:X`
+    Check(Box<Miniscript>),
+    /// `d:X`
+    DupIf(Box<Miniscript>),
+    /// `v:X`
+    Verify(Box<Miniscript>),
+    /// `j:X`
+    NonZero(Box<Miniscript>),
+    /// `n:X`
+    ZeroNotEqual(Box<Miniscript>),
+}
+
+/// Parse a key expression, checking it can be encoded in `ctx`
+pub(crate) fn parse_key(s: &str, ctx: ScriptContext) -> Result<DescriptorKey> {
+    let key: DescriptorKey = s.parse()?;
+    if key.is_uncompressed() {
+        bail!(DescriptorError::UncompressedInSegwit(s.to_string()));
+    }
+    if key.is_x_only() && ctx == ScriptContext::Segwitv0 {
+        bail!(DescriptorError::XOnlyOutsideTaproot(s.to_string()));
+    }
+
+    Ok(key)
+}
+
+/// Parse a timelock, which must fit in the 31 bits a script number can hold without the sign
+pub(crate) fn parse_timelock(s: &str) -> Result<u32> {
+    let invalid = || MiniscriptError::InvalidFragment(s.to_string());
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        bail!(invalid());
+    }
+
+    match s.parse().map_err(|_| invalid())? {
+        n @ 1..=0x7fffffff => Ok(n),
+        n => bail!(MiniscriptError::InvalidTimelock(n)),
+    }
+}
+
+/// Parse the threshold and keys of `multi()` and `multi_a()`
+fn parse_multi(
+    args: &[&str],
+    ctx: ScriptContext,
+    max_keys: usize,
+) -> Result<(usize, Vec<DescriptorKey>)> {
+    let k = args[0]
+        .parse()
+        .map_err(|_| MiniscriptError::InvalidFragment(args[0].to_string()))?;
+    let keys = args[1..]
+        .iter()
+        .map(|key| parse_key(key, ctx))
+        .collect::<Result<Vec<_>>>()?;
+
+    if keys.len() > max_keys {
+        bail!(DescriptorError::TooManyKeys(keys.len(), max_keys));
+    }
+    if k == 0 || k > keys.len() {
+        bail!(DescriptorError::InvalidThreshold(k, keys.len()));
+    }
+
+    Ok((k, keys))
+}
+
+impl Miniscript {
+    /// Parse a miniscript expression for `ctx`, checking its types
+    pub fn parse(s: &str, ctx: ScriptContext) -> Result<Self> {
+        let ms = Self::parse_fragment(s, ctx)?;
+        ms.ty(ctx)?;
+
+        Ok(ms)
+    }
+
+    /// Parse a miniscript that can be used as a whole script in `ctx`
+    pub fn parse_top_level(s: &str, ctx: ScriptContext) -> Result<Self> {
+        let ms = Self::parse(s, ctx)?;
+        let ty = ms.ty(ctx)?;
+        if ty.base != Base::B {
+            bail!(MiniscriptError::NotTopLevel(format!("{:?}", ty.base)));
+        }
+        if ctx == ScriptContext::Segwitv0 {
+            let size = ms.script_size();
+            if size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+                bail!(MiniscriptError::ScriptTooLarge(
+                    size,
+                    MAX_STANDARD_P2WSH_SCRIPT_SIZE
+                ));
+            }
+        }
+
+        Ok(ms)
+    }
+
+    fn parse_fragment(s: &str, ctx: ScriptContext) -> Result<Self> {
+        // wrappers are the letters before a colon that precedes any parenthesis
+        if let Some((wrappers, inner)) = s.split_once(':') {
+            if !wrappers.contains('(') {
+                let mut ms = Self::parse_fragment(inner, ctx)?;
+                for wrapper in wrappers.chars().rev() {
+                    let x = Box::new(ms);
+                    ms = match wrapper {
+                        'a' => Self::Alt(x),
+                        's' => Self::Swap(x),
+                        'c' => Self::Check(x),
+                        'd' => Self::DupIf(x),
+                        'v' => Self::Verify(x),
+                        'j' => Self::NonZero(x),
+                        'n' => Self::ZeroNotEqual(x),
+                        't' => Self::AndV(x, Box::new(Self::True)),
+                        'l' => Self::OrI(Box::new(Self::False), x),
+                        'u' => Self::OrI(x, Box::new(Self::False)),
+                        _ => bail!(MiniscriptError::InvalidFragment(s.to_string())),
+                    };
+                }
+                return Ok(ms);
+            }
+        }
+
+        match s {
+            "0" => return Ok(Self::False),
+            "1" => return Ok(Self::True),
+            _ => (),
+        }
+
+        let invalid = || MiniscriptError::InvalidFragment(s.to_string());
+        let (name, args) = split_call(s)?;
+        if let Some(lock) = HashLock::parse(name, args) {
+            return Ok(Self::Hash(lock?));
+        }
+        let args = split_args(args)?;
+        let sub =
+            |i: usize| -> Result<Box<Self>> { Ok(Box::new(Self::parse_fragment(args[i], ctx)?)) };
+
+        Ok(match (name, args.len()) {
+            ("pk_k", 1) => Self::PkK(parse_key(args[0], ctx)?),
+            ("pk_h", 1) => Self::PkH(parse_key(args[0], ctx)?),
+            ("pk", 1) => Self::Check(Box::new(Self::PkK(parse_key(args[0], ctx)?))),
+            ("pkh", 1) => Self::Check(Box::new(Self::PkH(parse_key(args[0], ctx)?))),
+            ("older", 1) => Self::Older(parse_timelock(args[0])?),
+            ("after", 1) => Self::After(parse_timelock(args[0])?),
+            ("andor", 3) => Self::AndOr(sub(0)?, sub(1)?, sub(2)?),
+            ("and_n", 2) => Self::AndOr(sub(0)?, sub(1)?, Box::new(Self::False)),
+            ("and_v", 2) => Self::AndV(sub(0)?, sub(1)?),
+            ("and_b", 2) => Self::AndB(sub(0)?, sub(1)?),
+            ("or_b", 2) => Self::OrB(sub(0)?, sub(1)?),
+            ("or_c", 2) => Self::OrC(sub(0)?, sub(1)?),
+            ("or_d", 2) => Self::OrD(sub(0)?, sub(1)?),
+            ("or_i", 2) => Self::OrI(sub(0)?, sub(1)?),
+            ("thresh", 2..) => Self::Thresh(
+                args[0].parse().map_err(|_| invalid())?,
+                (1..args.len())
+                    .map(|i| Self::parse_fragment(args[i], ctx))
+                    .collect::<Result<_>>()?,
+            ),
+            ("multi", 2..) => {
+                if ctx != ScriptContext::Segwitv0 {
+                    bail!(MiniscriptError::InvalidContext(s.to_string()));
+                }
+                let (k, keys) = parse_multi(&args, ctx, MAX_PUBKEYS_PER_MULTISIG)?;
+                Self::Multi(k, keys)
+            }
+            ("multi_a", 2..) => {
+                if ctx != ScriptContext::Tapscript {
+                    bail!(MiniscriptError::InvalidContext(s.to_string()));
+                }
+                let (k, keys) = parse_multi(&args, ctx, MAX_PUBKEYS_PER_MULTI_A)?;
+                Self::MultiA(k, keys)
+            }
+            _ => bail!(invalid()),
+        })
+    }
+
+    /// Type of the expression, failing if any fragment is used with arguments of the wrong type
+    pub fn ty(&self, ctx: ScriptContext) -> Result<Type> {
+        let ty = |ms: &Self| ms.ty(ctx);
+
+        match self {
+            Self::False => Ok(Type::FALSE),
+            Self::True => Ok(Type::TRUE),
+            Self::PkK(_) => Ok(Type::PK_K),
+            Self::PkH(_) => Ok(Type::PK_H),
+            Self::Older(_) | Self::After(_) => Ok(Type::TIME),
+            Self::Hash(_) => Ok(Type::HASH),
+            Self::AndOr(x, y, z) => Type::and_or(ty(x)?, ty(y)?, ty(z)?),
+            Self::AndV(x, y) => Type::and_v(ty(x)?, ty(y)?),
+            Self::AndB(x, y) => Type::and_b(ty(x)?, ty(y)?),
+            Self::OrB(x, z) => Type::or_b(ty(x)?, ty(z)?),
+            Self::OrC(x, z) => Type::or_c(ty(x)?, ty(z)?),
+            Self::OrD(x, z) => Type::or_d(ty(x)?, ty(z)?),
+            Self::OrI(x, z) => Type::or_i(ty(x)?, ty(z)?),
+            Self::Thresh(k, subs) => {
+                Type::thresh(*k, &subs.iter().map(ty).collect::<Result<Vec<_>>>()?)
+            }
+            Self::Multi(..) => Ok(Type::MULTI),
+            Self::MultiA(..) => Ok(Type::MULTI_A),
+            Self::Alt(x) => Type::alt(ty(x)?),
+            Self::Swap(x) => Type::swap(ty(x)?),
+            Self::Check(x) => Type::check(ty(x)?),
+            Self::DupIf(x) => Type::dup_if(ty(x)?, ctx == ScriptContext::Tapscript),
+            Self::Verify(x) => Type::verify(ty(x)?),
+            Self::NonZero(x) => Type::non_zero(ty(x)?),
+            Self::ZeroNotEqual(x) => Type::zero_not_equal(ty(x)?),
+        }
+    }
+
+    /// All keys of the expression, from left to right
+    pub fn keys(&self) -> Vec<&DescriptorKey> {
+        match self {
+            Self::PkK(key) | Self::PkH(key) => vec![key],
+            Self::Multi(_, keys) | Self::MultiA(_, keys) => keys.iter().collect(),
+            _ => self.children().into_iter().flat_map(Self::keys).collect(),
+        }
+    }
+
+    /// Direct sub-expressions of the expression
+    pub fn children(&self) -> Vec<&Miniscript> {
+        match self {
+            Self::AndOr(x, y, z) => vec![x, y, z],
+            Self::AndV(x, y)
+            | Self::AndB(x, y)
+            | Self::OrB(x, y)
+            | Self::OrC(x, y)
+            | Self::OrD(x, y)
+            | Self::OrI(x, y) => vec![x, y],
+            Self::Thresh(_, subs) => subs.iter().collect(),
+            Self::Alt(x)
+            | Self::Swap(x)
+            | Self::Check(x)
+            | Self::DupIf(x)
+            | Self::Verify(x)
+            | Self::NonZero(x)
+            | Self::ZeroNotEqual(x) => vec![x],
+            _ => vec![],
+        }
+    }
+
+    /// Same expression with every key replaced by `f(key)`
+    pub fn translate_keys(
+        &self,
+        f: &impl Fn(&DescriptorKey) -> Result<DescriptorKey>,
+    ) -> Result<Self> {
+        let t = |ms: &Self| -> Result<Box<Self>> { Ok(Box::new(ms.translate_keys(f)?)) };
+
+        Ok(match self {
+            Self::False => Self::False,
+            Self::True => Self::True,
+            Self::PkK(key) => Self::PkK(f(key)?),
+            Self::PkH(key) => Self::PkH(f(key)?),
+            Self::Older(n) => Self::Older(*n),
+            Self::After(n) => Self::After(*n),
+            Self::Hash(lock) => Self::Hash(lock.clone()),
+            Self::AndOr(x, y, z) => Self::AndOr(t(x)?, t(y)?, t(z)?),
+            Self::AndV(x, y) => Self::AndV(t(x)?, t(y)?),
+            Self::AndB(x, y) => Self::AndB(t(x)?, t(y)?),
+            Self::OrB(x, z) => Self::OrB(t(x)?, t(z)?),
+            Self::OrC(x, z) => Self::OrC(t(x)?, t(z)?),
+            Self::OrD(x, z) => Self::OrD(t(x)?, t(z)?),
+            Self::OrI(x, z) => Self::OrI(t(x)?, t(z)?),
+            Self::Thresh(k, subs) => Self::Thresh(
+                *k,
+                subs.iter()
+                    .map(|sub| sub.translate_keys(f))
+                    .collect::<Result<_>>()?,
+            ),
+            Self::Multi(k, keys) => Self::Multi(*k, keys.iter().map(f).collect::<Result<_>>()?),
+            Self::MultiA(k, keys) => Self::MultiA(*k, keys.iter().map(f).collect::<Result<_>>()?),
+            Self::Alt(x) => Self::Alt(t(x)?),
+            Self::Swap(x) => Self::Swap(t(x)?),
+            Self::Check(x) => Self::Check(t(x)?),
+            Self::DupIf(x) => Self::DupIf(t(x)?),
+            Self::Verify(x) => Self::Verify(t(x)?),
+            Self::NonZero(x) => Self::NonZero(t(x)?),
+            Self::ZeroNotEqual(x) => Self::ZeroNotEqual(t(x)?),
+        })
+    }
+
+    /// Script of the expression with the keys at `index`
+    pub fn to_script(&self, ctx: ScriptContext, index: u32) -> Result<Script> {
+        let enc = |ms: &Self| ms.to_script(ctx, index);
+        let key_bytes = |key: &DescriptorKey| -> Result<Vec<u8>> {
+            let point = key.derive(index)?;
+            Ok(match ctx {
+                ScriptContext::Segwitv0 => point.sec(true),
+                ScriptContext::Tapscript => point.x_only().to_vec(),
+            })
+        };
+
+        Ok(match self {
+            Self::False => Script::new().push_opcode(OP_0),
+            Self::True => Script::new().push_opcode(OP_1),
+            Self::PkK(key) => Script::new().push_slice(&key_bytes(key)?),
+            Self::PkH(key) => Script::new()
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(&hash160(&key_bytes(key)?))
+                .push_opcode(OP_EQUALVERIFY),
+            Self::Older(n) => Script::new()
+                .push_int(*n as i64)
+                .push_opcode(OP_CHECKSEQUENCEVERIFY),
+            Self::After(n) => Script::new()
+                .push_int(*n as i64)
+                .push_opcode(OP_CHECKLOCKTIMEVERIFY),
+            Self::Hash(lock) => Script::new()
+                .push_opcode(OP_SIZE)
+                .push_int(32)
+                .push_opcode(OP_EQUALVERIFY)
+                .push_opcode(match lock {
+                    HashLock::Sha256(_) => OP_SHA256,
+                    HashLock::Hash256(_) => OP_HASH256,
+                    HashLock::Ripemd160(_) => OP_RIPEMD160,
+                    HashLock::Hash160(_) => OP_HASH160,
+                })
+                .push_slice(lock.hash())
+                .push_opcode(OP_EQUAL),
+            Self::AndOr(x, y, z) => enc(x)?
+                .push_opcode(OP_NOTIF)
+                .push_script(&enc(z)?)
+                .push_opcode(OP_ELSE)
+                .push_script(&enc(y)?)
+                .push_opcode(OP_ENDIF),
+            Self::AndV(x, y) => enc(x)?.push_script(&enc(y)?),
+            Self::AndB(x, y) => enc(x)?.push_script(&enc(y)?).push_opcode(OP_BOOLAND),
+            Self::OrB(x, z) => enc(x)?.push_script(&enc(z)?).push_opcode(OP_BOOLOR),
+            Self::OrC(x, z) => enc(x)?
+                .push_opcode(OP_NOTIF)
+                .push_script(&enc(z)?)
+                .push_opcode(OP_ENDIF),
+            Self::OrD(x, z) => enc(x)?
+                .push_opcode(OP_IFDUP)
+                .push_opcode(OP_NOTIF)
+                .push_script(&enc(z)?)
+                .push_opcode(OP_ENDIF),
+            Self::OrI(x, z) => Script::new()
+                .push_opcode(OP_IF)
+                .push_script(&enc(x)?)
+                .push_opcode(OP_ELSE)
+                .push_script(&enc(z)?)
+                .push_opcode(OP_ENDIF),
+            Self::Thresh(k, subs) => {
+                let mut script = enc(&subs[0])?;
+                for sub in &subs[1..] {
+                    script = script.push_script(&enc(sub)?).push_opcode(OP_ADD);
+                }
+                script.push_int(*k as i64).push_opcode(OP_EQUAL)
+            }
+            Self::Multi(k, keys) => {
+                let mut script = Script::new().push_int(*k as i64);
+                for key in keys {
+                    script = script.push_slice(&key_bytes(key)?);
+                }
+                script
+                    .push_int(keys.len() as i64)
+                    .push_opcode(OP_CHECKMULTISIG)
+            }
+            Self::MultiA(k, keys) => {
+                let mut script = Script::new();
+                for (i, key) in keys.iter().enumerate() {
+                    script = script.push_slice(&key_bytes(key)?).push_opcode(if i == 0 {
+                        OP_CHECKSIG
+                    } else {
+                        OP_CHECKSIGADD
+                    });
+                }
+                script.push_int(*k as i64).push_opcode(OP_NUMEQUAL)
+            }
+            Self::Alt(x) => Script::new()
+                .push_opcode(OP_TOALTSTACK)
+                .push_script(&enc(x)?)
+                .push_opcode(OP_FROMALTSTACK),
+            Self::Swap(x) => Script::new().push_opcode(OP_SWAP).push_script(&enc(x)?),
+            Self::Check(x) => enc(x)?.push_opcode(OP_CHECKSIG),
+            Self::DupIf(x) => Script::new()
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_IF)
+                .push_script(&enc(x)?)
+                .push_opcode(OP_ENDIF),
+            Self::Verify(x) => verify(enc(x)?),
+            Self::NonZero(x) => Script::new()
+                .push_opcode(OP_SIZE)
+                .push_opcode(OP_0NOTEQUAL)
+                .push_opcode(OP_IF)
+                .push_script(&enc(x)?)
+                .push_opcode(OP_ENDIF),
+            Self::ZeroNotEqual(x) => enc(x)?.push_opcode(OP_0NOTEQUAL),
+        })
+    }
+
+    /// Size of the script in bytes, which does not depend on the derivation index
+    pub fn script_size(&self) -> usize {
+        let num = |n: usize| Script::new().push_int(n as i64).len();
+        let key = 34;
+        let children = self
+            .children()
+            .iter()
+            .map(|ms| ms.script_size())
+            .sum::<usize>();
+
+        children
+            + match self {
+                Self::False | Self::True => 1,
+                Self::PkK(_) => key,
+                Self::PkH(_) => 24,
+                Self::Older(n) | Self::After(n) => num(*n as usize) + 1,
+                Self::Hash(lock) => 6 + lock.hash().len() + 1,
+                Self::AndOr(..) => 3,
+                Self::AndV(..) => 0,
+                Self::AndB(..) | Self::OrB(..) => 1,
+                Self::OrC(..) => 2,
+                Self::OrD(..) => 3,
+                Self::OrI(..) => 3,
+                Self::Thresh(k, subs) => subs.len() - 1 + num(*k) + 1,
+                Self::Multi(k, keys) => num(*k) + keys.len() * key + num(keys.len()) + 1,
+                Self::MultiA(k, keys) => keys.len() * (key + 1) + num(*k) + 1,
+                Self::Alt(_) => 2,
+                Self::Swap(_) | Self::Check(_) => 1,
+                Self::DupIf(_) => 3,
+                Self::Verify(x) => usize::from(!x.ends_with_verifiable()),
+                Self::NonZero(_) => 4,
+                Self::ZeroNotEqual(_) => 1,
+            }
+    }
+
+    /// If the script ends with an opcode that has a `VERIFY` form
+    fn ends_with_verifiable(&self) -> bool {
+        match self {
+            Self::Hash(_) | Self::Thresh(..) | Self::Multi(..) | Self::MultiA(..) => true,
+            Self::Check(_) => true,
+            Self::AndV(_, y) => y.ends_with_verifiable(),
+            _ => false,
+        }
+    }
+
+    /// Size in bytes of the largest satisfaction witness, counting a length prefix per element
+    ///
+    /// Returns None if the expression cannot be satisfied
+    pub fn max_satisfaction_size(&self, ctx: ScriptContext) -> Option<usize> {
+        self.max_sizes(ctx).0
+    }
+
+    /// Largest satisfaction and dissatisfaction sizes
+    fn max_sizes(&self, ctx: ScriptContext) -> (Option<usize>, Option<usize>) {
+        let sizes = |ms: &Self| ms.max_sizes(ctx);
+        let add = |a: Option<usize>, b: Option<usize>| Some(a? + b?);
+        let max = |a: Option<usize>, b: Option<usize>| a.max(b);
+        let (sig, key) = match ctx {
+            ScriptContext::Segwitv0 => (MAX_ECDSA_SIG_SIZE + 1, 34),
+            ScriptContext::Tapscript => (MAX_SCHNORR_SIG_SIZE + 1, 33),
+        };
+
+        match self {
+            Self::False => (None, Some(0)),
+            Self::True => (Some(0), None),
+            Self::PkK(_) => (Some(sig), Some(1)),
+            Self::PkH(_) => (Some(sig + key), Some(1 + key)),
+            Self::Older(_) | Self::After(_) => (Some(0), None),
+            Self::Hash(_) => (Some(33), Some(33)),
+            Self::AndOr(x, y, z) => {
+                let ((sx, dx), (sy, _), (sz, dz)) = (sizes(x), sizes(y), sizes(z));
+                (max(add(sy, sx), add(sz, dx)), add(dz, dx))
+            }
+            Self::AndV(x, y) => (add(sizes(y).0, sizes(x).0), None),
+            Self::AndB(x, y) => {
+                let ((sx, dx), (sy, dy)) = (sizes(x), sizes(y));
+                (add(sy, sx), add(dy, dx))
+            }
+            Self::OrB(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sizes(x), sizes(z));
+                (max(add(dz, sx), add(sz, dx)), add(dz, dx))
+            }
+            Self::OrC(x, z) => {
+                let ((sx, dx), (sz, _)) = (sizes(x), sizes(z));
+                (max(sx, add(sz, dx)), None)
+            }
+            Self::OrD(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sizes(x), sizes(z));
+                (max(sx, add(sz, dx)), add(dz, dx))
+            }
+            Self::OrI(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sizes(x), sizes(z));
+                // the branch is selected by pushing 1 (two bytes) or an empty element
+                (
+                    max(add(sx, Some(2)), add(sz, Some(1))),
+                    max(add(dx, Some(2)), add(dz, Some(1))),
+                )
+            }
+            Self::Thresh(k, subs) => {
+                // best[j] is the largest witness with exactly j satisfied sub-expressions
+                let mut best = vec![None; subs.len() + 1];
+                best[0] = Some(0);
+                for sub in subs {
+                    let (sat, dissat) = sizes(sub);
+                    for j in (0..best.len()).rev() {
+                        let skip = add(best[j], dissat);
+                        let take = if j > 0 { add(best[j - 1], sat) } else { None };
+                        best[j] = max(skip, take);
+                    }
+                }
+                let dissat = subs
+                    .iter()
+                    .map(|sub| sizes(sub).1)
+                    .try_fold(0, |a, d| Some(a + d?));
+                (best[*k], dissat)
+            }
+            Self::Multi(k, _) => (Some(1 + k * sig), Some(1 + k)),
+            Self::MultiA(k, keys) => (Some(k * sig + keys.len() - k), Some(keys.len())),
+            Self::Alt(x) | Self::Swap(x) | Self::Check(x) | Self::ZeroNotEqual(x) => sizes(x),
+            Self::DupIf(x) => (add(sizes(x).0, Some(2)), Some(1)),
+            Self::Verify(x) => (sizes(x).0, None),
+            Self::NonZero(x) => (sizes(x).0, Some(1)),
+        }
+    }
+}
+
+/// Replace the final opcode of `script` by its `VERIFY` form, or append `OP_VERIFY`
+fn verify(script: Script) -> Script {
+    let last = script.instructions().last().and_then(|i| i.ok());
+    let verify_form = match last {
+        Some(Instruction::Op(OP_EQUAL)) => Some(OP_EQUALVERIFY),
+        Some(Instruction::Op(OP_NUMEQUAL)) => Some(OP_NUMEQUALVERIFY),
+        Some(Instruction::Op(OP_CHECKSIG)) => Some(OP_CHECKSIGVERIFY),
+        Some(Instruction::Op(OP_CHECKMULTISIG)) => Some(OP_CHECKMULTISIGVERIFY),
+        _ => None,
+    };
+
+    match verify_form {
+        Some(op) => {
+            let mut bytes = script.as_bytes().to_vec();
+            *bytes.last_mut().expect("script ends with an opcode") = op;
+            Script::from_bytes(bytes)
+        }
+        None => script.push_opcode(OP_VERIFY),
+    }
+}
+
+/// Write a wrapper, merging consecutive wrappers into a single prefix such as `sv:`
+fn write_wrapped(f: &mut Formatter<'_>, wrapper: char, inner: &Miniscript) -> fmt::Result {
+    let inner = inner.to_string();
+    let wrapped = match inner.split_once(':') {
+        Some((prefix, _)) => !prefix.contains('('),
+        None => false,
+    };
+
+    if wrapped {
+        write!(f, "{wrapper}{inner}")
+    } else {
+        write!(f, "{wrapper}:{inner}")
+    }
+}
+
+fn write_keys(f: &mut Formatter<'_>, name: &str, k: usize, keys: &[DescriptorKey]) -> fmt::Result {
+    write!(f, "{name}({k}")?;
+    for key in keys {
+        write!(f, ",{key}")?;
+    }
+    write!(f, ")")
+}
+
+impl Display for Miniscript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::False => write!(f, "0"),
+            Self::True => write!(f, "1"),
+            Self::PkK(key) => write!(f, "pk_k({key})"),
+            Self::PkH(key) => write!(f, "pk_h({key})"),
+            Self::Older(n) => write!(f, "older({n})"),
+            Self::After(n) => write!(f, "after({n})"),
+            Self::Hash(lock) => write!(f, "{lock}"),
+            Self::AndOr(x, y, z) => match **z {
+                Self::False => write!(f, "and_n({x},{y})"),
+                _ => write!(f, "andor({x},{y},{z})"),
+            },
+            Self::AndV(x, y) => match **y {
+                Self::True => write_wrapped(f, 't', x),
+                _ => write!(f, "and_v({x},{y})"),
+            },
+            Self::AndB(x, y) => write!(f, "and_b({x},{y})"),
+            Self::OrB(x, z) => write!(f, "or_b({x},{z})"),
+            Self::OrC(x, z) => write!(f, "or_c({x},{z})"),
+            Self::OrD(x, z) => write!(f, "or_d({x},{z})"),
+            Self::OrI(x, z) => match (&**x, &**z) {
+                (Self::False, _) => write_wrapped(f, 'l', z),
+                (_, Self::False) => write_wrapped(f, 'u', x),
+                _ => write!(f, "or_i({x},{z})"),
+            },
+            Self::Thresh(k, subs) => {
+                write!(f, "thresh({k}")?;
+                for sub in subs {
+                    write!(f, ",{sub}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Multi(k, keys) => write_keys(f, "multi", *k, keys),
+            Self::MultiA(k, keys) => write_keys(f, "multi_a", *k, keys),
+            Self::Alt(x) => write_wrapped(f, 'a', x),
+            Self::Swap(x) => write_wrapped(f, 's', x),
+            Self::Check(x) => match &**x {
+                Self::PkK(key) => write!(f, "pk({key})"),
+                Self::PkH(key) => write!(f, "pkh({key})"),
+                _ => write_wrapped(f, 'c', x),
+            },
+            Self::DupIf(x) => write_wrapped(f, 'd', x),
+            Self::Verify(x) => write_wrapped(f, 'v', x),
+            Self::NonZero(x) => write_wrapped(f, 'j', x),
+            Self::ZeroNotEqual(x) => write_wrapped(f, 'n', x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Expression, script, non-malleable and requiring a signature, from rust-miniscript
+    const VECTORS: [(&str, &str, bool, bool); 8] = [
+        (
+            "lltvln:after(1231488000)",
+            "6300676300676300670400046749b1926869516868",
+            true,
+            false,
+        ),
+        (
+            "uuj:and_v(v:multi(2,03d01115d548e7561b15c38f004d734633687cf4419620095bc5b0f47070afe85a,025601570cb47f238d2b0286db4a990fa0f3ba28d1a319f5e7cf55c2a2444da7cc),after(1231488000))",
+            "6363829263522103d01115d548e7561b15c38f004d734633687cf4419620095bc5b0f47070afe85a21025601570cb47f238d2b0286db4a990fa0f3ba28d1a319f5e7cf55c2a2444da7cc52af0400046749b168670068670068",
+            true,
+            true,
+        ),
+        (
+            "or_b(un:multi(2,03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729,024ce119c96e2fa357200b559b2f7dd5a5f02d5290aff74b03f3e471b273211c97),al:older(16))",
+            "63522103daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee872921024ce119c96e2fa357200b559b2f7dd5a5f02d5290aff74b03f3e471b273211c9752ae926700686b63006760b2686c9b",
+            false,
+            false,
+        ),
+        (
+            "j:and_v(vdv:after(1567547623),older(2016))",
+            "829263766304e7e06e5db169686902e007b268",
+            true,
+            false,
+        ),
+        (
+            "thresh(2,c:pk_h(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),s:sha256(e38990d0c7fc009880a9c07c23842e886c6bbdc964ce6bdd5817ad357335ee6f),a:hash160(dd69735817e0e3f6f826a9238dc2e291184f0131))",
+            "76a91420d637c1a6404d2227f3561fdbaff5a680dba64888ac7c82012088a820e38990d0c7fc009880a9c07c23842e886c6bbdc964ce6bdd5817ad357335ee6f87936b82012088a914dd69735817e0e3f6f826a9238dc2e291184f0131876c935287",
+            false,
+            false,
+        ),
+        (
+            "and_n(sha256(d1ec675902ef1633427ca360b290b0b3045a0d9058ddb5e648b4c3c3224c5c68),t:or_i(v:older(4252898),v:older(144)))",
+            "82012088a820d1ec675902ef1633427ca360b290b0b3045a0d9058ddb5e648b4c3c3224c5c68876400676303e2e440b26967029000b269685168",
+            false,
+            false,
+        ),
+        (
+            "c:and_v(or_c(multi(2,036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a00,02352bbf4a4cdd12564f93fa332ce333301d9ad40271f8107181340aef25be59d5),v:ripemd160(1b0f3c404d12075c68c938f9f60ebea4f74941a0)),pk_k(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))",
+            "5221036d2b085e9e382ed10b69fc311a03f8641ccfff21574de0927513a49d9a688a002102352bbf4a4cdd12564f93fa332ce333301d9ad40271f8107181340aef25be59d552ae6482012088a6141b0f3c404d12075c68c938f9f60ebea4f74941a088682103fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556ac",
+            true,
+            true,
+        ),
+        (
+            "c:andor(u:ripemd160(6ad07d21fd5dfc646f0b30577045ce201616b9ba),pk_h(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),or_i(pk_h(024ce119c96e2fa357200b559b2f7dd5a5f02d5290aff74b03f3e471b273211c97),pk_h(02352bbf4a4cdd12564f93fa332ce333301d9ad40271f8107181340aef25be59d5)))",
+            "6382012088a6146ad07d21fd5dfc646f0b30577045ce201616b9ba87670068646376a914385defb0ed10fe95817943ed37b4984f8f4255d6886776a9148f9dff39a81ee4abcbad2ad8bafff090415a2be888686776a91420d637c1a6404d2227f3561fdbaff5a680dba6488868ac",
+            false,
+            true,
+        ),
+    ];
+
+    #[test]
+    fn encode_and_type_vectors() -> Result<()> {
+        for (s, script, non_malleable, safe) in VECTORS {
+            let ms = Miniscript::parse(s, ScriptContext::Segwitv0)?;
+            let ty = ms.ty(ScriptContext::Segwitv0)?;
+            let encoded = ms.to_script(ScriptContext::Segwitv0, 0)?;
+
+            assert_eq!(hex::encode(encoded.as_bytes()), script, "{s}");
+            assert_eq!(ms.script_size(), encoded.len(), "{s}");
+            assert_eq!(ty.non_malleable, non_malleable, "{s}");
+            assert_eq!(ty.safe, safe, "{s}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn display_round_trip() -> Result<()> {
+        for s in [
+            "lltvln:after(1231488000)",
+            "and_v(v:pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5),or_d(pkh(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),older(144)))",
+            "thresh(2,pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5),s:pk(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),sln:after(500000))",
+        ] {
+            assert_eq!(Miniscript::parse(s, ScriptContext::Segwitv0)?.to_string(), s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tapscript_encoding() -> Result<()> {
+        let ms = Miniscript::parse(
+            "multi_a(1,79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)",
+            ScriptContext::Tapscript,
+        )?;
+        let script = ms.to_script(ScriptContext::Tapscript, 0)?;
+
+        assert_eq!(script.len(), 2 * 34 + 2);
+        assert_eq!(script.as_bytes()[33], OP_CHECKSIG);
+        assert_eq!(script.as_bytes()[67], OP_CHECKSIGADD);
+        assert_eq!(ms.max_satisfaction_size(ScriptContext::Tapscript), Some(67));
+        Ok(())
+    }
+
+    #[test]
+    fn witness_size_estimates() -> Result<()> {
+        let ms = Miniscript::parse(
+            "or_d(pk(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5),and_v(v:pkh(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),older(144)))",
+            ScriptContext::Segwitv0,
+        )?;
+
+        // the second branch reveals a signature and key after dissatisfying the first key
+        assert_eq!(
+            ms.max_satisfaction_size(ScriptContext::Segwitv0),
+            Some(73 + 34 + 1)
+        );
+        assert_eq!(
+            Miniscript::False.max_satisfaction_size(ScriptContext::Segwitv0),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_expressions() -> Result<()> {
+        let key = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        for (s, ctx) in [
+            (
+                format!("and_v(pk({key}),pk({key}))"),
+                ScriptContext::Segwitv0,
+            ),
+            (
+                format!("or_b(pk({key}),pk({key}))"),
+                ScriptContext::Segwitv0,
+            ),
+            ("s:older(1)".to_string(), ScriptContext::Segwitv0),
+            (format!("multi_a(1,{key})"), ScriptContext::Segwitv0),
+            (format!("multi(1,{key})"), ScriptContext::Tapscript),
+            (
+                format!("thresh(2,pk({key}),pk({key}))"),
+                ScriptContext::Segwitv0,
+            ),
+            ("older(0)".to_string(), ScriptContext::Segwitv0),
+            ("after(2147483648)".to_string(), ScriptContext::Segwitv0),
+            ("sha256(00)".to_string(), ScriptContext::Segwitv0),
+            (format!("x:pk({key})"), ScriptContext::Segwitv0),
+            (
+                "pk(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)".to_string(),
+                ScriptContext::Segwitv0,
+            ),
+        ] {
+            assert!(Miniscript::parse(&s, ctx).is_err(), "{s}");
+        }
+        assert!(
+            Miniscript::parse_top_level(&format!("v:pk({key})"), ScriptContext::Segwitv0).is_err()
+        );
+        Ok(())
+    }
+}
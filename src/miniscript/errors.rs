@@ -0,0 +1,22 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Miniscript and policies
+pub enum MiniscriptError {
+    #[error("invalid miniscript fragment `{0}`")]
+    InvalidFragment(String),
+    #[error("type check failed for `{0}`: {1}")]
+    TypeCheck(String, String),
+    #[error("miniscript must be of type B at the top level, found {0}")]
+    NotTopLevel(String),
+    #[error("fragment `{0}` is not valid in this script context")]
+    InvalidContext(String),
+    #[error("invalid timelock {0}")]
+    InvalidTimelock(u32),
+    #[error("script of {0} bytes exceeds the limit of {1}")]
+    ScriptTooLarge(usize, usize),
+    #[error("invalid policy `{0}`")]
+    InvalidPolicy(String),
+    #[error("could not satisfy the script with the available signatures, preimages and timelocks")]
+    CouldNotSatisfy,
+}
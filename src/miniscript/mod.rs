@@ -0,0 +1,5 @@
+pub mod ast;
+pub mod errors;
+pub mod policy;
+pub mod satisfy;
+pub mod types;
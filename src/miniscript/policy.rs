@@ -0,0 +1,272 @@
+use {
+    super::{
+        ast::{parse_timelock, HashLock, Miniscript, ScriptContext, MAX_PUBKEYS_PER_MULTI_A},
+        errors::MiniscriptError,
+        types::Input,
+    },
+    crate::{
+        descriptor::{
+            errors::DescriptorError,
+            expression::{split_args, split_call},
+            key::DescriptorKey,
+        },
+        script::raw::MAX_PUBKEYS_PER_MULTISIG,
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+#[derive(Clone)]
+/// Spending policy, the conditions a script should enforce without saying how
+pub enum Policy {
+    /// `pk(KEY)`
+    Key(DescriptorKey),
+    /// `after(n)`
+    After(u32),
+    /// `older(n)`
+    Older(u32),
+    /// `sha256(h)`, `hash256(h)`, `ripemd160(h)` and `hash160(h)`
+    Hash(HashLock),
+    /// `and(X,Y)`
+    And(Vec<Policy>),
+    /// `or([N@]X,[N@]Y)`, each branch weighted by how likely it is to be used
+    Or(Vec<(usize, Policy)>),
+    /// `thresh(k,X1,...,Xn)`
+    Thresh(usize, Vec<Policy>),
+}
+
+impl Policy {
+    /// Compile the policy to a miniscript for `ctx`
+    ///
+    /// The compiler is straightforward rather than size optimal: `and` becomes `and_v`, `or`
+    /// becomes `or_d` or `or_i` with the likelier branch first, and thresholds become `multi()`,
+    /// `multi_a()` or `thresh()`
+    pub fn compile(&self, ctx: ScriptContext) -> Result<Miniscript> {
+        let ms = self.compile_fragment(ctx)?;
+        // round trip through the parser so the result passes the same checks as written miniscript
+        Miniscript::parse_top_level(&ms.to_string(), ctx)
+    }
+
+    fn compile_fragment(&self, ctx: ScriptContext) -> Result<Miniscript> {
+        Ok(match self {
+            Self::Key(key) => Miniscript::Check(Box::new(Miniscript::PkK(key.clone()))),
+            Self::After(n) => Miniscript::After(*n),
+            Self::Older(n) => Miniscript::Older(*n),
+            Self::Hash(lock) => Miniscript::Hash(lock.clone()),
+            Self::And(subs) => {
+                let x = subs[0].compile_fragment(ctx)?;
+                let y = subs[1].compile_fragment(ctx)?;
+                Miniscript::AndV(Box::new(Miniscript::Verify(Box::new(x))), Box::new(y))
+            }
+            Self::Or(subs) => {
+                let mut subs = subs.iter().collect::<Vec<_>>();
+                // the left branch is the cheaper one to satisfy
+                subs.sort_by_key(|(weight, _)| std::cmp::Reverse(*weight));
+                let x = subs[0].1.compile_fragment(ctx)?;
+                let z = subs[1].1.compile_fragment(ctx)?;
+                let ty = x.ty(ctx)?;
+                if ty.dissatisfiable && ty.unit {
+                    Miniscript::OrD(Box::new(x), Box::new(z))
+                } else {
+                    Miniscript::OrI(Box::new(x), Box::new(z))
+                }
+            }
+            Self::Thresh(k, subs) => {
+                let keys = subs
+                    .iter()
+                    .map(|sub| match sub {
+                        Self::Key(key) => Some(key.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+
+                match (keys, ctx) {
+                    (Some(keys), ScriptContext::Segwitv0)
+                        if keys.len() <= MAX_PUBKEYS_PER_MULTISIG =>
+                    {
+                        Miniscript::Multi(*k, keys)
+                    }
+                    (Some(keys), ScriptContext::Tapscript)
+                        if keys.len() <= MAX_PUBKEYS_PER_MULTI_A =>
+                    {
+                        Miniscript::MultiA(*k, keys)
+                    }
+                    _ => {
+                        let mut compiled = vec![make_du(subs[0].compile_fragment(ctx)?, ctx)?];
+                        for sub in &subs[1..] {
+                            let ms = make_du(sub.compile_fragment(ctx)?, ctx)?;
+                            // s: is a byte shorter but needs an expression consuming one input
+                            compiled.push(match ms.ty(ctx)?.input {
+                                Input::One | Input::OneNonZero => Miniscript::Swap(Box::new(ms)),
+                                _ => Miniscript::Alt(Box::new(ms)),
+                            });
+                        }
+                        Miniscript::Thresh(*k, compiled)
+                    }
+                }
+            }
+        })
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let invalid = || MiniscriptError::InvalidPolicy(s.to_string());
+        let (name, args) = split_call(s)?;
+        if let Some(lock) = HashLock::parse(name, args) {
+            return Ok(Self::Hash(lock?));
+        }
+        let args = split_args(args)?;
+
+        Ok(match (name, args.len()) {
+            ("pk", 1) => Self::Key(args[0].parse()?),
+            ("after", 1) => Self::After(parse_timelock(args[0])?),
+            ("older", 1) => Self::Older(parse_timelock(args[0])?),
+            ("and", 2) => Self::And(args.iter().map(|a| Self::parse(a)).collect::<Result<_>>()?),
+            ("or", 2) => Self::Or(
+                args.iter()
+                    .map(|arg| match arg.split_once('@') {
+                        Some((weight, sub)) if !weight.contains('(') => Ok((
+                            weight.parse().ok().filter(|w| *w > 0).ok_or_else(invalid)?,
+                            Self::parse(sub)?,
+                        )),
+                        _ => Ok((1, Self::parse(arg)?)),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            ("thresh", 2..) => {
+                let k = args[0].parse().map_err(|_| invalid())?;
+                let subs = args[1..]
+                    .iter()
+                    .map(|a| Self::parse(a))
+                    .collect::<Result<Vec<_>>>()?;
+                if k == 0 || k > subs.len() {
+                    bail!(DescriptorError::InvalidThreshold(k, subs.len()));
+                }
+                Self::Thresh(k, subs)
+            }
+            _ => bail!(invalid()),
+        })
+    }
+}
+
+/// Wrap `ms` so that it can be dissatisfied and leaves exactly 1 on the stack when satisfied
+fn make_du(ms: Miniscript, ctx: ScriptContext) -> Result<Miniscript> {
+    let mut ms = ms;
+    if !ms.ty(ctx)?.unit {
+        ms = Miniscript::ZeroNotEqual(Box::new(ms));
+    }
+    if !ms.ty(ctx)?.dissatisfiable {
+        ms = Miniscript::OrI(Box::new(Miniscript::False), Box::new(ms));
+    }
+
+    Ok(ms)
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "pk({key})"),
+            Self::After(n) => write!(f, "after({n})"),
+            Self::Older(n) => write!(f, "older({n})"),
+            Self::Hash(lock) => write!(f, "{lock}"),
+            Self::And(subs) => write!(f, "and({},{})", subs[0], subs[1]),
+            Self::Or(subs) => {
+                write!(f, "or(")?;
+                for (i, (weight, sub)) in subs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    if *weight != 1 {
+                        write!(f, "{weight}@")?;
+                    }
+                    write!(f, "{sub}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Thresh(k, subs) => {
+                write!(f, "thresh({k}")?;
+                for sub in subs {
+                    write!(f, ",{sub}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{miniscript::satisfy::KeySigner, secp256k1::keys::PrivateKey, utils::hash::sha256},
+    };
+
+    const A: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const B: &str = "03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729";
+
+    #[test]
+    fn compile_policies() -> Result<()> {
+        for (policy, ms) in [
+            (format!("pk({A})"), format!("pk({A})")),
+            (
+                format!("or(9@pk({A}),older(144))"),
+                format!("or_d(pk({A}),older(144))"),
+            ),
+            (
+                format!("or(pk({A}),9@and(pk({B}),older(144)))"),
+                format!("or_i(and_v(v:pk({B}),older(144)),pk({A}))"),
+            ),
+            (
+                format!("thresh(2,pk({A}),pk({B}))"),
+                format!("multi(2,{A},{B})"),
+            ),
+            (
+                format!("thresh(2,pk({A}),pk({B}),after(500000))"),
+                format!("thresh(2,pk({A}),s:pk({B}),sln:after(500000))"),
+            ),
+        ] {
+            let parsed: Policy = policy.parse()?;
+            assert_eq!(parsed.to_string(), policy);
+            assert_eq!(parsed.compile(ScriptContext::Segwitv0)?.to_string(), ms);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compile_and_satisfy() -> Result<()> {
+        let key = PrivateKey::from_bytes(&sha256(b"policy"))?;
+        let x_only = hex::encode(key.point().x_only());
+        let policy: Policy = format!("thresh(1,pk({x_only}),pk({x_only}))").parse()?;
+        let ms = policy.compile(ScriptContext::Tapscript)?;
+        assert_eq!(ms.to_string(), format!("multi_a(1,{x_only},{x_only})"));
+
+        let signer = KeySigner::new(vec![key], sha256(b"spend"), 0);
+        let witness = ms.satisfy(ScriptContext::Tapscript, 0, &signer)?;
+        assert_eq!(witness.iter().filter(|w| !w.is_empty()).count(), 1);
+        assert!(policy.compile(ScriptContext::Segwitv0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_policies() {
+        for s in [
+            format!("and(pk({A}))"),
+            format!("or(0@pk({A}),pk({B}))"),
+            format!("thresh(3,pk({A}),pk({B}))"),
+            format!("pk_k({A})"),
+            "older(0)".to_string(),
+        ] {
+            assert!(s.parse::<Policy>().is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use {
+    super::{
+        ast::{HashLock, Miniscript, ScriptContext},
+        errors::MiniscriptError,
+    },
+    crate::{
+        secp256k1::{keys::PrivateKey, point::Point},
+        transaction::tx::LOCKTIME_THRESHOLD,
+    },
+    anyhow::{bail, Result},
+};
+
+/// Sequence bit disabling the relative timelock of an input
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+
+/// Sequence bit selecting a time based relative timelock
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+/// Sequence bits holding the relative timelock value
+const SEQUENCE_MASK: u32 = SEQUENCE_TYPE_FLAG | 0xffff;
+
+/// Source of the signatures, preimages and timelocks needed to satisfy a miniscript
+pub trait Satisfier {
+    /// DER signature with its sighash byte for the key
+    fn ecdsa_signature(&self, _key: &Point) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// BIP340 signature, with a sighash byte unless it is the default, for the key
+    fn schnorr_signature(&self, _key: &Point) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// 32 byte preimage of the hash
+    fn preimage(&self, _lock: &HashLock) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// If the spending input's sequence satisfies `older(n)`
+    fn check_older(&self, _n: u32) -> bool {
+        false
+    }
+
+    /// If the spending transaction's lock time satisfies `after(n)`
+    fn check_after(&self, _n: u32) -> bool {
+        false
+    }
+}
+
+/// Satisfier signing a sighash with private keys
+pub struct KeySigner {
+    keys: Vec<PrivateKey>,
+    sighash: [u8; 32],
+    sighash_type: u8,
+    preimages: Vec<Vec<u8>>,
+    sequence: u32,
+    lock_time: u32,
+}
+
+impl KeySigner {
+    /// Create a signer for `sighash`, committing to `sighash_type` in every signature
+    ///
+    /// A Schnorr signature omits the sighash byte when `sighash_type` is 0 (`SIGHASH_DEFAULT`)
+    pub fn new(keys: Vec<PrivateKey>, sighash: [u8; 32], sighash_type: u8) -> Self {
+        Self {
+            keys,
+            sighash,
+            sighash_type,
+            preimages: vec![],
+            sequence: SEQUENCE_DISABLE_FLAG,
+            lock_time: 0,
+        }
+    }
+
+    /// Add preimages that can be revealed for hash locks
+    pub fn with_preimages(mut self, preimages: Vec<Vec<u8>>) -> Self {
+        self.preimages = preimages;
+        self
+    }
+
+    /// Set the sequence of the spending input and lock time of the spending transaction
+    pub fn with_timelocks(mut self, sequence: u32, lock_time: u32) -> Self {
+        self.sequence = sequence;
+        self.lock_time = lock_time;
+        self
+    }
+}
+
+impl Satisfier for KeySigner {
+    fn ecdsa_signature(&self, key: &Point) -> Option<Vec<u8>> {
+        let private = self.keys.iter().find(|k| k.point() == key)?;
+        let mut sig = private.sign(&hex::encode(self.sighash)).ok()?.der();
+        sig.push(self.sighash_type);
+
+        Some(sig)
+    }
+
+    fn schnorr_signature(&self, key: &Point) -> Option<Vec<u8>> {
+        // tapscript keys are x-only, so either parity of the private key matches
+        let private = self
+            .keys
+            .iter()
+            .find(|k| k.point().x_only() == key.x_only())?;
+        let mut aux_rand = [0; 32];
+        getrandom::getrandom(&mut aux_rand).ok()?;
+        let mut sig = private
+            .sign_schnorr(&self.sighash, &aux_rand)
+            .ok()?
+            .to_bytes()
+            .to_vec();
+        if self.sighash_type != 0 {
+            sig.push(self.sighash_type);
+        }
+
+        Some(sig)
+    }
+
+    fn preimage(&self, lock: &HashLock) -> Option<Vec<u8>> {
+        self.preimages.iter().find(|p| lock.matches(p)).cloned()
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        // BIP112: both must be relative timelocks of the same unit
+        self.sequence & SEQUENCE_DISABLE_FLAG == 0
+            && n & SEQUENCE_TYPE_FLAG == self.sequence & SEQUENCE_TYPE_FLAG
+            && n & SEQUENCE_MASK <= self.sequence & SEQUENCE_MASK
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        // BIP65: both must be heights or both timestamps
+        (n < LOCKTIME_THRESHOLD) == (self.lock_time < LOCKTIME_THRESHOLD) && n <= self.lock_time
+    }
+}
+
+/// Witness stack, bottom element first, or None if it cannot be produced
+type Witness = Option<Vec<Vec<u8>>>;
+
+/// Serialised size of a witness, each element prefixed by its length
+fn size(witness: &[Vec<u8>]) -> usize {
+    witness.iter().map(|item| item.len() + 1).sum()
+}
+
+/// Stack `top` above `bottom`
+fn concat(bottom: &Witness, top: &Witness) -> Witness {
+    Some([bottom.as_ref()?.as_slice(), top.as_ref()?.as_slice()].concat())
+}
+
+/// Smallest of the possible witnesses
+fn smallest(options: impl IntoIterator<Item = Witness>) -> Witness {
+    options.into_iter().flatten().min_by_key(|w| size(w))
+}
+
+impl Miniscript {
+    /// Build the smallest witness satisfying the expression with the keys at `index`
+    ///
+    /// The witness script itself, or the tapscript leaf and control block, are not included
+    pub fn satisfy(
+        &self,
+        ctx: ScriptContext,
+        index: u32,
+        satisfier: &impl Satisfier,
+    ) -> Result<Vec<Vec<u8>>> {
+        match self.satisfactions(ctx, index, satisfier)?.0 {
+            Some(witness) => Ok(witness),
+            None => bail!(MiniscriptError::CouldNotSatisfy),
+        }
+    }
+
+    /// Smallest satisfaction and dissatisfaction witnesses
+    fn satisfactions(
+        &self,
+        ctx: ScriptContext,
+        index: u32,
+        satisfier: &impl Satisfier,
+    ) -> Result<(Witness, Witness)> {
+        let sats = |ms: &Self| ms.satisfactions(ctx, index, satisfier);
+        let one = Some(vec![vec![1]]);
+        let empty = Some(vec![vec![]]);
+        let signature = |point: &Point| match ctx {
+            ScriptContext::Segwitv0 => satisfier.ecdsa_signature(point),
+            ScriptContext::Tapscript => satisfier.schnorr_signature(point),
+        };
+        let key_bytes = |point: &Point| match ctx {
+            ScriptContext::Segwitv0 => point.sec(true),
+            ScriptContext::Tapscript => point.x_only().to_vec(),
+        };
+
+        Ok(match self {
+            Self::False => (None, Some(vec![])),
+            Self::True => (Some(vec![]), None),
+            Self::PkK(key) => (signature(&key.derive(index)?).map(|sig| vec![sig]), empty),
+            Self::PkH(key) => {
+                let point = key.derive(index)?;
+                let key = key_bytes(&point);
+                (
+                    signature(&point).map(|sig| vec![sig, key.clone()]),
+                    Some(vec![vec![], key]),
+                )
+            }
+            Self::Older(n) => (satisfier.check_older(*n).then(Vec::new), None),
+            Self::After(n) => (satisfier.check_after(*n).then(Vec::new), None),
+            Self::Hash(lock) => (
+                satisfier.preimage(lock).map(|preimage| vec![preimage]),
+                Some(vec![vec![0; 32]]),
+            ),
+            Self::AndOr(x, y, z) => {
+                let ((sx, dx), (sy, _), (sz, dz)) = (sats(x)?, sats(y)?, sats(z)?);
+                (
+                    smallest([concat(&sy, &sx), concat(&sz, &dx)]),
+                    concat(&dz, &dx),
+                )
+            }
+            Self::AndV(x, y) => (concat(&sats(y)?.0, &sats(x)?.0), None),
+            Self::AndB(x, y) => {
+                let ((sx, dx), (sy, dy)) = (sats(x)?, sats(y)?);
+                (concat(&sy, &sx), concat(&dy, &dx))
+            }
+            Self::OrB(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sats(x)?, sats(z)?);
+                (
+                    smallest([concat(&dz, &sx), concat(&sz, &dx)]),
+                    concat(&dz, &dx),
+                )
+            }
+            Self::OrC(x, z) => {
+                let ((sx, dx), (sz, _)) = (sats(x)?, sats(z)?);
+                (smallest([sx, concat(&sz, &dx)]), None)
+            }
+            Self::OrD(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sats(x)?, sats(z)?);
+                (smallest([sx, concat(&sz, &dx)]), concat(&dz, &dx))
+            }
+            Self::OrI(x, z) => {
+                let ((sx, dx), (sz, dz)) = (sats(x)?, sats(z)?);
+                (
+                    smallest([concat(&sx, &one), concat(&sz, &empty)]),
+                    smallest([concat(&dx, &one), concat(&dz, &empty)]),
+                )
+            }
+            Self::Thresh(k, subs) => {
+                // best[j] is the smallest witness with exactly j satisfied sub-expressions,
+                // the first sub-expression being evaluated against the top of the stack
+                let mut best: Vec<Witness> = vec![None; subs.len() + 1];
+                best[0] = Some(vec![]);
+                for sub in subs {
+                    let (sat, dissat) = sats(sub)?;
+                    for j in (0..best.len()).rev() {
+                        let skip = concat(&dissat, &best[j]);
+                        let take = if j > 0 {
+                            concat(&sat, &best[j - 1])
+                        } else {
+                            None
+                        };
+                        best[j] = smallest([skip, take]);
+                    }
+                }
+                let dissat = best[0].clone();
+                (best[*k].take(), dissat)
+            }
+            Self::Multi(k, keys) => {
+                // CHECKMULTISIG pops an extra dummy element below the signatures
+                let mut sigs = vec![vec![]];
+                for key in keys {
+                    if sigs.len() > *k {
+                        break;
+                    }
+                    if let Some(sig) = signature(&key.derive(index)?) {
+                        sigs.push(sig);
+                    }
+                }
+                ((sigs.len() > *k).then_some(sigs), Some(vec![vec![]; k + 1]))
+            }
+            Self::MultiA(k, keys) => {
+                // the first key is checked against the top of the stack
+                let (mut sigs, mut count) = (vec![], 0);
+                for key in keys {
+                    let sig = match count < *k {
+                        true => signature(&key.derive(index)?),
+                        false => None,
+                    };
+                    count += usize::from(sig.is_some());
+                    sigs.push(sig.unwrap_or_default());
+                }
+                sigs.reverse();
+                (
+                    (count == *k).then_some(sigs),
+                    Some(vec![vec![]; keys.len()]),
+                )
+            }
+            Self::Alt(x) | Self::Swap(x) | Self::Check(x) | Self::ZeroNotEqual(x) => sats(x)?,
+            Self::DupIf(x) => (concat(&sats(x)?.0, &one), empty),
+            Self::Verify(x) => (sats(x)?.0, None),
+            Self::NonZero(x) => (sats(x)?.0, empty),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{secp256k1::schnorr::SchnorrSignature, utils::hash::sha256},
+    };
+
+    fn keys() -> Result<Vec<PrivateKey>> {
+        (1..=3)
+            .map(|i| PrivateKey::from_bytes(&sha256(&[i])))
+            .collect()
+    }
+
+    fn hex_key(key: &PrivateKey) -> String {
+        hex::encode(key.point().sec(true))
+    }
+
+    #[test]
+    fn multisig_witness() -> Result<()> {
+        let keys = keys()?;
+        let ms = Miniscript::parse(
+            &format!(
+                "multi(2,{},{},{})",
+                hex_key(&keys[0]),
+                hex_key(&keys[1]),
+                hex_key(&keys[2])
+            ),
+            ScriptContext::Segwitv0,
+        )?;
+        let sighash = sha256(b"multisig");
+        let signer = KeySigner::new(vec![keys[2].clone(), keys[0].clone()], sighash, 1);
+        let witness = ms.satisfy(ScriptContext::Segwitv0, 0, &signer)?;
+
+        // the dummy, then signatures in key order
+        assert_eq!(witness.len(), 3);
+        assert!(witness[0].is_empty());
+        assert_eq!(witness[1], signer.ecdsa_signature(keys[0].point()).unwrap());
+        assert_eq!(witness[2], signer.ecdsa_signature(keys[2].point()).unwrap());
+        assert!(size(&witness) <= ms.max_satisfaction_size(ScriptContext::Segwitv0).unwrap());
+
+        let signer = KeySigner::new(vec![keys[1].clone()], sighash, 1);
+        assert!(ms.satisfy(ScriptContext::Segwitv0, 0, &signer).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn timelocked_recovery() -> Result<()> {
+        let keys = keys()?;
+        let ms = Miniscript::parse(
+            &format!(
+                "or_d(pk({}),and_v(v:pkh({}),older(144)))",
+                hex_key(&keys[0]),
+                hex_key(&keys[1])
+            ),
+            ScriptContext::Segwitv0,
+        )?;
+        let sighash = sha256(b"recovery");
+
+        let primary = KeySigner::new(vec![keys[0].clone()], sighash, 1);
+        assert_eq!(ms.satisfy(ScriptContext::Segwitv0, 0, &primary)?.len(), 1);
+
+        // the recovery key alone is not enough until the sequence reaches the timelock
+        let recovery = KeySigner::new(vec![keys[1].clone()], sighash, 1).with_timelocks(143, 0);
+        assert!(ms.satisfy(ScriptContext::Segwitv0, 0, &recovery).is_err());
+
+        let recovery = recovery.with_timelocks(144, 0);
+        let witness = ms.satisfy(ScriptContext::Segwitv0, 0, &recovery)?;
+        assert_eq!(witness[1], keys[1].point().sec(true));
+        assert!(witness[2].is_empty());
+        assert!(size(&witness) <= ms.max_satisfaction_size(ScriptContext::Segwitv0).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn hash_lock_and_tapscript_signature() -> Result<()> {
+        let keys = keys()?;
+        let preimage = vec![7; 32];
+        let ms = Miniscript::parse(
+            &format!(
+                "and_v(v:pk({}),sha256({}))",
+                hex::encode(keys[0].point().x_only()),
+                hex::encode(sha256(&preimage))
+            ),
+            ScriptContext::Tapscript,
+        )?;
+        let sighash = sha256(b"taproot");
+        let signer = KeySigner::new(vec![keys[0].clone()], sighash, 0);
+        assert!(ms.satisfy(ScriptContext::Tapscript, 0, &signer).is_err());
+
+        let signer = signer.with_preimages(vec![vec![1; 32], preimage.clone()]);
+        let witness = ms.satisfy(ScriptContext::Tapscript, 0, &signer)?;
+        assert_eq!(witness[0], preimage);
+
+        let sig = SchnorrSignature::from_bytes(&witness[1])?;
+        assert!(keys[0].point().verify_schnorr(&sighash, &sig));
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_timelock_units() {
+        let signer = KeySigner::new(vec![], [0; 32], 1).with_timelocks(0, 700_000);
+        assert!(signer.check_after(650_000));
+        assert!(!signer.check_after(700_001));
+        assert!(!signer.check_after(LOCKTIME_THRESHOLD + 1));
+        assert!(!signer.check_older(1));
+    }
+}
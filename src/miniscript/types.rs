@@ -0,0 +1,491 @@
+use {super::errors::MiniscriptError, anyhow::Result};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Basic type of a miniscript expression, describing what it does with the stack
+pub enum Base {
+    /// Consumes its inputs and pushes a non-zero value on satisfaction, zero otherwise
+    B,
+    /// Consumes its inputs and continues only on satisfaction, pushing nothing
+    V,
+    /// Consumes its inputs and pushes a public key whose signature is checked later
+    K,
+    /// Like `B` but operates on the element below the top of the stack
+    W,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Number and kind of stack elements consumed by an expression
+pub enum Input {
+    /// Consumes nothing (`z`)
+    Zero,
+    /// Consumes exactly one element (`o`)
+    One,
+    /// Consumes exactly one element, which is non-zero when satisfying (`o` and `n`)
+    OneNonZero,
+    /// Consumes any number of elements
+    Any,
+    /// Consumes at least one element, the top one non-zero when satisfying (`n`)
+    AnyNonZero,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Dissatisfactions a third party could construct for an expression
+pub enum Dissat {
+    /// No dissatisfaction exists without a signature (`f`)
+    None,
+    /// Exactly one dissatisfaction exists without a signature (`e`)
+    Unique,
+    /// Several dissatisfactions may exist
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Correctness and malleability properties of a miniscript expression
+pub struct Type {
+    pub base: Base,
+    pub input: Input,
+    /// A dissatisfaction exists (`d`)
+    pub dissatisfiable: bool,
+    /// Leaves exactly 1 on the stack when satisfied (`u`)
+    pub unit: bool,
+    pub dissat: Dissat,
+    /// Every satisfaction requires a signature (`s`)
+    pub safe: bool,
+    /// A non-malleable satisfaction exists for every combination of available resources (`m`)
+    pub non_malleable: bool,
+}
+
+fn check(condition: bool, fragment: &str, reason: &str) -> Result<()> {
+    if !condition {
+        return Err(MiniscriptError::TypeCheck(fragment.to_string(), reason.to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// Inputs of two expressions executed one after the other
+fn sequence_input(left: Input, right: Input) -> Input {
+    use Input::*;
+
+    match (left, right) {
+        (Zero, Zero) => Zero,
+        (Zero, One) | (One, Zero) => One,
+        (Zero, OneNonZero) | (OneNonZero, Zero) => OneNonZero,
+        (OneNonZero, _) | (AnyNonZero, _) | (Zero, AnyNonZero) => AnyNonZero,
+        _ => Any,
+    }
+}
+
+impl Type {
+    /// `0`
+    pub const FALSE: Self = Self {
+        base: Base::B,
+        input: Input::Zero,
+        dissatisfiable: true,
+        unit: true,
+        dissat: Dissat::Unique,
+        safe: true,
+        non_malleable: true,
+    };
+
+    /// `1`
+    pub const TRUE: Self = Self {
+        base: Base::B,
+        input: Input::Zero,
+        dissatisfiable: false,
+        unit: true,
+        dissat: Dissat::None,
+        safe: false,
+        non_malleable: true,
+    };
+
+    /// `pk_k`
+    pub const PK_K: Self = Self {
+        base: Base::K,
+        input: Input::OneNonZero,
+        dissatisfiable: true,
+        unit: true,
+        dissat: Dissat::Unique,
+        safe: true,
+        non_malleable: true,
+    };
+
+    /// `pk_h`
+    pub const PK_H: Self = Self {
+        input: Input::AnyNonZero,
+        ..Self::PK_K
+    };
+
+    /// `multi`
+    pub const MULTI: Self = Self {
+        base: Base::B,
+        input: Input::AnyNonZero,
+        ..Self::PK_K
+    };
+
+    /// `multi_a`
+    pub const MULTI_A: Self = Self {
+        base: Base::B,
+        input: Input::Any,
+        ..Self::PK_K
+    };
+
+    /// `sha256`, `hash256`, `ripemd160` and `hash160`
+    pub const HASH: Self = Self {
+        base: Base::B,
+        input: Input::OneNonZero,
+        dissatisfiable: true,
+        unit: true,
+        dissat: Dissat::Unknown,
+        safe: false,
+        non_malleable: true,
+    };
+
+    /// `older` and `after`
+    pub const TIME: Self = Self {
+        base: Base::B,
+        input: Input::Zero,
+        dissatisfiable: false,
+        unit: false,
+        dissat: Dissat::None,
+        safe: false,
+        non_malleable: true,
+    };
+
+    /// `z`, the expression consumes no stack elements
+    pub fn is_zero(&self) -> bool {
+        self.input == Input::Zero
+    }
+
+    /// `o`, the expression consumes exactly one stack element
+    pub fn is_one(&self) -> bool {
+        matches!(self.input, Input::One | Input::OneNonZero)
+    }
+
+    /// `n`, the top input is non-zero when satisfying
+    pub fn is_non_zero(&self) -> bool {
+        matches!(self.input, Input::OneNonZero | Input::AnyNonZero)
+    }
+
+    /// Dissatisfiable and unit, as required of most combinator arguments
+    fn is_du(&self) -> bool {
+        self.dissatisfiable && self.unit
+    }
+
+    /// `a:X`
+    pub fn alt(x: Self) -> Result<Self> {
+        check(x.base == Base::B, "a:", "argument must be B")?;
+
+        Ok(Self {
+            base: Base::W,
+            input: Input::Any,
+            ..x
+        })
+    }
+
+    /// `s:X`
+    pub fn swap(x: Self) -> Result<Self> {
+        check(x.base == Base::B, "s:", "argument must be B")?;
+        check(x.is_one(), "s:", "argument must consume one element")?;
+
+        Ok(Self {
+            base: Base::W,
+            input: Input::Any,
+            ..x
+        })
+    }
+
+    /// `c:X`
+    pub fn check(x: Self) -> Result<Self> {
+        check(x.base == Base::K, "c:", "argument must be K")?;
+
+        Ok(Self {
+            base: Base::B,
+            unit: true,
+            ..x
+        })
+    }
+
+    /// `d:X`, which only leaves exactly one element in tapscript thanks to `MINIMALIF`
+    pub fn dup_if(x: Self, tapscript: bool) -> Result<Self> {
+        check(x.base == Base::V, "d:", "argument must be V")?;
+        check(x.is_zero(), "d:", "argument must consume nothing")?;
+
+        Ok(Self {
+            base: Base::B,
+            input: Input::OneNonZero,
+            dissatisfiable: true,
+            unit: tapscript,
+            dissat: if x.dissat == Dissat::None {
+                Dissat::Unique
+            } else {
+                Dissat::Unknown
+            },
+            ..x
+        })
+    }
+
+    /// `v:X`
+    pub fn verify(x: Self) -> Result<Self> {
+        check(x.base == Base::B, "v:", "argument must be B")?;
+
+        Ok(Self {
+            base: Base::V,
+            dissatisfiable: false,
+            unit: false,
+            dissat: Dissat::None,
+            ..x
+        })
+    }
+
+    /// `j:X`
+    pub fn non_zero(x: Self) -> Result<Self> {
+        check(x.base == Base::B, "j:", "argument must be B")?;
+        check(x.is_non_zero(), "j:", "argument must have a non-zero input")?;
+
+        Ok(Self {
+            dissatisfiable: true,
+            dissat: if x.dissat == Dissat::None {
+                Dissat::Unique
+            } else {
+                Dissat::Unknown
+            },
+            ..x
+        })
+    }
+
+    /// `n:X`
+    pub fn zero_not_equal(x: Self) -> Result<Self> {
+        check(x.base == Base::B, "n:", "argument must be B")?;
+
+        Ok(Self { unit: true, ..x })
+    }
+
+    /// `and_v(X,Y)`
+    pub fn and_v(x: Self, y: Self) -> Result<Self> {
+        check(x.base == Base::V, "and_v", "first argument must be V")?;
+        check(
+            y.base != Base::W,
+            "and_v",
+            "second argument must be B, K or V",
+        )?;
+
+        Ok(Self {
+            base: y.base,
+            input: sequence_input(x.input, y.input),
+            dissatisfiable: false,
+            unit: y.unit,
+            dissat: if y.dissat == Dissat::None || x.safe {
+                Dissat::None
+            } else {
+                Dissat::Unknown
+            },
+            safe: x.safe || y.safe,
+            non_malleable: x.non_malleable && y.non_malleable,
+        })
+    }
+
+    /// `and_b(X,Y)`
+    pub fn and_b(x: Self, y: Self) -> Result<Self> {
+        check(x.base == Base::B, "and_b", "first argument must be B")?;
+        check(y.base == Base::W, "and_b", "second argument must be W")?;
+
+        Ok(Self {
+            base: Base::B,
+            input: sequence_input(x.input, y.input),
+            dissatisfiable: x.dissatisfiable && y.dissatisfiable,
+            unit: true,
+            dissat: match (x.dissat, y.dissat) {
+                (Dissat::None, Dissat::None) => Dissat::None,
+                (Dissat::None, _) if x.safe => Dissat::None,
+                (_, Dissat::None) if y.safe => Dissat::None,
+                (Dissat::Unique, Dissat::Unique) if x.safe && y.safe => Dissat::Unique,
+                _ => Dissat::Unknown,
+            },
+            safe: x.safe || y.safe,
+            non_malleable: x.non_malleable && y.non_malleable,
+        })
+    }
+
+    /// `or_b(X,Z)`
+    pub fn or_b(x: Self, z: Self) -> Result<Self> {
+        check(x.base == Base::B, "or_b", "first argument must be B")?;
+        check(z.base == Base::W, "or_b", "second argument must be W")?;
+        check(
+            x.dissatisfiable && z.dissatisfiable,
+            "or_b",
+            "arguments must be dissatisfiable",
+        )?;
+
+        Ok(Self {
+            base: Base::B,
+            input: match (x.input, z.input) {
+                (Input::Zero, Input::Zero) => Input::Zero,
+                (Input::Zero, Input::One | Input::OneNonZero)
+                | (Input::One | Input::OneNonZero, Input::Zero) => Input::One,
+                _ => Input::Any,
+            },
+            dissatisfiable: true,
+            unit: true,
+            dissat: Dissat::Unique,
+            safe: x.safe && z.safe,
+            non_malleable: x.non_malleable
+                && z.non_malleable
+                && x.dissat == Dissat::Unique
+                && z.dissat == Dissat::Unique
+                && (x.safe || z.safe),
+        })
+    }
+
+    /// `or_c(X,Z)` and `or_d(X,Z)`, which differ in the base of `Z` and of the result
+    fn or_cd(x: Self, z: Self, fragment: &str, base: Base) -> Result<Self> {
+        check(x.base == Base::B, fragment, "first argument must be B")?;
+        check(
+            z.base == base,
+            fragment,
+            "second argument has the wrong base",
+        )?;
+        check(
+            x.is_du(),
+            fragment,
+            "first argument must be dissatisfiable and unit",
+        )?;
+
+        Ok(Self {
+            base,
+            input: match (x.input, z.input) {
+                (Input::Zero, Input::Zero) => Input::Zero,
+                (Input::One | Input::OneNonZero, Input::Zero) => Input::One,
+                _ => Input::Any,
+            },
+            dissatisfiable: base == Base::B && z.dissatisfiable,
+            unit: base == Base::B && z.unit,
+            dissat: if base == Base::B {
+                z.dissat
+            } else {
+                Dissat::None
+            },
+            safe: x.safe && z.safe,
+            non_malleable: x.non_malleable
+                && z.non_malleable
+                && x.dissat == Dissat::Unique
+                && (x.safe || z.safe),
+        })
+    }
+
+    /// `or_c(X,Z)`
+    pub fn or_c(x: Self, z: Self) -> Result<Self> {
+        Self::or_cd(x, z, "or_c", Base::V)
+    }
+
+    /// `or_d(X,Z)`
+    pub fn or_d(x: Self, z: Self) -> Result<Self> {
+        Self::or_cd(x, z, "or_d", Base::B)
+    }
+
+    /// `or_i(X,Z)`
+    pub fn or_i(x: Self, z: Self) -> Result<Self> {
+        check(
+            x.base == z.base && x.base != Base::W,
+            "or_i",
+            "arguments must both be B, K or V",
+        )?;
+
+        Ok(Self {
+            base: x.base,
+            input: match (x.input, z.input) {
+                (Input::Zero, Input::Zero) => Input::One,
+                _ => Input::Any,
+            },
+            dissatisfiable: x.dissatisfiable || z.dissatisfiable,
+            unit: x.unit && z.unit,
+            dissat: match (x.dissat, z.dissat) {
+                (Dissat::None, Dissat::None) => Dissat::None,
+                (Dissat::Unique, Dissat::None) | (Dissat::None, Dissat::Unique) => Dissat::Unique,
+                _ => Dissat::Unknown,
+            },
+            safe: x.safe && z.safe,
+            non_malleable: x.non_malleable && z.non_malleable && (x.safe || z.safe),
+        })
+    }
+
+    /// `andor(X,Y,Z)`
+    pub fn and_or(x: Self, y: Self, z: Self) -> Result<Self> {
+        check(x.base == Base::B, "andor", "first argument must be B")?;
+        check(
+            y.base == z.base && y.base != Base::W,
+            "andor",
+            "second and third arguments must both be B, K or V",
+        )?;
+        check(
+            x.is_du(),
+            "andor",
+            "first argument must be dissatisfiable and unit",
+        )?;
+
+        use Input::*;
+        Ok(Self {
+            base: y.base,
+            input: match (x.input, y.input, z.input) {
+                (Zero, Zero, Zero) => Zero,
+                (Zero, One | OneNonZero, One | OneNonZero) | (One | OneNonZero, Zero, Zero) => One,
+                _ => Any,
+            },
+            dissatisfiable: z.dissatisfiable,
+            unit: y.unit && z.unit,
+            dissat: match (x.safe, y.dissat, z.dissat) {
+                (_, Dissat::None, Dissat::Unique) | (true, _, Dissat::Unique) => Dissat::Unique,
+                (_, Dissat::None, Dissat::None) | (true, _, Dissat::None) => Dissat::None,
+                _ => Dissat::Unknown,
+            },
+            safe: (x.safe || y.safe) && z.safe,
+            non_malleable: x.non_malleable
+                && y.non_malleable
+                && z.non_malleable
+                && x.dissat == Dissat::Unique
+                && (x.safe || y.safe || z.safe),
+        })
+    }
+
+    /// `thresh(k,X1,...,Xn)`
+    pub fn thresh(k: usize, subs: &[Self]) -> Result<Self> {
+        let n = subs.len();
+        check(k >= 1 && k <= n, "thresh", "threshold out of range")?;
+
+        let mut args = 0;
+        for (i, sub) in subs.iter().enumerate() {
+            let base = if i == 0 { Base::B } else { Base::W };
+            check(sub.base == base, "thresh", "arguments must be B then W")?;
+            check(
+                sub.is_du(),
+                "thresh",
+                "arguments must be dissatisfiable and unit",
+            )?;
+            args += match sub.input {
+                Input::Zero => 0,
+                Input::One | Input::OneNonZero => 1,
+                Input::Any | Input::AnyNonZero => 2,
+            };
+        }
+
+        let safe = subs.iter().filter(|sub| sub.safe).count();
+        let unique = subs.iter().all(|sub| sub.dissat == Dissat::Unique);
+        Ok(Self {
+            base: Base::B,
+            input: match args {
+                0 => Input::Zero,
+                1 => Input::One,
+                _ => Input::Any,
+            },
+            dissatisfiable: true,
+            unit: true,
+            dissat: if unique && safe == n {
+                Dissat::Unique
+            } else {
+                Dissat::Unknown
+            },
+            safe: safe > n - k,
+            non_malleable: subs.iter().all(|sub| sub.non_malleable) && safe >= n - k && unique,
+        })
+    }
+}
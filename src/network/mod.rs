@@ -0,0 +1,64 @@
+pub mod params;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// Bitcoin network that keys, addresses and blocks belong to
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    /// If the network is the main Bitcoin network
+    pub fn is_mainnet(&self) -> bool {
+        *self == Self::Mainnet
+    }
+
+    /// Human readable part of segwit addresses
+    pub fn bech32_hrp(&self) -> &'static str {
+        match self {
+            Self::Mainnet => "bc",
+            Self::Testnet | Self::Signet => "tb",
+            Self::Regtest => "bcrt",
+        }
+    }
+
+    /// Base58 version byte of pay to public key hash addresses
+    pub fn p2pkh_prefix(&self) -> u8 {
+        if self.is_mainnet() {
+            0x00
+        } else {
+            0x6f
+        }
+    }
+
+    /// Base58 version byte of pay to script hash addresses
+    pub fn p2sh_prefix(&self) -> u8 {
+        if self.is_mainnet() {
+            0x05
+        } else {
+            0xc4
+        }
+    }
+
+    /// Bytes that start every P2P message of the network
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            Self::Mainnet => [0xf9, 0xbe, 0xb4, 0xd9],
+            Self::Testnet => [0x0b, 0x11, 0x09, 0x07],
+            Self::Signet => [0x0a, 0x03, 0xcf, 0x40],
+            Self::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+
+    /// Port nodes of the network listen on by default
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::Mainnet => 8333,
+            Self::Testnet => 18333,
+            Self::Signet => 38333,
+            Self::Regtest => 18444,
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use {
+    super::Network,
+    crate::{
+        block::header::{bits_to_target, target_to_bits, BlockHash, BlockHeader},
+        script::interpreter::{SCRIPT_VERIFY_P2SH, SCRIPT_VERIFY_WITNESS},
+    },
+    ibig::UBig,
+};
+
+/// Merkle root of the genesis block of every network, committing to its only coinbase
+const GENESIS_MERKLE_ROOT: [u8; 32] = [
+    0x3b, 0xa3, 0xed, 0xfd, 0x7a, 0x7b, 0x12, 0xb2, 0x7a, 0xc7, 0x2c, 0x3e, 0x67, 0x76, 0x8f, 0x61,
+    0x7f, 0xc8, 0x1b, 0xc3, 0x88, 0x8a, 0x51, 0x32, 0x3a, 0x9f, 0xb8, 0xaa, 0x4b, 0x1e, 0x5e, 0x4a,
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Consensus parameters of a network that header validation depends on
+pub struct Params {
+    pub network: Network,
+    /// Header of the first block, which every chain of the network starts from
+    pub genesis: BlockHeader,
+    /// Highest target a block may have
+    pub pow_limit: UBig,
+    /// If a block more than 20 minutes after its parent may use the highest target, as on testnet
+    pub allow_min_difficulty_blocks: bool,
+    /// If the target never changes, as on regtest
+    pub no_retargeting: bool,
+    /// Blocks between halvings of the coinbase subsidy
+    pub subsidy_halving_interval: u32,
+    /// Height from which coinbases start with the block height, as specified by BIP34
+    pub bip34_height: u32,
+    /// Height from which `OP_CHECKLOCKTIMEVERIFY` is enforced, as specified by BIP65
+    pub bip65_height: u32,
+    /// Height from which signatures must be strict DER, as specified by BIP66
+    pub bip66_height: u32,
+    /// Height at which the BIP9 deployment of BIP68, BIP112 and BIP113 activated
+    pub csv_height: u32,
+    /// Height at which the BIP9 deployment of segwit, BIP141 and BIP143, activated
+    pub segwit_height: u32,
+    /// Blocks that are exempt from BIP30, by height, as they duplicate an earlier coinbase
+    pub bip30_exceptions: Vec<(u32, BlockHash)>,
+    /// Blocks whose scripts are verified under other flags than the rest of the chain, as they
+    /// break rules enforced from the genesis block
+    pub script_flag_exceptions: Vec<(BlockHash, u32)>,
+}
+
+impl Params {
+    /// Highest target a block may have, in the compact format
+    pub fn pow_limit_bits(&self) -> u32 {
+        target_to_bits(&self.pow_limit)
+    }
+}
+
+/// Block hash from its usual byte reversed hex
+fn block_hash(hex: &str) -> BlockHash {
+    let mut hash: BlockHash = hex::decode(hex).unwrap().try_into().unwrap();
+    hash.reverse();
+    hash
+}
+
+impl Network {
+    /// Consensus parameters of the network
+    pub fn params(&self) -> Params {
+        let (time, bits, nonce) = match self {
+            Self::Mainnet => (1231006505, 0x1d00ffff, 2083236893),
+            Self::Testnet => (1296688602, 0x1d00ffff, 414098458),
+            Self::Signet => (1598918400, 0x1e0377ae, 52613770),
+            Self::Regtest => (1296688602, 0x207fffff, 2),
+        };
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: [0; 32],
+            merkle_root: GENESIS_MERKLE_ROOT,
+            time,
+            bits,
+            nonce,
+        };
+
+        // the limits of mainnet and testnet keep every bit below the compact mantissa set
+        let pow_limit = match self {
+            Self::Mainnet | Self::Testnet => (UBig::from(1u8) << 224) - UBig::from(1u8),
+            Self::Signet | Self::Regtest => bits_to_target(bits).unwrap(),
+        };
+
+        // heights of BIP34, BIP65, BIP66, CSV and segwit
+        let (bip34_height, bip65_height, bip66_height, csv_height, segwit_height) = match self {
+            Self::Mainnet => (227931, 388381, 363725, 419328, 481824),
+            Self::Testnet => (21111, 581885, 330776, 770112, 834624),
+            Self::Signet => (1, 1, 1, 1, 1),
+            Self::Regtest => (1, 1, 1, 1, 0),
+        };
+        let bip30_exceptions = match self {
+            Self::Mainnet => vec![
+                (
+                    91842,
+                    block_hash("00000000000a4d0a398161ffc163c503763b1f4360639393e0e4c8e300e0caec"),
+                ),
+                (
+                    91880,
+                    block_hash("00000000000743f190a18c5577a3c2d2a1f610ae9601ac046a38084ccb7cd721"),
+                ),
+            ],
+            _ => vec![],
+        };
+        let script_flag_exceptions = match self {
+            Self::Mainnet => vec![
+                // a P2SH spend before BIP16 that fails its rules
+                (
+                    block_hash("00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22"),
+                    0,
+                ),
+                // a taproot spend before taproot that fails its rules
+                (
+                    block_hash("0000000000000000000f14c35b2d841e986ab5441de8c585d5ffe55ea1e395ad"),
+                    SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS,
+                ),
+            ],
+            Self::Testnet => vec![(
+                block_hash("00000000dd30457c001f4095d208cc1296b0eed002427aa599874af7a432b105"),
+                0,
+            )],
+            _ => vec![],
+        };
+
+        Params {
+            network: *self,
+            genesis,
+            pow_limit,
+            allow_min_difficulty_blocks: matches!(self, Self::Testnet | Self::Regtest),
+            no_retargeting: *self == Self::Regtest,
+            subsidy_halving_interval: match self {
+                Self::Regtest => 150,
+                _ => 210_000,
+            },
+            bip34_height,
+            bip65_height,
+            bip66_height,
+            csv_height,
+            segwit_height,
+            bip30_exceptions,
+            script_flag_exceptions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    #[test]
+    fn genesis_hashes() {
+        for (network, hash) in [
+            (
+                Network::Mainnet,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            (
+                Network::Testnet,
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            (
+                Network::Signet,
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            ),
+            (
+                Network::Regtest,
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+        ] {
+            let params = network.params();
+
+            assert_eq!(reversed_hex(&params.genesis.hash()), hash);
+            assert!(params.genesis.check_pow().is_ok());
+            assert_eq!(params.pow_limit_bits(), params.genesis.bits);
+        }
+    }
+}
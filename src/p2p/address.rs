@@ -0,0 +1,312 @@
+use {
+    super::errors::P2pError,
+    crate::utils::encoding::{compact_size, read_array, read_bytes, read_compact_size, var_bytes},
+    anyhow::{bail, Result},
+    std::{
+        io::Read,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    },
+};
+
+/// Service bit of nodes that serve the full block chain
+pub const NODE_NETWORK: u64 = 1 << 0;
+
+/// Service bit of nodes that accept BIP37 bloom filters
+pub const NODE_BLOOM: u64 = 1 << 2;
+
+/// Service bit of nodes that serve witness data
+pub const NODE_WITNESS: u64 = 1 << 3;
+
+/// Service bit of nodes that serve BIP157 compact block filters
+pub const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+
+/// Service bit of nodes that serve the last 288 blocks
+pub const NODE_NETWORK_LIMITED: u64 = 1 << 10;
+
+/// Longest address an addrv2 message may hold
+const MAX_ADDRV2_SIZE: usize = 512;
+
+/// Prefix of IPv4 addresses mapped into IPv6
+const IPV4_IN_IPV6_PREFIX: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+
+/// Prefix of the IPv6 addresses Tor v2 addresses used to be mapped to
+const TORV2_IN_IPV6_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Address and services of a node, in the format of version and addr messages
+pub struct NetAddress {
+    pub services: u64,
+    /// IPv6 address, or an IPv4 address mapped into IPv6
+    pub ip: [u8; 16],
+    pub port: u16,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Address of a node on one of the networks of BIP155
+pub enum AddrV2 {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    TorV2([u8; 10]),
+    TorV3([u8; 32]),
+    I2p([u8; 32]),
+    Cjdns(Ipv6Addr),
+    /// Address of a network this implementation does not know, with its network id
+    Unknown(u8, Vec<u8>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Entry of an addrv2 message
+pub struct AddrV2Message {
+    /// Time the node was last seen, in seconds since the UNIX epoch
+    pub time: u32,
+    pub services: u64,
+    pub addr: AddrV2,
+    pub port: u16,
+}
+
+impl NetAddress {
+    pub fn new(ip: IpAddr, port: u16, services: u64) -> Self {
+        let ip = match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        Self {
+            services,
+            ip: ip.octets(),
+            port,
+        }
+    }
+
+    /// IP address of the node, unmapping IPv4 addresses
+    pub fn ip_addr(&self) -> IpAddr {
+        Ipv6Addr::from(self.ip).to_canonical()
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            services: u64::from_le_bytes(read_array(reader)?),
+            ip: read_array(reader)?,
+            port: u16::from_be_bytes(read_array(reader)?),
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.services.to_le_bytes().as_slice(),
+            &self.ip,
+            &self.port.to_be_bytes(),
+        ]
+        .concat()
+    }
+}
+
+impl AddrV2 {
+    /// BIP155 id of the network of the address
+    pub fn network_id(&self) -> u8 {
+        match self {
+            Self::Ipv4(_) => 1,
+            Self::Ipv6(_) => 2,
+            Self::TorV2(_) => 3,
+            Self::TorV3(_) => 4,
+            Self::I2p(_) => 5,
+            Self::Cjdns(_) => 6,
+            Self::Unknown(id, _) => *id,
+        }
+    }
+
+    /// Parse a network id and address, checking the address length the network requires
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [network_id] = read_array(reader)?;
+        let len = read_compact_size(reader)?;
+        if len > MAX_ADDRV2_SIZE as u64 {
+            bail!(P2pError::OversizedField("address", len, MAX_ADDRV2_SIZE));
+        }
+
+        let expected = match network_id {
+            1 => 4,
+            2 | 6 => 16,
+            3 => 10,
+            4 | 5 => 32,
+            _ => len,
+        };
+        if len != expected {
+            bail!(P2pError::InvalidAddress(network_id));
+        }
+
+        let bytes = read_bytes(reader, len)?;
+        let addr = match network_id {
+            1 => Self::Ipv4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).unwrap())),
+            2 => {
+                // IPv4 and Tor addresses have networks of their own
+                if bytes.starts_with(&IPV4_IN_IPV6_PREFIX)
+                    || bytes.starts_with(&TORV2_IN_IPV6_PREFIX)
+                {
+                    bail!(P2pError::InvalidAddress(network_id));
+                }
+                Self::Ipv6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).unwrap()))
+            }
+            3 => Self::TorV2(bytes.try_into().unwrap()),
+            4 => Self::TorV3(bytes.try_into().unwrap()),
+            5 => Self::I2p(bytes.try_into().unwrap()),
+            6 => {
+                if bytes[0] != 0xfc {
+                    bail!(P2pError::InvalidAddress(network_id));
+                }
+                Self::Cjdns(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).unwrap()))
+            }
+            _ => Self::Unknown(network_id, bytes),
+        };
+
+        Ok(addr)
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        let bytes = match self {
+            Self::Ipv4(ip) => ip.octets().to_vec(),
+            Self::Ipv6(ip) | Self::Cjdns(ip) => ip.octets().to_vec(),
+            Self::TorV2(bytes) => bytes.to_vec(),
+            Self::TorV3(bytes) | Self::I2p(bytes) => bytes.to_vec(),
+            Self::Unknown(_, bytes) => bytes.clone(),
+        };
+
+        [vec![self.network_id()], var_bytes(&bytes)].concat()
+    }
+}
+
+impl AddrV2Message {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            time: u32::from_le_bytes(read_array(reader)?),
+            services: read_compact_size(reader)?,
+            addr: AddrV2::parse(reader)?,
+            port: u16::from_be_bytes(read_array(reader)?),
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.time.to_le_bytes().as_slice(),
+            &compact_size(self.services),
+            &self.addr.serialise(),
+            &self.port.to_be_bytes(),
+        ]
+        .concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(hex: &str) -> Result<AddrV2> {
+        let bytes = hex::decode(hex)?;
+        AddrV2::parse(&mut bytes.as_slice())
+    }
+
+    #[test]
+    fn net_address_maps_ipv4() -> Result<()> {
+        let ip = IpAddr::V4(Ipv4Addr::new(91, 240, 140, 128));
+        let address = NetAddress::new(ip, 48308, NODE_NETWORK);
+        assert_eq!(
+            hex::encode(address.serialise()),
+            "010000000000000000000000000000000000ffff5bf08c80bcb4"
+        );
+        assert_eq!(address.ip_addr(), ip);
+        assert_eq!(
+            NetAddress::parse(&mut address.serialise().as_slice())?,
+            address
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn addrv2_vectors() -> Result<()> {
+        // from the BIP155 tests of Bitcoin Core
+        let valid = [
+            ("010401020304", AddrV2::Ipv4(Ipv4Addr::new(1, 2, 3, 4))),
+            (
+                "02101a1b2a2b3a3b4a4b5a5b6a6b7a7b8a8b",
+                AddrV2::Ipv6("1a1b:2a2b:3a3b:4a4b:5a5b:6a6b:7a7b:8a8b".parse()?),
+            ),
+            (
+                "030af1f2f3f4f5f6f7f8f9fa",
+                AddrV2::TorV2(hex::decode("f1f2f3f4f5f6f7f8f9fa")?.try_into().unwrap()),
+            ),
+            (
+                "042053cd5648488c4707914182655b7664034e09e66f7e8cbf1084e654eb56c5bd88",
+                AddrV2::TorV3(
+                    hex::decode(
+                        "53cd5648488c4707914182655b7664034e09e66f7e8cbf1084e654eb56c5bd88",
+                    )?
+                    .try_into()
+                    .unwrap(),
+                ),
+            ),
+            (
+                "0520a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87",
+                AddrV2::I2p(
+                    hex::decode(
+                        "a2894dabaec08c0051a481a6dac88b64f98232ae42d4b6fd2fa81952dfe36a87",
+                    )?
+                    .try_into()
+                    .unwrap(),
+                ),
+            ),
+            (
+                "0610fc010001000200030004000500060007",
+                AddrV2::Cjdns("fc01:1:2:3:4:5:6:7".parse()?),
+            ),
+            ("aa0401020304", AddrV2::Unknown(170, vec![1, 2, 3, 4])),
+            ("aa00", AddrV2::Unknown(170, vec![])),
+        ];
+        for (hex, expected) in valid {
+            assert_eq!(addr(hex)?, expected);
+            assert_eq!(hex::encode(expected.serialise()), hex);
+        }
+
+        for hex in [
+            "01040102",
+            "010501020304",
+            "01fd010201020304",
+            "020400",
+            "021000000000000000000000ffff01020304",
+            "0210fd87d87eeb430102030405060708090a",
+            "030700",
+            "040000",
+            "050300",
+            "0610fd000001000200030004000500060007",
+            "060100",
+            "aafe0000000201020304050607",
+        ] {
+            assert!(addr(hex).is_err(), "{hex}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn addrv2_message_round_trip() -> Result<()> {
+        let bytes = hex::decode("61bc6649019902abab208d79627683fd4804010409090909208d")?;
+        let mut reader = bytes.as_slice();
+        let first = AddrV2Message::parse(&mut reader)?;
+        let second = AddrV2Message::parse(&mut reader)?;
+        assert!(reader.is_empty());
+
+        assert_eq!(
+            first,
+            AddrV2Message {
+                time: 0x4966bc61,
+                services: NODE_NETWORK,
+                addr: AddrV2::Unknown(153, vec![0xab, 0xab]),
+                port: 8333,
+            }
+        );
+        assert_eq!(
+            second.services,
+            NODE_NETWORK_LIMITED | NODE_WITNESS | NODE_COMPACT_FILTERS
+        );
+        assert_eq!(second.addr, AddrV2::Ipv4(Ipv4Addr::new(9, 9, 9, 9)));
+        assert_eq!([first.serialise(), second.serialise()].concat(), bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to encoding and decoding P2P messages
+pub enum P2pError {
+    #[error("message magic {0} does not belong to the network")]
+    WrongMagic(String),
+    #[error("invalid message command {0:?}")]
+    InvalidCommand(String),
+    #[error("payload of {0} bytes exceeds the maximum of {1}")]
+    OversizedPayload(u32, usize),
+    #[error("payload checksum mismatch")]
+    BadChecksum,
+    #[error("{0} message with {1} items exceeds the maximum of {2}")]
+    TooManyItems(&'static str, u64, usize),
+    #[error("{0} of {1} bytes exceeds the maximum of {2}")]
+    OversizedField(&'static str, u64, usize),
+    #[error("invalid address for network id {0}")]
+    InvalidAddress(u8),
+    #[error("header in a headers message has {0} transactions")]
+    HeaderWithTransactions(u64),
+    #[error("connected to self")]
+    SelfConnection,
+    #[error("peer protocol version {0} is older than the minimum of {1}")]
+    ObsoleteVersion(u32, u32),
+    #[error("peer misbehaviour score {0} reached the ban threshold")]
+    Banned(u32),
+    #[error("peer did not answer a ping within {0} seconds")]
+    PingTimeout(u64),
+    #[error("peer was disconnected")]
+    Disconnected,
+}
@@ -0,0 +1,578 @@
+use {
+    super::{
+        address::{AddrV2Message, NetAddress},
+        errors::P2pError,
+        payload::{
+            CFCheckpt, CFHeaders, CFilter, GetCFCheckpt, GetCFilters, GetHeadersMessage, Inventory,
+            Reject, SendCmpct, VersionMessage,
+        },
+    },
+    crate::{
+        block::{body::Block, header::BlockHeader},
+        filter::bloom::{BloomFilter, MAX_FILTERADD_SIZE},
+        network::Network,
+        transaction::tx::Transaction,
+        utils::{
+            encoding::{compact_size, read_array, read_bytes, read_compact_size, var_bytes},
+            errors::EncodingError,
+            hash::hash256,
+        },
+    },
+    anyhow::{bail, Result},
+    std::io::{Read, Write},
+};
+
+/// Protocol version spoken, the one introducing wtxid relay
+pub const PROTOCOL_VERSION: u32 = 70016;
+
+/// Largest payload a message may have
+pub const MAX_PAYLOAD_SIZE: usize = 4_000_000;
+
+/// Most entries an inv or getdata message may have
+pub const MAX_INV_SIZE: usize = 50_000;
+
+/// Most addresses an addr or addrv2 message may have
+pub const MAX_ADDR_TO_SEND: usize = 1000;
+
+/// Most headers a headers message may have
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+/// Length of the NUL padded command of a message
+const COMMAND_SIZE: usize = 12;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Message of the Bitcoin P2P protocol
+///
+/// On the wire a message is the magic of its network, its command, the length of its payload and
+/// the first 4 bytes of the hash256 of the payload, followed by the payload.
+pub enum Message {
+    Version(VersionMessage),
+    Verack,
+    Ping(u64),
+    Pong(u64),
+    GetAddr,
+    /// Addresses of nodes, with the time each was last seen
+    Addr(Vec<(u32, NetAddress)>),
+    AddrV2(Vec<AddrV2Message>),
+    /// Request to receive addrv2 rather than addr messages, as specified by BIP155
+    SendAddrV2,
+    Inv(Vec<Inventory>),
+    GetData(Vec<Inventory>),
+    GetHeaders(GetHeadersMessage),
+    Headers(Vec<BlockHeader>),
+    /// Request to announce new blocks with headers rather than inv messages, as specified by BIP130
+    SendHeaders,
+    Block(Block),
+    Tx(Transaction),
+    /// Lowest fee rate of transactions to announce, in satoshis per 1000 virtual bytes
+    FeeFilter(u64),
+    SendCmpct(SendCmpct),
+    /// Request to announce transactions by wtxid, as specified by BIP339
+    WtxidRelay,
+    Reject(Reject),
+    /// Request for compact block filters, as specified by BIP157
+    GetCFilters(GetCFilters),
+    CFilter(CFilter),
+    /// Request for compact block filter hashes, as specified by BIP157
+    GetCFHeaders(GetCFilters),
+    CFHeaders(CFHeaders),
+    /// Request for compact block filter checkpoints, as specified by BIP157
+    GetCFCheckpt(GetCFCheckpt),
+    CFCheckpt(CFCheckpt),
+    /// Bloom filter to relay only matching transactions with, as specified by BIP37
+    FilterLoad(BloomFilter),
+    /// Element to add to the loaded bloom filter
+    FilterAdd(Vec<u8>),
+    /// Request to remove the loaded bloom filter and relay all transactions again
+    FilterClear,
+    /// Message with a command this implementation does not know
+    Unknown {
+        command: String,
+        payload: Vec<u8>,
+    },
+}
+
+/// Read the CompactSize count of a list of items, refusing more than `max`
+fn read_count(reader: &mut impl Read, command: &'static str, max: usize) -> Result<u64> {
+    let count = read_compact_size(reader)?;
+    if count > max as u64 {
+        bail!(P2pError::TooManyItems(command, count, max));
+    }
+
+    Ok(count)
+}
+
+/// Items prefixed by their CompactSize count
+fn serialise_items<T>(items: &[T], serialise: impl Fn(&T) -> Vec<u8>) -> Vec<u8> {
+    let mut bytes = compact_size(items.len() as u64);
+    for item in items {
+        bytes.extend(serialise(item));
+    }
+
+    bytes
+}
+
+/// Parse a NUL padded command of printable ASCII characters
+fn parse_command(bytes: &[u8; COMMAND_SIZE]) -> Result<String> {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(COMMAND_SIZE);
+    let (command, padding) = bytes.split_at(len);
+    if !command.iter().all(u8::is_ascii_graphic) || padding.iter().any(|b| *b != 0) {
+        bail!(P2pError::InvalidCommand(
+            String::from_utf8_lossy(bytes).into_owned()
+        ));
+    }
+
+    Ok(String::from_utf8(command.to_vec())?)
+}
+
+impl Message {
+    /// Command identifying the type of the message
+    pub fn command(&self) -> &str {
+        match self {
+            Self::Version(_) => "version",
+            Self::Verack => "verack",
+            Self::Ping(_) => "ping",
+            Self::Pong(_) => "pong",
+            Self::GetAddr => "getaddr",
+            Self::Addr(_) => "addr",
+            Self::AddrV2(_) => "addrv2",
+            Self::SendAddrV2 => "sendaddrv2",
+            Self::Inv(_) => "inv",
+            Self::GetData(_) => "getdata",
+            Self::GetHeaders(_) => "getheaders",
+            Self::Headers(_) => "headers",
+            Self::SendHeaders => "sendheaders",
+            Self::Block(_) => "block",
+            Self::Tx(_) => "tx",
+            Self::FeeFilter(_) => "feefilter",
+            Self::SendCmpct(_) => "sendcmpct",
+            Self::WtxidRelay => "wtxidrelay",
+            Self::Reject(_) => "reject",
+            Self::GetCFilters(_) => "getcfilters",
+            Self::CFilter(_) => "cfilter",
+            Self::GetCFHeaders(_) => "getcfheaders",
+            Self::CFHeaders(_) => "cfheaders",
+            Self::GetCFCheckpt(_) => "getcfcheckpt",
+            Self::CFCheckpt(_) => "cfcheckpt",
+            Self::FilterLoad(_) => "filterload",
+            Self::FilterAdd(_) => "filteradd",
+            Self::FilterClear => "filterclear",
+            Self::Unknown { command, .. } => command,
+        }
+    }
+
+    /// Parse the payload of a message with `command`, which must span all of `payload`
+    pub fn parse_payload(command: &str, payload: &[u8]) -> Result<Self> {
+        let reader = &mut &payload[..];
+        let message = match command {
+            "version" => Self::Version(VersionMessage::parse(reader)?),
+            "verack" => Self::Verack,
+            "ping" => Self::Ping(u64::from_le_bytes(read_array(reader)?)),
+            "pong" => Self::Pong(u64::from_le_bytes(read_array(reader)?)),
+            "getaddr" => Self::GetAddr,
+            "addr" => {
+                let count = read_count(reader, "addr", MAX_ADDR_TO_SEND)?;
+                let addresses = (0..count)
+                    .map(|_| {
+                        Ok((
+                            u32::from_le_bytes(read_array(reader)?),
+                            NetAddress::parse(reader)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Self::Addr(addresses)
+            }
+            "addrv2" => {
+                let count = read_count(reader, "addrv2", MAX_ADDR_TO_SEND)?;
+                let addresses = (0..count)
+                    .map(|_| AddrV2Message::parse(reader))
+                    .collect::<Result<Vec<_>>>()?;
+                Self::AddrV2(addresses)
+            }
+            "sendaddrv2" => Self::SendAddrV2,
+            "inv" | "getdata" => {
+                let count = read_count(reader, "inv", MAX_INV_SIZE)?;
+                let inventory = (0..count)
+                    .map(|_| Inventory::parse(reader))
+                    .collect::<Result<Vec<_>>>()?;
+                match command {
+                    "inv" => Self::Inv(inventory),
+                    _ => Self::GetData(inventory),
+                }
+            }
+            "getheaders" => Self::GetHeaders(GetHeadersMessage::parse(reader)?),
+            "headers" => {
+                let count = read_count(reader, "headers", MAX_HEADERS_RESULTS)?;
+                let headers = (0..count)
+                    .map(|_| {
+                        let header = BlockHeader::parse(reader)?;
+                        // headers are sent as blocks without transactions
+                        match read_compact_size(reader)? {
+                            0 => Ok(header),
+                            n => bail!(P2pError::HeaderWithTransactions(n)),
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Self::Headers(headers)
+            }
+            "sendheaders" => Self::SendHeaders,
+            "block" => Self::Block(Block::parse(reader)?),
+            "tx" => Self::Tx(Transaction::parse(reader)?),
+            "feefilter" => Self::FeeFilter(u64::from_le_bytes(read_array(reader)?)),
+            "sendcmpct" => Self::SendCmpct(SendCmpct::parse(reader)?),
+            "wtxidrelay" => Self::WtxidRelay,
+            "reject" => Self::Reject(Reject::parse(reader)?),
+            "getcfilters" => Self::GetCFilters(GetCFilters::parse(reader)?),
+            "cfilter" => Self::CFilter(CFilter::parse(reader)?),
+            "getcfheaders" => Self::GetCFHeaders(GetCFilters::parse(reader)?),
+            "cfheaders" => Self::CFHeaders(CFHeaders::parse(reader)?),
+            "getcfcheckpt" => Self::GetCFCheckpt(GetCFCheckpt::parse(reader)?),
+            "cfcheckpt" => Self::CFCheckpt(CFCheckpt::parse(reader)?),
+            "filterload" => Self::FilterLoad(BloomFilter::parse(reader)?),
+            "filteradd" => {
+                let len = read_compact_size(reader)?;
+                if len > MAX_FILTERADD_SIZE as u64 {
+                    bail!(P2pError::OversizedField(
+                        "filteradd",
+                        len,
+                        MAX_FILTERADD_SIZE
+                    ));
+                }
+                Self::FilterAdd(read_bytes(reader, len)?)
+            }
+            "filterclear" => Self::FilterClear,
+            _ => {
+                let payload = reader.to_vec();
+                *reader = &[];
+                Self::Unknown {
+                    command: command.to_string(),
+                    payload,
+                }
+            }
+        };
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(message)
+    }
+
+    /// Serialised payload of the message
+    pub fn payload(&self) -> Vec<u8> {
+        match self {
+            Self::Version(version) => version.serialise(),
+            Self::Verack
+            | Self::GetAddr
+            | Self::SendAddrV2
+            | Self::SendHeaders
+            | Self::WtxidRelay
+            | Self::FilterClear => vec![],
+            Self::Ping(nonce) | Self::Pong(nonce) => nonce.to_le_bytes().to_vec(),
+            Self::Addr(addresses) => serialise_items(addresses, |(time, address)| {
+                [time.to_le_bytes().as_slice(), &address.serialise()].concat()
+            }),
+            Self::AddrV2(addresses) => serialise_items(addresses, AddrV2Message::serialise),
+            Self::Inv(inventory) | Self::GetData(inventory) => {
+                serialise_items(inventory, Inventory::serialise)
+            }
+            Self::GetHeaders(getheaders) => getheaders.serialise(),
+            Self::Headers(headers) => {
+                serialise_items(headers, |header| [header.serialise(), vec![0]].concat())
+            }
+            Self::Block(block) => block.serialise(),
+            Self::Tx(tx) => tx.serialise(),
+            Self::FeeFilter(fee_rate) => fee_rate.to_le_bytes().to_vec(),
+            Self::SendCmpct(sendcmpct) => sendcmpct.serialise(),
+            Self::Reject(reject) => reject.serialise(),
+            Self::GetCFilters(request) | Self::GetCFHeaders(request) => request.serialise(),
+            Self::CFilter(cfilter) => cfilter.serialise(),
+            Self::CFHeaders(cfheaders) => cfheaders.serialise(),
+            Self::GetCFCheckpt(request) => request.serialise(),
+            Self::CFCheckpt(cfcheckpt) => cfcheckpt.serialise(),
+            Self::FilterLoad(filter) => filter.serialise(),
+            Self::FilterAdd(data) => var_bytes(data),
+            Self::Unknown { payload, .. } => payload.clone(),
+        }
+    }
+
+    /// Read the command and payload of a message of `network`, refusing oversized payloads before
+    /// reading them
+    ///
+    /// The payload is not decoded, so a payload that fails to decode leaves the reader at the start
+    /// of the next message.
+    pub fn read_raw(reader: &mut impl Read, network: Network) -> Result<(String, Vec<u8>)> {
+        let magic: [u8; 4] = read_array(reader)?;
+        if magic != network.magic() {
+            bail!(P2pError::WrongMagic(hex::encode(magic)));
+        }
+        let command = parse_command(&read_array(reader)?)?;
+        let len = u32::from_le_bytes(read_array(reader)?);
+        if len as usize > MAX_PAYLOAD_SIZE {
+            bail!(P2pError::OversizedPayload(len, MAX_PAYLOAD_SIZE));
+        }
+        let checksum: [u8; 4] = read_array(reader)?;
+
+        let payload = read_bytes(reader, len as u64)?;
+        if hash256(&payload)[..4] != checksum {
+            bail!(P2pError::BadChecksum);
+        }
+
+        Ok((command, payload))
+    }
+
+    /// Read a message of `network`, refusing oversized payloads before reading them
+    pub fn read(reader: &mut impl Read, network: Network) -> Result<Self> {
+        let (command, payload) = Self::read_raw(reader, network)?;
+        Self::parse_payload(&command, &payload)
+    }
+
+    /// Serialise the message for `network`, with its envelope
+    ///
+    /// The command of an unknown message must be at most 12 printable ASCII characters.
+    pub fn serialise(&self, network: Network) -> Vec<u8> {
+        let payload = self.payload();
+        let mut command = [0; COMMAND_SIZE];
+        command[..self.command().len()].copy_from_slice(self.command().as_bytes());
+
+        [
+            network.magic().as_slice(),
+            &command,
+            &(payload.len() as u32).to_le_bytes(),
+            &hash256(&payload)[..4],
+            &payload,
+        ]
+        .concat()
+    }
+
+    /// Write the message for `network`, with its envelope
+    pub fn write(&self, writer: &mut impl Write, network: Network) -> Result<()> {
+        writer.write_all(&self.serialise(network))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            filter::bloom::BLOOM_UPDATE_ALL,
+            p2p::{
+                address::{AddrV2, NODE_BLOOM, NODE_NETWORK, NODE_NETWORK_LIMITED, NODE_WITNESS},
+                payload::{MSG_BLOCK, MSG_WTX, REJECT_INVALID},
+            },
+            script::raw::Script,
+            transaction::tx::{OutPoint, TxIn, TxOut},
+        },
+        std::net::{IpAddr, Ipv4Addr},
+    };
+
+    /// Version message of a Bitcoin Core 0.17.1 mainnet node
+    const VERSION: &str = "f9beb4d976657273696f6e000000000066000000be61b8277f1101000d04000000000000f00f4d5c00000000000000000000000000000000000000000000ffff5bf08c80b4bd0d04000000000000000000000000000000000000000000000000faa99559cc68a1c1102f5361746f7368693a302e31372e312f938c080001";
+
+    fn round_trip(message: Message) -> Result<()> {
+        let bytes = message.serialise(Network::Regtest);
+        let mut reader = bytes.as_slice();
+        assert_eq!(Message::read(&mut reader, Network::Regtest)?, message);
+        assert!(reader.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_message_vectors() -> Result<()> {
+        assert_eq!(
+            hex::encode(Message::Verack.serialise(Network::Mainnet)),
+            "f9beb4d976657261636b000000000000000000005df6e0e2"
+        );
+        let getaddr = hex::decode("f9beb4d9676574616464720000000000000000005df6e0e2")?;
+        assert_eq!(Message::GetAddr.serialise(Network::Mainnet), getaddr);
+        assert_eq!(
+            Message::read(&mut getaddr.as_slice(), Network::Mainnet)?,
+            Message::GetAddr
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ping_vector() {
+        assert_eq!(
+            hex::encode(Message::Ping(100).serialise(Network::Mainnet)),
+            "f9beb4d970696e670000000000000000080000002467f11d6400000000000000"
+        );
+    }
+
+    #[test]
+    fn reads_version_from_stream() -> Result<()> {
+        // a stream holds the start of the next message after the version message
+        let bytes = hex::decode(format!("{VERSION}f9be"))?;
+        let mut reader = bytes.as_slice();
+        let Message::Version(version) = Message::read(&mut reader, Network::Mainnet)? else {
+            panic!("expected a version message");
+        };
+        assert_eq!(reader, [0xf9, 0xbe]);
+
+        assert_eq!(version.version, 70015);
+        assert_eq!(
+            version.services,
+            NODE_NETWORK | NODE_BLOOM | NODE_WITNESS | NODE_NETWORK_LIMITED
+        );
+        assert_eq!(version.timestamp, 1548554224);
+        assert_eq!(
+            version.receiver.ip_addr(),
+            IpAddr::V4(Ipv4Addr::new(91, 240, 140, 128))
+        );
+        assert_eq!(version.nonce, 13952548347456104954);
+        assert_eq!(version.user_agent, "/Satoshi:0.17.1/");
+        assert_eq!(version.start_height, 560275);
+        assert!(version.relay);
+
+        let mut written = vec![];
+        Message::Version(version).write(&mut written, Network::Mainnet)?;
+        assert_eq!(hex::encode(written), VERSION);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_envelopes() -> Result<()> {
+        let bytes = hex::decode(VERSION)?;
+        assert!(Message::read(&mut bytes.as_slice(), Network::Testnet).is_err());
+        assert!(Message::read(&mut &bytes[..bytes.len() - 1], Network::Mainnet).is_err());
+
+        let mut corrupted = bytes.clone();
+        corrupted[40] ^= 1;
+        let err = Message::read(&mut corrupted.as_slice(), Network::Mainnet).unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::BadChecksum);
+
+        // bytes after the first NUL of the command must be NUL too
+        let mut command = bytes.clone();
+        command[12] = b'x';
+        assert!(Message::read(&mut command.as_slice(), Network::Mainnet).is_err());
+
+        // the payload length is refused before the payload is read
+        let mut oversized = bytes[..24].to_vec();
+        oversized[16..20].copy_from_slice(&4_000_001u32.to_le_bytes());
+        let err = Message::read(&mut oversized.as_slice(), Network::Mainnet).unwrap_err();
+        assert_eq!(
+            err.downcast::<P2pError>()?,
+            P2pError::OversizedPayload(4_000_001, MAX_PAYLOAD_SIZE)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn payloads_round_trip() -> Result<()> {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::new([7; 32], 1)));
+        tx.inputs[0].witness = vec![vec![1; 72], vec![2; 33]];
+        tx.outputs
+            .push(TxOut::new(50_000, Script::op_return(b"p2p")));
+        let header = Network::Regtest.params().genesis;
+
+        for message in [
+            Message::Pong(u64::MAX),
+            Message::SendAddrV2,
+            Message::SendHeaders,
+            Message::WtxidRelay,
+            Message::FeeFilter(1000),
+            Message::SendCmpct(SendCmpct {
+                announce: false,
+                version: 2,
+            }),
+            Message::Addr(vec![(
+                1700000000,
+                NetAddress::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 18444, NODE_NETWORK),
+            )]),
+            Message::AddrV2(vec![AddrV2Message {
+                time: 1700000000,
+                services: NODE_NETWORK | NODE_WITNESS,
+                addr: AddrV2::TorV3([9; 32]),
+                port: 8333,
+            }]),
+            Message::Inv(vec![Inventory::new(MSG_WTX, tx.wtxid())]),
+            Message::GetData(vec![Inventory::new(MSG_BLOCK, header.hash())]),
+            Message::GetHeaders(GetHeadersMessage {
+                version: PROTOCOL_VERSION,
+                locator: vec![header.hash()],
+                stop_hash: [0; 32],
+            }),
+            Message::Headers(vec![header; 3]),
+            Message::Tx(tx.clone()),
+            Message::Block(Block {
+                header,
+                transactions: vec![tx],
+            }),
+            Message::Reject(Reject {
+                message: "block".to_string(),
+                code: REJECT_INVALID,
+                reason: "bad-txnmrklroot".to_string(),
+                hash: Some(header.hash()),
+            }),
+            Message::GetCFilters(GetCFilters {
+                filter_type: 0,
+                start_height: 1,
+                stop_hash: header.hash(),
+            }),
+            Message::GetCFHeaders(GetCFilters {
+                filter_type: 0,
+                start_height: 0,
+                stop_hash: header.hash(),
+            }),
+            Message::CFilter(CFilter {
+                filter_type: 0,
+                block_hash: header.hash(),
+                filter: vec![0x01, 0x9d, 0xfc, 0xa8],
+            }),
+            Message::CFHeaders(CFHeaders {
+                filter_type: 0,
+                stop_hash: header.hash(),
+                previous_filter_header: [0; 32],
+                filter_hashes: vec![[4; 32], [5; 32]],
+            }),
+            Message::GetCFCheckpt(GetCFCheckpt {
+                filter_type: 0,
+                stop_hash: header.hash(),
+            }),
+            Message::CFCheckpt(CFCheckpt {
+                filter_type: 0,
+                stop_hash: header.hash(),
+                filter_headers: vec![[6; 32]],
+            }),
+            Message::FilterLoad(BloomFilter::new(10, 0.0001, 7, BLOOM_UPDATE_ALL)),
+            Message::FilterAdd(vec![8; 33]),
+            Message::FilterClear,
+            Message::Unknown {
+                command: "cmpctblock".to_string(),
+                payload: vec![1, 2, 3],
+            },
+        ] {
+            round_trip(message)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_oversized_lists() -> Result<()> {
+        let mut payload = compact_size(MAX_INV_SIZE as u64 + 1);
+        payload.extend(Inventory::new(MSG_BLOCK, [0; 32]).serialise());
+        let err = Message::parse_payload("inv", &payload).unwrap_err();
+        assert_eq!(
+            err.downcast::<P2pError>()?,
+            P2pError::TooManyItems("inv", MAX_INV_SIZE as u64 + 1, MAX_INV_SIZE)
+        );
+
+        let header = Network::Mainnet.params().genesis;
+        let payload = [vec![1], header.serialise(), vec![1]].concat();
+        assert!(Message::parse_payload("headers", &payload).is_err());
+
+        let payload = var_bytes(&[0; MAX_FILTERADD_SIZE + 1]);
+        let err = Message::parse_payload("filteradd", &payload).unwrap_err();
+        assert_eq!(
+            err.downcast::<P2pError>()?,
+            P2pError::OversizedField("filteradd", 521, MAX_FILTERADD_SIZE)
+        );
+
+        // empty messages must have empty payloads
+        assert!(Message::parse_payload("verack", &[0]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+pub mod address;
+pub mod errors;
+pub mod message;
+pub mod payload;
+pub mod peer;
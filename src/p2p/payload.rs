@@ -0,0 +1,562 @@
+use {
+    super::{
+        address::NetAddress,
+        errors::P2pError,
+        message::{MAX_PAYLOAD_SIZE, PROTOCOL_VERSION},
+    },
+    crate::{
+        block::header::BlockHash,
+        filter::basic::{filter_header, FilterHeader},
+        utils::encoding::{compact_size, read_array, read_compact_size, read_var_bytes, var_bytes},
+    },
+    anyhow::{bail, Result},
+    std::{
+        io::Read,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Inventory type of transactions
+pub const MSG_TX: u32 = 1;
+
+/// Inventory type of blocks
+pub const MSG_BLOCK: u32 = 2;
+
+/// Inventory type of BIP37 merkle blocks
+pub const MSG_FILTERED_BLOCK: u32 = 3;
+
+/// Inventory type of BIP152 compact blocks
+pub const MSG_CMPCT_BLOCK: u32 = 4;
+
+/// Inventory type of transactions announced by wtxid, as specified by BIP339
+pub const MSG_WTX: u32 = 5;
+
+/// Flag of inventory types requesting witness data
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+
+/// Reject code of messages that could not be decoded
+pub const REJECT_MALFORMED: u8 = 0x01;
+
+/// Reject code of invalid transactions and blocks
+pub const REJECT_INVALID: u8 = 0x10;
+
+/// Reject code of messages from an obsolete protocol version
+pub const REJECT_OBSOLETE: u8 = 0x11;
+
+/// Reject code of transactions and blocks that were already seen or conflict with them
+pub const REJECT_DUPLICATE: u8 = 0x12;
+
+/// Reject code of transactions that are valid but not relayed
+pub const REJECT_NONSTANDARD: u8 = 0x40;
+
+/// Reject code of transactions with an output below the dust threshold
+pub const REJECT_DUST: u8 = 0x41;
+
+/// Reject code of transactions paying too little fee
+pub const REJECT_INSUFFICIENTFEE: u8 = 0x42;
+
+/// Reject code of blocks that conflict with a checkpoint
+pub const REJECT_CHECKPOINT: u8 = 0x43;
+
+/// User agent this implementation announces in its version messages
+pub const USER_AGENT: &str = concat!(
+    "/",
+    env!("CARGO_PKG_NAME"),
+    ":",
+    env!("CARGO_PKG_VERSION"),
+    "/"
+);
+
+/// Longest user agent a version message may have
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Most hashes a block locator may have, far more than chains of any realistic height need
+const MAX_LOCATOR_SIZE: usize = 101;
+
+/// Most filter hashes a cfheaders message may have
+pub const MAX_CFHEADERS_SIZE: usize = 2000;
+
+/// Most filters a getcfilters message may request
+pub const MAX_GETCFILTERS_SIZE: usize = 1000;
+
+/// Blocks between the filter headers of cfcheckpt messages
+pub const CFCHECKPT_INTERVAL: u32 = 1000;
+
+/// Most filter headers a cfcheckpt message may have, as many as fit in a message
+const MAX_CFCHECKPT_SIZE: usize = MAX_PAYLOAD_SIZE / 32;
+
+/// Longest rejected message command or reason
+const MAX_REJECT_STRING_LENGTH: usize = 111;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of the version message a connection starts with
+pub struct VersionMessage {
+    /// Highest protocol version the node speaks
+    pub version: u32,
+    pub services: u64,
+    /// Time of the node, in seconds since the UNIX epoch
+    pub timestamp: i64,
+    /// Address of the node the message is sent to
+    pub receiver: NetAddress,
+    /// Address of the node sending the message, usually left empty
+    pub sender: NetAddress,
+    /// Random number detecting connections to self
+    pub nonce: u64,
+    pub user_agent: String,
+    /// Height of the best chain of the node
+    pub start_height: i32,
+    /// If the node wants transactions announced before it sends a bloom filter
+    pub relay: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Object announced by inv messages and requested by getdata messages
+pub struct Inventory {
+    /// Type of the object, one of the `MSG_*` constants
+    pub inv_type: u32,
+    pub hash: [u8; 32],
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of getheaders messages, requesting the headers after a locator
+pub struct GetHeadersMessage {
+    pub version: u32,
+    /// Hashes of blocks the node has, from its tip back to genesis with growing gaps
+    pub locator: Vec<BlockHash>,
+    /// Hash of the last header wanted, or zero for as many as possible
+    pub stop_hash: BlockHash,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of reject messages, explaining why a message was rejected
+pub struct Reject {
+    /// Command of the rejected message
+    pub message: String,
+    /// One of the `REJECT_*` constants
+    pub code: u8,
+    pub reason: String,
+    /// Hash of the rejected transaction or block
+    pub hash: Option<[u8; 32]>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Payload of sendcmpct messages, as specified by BIP152
+pub struct SendCmpct {
+    /// If new blocks should be announced with cmpctblock messages rather than inv or headers
+    pub announce: bool,
+    pub version: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Payload of getcfilters and getcfheaders messages, requesting the filters or filter headers of
+/// the blocks from `start_height` up to the one with `stop_hash`, as specified by BIP157
+pub struct GetCFilters {
+    pub filter_type: u8,
+    pub start_height: u32,
+    pub stop_hash: BlockHash,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of cfilter messages, holding the filter of a block
+pub struct CFilter {
+    pub filter_type: u8,
+    pub block_hash: BlockHash,
+    pub filter: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of cfheaders messages, holding the filter hashes of a range of blocks
+///
+/// The filter headers of the range follow from chaining the hashes to the previous filter header.
+pub struct CFHeaders {
+    pub filter_type: u8,
+    /// Hash of the last block of the range
+    pub stop_hash: BlockHash,
+    /// Filter header of the block before the range
+    pub previous_filter_header: [u8; 32],
+    pub filter_hashes: Vec<[u8; 32]>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Payload of getcfcheckpt messages, requesting the filter headers of every 1000th block up to the
+/// one with `stop_hash`
+pub struct GetCFCheckpt {
+    pub filter_type: u8,
+    pub stop_hash: BlockHash,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Payload of cfcheckpt messages, holding the filter headers of every 1000th block
+pub struct CFCheckpt {
+    pub filter_type: u8,
+    pub stop_hash: BlockHash,
+    pub filter_headers: Vec<[u8; 32]>,
+}
+
+/// Read hashes prefixed by their CompactSize count, refusing more than `max`
+fn read_hashes(reader: &mut impl Read, command: &'static str, max: usize) -> Result<Vec<[u8; 32]>> {
+    let count = read_compact_size(reader)?;
+    if count > max as u64 {
+        bail!(P2pError::TooManyItems(command, count, max));
+    }
+
+    (0..count).map(|_| read_array(reader)).collect()
+}
+
+/// Hashes prefixed by their CompactSize count
+fn serialise_hashes(hashes: &[[u8; 32]]) -> Vec<u8> {
+    let mut bytes = compact_size(hashes.len() as u64);
+    for hash in hashes {
+        bytes.extend(hash);
+    }
+
+    bytes
+}
+
+/// Read a string prefixed by its CompactSize length, refusing ones longer than `max`
+fn read_string(reader: &mut impl Read, field: &'static str, max: usize) -> Result<String> {
+    let len = read_compact_size(reader)?;
+    if len > max as u64 {
+        bail!(P2pError::OversizedField(field, len, max));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+impl VersionMessage {
+    /// Version message to a node at `receiver`, with the current time and a random nonce
+    pub fn new(receiver: NetAddress, services: u64, start_height: i32) -> Result<Self> {
+        let mut nonce = [0; 8];
+        getrandom::getrandom(&mut nonce)?;
+
+        Ok(Self {
+            version: PROTOCOL_VERSION,
+            services,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            receiver,
+            sender: NetAddress {
+                services,
+                ip: [0; 16],
+                port: 0,
+            },
+            nonce: u64::from_le_bytes(nonce),
+            user_agent: USER_AGENT.to_string(),
+            start_height,
+            relay: true,
+        })
+    }
+
+    /// Parse a version message payload, in which the relay flag is optional
+    pub fn parse(reader: &mut &[u8]) -> Result<Self> {
+        Ok(Self {
+            version: u32::from_le_bytes(read_array(reader)?),
+            services: u64::from_le_bytes(read_array(reader)?),
+            timestamp: i64::from_le_bytes(read_array(reader)?),
+            receiver: NetAddress::parse(reader)?,
+            sender: NetAddress::parse(reader)?,
+            nonce: u64::from_le_bytes(read_array(reader)?),
+            user_agent: read_string(reader, "user agent", MAX_USER_AGENT_LENGTH)?,
+            start_height: i32::from_le_bytes(read_array(reader)?),
+            relay: reader.is_empty() || read_array::<1>(reader)? != [0],
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.version.to_le_bytes().as_slice(),
+            &self.services.to_le_bytes(),
+            &self.timestamp.to_le_bytes(),
+            &self.receiver.serialise(),
+            &self.sender.serialise(),
+            &self.nonce.to_le_bytes(),
+            &var_bytes(self.user_agent.as_bytes()),
+            &self.start_height.to_le_bytes(),
+            &[self.relay as u8],
+        ]
+        .concat()
+    }
+}
+
+impl Inventory {
+    pub fn new(inv_type: u32, hash: [u8; 32]) -> Self {
+        Self { inv_type, hash }
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            inv_type: u32::from_le_bytes(read_array(reader)?),
+            hash: read_array(reader)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [self.inv_type.to_le_bytes().as_slice(), &self.hash].concat()
+    }
+}
+
+impl GetHeadersMessage {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let version = u32::from_le_bytes(read_array(reader)?);
+        let count = read_compact_size(reader)?;
+        if count > MAX_LOCATOR_SIZE as u64 {
+            bail!(P2pError::TooManyItems(
+                "getheaders",
+                count,
+                MAX_LOCATOR_SIZE
+            ));
+        }
+        let locator = (0..count)
+            .map(|_| read_array(reader))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            version,
+            locator,
+            stop_hash: read_array(reader)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.version.to_le_bytes().as_slice(),
+            &serialise_hashes(&self.locator),
+            &self.stop_hash,
+        ]
+        .concat()
+    }
+}
+
+impl Reject {
+    /// Parse a reject message payload, which has a hash when a transaction or block was rejected
+    pub fn parse(reader: &mut &[u8]) -> Result<Self> {
+        let message = read_string(reader, "rejected command", MAX_REJECT_STRING_LENGTH)?;
+        let [code] = read_array(reader)?;
+        let reason = read_string(reader, "reject reason", MAX_REJECT_STRING_LENGTH)?;
+        let hash = match reader.is_empty() {
+            true => None,
+            false => Some(read_array(reader)?),
+        };
+
+        Ok(Self {
+            message,
+            code,
+            reason,
+            hash,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = var_bytes(self.message.as_bytes());
+        bytes.push(self.code);
+        bytes.extend(var_bytes(self.reason.as_bytes()));
+        if let Some(hash) = &self.hash {
+            bytes.extend(hash);
+        }
+
+        bytes
+    }
+}
+
+impl SendCmpct {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [announce] = read_array(reader)?;
+        Ok(Self {
+            announce: announce != 0,
+            version: u64::from_le_bytes(read_array(reader)?),
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            [self.announce as u8].as_slice(),
+            &self.version.to_le_bytes(),
+        ]
+        .concat()
+    }
+}
+
+impl GetCFilters {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [filter_type] = read_array(reader)?;
+        Ok(Self {
+            filter_type,
+            start_height: u32::from_le_bytes(read_array(reader)?),
+            stop_hash: read_array(reader)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            [self.filter_type].as_slice(),
+            &self.start_height.to_le_bytes(),
+            &self.stop_hash,
+        ]
+        .concat()
+    }
+}
+
+impl CFilter {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [filter_type] = read_array(reader)?;
+        Ok(Self {
+            filter_type,
+            block_hash: read_array(reader)?,
+            filter: read_var_bytes(reader)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            [self.filter_type].as_slice(),
+            &self.block_hash,
+            &var_bytes(&self.filter),
+        ]
+        .concat()
+    }
+}
+
+impl CFHeaders {
+    /// Filter headers of the blocks of the range, chaining the filter hashes
+    pub fn filter_headers(&self) -> Vec<FilterHeader> {
+        self.filter_hashes
+            .iter()
+            .scan(self.previous_filter_header, |previous, hash| {
+                *previous = filter_header(hash, previous);
+                Some(*previous)
+            })
+            .collect()
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [filter_type] = read_array(reader)?;
+        Ok(Self {
+            filter_type,
+            stop_hash: read_array(reader)?,
+            previous_filter_header: read_array(reader)?,
+            filter_hashes: read_hashes(reader, "cfheaders", MAX_CFHEADERS_SIZE)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            [self.filter_type].as_slice(),
+            &self.stop_hash,
+            &self.previous_filter_header,
+            &serialise_hashes(&self.filter_hashes),
+        ]
+        .concat()
+    }
+}
+
+impl GetCFCheckpt {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [filter_type] = read_array(reader)?;
+        Ok(Self {
+            filter_type,
+            stop_hash: read_array(reader)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [[self.filter_type].as_slice(), &self.stop_hash].concat()
+    }
+}
+
+impl CFCheckpt {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let [filter_type] = read_array(reader)?;
+        Ok(Self {
+            filter_type,
+            stop_hash: read_array(reader)?,
+            filter_headers: read_hashes(reader, "cfcheckpt", MAX_CFCHECKPT_SIZE)?,
+        })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            [self.filter_type].as_slice(),
+            &self.stop_hash,
+            &serialise_hashes(&self.filter_headers),
+        ]
+        .concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    #[test]
+    fn version_message_vector() -> Result<()> {
+        // sent by a Bitcoin Core 0.9.99 node in May 2014
+        let bytes = hex::decode("721101000100000000000000e6e0845300000000010000000000000000000000000000000000ffff0000000000000100000000000000fd87d87eeb4364f22cf54dca59412db7208d47d920cffce83ee8102f5361746f7368693a302e392e39392f2c9f040001")?;
+        let version = VersionMessage::parse(&mut bytes.as_slice())?;
+        assert_eq!(version.version, 70002);
+        assert_eq!(version.services, 1);
+        assert_eq!(version.timestamp, 1401217254);
+        assert_eq!(version.nonce, 16735069437859780935);
+        assert_eq!(version.user_agent, "/Satoshi:0.9.99/");
+        assert_eq!(version.start_height, 302892);
+        assert!(version.relay);
+        assert_eq!(version.serialise(), bytes);
+
+        // the relay flag is optional
+        let version = VersionMessage::parse(&mut &bytes[..bytes.len() - 1])?;
+        assert!(version.relay);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_vectors() -> Result<()> {
+        let bytes = hex::decode("027478121474786e2d6d656d706f6f6c2d636f6e666c69637405df54d3860b3c41806a3546ab48279300affacf4b88591b229141dcf2f47004")?;
+        let reject = Reject::parse(&mut bytes.as_slice())?;
+        assert_eq!(reject.message, "tx");
+        assert_eq!(reject.code, REJECT_DUPLICATE);
+        assert_eq!(reject.reason, "txn-mempool-conflict");
+        assert_eq!(
+            reversed_hex(&reject.hash.unwrap()),
+            "0470f4f2dc4191221b59884bcffaaf00932748ab46356a80413c0b86d354df05"
+        );
+        assert_eq!(reject.serialise(), bytes);
+
+        let bytes = hex::decode("02747840096e6f6e2d66696e616c259bbe6c83db8bbdfca7ca303b19413dc245d9f2371b344ede5f8b1339a5460b")?;
+        let reject = Reject::parse(&mut bytes.as_slice())?;
+        assert_eq!(reject.code, REJECT_NONSTANDARD);
+        assert_eq!(reject.reason, "non-final");
+        assert_eq!(reject.serialise(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn getheaders_vector() -> Result<()> {
+        let bytes = hex::decode("72110100014a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b0000000000000000000000000000000000000000000000000000000000000000")?;
+        let getheaders = GetHeadersMessage::parse(&mut bytes.as_slice())?;
+        assert_eq!(getheaders.version, 70002);
+        assert_eq!(getheaders.locator.len(), 1);
+        assert_eq!(
+            hex::encode(getheaders.locator[0]),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(getheaders.stop_hash, [0; 32]);
+        assert_eq!(getheaders.serialise(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn cfheaders_chain_filter_hashes() {
+        let hashes = [[1; 32], [2; 32]];
+        let cfheaders = CFHeaders {
+            filter_type: 0,
+            stop_hash: [9; 32],
+            previous_filter_header: [0; 32],
+            filter_hashes: hashes.to_vec(),
+        };
+        let first = filter_header(&hashes[0], &[0; 32]);
+        assert_eq!(
+            cfheaders.filter_headers(),
+            [first, filter_header(&hashes[1], &first)]
+        );
+    }
+}
@@ -0,0 +1,457 @@
+use {
+    super::{errors::P2pError, message::Message, payload::VersionMessage},
+    crate::network::Network,
+    anyhow::{bail, Result},
+    std::{
+        collections::VecDeque,
+        io::{Read, Write},
+        time::{Duration, Instant},
+    },
+};
+
+/// Oldest protocol version of peers, the first with the relay flag of BIP37
+pub const MIN_PROTOCOL_VERSION: u32 = 70001;
+
+/// Protocol version that introduced sendheaders, as specified by BIP130
+pub const SENDHEADERS_VERSION: u32 = 70012;
+
+/// Protocol version that introduced wtxidrelay, as specified by BIP339
+pub const WTXID_RELAY_VERSION: u32 = 70016;
+
+/// Misbehaviour score at which a peer is disconnected
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Time between pings keeping a connection alive
+pub const PING_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+/// Time a peer has to answer a ping
+pub const PING_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// Features negotiated with a peer
+pub struct Features {
+    /// If transactions are announced by wtxid
+    pub wtxid_relay: bool,
+    /// If addresses are sent in addrv2 rather than addr messages
+    pub addrv2: bool,
+    /// If the peer wants new blocks announced with headers rather than inv messages
+    pub send_headers: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Event of a connection to a peer
+pub enum PeerEvent {
+    /// The version handshake completed
+    Connected,
+    /// The peer answered the last ping, after the round trip time
+    Pong(Duration),
+    /// The peer misbehaved, raising its score to `score`
+    Misbehaved { score: u32, reason: String },
+    /// Message that is not part of the connection management
+    Message(Message),
+}
+
+/// Connection to a Bitcoin node over a stream such as a TCP connection
+///
+/// The peer handles the version handshake, feature negotiation and pings itself, and hands
+/// everything else to the caller as events.
+pub struct Peer<S: Read + Write> {
+    stream: S,
+    network: Network,
+    local_version: VersionMessage,
+    remote_version: Option<VersionMessage>,
+    verack_received: bool,
+    features: Features,
+    misbehaviour: u32,
+    /// If the caller stopped talking to the peer
+    disconnected: bool,
+    /// Nonce and time of the ping last sent, until it is answered
+    pending_ping: Option<(u64, Instant)>,
+    last_ping: Option<Instant>,
+    events: VecDeque<PeerEvent>,
+}
+
+impl<S: Read + Write> Peer<S> {
+    /// Peer on the other end of `stream`, which will be sent `local_version`
+    pub fn new(stream: S, network: Network, local_version: VersionMessage) -> Self {
+        Self {
+            stream,
+            network,
+            local_version,
+            remote_version: None,
+            verack_received: false,
+            features: Features::default(),
+            misbehaviour: 0,
+            disconnected: false,
+            pending_ping: None,
+            last_ping: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Version message the peer sent
+    pub fn remote_version(&self) -> Option<&VersionMessage> {
+        self.remote_version.as_ref()
+    }
+
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    pub fn misbehaviour(&self) -> u32 {
+        self.misbehaviour
+    }
+
+    /// If the version handshake completed and the peer was not disconnected since
+    pub fn is_connected(&self) -> bool {
+        self.verack_received && !self.disconnected
+    }
+
+    /// Stop talking to the peer, failing every later send and read
+    pub fn disconnect(&mut self) {
+        self.disconnected = true;
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<()> {
+        if self.disconnected {
+            bail!(P2pError::Disconnected);
+        }
+        message.write(&mut self.stream, self.network)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Send the version message and wait for the handshake to complete
+    ///
+    /// Events received before the handshake completes are kept for `next_event`.
+    pub fn handshake(&mut self) -> Result<()> {
+        self.send(&Message::Version(self.local_version.clone()))?;
+
+        let mut early = vec![];
+        loop {
+            match self.next_event()? {
+                PeerEvent::Connected => break,
+                event => early.push(event),
+            }
+        }
+        for event in early.into_iter().rev() {
+            self.events.push_front(event);
+        }
+
+        Ok(())
+    }
+
+    /// Next event of the connection, reading messages until one produces an event
+    pub fn next_event(&mut self) -> Result<PeerEvent> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            if self.disconnected {
+                bail!(P2pError::Disconnected);
+            }
+
+            let (command, payload) = Message::read_raw(&mut self.stream, self.network)?;
+            match Message::parse_payload(&command, &payload) {
+                Ok(message) => self.handle(message)?,
+                Err(err) => {
+                    // lists over their limits are tolerated a few times, other garbage is not
+                    let score = match err.downcast_ref::<P2pError>() {
+                        Some(P2pError::TooManyItems(..)) => 20,
+                        _ => BAN_THRESHOLD,
+                    };
+                    self.misbehaving(score, format!("invalid {command} message: {err}"))?;
+                }
+            }
+        }
+    }
+
+    /// Raise the misbehaviour score of the peer, failing once it reaches the ban threshold
+    pub fn misbehaving(&mut self, score: u32, reason: String) -> Result<()> {
+        self.misbehaviour = self.misbehaviour.saturating_add(score);
+        if self.misbehaviour >= BAN_THRESHOLD {
+            bail!(P2pError::Banned(self.misbehaviour));
+        }
+        self.events.push_back(PeerEvent::Misbehaved {
+            score: self.misbehaviour,
+            reason,
+        });
+
+        Ok(())
+    }
+
+    /// Send a ping with a random nonce, whose pong is reported as an event
+    pub fn ping(&mut self) -> Result<()> {
+        let mut nonce = [0; 8];
+        getrandom::getrandom(&mut nonce)?;
+        let nonce = u64::from_le_bytes(nonce);
+
+        self.send(&Message::Ping(nonce))?;
+        let now = Instant::now();
+        self.pending_ping = Some((nonce, now));
+        self.last_ping = Some(now);
+        Ok(())
+    }
+
+    /// Ping the peer if it is time to, failing if the last ping went unanswered for too long
+    pub fn keepalive(&mut self, now: Instant) -> Result<()> {
+        if let Some((_, sent)) = self.pending_ping {
+            if now.saturating_duration_since(sent) > PING_TIMEOUT {
+                bail!(P2pError::PingTimeout(PING_TIMEOUT.as_secs()));
+            }
+            return Ok(());
+        }
+
+        match self.last_ping {
+            Some(last) if now.saturating_duration_since(last) < PING_INTERVAL => Ok(()),
+            _ => self.ping(),
+        }
+    }
+
+    fn handle(&mut self, message: Message) -> Result<()> {
+        let Some(remote_version) = &self.remote_version else {
+            let Message::Version(version) = message else {
+                return self.misbehaving(1, "message before version".to_string());
+            };
+            return self.accept_version(version);
+        };
+        let remote_version = remote_version.version;
+
+        match message {
+            Message::Version(_) => self.misbehaving(1, "duplicate version".to_string())?,
+            // feature negotiation must happen before verack
+            Message::WtxidRelay | Message::SendAddrV2 if self.verack_received => self.misbehaving(
+                BAN_THRESHOLD,
+                "feature negotiation after verack".to_string(),
+            )?,
+            Message::WtxidRelay => {
+                self.features.wtxid_relay = remote_version >= WTXID_RELAY_VERSION;
+            }
+            Message::SendAddrV2 => self.features.addrv2 = true,
+            Message::Verack if !self.verack_received => {
+                self.verack_received = true;
+                if remote_version >= SENDHEADERS_VERSION {
+                    self.send(&Message::SendHeaders)?;
+                }
+                self.events.push_back(PeerEvent::Connected);
+            }
+            // anything else is ignored until the handshake completes
+            _ if !self.verack_received => {}
+            Message::Verack => {}
+            Message::SendHeaders => self.features.send_headers = true,
+            Message::Ping(nonce) => self.send(&Message::Pong(nonce))?,
+            Message::Pong(nonce) => {
+                // pongs that do not answer the pending ping are ignored
+                if let Some((_, sent)) = self.pending_ping.filter(|(ping, _)| *ping == nonce) {
+                    self.pending_ping = None;
+                    self.events.push_back(PeerEvent::Pong(sent.elapsed()));
+                }
+            }
+            message => self.events.push_back(PeerEvent::Message(message)),
+        }
+
+        Ok(())
+    }
+
+    /// Answer the version message of the peer, negotiating features before verack
+    fn accept_version(&mut self, version: VersionMessage) -> Result<()> {
+        if version.nonce == self.local_version.nonce {
+            bail!(P2pError::SelfConnection);
+        }
+        if version.version < MIN_PROTOCOL_VERSION {
+            bail!(P2pError::ObsoleteVersion(
+                version.version,
+                MIN_PROTOCOL_VERSION
+            ));
+        }
+
+        if version.version >= WTXID_RELAY_VERSION {
+            self.send(&Message::WtxidRelay)?;
+        }
+        self.send(&Message::SendAddrV2)?;
+        self.send(&Message::Verack)?;
+        self.remote_version = Some(version);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::p2p::{
+            address::{NetAddress, NODE_NETWORK, NODE_WITNESS},
+            message::{MAX_INV_SIZE, PROTOCOL_VERSION},
+            payload::{Inventory, MSG_BLOCK},
+        },
+        crate::utils::encoding::compact_size,
+        std::{
+            net::{IpAddr, Ipv4Addr, TcpListener, TcpStream},
+            thread::{self, JoinHandle},
+        },
+    };
+
+    const NETWORK: Network = Network::Regtest;
+
+    fn version(protocol_version: u32) -> Result<VersionMessage> {
+        let receiver = NetAddress::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 18444, 0);
+        let mut version = VersionMessage::new(receiver, NODE_NETWORK | NODE_WITNESS, 0)?;
+        version.version = protocol_version;
+        Ok(version)
+    }
+
+    /// Peer connected over TCP to a fake node running `script` in another thread
+    fn fake_peer(
+        script: impl FnOnce(&mut TcpStream) -> Result<()> + Send + 'static,
+    ) -> Result<(Peer<TcpStream>, JoinHandle<Result<()>>)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let node = thread::spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            script(&mut stream)
+        });
+
+        Ok((Peer::new(stream, NETWORK, version(PROTOCOL_VERSION)?), node))
+    }
+
+    fn read(stream: &mut TcpStream) -> Result<Message> {
+        Message::read(stream, NETWORK)
+    }
+
+    /// Answer the handshake of a peer the way Bitcoin Core does
+    fn accept_handshake(stream: &mut TcpStream, protocol_version: u32) -> Result<()> {
+        assert!(matches!(read(stream)?, Message::Version(_)));
+        Message::Version(version(protocol_version)?).write(stream, NETWORK)?;
+        if protocol_version >= WTXID_RELAY_VERSION {
+            Message::WtxidRelay.write(stream, NETWORK)?;
+        }
+        Message::SendAddrV2.write(stream, NETWORK)?;
+        Message::Verack.write(stream, NETWORK)?;
+
+        if protocol_version >= WTXID_RELAY_VERSION {
+            assert_eq!(read(stream)?, Message::WtxidRelay);
+        }
+        assert_eq!(read(stream)?, Message::SendAddrV2);
+        assert_eq!(read(stream)?, Message::Verack);
+        assert_eq!(read(stream)?, Message::SendHeaders);
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_negotiates_features_and_answers_pings() -> Result<()> {
+        let inv = Message::Inv(vec![Inventory::new(MSG_BLOCK, [1; 32])]);
+        let expected = inv.clone();
+        let (mut peer, node) = fake_peer(move |stream| {
+            accept_handshake(stream, PROTOCOL_VERSION)?;
+            Message::SendHeaders.write(stream, NETWORK)?;
+            Message::Ping(7).write(stream, NETWORK)?;
+            inv.write(stream, NETWORK)?;
+            assert_eq!(read(stream)?, Message::Pong(7));
+
+            let Message::Ping(nonce) = read(stream)? else {
+                panic!("expected a ping");
+            };
+            Message::Pong(nonce + 1).write(stream, NETWORK)?;
+            Message::Pong(nonce).write(stream, NETWORK)?;
+            Ok(())
+        })?;
+
+        peer.handshake()?;
+        assert!(peer.is_connected());
+        assert_eq!(peer.remote_version().unwrap().version, PROTOCOL_VERSION);
+        assert_eq!(peer.next_event()?, PeerEvent::Message(expected));
+        assert_eq!(
+            peer.features(),
+            Features {
+                wtxid_relay: true,
+                addrv2: true,
+                send_headers: true,
+            }
+        );
+
+        peer.ping()?;
+        assert!(matches!(peer.next_event()?, PeerEvent::Pong(_)));
+
+        peer.disconnect();
+        assert!(!peer.is_connected());
+        let err = peer.send(&Message::Verack).unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::Disconnected);
+        node.join().unwrap()
+    }
+
+    #[test]
+    fn older_peers_do_not_negotiate_wtxid_relay() -> Result<()> {
+        let (mut peer, node) = fake_peer(|stream| accept_handshake(stream, 70015))?;
+        peer.handshake()?;
+        assert!(!peer.features().wtxid_relay);
+        assert!(peer.features().addrv2);
+        node.join().unwrap()
+    }
+
+    #[test]
+    fn refuses_self_connections() -> Result<()> {
+        let local = version(PROTOCOL_VERSION)?;
+        let remote = local.clone();
+        let (mut peer, node) = fake_peer(move |stream| {
+            read(stream)?;
+            Message::Version(remote).write(stream, NETWORK)
+        })?;
+        peer.local_version = local;
+
+        let err = peer.handshake().unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::SelfConnection);
+        node.join().unwrap()
+    }
+
+    #[test]
+    fn bans_misbehaving_peers() -> Result<()> {
+        let (mut peer, node) = fake_peer(|stream| {
+            accept_handshake(stream, PROTOCOL_VERSION)?;
+            // inv messages with one entry more than allowed
+            let mut payload = compact_size(MAX_INV_SIZE as u64 + 1);
+            payload.extend(Inventory::new(MSG_BLOCK, [0; 32]).serialise());
+            let oversized = Message::Unknown {
+                command: "inv".to_string(),
+                payload,
+            };
+            for _ in 0..5 {
+                oversized.write(stream, NETWORK)?;
+            }
+            Ok(())
+        })?;
+
+        peer.handshake()?;
+        for score in [20, 40, 60, 80] {
+            let PeerEvent::Misbehaved { score: total, .. } = peer.next_event()? else {
+                panic!("expected misbehaviour");
+            };
+            assert_eq!(total, score);
+        }
+        let err = peer.next_event().unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::Banned(100));
+        node.join().unwrap()
+    }
+
+    #[test]
+    fn keepalive_times_out_unanswered_pings() -> Result<()> {
+        let (mut peer, node) = fake_peer(|stream| {
+            accept_handshake(stream, PROTOCOL_VERSION)?;
+            assert!(matches!(read(stream)?, Message::Ping(_)));
+            Ok(())
+        })?;
+        peer.handshake()?;
+
+        let now = Instant::now();
+        peer.keepalive(now)?;
+        peer.keepalive(now + PING_INTERVAL)?;
+        let err = peer
+            .keepalive(now + PING_TIMEOUT + Duration::from_secs(1))
+            .unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::PingTimeout(1200));
+        node.join().unwrap()
+    }
+}
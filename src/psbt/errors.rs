@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Partially Signed Bitcoin Transactions
+pub enum PsbtError {
+    #[error("invalid PSBT magic bytes")]
+    InvalidMagic,
+    #[error("duplicate key `{0}`")]
+    DuplicateKey(String),
+    #[error("invalid key data for key `{0}`")]
+    InvalidKey(String),
+    #[error("invalid value for key `{0}`")]
+    InvalidValue(String),
+    #[error("missing required field `{0}`")]
+    MissingField(&'static str),
+    #[error("field `{0}` not allowed in a version {1} PSBT")]
+    UnexpectedField(&'static str, u32),
+    #[error("unsupported PSBT version {0}")]
+    UnsupportedVersion(u32),
+    #[error("unsigned transaction input {0} has a script sig or witness")]
+    UnsignedTxHasScripts(usize),
+    #[error("input {0} has no UTXO")]
+    MissingUtxo(usize),
+    #[error("UTXO of input {0} does not match its previous output")]
+    UtxoMismatch(usize),
+    #[error("redeem or witness script of input {0} does not match its UTXO")]
+    ScriptMismatch(usize),
+    #[error("input {0} cannot be finalised")]
+    CannotFinalise(usize),
+    #[error("input {0} is not finalised")]
+    NotFinalised(usize),
+    #[error("PSBTs spend different transactions")]
+    TransactionMismatch,
+    #[error("inputs require both a height and a time based lock time")]
+    LockTimeConflict,
+    #[error("PSBT does not allow adding {0}")]
+    NotModifiable(&'static str),
+}
@@ -0,0 +1,460 @@
+use {
+    super::{
+        errors::PsbtError,
+        map::{
+            is_control_block, is_ecdsa_signature, is_schnorr_signature, merge, read_key_source,
+            read_map, read_tap_key_origin, read_x_only, serialise_key_source,
+            serialise_tap_key_origin, write_pair, write_unknown_and_close, Pair,
+        },
+        partial::{KeySource, TapKeyOrigin},
+    },
+    crate::{
+        script::raw::Script,
+        transaction::tx::{
+            read_witness, serialise_witness, OutPoint, Transaction, TxOut, LOCKTIME_THRESHOLD,
+        },
+        utils::{
+            encoding::read_array,
+            hash::{hash160, hash256, ripemd160, sha256},
+        },
+    },
+    anyhow::{bail, Result},
+    std::{collections::BTreeMap, mem::take},
+};
+
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const PSBT_IN_RIPEMD160: u8 = 0x0a;
+const PSBT_IN_SHA256: u8 = 0x0b;
+const PSBT_IN_HASH160: u8 = 0x0c;
+const PSBT_IN_HASH256: u8 = 0x0d;
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// Input map of a PSBT
+///
+/// Public keys are keyed by their SEC or x-only serialisation, and signatures are stored with
+/// their sighash byte as they appear on the stack
+pub struct Input {
+    /// Output being spent, taken from the unsigned transaction in version 0
+    pub previous_output: OutPoint,
+    /// Sequence of the input, final when `None`
+    pub sequence: Option<u32>,
+    /// Unix timestamp the transaction lock time must be at least, version 2 only
+    pub required_time_lock_time: Option<u32>,
+    /// Block height the transaction lock time must be at least, version 2 only
+    pub required_height_lock_time: Option<u32>,
+    /// Full transaction being spent from
+    pub non_witness_utxo: Option<Transaction>,
+    /// Output being spent, for segwit inputs
+    pub witness_utxo: Option<TxOut>,
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub final_script_sig: Option<Script>,
+    /// Final witness stack, bottom element first
+    pub final_script_witness: Option<Vec<Vec<u8>>>,
+    pub ripemd160_preimages: BTreeMap<[u8; 20], Vec<u8>>,
+    pub sha256_preimages: BTreeMap<[u8; 32], Vec<u8>>,
+    pub hash160_preimages: BTreeMap<[u8; 20], Vec<u8>>,
+    pub hash256_preimages: BTreeMap<[u8; 32], Vec<u8>>,
+    /// Signature for a taproot key path spend
+    pub tap_key_sig: Option<Vec<u8>>,
+    /// Signatures for taproot script path spends, keyed by x-only key and leaf hash
+    pub tap_script_sigs: BTreeMap<([u8; 32], [u8; 32]), Vec<u8>>,
+    /// Leaf scripts and their leaf versions, keyed by control block
+    pub tap_scripts: BTreeMap<Vec<u8>, (Script, u8)>,
+    /// Leaf hashes each x-only key is used in, with its key source
+    pub tap_key_origins: BTreeMap<[u8; 32], TapKeyOrigin>,
+    pub tap_internal_key: Option<[u8; 32]>,
+    pub tap_merkle_root: Option<[u8; 32]>,
+    /// Unknown and proprietary pairs, keyed by their full key
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// If a pair with a type only defined for version 2 is that field, rather than an unknown pair of
+/// an older PSBT whose key carries data
+pub(crate) fn is_version_2_key(pair: &Pair, version: u32) -> bool {
+    version >= 2 || pair.key_data.is_empty()
+}
+
+/// Fail if a field only defined for version 2 appears in an older PSBT
+fn check_version_2(version: u32, field: &'static str) -> Result<()> {
+    if version < 2 {
+        bail!(PsbtError::UnexpectedField(field, version));
+    }
+
+    Ok(())
+}
+
+/// Value of a hash preimage pair, checked against the hash in its key
+fn preimage<const N: usize>(pair: &Pair, hash: fn(&[u8]) -> [u8; N]) -> Result<([u8; N], Vec<u8>)> {
+    let key = pair.key_array()?;
+    if hash(&pair.value) != key {
+        return Err(pair.invalid_value());
+    }
+
+    Ok((key, pair.value.clone()))
+}
+
+impl Input {
+    /// Input spending `previous_output`, as added to version 2 PSBTs
+    pub fn new(previous_output: OutPoint) -> Self {
+        Self {
+            previous_output,
+            ..Default::default()
+        }
+    }
+
+    /// If the final script sig or witness is set
+    pub fn is_finalised(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    /// Parse a serialised input map, up to and including its separator
+    pub(crate) fn parse(reader: &mut &[u8], version: u32) -> Result<Self> {
+        let mut input = Self::default();
+        let (mut txid, mut vout) = (None, None);
+
+        for pair in read_map(reader)? {
+            match pair.standard_type() {
+                Some(PSBT_IN_NON_WITNESS_UTXO) => {
+                    pair.expect_empty_key()?;
+                    input.non_witness_utxo = Some(pair.parse_value(Transaction::parse)?);
+                }
+                Some(PSBT_IN_WITNESS_UTXO) => {
+                    pair.expect_empty_key()?;
+                    input.witness_utxo = Some(pair.parse_value(TxOut::parse)?);
+                }
+                Some(PSBT_IN_PARTIAL_SIG) => {
+                    let key = pair.sec_key()?;
+                    if !is_ecdsa_signature(&pair.value) {
+                        return Err(pair.invalid_value());
+                    }
+                    input.partial_sigs.insert(key, pair.value);
+                }
+                Some(PSBT_IN_SIGHASH_TYPE) => {
+                    pair.expect_empty_key()?;
+                    input.sighash_type = Some(u32::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_IN_REDEEM_SCRIPT) => {
+                    pair.expect_empty_key()?;
+                    input.redeem_script = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_IN_WITNESS_SCRIPT) => {
+                    pair.expect_empty_key()?;
+                    input.witness_script = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_IN_BIP32_DERIVATION) => {
+                    let key = pair.sec_key()?;
+                    let source = pair.parse_value(read_key_source)?;
+                    input.bip32_derivation.insert(key, source);
+                }
+                Some(PSBT_IN_FINAL_SCRIPTSIG) => {
+                    pair.expect_empty_key()?;
+                    input.final_script_sig = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_IN_FINAL_SCRIPTWITNESS) => {
+                    pair.expect_empty_key()?;
+                    input.final_script_witness = Some(pair.parse_value(read_witness)?);
+                }
+                Some(PSBT_IN_RIPEMD160) => {
+                    let (hash, value) = preimage(&pair, ripemd160)?;
+                    input.ripemd160_preimages.insert(hash, value);
+                }
+                Some(PSBT_IN_SHA256) => {
+                    let (hash, value) = preimage(&pair, sha256)?;
+                    input.sha256_preimages.insert(hash, value);
+                }
+                Some(PSBT_IN_HASH160) => {
+                    let (hash, value) = preimage(&pair, hash160)?;
+                    input.hash160_preimages.insert(hash, value);
+                }
+                Some(PSBT_IN_HASH256) => {
+                    let (hash, value) = preimage(&pair, hash256)?;
+                    input.hash256_preimages.insert(hash, value);
+                }
+                // version 0 predates these types, so keys with data there are unknown pairs
+                Some(PSBT_IN_PREVIOUS_TXID) if is_version_2_key(&pair, version) => {
+                    check_version_2(version, "PSBT_IN_PREVIOUS_TXID")?;
+                    pair.expect_empty_key()?;
+                    txid = Some(pair.parse_value(read_array)?);
+                }
+                Some(PSBT_IN_OUTPUT_INDEX) if is_version_2_key(&pair, version) => {
+                    check_version_2(version, "PSBT_IN_OUTPUT_INDEX")?;
+                    pair.expect_empty_key()?;
+                    vout = Some(u32::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_IN_SEQUENCE) if is_version_2_key(&pair, version) => {
+                    check_version_2(version, "PSBT_IN_SEQUENCE")?;
+                    pair.expect_empty_key()?;
+                    input.sequence = Some(u32::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_IN_REQUIRED_TIME_LOCKTIME) if is_version_2_key(&pair, version) => {
+                    check_version_2(version, "PSBT_IN_REQUIRED_TIME_LOCKTIME")?;
+                    pair.expect_empty_key()?;
+                    let lock_time = u32::from_le_bytes(pair.parse_value(read_array)?);
+                    if lock_time < LOCKTIME_THRESHOLD {
+                        return Err(pair.invalid_value());
+                    }
+                    input.required_time_lock_time = Some(lock_time);
+                }
+                Some(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME) if is_version_2_key(&pair, version) => {
+                    check_version_2(version, "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME")?;
+                    pair.expect_empty_key()?;
+                    let lock_time = u32::from_le_bytes(pair.parse_value(read_array)?);
+                    if lock_time == 0 || lock_time >= LOCKTIME_THRESHOLD {
+                        return Err(pair.invalid_value());
+                    }
+                    input.required_height_lock_time = Some(lock_time);
+                }
+                Some(PSBT_IN_TAP_KEY_SIG) => {
+                    pair.expect_empty_key()?;
+                    if !is_schnorr_signature(&pair.value) {
+                        return Err(pair.invalid_value());
+                    }
+                    input.tap_key_sig = Some(pair.value);
+                }
+                Some(PSBT_IN_TAP_SCRIPT_SIG) => {
+                    let key: [u8; 64] = pair.key_array()?;
+                    let (x_only, leaf_hash) = key.split_at(32);
+                    if read_x_only(&mut &x_only[..]).is_err() {
+                        return Err(pair.invalid_key());
+                    }
+                    if !is_schnorr_signature(&pair.value) {
+                        return Err(pair.invalid_value());
+                    }
+                    input
+                        .tap_script_sigs
+                        .insert((x_only.try_into()?, leaf_hash.try_into()?), pair.value);
+                }
+                Some(PSBT_IN_TAP_LEAF_SCRIPT) => {
+                    if !is_control_block(&pair.key_data) {
+                        return Err(pair.invalid_key());
+                    }
+                    let Some((leaf_version, script)) = pair.value.split_last() else {
+                        return Err(pair.invalid_value());
+                    };
+                    input.tap_scripts.insert(
+                        pair.key_data.clone(),
+                        (Script::from_bytes(script.to_vec()), *leaf_version),
+                    );
+                }
+                Some(PSBT_IN_TAP_BIP32_DERIVATION) => {
+                    let key = pair.x_only_key()?;
+                    let origin = pair.parse_value(read_tap_key_origin)?;
+                    input.tap_key_origins.insert(key, origin);
+                }
+                Some(PSBT_IN_TAP_INTERNAL_KEY) => {
+                    pair.expect_empty_key()?;
+                    input.tap_internal_key = Some(pair.parse_value(read_x_only)?);
+                }
+                Some(PSBT_IN_TAP_MERKLE_ROOT) => {
+                    pair.expect_empty_key()?;
+                    input.tap_merkle_root = Some(pair.parse_value(read_array)?);
+                }
+                _ => {
+                    input.unknown.insert(pair.key, pair.value);
+                }
+            }
+        }
+
+        if version >= 2 {
+            let Some(txid) = txid else {
+                bail!(PsbtError::MissingField("PSBT_IN_PREVIOUS_TXID"));
+            };
+            let Some(vout) = vout else {
+                bail!(PsbtError::MissingField("PSBT_IN_OUTPUT_INDEX"));
+            };
+            input.previous_output = OutPoint::new(txid, vout);
+        }
+
+        Ok(input)
+    }
+
+    /// Serialise the input map, followed by its separator
+    pub(crate) fn serialise(&self, version: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        if let Some(tx) = &self.non_witness_utxo {
+            write_pair(&mut bytes, PSBT_IN_NON_WITNESS_UTXO, &[], &tx.serialise());
+        }
+        if let Some(output) = &self.witness_utxo {
+            write_pair(&mut bytes, PSBT_IN_WITNESS_UTXO, &[], &output.serialise());
+        }
+        for (key, sig) in &self.partial_sigs {
+            write_pair(&mut bytes, PSBT_IN_PARTIAL_SIG, key, sig);
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            write_pair(
+                &mut bytes,
+                PSBT_IN_SIGHASH_TYPE,
+                &[],
+                &sighash_type.to_le_bytes(),
+            );
+        }
+        if let Some(script) = &self.redeem_script {
+            write_pair(&mut bytes, PSBT_IN_REDEEM_SCRIPT, &[], script.as_bytes());
+        }
+        if let Some(script) = &self.witness_script {
+            write_pair(&mut bytes, PSBT_IN_WITNESS_SCRIPT, &[], script.as_bytes());
+        }
+        for (key, source) in &self.bip32_derivation {
+            write_pair(
+                &mut bytes,
+                PSBT_IN_BIP32_DERIVATION,
+                key,
+                &serialise_key_source(source),
+            );
+        }
+        if let Some(script) = &self.final_script_sig {
+            write_pair(&mut bytes, PSBT_IN_FINAL_SCRIPTSIG, &[], script.as_bytes());
+        }
+        if let Some(witness) = &self.final_script_witness {
+            write_pair(
+                &mut bytes,
+                PSBT_IN_FINAL_SCRIPTWITNESS,
+                &[],
+                &serialise_witness(witness),
+            );
+        }
+        for (hash, preimage) in &self.ripemd160_preimages {
+            write_pair(&mut bytes, PSBT_IN_RIPEMD160, hash, preimage);
+        }
+        for (hash, preimage) in &self.sha256_preimages {
+            write_pair(&mut bytes, PSBT_IN_SHA256, hash, preimage);
+        }
+        for (hash, preimage) in &self.hash160_preimages {
+            write_pair(&mut bytes, PSBT_IN_HASH160, hash, preimage);
+        }
+        for (hash, preimage) in &self.hash256_preimages {
+            write_pair(&mut bytes, PSBT_IN_HASH256, hash, preimage);
+        }
+
+        if version >= 2 {
+            let OutPoint { txid, vout } = self.previous_output;
+            write_pair(&mut bytes, PSBT_IN_PREVIOUS_TXID, &[], &txid);
+            write_pair(&mut bytes, PSBT_IN_OUTPUT_INDEX, &[], &vout.to_le_bytes());
+            if let Some(sequence) = self.sequence {
+                write_pair(&mut bytes, PSBT_IN_SEQUENCE, &[], &sequence.to_le_bytes());
+            }
+            if let Some(lock_time) = self.required_time_lock_time {
+                write_pair(
+                    &mut bytes,
+                    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+            if let Some(lock_time) = self.required_height_lock_time {
+                write_pair(
+                    &mut bytes,
+                    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+        }
+
+        if let Some(sig) = &self.tap_key_sig {
+            write_pair(&mut bytes, PSBT_IN_TAP_KEY_SIG, &[], sig);
+        }
+        for ((x_only, leaf_hash), sig) in &self.tap_script_sigs {
+            write_pair(
+                &mut bytes,
+                PSBT_IN_TAP_SCRIPT_SIG,
+                &[x_only.as_slice(), leaf_hash].concat(),
+                sig,
+            );
+        }
+        for (control_block, (script, leaf_version)) in &self.tap_scripts {
+            let value = [script.as_bytes(), &[*leaf_version]].concat();
+            write_pair(&mut bytes, PSBT_IN_TAP_LEAF_SCRIPT, control_block, &value);
+        }
+        for (x_only, origin) in &self.tap_key_origins {
+            write_pair(
+                &mut bytes,
+                PSBT_IN_TAP_BIP32_DERIVATION,
+                x_only,
+                &serialise_tap_key_origin(origin),
+            );
+        }
+        if let Some(key) = &self.tap_internal_key {
+            write_pair(&mut bytes, PSBT_IN_TAP_INTERNAL_KEY, &[], key);
+        }
+        if let Some(root) = &self.tap_merkle_root {
+            write_pair(&mut bytes, PSBT_IN_TAP_MERKLE_ROOT, &[], root);
+        }
+
+        write_unknown_and_close(&mut bytes, &self.unknown);
+        bytes
+    }
+
+    /// Merge the fields of `other`, an input spending the same output, into the input
+    pub(crate) fn combine(&mut self, other: Self) {
+        self.sequence = self.sequence.or(other.sequence);
+        self.required_time_lock_time = self
+            .required_time_lock_time
+            .or(other.required_time_lock_time);
+        self.required_height_lock_time = self
+            .required_height_lock_time
+            .or(other.required_height_lock_time);
+        self.non_witness_utxo = self.non_witness_utxo.take().or(other.non_witness_utxo);
+        self.witness_utxo = self.witness_utxo.take().or(other.witness_utxo);
+        merge(&mut self.partial_sigs, other.partial_sigs);
+        self.sighash_type = self.sighash_type.or(other.sighash_type);
+        self.redeem_script = self.redeem_script.take().or(other.redeem_script);
+        self.witness_script = self.witness_script.take().or(other.witness_script);
+        merge(&mut self.bip32_derivation, other.bip32_derivation);
+        self.final_script_sig = self.final_script_sig.take().or(other.final_script_sig);
+        self.final_script_witness = self
+            .final_script_witness
+            .take()
+            .or(other.final_script_witness);
+        merge(&mut self.ripemd160_preimages, other.ripemd160_preimages);
+        merge(&mut self.sha256_preimages, other.sha256_preimages);
+        merge(&mut self.hash160_preimages, other.hash160_preimages);
+        merge(&mut self.hash256_preimages, other.hash256_preimages);
+        self.tap_key_sig = self.tap_key_sig.take().or(other.tap_key_sig);
+        merge(&mut self.tap_script_sigs, other.tap_script_sigs);
+        merge(&mut self.tap_scripts, other.tap_scripts);
+        merge(&mut self.tap_key_origins, other.tap_key_origins);
+        self.tap_internal_key = self.tap_internal_key.or(other.tap_internal_key);
+        self.tap_merkle_root = self.tap_merkle_root.or(other.tap_merkle_root);
+        merge(&mut self.unknown, other.unknown);
+    }
+
+    /// Clear everything but the transaction fields, UTXOs, final scripts and unknown pairs
+    pub(crate) fn clear_after_finalising(&mut self) {
+        *self = Self {
+            previous_output: self.previous_output,
+            sequence: self.sequence,
+            required_time_lock_time: self.required_time_lock_time,
+            required_height_lock_time: self.required_height_lock_time,
+            non_witness_utxo: self.non_witness_utxo.take(),
+            witness_utxo: self.witness_utxo.take(),
+            final_script_sig: self.final_script_sig.take(),
+            final_script_witness: self.final_script_witness.take(),
+            unknown: take(&mut self.unknown),
+            ..Default::default()
+        };
+    }
+}
@@ -0,0 +1,209 @@
+use {
+    super::{
+        errors::PsbtError,
+        partial::{KeySource, TapKeyOrigin},
+    },
+    crate::{
+        hd::path::{ChildNumber, DerivationPath},
+        secp256k1::{point::Point, signature::Signature},
+        utils::encoding::{compact_size, read_array, read_compact_size, read_var_bytes, var_bytes},
+    },
+    anyhow::{bail, Error, Result},
+    std::collections::BTreeMap,
+};
+
+/// Key-value pair of a PSBT map
+pub(crate) struct Pair {
+    pub key_type: u64,
+    pub key_data: Vec<u8>,
+    /// Full key, the type followed by the key data
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl Pair {
+    /// Type of the key, if it fits the single byte every standard type uses
+    pub fn standard_type(&self) -> Option<u8> {
+        u8::try_from(self.key_type).ok()
+    }
+
+    pub fn invalid_key(&self) -> Error {
+        PsbtError::InvalidKey(hex::encode(&self.key)).into()
+    }
+
+    pub fn invalid_value(&self) -> Error {
+        PsbtError::InvalidValue(hex::encode(&self.key)).into()
+    }
+
+    /// Fail unless the key is only its type
+    pub fn expect_empty_key(&self) -> Result<()> {
+        if !self.key_data.is_empty() {
+            return Err(self.invalid_key());
+        }
+
+        Ok(())
+    }
+
+    /// Key data that must be exactly `N` bytes
+    pub fn key_array<const N: usize>(&self) -> Result<[u8; N]> {
+        self.key_data
+            .as_slice()
+            .try_into()
+            .map_err(|_| self.invalid_key())
+    }
+
+    /// Parse the value with `parse`, which must use all of its bytes
+    pub fn parse_value<'a, T>(
+        &'a self,
+        parse: impl FnOnce(&mut &'a [u8]) -> Result<T>,
+    ) -> Result<T> {
+        let mut reader = self.value.as_slice();
+        match parse(&mut reader) {
+            Ok(value) if reader.is_empty() => Ok(value),
+            _ => Err(self.invalid_value()),
+        }
+    }
+
+    /// Key data holding a SEC serialised public key
+    pub fn sec_key(&self) -> Result<Vec<u8>> {
+        if !matches!(self.key_data.len(), 33 | 65) || Point::from_sec(&self.key_data).is_err() {
+            return Err(self.invalid_key());
+        }
+
+        Ok(self.key_data.clone())
+    }
+
+    /// Key data holding an x-only public key
+    pub fn x_only_key(&self) -> Result<[u8; 32]> {
+        let key = self.key_array()?;
+        if Point::from_x_only(&key).is_err() {
+            return Err(self.invalid_key());
+        }
+
+        Ok(key)
+    }
+}
+
+/// Read the pairs of a map up to its separator, rejecting duplicate keys
+pub(crate) fn read_map(reader: &mut &[u8]) -> Result<Vec<Pair>> {
+    let mut pairs: Vec<Pair> = vec![];
+    loop {
+        let key = read_var_bytes(reader)?;
+        if key.is_empty() {
+            return Ok(pairs);
+        }
+        let value = read_var_bytes(reader)?;
+        if pairs.iter().any(|pair| pair.key == key) {
+            bail!(PsbtError::DuplicateKey(hex::encode(key)));
+        }
+
+        let mut key_data = key.as_slice();
+        let key_type = read_compact_size(&mut key_data)?;
+        pairs.push(Pair {
+            key_type,
+            key_data: key_data.to_vec(),
+            key,
+            value,
+        });
+    }
+}
+
+/// Append a key-value pair to a serialised map
+pub(crate) fn write_pair(bytes: &mut Vec<u8>, key_type: u8, key_data: &[u8], value: &[u8]) {
+    bytes.extend(var_bytes(&[&[key_type], key_data].concat()));
+    bytes.extend(var_bytes(value));
+}
+
+/// Append pairs whose keys already include their type, then the map separator
+pub(crate) fn write_unknown_and_close(bytes: &mut Vec<u8>, unknown: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in unknown {
+        bytes.extend(var_bytes(key));
+        bytes.extend(var_bytes(value));
+    }
+    bytes.push(0x00);
+}
+
+/// Merge `other` into `map`, keeping the existing value of keys present in both
+pub(crate) fn merge<K: Ord, V>(map: &mut BTreeMap<K, V>, other: BTreeMap<K, V>) {
+    for (key, value) in other {
+        map.entry(key).or_insert(value);
+    }
+}
+
+/// Parse a master key fingerprint followed by little endian child numbers
+pub(crate) fn read_key_source(reader: &mut &[u8]) -> Result<KeySource> {
+    let fingerprint = read_array(reader)?;
+    if !reader.len().is_multiple_of(4) {
+        bail!(PsbtError::InvalidValue("key source".to_string()));
+    }
+
+    let children = reader
+        .chunks(4)
+        .map(|child| ChildNumber::from(u32::from_le_bytes(child.try_into().unwrap())))
+        .collect();
+    *reader = &[];
+
+    Ok((fingerprint, DerivationPath::new(children)))
+}
+
+pub(crate) fn serialise_key_source((fingerprint, path): &KeySource) -> Vec<u8> {
+    let mut bytes = fingerprint.to_vec();
+    for child in path.children() {
+        bytes.extend(u32::from(*child).to_le_bytes());
+    }
+
+    bytes
+}
+
+/// Parse the leaf hashes and key source of a taproot key
+pub(crate) fn read_tap_key_origin(reader: &mut &[u8]) -> Result<TapKeyOrigin> {
+    let count = read_compact_size(reader)?;
+    let leaf_hashes = (0..count)
+        .map(|_| read_array(reader))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((leaf_hashes, read_key_source(reader)?))
+}
+
+pub(crate) fn serialise_tap_key_origin((leaf_hashes, source): &TapKeyOrigin) -> Vec<u8> {
+    let mut bytes = compact_size(leaf_hashes.len() as u64);
+    for leaf_hash in leaf_hashes {
+        bytes.extend(leaf_hash);
+    }
+    bytes.extend(serialise_key_source(source));
+
+    bytes
+}
+
+/// Parse a 32 byte x-only public key that must lie on the curve
+pub(crate) fn read_x_only(reader: &mut &[u8]) -> Result<[u8; 32]> {
+    let key = read_array(reader)?;
+    Point::from_x_only(&key)?;
+
+    Ok(key)
+}
+
+/// If `sig` is a strict DER signature followed by a sighash byte
+pub(crate) fn is_ecdsa_signature(sig: &[u8]) -> bool {
+    sig.split_last()
+        .is_some_and(|(_, der)| Signature::from_der(der).is_ok())
+}
+
+/// If `sig` is a BIP340 signature, followed by a sighash byte unless it is the default
+pub(crate) fn is_schnorr_signature(sig: &[u8]) -> bool {
+    match sig.len() {
+        64 => true,
+        65 => sig[64] != 0x00,
+        _ => false,
+    }
+}
+
+/// If `control_block` has a valid length and internal key
+pub(crate) fn is_control_block(control_block: &[u8]) -> bool {
+    // the internal key is followed by up to 128 hashes of the merkle path
+    let len = control_block.len();
+    len >= 33
+        && (len - 33).is_multiple_of(32)
+        && (len - 33) / 32 <= 128
+        && Point::from_x_only(&control_block[1..33]).is_ok()
+}
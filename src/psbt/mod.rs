@@ -0,0 +1,5 @@
+pub mod errors;
+pub mod input;
+pub(crate) mod map;
+pub mod output;
+pub mod partial;
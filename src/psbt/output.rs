@@ -0,0 +1,217 @@
+use {
+    super::{
+        errors::PsbtError,
+        input::is_version_2_key,
+        map::{
+            merge, read_key_source, read_map, read_tap_key_origin, read_x_only,
+            serialise_key_source, serialise_tap_key_origin, write_pair, write_unknown_and_close,
+        },
+        partial::{KeySource, TapKeyOrigin},
+    },
+    crate::{
+        script::{raw::Script, taproot::TAPROOT_CONTROL_MAX_NODE_COUNT},
+        transaction::tx::{read_script, TxOut},
+        utils::encoding::{read_array, var_bytes},
+    },
+    anyhow::{bail, Result},
+    std::collections::BTreeMap,
+};
+
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+const PSBT_OUT_TAP_TREE: u8 = 0x06;
+const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// Output map of a PSBT
+pub struct Output {
+    /// Value of the output, taken from the unsigned transaction in version 0
+    pub amount: u64,
+    /// Output script, taken from the unsigned transaction in version 0
+    pub script: Script,
+    pub redeem_script: Option<Script>,
+    pub witness_script: Option<Script>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub tap_internal_key: Option<[u8; 32]>,
+    /// Leaves of the taproot script tree in depth first order, as depth, leaf version and script
+    pub tap_tree: Option<Vec<(u8, u8, Script)>>,
+    /// Leaf hashes each x-only key is used in, with its key source
+    pub tap_key_origins: BTreeMap<[u8; 32], TapKeyOrigin>,
+    /// Unknown and proprietary pairs, keyed by their full key
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Parse the leaves of a taproot script tree, which must form a complete binary tree
+fn read_tap_tree(reader: &mut &[u8]) -> Result<Vec<(u8, u8, Script)>> {
+    let mut leaves = vec![];
+    // depths of the subtrees still waiting for a sibling
+    let mut open: Vec<u8> = vec![];
+    while !reader.is_empty() {
+        let [depth, leaf_version] = read_array(reader)?;
+        if usize::from(depth) > TAPROOT_CONTROL_MAX_NODE_COUNT || open.last() == Some(&0) {
+            bail!(PsbtError::InvalidValue("tap tree".to_string()));
+        }
+        leaves.push((depth, leaf_version, read_script(reader)?));
+
+        // siblings combine into their parent one level up
+        let mut depth = depth;
+        while depth > 0 && open.last() == Some(&depth) {
+            open.pop();
+            depth -= 1;
+        }
+        open.push(depth);
+    }
+    if open != [0] {
+        bail!(PsbtError::InvalidValue("tap tree".to_string()));
+    }
+
+    Ok(leaves)
+}
+
+impl Output {
+    /// Output paying `amount` satoshis to `script`
+    pub fn new(amount: u64, script: Script) -> Self {
+        Self {
+            amount,
+            script,
+            ..Default::default()
+        }
+    }
+
+    /// Transaction output described by the map
+    pub fn tx_out(&self) -> TxOut {
+        TxOut::new(self.amount, self.script.clone())
+    }
+
+    /// Parse a serialised output map, up to and including its separator
+    pub(crate) fn parse(reader: &mut &[u8], version: u32) -> Result<Self> {
+        let mut output = Self::default();
+        let (mut amount, mut script) = (None, None);
+
+        for pair in read_map(reader)? {
+            match pair.standard_type() {
+                Some(PSBT_OUT_REDEEM_SCRIPT) => {
+                    pair.expect_empty_key()?;
+                    output.redeem_script = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_OUT_WITNESS_SCRIPT) => {
+                    pair.expect_empty_key()?;
+                    output.witness_script = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_OUT_BIP32_DERIVATION) => {
+                    let key = pair.sec_key()?;
+                    let source = pair.parse_value(read_key_source)?;
+                    output.bip32_derivation.insert(key, source);
+                }
+                Some(PSBT_OUT_AMOUNT) if is_version_2_key(&pair, version) => {
+                    if version < 2 {
+                        bail!(PsbtError::UnexpectedField("PSBT_OUT_AMOUNT", version));
+                    }
+                    pair.expect_empty_key()?;
+                    amount = Some(u64::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_OUT_SCRIPT) if is_version_2_key(&pair, version) => {
+                    if version < 2 {
+                        bail!(PsbtError::UnexpectedField("PSBT_OUT_SCRIPT", version));
+                    }
+                    pair.expect_empty_key()?;
+                    script = Some(Script::from_bytes(pair.value));
+                }
+                Some(PSBT_OUT_TAP_INTERNAL_KEY) => {
+                    pair.expect_empty_key()?;
+                    output.tap_internal_key = Some(pair.parse_value(read_x_only)?);
+                }
+                Some(PSBT_OUT_TAP_TREE) => {
+                    pair.expect_empty_key()?;
+                    output.tap_tree = Some(pair.parse_value(read_tap_tree)?);
+                }
+                Some(PSBT_OUT_TAP_BIP32_DERIVATION) => {
+                    let key = pair.x_only_key()?;
+                    let origin = pair.parse_value(read_tap_key_origin)?;
+                    output.tap_key_origins.insert(key, origin);
+                }
+                _ => {
+                    output.unknown.insert(pair.key, pair.value);
+                }
+            }
+        }
+
+        if version >= 2 {
+            let Some(amount) = amount else {
+                bail!(PsbtError::MissingField("PSBT_OUT_AMOUNT"));
+            };
+            let Some(script) = script else {
+                bail!(PsbtError::MissingField("PSBT_OUT_SCRIPT"));
+            };
+            output.amount = amount;
+            output.script = script;
+        }
+
+        Ok(output)
+    }
+
+    /// Serialise the output map, followed by its separator
+    pub(crate) fn serialise(&self, version: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        if let Some(script) = &self.redeem_script {
+            write_pair(&mut bytes, PSBT_OUT_REDEEM_SCRIPT, &[], script.as_bytes());
+        }
+        if let Some(script) = &self.witness_script {
+            write_pair(&mut bytes, PSBT_OUT_WITNESS_SCRIPT, &[], script.as_bytes());
+        }
+        for (key, source) in &self.bip32_derivation {
+            write_pair(
+                &mut bytes,
+                PSBT_OUT_BIP32_DERIVATION,
+                key,
+                &serialise_key_source(source),
+            );
+        }
+        if version >= 2 {
+            write_pair(&mut bytes, PSBT_OUT_AMOUNT, &[], &self.amount.to_le_bytes());
+            write_pair(&mut bytes, PSBT_OUT_SCRIPT, &[], self.script.as_bytes());
+        }
+        if let Some(key) = &self.tap_internal_key {
+            write_pair(&mut bytes, PSBT_OUT_TAP_INTERNAL_KEY, &[], key);
+        }
+        if let Some(leaves) = &self.tap_tree {
+            let value = leaves
+                .iter()
+                .flat_map(|(depth, leaf_version, script)| {
+                    [
+                        &[*depth, *leaf_version],
+                        var_bytes(script.as_bytes()).as_slice(),
+                    ]
+                    .concat()
+                })
+                .collect::<Vec<_>>();
+            write_pair(&mut bytes, PSBT_OUT_TAP_TREE, &[], &value);
+        }
+        for (x_only, origin) in &self.tap_key_origins {
+            write_pair(
+                &mut bytes,
+                PSBT_OUT_TAP_BIP32_DERIVATION,
+                x_only,
+                &serialise_tap_key_origin(origin),
+            );
+        }
+
+        write_unknown_and_close(&mut bytes, &self.unknown);
+        bytes
+    }
+
+    /// Merge the fields of `other`, a map of the same output, into the output
+    pub(crate) fn combine(&mut self, other: Self) {
+        self.redeem_script = self.redeem_script.take().or(other.redeem_script);
+        self.witness_script = self.witness_script.take().or(other.witness_script);
+        merge(&mut self.bip32_derivation, other.bip32_derivation);
+        self.tap_internal_key = self.tap_internal_key.or(other.tap_internal_key);
+        self.tap_tree = self.tap_tree.take().or(other.tap_tree);
+        merge(&mut self.tap_key_origins, other.tap_key_origins);
+        merge(&mut self.unknown, other.unknown);
+    }
+}
@@ -0,0 +1,1436 @@
+use {
+    super::{
+        errors::PsbtError,
+        input::Input,
+        map::{
+            merge, read_key_source, read_map, serialise_key_source, write_pair,
+            write_unknown_and_close,
+        },
+        output::Output,
+    },
+    crate::{
+        descriptor::output::Descriptor,
+        hd::{
+            bip32::{ExtendedPublicKey, Fingerprint},
+            path::DerivationPath,
+        },
+        script::{
+            opcodes::{
+                OP_1, OP_16, OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_DUP, OP_EQUALVERIFY,
+                OP_HASH160, OP_NUMEQUAL,
+            },
+            raw::{Instruction, Script},
+            taproot::{control_block, tap_leaf_hash, tweak_private_key, TAPSCRIPT_LEAF_VERSION},
+        },
+        secp256k1::keys::PrivateKey,
+        transaction::{
+            errors::TransactionError,
+            sighash::{
+                SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT, SIGHASH_NONE, SIGHASH_SINGLE,
+            },
+            tx::{OutPoint, Transaction, TxIn, TxOut, SEQUENCE_FINAL},
+        },
+        utils::{
+            base64,
+            encoding::{compact_size, read_array, read_compact_size},
+            errors::EncodingError,
+            hash::hash160,
+        },
+    },
+    anyhow::{bail, Result},
+    std::{
+        collections::BTreeMap,
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+/// Magic bytes starting every serialised PSBT, `psbt` followed by 0xff
+const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xff];
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_XPUB: u8 = 0x01;
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+
+/// Flag of a version 2 PSBT allowing inputs to be added
+pub const TX_MODIFIABLE_INPUTS: u8 = 0x01;
+
+/// Flag of a version 2 PSBT allowing outputs to be added
+pub const TX_MODIFIABLE_OUTPUTS: u8 = 0x02;
+
+/// Flag of a version 2 PSBT marking a `SIGHASH_SINGLE` signature, pinning inputs to their outputs
+pub const TX_MODIFIABLE_SIGHASH_SINGLE: u8 = 0x04;
+
+/// Master key fingerprint and full derivation path of a key
+pub type KeySource = (Fingerprint, DerivationPath);
+
+/// Hashes of the leaves a taproot key is used in, with its key source
+pub type TapKeyOrigin = (Vec<[u8; 32]>, KeySource);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Partially Signed Bitcoin Transaction as specified by BIP174, BIP370 and BIP371
+///
+/// The transaction is kept in the input and output maps for both versions, version 0 serialising
+/// it as the global unsigned transaction instead
+pub struct Psbt {
+    /// PSBT version, 0 or 2
+    pub version: u32,
+    pub tx_version: i32,
+    /// Lock time of the transaction, used by version 2 when no input requires one
+    pub fallback_lock_time: Option<u32>,
+    /// `TX_MODIFIABLE_*` flags, version 2 only
+    pub tx_modifiable: Option<u8>,
+    pub xpubs: Vec<(ExtendedPublicKey, KeySource)>,
+    pub inputs: Vec<Input>,
+    pub outputs: Vec<Output>,
+    /// Unknown and proprietary pairs, keyed by their full key
+    pub unknown: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// Scripts an input is signed and spent with, resolved from its UTXO
+struct Spend {
+    /// Script committed to by the sighash
+    script_code: Script,
+    redeem_script: Option<Script>,
+    witness_script: Option<Script>,
+    segwit: bool,
+}
+
+/// If `script` pushes `data`
+fn script_pushes(script: &Script, data: &[u8]) -> bool {
+    script
+        .instructions()
+        .any(|instruction| matches!(instruction, Ok(Instruction::Push(push)) if push == data))
+}
+
+/// Number pushed by `OP_1` to `OP_16`
+fn small_int(op: u8) -> Option<usize> {
+    (OP_1..=OP_16)
+        .contains(&op)
+        .then(|| (op - OP_1) as usize + 1)
+}
+
+/// Stack satisfying a P2PK, P2PKH or multisig script with ECDSA signatures, bottom element first
+fn satisfy_ecdsa(script: &Script, sigs: &BTreeMap<Vec<u8>, Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+    use Instruction::{Op, Push};
+
+    let instructions = script.instructions().collect::<Result<Vec<_>>>().ok()?;
+    match instructions.as_slice() {
+        [Push(key), Op(OP_CHECKSIG)] => Some(vec![sigs.get(*key)?.clone()]),
+        [Op(OP_DUP), Op(OP_HASH160), Push(hash), Op(OP_EQUALVERIFY), Op(OP_CHECKSIG)] => sigs
+            .iter()
+            .find(|(key, _)| hash160(key) == *hash)
+            .map(|(key, sig)| vec![sig.clone(), key.clone()]),
+        [Op(k), keys @ .., Op(n), Op(OP_CHECKMULTISIG)] => {
+            let k = small_int(*k)?;
+            if small_int(*n)? != keys.len() {
+                return None;
+            }
+            // signatures must be in the order of their keys
+            let mut stack = vec![vec![]];
+            for key in keys {
+                let Push(key) = key else {
+                    return None;
+                };
+                if let Some(sig) = sigs.get(*key).filter(|_| stack.len() <= k) {
+                    stack.push(sig.clone());
+                }
+            }
+            // the dummy element consumed by OP_CHECKMULTISIG comes first
+            (stack.len() == k + 1).then_some(stack)
+        }
+        _ => None,
+    }
+}
+
+/// Witness spending a taproot input with its key path signature, or with a leaf script of one
+/// key or a `multi_a` of keys
+fn satisfy_taproot(input: &Input) -> Option<Vec<Vec<u8>>> {
+    use Instruction::{Op, Push};
+
+    if let Some(sig) = &input.tap_key_sig {
+        return Some(vec![sig.clone()]);
+    }
+
+    input
+        .tap_scripts
+        .iter()
+        .find_map(|(control_block, (script, leaf_version))| {
+            let leaf_hash = tap_leaf_hash(script, *leaf_version);
+            let sig = |key: &[u8]| {
+                let key = key.try_into().ok()?;
+                input.tap_script_sigs.get(&(key, leaf_hash)).cloned()
+            };
+
+            let instructions = script.instructions().collect::<Result<Vec<_>>>().ok()?;
+            let mut stack = match instructions.as_slice() {
+                [Push(key), Op(OP_CHECKSIG)] => vec![sig(key)?],
+                [Push(first), Op(OP_CHECKSIG), rest @ .., Op(k), Op(OP_NUMEQUAL)] => {
+                    let k = small_int(*k)?;
+                    let mut keys = vec![*first];
+                    for pair in rest.chunks(2) {
+                        let [Push(key), Op(OP_CHECKSIGADD)] = pair else {
+                            return None;
+                        };
+                        keys.push(*key);
+                    }
+
+                    // the first key's signature is on top, and exactly k signatures are allowed
+                    let mut count = 0;
+                    let mut stack = vec![];
+                    for key in keys.iter().rev() {
+                        match sig(key).filter(|_| count < k) {
+                            Some(sig) => {
+                                count += 1;
+                                stack.push(sig);
+                            }
+                            None => stack.push(vec![]),
+                        }
+                    }
+                    if count < k {
+                        return None;
+                    }
+                    stack
+                }
+                _ => return None,
+            };
+            stack.extend([script.as_bytes().to_vec(), control_block.clone()]);
+
+            Some(stack)
+        })
+}
+
+/// BIP340 signature of `sighash` with fresh auxiliary randomness, followed by the sighash byte
+/// unless it is `SIGHASH_DEFAULT`
+fn schnorr_signature(key: &PrivateKey, sighash: &[u8; 32], sighash_type: u8) -> Result<Vec<u8>> {
+    let mut aux_rand = [0; 32];
+    getrandom::getrandom(&mut aux_rand)?;
+
+    let mut sig = key.sign_schnorr(sighash, &aux_rand)?.to_bytes().to_vec();
+    if sighash_type != SIGHASH_DEFAULT {
+        sig.push(sighash_type);
+    }
+
+    Ok(sig)
+}
+
+/// Key sources of the keys of a descriptor outside of taproot, keyed by SEC serialisation
+fn bip32_derivation(descriptor: &Descriptor, index: u32) -> Result<BTreeMap<Vec<u8>, KeySource>> {
+    let mut derivation = BTreeMap::new();
+    for key in descriptor.keys() {
+        if let Some(source) = key.key_source(index)? {
+            derivation.insert(key.derive(index)?.sec(!key.is_uncompressed()), source);
+        }
+    }
+
+    Ok(derivation)
+}
+
+/// Key sources of the keys of a `tr()` descriptor, with the hashes of the leaves using them
+fn tap_key_origins(
+    descriptor: &Descriptor,
+    index: u32,
+) -> Result<BTreeMap<[u8; 32], TapKeyOrigin>> {
+    let mut origins = BTreeMap::new();
+    let Descriptor::Tr { internal_key, tree } = descriptor else {
+        return Ok(origins);
+    };
+
+    if let Some(source) = internal_key.key_source(index)? {
+        origins.insert(internal_key.derive(index)?.x_only(), (vec![], source));
+    }
+    for leaf in tree.iter().flat_map(|tree| tree.leaves()) {
+        let leaf_hash = tap_leaf_hash(&leaf.to_script(index)?, TAPSCRIPT_LEAF_VERSION);
+        for key in leaf.keys() {
+            if let Some(source) = key.key_source(index)? {
+                let (leaf_hashes, _) = origins
+                    .entry(key.derive(index)?.x_only())
+                    .or_insert((vec![], source));
+                leaf_hashes.push(leaf_hash);
+            }
+        }
+    }
+
+    Ok(origins)
+}
+
+impl Psbt {
+    /// Create a version 0 PSBT of a transaction without script sigs or witnesses, the Creator role
+    pub fn from_unsigned_tx(tx: Transaction) -> Result<Self> {
+        if let Some(index) = tx
+            .inputs
+            .iter()
+            .position(|input| !input.script_sig.is_empty() || !input.witness.is_empty())
+        {
+            bail!(PsbtError::UnsignedTxHasScripts(index));
+        }
+
+        Ok(Self {
+            version: 0,
+            tx_version: tx.version,
+            fallback_lock_time: Some(tx.lock_time),
+            tx_modifiable: None,
+            xpubs: vec![],
+            inputs: tx
+                .inputs
+                .iter()
+                .map(|input| Input {
+                    sequence: Some(input.sequence),
+                    ..Input::new(input.previous_output)
+                })
+                .collect(),
+            outputs: tx
+                .outputs
+                .into_iter()
+                .map(|output| Output::new(output.value, output.script_pubkey))
+                .collect(),
+            unknown: BTreeMap::new(),
+        })
+    }
+
+    /// Create an empty version 2 PSBT that inputs and outputs can be added to
+    pub fn new_v2(tx_version: i32, fallback_lock_time: Option<u32>) -> Self {
+        Self {
+            version: 2,
+            tx_version,
+            fallback_lock_time,
+            tx_modifiable: Some(TX_MODIFIABLE_INPUTS | TX_MODIFIABLE_OUTPUTS),
+            xpubs: vec![],
+            inputs: vec![],
+            outputs: vec![],
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    /// Add an input to a version 2 PSBT that allows it, the BIP370 Constructor role
+    pub fn add_input(&mut self, input: Input) -> Result<()> {
+        if self.version < 2 || self.tx_modifiable.unwrap_or(0) & TX_MODIFIABLE_INPUTS == 0 {
+            bail!(PsbtError::NotModifiable("inputs"));
+        }
+
+        self.inputs.push(input);
+        // the lock time requirements of the inputs must stay compatible
+        if let Err(err) = self.lock_time() {
+            self.inputs.pop();
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Add an output to a version 2 PSBT that allows it, the BIP370 Constructor role
+    pub fn add_output(&mut self, output: Output) -> Result<()> {
+        if self.version < 2 || self.tx_modifiable.unwrap_or(0) & TX_MODIFIABLE_OUTPUTS == 0 {
+            bail!(PsbtError::NotModifiable("outputs"));
+        }
+        self.outputs.push(output);
+
+        Ok(())
+    }
+
+    /// Lock time of the transaction as determined by BIP370
+    ///
+    /// Height based lock times are preferred when every input requiring a lock time allows both
+    pub fn lock_time(&self) -> Result<u32> {
+        let required = self
+            .inputs
+            .iter()
+            .filter(|input| {
+                input.required_height_lock_time.is_some() || input.required_time_lock_time.is_some()
+            })
+            .collect::<Vec<_>>();
+        if required.is_empty() {
+            return Ok(self.fallback_lock_time.unwrap_or(0));
+        }
+
+        let heights = required
+            .iter()
+            .map(|input| input.required_height_lock_time)
+            .collect::<Option<Vec<_>>>();
+        let times = required
+            .iter()
+            .map(|input| input.required_time_lock_time)
+            .collect::<Option<Vec<_>>>();
+        match (heights, times) {
+            (Some(heights), _) => Ok(heights.into_iter().max().unwrap_or(0)),
+            (None, Some(times)) => Ok(times.into_iter().max().unwrap_or(0)),
+            (None, None) => bail!(PsbtError::LockTimeConflict),
+        }
+    }
+
+    /// Unsigned transaction described by the PSBT
+    pub fn unsigned_tx(&self) -> Result<Transaction> {
+        Ok(self.transaction_with_lock_time(self.lock_time()?))
+    }
+
+    fn transaction_with_lock_time(&self, lock_time: u32) -> Transaction {
+        let mut tx = Transaction::new(self.tx_version, lock_time);
+        tx.inputs = self
+            .inputs
+            .iter()
+            .map(|input| TxIn {
+                sequence: input.sequence.unwrap_or(SEQUENCE_FINAL),
+                ..TxIn::new(input.previous_output)
+            })
+            .collect();
+        tx.outputs = self.outputs.iter().map(Output::tx_out).collect();
+
+        tx
+    }
+
+    /// Output spent by input `index`, from its witness or non-witness UTXO
+    pub fn spent_output(&self, index: usize) -> Result<TxOut> {
+        let Some(input) = self.inputs.get(index) else {
+            bail!(TransactionError::InputOutOfRange(index, self.inputs.len()));
+        };
+        if let Some(output) = &input.witness_utxo {
+            return Ok(output.clone());
+        }
+        let Some(tx) = &input.non_witness_utxo else {
+            bail!(PsbtError::MissingUtxo(index));
+        };
+
+        let OutPoint { txid, vout } = input.previous_output;
+        match tx.outputs.get(vout as usize) {
+            Some(output) if tx.txid() == txid => Ok(output.clone()),
+            _ => bail!(PsbtError::UtxoMismatch(index)),
+        }
+    }
+
+    /// Scripts input `index` is spent with, `None` if a redeem or witness script is missing
+    fn spend(&self, index: usize, script_pubkey: &Script) -> Result<Option<Spend>> {
+        let input = &self.inputs[index];
+        let redeem_script = match script_pubkey.is_p2sh() {
+            true => match &input.redeem_script {
+                Some(redeem) if redeem.to_p2sh() == *script_pubkey => Some(redeem.clone()),
+                Some(_) => bail!(PsbtError::ScriptMismatch(index)),
+                None => return Ok(None),
+            },
+            false => None,
+        };
+        let script = redeem_script.as_ref().unwrap_or(script_pubkey);
+
+        let (script_code, witness_script, segwit) = if script.is_p2wpkh() {
+            // P2WPKH signs the P2PKH script of its key hash
+            let hash = script.as_bytes()[2..].try_into()?;
+            (Script::p2pkh(&hash), None, true)
+        } else if script.is_p2wsh() {
+            match &input.witness_script {
+                Some(witness) if witness.to_p2wsh() == *script => {
+                    (witness.clone(), Some(witness.clone()), true)
+                }
+                Some(_) => bail!(PsbtError::ScriptMismatch(index)),
+                None => return Ok(None),
+            }
+        } else {
+            (script.clone(), None, false)
+        };
+
+        Ok(Some(Spend {
+            script_code,
+            redeem_script,
+            witness_script,
+            segwit,
+        }))
+    }
+
+    /// Add the scripts and key origins of `descriptor` at `derivation_index` to input `index`,
+    /// the Updater role
+    ///
+    /// The descriptor must describe the output the input spends
+    pub fn update_input_with_descriptor(
+        &mut self,
+        index: usize,
+        descriptor: &Descriptor,
+        derivation_index: u32,
+    ) -> Result<()> {
+        if self.spent_output(index)?.script_pubkey != descriptor.script_pubkey(derivation_index)? {
+            bail!(PsbtError::ScriptMismatch(index));
+        }
+
+        let input = &mut self.inputs[index];
+        if let Descriptor::Tr { internal_key, .. } = descriptor {
+            let internal_key = internal_key.derive(derivation_index)?;
+            let tree = descriptor.tap_tree(derivation_index)?;
+            input.tap_internal_key = Some(internal_key.x_only());
+            input.tap_merkle_root = tree.as_ref().map(|tree| tree.merkle_root());
+            if let Some(tree) = &tree {
+                for (script, _) in tree.leaves() {
+                    input.tap_scripts.insert(
+                        control_block(&internal_key, tree, script)?,
+                        (script.clone(), TAPSCRIPT_LEAF_VERSION),
+                    );
+                }
+            }
+            merge(
+                &mut input.tap_key_origins,
+                tap_key_origins(descriptor, derivation_index)?,
+            );
+        } else {
+            input.redeem_script = descriptor.redeem_script(derivation_index)?;
+            input.witness_script = descriptor.witness_script(derivation_index)?;
+            merge(
+                &mut input.bip32_derivation,
+                bip32_derivation(descriptor, derivation_index)?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Add the scripts and key origins of `descriptor` at `derivation_index` to output `index`,
+    /// the Updater role
+    ///
+    /// The descriptor must describe the output script
+    pub fn update_output_with_descriptor(
+        &mut self,
+        index: usize,
+        descriptor: &Descriptor,
+        derivation_index: u32,
+    ) -> Result<()> {
+        let output = &mut self.outputs[index];
+        if output.script != descriptor.script_pubkey(derivation_index)? {
+            bail!(PsbtError::InvalidValue(format!("output {index} script")));
+        }
+
+        if let Descriptor::Tr { internal_key, .. } = descriptor {
+            output.tap_internal_key = Some(internal_key.derive(derivation_index)?.x_only());
+            output.tap_tree = descriptor.tap_tree(derivation_index)?.map(|tree| {
+                tree.leaves()
+                    .into_iter()
+                    .map(|(script, depth)| (depth as u8, TAPSCRIPT_LEAF_VERSION, script.clone()))
+                    .collect()
+            });
+            merge(
+                &mut output.tap_key_origins,
+                tap_key_origins(descriptor, derivation_index)?,
+            );
+        } else {
+            output.redeem_script = descriptor.redeem_script(derivation_index)?;
+            output.witness_script = descriptor.witness_script(derivation_index)?;
+            merge(
+                &mut output.bip32_derivation,
+                bip32_derivation(descriptor, derivation_index)?,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Sign every input the keys can sign for, the Signer role
+    ///
+    /// Legacy and segwit v0 inputs get ECDSA partial signatures, taproot inputs Schnorr key path
+    /// and script path signatures. Inputs without a UTXO or the scripts they need are skipped.
+    /// Returns the number of signatures added.
+    pub fn sign(&mut self, keys: &[PrivateKey]) -> Result<usize> {
+        let tx = self.unsigned_tx()?;
+        let mut count = 0;
+        for index in 0..self.inputs.len() {
+            let input = &self.inputs[index];
+            if input.is_finalised()
+                || (input.witness_utxo.is_none() && input.non_witness_utxo.is_none())
+            {
+                continue;
+            }
+
+            let script_pubkey = self.spent_output(index)?.script_pubkey;
+            let (signed, sighash_type) = if script_pubkey.is_p2tr() {
+                self.sign_taproot(&tx, index, keys)?
+            } else {
+                self.sign_ecdsa(&tx, index, keys)?
+            };
+            if signed > 0 {
+                self.restrict_modifiable(sighash_type);
+            }
+            count += signed;
+        }
+
+        Ok(count)
+    }
+
+    /// Add ECDSA signatures of legacy or segwit v0 input `index`
+    ///
+    /// Returns the number of signatures and the sighash type they commit to
+    fn sign_ecdsa(
+        &mut self,
+        tx: &Transaction,
+        index: usize,
+        keys: &[PrivateKey],
+    ) -> Result<(usize, u32)> {
+        let spent = self.spent_output(index)?;
+        let sighash_type = self.inputs[index]
+            .sighash_type
+            .unwrap_or(SIGHASH_ALL as u32);
+        let sighash_byte = u8::try_from(sighash_type)
+            .map_err(|_| TransactionError::InvalidSighashType(sighash_type))?;
+        let Some(spend) = self.spend(index, &spent.script_pubkey)? else {
+            return Ok((0, sighash_type));
+        };
+        // a legacy signature does not commit to the spent amount, so only the full previous
+        // transaction proves it
+        if !spend.segwit && self.inputs[index].non_witness_utxo.is_none() {
+            return Ok((0, sighash_type));
+        }
+        let sighash = match spend.segwit {
+            true => tx.segwit_v0_sighash(index, &spend.script_code, spent.value, sighash_type)?,
+            false => tx.legacy_sighash(index, &spend.script_code, sighash_type)?,
+        };
+
+        // segwit only allows compressed keys
+        let forms: &[bool] = if spend.segwit {
+            &[true]
+        } else {
+            &[true, false]
+        };
+        let mut count = 0;
+        for key in keys {
+            let Some(sec) = forms
+                .iter()
+                .map(|compressed| key.point().sec(*compressed))
+                .find(|sec| {
+                    script_pushes(&spend.script_code, sec)
+                        || script_pushes(&spend.script_code, &hash160(sec))
+                })
+            else {
+                continue;
+            };
+
+            let mut sig = key.sign(&hex::encode(sighash))?.der();
+            sig.push(sighash_byte);
+            self.inputs[index].partial_sigs.insert(sec, sig);
+            count += 1;
+        }
+
+        Ok((count, sighash_type))
+    }
+
+    /// Add Schnorr signatures of taproot input `index`, for the key path if a key tweaks to the
+    /// output key and for every leaf script using a key
+    ///
+    /// Returns the number of signatures and the sighash type they commit to
+    fn sign_taproot(
+        &mut self,
+        tx: &Transaction,
+        index: usize,
+        keys: &[PrivateKey],
+    ) -> Result<(usize, u32)> {
+        let spent_outputs = (0..self.inputs.len())
+            .map(|i| self.spent_output(i))
+            .collect::<Result<Vec<_>>>()?;
+        let input = &self.inputs[index];
+        let sighash_type = match input.sighash_type {
+            Some(sighash_type) => u8::try_from(sighash_type)
+                .map_err(|_| TransactionError::InvalidSighashType(sighash_type))?,
+            None => SIGHASH_DEFAULT,
+        };
+        let output_key = &spent_outputs[index].script_pubkey.as_bytes()[2..];
+
+        let mut key_sig = None;
+        let mut script_sigs = vec![];
+        for key in keys {
+            let x_only = key.point().x_only();
+            if input.tap_internal_key == Some(x_only) {
+                let tweaked = tweak_private_key(key, input.tap_merkle_root.as_ref())?;
+                if tweaked.point().x_only() == output_key {
+                    let sighash =
+                        tx.taproot_sighash(index, &spent_outputs, sighash_type, None, None)?;
+                    key_sig = Some(schnorr_signature(&tweaked, &sighash, sighash_type)?);
+                }
+            }
+
+            for (script, leaf_version) in input.tap_scripts.values() {
+                if !script_pushes(script, &x_only) {
+                    continue;
+                }
+                let leaf_hash = tap_leaf_hash(script, *leaf_version);
+                let sighash = tx.taproot_sighash(
+                    index,
+                    &spent_outputs,
+                    sighash_type,
+                    Some(&leaf_hash),
+                    None,
+                )?;
+                script_sigs.push((
+                    (x_only, leaf_hash),
+                    schnorr_signature(key, &sighash, sighash_type)?,
+                ));
+            }
+        }
+
+        let input = &mut self.inputs[index];
+        let count = script_sigs.len() + key_sig.is_some() as usize;
+        input.tap_key_sig = key_sig.or(input.tap_key_sig.take());
+        input.tap_script_sigs.extend(script_sigs);
+
+        // the default sighash type commits to everything like SIGHASH_ALL
+        Ok((count, sighash_type.max(SIGHASH_ALL) as u32))
+    }
+
+    /// Clear the modifiable flags of a version 2 PSBT that a signature of `sighash_type` forbids
+    fn restrict_modifiable(&mut self, sighash_type: u32) {
+        let Some(flags) = self.tx_modifiable.as_mut() else {
+            return;
+        };
+        let base = sighash_type as u8 & !SIGHASH_ANYONECANPAY;
+
+        if sighash_type as u8 & SIGHASH_ANYONECANPAY == 0 {
+            *flags &= !TX_MODIFIABLE_INPUTS;
+        }
+        if base != SIGHASH_NONE {
+            *flags &= !TX_MODIFIABLE_OUTPUTS;
+        }
+        if base == SIGHASH_SINGLE {
+            *flags |= TX_MODIFIABLE_SIGHASH_SINGLE;
+        }
+    }
+
+    /// Merge `other`, a PSBT of the same transaction, into the PSBT, the Combiner role
+    pub fn combine(&mut self, other: Self) -> Result<()> {
+        if self.version != other.version
+            || self.inputs.len() != other.inputs.len()
+            || self.unsigned_tx()?.txid() != other.unsigned_tx()?.txid()
+        {
+            bail!(PsbtError::TransactionMismatch);
+        }
+
+        for (xpub, source) in other.xpubs {
+            if !self.xpubs.iter().any(|(known, _)| *known == xpub) {
+                self.xpubs.push((xpub, source));
+            }
+        }
+        for (input, other) in self.inputs.iter_mut().zip(other.inputs) {
+            input.combine(other);
+        }
+        for (output, other) in self.outputs.iter_mut().zip(other.outputs) {
+            output.combine(other);
+        }
+        merge(&mut self.unknown, other.unknown);
+
+        Ok(())
+    }
+
+    /// Build the final script sig and witness of every input, the Finalizer role
+    ///
+    /// Supports P2PK, P2PKH, multisig, their P2SH and P2WSH forms, P2WPKH, and taproot key path or
+    /// single key and `multi_a` script path spends
+    pub fn finalise(&mut self) -> Result<()> {
+        (0..self.inputs.len()).try_for_each(|index| self.finalise_input(index))
+    }
+
+    /// Build the final script sig and witness of input `index`, clearing the fields used
+    pub fn finalise_input(&mut self, index: usize) -> Result<()> {
+        if self.inputs.get(index).is_some_and(Input::is_finalised) {
+            return Ok(());
+        }
+        let script_pubkey = self.spent_output(index)?.script_pubkey;
+
+        let (script_sig, witness) = if script_pubkey.is_p2tr() {
+            let Some(witness) = satisfy_taproot(&self.inputs[index]) else {
+                bail!(PsbtError::CannotFinalise(index));
+            };
+            (None, Some(witness))
+        } else {
+            let Some(spend) = self.spend(index, &script_pubkey)? else {
+                bail!(PsbtError::CannotFinalise(index));
+            };
+            let Some(stack) = satisfy_ecdsa(&spend.script_code, &self.inputs[index].partial_sigs)
+            else {
+                bail!(PsbtError::CannotFinalise(index));
+            };
+
+            let (mut script_sig, witness) = match spend.segwit {
+                true => {
+                    let mut witness = stack;
+                    witness.extend(
+                        spend
+                            .witness_script
+                            .map(|script| script.as_bytes().to_vec()),
+                    );
+                    (Script::new(), Some(witness))
+                }
+                false => {
+                    let script_sig = stack
+                        .iter()
+                        .fold(Script::new(), |script, item| script.push_slice(item));
+                    (script_sig, None)
+                }
+            };
+            if let Some(redeem) = spend.redeem_script {
+                script_sig = script_sig.push_slice(redeem.as_bytes());
+            }
+            ((!script_sig.is_empty()).then_some(script_sig), witness)
+        };
+
+        let input = &mut self.inputs[index];
+        input.final_script_sig = script_sig;
+        input.final_script_witness = witness;
+        input.clear_after_finalising();
+
+        Ok(())
+    }
+
+    /// Signed transaction of a finalised PSBT, the Extractor role
+    pub fn extract_tx(&self) -> Result<Transaction> {
+        let mut tx = self.unsigned_tx()?;
+        for (index, (tx_in, input)) in tx.inputs.iter_mut().zip(&self.inputs).enumerate() {
+            if !input.is_finalised() {
+                bail!(PsbtError::NotFinalised(index));
+            }
+            tx_in.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            tx_in.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+
+        Ok(tx)
+    }
+
+    /// Parse a binary PSBT
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let Some(mut reader) = bytes.strip_prefix(PSBT_MAGIC.as_slice()) else {
+            bail!(PsbtError::InvalidMagic);
+        };
+        let reader = &mut reader;
+
+        let mut tx = None;
+        let mut version = 0;
+        let (mut tx_version, mut fallback_lock_time, mut tx_modifiable) = (None, None, None);
+        let (mut input_count, mut output_count) = (None, None);
+        let mut xpubs = vec![];
+        let mut unknown = BTreeMap::new();
+
+        for pair in read_map(reader)? {
+            match pair.standard_type() {
+                Some(PSBT_GLOBAL_UNSIGNED_TX) => {
+                    pair.expect_empty_key()?;
+                    tx = Some(pair.parse_value(Transaction::parse_without_witness)?);
+                }
+                Some(PSBT_GLOBAL_XPUB) => {
+                    let xpub =
+                        ExtendedPublicKey::parse(&pair.key_data).map_err(|_| pair.invalid_key())?;
+                    xpubs.push((xpub, pair.parse_value(read_key_source)?));
+                }
+                Some(PSBT_GLOBAL_TX_VERSION) => {
+                    pair.expect_empty_key()?;
+                    tx_version = Some(i32::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_GLOBAL_FALLBACK_LOCKTIME) => {
+                    pair.expect_empty_key()?;
+                    fallback_lock_time = Some(u32::from_le_bytes(pair.parse_value(read_array)?));
+                }
+                Some(PSBT_GLOBAL_INPUT_COUNT) => {
+                    pair.expect_empty_key()?;
+                    input_count = Some(pair.parse_value(read_compact_size)?);
+                }
+                Some(PSBT_GLOBAL_OUTPUT_COUNT) => {
+                    pair.expect_empty_key()?;
+                    output_count = Some(pair.parse_value(read_compact_size)?);
+                }
+                Some(PSBT_GLOBAL_TX_MODIFIABLE) => {
+                    pair.expect_empty_key()?;
+                    let [flags] = pair.parse_value(read_array)?;
+                    tx_modifiable = Some(flags);
+                }
+                Some(PSBT_GLOBAL_VERSION) => {
+                    pair.expect_empty_key()?;
+                    version = u32::from_le_bytes(pair.parse_value(read_array)?);
+                }
+                _ => {
+                    unknown.insert(pair.key, pair.value);
+                }
+            }
+        }
+
+        let mut psbt = match version {
+            0 => {
+                for (field, present) in [
+                    ("PSBT_GLOBAL_TX_VERSION", tx_version.is_some()),
+                    (
+                        "PSBT_GLOBAL_FALLBACK_LOCKTIME",
+                        fallback_lock_time.is_some(),
+                    ),
+                    ("PSBT_GLOBAL_INPUT_COUNT", input_count.is_some()),
+                    ("PSBT_GLOBAL_OUTPUT_COUNT", output_count.is_some()),
+                    ("PSBT_GLOBAL_TX_MODIFIABLE", tx_modifiable.is_some()),
+                ] {
+                    if present {
+                        bail!(PsbtError::UnexpectedField(field, version));
+                    }
+                }
+                let Some(tx) = tx else {
+                    bail!(PsbtError::MissingField("PSBT_GLOBAL_UNSIGNED_TX"));
+                };
+                Self::from_unsigned_tx(tx)?
+            }
+            2 => {
+                if tx.is_some() {
+                    bail!(PsbtError::UnexpectedField(
+                        "PSBT_GLOBAL_UNSIGNED_TX",
+                        version
+                    ));
+                }
+                let Some(tx_version) = tx_version else {
+                    bail!(PsbtError::MissingField("PSBT_GLOBAL_TX_VERSION"));
+                };
+                let (Some(input_count), Some(output_count)) = (input_count, output_count) else {
+                    bail!(PsbtError::MissingField("PSBT_GLOBAL_INPUT_COUNT"));
+                };
+
+                let mut psbt = Self::new_v2(tx_version, fallback_lock_time);
+                psbt.tx_modifiable = tx_modifiable;
+                // maps are read below, the counts only decide how many
+                psbt.inputs = vec![Input::default(); input_count.min(reader.len() as u64) as usize];
+                psbt.outputs =
+                    vec![Output::default(); output_count.min(reader.len() as u64) as usize];
+                if psbt.inputs.len() as u64 != input_count
+                    || psbt.outputs.len() as u64 != output_count
+                {
+                    bail!(EncodingError::OversizedAllocation(
+                        input_count.max(output_count),
+                        reader.len()
+                    ));
+                }
+                psbt
+            }
+            _ => bail!(PsbtError::UnsupportedVersion(version)),
+        };
+        psbt.xpubs = xpubs;
+        psbt.unknown = unknown;
+
+        for (index, input) in psbt.inputs.iter_mut().enumerate() {
+            let parsed = Input::parse(reader, version)?;
+            *input = match version {
+                // version 0 inputs take the outpoint and sequence from the unsigned transaction
+                0 => Input {
+                    previous_output: input.previous_output,
+                    sequence: input.sequence,
+                    ..parsed
+                },
+                _ => parsed,
+            };
+            if input
+                .non_witness_utxo
+                .as_ref()
+                .is_some_and(|tx| tx.txid() != input.previous_output.txid)
+            {
+                bail!(PsbtError::UtxoMismatch(index));
+            }
+        }
+        for output in psbt.outputs.iter_mut() {
+            let parsed = Output::parse(reader, version)?;
+            *output = match version {
+                0 => Output {
+                    amount: output.amount,
+                    script: output.script.clone(),
+                    ..parsed
+                },
+                _ => parsed,
+            };
+        }
+
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(psbt)
+    }
+
+    /// Serialise the PSBT in the binary format
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = PSBT_MAGIC.to_vec();
+
+        if self.version < 2 {
+            let tx = self.transaction_with_lock_time(self.fallback_lock_time.unwrap_or(0));
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_UNSIGNED_TX,
+                &[],
+                &tx.serialise_without_witness(),
+            );
+        }
+        for (xpub, source) in &self.xpubs {
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_XPUB,
+                &xpub.serialise(),
+                &serialise_key_source(source),
+            );
+        }
+        if self.version >= 2 {
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_TX_VERSION,
+                &[],
+                &self.tx_version.to_le_bytes(),
+            );
+            if let Some(lock_time) = self.fallback_lock_time {
+                write_pair(
+                    &mut bytes,
+                    PSBT_GLOBAL_FALLBACK_LOCKTIME,
+                    &[],
+                    &lock_time.to_le_bytes(),
+                );
+            }
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_INPUT_COUNT,
+                &[],
+                &compact_size(self.inputs.len() as u64),
+            );
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_OUTPUT_COUNT,
+                &[],
+                &compact_size(self.outputs.len() as u64),
+            );
+            if let Some(flags) = self.tx_modifiable {
+                write_pair(&mut bytes, PSBT_GLOBAL_TX_MODIFIABLE, &[], &[flags]);
+            }
+        }
+        if self.version > 0 {
+            write_pair(
+                &mut bytes,
+                PSBT_GLOBAL_VERSION,
+                &[],
+                &self.version.to_le_bytes(),
+            );
+        }
+        write_unknown_and_close(&mut bytes, &self.unknown);
+
+        for input in &self.inputs {
+            bytes.extend(input.serialise(self.version));
+        }
+        for output in &self.outputs {
+            bytes.extend(output.serialise(self.version));
+        }
+
+        bytes
+    }
+}
+
+impl Display for Psbt {
+    /// Format the serialised PSBT as base64
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base64::encode(&self.serialise()))
+    }
+}
+
+impl FromStr for Psbt {
+    type Err = anyhow::Error;
+
+    /// Parse a base64 serialised PSBT
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(&base64::decode(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            script::taproot::output_key,
+            secp256k1::{point::Point, schnorr::SchnorrSignature, signature::Signature},
+            utils::encoding::reversed_hex,
+        },
+        ibig::UBig,
+    };
+
+    /// Valid version 0 vectors of BIP174
+    const BIP174_VALID: [&str; 5] = [
+        "70736274ff0100a00200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40000000000feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e240000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac000000000001076a47304402204759661797c01b036b25928948686218347d89864b719e1f7fcf57d1e511658702205309eabf56aa4d8891ffd111fdf1336f3a29da866d7f8486d75546ceedaf93190121035cdc61fc7ba971c0b501a646a2a83b102cb43881217ca682dc86e2d73fa882920001012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537f2e265405a34dbafa9e3dda01fb82308000000",
+        "70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf60000000000feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000001030401000000000000",
+        "70736274ff0100a00200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40000000000feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e240000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac00000000000100df0200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf30740ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e13000001012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537f2e265405a34dbafa9e3dda01fb8230800220202ead596687ca806043edc3de116cdf29d5e9257c196cd055cf698c8d02bf24e9910b4a6ba670000008000000080020000800022020394f62be9df19952c5587768aeb7698061ad2c4a25c894f47d8c162b4d7213d0510b4a6ba6700000080010000800200008000",
+        "70736274ff0100550200000001279a2323a5dfb51fc45f220fa58b0fc13e1e3342792a85d7e36cd6333b5cbc390000000000ffffffff01a05aea0b000000001976a914ffe9c0061097cc3b636f2cb0460fa4fc427d2b4588ac0000000000010120955eea0b0000000017a9146345200f68d189e1adc0df1c4d16ea8f14c0dbeb87220203b1341ccba7683b6af4f1238cd6e97e7167d569fac47f1e48d47541844355bd4646304302200424b58effaaa694e1559ea5c93bbfd4a89064224055cdf070b6771469442d07021f5c8eb0fea6516d60b8acb33ad64ede60e8785bfb3aa94b99bdf86151db9a9a010104220020771fd18ad459666dd49f3d564e3dbc42f4c84774e360ada16816a8ed488d5681010547522103b1341ccba7683b6af4f1238cd6e97e7167d569fac47f1e48d47541844355bd462103de55d1e1dac805e3f8a58c1fbf9b94c02f3dbaafe127fefca4995f26f82083bd52ae220603b1341ccba7683b6af4f1238cd6e97e7167d569fac47f1e48d47541844355bd4610b4a6ba67000000800000008004000080220603de55d1e1dac805e3f8a58c1fbf9b94c02f3dbaafe127fefca4995f26f82083bd10b4a6ba670000008000000080050000800000",
+        "70736274ff01003f0200000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff0000000000ffffffff010000000000000000036a010000000000000a0f0102030405060708090f0102030405060708090a0b0c0d0e0f0000",
+    ];
+
+    /// Invalid version 0 vectors of BIP174, a bare transaction, a truncated PSBT, script sigs in the
+    /// unsigned transaction, no unsigned transaction and a duplicate key
+    const BIP174_INVALID: [&str; 5] = [
+        "0200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf30740ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300",
+        "70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf60000000000feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000000",
+        "70736274ff0100fd0a010200000002ab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be4000000006a47304402204759661797c01b036b25928948686218347d89864b719e1f7fcf57d1e511658702205309eabf56aa4d8891ffd111fdf1336f3a29da866d7f8486d75546ceedaf93190121035cdc61fc7ba971c0b501a646a2a83b102cb43881217ca682dc86e2d73fa88292feffffffab0949a08c5af7c49b8212f417e2f15ab3f5c33dcf153821a8139f877a5b7be40100000000feffffff02603bea0b000000001976a914768a40bbd740cbe81d988e71de2a4d5c71396b1d88ac8e240000000000001976a9146f4620b553fa095e721b9ee0efe9fa039cca459788ac00000000000001012000e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787010416001485d13537f2e265405a34dbafa9e3dda01fb82308000000",
+        "70736274ff000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000000",
+        "70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf60000000000feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300000100fda5010100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab30000000001003f0200000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff0000000000ffffffff010000000000000000036a010000000000000000",
+    ];
+
+    /// Valid taproot vectors of BIP371
+    const BIP371_VALID: [&str; 6] = [
+        "70736274ff010052020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a01000000160014768e1eeb4cf420866033f80aceff0f9720744969000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232002202036b772a6db74d8753c98a827958de6c78ab3312109f37d3e0304484242ece73d818772b2da7540000800100008000000080000000000000000000",
+        "70736274ff010052020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a01000000160014768e1eeb4cf420866033f80aceff0f9720744969000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757011340bb53ec917bad9d906af1ba87181c48b86ace5aae2b53605a725ca74625631476fc6f5baedaf4f2ee0f477f36f58f3970d5b8273b7e497b97af2e3f125c97af342116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232002202036b772a6db74d8753c98a827958de6c78ab3312109f37d3e0304484242ece73d818772b2da7540000800100008000000080000000000000000000",
+        "70736274ff01005e020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f823202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc04215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac097c6e6fea5ff714ff5724499990810e406e98aa10f5bf7e5f6784bc1d0a9a6ce23204320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2acc06215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f82320fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca9acc021162cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d23901cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09772b2da7560000800100008002000080000000000000000021164320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b23901115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f8772b2da75600008001000080010000800000000000000000211650929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2116fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca939016f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970772b2da7560000800100008003000080000000000000000001172050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0011820f0362e2f75a6f420a5bde3eb221d96ae6720cf25f81890c95b1d775acb515e65000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000",
+        "70736274ff01005e020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff0148e6052a010000002251200a8cbdc86de1ce1c0f9caeb22d6df7ced3683fe423e05d1e402a879341d6f6f5000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07572116fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000011720fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2320001052050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac001066f02c02220736e572900fe1252589a2143c8f3c79f71a0412d2353af755e9701c782694a02ac02c02220631c5f3b5832b8fbdebfb19704ceeb323c21f40f7a24f43d68ef0cc26b125969ac01c0222044faa49a0338de488c8dfffecdfb6f329f380bd566ef20c8df6d813eab1c4273ac210744faa49a0338de488c8dfffecdfb6f329f380bd566ef20c8df6d813eab1c42733901f06b798b92a10ed9a9d0bbfd3af173a53b1617da3a4159ca008216cd856b2e0e772b2da75600008001000080010000800000000003000000210750929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2107631c5f3b5832b8fbdebfb19704ceeb323c21f40f7a24f43d68ef0cc26b125969390118ace409889785e0ea70ceebb8e1ca892a7a78eaede0f2e296cf435961a8f4ca772b2da756000080010000800200008000000000030000002107736e572900fe1252589a2143c8f3c79f71a0412d2353af755e9701c782694a02390129a5b4915090162d759afd3fe0f93fa3326056d0b4088cb933cae7826cb8d82c772b2da7560000800100008003000080000000000300000000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a0100000022512083698e458c6664e1595d75da2597de1e22ee97d798e706c4c0a4b5a9823cd743000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b0940bf818d9757d6ffeb538ba057fb4c1fc4e0f5ef186e765beb564791e02af5fd3d5e2551d4e34e33d86f276b82c99c79aed3f0395a081efcd2cc2c65dd7e693d7941144320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f840e1f1ab6fabfa26b236f21833719dc1d428ab768d80f91f9988d8abef47bfb863bb1f2a529f768c15f00ce34ec283cdc07e88f8428be28f6ef64043c32911811a4114fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca96f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae97040ec1f0379206461c83342285423326708ab031f0da4a253ee45aafa5b8c92034d8b605490f8cd13e00f989989b97e215faa36f12dee3693d2daccf3781c1757f66215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f823202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc04215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac097c6e6fea5ff714ff5724499990810e406e98aa10f5bf7e5f6784bc1d0a9a6ce23204320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b2acc06215c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f82320fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca9acc021162cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d23901cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b09772b2da7560000800100008002000080000000000000000021164320b0bf16f011b53ea7be615924aa7f27e5d29ad20ea1155d848676c3bad1b23901115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f8772b2da75600008001000080010000800000000000000000211650929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac005007c461e5d2116fa0f7a3cef3b1d0c0a6ce7d26e17ada0b2e5c92d19efad48b41859cb8a451ca939016f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970772b2da7560000800100008003000080000000000000000001172050929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0011820f0362e2f75a6f420a5bde3eb221d96ae6720cf25f81890c95b1d775acb515e65000105201124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e67121071124da7aec92ccd06c954562647f437b138b95721a84be2bf2276bbddab3e6711900772b2da7560000800100008000000080000000000500000000",
+    ];
+
+    /// Invalid taproot vectors of BIP371
+    const BIP371_INVALID: [&str; 10] = [
+        "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a075701172102fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa232000000",
+        "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757011342173bb3d36c074afb716fec6307a069a2e450b995f3c82785945ab8df0e24260dcd703b0cbf34de399184a9481ac2b3586db6601f026a77f7e4938481bc34751701aa000000",
+        "70736274ff010071020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02787c01000000000016001483a7e34bd99ff03a4962ef8a1a101bb295461ece606b042a010000001600147ac369df1b20e033d6116623957b0ac49f3c52e8000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757221602fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da75600008001000080000000800100000000000000000000",
+        "70736274ff01007d020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02887b0100000000001600142382871c7e8421a00093f754d91281e675874b9f606b042a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000001052102fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa23200",
+        "70736274ff01007d020000000127744ababf3027fe0d6cf23a96eee2efb188ef52301954585883e69b6624b2420000000000ffffffff02887b0100000000001600142382871c7e8421a00093f754d91281e675874b9f606b042a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a0757000000000001012b00f2052a010000002251205a2c2cf5b52cf31f83ad2e8da63ff03183ecd8f609c7510ae8a48e03910a07570000220702fe349064c98d6e2a853fa3c9b12bd8b304a19c195c60efa7ee2393046d3fa2321900772b2da7560000800100008000000080010000000000000000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6924214022cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b094089756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb0000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b094289756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb01010000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b69241142cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2cd970e15f53fc0c82f950fd560ffa919b76172be017368a89913af074f400b093989756aa3739ccc689ec0fcf3a360be32cc0b59b16e93a1e8bb4605726b2ca7a3ff706c4176649632b2cc68e1f912b8a578e3719ce7710885c7a966f49bcd43cb0000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926315c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e1f80023202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc00000",
+        "70736274ff01005e02000000019bd48765230bf9a72e662001f972556e54f0c6f97feb56bcb5600d817f6995260100000000ffffffff0148e6052a01000000225120030da4fce4f7db28c2cb2951631e003713856597fe963882cb500e68112cca63000000000001012b00f2052a01000000225120c2247efbfd92ac47f6f40b8d42d169175a19fa9fa10e4a25d7f35eb4dd85b6926115c150929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac06f7d62059e9497a1a4a267569d9876da60101aff38e3529b9b939ce7f91ae970115f2e490af7cc45c4f78511f36057ce5c5a5c56325a29fb44dfc203f356e123202cb13ac68248de806aa6a3659cf3c03eb6821d09c8114a4e868febde865bb6d2acc00000",
+    ];
+
+    /// BIP174 Finalizer and Extractor vector, the combined PSBT, the finalised PSBT and the signed
+    /// transaction
+    const BIP174_FINALISE: [&str; 3] = [
+        "70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000002202029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01220202dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d7483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01010304010000000104475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae2206029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f10d90c6a4f000000800000008000000080220602dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d710d90c6a4f0000008000000080010000800001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e887220203089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f012202023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e73473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d2010103040100000001042200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b2028903010547522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae2206023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7310d90c6a4f000000800000008003000080220603089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc10d90c6a4f00000080000000800200008000220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000",
+        "70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000000107da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae0001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e8870107232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b20289030108da0400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000",
+        "0200000000010258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd7500000000da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752aeffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d01000000232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b2028903ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f000400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00000000",
+    ];
+
+    /// Key controlling the outputs spent by the signing tests
+    fn test_key() -> Result<PrivateKey> {
+        PrivateKey::new(
+            "8c2e5a94f1d7b06e3a4c9d2f1e0b7a6c5d4e3f2a1b0c9d8e7f6a5b4c3d2e1f0a",
+            16,
+        )
+    }
+
+    /// Version 0 PSBT spending a P2WPKH and a taproot key path output of `key`
+    fn spending_psbt(key: &PrivateKey) -> Result<Psbt> {
+        let point = key.point();
+        let p2wpkh = Script::p2wpkh(&point.hash160(true));
+        let p2tr = Script::p2tr(&output_key(point, None)?.x_only());
+
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::new([1; 32], 0)));
+        tx.inputs.push(TxIn::new(OutPoint::new([2; 32], 1)));
+        tx.outputs.push(TxOut::new(90_000, p2wpkh.clone()));
+
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut::new(50_000, p2wpkh));
+        psbt.inputs[1].witness_utxo = Some(TxOut::new(50_000, p2tr));
+        psbt.inputs[1].tap_internal_key = Some(point.x_only());
+        Ok(psbt)
+    }
+
+    #[test]
+    fn bip174_valid_vectors() -> Result<()> {
+        for vector in BIP174_VALID {
+            let psbt = Psbt::parse(&hex::decode(vector)?)?;
+
+            assert_eq!(hex::encode(psbt.serialise()), vector);
+            assert_eq!(psbt.to_string().parse::<Psbt>()?, psbt);
+            assert_eq!(psbt.version, 0);
+        }
+
+        // P2SH-P2WPKH input with its redeem script
+        let psbt = Psbt::parse(&hex::decode(BIP174_VALID[0])?)?;
+        let redeem = psbt.inputs[1].redeem_script.as_ref().unwrap();
+        assert!(redeem.is_p2wpkh());
+        assert_eq!(redeem.to_p2sh(), psbt.spent_output(1)?.script_pubkey);
+        assert!(psbt.inputs[0].final_script_sig.is_some());
+
+        // unknown input pairs are kept with their full key
+        let psbt = Psbt::parse(&hex::decode(BIP174_VALID[4])?)?;
+        assert_eq!(
+            reversed_hex(&psbt.unsigned_tx()?.txid()),
+            "75c5c9665a570569ad77dd1279e6fd4628a093c4dcbf8d41532614044c14c115"
+        );
+        assert_eq!(
+            psbt.inputs[0].unknown[&hex::decode("0f010203040506070809")?],
+            hex::decode("0102030405060708090a0b0c0d0e0f")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bip174_invalid_vectors() -> Result<()> {
+        let errors = BIP174_INVALID
+            .iter()
+            .map(|vector| Psbt::parse(&hex::decode(vector).unwrap()).unwrap_err())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors[0].downcast_ref(), Some(&PsbtError::InvalidMagic));
+        assert_eq!(
+            errors[2].downcast_ref(),
+            Some(&PsbtError::UnsignedTxHasScripts(0))
+        );
+        assert_eq!(
+            errors[3].downcast_ref(),
+            Some(&PsbtError::MissingField("PSBT_GLOBAL_UNSIGNED_TX"))
+        );
+        assert_eq!(
+            errors[4].downcast_ref(),
+            Some(&PsbtError::DuplicateKey("00".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bip371_vectors() -> Result<()> {
+        for vector in BIP371_VALID {
+            let psbt = Psbt::parse(&hex::decode(vector)?)?;
+            assert_eq!(Psbt::parse(&psbt.serialise())?, psbt);
+        }
+
+        let psbt = Psbt::parse(&hex::decode(BIP371_VALID[1])?)?;
+        let internal_key = psbt.inputs[0].tap_internal_key.unwrap();
+        assert!(psbt.inputs[0].tap_key_origins.contains_key(&internal_key));
+        assert!(psbt.inputs[0].tap_key_sig.is_some());
+
+        let psbt = Psbt::parse(&hex::decode(BIP371_VALID[4])?)?;
+        assert!(psbt.outputs[0].tap_tree.is_some());
+
+        let psbt = Psbt::parse(&hex::decode(BIP371_VALID[5])?)?;
+        assert!(psbt.inputs[0].tap_merkle_root.is_some());
+        assert!(!psbt.inputs[0].tap_scripts.is_empty());
+        assert!(!psbt.inputs[0].tap_script_sigs.is_empty());
+
+        for vector in BIP371_INVALID {
+            assert!(Psbt::parse(&hex::decode(vector)?).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn finalise_and_extract() -> Result<()> {
+        let [combined, finalised, signed] = BIP174_FINALISE;
+        let mut psbt = Psbt::parse(&hex::decode(combined)?)?;
+
+        // the partial signatures commit to our sighashes
+        let tx = psbt.unsigned_tx()?;
+        for (index, input) in psbt.inputs.iter().enumerate() {
+            let spent = psbt.spent_output(index)?;
+            let (script_code, segwit) = match &input.witness_script {
+                Some(script) => (script, true),
+                None => (input.redeem_script.as_ref().unwrap(), false),
+            };
+            for (sec, sig) in &input.partial_sigs {
+                let (sighash_type, der) = sig.split_last().unwrap();
+                let z = match segwit {
+                    true => {
+                        tx.segwit_v0_sighash(index, script_code, spent.value, *sighash_type as u32)?
+                    }
+                    false => tx.legacy_sighash(index, script_code, *sighash_type as u32)?,
+                };
+                assert!(Point::from_sec(sec)?
+                    .verify(UBig::from_be_bytes(&z), Signature::from_der(der)?));
+            }
+        }
+
+        assert_eq!(
+            psbt.extract_tx().unwrap_err().downcast_ref(),
+            Some(&PsbtError::NotFinalised(0))
+        );
+        psbt.finalise()?;
+        assert_eq!(hex::encode(psbt.serialise()), finalised);
+        assert_eq!(psbt.extract_tx()?.to_string(), signed);
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_finalise() -> Result<()> {
+        let key = test_key()?;
+        let mut psbt = spending_psbt(&key)?;
+
+        assert_eq!(psbt.sign(std::slice::from_ref(&key))?, 2);
+        let tx = psbt.unsigned_tx()?;
+        let spent_outputs = vec![psbt.spent_output(0)?, psbt.spent_output(1)?];
+
+        let sec = key.point().sec(true);
+        let (sighash_type, der) = psbt.inputs[0].partial_sigs[&sec].split_last().unwrap();
+        assert_eq!(*sighash_type, SIGHASH_ALL);
+        let z = tx.segwit_v0_sighash(
+            0,
+            &Script::p2pkh(&hash160(&sec)),
+            50_000,
+            SIGHASH_ALL as u32,
+        )?;
+        assert!(key
+            .point()
+            .verify(UBig::from_be_bytes(&z), Signature::from_der(der)?));
+
+        let sig = SchnorrSignature::from_bytes(psbt.inputs[1].tap_key_sig.as_ref().unwrap())?;
+        let z = tx.taproot_sighash(1, &spent_outputs, SIGHASH_DEFAULT, None, None)?;
+        assert!(output_key(key.point(), None)?.verify_schnorr(&z, &sig));
+
+        psbt.finalise()?;
+        let signed = psbt.extract_tx()?;
+        assert_eq!(
+            signed.inputs[0].witness,
+            vec![
+                psbt.inputs[0].final_script_witness.clone().unwrap()[0].clone(),
+                sec
+            ]
+        );
+        assert_eq!(signed.inputs[1].witness.len(), 1);
+        assert!(signed
+            .inputs
+            .iter()
+            .all(|input| input.script_sig.is_empty()));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_legacy_needs_previous_transaction() -> Result<()> {
+        let key = test_key()?;
+        let p2pkh = Script::p2pkh(&key.point().hash160(true));
+        let mut previous = Transaction::new(2, 0);
+        previous.inputs.push(TxIn::new(OutPoint::new([1; 32], 0)));
+        previous.outputs.push(TxOut::new(50_000, p2pkh.clone()));
+
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::new(previous.txid(), 0)));
+        tx.outputs.push(TxOut::new(40_000, p2pkh.clone()));
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+
+        // the amount of a witness UTXO cannot be trusted for a legacy spend
+        psbt.inputs[0].witness_utxo = Some(TxOut::new(50_000, p2pkh));
+        assert_eq!(psbt.sign(std::slice::from_ref(&key))?, 0);
+
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].non_witness_utxo = Some(previous);
+        psbt.inputs[0].sighash_type = Some(0x101);
+        let result = psbt.sign(std::slice::from_ref(&key));
+        assert_eq!(
+            result.err().unwrap().downcast::<TransactionError>()?,
+            TransactionError::InvalidSighashType(0x101)
+        );
+
+        psbt.inputs[0].sighash_type = None;
+        assert_eq!(psbt.sign(std::slice::from_ref(&key))?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn update_and_sign_script_path() -> Result<()> {
+        let key = test_key()?;
+        let descriptor: Descriptor = format!(
+            "tr(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,pk([d34db33f/86'/0'/0']{}))",
+            hex::encode(key.point().x_only())
+        )
+        .parse()?;
+
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::new([1; 32], 0)));
+        tx.outputs
+            .push(TxOut::new(9_000, descriptor.script_pubkey(0)?));
+        let mut psbt = Psbt::from_unsigned_tx(tx)?;
+        psbt.inputs[0].witness_utxo = Some(TxOut::new(10_000, descriptor.script_pubkey(0)?));
+        psbt.update_input_with_descriptor(0, &descriptor, 0)?;
+        psbt.update_output_with_descriptor(0, &descriptor, 0)?;
+
+        let input = &psbt.inputs[0];
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert_eq!(input.tap_key_origins[&key.point().x_only()].0.len(), 1);
+        assert_eq!(psbt.outputs[0].tap_tree.as_ref().unwrap().len(), 1);
+        assert_eq!(Psbt::parse(&psbt.serialise())?, psbt);
+
+        // the internal key is not ours, so only the leaf is signed
+        assert_eq!(psbt.sign(std::slice::from_ref(&key))?, 1);
+        psbt.finalise()?;
+        assert_eq!(psbt.extract_tx()?.inputs[0].witness.len(), 3);
+
+        let mut other = psbt.clone();
+        other.inputs[0].witness_utxo = Some(TxOut::new(10_000, Script::new()));
+        assert_eq!(
+            other
+                .update_input_with_descriptor(0, &descriptor, 0)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&PsbtError::ScriptMismatch(0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn combine_signatures() -> Result<()> {
+        let key = test_key()?;
+        let other = PrivateKey::new("3", 16)?;
+        let mut psbt = spending_psbt(&key)?;
+        let mut multisig = Transaction::new(2, 0);
+        multisig.inputs.push(TxIn::new(OutPoint::new([3; 32], 0)));
+        let witness_script = Script::multisig(2, &[key.point().clone(), other.point().clone()]);
+
+        let mut first = Psbt::from_unsigned_tx(multisig)?;
+        first.inputs[0].witness_utxo = Some(TxOut::new(10_000, witness_script.to_p2wsh()));
+        first.inputs[0].witness_script = Some(witness_script);
+        let mut second = first.clone();
+
+        first.sign(std::slice::from_ref(&key))?;
+        assert_eq!(
+            first.finalise().unwrap_err().downcast_ref(),
+            Some(&PsbtError::CannotFinalise(0))
+        );
+        second.sign(std::slice::from_ref(&other))?;
+        first.combine(second)?;
+        first.finalise()?;
+
+        // dummy element, both signatures in key order and the witness script
+        let witness = &first.extract_tx()?.inputs[0].witness;
+        assert_eq!(witness.len(), 4);
+        assert!(witness[0].is_empty());
+
+        psbt.inputs.pop();
+        assert_eq!(
+            first.combine(psbt).unwrap_err().downcast_ref(),
+            Some(&PsbtError::TransactionMismatch)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn version_2_lock_time() -> Result<()> {
+        let mut psbt = Psbt::new_v2(2, Some(10));
+        assert_eq!(psbt.lock_time()?, 10);
+
+        let mut input = Input::new(OutPoint::new([1; 32], 0));
+        input.required_height_lock_time = Some(700_000);
+        input.required_time_lock_time = Some(1_700_000_000);
+        psbt.add_input(input)?;
+        psbt.add_output(Output::new(1_000, Script::op_return(b"psbt")))?;
+        assert_eq!(psbt.lock_time()?, 700_000);
+
+        let mut input = Input::new(OutPoint::new([2; 32], 0));
+        input.required_time_lock_time = Some(1_800_000_000);
+        psbt.add_input(input)?;
+        assert_eq!(psbt.lock_time()?, 1_800_000_000);
+
+        let mut input = Input::new(OutPoint::new([3; 32], 0));
+        input.required_height_lock_time = Some(800_000);
+        assert_eq!(
+            psbt.add_input(input).unwrap_err().downcast_ref(),
+            Some(&PsbtError::LockTimeConflict)
+        );
+        assert_eq!(psbt.inputs.len(), 2);
+
+        let parsed: Psbt = psbt.to_string().parse()?;
+        assert_eq!(parsed, psbt);
+        assert_eq!(parsed.unsigned_tx()?.lock_time, 1_800_000_000);
+
+        // a version 0 PSBT cannot be changed and a version 2 one must not carry the transaction
+        let mut v0 = Psbt::from_unsigned_tx(psbt.unsigned_tx()?)?;
+        assert!(v0.add_output(Output::default()).is_err());
+        v0.version = 2;
+        let mut bytes = v0.serialise();
+        bytes.splice(5..5, {
+            let mut tx = vec![];
+            write_pair(
+                &mut tx,
+                PSBT_GLOBAL_UNSIGNED_TX,
+                &[],
+                &psbt.unsigned_tx()?.serialise(),
+            );
+            tx
+        });
+        assert_eq!(
+            Psbt::parse(&bytes).unwrap_err().downcast_ref(),
+            Some(&PsbtError::UnexpectedField("PSBT_GLOBAL_UNSIGNED_TX", 2))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use {
+    super::{errors::ScriptError, raw::Script},
+    crate::{
+        network::Network,
+        secp256k1::point::Point,
+        utils::{
+            base58::{decode_check, encode_check},
+            bech32::{decode_segwit, encode_segwit},
+        },
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        str::FromStr,
+    },
+};
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Output script commitment carried by an address
+pub enum Payload {
+    /// HASH160 of a public key
+    PubkeyHash([u8; 20]),
+    /// HASH160 of a redeem script
+    ScriptHash([u8; 20]),
+    /// Segwit witness program
+    WitnessProgram { version: u8, program: Vec<u8> },
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// Human readable encoding of an output script on a network
+pub struct Address {
+    network: Network,
+    payload: Payload,
+}
+
+impl Address {
+    /// Create an address from its payload
+    pub fn new(network: Network, payload: Payload) -> Self {
+        Self { network, payload }
+    }
+
+    /// Pay to public key hash address
+    pub fn p2pkh(point: &Point, compressed: bool, network: Network) -> Self {
+        Self::new(network, Payload::PubkeyHash(point.hash160(compressed)))
+    }
+
+    /// Pay to script hash address of `redeem_script`
+    pub fn p2sh(redeem_script: &Script, network: Network) -> Self {
+        Self::new(network, Payload::ScriptHash(redeem_script.script_hash()))
+    }
+
+    /// Pay to witness public key hash address
+    pub fn p2wpkh(point: &Point, network: Network) -> Self {
+        Self::new(
+            network,
+            Payload::WitnessProgram {
+                version: 0,
+                program: point.hash160(true).to_vec(),
+            },
+        )
+    }
+
+    /// Pay to witness script hash address of `witness_script`
+    pub fn p2wsh(witness_script: &Script, network: Network) -> Self {
+        Self::new(
+            network,
+            Payload::WitnessProgram {
+                version: 0,
+                program: witness_script.witness_script_hash().to_vec(),
+            },
+        )
+    }
+
+    /// Pay to taproot address of the x-only output key
+    pub fn p2tr(output_key: &[u8; 32], network: Network) -> Self {
+        Self::new(
+            network,
+            Payload::WitnessProgram {
+                version: 1,
+                program: output_key.to_vec(),
+            },
+        )
+    }
+
+    /// Address of an output script, if it has a standard address form
+    pub fn from_script(script: &Script, network: Network) -> Result<Self> {
+        let bytes = script.as_bytes();
+        let payload = if script.is_p2pkh() {
+            Payload::PubkeyHash(bytes[3..23].try_into()?)
+        } else if script.is_p2sh() {
+            Payload::ScriptHash(bytes[2..22].try_into()?)
+        } else if let Some((version, program)) = script.witness_version_and_program() {
+            Payload::WitnessProgram {
+                version,
+                program: program.to_vec(),
+            }
+        } else {
+            bail!(ScriptError::NoAddressForm(script.to_string()));
+        };
+
+        Ok(Self::new(network, payload))
+    }
+
+    /// Output script paying to the address
+    pub fn script_pubkey(&self) -> Script {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => Script::p2pkh(hash),
+            Payload::ScriptHash(hash) => Script::p2sh(hash),
+            Payload::WitnessProgram { version, program } => {
+                Script::witness_program(*version, program)
+            }
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => write!(
+                f,
+                "{}",
+                encode_check(&[&[self.network.p2pkh_prefix()], hash.as_slice()].concat())
+            ),
+            Payload::ScriptHash(hash) => write!(
+                f,
+                "{}",
+                encode_check(&[&[self.network.p2sh_prefix()], hash.as_slice()].concat())
+            ),
+            Payload::WitnessProgram { version, program } => write!(
+                f,
+                "{}",
+                encode_segwit(self.network.bech32_hrp(), *version, program)
+                    .map_err(|_| fmt::Error)?
+            ),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    /// Parse an address, base58 testnet addresses are also valid on signet and regtest
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok((hrp, version, program)) = decode_segwit(s) {
+            let network = match hrp.as_str() {
+                "bc" => Network::Mainnet,
+                "tb" => Network::Testnet,
+                "bcrt" => Network::Regtest,
+                _ => bail!(ScriptError::InvalidAddress(s.to_string())),
+            };
+            return Ok(Self::new(
+                network,
+                Payload::WitnessProgram { version, program },
+            ));
+        }
+
+        let data = decode_check(s).map_err(|_| ScriptError::InvalidAddress(s.to_string()))?;
+        let (prefix, hash) = match data.split_first() {
+            Some((prefix, hash)) if hash.len() == 20 => (*prefix, hash.try_into()?),
+            _ => bail!(ScriptError::InvalidAddress(s.to_string())),
+        };
+
+        let (network, payload) = match prefix {
+            0x00 => (Network::Mainnet, Payload::PubkeyHash(hash)),
+            0x05 => (Network::Mainnet, Payload::ScriptHash(hash)),
+            0x6f => (Network::Testnet, Payload::PubkeyHash(hash)),
+            0xc4 => (Network::Testnet, Payload::ScriptHash(hash)),
+            _ => bail!(ScriptError::InvalidAddress(s.to_string())),
+        };
+
+        Ok(Self::new(network, payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn p2pkh_address() -> Result<()> {
+        let point =
+            Point::parse("0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352")?;
+        let address = Address::p2pkh(&point, true, Network::Mainnet);
+
+        assert_eq!(address.to_string(), "1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAs");
+        assert_eq!(
+            "1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAs".parse::<Address>()?,
+            address
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn p2wpkh_address() -> Result<()> {
+        let point =
+            Point::parse("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
+        let address = Address::p2wpkh(&point, Network::Mainnet);
+
+        assert_eq!(
+            address.to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert!(address.script_pubkey().is_p2wpkh());
+        Ok(())
+    }
+
+    #[test]
+    fn script_round_trip() -> Result<()> {
+        for s in [
+            "3JZJNxvDKe6Y55ZaF5223XHwfF2eoMNnoV",
+            "bc1qpq2cfgz5lktxzr5zqv7nrzz46hsvq3492ump9pz8rzcl8wqtwqcspx5y6a",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+        ] {
+            let address: Address = s.parse()?;
+            let script = address.script_pubkey();
+
+            assert_eq!(address.to_string(), s);
+            assert_eq!(Address::from_script(&script, address.network())?, address);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn testnet_and_regtest_prefixes() -> Result<()> {
+        let point =
+            Point::parse("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
+        let testnet = Address::p2pkh(&point, true, Network::Testnet).to_string();
+        let regtest = Address::p2wpkh(&point, Network::Regtest).to_string();
+
+        assert!(testnet.starts_with('m') || testnet.starts_with('n'));
+        assert!(regtest.starts_with("bcrt1q"));
+        assert_eq!(testnet.parse::<Address>()?.network(), Network::Testnet);
+        assert_eq!(regtest.parse::<Address>()?.network(), Network::Regtest);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_addresses() -> Result<()> {
+        for s in ["1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAt", "xx1qqqqqqqqqqqqq", ""] {
+            assert!(s.parse::<Address>().is_err(), "{s}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Scripts and Addresses
+pub enum ScriptError {
+    #[error("push extends past the end of the script")]
+    TruncatedPush,
+    #[error("script number of {0} bytes is too long")]
+    NumOverflow(usize),
+    #[error("script number is not minimally encoded")]
+    NonMinimalNum,
+    #[error("script `{0}` has no address form")]
+    NoAddressForm(String),
+    #[error("invalid address `{0}`")]
+    InvalidAddress(String),
+    #[error("taproot tweak is not a valid scalar")]
+    InvalidTweak,
+    #[error("script `{0}` is not a leaf of the taproot tree")]
+    LeafNotInTree(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors raised while verifying a script, with the conditions of Bitcoin Core's script errors
+pub enum InterpreterError {
+    #[error("script evaluated without error but finished with a false or empty top stack element")]
+    EvalFalse,
+    #[error("OP_RETURN was encountered")]
+    OpReturn,
+    #[error("script is larger than 10000 bytes")]
+    ScriptSize,
+    #[error("push of more than 520 bytes")]
+    PushSize,
+    #[error("more than 201 non-push operations")]
+    OpCount,
+    #[error("more than 1000 stack elements")]
+    StackSize,
+    #[error("signature count negative or greater than the public key count")]
+    SigCount,
+    #[error("public key count negative or above the limit")]
+    PubkeyCount,
+    #[error("script failed an OP_VERIFY operation")]
+    Verify,
+    #[error("script failed an OP_EQUALVERIFY operation")]
+    EqualVerify,
+    #[error("script failed an OP_CHECKMULTISIGVERIFY operation")]
+    CheckMultisigVerify,
+    #[error("script failed an OP_CHECKSIGVERIFY operation")]
+    CheckSigVerify,
+    #[error("script failed an OP_NUMEQUALVERIFY operation")]
+    NumEqualVerify,
+    #[error("opcode {0:#04x} is missing or not understood")]
+    BadOpcode(u8),
+    #[error("disabled opcode {0:#04x}")]
+    DisabledOpcode(u8),
+    #[error("operation not valid with the current stack size")]
+    InvalidStackOperation,
+    #[error("operation not valid with the current altstack size")]
+    InvalidAltstackOperation,
+    #[error("invalid OP_IF construction")]
+    UnbalancedConditional,
+    #[error("script number is invalid or overflows")]
+    InvalidNumber,
+    #[error("negative lock time")]
+    NegativeLocktime,
+    #[error("lock time requirement not satisfied")]
+    UnsatisfiedLocktime,
+    #[error("non-canonical DER signature")]
+    SigDer,
+    #[error("only push operators allowed in signatures")]
+    SigPushOnly,
+    #[error("dummy CHECKMULTISIG argument must be zero")]
+    SigNullDummy,
+    #[error("stack size must be exactly one after execution")]
+    CleanStack,
+    #[error("witness program has an incorrect length")]
+    WitnessProgramWrongLength,
+    #[error("witness program was passed an empty witness")]
+    WitnessProgramWitnessEmpty,
+    #[error("witness program hash mismatch")]
+    WitnessProgramMismatch,
+    #[error("witness requires an empty scriptSig")]
+    WitnessMalleated,
+    #[error("witness requires only a redeem script in the scriptSig")]
+    WitnessMalleatedP2sh,
+    #[error("witness provided for a non-witness script")]
+    WitnessUnexpected,
+    #[error("invalid Schnorr signature size")]
+    SchnorrSigSize,
+    #[error("invalid Schnorr signature hash type")]
+    SchnorrSigHashType,
+    #[error("invalid Schnorr signature")]
+    SchnorrSig,
+    #[error("invalid taproot control block size")]
+    TaprootWrongControlSize,
+    #[error("too much signature validation relative to the witness weight")]
+    TapscriptValidationWeight,
+    #[error("OP_CHECKMULTISIG(VERIFY) is not available in tapscript")]
+    TapscriptCheckMultisig,
+    #[error("OP_IF and OP_NOTIF arguments must be minimal in tapscript")]
+    TapscriptMinimalIf,
+    #[error("empty public key in tapscript")]
+    PubkeyType,
+}
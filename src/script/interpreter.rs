@@ -0,0 +1,1378 @@
+use {
+    super::{
+        errors::InterpreterError,
+        opcodes::*,
+        raw::{
+            decode_num_lax, encode_num, Instruction, Instructions, Script,
+            MAX_PUBKEYS_PER_MULTISIG, MAX_SCRIPT_SIZE,
+        },
+        taproot::{
+            output_key, tap_branch_hash, tap_leaf_hash, TAPROOT_CONTROL_MAX_NODE_COUNT,
+            TAPSCRIPT_LEAF_VERSION,
+        },
+    },
+    crate::{
+        secp256k1::{point::Point, schnorr::SchnorrSignature, signature::Signature},
+        transaction::{
+            errors::TransactionError,
+            sighash::SIGHASH_DEFAULT,
+            tx::{
+                Transaction, TxOut, LOCKTIME_THRESHOLD, SEQUENCE_FINAL,
+                SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK,
+                SEQUENCE_LOCKTIME_TYPE_FLAG,
+            },
+        },
+        utils::{
+            encoding::{compact_size, var_bytes},
+            hash::{hash160, hash256, ripemd160, sha1, sha256},
+        },
+    },
+    anyhow::{bail, Result},
+    ibig::UBig,
+};
+
+/// Evaluate P2SH redeem scripts, as specified by BIP16
+pub const SCRIPT_VERIFY_P2SH: u32 = 1 << 0;
+
+/// Require strict DER signatures, as specified by BIP66
+pub const SCRIPT_VERIFY_DERSIG: u32 = 1 << 2;
+
+/// Require the extra argument of `OP_CHECKMULTISIG` to be empty, as specified by BIP147
+pub const SCRIPT_VERIFY_NULLDUMMY: u32 = 1 << 4;
+
+/// Enable `OP_CHECKLOCKTIMEVERIFY`, as specified by BIP65
+pub const SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+
+/// Enable `OP_CHECKSEQUENCEVERIFY`, as specified by BIP112
+pub const SCRIPT_VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+
+/// Verify witness programs, as specified by BIP141
+pub const SCRIPT_VERIFY_WITNESS: u32 = 1 << 11;
+
+/// Verify taproot outputs and tapscripts, as specified by BIP341 and BIP342
+pub const SCRIPT_VERIFY_TAPROOT: u32 = 1 << 17;
+
+/// Flags of every soft fork, which blocks must satisfy
+pub const MANDATORY_SCRIPT_VERIFY_FLAGS: u32 = SCRIPT_VERIFY_P2SH
+    | SCRIPT_VERIFY_DERSIG
+    | SCRIPT_VERIFY_NULLDUMMY
+    | SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY
+    | SCRIPT_VERIFY_CHECKSEQUENCEVERIFY
+    | SCRIPT_VERIFY_WITNESS
+    | SCRIPT_VERIFY_TAPROOT;
+
+/// Largest element that can be pushed to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// Most non-push operations a pre-tapscript script may execute
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Most elements the stack and altstack may hold together
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Validation weight each signature of a tapscript consumes from its budget
+const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+
+/// Validation weight a tapscript is given on top of the size of its witness
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// First byte of the last witness element of a taproot spend that marks it as the annex
+const ANNEX_TAG: u8 = 0x50;
+
+/// Size of a control block without any merkle path
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+
+/// Size of each node of the merkle path of a control block
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+
+/// Opcodes that are disabled, failing the script even if they are not executed
+const DISABLED_OPCODES: [u8; 15] = [
+    OP_CAT, OP_SUBSTR, OP_LEFT, OP_RIGHT, OP_INVERT, OP_AND, OP_OR, OP_XOR, OP_2MUL, OP_2DIV,
+    OP_MUL, OP_DIV, OP_MOD, OP_LSHIFT, OP_RSHIFT,
+];
+
+type Stack = Vec<Vec<u8>>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Rules a script is executed under
+pub enum SigVersion {
+    /// Output scripts, P2SH redeem scripts and their scriptSigs
+    Base,
+    /// Segwit v0 witness scripts, as specified by BIP143
+    WitnessV0,
+    /// Tapscript leaves, as specified by BIP342
+    Tapscript,
+}
+
+/// Input of a transaction whose scripts are verified, checking signatures and lock times against it
+pub struct TransactionChecker<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    spent_outputs: &'a [TxOut],
+}
+
+/// State of a taproot spend shared by the signature checks of its script
+struct ExecData {
+    /// Leaf hash of the executed tapscript, or `None` for key path spends
+    leaf_hash: Option<[u8; 32]>,
+    /// Opcode position of the last executed `OP_CODESEPARATOR`
+    codesep_pos: u32,
+    validation_weight_left: i64,
+    /// Annex, including its tag
+    annex: Option<Vec<u8>>,
+}
+
+impl ExecData {
+    fn new() -> Self {
+        Self {
+            leaf_hash: None,
+            codesep_pos: u32::MAX,
+            validation_weight_left: 0,
+            annex: None,
+        }
+    }
+}
+
+impl<'a> TransactionChecker<'a> {
+    /// Checker of input `index` of `tx`, which spends `spent_outputs` with all of its inputs
+    pub fn new(tx: &'a Transaction, index: usize, spent_outputs: &'a [TxOut]) -> Result<Self> {
+        if index >= tx.inputs.len() {
+            bail!(TransactionError::InputOutOfRange(index, tx.inputs.len()));
+        }
+        if spent_outputs.len() != tx.inputs.len() {
+            bail!(TransactionError::SpentOutputsMismatch(
+                spent_outputs.len(),
+                tx.inputs.len()
+            ));
+        }
+
+        Ok(Self {
+            tx,
+            index,
+            spent_outputs,
+        })
+    }
+
+    /// If `sig`, a DER signature followed by its sighash type, signs the input for `pubkey`
+    ///
+    /// Signatures are parsed laxly like pre-BIP66 nodes did, strict DER being enforced beforehand
+    /// by `check_signature_encoding` when `SCRIPT_VERIFY_DERSIG` is set.
+    fn check_ecdsa(
+        &self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &Script,
+        sig_version: SigVersion,
+    ) -> bool {
+        let Some((sighash_type, der)) = sig.split_last() else {
+            return false;
+        };
+        let (Ok(point), Ok(signature)) = (Point::from_sec(pubkey), Signature::from_der_lax(der))
+        else {
+            return false;
+        };
+        let sighash = match sig_version {
+            SigVersion::WitnessV0 => self.tx.segwit_v0_sighash(
+                self.index,
+                script_code,
+                self.spent_outputs[self.index].value,
+                *sighash_type as u32,
+            ),
+            _ => self
+                .tx
+                .legacy_sighash(self.index, script_code, *sighash_type as u32),
+        };
+
+        sighash.is_ok_and(|z| point.verify(UBig::from_be_bytes(&z), signature))
+    }
+
+    /// Check `sig`, a BIP340 signature optionally followed by its sighash type, for the x-only
+    /// `pubkey`
+    fn check_schnorr(&self, sig: &[u8], pubkey: &[u8], exec: &ExecData) -> Result<()> {
+        // an explicit sighash type cannot be the default one
+        let (sig, sighash_type) = match sig.len() {
+            64 => (sig, SIGHASH_DEFAULT),
+            65 if sig[64] != SIGHASH_DEFAULT => (&sig[..64], sig[64]),
+            65 => bail!(InterpreterError::SchnorrSigHashType),
+            _ => bail!(InterpreterError::SchnorrSigSize),
+        };
+        let annex = exec.annex.as_deref();
+        let sighash = match &exec.leaf_hash {
+            Some(leaf_hash) => self.tx.tapscript_sighash(
+                self.index,
+                self.spent_outputs,
+                sighash_type,
+                leaf_hash,
+                exec.codesep_pos,
+                annex,
+            ),
+            None => {
+                self.tx
+                    .taproot_sighash(self.index, self.spent_outputs, sighash_type, None, annex)
+            }
+        };
+        let Ok(sighash) = sighash else {
+            bail!(InterpreterError::SchnorrSigHashType);
+        };
+
+        let valid = match (
+            Point::from_x_only(pubkey),
+            SchnorrSignature::from_bytes(sig),
+        ) {
+            (Ok(point), Ok(sig)) => point.verify_schnorr(&sighash, &sig),
+            _ => false,
+        };
+        if !valid {
+            bail!(InterpreterError::SchnorrSig);
+        }
+
+        Ok(())
+    }
+
+    /// If the transaction satisfies the absolute lock time `lock_time` of `OP_CHECKLOCKTIMEVERIFY`
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        // heights and times cannot be compared
+        if (tx_lock_time < threshold) != (lock_time < threshold) || lock_time > tx_lock_time {
+            return false;
+        }
+
+        // a final input disables the lock time of the transaction
+        self.tx.inputs[self.index].sequence != SEQUENCE_FINAL
+    }
+
+    /// If the input satisfies the relative lock time `sequence` of `OP_CHECKSEQUENCEVERIFY`
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.tx.inputs[self.index].sequence as i64;
+        if (self.tx.version as u32) < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0
+        {
+            return false;
+        }
+
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let (tx_sequence, sequence) = (tx_sequence & mask, sequence & mask);
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+        // blocks and times cannot be compared
+        (tx_sequence < type_flag) == (sequence < type_flag) && sequence <= tx_sequence
+    }
+}
+
+/// If a stack element is true, any non-zero value other than negative zero
+pub fn cast_to_bool(bytes: &[u8]) -> bool {
+    match bytes.split_last() {
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || last & 0x7f != 0,
+        None => false,
+    }
+}
+
+fn bool_bytes(value: bool) -> Vec<u8> {
+    match value {
+        true => vec![1],
+        false => vec![],
+    }
+}
+
+/// Script number of at most `max_len` bytes in a stack element
+fn num(bytes: &[u8], max_len: usize) -> Result<i64> {
+    decode_num_lax(bytes, max_len).map_err(|_| InterpreterError::InvalidNumber.into())
+}
+
+/// Fail unless the stack holds at least `count` elements
+fn require(stack: &Stack, count: usize) -> Result<()> {
+    if stack.len() < count {
+        bail!(InterpreterError::InvalidStackOperation);
+    }
+
+    Ok(())
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>> {
+    stack
+        .pop()
+        .ok_or_else(|| InterpreterError::InvalidStackOperation.into())
+}
+
+/// Element `depth` places from the top of the stack, the top being at depth 1
+fn top(stack: &Stack, depth: usize) -> Result<&Vec<u8>> {
+    require(stack, depth)?;
+    Ok(&stack[stack.len() - depth])
+}
+
+/// If a tapscript opcode makes the script succeed unconditionally, reserved for upgrades
+fn is_op_success(op: u8) -> bool {
+    matches!(
+        op,
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254
+    )
+}
+
+/// If `sig` is a strict DER signature followed by a sighash type, as specified by BIP66
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // 0x30 [total length] 0x02 [R length] [R] 0x02 [S length] [S] [sighash type]
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // both integers must be positive and minimally encoded
+    let valid_integer = |start: usize, len: usize| {
+        sig[start - 2] == 0x02
+            && len != 0
+            && sig[start] & 0x80 == 0
+            && !(len > 1 && sig[start] == 0 && sig[start + 1] & 0x80 == 0)
+    };
+    valid_integer(4, len_r) && valid_integer(len_r + 6, len_s)
+}
+
+/// Script code with every push of `sig` removed, as legacy signature checks do
+fn find_and_delete(script_code: &[u8], sig: &[u8]) -> Vec<u8> {
+    let pattern = Script::new().push_slice(sig);
+    let pattern = pattern.as_bytes();
+
+    // matches are only removed at instruction boundaries
+    let mut result = vec![];
+    let mut rest = script_code;
+    loop {
+        while rest.starts_with(pattern) {
+            rest = &rest[pattern.len()..];
+        }
+        let mut instructions = Instructions::new(rest);
+        if !matches!(instructions.next(), Some(Ok(_))) {
+            result.extend(rest);
+            return result;
+        }
+        let next = instructions.remaining();
+        result.extend(&rest[..rest.len() - next.len()]);
+        rest = next;
+    }
+}
+
+/// Script code legacy signatures commit to, without the signatures themselves or any
+/// `OP_CODESEPARATOR`
+fn legacy_script_code(script_code: &[u8], sigs: &[Vec<u8>]) -> Script {
+    let script_code = sigs.iter().fold(script_code.to_vec(), |code, sig| {
+        find_and_delete(&code, sig)
+    });
+
+    let mut result = vec![];
+    let mut instructions = Instructions::new(&script_code);
+    loop {
+        let rest = instructions.remaining();
+        match instructions.next() {
+            Some(Ok(Instruction::Op(OP_CODESEPARATOR))) => {}
+            Some(Ok(_)) => result.extend(&rest[..rest.len() - instructions.remaining().len()]),
+            _ => {
+                result.extend(rest);
+                break;
+            }
+        }
+    }
+
+    Script::from_bytes(result)
+}
+
+/// Fail on signatures that are not strict DER once BIP66 is enforced
+fn check_signature_encoding(sig: &[u8], flags: u32) -> Result<()> {
+    if !sig.is_empty() && flags & SCRIPT_VERIFY_DERSIG != 0 && !is_valid_signature_encoding(sig) {
+        bail!(InterpreterError::SigDer);
+    }
+
+    Ok(())
+}
+
+/// If `sig` signs for `pubkey`, as checked by `OP_CHECKSIG` and its variants
+///
+/// Invalid signatures fail tapscripts, while they only make legacy checks false.
+fn eval_checksig(
+    sig: &[u8],
+    pubkey: &[u8],
+    script_code: &[u8],
+    flags: u32,
+    checker: &TransactionChecker,
+    sig_version: SigVersion,
+    exec: &mut ExecData,
+) -> Result<bool> {
+    if sig_version != SigVersion::Tapscript {
+        let script_code = match sig_version {
+            SigVersion::Base => legacy_script_code(script_code, &[sig.to_vec()]),
+            _ => Script::from_bytes(script_code.to_vec()),
+        };
+        check_signature_encoding(sig, flags)?;
+        return Ok(checker.check_ecdsa(sig, pubkey, &script_code, sig_version));
+    }
+
+    if !sig.is_empty() {
+        exec.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+        if exec.validation_weight_left < 0 {
+            bail!(InterpreterError::TapscriptValidationWeight);
+        }
+    }
+    match pubkey.len() {
+        0 => bail!(InterpreterError::PubkeyType),
+        32 if !sig.is_empty() => checker.check_schnorr(sig, pubkey, exec)?,
+        // keys of other sizes are reserved for upgrades and always succeed
+        _ => {}
+    }
+
+    Ok(!sig.is_empty())
+}
+
+/// Execute the `OP_CHECKMULTISIG` at the top of the stack
+fn eval_checkmultisig(
+    stack: &mut Stack,
+    script_code: &[u8],
+    flags: u32,
+    checker: &TransactionChecker,
+    sig_version: SigVersion,
+    op_count: &mut usize,
+) -> Result<bool> {
+    // [dummy] [sig ...] sig_count [pubkey ...] key_count
+    let key_count = num(top(stack, 1)?, 4)?;
+    if !(0..=MAX_PUBKEYS_PER_MULTISIG as i64).contains(&key_count) {
+        bail!(InterpreterError::PubkeyCount);
+    }
+    let key_count = key_count as usize;
+    *op_count += key_count;
+    if *op_count > MAX_OPS_PER_SCRIPT {
+        bail!(InterpreterError::OpCount);
+    }
+    let sig_count = num(top(stack, key_count + 2)?, 4)?;
+    if !(0..=key_count as i64).contains(&sig_count) {
+        bail!(InterpreterError::SigCount);
+    }
+    let sig_count = sig_count as usize;
+    // the dummy element must be there too
+    require(stack, key_count + sig_count + 3)?;
+
+    let args = stack.split_off(stack.len() - key_count - sig_count - 2);
+    let keys = &args[args.len() - 1 - key_count..args.len() - 1];
+    let sigs = &args[..sig_count];
+
+    let script_code = match sig_version {
+        SigVersion::Base => legacy_script_code(script_code, sigs),
+        _ => Script::from_bytes(script_code.to_vec()),
+    };
+
+    // signatures must be in the order of the keys, each key checked at most once
+    let (mut sigs_left, mut keys_left) = (sig_count, key_count);
+    while sigs_left > 0 && sigs_left <= keys_left {
+        let sig = &sigs[sigs_left - 1];
+        check_signature_encoding(sig, flags)?;
+        if checker.check_ecdsa(sig, &keys[keys_left - 1], &script_code, sig_version) {
+            sigs_left -= 1;
+        }
+        keys_left -= 1;
+    }
+    let success = sigs_left == 0;
+
+    // an extra element is consumed, which must be empty under BIP147
+    let dummy = pop(stack)?;
+    if flags & SCRIPT_VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+        bail!(InterpreterError::SigNullDummy);
+    }
+
+    Ok(success)
+}
+
+/// Execute `script` on `stack`
+fn eval_script(
+    stack: &mut Stack,
+    script: &Script,
+    flags: u32,
+    checker: &TransactionChecker,
+    sig_version: SigVersion,
+    exec: &mut ExecData,
+) -> Result<()> {
+    let legacy = sig_version != SigVersion::Tapscript;
+    if legacy && script.len() > MAX_SCRIPT_SIZE {
+        bail!(InterpreterError::ScriptSize);
+    }
+
+    let bytes = script.as_bytes();
+    let mut alt_stack = vec![];
+    // whether each enclosing branch is executed
+    let mut branches: Vec<bool> = vec![];
+    let mut op_count = 0;
+    // start of the script code signatures commit to, after the last OP_CODESEPARATOR
+    let mut code_start = 0;
+    let mut instructions = Instructions::new(bytes);
+    for opcode_pos in 0u32.. {
+        let pc = bytes.len() - instructions.remaining().len();
+        let instruction = match instructions.next() {
+            Some(Ok(instruction)) => instruction,
+            Some(Err(_)) => bail!(InterpreterError::BadOpcode(bytes[pc])),
+            None => break,
+        };
+        let executing = !branches.contains(&false);
+
+        let op = match instruction {
+            Instruction::Push(data) => {
+                if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    bail!(InterpreterError::PushSize);
+                }
+                if executing {
+                    stack.push(data.to_vec());
+                }
+                None
+            }
+            Instruction::Op(op) => Some(op),
+        };
+
+        if let Some(op) = op {
+            if legacy && op > OP_16 {
+                op_count += 1;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    bail!(InterpreterError::OpCount);
+                }
+            }
+            if DISABLED_OPCODES.contains(&op) {
+                bail!(InterpreterError::DisabledOpcode(op));
+            }
+            // conditionals are tracked in unexecuted branches too
+            if !executing && !(OP_IF..=OP_ENDIF).contains(&op) {
+                continue;
+            }
+
+            match op {
+                OP_1NEGATE | OP_1..=OP_16 => {
+                    stack.push(encode_num(op as i64 - (OP_1 - 1) as i64));
+                }
+                OP_NOP | OP_NOP1 | OP_NOP4..=OP_NOP10 => {}
+                OP_CHECKLOCKTIMEVERIFY => {
+                    if flags & SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY != 0 {
+                        // lock times past 2^31 need 5 bytes
+                        let lock_time = num(top(stack, 1)?, 5)?;
+                        if lock_time < 0 {
+                            bail!(InterpreterError::NegativeLocktime);
+                        }
+                        if !checker.check_lock_time(lock_time) {
+                            bail!(InterpreterError::UnsatisfiedLocktime);
+                        }
+                    }
+                }
+                OP_CHECKSEQUENCEVERIFY => {
+                    if flags & SCRIPT_VERIFY_CHECKSEQUENCEVERIFY != 0 {
+                        let sequence = num(top(stack, 1)?, 5)?;
+                        if sequence < 0 {
+                            bail!(InterpreterError::NegativeLocktime);
+                        }
+                        // sequences with the disable flag are reserved for upgrades
+                        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0
+                            && !checker.check_sequence(sequence)
+                        {
+                            bail!(InterpreterError::UnsatisfiedLocktime);
+                        }
+                    }
+                }
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let Some(condition) = stack.pop() else {
+                            bail!(InterpreterError::UnbalancedConditional);
+                        };
+                        if sig_version == SigVersion::Tapscript
+                            && (condition.len() > 1 || condition.first().is_some_and(|b| *b != 1))
+                        {
+                            bail!(InterpreterError::TapscriptMinimalIf);
+                        }
+                        value = cast_to_bool(&condition) != (op == OP_NOTIF);
+                    }
+                    branches.push(value);
+                }
+                OP_ELSE => {
+                    let Some(branch) = branches.last_mut() else {
+                        bail!(InterpreterError::UnbalancedConditional);
+                    };
+                    *branch = !*branch;
+                }
+                OP_ENDIF => {
+                    if branches.pop().is_none() {
+                        bail!(InterpreterError::UnbalancedConditional);
+                    }
+                }
+                OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 1)?) {
+                        bail!(InterpreterError::Verify);
+                    }
+                    stack.pop();
+                }
+                OP_RETURN => bail!(InterpreterError::OpReturn),
+
+                OP_TOALTSTACK => alt_stack.push(pop(stack)?),
+                OP_FROMALTSTACK => {
+                    let Some(element) = alt_stack.pop() else {
+                        bail!(InterpreterError::InvalidAltstackOperation);
+                    };
+                    stack.push(element);
+                }
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+                OP_2DUP | OP_3DUP | OP_2OVER => {
+                    let (count, depth) = match op {
+                        OP_2DUP => (2, 2),
+                        OP_3DUP => (3, 3),
+                        _ => (2, 4),
+                    };
+                    require(stack, depth)?;
+                    let start = stack.len() - depth;
+                    stack.extend_from_within(start..start + count);
+                }
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let start = stack.len() - 6;
+                    let pair = stack.drain(start..start + 2).collect::<Vec<_>>();
+                    stack.extend(pair);
+                }
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack[len - 4..].rotate_left(2);
+                }
+                OP_IFDUP => {
+                    let element = top(stack, 1)?;
+                    if cast_to_bool(element) {
+                        stack.push(element.clone());
+                    }
+                }
+                OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+                OP_DROP => {
+                    pop(stack)?;
+                }
+                OP_DUP => stack.push(top(stack, 1)?.clone()),
+                OP_NIP => {
+                    require(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                }
+                OP_OVER => stack.push(top(stack, 2)?.clone()),
+                OP_PICK | OP_ROLL => {
+                    let n = num(top(stack, 1)?, 4)?;
+                    stack.pop();
+                    if n < 0 || n as usize >= stack.len() {
+                        bail!(InterpreterError::InvalidStackOperation);
+                    }
+                    let index = stack.len() - 1 - n as usize;
+                    let element = match op {
+                        OP_ROLL => stack.remove(index),
+                        _ => stack[index].clone(),
+                    };
+                    stack.push(element);
+                }
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let element = stack.remove(stack.len() - 3);
+                    stack.push(element);
+                }
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+                OP_TUCK => {
+                    let element = top(stack, 1)?.clone();
+                    require(stack, 2)?;
+                    stack.insert(stack.len() - 2, element);
+                }
+                OP_SIZE => stack.push(encode_num(top(stack, 1)?.len() as i64)),
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+                    match op {
+                        OP_EQUALVERIFY if !equal => bail!(InterpreterError::EqualVerify),
+                        OP_EQUALVERIFY => {}
+                        _ => stack.push(bool_bytes(equal)),
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let n = num(top(stack, 1)?, 4)?;
+                    stack.pop();
+                    let result = match op {
+                        OP_1ADD => n + 1,
+                        OP_1SUB => n - 1,
+                        OP_NEGATE => -n,
+                        OP_ABS => n.abs(),
+                        OP_NOT => (n == 0) as i64,
+                        _ => (n != 0) as i64,
+                    };
+                    stack.push(encode_num(result));
+                }
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    let a = num(top(stack, 2)?, 4)?;
+                    let b = num(top(stack, 1)?, 4)?;
+                    stack.truncate(stack.len() - 2);
+                    let result = match op {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+                    match op {
+                        OP_NUMEQUALVERIFY if result == 0 => bail!(InterpreterError::NumEqualVerify),
+                        OP_NUMEQUALVERIFY => {}
+                        _ => stack.push(encode_num(result)),
+                    }
+                }
+                OP_WITHIN => {
+                    let x = num(top(stack, 3)?, 4)?;
+                    let min = num(top(stack, 2)?, 4)?;
+                    let max = num(top(stack, 1)?, 4)?;
+                    stack.truncate(stack.len() - 3);
+                    stack.push(bool_bytes(min <= x && x < max));
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let data = pop(stack)?;
+                    stack.push(match op {
+                        OP_RIPEMD160 => ripemd160(&data).to_vec(),
+                        OP_SHA1 => sha1(&data).to_vec(),
+                        OP_SHA256 => sha256(&data).to_vec(),
+                        OP_HASH160 => hash160(&data).to_vec(),
+                        _ => hash256(&data).to_vec(),
+                    });
+                }
+                OP_CODESEPARATOR => {
+                    code_start = pc + 1;
+                    exec.codesep_pos = opcode_pos;
+                }
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    let sig = top(stack, 2)?;
+                    let pubkey = top(stack, 1)?;
+                    let success = eval_checksig(
+                        sig,
+                        pubkey,
+                        &bytes[code_start..],
+                        flags,
+                        checker,
+                        sig_version,
+                        exec,
+                    )?;
+                    stack.truncate(stack.len() - 2);
+                    match op {
+                        OP_CHECKSIGVERIFY if !success => bail!(InterpreterError::CheckSigVerify),
+                        OP_CHECKSIGVERIFY => {}
+                        _ => stack.push(bool_bytes(success)),
+                    }
+                }
+                OP_CHECKSIGADD if sig_version == SigVersion::Tapscript => {
+                    let sig = top(stack, 3)?;
+                    let n = num(top(stack, 2)?, 4)?;
+                    let pubkey = top(stack, 1)?;
+                    let success =
+                        eval_checksig(sig, pubkey, &[], flags, checker, sig_version, exec)?;
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_num(n + success as i64));
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    if sig_version == SigVersion::Tapscript {
+                        bail!(InterpreterError::TapscriptCheckMultisig);
+                    }
+                    let success = eval_checkmultisig(
+                        stack,
+                        &bytes[code_start..],
+                        flags,
+                        checker,
+                        sig_version,
+                        &mut op_count,
+                    )?;
+                    match op {
+                        OP_CHECKMULTISIGVERIFY if !success => {
+                            bail!(InterpreterError::CheckMultisigVerify)
+                        }
+                        OP_CHECKMULTISIGVERIFY => {}
+                        _ => stack.push(bool_bytes(success)),
+                    }
+                }
+                _ => bail!(InterpreterError::BadOpcode(op)),
+            }
+        }
+
+        if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+            bail!(InterpreterError::StackSize);
+        }
+    }
+
+    if !branches.is_empty() {
+        bail!(InterpreterError::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+/// Execute a witness script on the rest of the witness, which must leave a single true element
+fn execute_witness_script(
+    mut stack: Stack,
+    script: &Script,
+    flags: u32,
+    checker: &TransactionChecker,
+    sig_version: SigVersion,
+    exec: &mut ExecData,
+) -> Result<()> {
+    if sig_version == SigVersion::Tapscript {
+        let mut instructions = script.instructions();
+        loop {
+            let pc = script.len() - instructions.remaining().len();
+            match instructions.next() {
+                Some(Ok(Instruction::Op(op))) if is_op_success(op) => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(_)) => bail!(InterpreterError::BadOpcode(script.as_bytes()[pc])),
+                None => break,
+            }
+        }
+        if stack.len() > MAX_STACK_SIZE {
+            bail!(InterpreterError::StackSize);
+        }
+    }
+    if stack
+        .iter()
+        .any(|element| element.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        bail!(InterpreterError::PushSize);
+    }
+
+    eval_script(&mut stack, script, flags, checker, sig_version, exec)?;
+    if stack.len() != 1 {
+        bail!(InterpreterError::CleanStack);
+    }
+    if !cast_to_bool(&stack[0]) {
+        bail!(InterpreterError::EvalFalse);
+    }
+
+    Ok(())
+}
+
+/// If `control` proves that the taproot output key `program` commits to the leaf `leaf_hash`
+fn verify_taproot_commitment(control: &[u8], program: &[u8], leaf_hash: &[u8; 32]) -> bool {
+    let Ok(internal_key) = Point::from_x_only(&control[1..TAPROOT_CONTROL_BASE_SIZE]) else {
+        return false;
+    };
+    let merkle_root = control[TAPROOT_CONTROL_BASE_SIZE..]
+        .chunks_exact(TAPROOT_CONTROL_NODE_SIZE)
+        .fold(*leaf_hash, |hash, node| {
+            tap_branch_hash(&hash, node.try_into().unwrap())
+        });
+
+    // the low bit of the first byte is the parity of the output key
+    output_key(&internal_key, Some(&merkle_root)).is_ok_and(|key| {
+        key.x_only().as_slice() == program && key.has_even_y() == (control[0] & 1 == 0)
+    })
+}
+
+/// Verify the witness of a spend of the witness program `program` of `version`
+fn verify_witness_program(
+    witness: &[Vec<u8>],
+    version: u8,
+    program: &[u8],
+    flags: u32,
+    checker: &TransactionChecker,
+    is_p2sh: bool,
+) -> Result<()> {
+    let mut stack = witness.to_vec();
+    match (version, program.len()) {
+        (0, 32) => {
+            let Some(script) = stack.pop() else {
+                bail!(InterpreterError::WitnessProgramWitnessEmpty);
+            };
+            if sha256(&script) != program {
+                bail!(InterpreterError::WitnessProgramMismatch);
+            }
+            let script = Script::from_bytes(script);
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut ExecData::new(),
+            )
+        }
+        (0, 20) => {
+            if stack.len() != 2 {
+                bail!(InterpreterError::WitnessProgramMismatch);
+            }
+            let script = Script::p2pkh(program.try_into().unwrap());
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut ExecData::new(),
+            )
+        }
+        (0, _) => bail!(InterpreterError::WitnessProgramWrongLength),
+        (1, 32) if !is_p2sh && flags & SCRIPT_VERIFY_TAPROOT != 0 => {
+            if stack.is_empty() {
+                bail!(InterpreterError::WitnessProgramWitnessEmpty);
+            }
+            let mut exec = ExecData::new();
+            if stack.len() >= 2 && stack.last().unwrap().first() == Some(&ANNEX_TAG) {
+                exec.annex = stack.pop();
+            }
+
+            if stack.len() == 1 {
+                // key path spend
+                return checker.check_schnorr(&stack[0], program, &exec);
+            }
+
+            let control = stack.pop().unwrap();
+            let script = Script::from_bytes(stack.pop().unwrap());
+            let path_len = control.len().wrapping_sub(TAPROOT_CONTROL_BASE_SIZE);
+            if control.len() < TAPROOT_CONTROL_BASE_SIZE
+                || path_len > TAPROOT_CONTROL_MAX_NODE_COUNT * TAPROOT_CONTROL_NODE_SIZE
+                || !path_len.is_multiple_of(TAPROOT_CONTROL_NODE_SIZE)
+            {
+                bail!(InterpreterError::TaprootWrongControlSize);
+            }
+            let leaf_version = control[0] & 0xfe;
+            let leaf_hash = tap_leaf_hash(&script, leaf_version);
+            if !verify_taproot_commitment(&control, program, &leaf_hash) {
+                bail!(InterpreterError::WitnessProgramMismatch);
+            }
+            // other leaf versions are reserved for upgrades
+            if leaf_version != TAPSCRIPT_LEAF_VERSION {
+                return Ok(());
+            }
+
+            let witness_size = witness
+                .iter()
+                .map(|element| var_bytes(element).len())
+                .sum::<usize>()
+                + compact_size(witness.len() as u64).len();
+            exec.leaf_hash = Some(leaf_hash);
+            exec.validation_weight_left = witness_size as i64 + VALIDATION_WEIGHT_OFFSET;
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                checker,
+                SigVersion::Tapscript,
+                &mut exec,
+            )
+        }
+        // other versions are reserved for upgrades
+        _ => Ok(()),
+    }
+}
+
+/// Verify that `script_sig` and `witness` satisfy `script_pubkey` under `flags`
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    flags: u32,
+    checker: &TransactionChecker,
+) -> Result<()> {
+    let mut stack = vec![];
+    let mut exec = ExecData::new();
+    eval_script(
+        &mut stack,
+        script_sig,
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut exec,
+    )?;
+    let script_sig_stack = stack.clone();
+    eval_script(
+        &mut stack,
+        script_pubkey,
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut exec,
+    )?;
+    if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+        bail!(InterpreterError::EvalFalse);
+    }
+
+    let mut had_witness = false;
+    if flags & SCRIPT_VERIFY_WITNESS != 0 {
+        if let Some((version, program)) = script_pubkey.witness_version_and_program() {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                bail!(InterpreterError::WitnessMalleated);
+            }
+            verify_witness_program(witness, version, program, flags, checker, false)?;
+        }
+    }
+
+    if flags & SCRIPT_VERIFY_P2SH != 0 && script_pubkey.is_p2sh() {
+        if !script_sig.is_push_only() {
+            bail!(InterpreterError::SigPushOnly);
+        }
+        // the last push of the scriptSig is the redeem script, run on the rest
+        stack = script_sig_stack;
+        let redeem_script = Script::from_bytes(pop(&mut stack)?);
+        eval_script(
+            &mut stack,
+            &redeem_script,
+            flags,
+            checker,
+            SigVersion::Base,
+            &mut exec,
+        )?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            bail!(InterpreterError::EvalFalse);
+        }
+
+        if flags & SCRIPT_VERIFY_WITNESS != 0 {
+            if let Some((version, program)) = redeem_script.witness_version_and_program() {
+                had_witness = true;
+                if *script_sig != Script::new().push_slice(redeem_script.as_bytes()) {
+                    bail!(InterpreterError::WitnessMalleatedP2sh);
+                }
+                verify_witness_program(witness, version, program, flags, checker, true)?;
+            }
+        }
+    }
+
+    if flags & SCRIPT_VERIFY_WITNESS != 0 && !had_witness && !witness.is_empty() {
+        bail!(InterpreterError::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+/// Verify the scripts of input `index` of `tx`, which spends `spent_outputs` with all of its
+/// inputs
+pub fn verify_input(
+    tx: &Transaction,
+    index: usize,
+    spent_outputs: &[TxOut],
+    flags: u32,
+) -> Result<()> {
+    let checker = TransactionChecker::new(tx, index, spent_outputs)?;
+    let input = &tx.inputs[index];
+    verify_script(
+        &input.script_sig,
+        &spent_outputs[index].script_pubkey,
+        &input.witness,
+        flags,
+        &checker,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            script::taproot::{control_block, tweak_private_key, TapTree},
+            secp256k1::keys::PrivateKey,
+            transaction::{
+                sighash::SIGHASH_ALL,
+                tx::{OutPoint, TxIn},
+            },
+        },
+    };
+
+    fn key(secret: &str) -> PrivateKey {
+        PrivateKey::new(secret, 16).unwrap()
+    }
+
+    /// Transaction spending `script_pubkey` with one input, and the output it spends
+    fn spending(script_pubkey: Script) -> (Transaction, Vec<TxOut>) {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::new([7; 32], 1)));
+        tx.outputs.push(TxOut::new(90_000, Script::op_return(b"")));
+        (tx, vec![TxOut::new(100_000, script_pubkey)])
+    }
+
+    fn ecdsa_sig(key: &PrivateKey, sighash: [u8; 32]) -> Vec<u8> {
+        let mut sig = key.sign(&hex::encode(sighash)).unwrap().der();
+        sig.push(SIGHASH_ALL);
+        sig
+    }
+
+    /// Run `script_sig` then `script_pubkey` without a transaction to check signatures against
+    fn run(script_sig: Script, script_pubkey: Script) -> Result<()> {
+        let (tx, spent) = spending(script_pubkey.clone());
+        let checker = TransactionChecker::new(&tx, 0, &spent)?;
+        verify_script(
+            &script_sig,
+            &script_pubkey,
+            &[],
+            MANDATORY_SCRIPT_VERIFY_FLAGS,
+            &checker,
+        )
+    }
+
+    fn error(result: Result<()>) -> InterpreterError {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn arithmetic_and_flow_control() -> Result<()> {
+        let script_pubkey = Script::new()
+            .push_opcode(OP_ADD)
+            .push_int(5)
+            .push_opcode(OP_EQUAL);
+        run(Script::new().push_int(2).push_int(3), script_pubkey.clone())?;
+        assert_eq!(
+            error(run(Script::new().push_int(2).push_int(2), script_pubkey)),
+            InterpreterError::EvalFalse
+        );
+
+        let script_pubkey = Script::new()
+            .push_opcode(OP_IF)
+            .push_int(2)
+            .push_opcode(OP_ELSE)
+            .push_int(3)
+            .push_opcode(OP_ENDIF)
+            .push_int(3)
+            .push_opcode(OP_NUMEQUAL);
+        run(Script::new().push_int(0), script_pubkey.clone())?;
+        assert_eq!(
+            error(run(Script::new().push_int(1), script_pubkey)),
+            InterpreterError::EvalFalse
+        );
+
+        let script_pubkey = Script::new()
+            .push_slice(b"abc")
+            .push_opcode(OP_SHA1)
+            .push_slice(&sha1(b"abc"))
+            .push_opcode(OP_EQUAL);
+        run(Script::new(), script_pubkey)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn script_errors() {
+        let run_pubkey = |script: Script| error(run(Script::new(), script));
+        assert_eq!(
+            run_pubkey(Script::new().push_int(1).push_opcode(OP_RETURN)),
+            InterpreterError::OpReturn
+        );
+        assert_eq!(
+            run_pubkey(Script::new().push_int(1).push_opcode(OP_IF)),
+            InterpreterError::UnbalancedConditional
+        );
+        assert_eq!(
+            run_pubkey(Script::new().push_opcode(OP_DROP)),
+            InterpreterError::InvalidStackOperation
+        );
+        // disabled opcodes fail even in unexecuted branches
+        assert_eq!(
+            run_pubkey(
+                Script::new()
+                    .push_int(0)
+                    .push_opcode(OP_IF)
+                    .push_opcode(OP_CAT)
+                    .push_opcode(OP_ENDIF)
+            ),
+            InterpreterError::DisabledOpcode(OP_CAT)
+        );
+        let many_ops = (0..=MAX_OPS_PER_SCRIPT).fold(Script::new().push_int(1), |script, _| {
+            script.push_opcode(OP_NOP)
+        });
+        assert_eq!(run_pubkey(many_ops), InterpreterError::OpCount);
+        assert_eq!(
+            run_pubkey(Script::new().push_int(1).push_opcode(OP_VERIFY)),
+            InterpreterError::EvalFalse
+        );
+        // OP_CHECKLOCKTIMEVERIFY against a lock time of 0
+        assert_eq!(
+            run_pubkey(
+                Script::new()
+                    .push_int(100)
+                    .push_opcode(OP_CHECKLOCKTIMEVERIFY)
+            ),
+            InterpreterError::UnsatisfiedLocktime
+        );
+    }
+
+    #[test]
+    fn find_and_delete_matches_whole_pushes() {
+        let sig = [0xab; 3];
+        let push = Script::new().push_slice(&sig);
+        let code = [push.as_bytes(), &[OP_CHECKSIG], push.as_bytes()].concat();
+        assert_eq!(find_and_delete(&code, &sig), vec![OP_CHECKSIG]);
+        // the bytes of a push inside another push are left alone
+        let outer = Script::new().push_slice(push.as_bytes());
+        assert_eq!(find_and_delete(outer.as_bytes(), &sig), outer.as_bytes());
+    }
+
+    #[test]
+    fn p2pkh_and_p2wpkh() -> Result<()> {
+        let key = key("1d1");
+        let pubkey = key.point().sec(true);
+        let hash = key.point().hash160(true);
+
+        let (mut tx, spent) = spending(Script::p2pkh(&hash));
+        let sighash = tx.legacy_sighash(0, &spent[0].script_pubkey, SIGHASH_ALL as u32)?;
+        tx.inputs[0].script_sig = Script::new()
+            .push_slice(&ecdsa_sig(&key, sighash))
+            .push_slice(&pubkey);
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+        tx.outputs[0].value += 1;
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::EvalFalse
+        );
+
+        let (mut tx, spent) = spending(Script::p2wpkh(&hash));
+        let sighash = tx.segwit_v0_sighash(0, &Script::p2pkh(&hash), 100_000, 1)?;
+        tx.inputs[0].witness = vec![ecdsa_sig(&key, sighash), pubkey];
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+        tx.inputs[0].script_sig = Script::new().push_int(1);
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::WitnessMalleated
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lax_der_before_bip66() -> Result<()> {
+        let key = key("1d1");
+        let (mut tx, spent) = spending(Script::p2pk(key.point(), true));
+        let sighash = tx.legacy_sighash(0, &spent[0].script_pubkey, SIGHASH_ALL as u32)?;
+
+        // pad r with a zero byte, which strict DER forbids
+        let sig = ecdsa_sig(&key, sighash);
+        let len_r = sig[3];
+        let padded = [&[0x30, sig[1] + 1, 0x02, len_r + 1, 0x00], &sig[4..]].concat();
+        tx.inputs[0].script_sig = Script::new().push_slice(&padded);
+
+        verify_input(
+            &tx,
+            0,
+            &spent,
+            MANDATORY_SCRIPT_VERIFY_FLAGS & !SCRIPT_VERIFY_DERSIG,
+        )?;
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::SigDer
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn p2sh_multisig() -> Result<()> {
+        let keys = [key("a1"), key("a2"), key("a3")];
+        let points = keys
+            .iter()
+            .map(|key| key.point().clone())
+            .collect::<Vec<_>>();
+        let redeem_script = Script::multisig(2, &points);
+        let (mut tx, spent) = spending(Script::p2sh(&hash160(redeem_script.as_bytes())));
+        let sighash = tx.legacy_sighash(0, &redeem_script, SIGHASH_ALL as u32)?;
+        let sigs = [ecdsa_sig(&keys[0], sighash), ecdsa_sig(&keys[2], sighash)];
+
+        tx.inputs[0].script_sig = Script::new()
+            .push_int(0)
+            .push_slice(&sigs[0])
+            .push_slice(&sigs[1])
+            .push_slice(redeem_script.as_bytes());
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+
+        // signatures out of the order of the keys fail
+        tx.inputs[0].script_sig = Script::new()
+            .push_int(0)
+            .push_slice(&sigs[1])
+            .push_slice(&sigs[0])
+            .push_slice(redeem_script.as_bytes());
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::EvalFalse
+        );
+
+        tx.inputs[0].script_sig = Script::new()
+            .push_int(1)
+            .push_slice(&sigs[0])
+            .push_slice(&sigs[1])
+            .push_slice(redeem_script.as_bytes());
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::SigNullDummy
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn p2tr_key_and_script_paths() -> Result<()> {
+        let internal = key("b1");
+        let leaf_key = key("b2");
+        let leaf = Script::new()
+            .push_slice(&leaf_key.point().x_only())
+            .push_opcode(OP_CHECKSIG);
+        let tree = TapTree::Branch(
+            Box::new(TapTree::Leaf(leaf.clone())),
+            Box::new(TapTree::Leaf(Script::new().push_int(1))),
+        );
+        let root = tree.merkle_root();
+        let output = output_key(internal.point(), Some(&root))?;
+        let (mut tx, spent) = spending(Script::p2tr(&output.x_only()));
+
+        let sighash = tx.taproot_sighash(0, &spent, SIGHASH_DEFAULT, None, None)?;
+        let sig = tweak_private_key(&internal, Some(&root))?.sign_schnorr(&sighash, &[0; 32])?;
+        tx.inputs[0].witness = vec![sig.to_bytes().to_vec()];
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+        // an untweaked signature fails
+        let sig = internal.sign_schnorr(&sighash, &[0; 32])?;
+        tx.inputs[0].witness = vec![sig.to_bytes().to_vec()];
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::SchnorrSig
+        );
+
+        let leaf_hash = tap_leaf_hash(&leaf, TAPSCRIPT_LEAF_VERSION);
+        let sighash = tx.tapscript_sighash(0, &spent, SIGHASH_ALL, &leaf_hash, u32::MAX, None)?;
+        let mut sig = leaf_key
+            .sign_schnorr(&sighash, &[0; 32])?
+            .to_bytes()
+            .to_vec();
+        sig.push(SIGHASH_ALL);
+        let control = control_block(internal.point(), &tree, &leaf)?;
+        tx.inputs[0].witness = vec![sig, leaf.as_bytes().to_vec(), control.clone()];
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+
+        // the other leaf is spent without any signature
+        let other = Script::new().push_int(1);
+        let other_control = control_block(internal.point(), &tree, &other)?;
+        tx.inputs[0].witness = vec![other.as_bytes().to_vec(), other_control];
+        verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+
+        tx.inputs[0].witness = vec![leaf.as_bytes().to_vec(), control[..40].to_vec()];
+        assert_eq!(
+            error(verify_input(&tx, 0, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            InterpreterError::TaprootWrongControlSize
+        );
+
+        Ok(())
+    }
+}
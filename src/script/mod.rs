@@ -0,0 +1,6 @@
+pub mod address;
+pub mod errors;
+pub mod interpreter;
+pub mod opcodes;
+pub mod raw;
+pub mod taproot;
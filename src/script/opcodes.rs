@@ -0,0 +1,366 @@
+/// Push an empty array onto the stack
+pub const OP_0: u8 = 0x00;
+/// Next byte is the number of bytes to push
+pub const OP_PUSHDATA1: u8 = 0x4c;
+/// Next 2 bytes are the number of bytes to push
+pub const OP_PUSHDATA2: u8 = 0x4d;
+/// Next 4 bytes are the number of bytes to push
+pub const OP_PUSHDATA4: u8 = 0x4e;
+/// Push -1
+pub const OP_1NEGATE: u8 = 0x4f;
+/// Fail the script unless in an unexecuted branch
+pub const OP_RESERVED: u8 = 0x50;
+/// Push 1
+pub const OP_1: u8 = 0x51;
+/// Push 2
+pub const OP_2: u8 = 0x52;
+/// Push 3
+pub const OP_3: u8 = 0x53;
+/// Push 4
+pub const OP_4: u8 = 0x54;
+/// Push 5
+pub const OP_5: u8 = 0x55;
+/// Push 6
+pub const OP_6: u8 = 0x56;
+/// Push 7
+pub const OP_7: u8 = 0x57;
+/// Push 8
+pub const OP_8: u8 = 0x58;
+/// Push 9
+pub const OP_9: u8 = 0x59;
+/// Push 10
+pub const OP_10: u8 = 0x5a;
+/// Push 11
+pub const OP_11: u8 = 0x5b;
+/// Push 12
+pub const OP_12: u8 = 0x5c;
+/// Push 13
+pub const OP_13: u8 = 0x5d;
+/// Push 14
+pub const OP_14: u8 = 0x5e;
+/// Push 15
+pub const OP_15: u8 = 0x5f;
+/// Push 16
+pub const OP_16: u8 = 0x60;
+/// Do nothing
+pub const OP_NOP: u8 = 0x61;
+/// Fail the script unless in an unexecuted branch
+pub const OP_VER: u8 = 0x62;
+/// Execute the branch if the top stack item is true
+pub const OP_IF: u8 = 0x63;
+/// Execute the branch if the top stack item is false
+pub const OP_NOTIF: u8 = 0x64;
+/// Always fail the script
+pub const OP_VERIF: u8 = 0x65;
+/// Always fail the script
+pub const OP_VERNOTIF: u8 = 0x66;
+/// Execute the branch if the previous branch was not executed
+pub const OP_ELSE: u8 = 0x67;
+/// End a conditional block
+pub const OP_ENDIF: u8 = 0x68;
+/// Fail the script if the top stack item is false
+pub const OP_VERIFY: u8 = 0x69;
+/// Fail the script, marking an output as unspendable
+pub const OP_RETURN: u8 = 0x6a;
+/// Move the top stack item to the alt stack
+pub const OP_TOALTSTACK: u8 = 0x6b;
+/// Move the top alt stack item to the stack
+pub const OP_FROMALTSTACK: u8 = 0x6c;
+/// Remove the top two stack items
+pub const OP_2DROP: u8 = 0x6d;
+/// Duplicate the top two stack items
+pub const OP_2DUP: u8 = 0x6e;
+/// Duplicate the top three stack items
+pub const OP_3DUP: u8 = 0x6f;
+/// Copy the pair of items two spaces back to the top
+pub const OP_2OVER: u8 = 0x70;
+/// Move the fifth and sixth items to the top
+pub const OP_2ROT: u8 = 0x71;
+/// Swap the top two pairs of items
+pub const OP_2SWAP: u8 = 0x72;
+/// Duplicate the top stack item if it is not zero
+pub const OP_IFDUP: u8 = 0x73;
+/// Push the number of stack items
+pub const OP_DEPTH: u8 = 0x74;
+/// Remove the top stack item
+pub const OP_DROP: u8 = 0x75;
+/// Duplicate the top stack item
+pub const OP_DUP: u8 = 0x76;
+/// Remove the second to top stack item
+pub const OP_NIP: u8 = 0x77;
+/// Copy the second to top stack item to the top
+pub const OP_OVER: u8 = 0x78;
+/// Copy the item n back to the top
+pub const OP_PICK: u8 = 0x79;
+/// Move the item n back to the top
+pub const OP_ROLL: u8 = 0x7a;
+/// Rotate the top three stack items
+pub const OP_ROT: u8 = 0x7b;
+/// Swap the top two stack items
+pub const OP_SWAP: u8 = 0x7c;
+/// Copy the top stack item before the second to top item
+pub const OP_TUCK: u8 = 0x7d;
+/// Disabled
+pub const OP_CAT: u8 = 0x7e;
+/// Disabled
+pub const OP_SUBSTR: u8 = 0x7f;
+/// Disabled
+pub const OP_LEFT: u8 = 0x80;
+/// Disabled
+pub const OP_RIGHT: u8 = 0x81;
+/// Push the length of the top stack item
+pub const OP_SIZE: u8 = 0x82;
+/// Disabled
+pub const OP_INVERT: u8 = 0x83;
+/// Disabled
+pub const OP_AND: u8 = 0x84;
+/// Disabled
+pub const OP_OR: u8 = 0x85;
+/// Disabled
+pub const OP_XOR: u8 = 0x86;
+/// Push 1 if the top two items are equal, 0 otherwise
+pub const OP_EQUAL: u8 = 0x87;
+/// OP_EQUAL followed by OP_VERIFY
+pub const OP_EQUALVERIFY: u8 = 0x88;
+/// Fail the script unless in an unexecuted branch
+pub const OP_RESERVED1: u8 = 0x89;
+/// Fail the script unless in an unexecuted branch
+pub const OP_RESERVED2: u8 = 0x8a;
+/// Add 1 to the top item
+pub const OP_1ADD: u8 = 0x8b;
+/// Subtract 1 from the top item
+pub const OP_1SUB: u8 = 0x8c;
+/// Disabled
+pub const OP_2MUL: u8 = 0x8d;
+/// Disabled
+pub const OP_2DIV: u8 = 0x8e;
+/// Negate the top item
+pub const OP_NEGATE: u8 = 0x8f;
+/// Absolute value of the top item
+pub const OP_ABS: u8 = 0x90;
+/// Push 1 if the top item is 0, 0 otherwise
+pub const OP_NOT: u8 = 0x91;
+/// Push 0 if the top item is 0, 1 otherwise
+pub const OP_0NOTEQUAL: u8 = 0x92;
+/// Add the top two items
+pub const OP_ADD: u8 = 0x93;
+/// Subtract the top item from the second to top item
+pub const OP_SUB: u8 = 0x94;
+/// Disabled
+pub const OP_MUL: u8 = 0x95;
+/// Disabled
+pub const OP_DIV: u8 = 0x96;
+/// Disabled
+pub const OP_MOD: u8 = 0x97;
+/// Disabled
+pub const OP_LSHIFT: u8 = 0x98;
+/// Disabled
+pub const OP_RSHIFT: u8 = 0x99;
+/// Push 1 if both top items are not 0
+pub const OP_BOOLAND: u8 = 0x9a;
+/// Push 1 if either top item is not 0
+pub const OP_BOOLOR: u8 = 0x9b;
+/// Push 1 if the top two numbers are equal
+pub const OP_NUMEQUAL: u8 = 0x9c;
+/// OP_NUMEQUAL followed by OP_VERIFY
+pub const OP_NUMEQUALVERIFY: u8 = 0x9d;
+/// Push 1 if the top two numbers are not equal
+pub const OP_NUMNOTEQUAL: u8 = 0x9e;
+/// Push 1 if the second to top number is less than the top number
+pub const OP_LESSTHAN: u8 = 0x9f;
+/// Push 1 if the second to top number is greater than the top number
+pub const OP_GREATERTHAN: u8 = 0xa0;
+/// Push 1 if the second to top number is at most the top number
+pub const OP_LESSTHANOREQUAL: u8 = 0xa1;
+/// Push 1 if the second to top number is at least the top number
+pub const OP_GREATERTHANOREQUAL: u8 = 0xa2;
+/// Push the smaller of the top two numbers
+pub const OP_MIN: u8 = 0xa3;
+/// Push the larger of the top two numbers
+pub const OP_MAX: u8 = 0xa4;
+/// Push 1 if x is within the range min (inclusive) to max (exclusive)
+pub const OP_WITHIN: u8 = 0xa5;
+/// RIPEMD160 of the top item
+pub const OP_RIPEMD160: u8 = 0xa6;
+/// SHA1 of the top item
+pub const OP_SHA1: u8 = 0xa7;
+/// SHA256 of the top item
+pub const OP_SHA256: u8 = 0xa8;
+/// RIPEMD160 of the SHA256 of the top item
+pub const OP_HASH160: u8 = 0xa9;
+/// Double SHA256 of the top item
+pub const OP_HASH256: u8 = 0xaa;
+/// Mark the start of the script code covered by signatures
+pub const OP_CODESEPARATOR: u8 = 0xab;
+/// Verify a signature against a public key
+pub const OP_CHECKSIG: u8 = 0xac;
+/// OP_CHECKSIG followed by OP_VERIFY
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+/// Verify m of n signatures against public keys
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+/// OP_CHECKMULTISIG followed by OP_VERIFY
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+/// Do nothing
+pub const OP_NOP1: u8 = 0xb0;
+/// Fail the script unless the transaction locktime satisfies the top item (BIP65)
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+/// Fail the script unless the input sequence satisfies the top item (BIP112)
+pub const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+/// Do nothing
+pub const OP_NOP4: u8 = 0xb3;
+/// Do nothing
+pub const OP_NOP5: u8 = 0xb4;
+/// Do nothing
+pub const OP_NOP6: u8 = 0xb5;
+/// Do nothing
+pub const OP_NOP7: u8 = 0xb6;
+/// Do nothing
+pub const OP_NOP8: u8 = 0xb7;
+/// Do nothing
+pub const OP_NOP9: u8 = 0xb8;
+/// Do nothing
+pub const OP_NOP10: u8 = 0xb9;
+/// Verify a signature and add the result to a counter (BIP342)
+pub const OP_CHECKSIGADD: u8 = 0xba;
+/// Invalid opcode
+pub const OP_INVALIDOPCODE: u8 = 0xff;
+
+/// Alias of `OP_0`
+pub const OP_FALSE: u8 = OP_0;
+/// Alias of `OP_1`
+pub const OP_TRUE: u8 = OP_1;
+/// Alias of `OP_NOP2`
+pub const OP_CLTV: u8 = OP_CHECKLOCKTIMEVERIFY;
+/// Alias of `OP_NOP3`
+pub const OP_CSV: u8 = OP_CHECKSEQUENCEVERIFY;
+
+/// Name of an opcode as it appears in script ASM
+pub fn name(op: u8) -> &'static str {
+    match op {
+        OP_0 => "OP_0",
+        OP_PUSHDATA1 => "OP_PUSHDATA1",
+        OP_PUSHDATA2 => "OP_PUSHDATA2",
+        OP_PUSHDATA4 => "OP_PUSHDATA4",
+        OP_1NEGATE => "OP_1NEGATE",
+        OP_RESERVED => "OP_RESERVED",
+        OP_1 => "OP_1",
+        OP_2 => "OP_2",
+        OP_3 => "OP_3",
+        OP_4 => "OP_4",
+        OP_5 => "OP_5",
+        OP_6 => "OP_6",
+        OP_7 => "OP_7",
+        OP_8 => "OP_8",
+        OP_9 => "OP_9",
+        OP_10 => "OP_10",
+        OP_11 => "OP_11",
+        OP_12 => "OP_12",
+        OP_13 => "OP_13",
+        OP_14 => "OP_14",
+        OP_15 => "OP_15",
+        OP_16 => "OP_16",
+        OP_NOP => "OP_NOP",
+        OP_VER => "OP_VER",
+        OP_IF => "OP_IF",
+        OP_NOTIF => "OP_NOTIF",
+        OP_VERIF => "OP_VERIF",
+        OP_VERNOTIF => "OP_VERNOTIF",
+        OP_ELSE => "OP_ELSE",
+        OP_ENDIF => "OP_ENDIF",
+        OP_VERIFY => "OP_VERIFY",
+        OP_RETURN => "OP_RETURN",
+        OP_TOALTSTACK => "OP_TOALTSTACK",
+        OP_FROMALTSTACK => "OP_FROMALTSTACK",
+        OP_2DROP => "OP_2DROP",
+        OP_2DUP => "OP_2DUP",
+        OP_3DUP => "OP_3DUP",
+        OP_2OVER => "OP_2OVER",
+        OP_2ROT => "OP_2ROT",
+        OP_2SWAP => "OP_2SWAP",
+        OP_IFDUP => "OP_IFDUP",
+        OP_DEPTH => "OP_DEPTH",
+        OP_DROP => "OP_DROP",
+        OP_DUP => "OP_DUP",
+        OP_NIP => "OP_NIP",
+        OP_OVER => "OP_OVER",
+        OP_PICK => "OP_PICK",
+        OP_ROLL => "OP_ROLL",
+        OP_ROT => "OP_ROT",
+        OP_SWAP => "OP_SWAP",
+        OP_TUCK => "OP_TUCK",
+        OP_CAT => "OP_CAT",
+        OP_SUBSTR => "OP_SUBSTR",
+        OP_LEFT => "OP_LEFT",
+        OP_RIGHT => "OP_RIGHT",
+        OP_SIZE => "OP_SIZE",
+        OP_INVERT => "OP_INVERT",
+        OP_AND => "OP_AND",
+        OP_OR => "OP_OR",
+        OP_XOR => "OP_XOR",
+        OP_EQUAL => "OP_EQUAL",
+        OP_EQUALVERIFY => "OP_EQUALVERIFY",
+        OP_RESERVED1 => "OP_RESERVED1",
+        OP_RESERVED2 => "OP_RESERVED2",
+        OP_1ADD => "OP_1ADD",
+        OP_1SUB => "OP_1SUB",
+        OP_2MUL => "OP_2MUL",
+        OP_2DIV => "OP_2DIV",
+        OP_NEGATE => "OP_NEGATE",
+        OP_ABS => "OP_ABS",
+        OP_NOT => "OP_NOT",
+        OP_0NOTEQUAL => "OP_0NOTEQUAL",
+        OP_ADD => "OP_ADD",
+        OP_SUB => "OP_SUB",
+        OP_MUL => "OP_MUL",
+        OP_DIV => "OP_DIV",
+        OP_MOD => "OP_MOD",
+        OP_LSHIFT => "OP_LSHIFT",
+        OP_RSHIFT => "OP_RSHIFT",
+        OP_BOOLAND => "OP_BOOLAND",
+        OP_BOOLOR => "OP_BOOLOR",
+        OP_NUMEQUAL => "OP_NUMEQUAL",
+        OP_NUMEQUALVERIFY => "OP_NUMEQUALVERIFY",
+        OP_NUMNOTEQUAL => "OP_NUMNOTEQUAL",
+        OP_LESSTHAN => "OP_LESSTHAN",
+        OP_GREATERTHAN => "OP_GREATERTHAN",
+        OP_LESSTHANOREQUAL => "OP_LESSTHANOREQUAL",
+        OP_GREATERTHANOREQUAL => "OP_GREATERTHANOREQUAL",
+        OP_MIN => "OP_MIN",
+        OP_MAX => "OP_MAX",
+        OP_WITHIN => "OP_WITHIN",
+        OP_RIPEMD160 => "OP_RIPEMD160",
+        OP_SHA1 => "OP_SHA1",
+        OP_SHA256 => "OP_SHA256",
+        OP_HASH160 => "OP_HASH160",
+        OP_HASH256 => "OP_HASH256",
+        OP_CODESEPARATOR => "OP_CODESEPARATOR",
+        OP_CHECKSIG => "OP_CHECKSIG",
+        OP_CHECKSIGVERIFY => "OP_CHECKSIGVERIFY",
+        OP_CHECKMULTISIG => "OP_CHECKMULTISIG",
+        OP_CHECKMULTISIGVERIFY => "OP_CHECKMULTISIGVERIFY",
+        OP_NOP1 => "OP_NOP1",
+        OP_CHECKLOCKTIMEVERIFY => "OP_CHECKLOCKTIMEVERIFY",
+        OP_CHECKSEQUENCEVERIFY => "OP_CHECKSEQUENCEVERIFY",
+        OP_NOP4 => "OP_NOP4",
+        OP_NOP5 => "OP_NOP5",
+        OP_NOP6 => "OP_NOP6",
+        OP_NOP7 => "OP_NOP7",
+        OP_NOP8 => "OP_NOP8",
+        OP_NOP9 => "OP_NOP9",
+        OP_NOP10 => "OP_NOP10",
+        OP_CHECKSIGADD => "OP_CHECKSIGADD",
+        OP_INVALIDOPCODE => "OP_INVALIDOPCODE",
+        0x01..=0x4b => "OP_PUSHBYTES",
+        _ => "OP_UNKNOWN",
+    }
+}
+
+/// Small integer pushed by `OP_1` to `OP_16`, `OP_0` and `OP_1NEGATE`
+pub fn small_int(op: u8) -> Option<i64> {
+    match op {
+        OP_0 => Some(0),
+        OP_1NEGATE => Some(-1),
+        OP_1..=OP_16 => Some((op - OP_1 + 1) as i64),
+        _ => None,
+    }
+}
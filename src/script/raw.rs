@@ -0,0 +1,580 @@
+use {
+    super::{errors::ScriptError, opcodes::*},
+    crate::{
+        secp256k1::point::Point,
+        utils::hash::{hash160, sha256},
+    },
+    anyhow::{bail, Result},
+    std::fmt::{self, Display, Formatter},
+};
+
+/// Most keys allowed by `OP_CHECKMULTISIG`, and so in miniscript's `multi()`
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Largest script that can be executed
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// Bitcoin script, a sequence of opcodes and data pushes
+pub struct Script {
+    bytes: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Single step of a script
+pub enum Instruction<'a> {
+    /// Opcode that does not push data
+    Op(u8),
+    /// Data pushed by `OP_0`, `OP_PUSHBYTES_n` or `OP_PUSHDATAn`
+    Push(&'a [u8]),
+}
+
+/// Iterator over the instructions of a script
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+}
+
+impl Script {
+    /// Create an empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a script from its raw bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    /// Raw bytes of the script
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Length of the script in bytes
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// If the script has no bytes
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Append an opcode to the script
+    pub fn push_opcode(mut self, op: u8) -> Self {
+        self.bytes.push(op);
+        self
+    }
+
+    /// Append a minimal push of `data` to the script
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        match data.len() {
+            0 => self.bytes.push(OP_0),
+            len @ 1..=0x4b => self.bytes.push(len as u8),
+            len @ 0x4c..=0xff => self.bytes.extend([OP_PUSHDATA1, len as u8]),
+            len @ 0x100..=0xffff => {
+                self.bytes.push(OP_PUSHDATA2);
+                self.bytes.extend((len as u16).to_le_bytes());
+            }
+            len => {
+                self.bytes.push(OP_PUSHDATA4);
+                self.bytes.extend((len as u32).to_le_bytes());
+            }
+        }
+        self.bytes.extend(data);
+        self
+    }
+
+    /// Append a minimal push of the number `n` to the script
+    pub fn push_int(self, n: i64) -> Self {
+        match n {
+            0 => self.push_opcode(OP_0),
+            -1 => self.push_opcode(OP_1NEGATE),
+            1..=16 => self.push_opcode(OP_1 + n as u8 - 1),
+            _ => self.push_slice(&encode_num(n)),
+        }
+    }
+
+    /// Append the bytes of another script
+    pub fn push_script(mut self, script: &Script) -> Self {
+        self.bytes.extend(script.as_bytes());
+        self
+    }
+
+    /// Iterate over the instructions of the script
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions::new(&self.bytes)
+    }
+
+    /// Pay to public key script
+    pub fn p2pk(point: &Point, compressed: bool) -> Self {
+        Self::new()
+            .push_slice(&point.sec(compressed))
+            .push_opcode(OP_CHECKSIG)
+    }
+
+    /// Pay to public key hash script
+    pub fn p2pkh(hash: &[u8; 20]) -> Self {
+        Self::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_HASH160)
+            .push_slice(hash)
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+    }
+
+    /// Pay to script hash script
+    pub fn p2sh(hash: &[u8; 20]) -> Self {
+        Self::new()
+            .push_opcode(OP_HASH160)
+            .push_slice(hash)
+            .push_opcode(OP_EQUAL)
+    }
+
+    /// Pay to witness public key hash script
+    pub fn p2wpkh(hash: &[u8; 20]) -> Self {
+        Self::witness_program(0, hash)
+    }
+
+    /// Pay to witness script hash script
+    pub fn p2wsh(hash: &[u8; 32]) -> Self {
+        Self::witness_program(0, hash)
+    }
+
+    /// Pay to taproot script of the x-only output key
+    pub fn p2tr(output_key: &[u8; 32]) -> Self {
+        Self::witness_program(1, output_key)
+    }
+
+    /// Script paying to witness `version` and `program`
+    pub fn witness_program(version: u8, program: &[u8]) -> Self {
+        let version = if version == 0 {
+            OP_0
+        } else {
+            OP_1 + version - 1
+        };
+        Self::new().push_opcode(version).push_slice(program)
+    }
+
+    /// Bare `k` of `n` multisig script of compressed public keys
+    pub fn multisig(k: usize, points: &[Point]) -> Self {
+        points
+            .iter()
+            .fold(Self::new().push_int(k as i64), |script, point| {
+                script.push_slice(&point.sec(true))
+            })
+            .push_int(points.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+    }
+
+    /// Unspendable script carrying `data`
+    pub fn op_return(data: &[u8]) -> Self {
+        Self::new().push_opcode(OP_RETURN).push_slice(data)
+    }
+
+    /// HASH160 of the script, committed to by pay to script hash outputs
+    pub fn script_hash(&self) -> [u8; 20] {
+        hash160(&self.bytes)
+    }
+
+    /// SHA256 of the script, committed to by pay to witness script hash outputs
+    pub fn witness_script_hash(&self) -> [u8; 32] {
+        sha256(&self.bytes)
+    }
+
+    /// Pay to script hash script wrapping this script
+    pub fn to_p2sh(&self) -> Self {
+        Self::p2sh(&self.script_hash())
+    }
+
+    /// Pay to witness script hash script wrapping this script
+    pub fn to_p2wsh(&self) -> Self {
+        Self::p2wsh(&self.witness_script_hash())
+    }
+
+    /// Number of signature operations in the script
+    ///
+    /// `OP_CHECKMULTISIG` counts as 20 unless `accurate` and the key count is pushed right before
+    /// it, the rule for P2SH redeem and witness scripts. Counting stops at an invalid push.
+    pub fn sigop_count(&self, accurate: bool) -> usize {
+        let mut count = 0;
+        let mut last = None;
+        for instruction in self.instructions() {
+            let Ok(instruction) = instruction else {
+                break;
+            };
+            match instruction {
+                Instruction::Op(OP_CHECKSIG | OP_CHECKSIGVERIFY) => count += 1,
+                Instruction::Op(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY) => {
+                    count += match last {
+                        Some(Instruction::Op(op @ OP_1..=OP_16)) if accurate => {
+                            (op - OP_1 + 1) as usize
+                        }
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    }
+                }
+                _ => {}
+            }
+            last = Some(instruction);
+        }
+
+        count
+    }
+
+    /// If the script is a pay to public key hash script
+    pub fn is_p2pkh(&self) -> bool {
+        matches!(
+            self.bytes.as_slice(),
+            [OP_DUP, OP_HASH160, 20, .., OP_EQUALVERIFY, OP_CHECKSIG] if self.len() == 25
+        )
+    }
+
+    /// If the script is a pay to public key script
+    pub fn is_p2pk(&self) -> bool {
+        match self.bytes.as_slice() {
+            [len @ (33 | 65), .., OP_CHECKSIG] => self.len() == *len as usize + 2,
+            _ => false,
+        }
+    }
+
+    /// If the script is a bare `m` of `n` multisig script
+    pub fn is_multisig(&self) -> bool {
+        let Ok(instructions) = self.instructions().collect::<Result<Vec<_>>>() else {
+            return false;
+        };
+        match instructions.as_slice() {
+            [Instruction::Op(m @ OP_1..=OP_16), keys @ .., Instruction::Op(n @ OP_1..=OP_16), Instruction::Op(OP_CHECKMULTISIG)] => {
+                m <= n
+                    && keys.len() == (n - OP_1 + 1) as usize
+                    && keys.iter().all(
+                        |key| matches!(key, Instruction::Push(key) if matches!(key.len(), 33 | 65)),
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    /// If the script is a pay to script hash script
+    pub fn is_p2sh(&self) -> bool {
+        matches!(self.bytes.as_slice(), [OP_HASH160, 20, .., OP_EQUAL] if self.len() == 23)
+    }
+
+    /// Witness version and program if the script is a segwit output script
+    pub fn witness_version_and_program(&self) -> Option<(u8, &[u8])> {
+        match self.bytes.as_slice() {
+            [version @ (OP_0 | OP_1..=OP_16), len, program @ ..]
+                if (4..=42).contains(&self.len()) && *len as usize == program.len() =>
+            {
+                let version = if *version == OP_0 {
+                    0
+                } else {
+                    version - OP_1 + 1
+                };
+                Some((version, program))
+            }
+            _ => None,
+        }
+    }
+
+    /// If the script is a pay to witness public key hash script
+    pub fn is_p2wpkh(&self) -> bool {
+        matches!(self.witness_version_and_program(), Some((0, program)) if program.len() == 20)
+    }
+
+    /// If the script is a pay to witness script hash script
+    pub fn is_p2wsh(&self) -> bool {
+        matches!(self.witness_version_and_program(), Some((0, program)) if program.len() == 32)
+    }
+
+    /// If the script is a pay to taproot script
+    pub fn is_p2tr(&self) -> bool {
+        matches!(self.witness_version_and_program(), Some((1, program)) if program.len() == 32)
+    }
+
+    /// If the script is provably unspendable
+    pub fn is_op_return(&self) -> bool {
+        self.bytes.first() == Some(&OP_RETURN)
+    }
+
+    /// If the script only pushes data, counting `OP_RESERVED` as a push like consensus does
+    pub fn is_push_only(&self) -> bool {
+        self.instructions().all(|instruction| match instruction {
+            Ok(Instruction::Op(op)) => op <= OP_16,
+            Ok(Instruction::Push(_)) => true,
+            Err(_) => false,
+        })
+    }
+
+    /// If outputs locked with the script can never be spent, so need not be kept as coins
+    pub fn is_unspendable(&self) -> bool {
+        self.is_op_return() || self.len() > MAX_SCRIPT_SIZE
+    }
+}
+
+impl From<Vec<u8>> for Script {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::from_bytes(bytes)
+    }
+}
+
+impl Display for Script {
+    /// Format the script as ASM
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, instruction) in self.instructions().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            match instruction {
+                Ok(Instruction::Op(op)) => write!(f, "{}", name(op))?,
+                Ok(Instruction::Push([])) => write!(f, "OP_0")?,
+                Ok(Instruction::Push(data)) => write!(f, "{}", hex::encode(data))?,
+                Err(_) => return write!(f, "[error]"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Instructions<'a> {
+    /// Iterate over the instructions of the raw script `bytes`
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Bytes of the script after the instructions read so far
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (op, rest) = self.bytes.split_first()?;
+
+        // number of bytes holding the push length
+        let (len, rest) = match *op {
+            OP_0 => (0, rest),
+            0x01..=0x4b => (*op as usize, rest),
+            OP_PUSHDATA1 if !rest.is_empty() => (rest[0] as usize, &rest[1..]),
+            OP_PUSHDATA2 if rest.len() >= 2 => {
+                (u16::from_le_bytes([rest[0], rest[1]]) as usize, &rest[2..])
+            }
+            OP_PUSHDATA4 if rest.len() >= 4 => (
+                u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize,
+                &rest[4..],
+            ),
+            OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => {
+                self.bytes = &[];
+                return Some(Err(ScriptError::TruncatedPush.into()));
+            }
+            _ => {
+                self.bytes = rest;
+                return Some(Ok(Instruction::Op(*op)));
+            }
+        };
+
+        if rest.len() < len {
+            self.bytes = &[];
+            return Some(Err(ScriptError::TruncatedPush.into()));
+        }
+
+        let (data, rest) = rest.split_at(len);
+        self.bytes = rest;
+        Some(Ok(Instruction::Push(data)))
+    }
+}
+
+/// Encode `n` as a little endian sign-magnitude script number
+pub fn encode_num(n: i64) -> Vec<u8> {
+    if n == 0 {
+        return vec![];
+    }
+
+    let mut abs = n.unsigned_abs();
+    let mut bytes = vec![];
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+
+    // the top bit carries the sign, add a byte if it is already in use
+    if bytes.last().unwrap() & 0x80 != 0 {
+        bytes.push(if n < 0 { 0x80 } else { 0x00 });
+    } else if n < 0 {
+        *bytes.last_mut().unwrap() |= 0x80;
+    }
+
+    bytes
+}
+
+/// Decode a script number of at most `max_len` bytes, rejecting non-minimal encodings
+pub fn decode_num(bytes: &[u8], max_len: usize) -> Result<i64> {
+    // the last byte may only be 0x00 or 0x80 if the sign bit is needed by the byte before it
+    if let Some(last) = bytes.last() {
+        if last & 0x7f == 0 && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0) {
+            bail!(ScriptError::NonMinimalNum);
+        }
+    }
+
+    decode_num_lax(bytes, max_len)
+}
+
+/// Decode a script number of at most `max_len` bytes, accepting non-minimal encodings as
+/// consensus does
+pub fn decode_num_lax(bytes: &[u8], max_len: usize) -> Result<i64> {
+    if bytes.len() > max_len {
+        bail!(ScriptError::NumOverflow(bytes.len()));
+    }
+    let Some(last) = bytes.last() else {
+        return Ok(0);
+    };
+
+    let mut n = bytes
+        .iter()
+        .rev()
+        .fold(0_i64, |n, byte| n << 8 | *byte as i64);
+    if last & 0x80 != 0 {
+        // clear the sign bit and negate
+        n &= !(0x80_i64 << (8 * (bytes.len() - 1)));
+        n = -n;
+    }
+
+    Ok(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn p2pkh_template() -> Result<()> {
+        let hash: [u8; 20] = hex::decode("84e9ed95a38613f0527ff685a9928abe2d4754d4")?
+            .try_into()
+            .unwrap();
+        let script = Script::p2pkh(&hash);
+
+        assert_eq!(
+            hex::encode(script.as_bytes()),
+            "76a91484e9ed95a38613f0527ff685a9928abe2d4754d488ac"
+        );
+        assert!(script.is_p2pkh());
+        assert_eq!(
+            script.to_string(),
+            "OP_DUP OP_HASH160 84e9ed95a38613f0527ff685a9928abe2d4754d4 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn witness_program_detection() -> Result<()> {
+        let script = Script::p2tr(&[7; 32]);
+
+        assert!(script.is_p2tr());
+        assert!(!script.is_p2wsh());
+        assert_eq!(
+            script.witness_version_and_program(),
+            Some((1, [7; 32].as_slice()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_pushes() -> Result<()> {
+        let script = Script::new()
+            .push_slice(&[])
+            .push_slice(&[1; 75])
+            .push_slice(&[2; 76])
+            .push_slice(&[3; 256]);
+        let lens = script
+            .instructions()
+            .map(|i| match i? {
+                Instruction::Push(data) => Ok(data.len()),
+                Instruction::Op(_) => Ok(usize::MAX),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(lens, [0, 75, 76, 256]);
+        assert_eq!(script.as_bytes()[77], OP_PUSHDATA1);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_push() -> Result<()> {
+        let script = Script::from_bytes(vec![0x05, 0x01]);
+
+        assert!(script.instructions().next().unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn script_numbers() -> Result<()> {
+        for (n, enc) in [
+            (0, ""),
+            (1, "01"),
+            (-1, "81"),
+            (127, "7f"),
+            (128, "8000"),
+            (-128, "8080"),
+            (255, "ff00"),
+            (500000, "20a107"),
+        ] {
+            assert_eq!(hex::encode(encode_num(n)), enc, "{n}");
+            assert_eq!(decode_num(&hex::decode(enc)?, 4)?, n, "{enc}");
+        }
+        assert!(decode_num(&[0x01, 0x00], 4).is_err());
+        assert!(decode_num(&[0x01; 5], 4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn push_small_ints() -> Result<()> {
+        let script = Script::new()
+            .push_int(0)
+            .push_int(16)
+            .push_int(-1)
+            .push_int(17);
+
+        assert_eq!(hex::encode(script.as_bytes()), "00604f0111");
+        Ok(())
+    }
+
+    #[test]
+    fn sigop_counting() -> Result<()> {
+        let point =
+            Point::parse("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
+        let multisig = Script::multisig(1, &[point.clone(), point.clone()]);
+
+        assert_eq!(Script::p2pk(&point, true).sigop_count(false), 1);
+        assert_eq!(multisig.sigop_count(false), MAX_PUBKEYS_PER_MULTISIG);
+        assert_eq!(multisig.sigop_count(true), 2);
+        assert_eq!(multisig.to_p2sh().sigop_count(true), 0);
+
+        // counting stops at the truncated push
+        let script = Script::from_bytes(vec![OP_CHECKSIG, OP_PUSHDATA1, 0x05, OP_CHECKSIG]);
+        assert_eq!(script.sigop_count(true), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn bare_key_templates() -> Result<()> {
+        let point =
+            Point::parse("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
+
+        assert!(Script::p2pk(&point, true).is_p2pk());
+        assert!(Script::p2pk(&point, false).is_p2pk());
+        assert!(!Script::p2pkh(&point.hash160(true)).is_p2pk());
+        assert!(Script::multisig(2, &[point.clone(), point.clone()]).is_multisig());
+        assert!(!Script::p2pk(&point, true).is_multisig());
+        // more signatures than keys
+        let script = Script::new()
+            .push_int(2)
+            .push_slice(&point.sec(true))
+            .push_int(1)
+            .push_opcode(OP_CHECKMULTISIG);
+        assert!(!script.is_multisig());
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use {
+    super::{errors::ScriptError, raw::Script},
+    crate::{
+        secp256k1::{
+            constants::{G, N},
+            keys::PrivateKey,
+            point::Point,
+        },
+        utils::{
+            encoding::{compact_size, to_32_bytes},
+            hash::tagged_hash,
+        },
+    },
+    anyhow::{bail, Result},
+    ibig::UBig,
+};
+
+/// Leaf version of BIP342 tapscripts
+pub const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+
+/// Most nodes the merkle path of a control block may hold, which is the deepest a leaf can be
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Binary tree of scripts committed to by a taproot output
+pub enum TapTree {
+    Leaf(Script),
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    /// Hash committing to the whole tree
+    pub fn merkle_root(&self) -> [u8; 32] {
+        match self {
+            Self::Leaf(script) => tap_leaf_hash(script, TAPSCRIPT_LEAF_VERSION),
+            Self::Branch(left, right) => tap_branch_hash(&left.merkle_root(), &right.merkle_root()),
+        }
+    }
+
+    /// Leaf scripts of the tree with their depths, from left to right
+    pub fn leaves(&self) -> Vec<(&Script, usize)> {
+        match self {
+            Self::Leaf(script) => vec![(script, 0)],
+            Self::Branch(left, right) => left
+                .leaves()
+                .into_iter()
+                .chain(right.leaves())
+                .map(|(script, depth)| (script, depth + 1))
+                .collect(),
+        }
+    }
+
+    /// Hashes proving the inclusion of `script` in the tree, from the leaf upwards
+    pub fn merkle_path(&self, script: &Script) -> Option<Vec<[u8; 32]>> {
+        match self {
+            Self::Leaf(leaf) => (leaf == script).then(Vec::new),
+            Self::Branch(left, right) => {
+                if let Some(mut path) = left.merkle_path(script) {
+                    path.push(right.merkle_root());
+                    Some(path)
+                } else {
+                    let mut path = right.merkle_path(script)?;
+                    path.push(left.merkle_root());
+                    Some(path)
+                }
+            }
+        }
+    }
+}
+
+/// Hash of a tapscript leaf
+pub fn tap_leaf_hash(script: &Script, leaf_version: u8) -> [u8; 32] {
+    tagged_hash(
+        "TapLeaf",
+        &[
+            &[leaf_version],
+            compact_size(script.len() as u64).as_slice(),
+            script.as_bytes(),
+        ]
+        .concat(),
+    )
+}
+
+/// Hash of a branch, children are sorted so the proof does not need their order
+pub fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+    tagged_hash("TapBranch", &[a.as_slice(), b.as_slice()].concat())
+}
+
+/// Scalar tweaking the internal key with the script tree `merkle_root`
+pub fn tap_tweak(internal_key: &[u8; 32], merkle_root: Option<&[u8; 32]>) -> Result<UBig> {
+    let data = match merkle_root {
+        Some(root) => [internal_key.as_slice(), root.as_slice()].concat(),
+        None => internal_key.to_vec(),
+    };
+    let tweak = UBig::from_be_bytes(&tagged_hash("TapTweak", &data));
+    if N.with(|n| tweak >= *n) {
+        bail!(ScriptError::InvalidTweak);
+    }
+
+    Ok(tweak)
+}
+
+/// Taproot output key of `internal_key` committing to the script tree `merkle_root`
+///
+/// Returns the output point, whose x-only serialisation goes in the output script
+pub fn output_key(internal_key: &Point, merkle_root: Option<&[u8; 32]>) -> Result<Point> {
+    // internal keys are x-only, so always have an even y coordinate
+    let internal_key = Point::from_x_only(&internal_key.x_only())?;
+    let tweak = tap_tweak(&internal_key.x_only(), merkle_root)?;
+
+    let point = G.with(|g| &tweak * g) + internal_key;
+    if point.is_inf() {
+        bail!(ScriptError::InvalidTweak);
+    }
+
+    Ok(point)
+}
+
+/// Private key of the taproot output key of `key` committing to the script tree `merkle_root`
+///
+/// Used to sign for key path spends
+pub fn tweak_private_key(key: &PrivateKey, merkle_root: Option<&[u8; 32]>) -> Result<PrivateKey> {
+    let tweak = tap_tweak(&key.point().x_only(), merkle_root)?;
+    let secret = UBig::from_be_bytes(&key.to_bytes());
+
+    // the internal key is x-only, so its secret is negated if the point has an odd y coordinate
+    let tweaked = N.with(|n| {
+        let secret = if key.point().has_even_y() {
+            secret
+        } else {
+            n - secret
+        };
+        (secret + tweak) % n
+    });
+
+    PrivateKey::from_bytes(&to_32_bytes(&tweaked))
+}
+
+/// Control block proving `script` is committed to by the output key of `internal_key` and `tree`
+pub fn control_block(internal_key: &Point, tree: &TapTree, script: &Script) -> Result<Vec<u8>> {
+    let Some(path) = tree.merkle_path(script) else {
+        bail!(ScriptError::LeafNotInTree(script.to_string()));
+    };
+    let output = output_key(internal_key, Some(&tree.merkle_root()))?;
+    let parity = if output.has_even_y() { 0 } else { 1 };
+
+    Ok([
+        &[TAPSCRIPT_LEAF_VERSION | parity],
+        internal_key.x_only().as_slice(),
+        &path.concat(),
+    ]
+    .concat())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tweaked_private_key_matches_output_key() -> Result<()> {
+        let root = tap_leaf_hash(&Script::new().push_int(1), TAPSCRIPT_LEAF_VERSION);
+        // one key with an even and one with an odd y coordinate
+        for secret in ["1", "7"] {
+            let key = PrivateKey::new(secret, 16)?;
+            for merkle_root in [None, Some(&root)] {
+                assert_eq!(
+                    tweak_private_key(&key, merkle_root)?.point().x_only(),
+                    output_key(key.point(), merkle_root)?.x_only()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bip86_key_path_output() -> Result<()> {
+        let internal_key = Point::from_x_only(&hex::decode(
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+        )?)?;
+
+        assert_eq!(
+            hex::encode(output_key(&internal_key, None)?.x_only()),
+            "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merkle_paths_reach_the_root() -> Result<()> {
+        let scripts = (1..=3)
+            .map(|i| Script::new().push_int(i))
+            .collect::<Vec<_>>();
+        let tree = TapTree::Branch(
+            Box::new(TapTree::Leaf(scripts[0].clone())),
+            Box::new(TapTree::Branch(
+                Box::new(TapTree::Leaf(scripts[1].clone())),
+                Box::new(TapTree::Leaf(scripts[2].clone())),
+            )),
+        );
+
+        for script in &scripts {
+            let root = tree.merkle_path(script).unwrap().iter().fold(
+                tap_leaf_hash(script, TAPSCRIPT_LEAF_VERSION),
+                |hash, sibling| tap_branch_hash(&hash, sibling),
+            );
+            assert_eq!(root, tree.merkle_root());
+        }
+        assert_eq!(
+            tree.leaves().iter().map(|(_, d)| *d).collect::<Vec<_>>(),
+            [1, 2, 2]
+        );
+        Ok(())
+    }
+}
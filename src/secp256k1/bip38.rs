@@ -0,0 +1,535 @@
+use {
+    super::{
+        constants::{G, N},
+        errors::Bip38Error,
+        keys::PrivateKey,
+        point::Point,
+    },
+    crate::utils::{
+        base58::{decode_check, encode_check},
+        encoding::to_32_bytes,
+        hash::hash256,
+    },
+    aes::{
+        cipher::{BlockDecrypt, BlockEncrypt, KeyInit},
+        Aes256, Block,
+    },
+    anyhow::{bail, Result},
+    ibig::UBig,
+    unicode_normalization::UnicodeNormalization,
+    zeroize::Zeroizing,
+};
+
+/// Prefix of keys encrypted without EC multiplication
+const NON_EC_PREFIX: [u8; 2] = [0x01, 0x42];
+
+/// Prefix of keys generated by EC multiplication from an intermediate code
+const EC_PREFIX: [u8; 2] = [0x01, 0x43];
+
+/// Magic of intermediate codes whose owner entropy ends with a lot and sequence number
+const INTERMEDIATE_LOT_MAGIC: [u8; 8] = [0x2c, 0xe9, 0xb3, 0xe1, 0xff, 0x39, 0xe2, 0x51];
+
+/// Magic of intermediate codes whose owner entropy is all salt
+const INTERMEDIATE_MAGIC: [u8; 8] = [0x2c, 0xe9, 0xb3, 0xe1, 0xff, 0x39, 0xe2, 0x53];
+
+/// Magic of confirmation codes
+const CONFIRMATION_MAGIC: [u8; 5] = [0x64, 0x3b, 0xf6, 0xa8, 0x9a];
+
+/// Flag bits of keys encrypted without EC multiplication
+const FLAG_NON_EC: u8 = 0xc0;
+
+/// Flag bit of keys whose address uses the compressed public key
+const FLAG_COMPRESSED: u8 = 0x20;
+
+/// Flag bit of EC-multiplied keys whose owner entropy ends with a lot and sequence number
+const FLAG_LOT_SEQUENCE: u8 = 0x04;
+
+/// Largest lot number of an intermediate code
+const MAX_LOT: u32 = 1_048_575;
+
+/// Largest sequence number of an intermediate code
+const MAX_SEQUENCE: u32 = 4095;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Key generated from an intermediate code, by someone who does not know the passphrase
+pub struct GeneratedKey {
+    /// Encrypted private key, only the passphrase owner can decrypt
+    pub encrypted_key: String,
+    /// Mainnet P2PKH address of the key
+    pub address: String,
+    /// Code proving to the passphrase owner that the address belongs to their passphrase
+    pub confirmation_code: String,
+}
+
+impl PrivateKey {
+    /// Encrypt the key with `passphrase` as a BIP38 `6P` string, for the address of the
+    /// compressed or uncompressed public key
+    pub fn encrypt_bip38(&self, passphrase: &str, compressed: bool) -> Result<String> {
+        let address_hash = hash_address(&address(self.point(), compressed));
+        let derived = scrypt::<64>(normalise(passphrase).as_bytes(), &address_hash, 14, 8, 8);
+        let secret = Zeroizing::new(self.to_bytes());
+        let half1 = encrypt_half(&derived[32..], &secret[..16], &derived[..16]);
+        let half2 = encrypt_half(&derived[32..], &secret[16..], &derived[16..32]);
+        let flags = FLAG_NON_EC | if compressed { FLAG_COMPRESSED } else { 0 };
+
+        Ok(encode_check(
+            &[&NON_EC_PREFIX[..], &[flags], &address_hash, &half1, &half2].concat(),
+        ))
+    }
+
+    /// Decrypt a BIP38 key with `passphrase`, whether or not it was EC-multiplied
+    ///
+    /// Returns the key and whether its address uses the compressed public key.
+    pub fn decrypt_bip38(encrypted: &str, passphrase: &str) -> Result<(Self, bool)> {
+        let data = decode_check(encrypted)?;
+        if data.len() != 39 {
+            bail!(Bip38Error::InvalidLength(
+                "encrypted key".to_string(),
+                data.len()
+            ));
+        }
+        let flags = data[2];
+        let compressed = flags & FLAG_COMPRESSED != 0;
+        let address_hash = &data[3..7];
+
+        let secret = match [data[0], data[1]] {
+            NON_EC_PREFIX => {
+                if flags & !FLAG_COMPRESSED != FLAG_NON_EC {
+                    bail!(Bip38Error::InvalidFlags(flags));
+                }
+                let derived =
+                    scrypt::<64>(normalise(passphrase).as_bytes(), address_hash, 14, 8, 8);
+                let half1 = decrypt_half(&derived[32..], &data[7..23], &derived[..16]);
+                let half2 = decrypt_half(&derived[32..], &data[23..], &derived[16..32]);
+                Zeroizing::new([half1, half2].concat())
+            }
+            EC_PREFIX => {
+                if flags & !(FLAG_COMPRESSED | FLAG_LOT_SEQUENCE) != 0 {
+                    bail!(Bip38Error::InvalidFlags(flags));
+                }
+                let owner_entropy = &data[7..15];
+                let pass_factor =
+                    pass_factor(passphrase, owner_entropy, flags & FLAG_LOT_SEQUENCE != 0);
+                let pass_point = G.with(|g| UBig::from_be_bytes(&*pass_factor) * g);
+                let derived = ec_derived(&pass_point, address_hash, owner_entropy);
+
+                // the second half holds the end of the first half and the end of seed b
+                let half2 = decrypt_half(&derived[32..], &data[23..], &derived[16..32]);
+                let half1 = [&data[15..23], &half2[..8]].concat();
+                let seed_b = Zeroizing::new(
+                    [
+                        &decrypt_half(&derived[32..], &half1, &derived[..16])[..],
+                        &half2[8..],
+                    ]
+                    .concat(),
+                );
+                let factor_b = UBig::from_be_bytes(&hash256(&seed_b));
+                let secret = N.with(|n| UBig::from_be_bytes(&*pass_factor) * factor_b % n);
+                Zeroizing::new(to_32_bytes(&secret).to_vec())
+            }
+            _ => bail!(Bip38Error::InvalidPrefix),
+        };
+
+        let key = Self::from_bytes(&secret).map_err(|_| Bip38Error::WrongPassphrase)?;
+        if hash_address(&address(key.point(), compressed)) != address_hash {
+            bail!(Bip38Error::WrongPassphrase);
+        }
+
+        Ok((key, compressed))
+    }
+}
+
+/// Intermediate code of `passphrase`, from which others can generate keys that only the
+/// passphrase owner can decrypt
+///
+/// A lot and sequence number, when given, are embedded in every key generated from the code.
+pub fn intermediate_code(passphrase: &str, lot_sequence: Option<(u32, u32)>) -> Result<String> {
+    let mut owner_entropy = [0; 8];
+    match lot_sequence {
+        Some((lot, sequence)) => {
+            if lot > MAX_LOT || sequence > MAX_SEQUENCE {
+                bail!(Bip38Error::InvalidLotSequence(lot, sequence));
+            }
+            getrandom::getrandom(&mut owner_entropy[..4])?;
+            owner_entropy[4..].copy_from_slice(&(lot * 4096 + sequence).to_be_bytes());
+        }
+        None => getrandom::getrandom(&mut owner_entropy)?,
+    }
+
+    let pass_factor = pass_factor(passphrase, &owner_entropy, lot_sequence.is_some());
+    let pass_point = G.with(|g| UBig::from_be_bytes(&*pass_factor) * g);
+    let magic = match lot_sequence {
+        Some(_) => INTERMEDIATE_LOT_MAGIC,
+        None => INTERMEDIATE_MAGIC,
+    };
+
+    Ok(encode_check(
+        &[&magic[..], &owner_entropy, &pass_point.sec(true)].concat(),
+    ))
+}
+
+/// Generate a new encrypted key from an intermediate code, without learning its passphrase
+pub fn generate_encrypted_key(intermediate: &str, compressed: bool) -> Result<GeneratedKey> {
+    let data = decode_check(intermediate)?;
+    if data.len() != 49 {
+        bail!(Bip38Error::InvalidLength(
+            "intermediate code".to_string(),
+            data.len()
+        ));
+    }
+    let lot_sequence = match data[..8].try_into().unwrap() {
+        INTERMEDIATE_LOT_MAGIC => true,
+        INTERMEDIATE_MAGIC => false,
+        _ => bail!(Bip38Error::InvalidPrefix),
+    };
+    let owner_entropy = &data[8..16];
+    let pass_point = Point::from_sec(&data[16..])?;
+
+    let mut seed_b = Zeroizing::new([0; 24]);
+    getrandom::getrandom(seed_b.as_mut())?;
+    let factor_b = UBig::from_be_bytes(&hash256(&*seed_b));
+    let address = address(&(&factor_b * &pass_point), compressed);
+    let address_hash = hash_address(&address);
+    let derived = ec_derived(&pass_point, &address_hash, owner_entropy);
+
+    let half1 = encrypt_half(&derived[32..], &seed_b[..16], &derived[..16]);
+    let half2 = encrypt_half(
+        &derived[32..],
+        &[&half1[8..], &seed_b[16..]].concat(),
+        &derived[16..32],
+    );
+    let flags = if compressed { FLAG_COMPRESSED } else { 0 }
+        | if lot_sequence { FLAG_LOT_SEQUENCE } else { 0 };
+    let encrypted_key = encode_check(
+        &[
+            &EC_PREFIX[..],
+            &[flags],
+            &address_hash,
+            owner_entropy,
+            &half1[..8],
+            &half2,
+        ]
+        .concat(),
+    );
+
+    // point b is encrypted so only the passphrase owner can multiply it into the address
+    let point_b = G.with(|g| &factor_b * g).sec(true);
+    let prefix = point_b[0] ^ (derived[63] & 1);
+    let point_b_x1 = encrypt_half(&derived[32..], &point_b[1..17], &derived[..16]);
+    let point_b_x2 = encrypt_half(&derived[32..], &point_b[17..], &derived[16..32]);
+    let confirmation_code = encode_check(
+        &[
+            &CONFIRMATION_MAGIC[..],
+            &[flags],
+            &address_hash,
+            owner_entropy,
+            &[prefix],
+            &point_b_x1,
+            &point_b_x2,
+        ]
+        .concat(),
+    );
+
+    Ok(GeneratedKey {
+        encrypted_key,
+        address,
+        confirmation_code,
+    })
+}
+
+/// Check a confirmation code against `passphrase`, returning the address of its key
+pub fn verify_confirmation_code(confirmation_code: &str, passphrase: &str) -> Result<String> {
+    let data = decode_check(confirmation_code)?;
+    if data.len() != 51 {
+        bail!(Bip38Error::InvalidLength(
+            "confirmation code".to_string(),
+            data.len()
+        ));
+    }
+    if data[..5] != CONFIRMATION_MAGIC {
+        bail!(Bip38Error::InvalidPrefix);
+    }
+    let flags = data[5];
+    if flags & !(FLAG_COMPRESSED | FLAG_LOT_SEQUENCE) != 0 {
+        bail!(Bip38Error::InvalidFlags(flags));
+    }
+    let address_hash = &data[6..10];
+    let owner_entropy = &data[10..18];
+
+    let pass_factor = pass_factor(passphrase, owner_entropy, flags & FLAG_LOT_SEQUENCE != 0);
+    let pass_point = G.with(|g| UBig::from_be_bytes(&*pass_factor) * g);
+    let derived = ec_derived(&pass_point, address_hash, owner_entropy);
+    let point_b = [
+        &[data[18] ^ (derived[63] & 1)][..],
+        &decrypt_half(&derived[32..], &data[19..35], &derived[..16]),
+        &decrypt_half(&derived[32..], &data[35..], &derived[16..32]),
+    ]
+    .concat();
+    let point_b = Point::from_sec(&point_b).map_err(|_| Bip38Error::WrongPassphrase)?;
+
+    let address = address(
+        &(UBig::from_be_bytes(&*pass_factor) * point_b),
+        flags & FLAG_COMPRESSED != 0,
+    );
+    if hash_address(&address) != address_hash {
+        bail!(Bip38Error::WrongPassphrase);
+    }
+
+    Ok(address)
+}
+
+/// Passphrase in Unicode normalisation form C, as BIP38 hashes it
+fn normalise(passphrase: &str) -> Zeroizing<String> {
+    Zeroizing::new(passphrase.nfc().collect())
+}
+
+/// scrypt of `password` and `salt` with cost parameters fixed by BIP38
+fn scrypt<const LEN: usize>(
+    password: &[u8],
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Zeroizing<[u8; LEN]> {
+    let params = scrypt::Params::new(log_n, r, p, LEN).expect("BIP38 parameters are valid");
+    let mut output = Zeroizing::new([0; LEN]);
+    scrypt::scrypt(password, salt, &params, output.as_mut()).expect("output length is valid");
+
+    output
+}
+
+/// Secret of the passphrase owner, multiplied into every key generated from their codes
+fn pass_factor(passphrase: &str, owner_entropy: &[u8], lot_sequence: bool) -> Zeroizing<[u8; 32]> {
+    // with a lot and sequence number only the first half of the entropy is salt
+    let owner_salt = if lot_sequence {
+        &owner_entropy[..4]
+    } else {
+        owner_entropy
+    };
+    let prefactor = scrypt::<32>(normalise(passphrase).as_bytes(), owner_salt, 14, 8, 8);
+    if !lot_sequence {
+        return prefactor;
+    }
+
+    Zeroizing::new(hash256(&[&prefactor[..], owner_entropy].concat()))
+}
+
+/// Keys encrypting seed b and point b of an EC-multiplied key
+fn ec_derived(
+    pass_point: &Point,
+    address_hash: &[u8],
+    owner_entropy: &[u8],
+) -> Zeroizing<[u8; 64]> {
+    scrypt::<64>(
+        &pass_point.sec(true),
+        &[address_hash, owner_entropy].concat(),
+        10,
+        1,
+        1,
+    )
+}
+
+/// Mainnet P2PKH address of `point`, whose hash BIP38 uses as a checksum and salt
+fn address(point: &Point, compressed: bool) -> String {
+    encode_check(&[&[0x00][..], &point.hash160(compressed)].concat())
+}
+
+fn hash_address(address: &str) -> [u8; 4] {
+    hash256(address.as_bytes())[..4].try_into().unwrap()
+}
+
+/// AES-256 encryption of `half` XORed with `pad`
+fn encrypt_half(key: &[u8], half: &[u8], pad: &[u8]) -> [u8; 16] {
+    let mut block = Block::default();
+    for ((block, half), pad) in block.iter_mut().zip(half).zip(pad) {
+        *block = half ^ pad;
+    }
+    Aes256::new_from_slice(key)
+        .expect("key is 32 bytes")
+        .encrypt_block(&mut block);
+
+    block.into()
+}
+
+/// AES-256 decryption of `block` XORed with `pad`
+fn decrypt_half(key: &[u8], block: &[u8], pad: &[u8]) -> [u8; 16] {
+    let mut block = *Block::from_slice(block);
+    Aes256::new_from_slice(key)
+        .expect("key is 32 bytes")
+        .decrypt_block(&mut block);
+    for (block, pad) in block.iter_mut().zip(pad) {
+        *block ^= pad;
+    }
+
+    block.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(result: Result<impl Sized>) -> Bip38Error {
+        result.err().unwrap().downcast().unwrap()
+    }
+
+    #[test]
+    fn non_ec_multiplied_vectors() -> Result<()> {
+        let vectors = [
+            (
+                "TestingOneTwoThree",
+                "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg",
+                "cbf4b9f70470856bb4f40f80b87edb90865997ffee6df315ab166d713af433a5",
+                false,
+            ),
+            (
+                "Satoshi",
+                "6PRNFFkZc2NZ6dJqFfhRoFNMR9Lnyj7dYGrzdgXXVMXcxoKTePPX1dWByq",
+                "09c2686880095b1a4c249ee3ac4eea8a014f11e6f986d0b5025ac1f39afbd9ae",
+                false,
+            ),
+            (
+                "\u{03d2}\u{0301}\u{0000}\u{10400}\u{1f4a9}",
+                "6PRW5o9FLp4gJDDVqJQKJFTpMvdsSGJxMYHtHaQBF3ooa8mwD69bapcDQn",
+                "64eeab5f9be2a01a8365a579511eb3373c87c40da6d2a25f05bda68fe077b66e",
+                false,
+            ),
+            (
+                "TestingOneTwoThree",
+                "6PYNKZ1EAgYgmQfmNVamxyXVWHzK5s6DGhwP4J5o44cvXdoY7sRzhtpUeo",
+                "cbf4b9f70470856bb4f40f80b87edb90865997ffee6df315ab166d713af433a5",
+                true,
+            ),
+            (
+                "Satoshi",
+                "6PYLtMnXvfG3oJde97zRyLYFZCYizPU5T3LwgdYJz1fRhh16bU7u6PPmY7",
+                "09c2686880095b1a4c249ee3ac4eea8a014f11e6f986d0b5025ac1f39afbd9ae",
+                true,
+            ),
+        ];
+
+        for (passphrase, encrypted, secret, compressed) in vectors {
+            let (key, decrypted_compressed) = PrivateKey::decrypt_bip38(encrypted, passphrase)?;
+            assert_eq!(hex::encode(key.to_bytes()), secret);
+            assert_eq!(decrypted_compressed, compressed);
+            assert_eq!(key.encrypt_bip38(passphrase, compressed)?, encrypted);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ec_multiplied_vectors() -> Result<()> {
+        let vectors = [
+            (
+                "TestingOneTwoThree",
+                "6PfQu77ygVyJLZjfvMLyhLMQbYnu5uguoJJ4kMCLqWwPEdfpwANVS76gTX",
+                "1PE6TQi6HTVNz5DLwB1LcpMBALubfuN2z2",
+                "a43a940577f4e97f5c4d39eb14ff083a98187c64ea7c99ef7ce460833959a519",
+            ),
+            (
+                "Satoshi",
+                "6PfLGnQs6VZnrNpmVKfjotbnQuaJK4KZoPFrAjx1JMJUa1Ft8gnf5WxfKd",
+                "1CqzrtZC6mXSAhoxtFwVjz8LtwLJjDYU3V",
+                "c2c8036df268f498099350718c4a3ef3984d2be84618c2650f5171dcc5eb660a",
+            ),
+            (
+                "MOLON LABE",
+                "6PgNBNNzDkKdhkT6uJntUXwwzQV8Rr2tZcbkDcuC9DZRsS6AtHts4Ypo1j",
+                "1Jscj8ALrYu2y9TD8NrpvDBugPedmbj4Yh",
+                "44ea95afbf138356a05ea32110dfd627232d0f2991ad221187be356f19fa8190",
+            ),
+            (
+                "\u{039c}\u{039f}\u{039b}\u{03a9}\u{039d} \u{039b}\u{0391}\u{0392}\u{0395}",
+                "6PgGWtx25kUg8QWvwuJAgorN6k9FbE25rv5dMRwu5SKMnfpfVe5mar2ngH",
+                "1Lurmih3KruL4xDB5FmHof38yawNtP9oGf",
+                "ca2759aa4adb0f96c414f36abeb8db59342985be9fa50faac228c8e7d90e3006",
+            ),
+        ];
+
+        for (passphrase, encrypted, expected_address, secret) in vectors {
+            let (key, compressed) = PrivateKey::decrypt_bip38(encrypted, passphrase)?;
+            assert_eq!(hex::encode(key.to_bytes()), secret);
+            assert!(!compressed);
+            assert_eq!(address(key.point(), compressed), expected_address);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn confirmation_code_vectors() -> Result<()> {
+        assert_eq!(
+            verify_confirmation_code(
+                "cfrm38V8aXBn7JWA1ESmFMUn6erxeBGZGAxJPY4e36S9QWkzZKtaVqLNMgnifETYw7BPwWC9aPD",
+                "MOLON LABE"
+            )?,
+            "1Jscj8ALrYu2y9TD8NrpvDBugPedmbj4Yh"
+        );
+        assert_eq!(
+            verify_confirmation_code(
+                "cfrm38V8G4qq2ywYEFfWLD5Cc6msj9UwsG2Mj4Z6QdGJAFQpdatZLavkgRd1i4iBMdRngDqDs51",
+                "\u{039c}\u{039f}\u{039b}\u{03a9}\u{039d} \u{039b}\u{0391}\u{0392}\u{0395}"
+            )?,
+            "1Lurmih3KruL4xDB5FmHof38yawNtP9oGf"
+        );
+        assert_eq!(
+            error(verify_confirmation_code(
+                "cfrm38V8aXBn7JWA1ESmFMUn6erxeBGZGAxJPY4e36S9QWkzZKtaVqLNMgnifETYw7BPwWC9aPD",
+                "molon labe"
+            )),
+            Bip38Error::WrongPassphrase
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn generates_keys_from_intermediate_codes() -> Result<()> {
+        // intermediate code of the first EC-multiplied vector
+        let generated = generate_encrypted_key(
+            "passphrasepxFy57B9v8HtUsszJYKReoNDV6VHjUSGt8EVJmux9n1J3Ltf1gRxyDGXqnf9qm",
+            true,
+        )?;
+        let (key, compressed) =
+            PrivateKey::decrypt_bip38(&generated.encrypted_key, "TestingOneTwoThree")?;
+        assert!(compressed);
+        assert_eq!(address(key.point(), true), generated.address);
+        assert_eq!(
+            verify_confirmation_code(&generated.confirmation_code, "TestingOneTwoThree")?,
+            generated.address
+        );
+
+        let intermediate = intermediate_code("MOLON LABE", Some((263183, 1)))?;
+        assert!(intermediate.starts_with("passphrase"));
+        let generated = generate_encrypted_key(&intermediate, false)?;
+        assert!(generated.encrypted_key.starts_with("6Pg"));
+        assert!(generated.confirmation_code.starts_with("cfrm38"));
+        let (key, _) = PrivateKey::decrypt_bip38(&generated.encrypted_key, "MOLON LABE")?;
+        assert_eq!(address(key.point(), false), generated.address);
+        assert_eq!(
+            verify_confirmation_code(&generated.confirmation_code, "MOLON LABE")?,
+            generated.address
+        );
+
+        assert_eq!(
+            error(intermediate_code("MOLON LABE", Some((MAX_LOT + 1, 0)))),
+            Bip38Error::InvalidLotSequence(MAX_LOT + 1, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_wrong_passphrase() {
+        assert_eq!(
+            error(PrivateKey::decrypt_bip38(
+                "6PRVWUbkzzsbcVac2qwfssoUJAN1Xhrg6bNk8J7Nzm5H7kxEbn2Nh2ZoGg",
+                "TestingOneTwoThre"
+            )),
+            Bip38Error::WrongPassphrase
+        );
+        assert_eq!(
+            error(PrivateKey::decrypt_bip38(
+                "6PfQu77ygVyJLZjfvMLyhLMQbYnu5uguoJJ4kMCLqWwPEdfpwANVS76gTX",
+                "Satoshi"
+            )),
+            Bip38Error::WrongPassphrase
+        );
+    }
+}
@@ -5,6 +5,8 @@ use {super::element::Element, ibig::UBig, thiserror::Error};
 pub enum SECP256K1CurveError {
     #[error("(`{0:?}`, {1:?}`) not on the curve")]
     InvalidPoint(Option<Element>, Option<Element>),
+    #[error("invalid SEC encoding `{0}`")]
+    InvalidSec(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Error)]
@@ -13,3 +15,38 @@ pub enum SECP256K1FieldError {
     #[error("`{0}` not in field range 0 to 2^256 - 2^32 - 977")]
     NotInRange(UBig),
 }
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Private Keys
+pub enum SECP256K1KeyError {
+    #[error("secret not in range 1 to the curve order")]
+    SecretOutOfRange,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Signatures
+pub enum SECP256K1SignatureError {
+    #[error("invalid Schnorr signature length {0}, expected 64")]
+    InvalidSchnorrLength(usize),
+    #[error("signing nonce is zero")]
+    ZeroNonce,
+    #[error("invalid DER signature encoding")]
+    InvalidDer,
+    #[error("signature does not recover a public key")]
+    Unrecoverable,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to BIP38 passphrase-protected keys
+pub enum Bip38Error {
+    #[error("invalid {0} length {1}")]
+    InvalidLength(String, usize),
+    #[error("unknown BIP38 prefix")]
+    InvalidPrefix,
+    #[error("invalid BIP38 flag byte {0:#04x}")]
+    InvalidFlags(u8),
+    #[error("lot {0} or sequence {1} out of range")]
+    InvalidLotSequence(u32, u32),
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+}
@@ -1,19 +1,26 @@
 use {
     super::{
         constants::{G, N, N_RING},
+        errors::{SECP256K1KeyError, SECP256K1SignatureError},
         point::Point,
+        schnorr::SchnorrSignature,
         signature::Signature,
     },
-    anyhow::Result,
+    crate::utils::{encoding::to_32_bytes, hash::tagged_hash},
+    anyhow::{bail, Result},
     hmac::{Hmac, Mac},
     ibig::{modular::IntoModulo, UBig},
     sha2::Sha256,
-    std::fmt::{self, Display, Formatter},
+    std::fmt::{self, Debug, Display, Formatter},
+    zeroize::{Zeroize, Zeroizing},
 };
 
+#[derive(Clone)]
+/// Secp256k1 private key, whose secret is wiped from memory when it is dropped
 pub struct PrivateKey {
     point: Point,
-    e: UBig,
+    /// Secret scalar as 32 big endian bytes
+    secret: [u8; 32],
 }
 
 impl PrivateKey {
@@ -22,22 +29,104 @@ impl PrivateKey {
         let e = UBig::from_str_radix(e, radix)?;
         Ok(Self {
             point: G.with(|g| &e * g),
-            e,
+            secret: to_32_bytes(&e),
         })
     }
 
+    /// Create a new Private Key from a 32 byte big endian secret
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let e = UBig::from_be_bytes(bytes);
+        // secret must be a non zero scalar smaller than the curve order
+        if bytes.len() != 32 || e == UBig::from(0_u8) || N.with(|n| e >= *n) {
+            bail!(SECP256K1KeyError::SecretOutOfRange);
+        }
+
+        Ok(Self {
+            point: G.with(|g| &e * g),
+            secret: to_32_bytes(&e),
+        })
+    }
+
+    /// Return the secret as 32 big endian bytes
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.secret
+    }
+
+    /// Secret scalar, converted for the signing arithmetic
+    fn e(&self) -> UBig {
+        UBig::from_be_bytes(&self.secret)
+    }
+
+    /// Return the public key point of the Private Key
+    pub fn point(&self) -> &Point {
+        &self.point
+    }
+
     /// Generate a Signature from a message hash (in hexadecimal) using the Private Key
     pub fn sign(&self, z: &str) -> Result<Signature> {
+        Ok(self.sign_recoverable(z)?.0)
+    }
+
+    /// Generate a Signature from a message hash (in hexadecimal), with the recovery id that
+    /// identifies the public key among those the signature is valid for
+    pub fn sign_recoverable(&self, z: &str) -> Result<(Signature, u8)> {
         let z = UBig::from_str_radix(z, 16)?;
         let k = self.deterministic_k(&z)?; // generate deterministic k for given z
-        let r = G.with(|g| (&k * g).x()); // x co coordinate of R point
+        let point_r = G.with(|g| &k * g);
+        let r = point_r.x(); // x co coordinate of R point
         let s = N_RING.with(|n_ring| {
-            ((z + &r * &self.e).into_modulo(n_ring) / k.into_modulo(n_ring)).residue()
+            ((z + &r * self.e()).into_modulo(n_ring) / k.into_modulo(n_ring)).residue()
         }); // s = (z + r * secret) / k
 
-        let s = N.with(|n| if s > n / 2 { n - s } else { s }); // correct s if greater than half the order
+        // parity of the y coordinate of R, and whether its x coordinate exceeded the order
+        let mut recovery_id =
+            u8::from(!point_r.has_even_y()) | (u8::from(N.with(|n| r >= *n)) << 1);
+        let r = N.with(|n| r % n);
+
+        // correct s if greater than half the order, which negates R
+        let s = N.with(|n| {
+            if s > n / 2 {
+                recovery_id ^= 1;
+                n - s
+            } else {
+                s
+            }
+        });
 
-        Ok(Signature::new(r, s))
+        Ok((Signature::new(r, s), recovery_id))
+    }
+
+    /// Generate a BIP340 Schnorr Signature of the 32 byte message `msg`
+    ///
+    /// `aux_rand` is fresh randomness mixed into the nonce to protect against side channels
+    pub fn sign_schnorr(&self, msg: &[u8; 32], aux_rand: &[u8; 32]) -> Result<SchnorrSignature> {
+        // the secret is negated so the public key has an even y coordinate
+        let d = if self.point.has_even_y() {
+            self.e()
+        } else {
+            N.with(|n| n - self.e())
+        };
+        let p = self.point.x_only();
+
+        let mut t = Zeroizing::new(to_32_bytes(&d));
+        for (t, a) in t.iter_mut().zip(tagged_hash("BIP0340/aux", aux_rand)) {
+            *t ^= a;
+        }
+        let rand = tagged_hash("BIP0340/nonce", &[t.as_slice(), &p, msg].concat());
+        let k = N.with(|n| UBig::from_be_bytes(&rand) % n);
+        if k == UBig::from(0_u8) {
+            bail!(SECP256K1SignatureError::ZeroNonce);
+        }
+
+        // the nonce is negated so the nonce point has an even y coordinate
+        let r = G.with(|g| &k * g);
+        let k = if r.has_even_y() { k } else { N.with(|n| n - k) };
+        let r = r.x_only();
+
+        let e = tagged_hash("BIP0340/challenge", &[r.as_slice(), &p, msg].concat());
+        let s = N.with(|n| (k + UBig::from_be_bytes(&e) % n * d) % n);
+
+        Ok(SchnorrSignature::new(r, to_32_bytes(&s)))
     }
 
     /// Generate a unique, deterministic k for a given message hash (`z`) and Private Key (`self`).
@@ -51,7 +140,7 @@ impl PrivateKey {
                 z.to_be_bytes()
             }
         });
-        let secret_bytes = self.e.to_be_bytes();
+        let secret_bytes = Zeroizing::new(self.e().to_be_bytes());
 
         type Sha256Hmac = Hmac<Sha256>;
 
@@ -110,11 +199,87 @@ impl PrivateKey {
     }
 }
 
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
 impl Display for PrivateKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.point)
     }
 }
 
+impl Debug for PrivateKey {
+    /// Public key only, never the secret
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateKey")
+            .field("point", &self.point)
+            .finish_non_exhaustive()
+    }
+}
+
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() -> Result<()> {
+        let key = PrivateKey::new("12345", 10)?;
+        let parsed = PrivateKey::from_bytes(&key.to_bytes())?;
+
+        assert_eq!(parsed.point(), key.point());
+        Ok(())
+    }
+
+    #[test]
+    fn debug_hides_the_secret() -> Result<()> {
+        let key = PrivateKey::new("deadbeef", 16)?;
+        let debug = format!("{key:?}");
+
+        assert!(!debug.contains("deadbeef") && !debug.contains(&hex::encode(key.to_bytes())));
+        assert!(debug.contains(&format!("{:?}", key.point())));
+        Ok(())
+    }
+
+    #[test]
+    fn schnorr_signature_vectors() -> Result<()> {
+        // BIP340 test vectors 0 and 1
+        for (secret, aux, msg, signature) in [
+            (
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "e907831f80848d1069a5371b402410364bdf1c5f8307b0084c55f1ce2dca821525f66a4a85ea8b71e482a74f382d2ce5ebeee8fdb2172f477df4900d310536c0",
+            ),
+            (
+                "b7e151628aed2a6abf7158809cf4f3c762e7160f38b4da56a784d9045190cfef",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89",
+                "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a",
+            ),
+        ] {
+            let key = PrivateKey::from_bytes(&hex::decode(secret)?)?;
+            let msg = hex::decode(msg)?.try_into().unwrap();
+            let sig = key.sign_schnorr(&msg, &hex::decode(aux)?.try_into().unwrap())?;
+
+            assert_eq!(sig.to_string(), signature);
+            assert!(key.point().verify_schnorr(&msg, &sig));
+            assert!(!key.point().verify_schnorr(&[0xff; 32], &sig));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn zero_secret_rejected() -> Result<()> {
+        assert!(PrivateKey::from_bytes(&[0; 32]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn secret_above_order_rejected() -> Result<()> {
+        assert!(PrivateKey::from_bytes(&[0xff; 32]).is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
-mod constants;
+pub mod bip38;
+pub(crate) mod constants;
 mod element;
 mod errors;
 pub mod keys;
 pub mod point;
+pub mod schnorr;
 pub mod signature;
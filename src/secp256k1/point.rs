@@ -1,10 +1,15 @@
 use {
     super::{
-        constants::{B, G, N_RING, P},
+        constants::{B, G, N, N_RING, P},
         element::Element,
-        errors::SECP256K1CurveError,
+        errors::{SECP256K1CurveError, SECP256K1SignatureError},
+        schnorr::SchnorrSignature,
         signature::Signature,
     },
+    crate::utils::{
+        encoding::to_32_bytes,
+        hash::{hash160, tagged_hash},
+    },
     anyhow::{bail, Result},
     hex::encode_upper,
     ibig::{modular::IntoModulo, UBig},
@@ -66,6 +71,12 @@ impl Point {
     ///
     /// `z` is the message hash (256 bits)
     pub fn verify(&self, z: UBig, signature: Signature) -> bool {
+        // r and s must be non-zero scalars, or s has no inverse
+        let in_range = |x: UBig| N.with(|n| x != UBig::from(0u8) && x < *n);
+        if !in_range(signature.r()) || !in_range(signature.s()) || self.is_inf() {
+            return false;
+        }
+
         let u = N_RING.with(|o| (z.into_modulo(o) / signature.s().into_modulo(o)).residue());
 
         let v = N_RING
@@ -77,7 +88,65 @@ impl Point {
 
         let r = ug + vp;
 
-        r.x.unwrap().num() == signature.r()
+        // x of R is reduced modulo the order, as r is
+        r.x.is_some_and(|x| N.with(|n| x.num() % n) == signature.r())
+    }
+
+    /// Recover the public key that generated `signature` of the message hash `z`, picked among
+    /// the candidates by `recovery_id`
+    pub fn recover(z: UBig, signature: &Signature, recovery_id: u8) -> Result<Self> {
+        let in_range = |x: UBig| N.with(|n| x != UBig::from(0u8) && x < *n);
+        if recovery_id > 3 || !in_range(signature.r()) || !in_range(signature.s()) {
+            bail!(SECP256K1SignatureError::Unrecoverable);
+        }
+
+        // R is the nonce point, whose x coordinate reduced modulo the order is r
+        let x = signature.r()
+            + if recovery_id & 2 != 0 {
+                N.with(|n| n.clone())
+            } else {
+                UBig::from(0u8)
+            };
+        if P.with(|p| x >= *p) {
+            bail!(SECP256K1SignatureError::Unrecoverable);
+        }
+        let point_r = Self::lift_x(Element::new(&x.to_string(), 10)?, recovery_id & 1 == 0)
+            .map_err(|_| SECP256K1SignatureError::Unrecoverable)?;
+
+        // P = (sR - zG) / r
+        let u = N_RING.with(|o| {
+            ((N.with(|n| n - z % n)).into_modulo(o) / signature.r().into_modulo(o)).residue()
+        });
+        let v = N_RING
+            .with(|o| (signature.s().into_modulo(o) / signature.r().into_modulo(o)).residue());
+        let point = G.with(|g| u * g) + v * point_r;
+        if point.is_inf() {
+            bail!(SECP256K1SignatureError::Unrecoverable);
+        }
+
+        Ok(point)
+    }
+
+    /// Verify the BIP340 Schnorr signature of the 32 byte message `msg` by the x-only key of `self`
+    pub fn verify_schnorr(&self, msg: &[u8; 32], signature: &SchnorrSignature) -> bool {
+        let Ok(p) = Self::from_x_only(&self.x_only()) else {
+            return false;
+        };
+        let r = UBig::from_be_bytes(signature.r());
+        let s = UBig::from_be_bytes(signature.s());
+        if P.with(|p| r >= *p) || N.with(|n| s >= *n) {
+            return false;
+        }
+
+        let e = tagged_hash(
+            "BIP0340/challenge",
+            &[signature.r(), p.x_only().as_slice(), msg].concat(),
+        );
+        let e = N.with(|n| UBig::from_be_bytes(&e) % n);
+        // R = sG - eP
+        let point = G.with(|g| s * g) + N.with(|n| (n - e) % n) * p;
+
+        !point.is_inf() && point.has_even_y() && point.x() == r
     }
 
     pub fn x(&self) -> UBig {
@@ -88,64 +157,81 @@ impl Point {
         self.y.clone().unwrap().num()
     }
 
-    /// Return the SEC serialisation of the point
+    /// Return the hex encoded SEC serialisation of the point
     pub fn serialise(&self, compressed: bool) -> String {
+        encode_upper(self.sec(compressed))
+    }
+
+    /// Return the SEC serialisation of the point
+    pub fn sec(&self, compressed: bool) -> Vec<u8> {
+        let x = to_32_bytes(&self.x());
         if compressed {
-            let mut prefix = b"\x03"; // assume y is odd
-            if self.y().trailing_zeros().is_none() || self.y().trailing_zeros().unwrap() > 0 {
-                // y is even
-                prefix = b"\x02"; // update prefix
-            }
-            let enc = [prefix, self.x().to_be_bytes().as_slice()].concat();
-            encode_upper(enc)
+            // prefix encodes the parity of y
+            let prefix = if self.has_even_y() { 0x02 } else { 0x03 };
+            [&[prefix], x.as_slice()].concat()
         } else {
-            let enc = [
-                b"\x04",
-                self.x().to_be_bytes().as_slice(),
-                &self.y().to_be_bytes().as_slice(),
-            ]
-            .concat();
-            encode_upper(enc)
+            [&[0x04], x.as_slice(), to_32_bytes(&self.y()).as_slice()].concat()
         }
     }
 
-    /// Parse the hex encoded SEC serialisation of the point
-    pub fn parse(self, sec_hex: &str) -> Result<Self> {
-        // get prefix of point
-        let prefix = sec_hex.as_bytes()[0];
-
-        // uncompressed SEC serialisation
-        if prefix == b'\x04' {
-            return Ok(Self {
-                x: Some(Element::new(sec_hex.get(1..33).unwrap(), 16)?),
-                y: Some(Element::new(sec_hex.get(33..65).unwrap(), 16)?),
-            });
+    /// Return the HASH160 of the SEC serialisation of the point
+    pub fn hash160(&self, compressed: bool) -> [u8; 20] {
+        hash160(&self.sec(compressed))
+    }
+
+    /// Return the BIP340 x-only serialisation of the point
+    pub fn x_only(&self) -> [u8; 32] {
+        to_32_bytes(&self.x())
+    }
+
+    /// Parse the BIP340 x-only serialisation of a point, which implies an even y coordinate
+    pub fn from_x_only(x_only: &[u8]) -> Result<Self> {
+        if x_only.len() != 32 {
+            bail!(SECP256K1CurveError::InvalidSec(hex::encode(x_only)));
         }
 
-        // compressed SEC serialisation
-        let x = Element::new(sec_hex.get(1..33).unwrap(), 16)?;
-        let beta = B.with(|b| b + x.pow("3", 10).unwrap()).sqrt();
+        Self::lift_x(Element::new(&hex::encode(x_only), 16)?, true)
+    }
 
-        let odd_y;
-        let even_y;
+    /// If the y coordinate of the point is even
+    pub fn has_even_y(&self) -> bool {
+        self.y() % 2 == 0
+    }
 
-        // if beta is even
-        if beta.num() % 2 == 0 {
-            odd_y = Element::new(&P.with(|p| p - beta.num()).to_string(), 10)?;
-            even_y = beta;
-        } else {
-            // beta is odd
-            even_y = Element::new(&P.with(|p| p - beta.num()).to_string(), 10)?;
-            odd_y = beta;
+    /// Parse the hex encoded SEC serialisation of the point
+    pub fn parse(sec_hex: &str) -> Result<Self> {
+        Self::from_sec(&hex::decode(sec_hex)?)
+    }
+
+    /// Parse the SEC serialisation of the point
+    pub fn from_sec(sec: &[u8]) -> Result<Self> {
+        match (sec.first(), sec.len()) {
+            // uncompressed SEC serialisation
+            (Some(0x04), 65) => Self::new(
+                Some(Element::new(&hex::encode(&sec[1..33]), 16)?),
+                Some(Element::new(&hex::encode(&sec[33..65]), 16)?),
+            ),
+            // compressed SEC serialisation
+            (Some(prefix @ (0x02 | 0x03)), 33) => {
+                let x = Element::new(&hex::encode(&sec[1..33]), 16)?;
+                Self::lift_x(x, *prefix == 0x02)
+            }
+            _ => bail!(SECP256K1CurveError::InvalidSec(hex::encode(sec))),
         }
+    }
 
-        // y is even
-        if prefix == b'\x02' {
-            Ok(Self::new(Some(x), Some(even_y))?)
+    /// Return the point with x coordinate `x` and a y coordinate of the requested parity
+    pub(crate) fn lift_x(x: Element, even: bool) -> Result<Self> {
+        let beta = B.with(|b| b + x.pow("3", 10).unwrap()).sqrt();
+
+        // beta or its negation is the y coordinate
+        let y = if (beta.num() % 2 == 0) == even {
+            beta
         } else {
-            // y is odd
-            Ok(Self::new(Some(x), Some(odd_y))?)
-        }
+            Element::new(&P.with(|p| (p - beta.num()) % p).to_string(), 10)?
+        };
+
+        Self::new(Some(x), Some(y))
     }
 }
 
@@ -322,4 +408,32 @@ mod test {
         assert_eq!(scalar * &g, &g + &g + g);
         Ok(())
     }
+
+    #[test]
+    fn sec_round_trip() -> Result<()> {
+        let p = G.with(|g| ubig!(5001) * g);
+
+        assert_eq!(
+            p.serialise(true),
+            "0357A4F368868A8A6D572991E484E664810FF14C05C0FA023275251151FE0E53D1"
+        );
+        assert_eq!(Point::from_sec(&p.sec(true))?, p);
+        assert_eq!(Point::from_sec(&p.sec(false))?, p);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_compressed_even() -> Result<()> {
+        let p = Point::parse("0250863AD64A87AE8A2FE83C1AF1A8403CB53F53E486D8511DAD8A04887E5B2352")?;
+
+        assert!(p.has_even_y());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_sec() -> Result<()> {
+        assert!(Point::from_sec(&[0x05; 33]).is_err());
+        assert!(Point::from_sec(&[0x02; 32]).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,51 @@
+use {
+    super::errors::SECP256K1SignatureError,
+    anyhow::{bail, Result},
+    std::fmt::{self, Display, Formatter},
+};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// BIP340 Schnorr signature, the x coordinate of the nonce point followed by the scalar `s`
+pub struct SchnorrSignature {
+    bytes: [u8; 64],
+}
+
+impl SchnorrSignature {
+    /// Create a signature from the x-only nonce point `r` and the scalar `s`
+    pub fn new(r: [u8; 32], s: [u8; 32]) -> Self {
+        let mut bytes = [0; 64];
+        bytes[..32].copy_from_slice(&r);
+        bytes[32..].copy_from_slice(&s);
+        Self { bytes }
+    }
+
+    /// Parse the 64 byte serialisation of the signature
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Ok(bytes) = bytes.try_into() else {
+            bail!(SECP256K1SignatureError::InvalidSchnorrLength(bytes.len()));
+        };
+
+        Ok(Self { bytes })
+    }
+
+    /// Return the 64 byte serialisation of the signature
+    pub fn to_bytes(&self) -> [u8; 64] {
+        self.bytes
+    }
+
+    /// Return the x coordinate of the nonce point
+    pub fn r(&self) -> &[u8] {
+        &self.bytes[..32]
+    }
+
+    /// Return the scalar of the signature
+    pub fn s(&self) -> &[u8] {
+        &self.bytes[32..]
+    }
+}
+
+impl Display for SchnorrSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.bytes))
+    }
+}
@@ -1,9 +1,18 @@
 use {
+    super::errors::SECP256K1SignatureError,
+    anyhow::{bail, Result},
     hex::encode_upper,
     ibig::UBig,
     std::fmt::{self, Display, Formatter},
 };
 
+/// Size of a DER encoded signature with its sighash byte, at most
+///
+/// Signatures are assumed to have a low S value, as relay policy requires and `PrivateKey::sign`
+/// produces, so only r can take 33 bytes.
+pub const MAX_ECDSA_SIG_SIZE: usize = 72;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Signature {
     r: UBig,
     s: UBig,
@@ -27,6 +36,11 @@ impl Signature {
 
     /// Serialises the signature using DER encoding.
     pub fn serialise(&self) -> String {
+        encode_upper(self.der())
+    }
+
+    /// Return the DER encoding of the signature.
+    pub fn der(&self) -> Vec<u8> {
         // encode r value
         let mut r_bytes = self.r.to_be_bytes();
         // first byte >= 0x80
@@ -49,10 +63,118 @@ impl Signature {
         .concat();
 
         // create final signature
-        enc = [&[0x30_u8, enc.len() as u8], enc.as_slice()].concat();
+        [&[0x30_u8, enc.len() as u8], enc.as_slice()].concat()
+    }
+
+    /// Parse a strict DER encoded signature as required by BIP66
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        // sequence tag and length covering the rest of the encoding
+        let [0x30, len, rest @ ..] = der else {
+            bail!(SECP256K1SignatureError::InvalidDer);
+        };
+        if *len as usize != rest.len() {
+            bail!(SECP256K1SignatureError::InvalidDer);
+        }
+
+        let (r, rest) = parse_der_integer(rest)?;
+        let (s, rest) = parse_der_integer(rest)?;
+        if !rest.is_empty() {
+            bail!(SECP256K1SignatureError::InvalidDer);
+        }
 
-        encode_upper(enc)
+        Ok(Self::new(r, s))
     }
+
+    /// Parse a DER-like signature as OpenSSL did before BIP66, as `ecdsa_signature_parse_der_lax`
+    /// of Bitcoin Core does
+    ///
+    /// Lengths may use the long form, integers may be padded or negative and trailing bytes are
+    /// ignored. Values longer than 32 bytes fail, which only rejects signatures that could never
+    /// verify.
+    pub fn from_der_lax(der: &[u8]) -> Result<Self> {
+        let mut pos = 0;
+        // sequence tag, with a length that is skipped over
+        if der.first() != Some(&0x30) {
+            bail!(SECP256K1SignatureError::InvalidDer);
+        }
+        pos += 1;
+        let Some(&len) = der.get(pos) else {
+            bail!(SECP256K1SignatureError::InvalidDer);
+        };
+        pos += 1;
+        if len & 0x80 != 0 {
+            let len = (len - 0x80) as usize;
+            if len > der.len() - pos {
+                bail!(SECP256K1SignatureError::InvalidDer);
+            }
+            pos += len;
+        }
+
+        let (r, next) = parse_lax_integer(der, pos)?;
+        let (s, _) = parse_lax_integer(der, next)?;
+
+        Ok(Self::new(r, s))
+    }
+}
+
+/// Parse an integer of a lax DER signature at `pos`, returning it and the position after it
+fn parse_lax_integer(der: &[u8], mut pos: usize) -> Result<(UBig, usize)> {
+    if der.get(pos) != Some(&0x02) {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    }
+    pos += 1;
+    let Some(&len_byte) = der.get(pos) else {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    };
+    pos += 1;
+
+    let len = match len_byte & 0x80 {
+        0 => len_byte as usize,
+        // long form lengths, with leading zeros ignored
+        _ => {
+            let size = (len_byte - 0x80) as usize;
+            if size > der.len() - pos {
+                bail!(SECP256K1SignatureError::InvalidDer);
+            }
+            let bytes = &der[pos..pos + size];
+            pos += size;
+            let bytes = &bytes[bytes.iter().take_while(|b| **b == 0).count()..];
+            if bytes.len() >= size_of::<usize>() {
+                bail!(SECP256K1SignatureError::InvalidDer);
+            }
+            bytes.iter().fold(0, |len, b| (len << 8) + *b as usize)
+        }
+    };
+    if len > der.len() - pos {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    }
+
+    let int = &der[pos..pos + len];
+    let int = &int[int.iter().take_while(|b| **b == 0).count()..];
+    if int.len() > 32 {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    }
+
+    Ok((UBig::from_be_bytes(int), pos + len))
+}
+
+/// Parse a positive, minimally encoded DER integer, returning it and the remaining bytes
+fn parse_der_integer(der: &[u8]) -> Result<(UBig, &[u8])> {
+    let [0x02, len, rest @ ..] = der else {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    };
+    let len = *len as usize;
+    if len == 0 || len > 33 || len > rest.len() {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    }
+
+    let (int, rest) = rest.split_at(len);
+    // negative numbers and unnecessary leading zeros are not allowed
+    if int[0] & 0x80 != 0 || (len > 1 && int[0] == 0 && int[1] & 0x80 == 0) {
+        bail!(SECP256K1SignatureError::InvalidDer);
+    }
+
+    Ok((UBig::from_be_bytes(int), rest))
 }
 
 impl Display for Signature {
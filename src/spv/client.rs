@@ -0,0 +1,1011 @@
+use {
+    super::{
+        errors::SpvError,
+        store::{HeaderStore, ScanState},
+    },
+    crate::{
+        block::{
+            body::Block,
+            header::{BlockHash, BlockHeader},
+        },
+        chain::headers::{Accepted, HeaderChain},
+        filter::basic::{BlockFilter, FilterHeader, BASIC_FILTER_TYPE},
+        network::params::Params,
+        p2p::{
+            message::{Message, MAX_HEADERS_RESULTS, PROTOCOL_VERSION},
+            payload::{
+                GetCFCheckpt, GetCFilters, GetHeadersMessage, Inventory, CFCHECKPT_INTERVAL,
+                MAX_CFHEADERS_SIZE, MAX_GETCFILTERS_SIZE, MSG_BLOCK, MSG_WITNESS_FLAG,
+            },
+            peer::{Peer, PeerEvent, BAN_THRESHOLD},
+        },
+        script::{address::Address, raw::Script, taproot::output_key},
+        secp256k1::point::Point,
+        transaction::tx::{OutPoint, TxOut},
+        utils::encoding::reversed_hex,
+    },
+    anyhow::{bail, Result},
+    std::{
+        collections::{HashMap, HashSet},
+        io::{Read, Write},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Most blocks requested from a peer at once
+const MAX_BLOCKS_IN_TRANSIT: usize = 16;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Output paying one of the watched scripts
+pub struct WalletOutput {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    /// Height of the block that created the output
+    pub height: u32,
+    /// Height of the block that spent the output
+    pub spent_height: Option<u32>,
+}
+
+/// Light client that syncs headers from peers and finds the outputs of watched scripts
+///
+/// Headers are validated into a header chain, so the client follows the chain with the most work
+/// of all its peers rather than trusting any of them. Only the blocks whose BIP158 filters match a
+/// watched script are downloaded. Filters are checked against the chain of filter headers, and
+/// blocks against the header chain, before they are used.
+pub struct SpvClient {
+    chain: HeaderChain,
+    store: HeaderStore,
+    scripts: HashSet<Script>,
+    scan: ScanState,
+    /// File the scan progress is saved to
+    scan_path: PathBuf,
+}
+
+/// Current time in seconds since the UNIX epoch, standing in for the network adjusted time
+fn adjusted_time() -> Result<u32> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as u32)
+}
+
+/// Wait for a message `select` picks out of the events of `peer`
+fn wait_for<S: Read + Write, T>(
+    peer: &mut Peer<S>,
+    mut select: impl FnMut(Message) -> Option<T>,
+) -> Result<T> {
+    loop {
+        if let PeerEvent::Message(message) = peer.next_event()? {
+            if let Some(selected) = select(message) {
+                return Ok(selected);
+            }
+        }
+    }
+}
+
+/// If `err` is peers disagreeing on filter headers without either being shown to lie
+fn is_conflict(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<SpvError>(),
+        Some(SpvError::FilterHeadersConflict(_))
+    )
+}
+
+impl SpvClient {
+    /// Client of the network of `params` keeping its headers in the store at `path`
+    ///
+    /// The scan progress is kept next to the headers, in a file with the `scan` extension.
+    pub fn open(params: Params, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut store = HeaderStore::open(path)?;
+        let chain = store.load(params)?;
+        let scan_path = path.with_extension("scan");
+        let scan = ScanState::load(&scan_path)?;
+
+        let mut client = Self {
+            chain,
+            store,
+            scripts: HashSet::new(),
+            scan,
+            scan_path,
+        };
+        // the headers may have been reorganised after the scan progress was saved
+        let known = (0..)
+            .zip(&client.scan.filter_headers)
+            .take_while(|(height, (hash, _))| client.chain.hash_at(*height) == Some(*hash))
+            .count();
+        client.scan.filter_headers.truncate(known);
+        client.rollback(known.saturating_sub(1) as u32);
+
+        Ok(client)
+    }
+
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    /// Height of the last block scanned for watched scripts
+    pub fn scanned_height(&self) -> u32 {
+        self.scan.scanned_height
+    }
+
+    /// Find outputs paying `script` in blocks scanned from now on
+    pub fn watch_script(&mut self, script: Script) {
+        self.scripts.insert(script);
+    }
+
+    pub fn watch_address(&mut self, address: &Address) {
+        self.watch_script(address.script_pubkey());
+    }
+
+    /// Watch the P2PKH, P2WPKH and key path only P2TR scripts of `point`
+    pub fn watch_key(&mut self, point: &Point) -> Result<()> {
+        let hash = point.hash160(true);
+        self.watch_script(Script::p2pkh(&hash));
+        self.watch_script(Script::p2wpkh(&hash));
+        self.watch_script(Script::p2tr(&output_key(point, None)?.x_only()));
+        Ok(())
+    }
+
+    /// Scan blocks again from `height`, finding outputs of scripts watched since they were scanned
+    pub fn rescan_from(&mut self, height: u32) {
+        self.rollback(height.saturating_sub(1));
+    }
+
+    /// Outputs of watched scripts in the active chain, spent or not
+    pub fn outputs(&self) -> impl Iterator<Item = &WalletOutput> {
+        self.scan.outputs.values()
+    }
+
+    /// Number of blocks confirming `outpoint`, counting the one that created it
+    pub fn confirmations(&self, outpoint: &OutPoint) -> Option<u32> {
+        let output = self.scan.outputs.get(outpoint)?;
+        Some(self.chain.height() + 1 - output.height)
+    }
+
+    /// Value of the unspent outputs with at least `min_confirmations`
+    pub fn balance(&self, min_confirmations: u32) -> u64 {
+        self.scan
+            .outputs
+            .values()
+            .filter(|output| output.spent_height.is_none())
+            .filter(|output| self.chain.height() + 1 - output.height >= min_confirmations)
+            .map(|output| output.txout.value)
+            .sum()
+    }
+
+    /// Download the headers `peer` has beyond the active chain, returning how many extended it
+    ///
+    /// A peer sending an invalid header is banned.
+    pub fn sync_headers<S: Read + Write>(&mut self, peer: &mut Peer<S>) -> Result<u32> {
+        let start_height = self.chain.height();
+        loop {
+            peer.send(&Message::GetHeaders(GetHeadersMessage {
+                version: PROTOCOL_VERSION,
+                locator: self.chain.locator(),
+                stop_hash: [0; 32],
+            }))?;
+            let headers = wait_for(peer, |message| match message {
+                Message::Headers(headers) => Some(headers),
+                _ => None,
+            })?;
+
+            for header in &headers {
+                if let Err(err) = self.accept_header(*header) {
+                    peer.misbehaving(BAN_THRESHOLD, format!("invalid header: {err}"))?;
+                }
+            }
+            // a full batch means the peer may have more
+            if headers.len() < MAX_HEADERS_RESULTS {
+                return Ok(self.chain.height().saturating_sub(start_height));
+            }
+        }
+    }
+
+    /// Download the filter headers of the active chain that are not known yet from `peer`
+    ///
+    /// A peer sending filter headers that do not follow the known ones is banned.
+    pub fn sync_filter_headers<S: Read + Write>(&mut self, peer: &mut Peer<S>) -> Result<()> {
+        while self.scan.filter_headers.len() as u32 <= self.chain.height() {
+            let start_height = self.scan.filter_headers.len() as u32;
+            let stop_height = self
+                .chain
+                .height()
+                .min(start_height + MAX_CFHEADERS_SIZE as u32 - 1);
+            let filter_headers = self.request_filter_headers(peer, start_height, stop_height)?;
+            for (height, filter_header) in (start_height..).zip(filter_headers) {
+                let hash = self.chain.hash_at(height).unwrap();
+                self.scan.filter_headers.push((hash, filter_header));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scan the blocks of the active chain that were not scanned yet, with filters from `peer`
+    ///
+    /// Only the blocks whose filters match a watched script are downloaded. A peer sending a
+    /// filter that does not match its filter header, or a block that does not match its header,
+    /// is banned. The progress is saved after each batch of filters.
+    pub fn scan_blocks<S: Read + Write>(&mut self, peer: &mut Peer<S>) -> Result<()> {
+        self.sync_filter_headers(peer)?;
+        while self.scan.scanned_height < self.chain.height() {
+            let start_height = self.scan.scanned_height + 1;
+            let stop_height = self
+                .chain
+                .height()
+                .min(self.scan.scanned_height + MAX_GETCFILTERS_SIZE as u32);
+            let matched = self.match_filters(peer, start_height, stop_height)?;
+
+            for heights in matched.chunks(MAX_BLOCKS_IN_TRANSIT) {
+                let blocks = self.download_blocks(peer, heights)?;
+                for (height, block) in heights.iter().zip(&blocks) {
+                    self.scan_block(block, *height);
+                }
+                self.scan.scanned_height = *heights.last().unwrap();
+            }
+            self.scan.scanned_height = stop_height;
+            self.scan.save(&self.scan_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sync headers from every peer, then scan the new blocks with filter headers the peers agree
+    /// on
+    ///
+    /// The filter headers come from the first peer and are checked against the checkpoints of
+    /// every other one. Where two peers disagree, the peer whose filter leaves out an output of
+    /// the block is banned. Peers that fail or misbehave are disconnected and skipped.
+    pub fn sync<S: Read + Write>(&mut self, peers: &mut [Peer<S>]) -> Result<()> {
+        let mut failure = None;
+        for peer in peers.iter_mut() {
+            if let Err(err) = self.sync_headers(peer) {
+                peer.disconnect();
+                failure = Some(err);
+            }
+        }
+
+        while let Some(first) = peers.iter().position(Peer::is_connected) {
+            let (source, others) = peers[first..].split_first_mut().unwrap();
+            let result = self.sync_filter_headers(source).and_then(|_| {
+                for other in others.iter_mut().filter(|other| other.is_connected()) {
+                    if let Err(err) = self.check_filter_headers(source, other) {
+                        if source.misbehaviour() >= BAN_THRESHOLD || is_conflict(&err) {
+                            return Err(err);
+                        }
+                        other.disconnect();
+                    }
+                }
+                self.scan_blocks(source)
+            });
+            match result {
+                Ok(()) => return Ok(()),
+                // neither peer could be shown to lie, so neither is trusted
+                Err(err) if is_conflict(&err) => return Err(err),
+                Err(err) => {
+                    source.disconnect();
+                    failure = Some(err);
+                }
+            }
+        }
+
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Compare the known filter headers, which came from `source`, with the checkpoints and
+    /// filter headers of `other`, banning whichever of the two is shown to lie
+    fn check_filter_headers<S: Read + Write>(
+        &mut self,
+        source: &mut Peer<S>,
+        other: &mut Peer<S>,
+    ) -> Result<()> {
+        let stop_hash = self.chain.hash_at(self.chain.height()).unwrap();
+        other.send(&Message::GetCFCheckpt(GetCFCheckpt {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash,
+        }))?;
+        let checkpoints = wait_for(other, |message| match message {
+            Message::CFCheckpt(cfcheckpt)
+                if cfcheckpt.filter_type == BASIC_FILTER_TYPE
+                    && cfcheckpt.stop_hash == stop_hash =>
+            {
+                Some(cfcheckpt.filter_headers)
+            }
+            _ => None,
+        })?;
+        if checkpoints.len() as u32 != self.chain.height() / CFCHECKPT_INTERVAL {
+            other.misbehaving(
+                BAN_THRESHOLD,
+                "wrong number of filter checkpoints".to_string(),
+            )?;
+        }
+
+        // the first interval the peer disagrees on, or the blocks after the last checkpoint
+        let mut start_height = 0;
+        let mut stop_height = self.chain.height();
+        let mut disputed = None;
+        for (height, checkpoint) in (1..).map(|n| n * CFCHECKPT_INTERVAL).zip(checkpoints) {
+            if self.scan.filter_headers[height as usize].1 != checkpoint {
+                stop_height = height;
+                disputed = Some(checkpoint);
+                break;
+            }
+            start_height = height + 1;
+        }
+        if start_height > stop_height {
+            return Ok(());
+        }
+
+        let filter_headers = self.request_filter_headers(other, start_height, stop_height)?;
+        if disputed.is_some_and(|checkpoint| filter_headers.last() != Some(&checkpoint)) {
+            other.misbehaving(
+                BAN_THRESHOLD,
+                "filter headers do not match the checkpoint".to_string(),
+            )?;
+        }
+        let conflict = (start_height..)
+            .zip(filter_headers)
+            .find(|(height, filter_header)| {
+                self.scan.filter_headers[*height as usize].1 != *filter_header
+            });
+        match conflict {
+            Some((height, filter_header)) => {
+                self.resolve_conflict(source, other, height, filter_header)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Ban whichever of `source` and `other` serves a filter at `height` that leaves out an output
+    /// of the block, where `other` has `their_header` rather than the known filter header
+    ///
+    /// Fails with a conflict if neither can be shown to lie.
+    fn resolve_conflict<S: Read + Write>(
+        &mut self,
+        source: &mut Peer<S>,
+        other: &mut Peer<S>,
+        height: u32,
+        their_header: FilterHeader,
+    ) -> Result<()> {
+        // the peers agree on the filter headers before `height`
+        let previous = self.previous_filter_header(height);
+        let (_, our_header) = self.scan.filter_headers[height as usize];
+
+        let ours = self.request_filter(source, height)?;
+        let theirs = self.request_filter(other, height)?;
+        let block = self.download_blocks(other, &[height])?.remove(0);
+        if their_header != theirs.filter_header(&previous) || !theirs.holds_outputs_of(&block)? {
+            other.misbehaving(
+                BAN_THRESHOLD,
+                "filter leaves out an output of its block".to_string(),
+            )?;
+        }
+        if our_header != ours.filter_header(&previous) || !ours.holds_outputs_of(&block)? {
+            // what was scanned with the filters of the source cannot be trusted
+            self.scan.filter_headers.truncate(height as usize);
+            self.rollback(height.saturating_sub(1));
+            self.scan.save(&self.scan_path)?;
+            source.misbehaving(
+                BAN_THRESHOLD,
+                "filter leaves out an output of its block".to_string(),
+            )?;
+        }
+
+        bail!(SpvError::FilterHeadersConflict(height))
+    }
+
+    /// Filter header of the block before `height`, or zero before the genesis block
+    fn previous_filter_header(&self, height: u32) -> FilterHeader {
+        height.checked_sub(1).map_or([0; 32], |previous| {
+            self.scan.filter_headers[previous as usize].1
+        })
+    }
+
+    /// Filter headers of the blocks from `start_height` to `stop_height` from `peer`, following
+    /// the known filter header of the block before them
+    ///
+    /// A peer sending filter headers that do not follow the known ones is banned.
+    fn request_filter_headers<S: Read + Write>(
+        &self,
+        peer: &mut Peer<S>,
+        start_height: u32,
+        stop_height: u32,
+    ) -> Result<Vec<FilterHeader>> {
+        let stop_hash = self.chain.hash_at(stop_height).unwrap();
+        peer.send(&Message::GetCFHeaders(GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash,
+        }))?;
+        let cfheaders = wait_for(peer, |message| match message {
+            Message::CFHeaders(cfheaders)
+                if cfheaders.filter_type == BASIC_FILTER_TYPE
+                    && cfheaders.stop_hash == stop_hash =>
+            {
+                Some(cfheaders)
+            }
+            _ => None,
+        })?;
+
+        if cfheaders.previous_filter_header != self.previous_filter_header(start_height)
+            || cfheaders.filter_hashes.len() as u32 != stop_height - start_height + 1
+        {
+            peer.misbehaving(
+                BAN_THRESHOLD,
+                "filter headers do not follow the known ones".to_string(),
+            )?;
+        }
+
+        Ok(cfheaders.filter_headers())
+    }
+
+    /// Filter of the block at `height` from `peer`
+    fn request_filter<S: Read + Write>(
+        &self,
+        peer: &mut Peer<S>,
+        height: u32,
+    ) -> Result<BlockFilter> {
+        let hash = self.chain.hash_at(height).unwrap();
+        peer.send(&Message::GetCFilters(GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height: height,
+            stop_hash: hash,
+        }))?;
+        wait_for(peer, |message| match message {
+            Message::CFilter(cfilter)
+                if cfilter.filter_type == BASIC_FILTER_TYPE && cfilter.block_hash == hash =>
+            {
+                Some(BlockFilter::new(cfilter.filter))
+            }
+            _ => None,
+        })
+    }
+
+    /// Heights of the blocks from `start_height` to `stop_height` whose filters from `peer` match
+    /// a watched script
+    fn match_filters<S: Read + Write>(
+        &mut self,
+        peer: &mut Peer<S>,
+        start_height: u32,
+        stop_height: u32,
+    ) -> Result<Vec<u32>> {
+        peer.send(&Message::GetCFilters(GetCFilters {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash: self.chain.hash_at(stop_height).unwrap(),
+        }))?;
+
+        let mut matched = vec![];
+        for height in start_height..=stop_height {
+            let cfilter = wait_for(peer, |message| match message {
+                Message::CFilter(cfilter) if cfilter.filter_type == BASIC_FILTER_TYPE => {
+                    Some(cfilter)
+                }
+                _ => None,
+            })?;
+            let (hash, filter_header) = self.scan.filter_headers[height as usize];
+            if cfilter.block_hash != hash {
+                bail!(SpvError::UnexpectedFilter(reversed_hex(
+                    &cfilter.block_hash
+                )));
+            }
+
+            let filter = BlockFilter::new(cfilter.filter);
+            if filter.filter_header(&self.previous_filter_header(height)) != filter_header {
+                peer.misbehaving(
+                    BAN_THRESHOLD,
+                    "filter does not match its header".to_string(),
+                )?;
+            }
+            if filter.match_any(&hash, &self.scripts)? {
+                matched.push(height);
+            }
+        }
+
+        Ok(matched)
+    }
+
+    /// Download the blocks of the active chain at `heights` from `peer`
+    ///
+    /// A peer sending a block that does not match its header is banned.
+    fn download_blocks<S: Read + Write>(
+        &mut self,
+        peer: &mut Peer<S>,
+        heights: &[u32],
+    ) -> Result<Vec<Block>> {
+        let hashes = heights
+            .iter()
+            .map(|height| self.chain.hash_at(*height).unwrap())
+            .collect::<Vec<_>>();
+        let inventory = hashes
+            .iter()
+            .map(|hash| Inventory::new(MSG_BLOCK | MSG_WITNESS_FLAG, *hash))
+            .collect();
+        peer.send(&Message::GetData(inventory))?;
+
+        let mut blocks: HashMap<BlockHash, Block> = HashMap::new();
+        while blocks.len() < hashes.len() {
+            let block = wait_for(peer, |message| match message {
+                Message::Block(block) => Some(block),
+                _ => None,
+            })?;
+            let hash = block.hash();
+            if !hashes.contains(&hash) {
+                bail!(SpvError::UnexpectedBlock(reversed_hex(&hash)));
+            }
+            if let Err(err) = block
+                .check_merkle_root()
+                .and_then(|_| block.check_witness_commitment())
+            {
+                peer.misbehaving(BAN_THRESHOLD, format!("invalid block: {err}"))?;
+            }
+            blocks.insert(hash, block);
+        }
+
+        Ok(hashes
+            .iter()
+            .map(|hash| blocks.remove(hash).unwrap())
+            .collect())
+    }
+
+    /// Record the outputs of `block` paying watched scripts and the inputs spending them
+    fn scan_block(&mut self, block: &Block, height: u32) {
+        for tx in &block.transactions {
+            for input in &tx.inputs {
+                if let Some(output) = self.scan.outputs.get_mut(&input.previous_output) {
+                    output.spent_height = Some(height);
+                }
+            }
+
+            let txid = tx.txid();
+            for (vout, txout) in tx.outputs.iter().enumerate() {
+                if self.scripts.contains(&txout.script_pubkey) {
+                    let outpoint = OutPoint::new(txid, vout as u32);
+                    self.scan.outputs.insert(
+                        outpoint,
+                        WalletOutput {
+                            outpoint,
+                            txout: txout.clone(),
+                            height,
+                            spent_height: None,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Accept `header` into the chain and the store
+    fn accept_header(&mut self, header: BlockHeader) -> Result<()> {
+        match self.chain.accept(header, adjusted_time()?)? {
+            Accepted::Extended => self.store.append(&[header])?,
+            Accepted::Reorganised { fork_height, .. } => {
+                self.rollback(fork_height);
+                self.scan.filter_headers.truncate(fork_height as usize + 1);
+                self.scan.save(&self.scan_path)?;
+                self.store.truncate(fork_height)?;
+                let branch = (fork_height + 1..=self.chain.height())
+                    .map(|height| {
+                        let hash = self.chain.hash_at(height).unwrap();
+                        self.chain.get(&hash).unwrap().header
+                    })
+                    .collect::<Vec<_>>();
+                self.store.append(&branch)?;
+            }
+            Accepted::Duplicate | Accepted::SideChain => {}
+        }
+
+        Ok(())
+    }
+
+    /// Forget what was scanned above `height`
+    fn rollback(&mut self, height: u32) {
+        self.scan
+            .outputs
+            .retain(|_, output| output.height <= height);
+        for output in self.scan.outputs.values_mut() {
+            if output.spent_height.is_some_and(|spent| spent > height) {
+                output.spent_height = None;
+            }
+        }
+        self.scan.scanned_height = self.scan.scanned_height.min(height);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            network::Network,
+            p2p::{
+                address::NetAddress,
+                errors::P2pError,
+                payload::{CFCheckpt, CFHeaders, CFilter, VersionMessage},
+            },
+            secp256k1::keys::PrivateKey,
+            transaction::tx::{Transaction, TxIn},
+        },
+        std::{
+            fs,
+            net::{Ipv4Addr, TcpListener, TcpStream},
+            path::PathBuf,
+            thread::{self, JoinHandle},
+        },
+    };
+
+    const NETWORK: Network = Network::Regtest;
+
+    const COIN: u64 = 100_000_000;
+
+    /// Key the watched outputs pay to
+    fn key() -> PrivateKey {
+        PrivateKey::new("5ec7", 16).unwrap()
+    }
+
+    fn key_script() -> Script {
+        Script::p2wpkh(&key().point().hash160(true))
+    }
+
+    /// Empty header store in the temporary directory, unique to the test
+    fn store_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("spv-{name}-{}.headers", std::process::id()));
+        remove_store(&path);
+        path
+    }
+
+    /// Remove the header store at `path` and the scan progress next to it
+    fn remove_store(path: &Path) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.with_extension("scan"));
+    }
+
+    /// Block on top of `prev` with a coinbase paying `script` and `transactions`
+    fn block(
+        prev: &BlockHeader,
+        height: u32,
+        script: &Script,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let mut coinbase = Transaction::new(2, 0);
+        let mut input = TxIn::new(OutPoint::null());
+        input.script_sig = Script::new().push_int(height as i64).push_int(0);
+        coinbase.inputs.push(input);
+        coinbase.outputs.push(TxOut::new(50 * COIN, script.clone()));
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: 4,
+                prev_blockhash: prev.hash(),
+                merkle_root: [0; 32],
+                time: prev.time + 600,
+                bits: NETWORK.params().pow_limit_bits(),
+                nonce: 0,
+            },
+            transactions: [vec![coinbase], transactions].concat(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().0;
+        while block.header.check_pow().is_err() {
+            block.header.nonce += 1;
+        }
+
+        block
+    }
+
+    /// Blocks extending `prev` at `height`, each with a coinbase paying `script`
+    fn extend(
+        blocks: &mut Vec<Block>,
+        mut prev: BlockHeader,
+        height: u32,
+        count: u32,
+        script: &Script,
+    ) {
+        for height in height..height + count {
+            let block = block(&prev, height, script, vec![]);
+            prev = block.header;
+            blocks.push(block);
+        }
+    }
+
+    /// Thread of a test node, returning the heights of the blocks it sent
+    type Node = JoinHandle<Result<Vec<u32>>>;
+
+    #[derive(Clone, Copy, PartialEq)]
+    /// Data a test node changes after the blocks were mined
+    enum Tampered {
+        /// The transactions of the block at the height
+        Block(u32),
+        /// The filter of the block at the height
+        Filter(u32),
+        /// The filter of the block at the height and the filter headers committing to it, which
+        /// leave out the outputs of the block
+        Hidden(u32),
+        /// The connection, which is closed after the handshake
+        Hangup,
+    }
+
+    /// Node serving `blocks`, which follow the regtest genesis block, and their filters until the
+    /// connection closes
+    fn serve(blocks: Vec<Block>, tampered: Option<Tampered>) -> Result<(Peer<TcpStream>, Node)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let stream = TcpStream::connect(listener.local_addr()?)?;
+        let receiver = NetAddress::new(listener.local_addr()?.ip(), 18444, 0);
+        let node_version = VersionMessage::new(receiver, 0, blocks.len() as i32)?;
+
+        let node = thread::spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            let genesis = Block {
+                header: NETWORK.params().genesis,
+                transactions: vec![],
+            };
+            let blocks = [vec![genesis], blocks].concat();
+
+            let mut scripts = HashMap::new();
+            let mut filters = vec![];
+            for block in &blocks {
+                for tx in &block.transactions {
+                    for (vout, txout) in tx.outputs.iter().enumerate() {
+                        let outpoint = OutPoint::new(tx.txid(), vout as u32);
+                        scripts.insert(outpoint, txout.script_pubkey.clone());
+                    }
+                }
+                let hidden = tampered == Some(Tampered::Hidden(filters.len() as u32));
+                filters.push(match hidden {
+                    // an empty set
+                    true => BlockFilter::new(vec![0]),
+                    false => {
+                        BlockFilter::from_block(block, |outpoint| Ok(scripts[outpoint].clone()))?
+                    }
+                });
+            }
+            let filter_headers = filters
+                .iter()
+                .scan([0; 32], |previous, filter| {
+                    *previous = filter.filter_header(previous);
+                    Some(*previous)
+                })
+                .collect::<Vec<_>>();
+            let height_of = |hash: &BlockHash| {
+                blocks
+                    .iter()
+                    .position(|block| block.hash() == *hash)
+                    .unwrap()
+            };
+
+            let mut served = vec![];
+            Message::Version(node_version).write(&mut stream, NETWORK)?;
+            Message::Verack.write(&mut stream, NETWORK)?;
+            if tampered == Some(Tampered::Hangup) {
+                // once the handshake is done
+                while Message::read(&mut stream, NETWORK)? != Message::Verack {}
+                return Ok(served);
+            }
+            // the client closing the connection ends the script
+            while let Ok(message) = Message::read(&mut stream, NETWORK) {
+                let reply = match message {
+                    Message::GetHeaders(getheaders) => {
+                        let start = getheaders
+                            .locator
+                            .iter()
+                            .find_map(|hash| blocks.iter().position(|block| block.hash() == *hash))
+                            .unwrap_or(0);
+                        let headers = blocks[start + 1..].iter().map(|block| block.header);
+                        vec![Message::Headers(headers.collect())]
+                    }
+                    Message::GetCFHeaders(request) => {
+                        let start = request.start_height as usize;
+                        let stop = height_of(&request.stop_hash);
+                        vec![Message::CFHeaders(CFHeaders {
+                            filter_type: BASIC_FILTER_TYPE,
+                            stop_hash: request.stop_hash,
+                            previous_filter_header: start
+                                .checked_sub(1)
+                                .map_or([0; 32], |previous| filter_headers[previous]),
+                            filter_hashes: filters[start..=stop]
+                                .iter()
+                                .map(BlockFilter::filter_hash)
+                                .collect(),
+                        })]
+                    }
+                    Message::GetCFCheckpt(request) => {
+                        let stop = height_of(&request.stop_hash);
+                        let checkpoints = (1..)
+                            .map(|n| n * CFCHECKPT_INTERVAL as usize)
+                            .take_while(|height| *height <= stop);
+                        vec![Message::CFCheckpt(CFCheckpt {
+                            filter_type: BASIC_FILTER_TYPE,
+                            stop_hash: request.stop_hash,
+                            filter_headers: checkpoints
+                                .map(|height| filter_headers[height])
+                                .collect(),
+                        })]
+                    }
+                    Message::GetCFilters(request) => {
+                        let start = request.start_height as usize;
+                        (start..=height_of(&request.stop_hash))
+                            .map(|height| {
+                                let mut filter = filters[height].content().to_vec();
+                                if tampered == Some(Tampered::Filter(height as u32)) {
+                                    // an empty set
+                                    filter = vec![0];
+                                }
+                                Message::CFilter(CFilter {
+                                    filter_type: BASIC_FILTER_TYPE,
+                                    block_hash: blocks[height].hash(),
+                                    filter,
+                                })
+                            })
+                            .collect()
+                    }
+                    Message::GetData(inventory) => inventory
+                        .iter()
+                        .map(|inv| {
+                            let height = height_of(&inv.hash);
+                            served.push(height as u32);
+                            let mut block = blocks[height].clone();
+                            if tampered == Some(Tampered::Block(height as u32)) {
+                                block.transactions[0].outputs[0].value += 1;
+                            }
+                            Message::Block(block)
+                        })
+                        .collect(),
+                    Message::Ping(nonce) => vec![Message::Pong(nonce)],
+                    _ => vec![],
+                };
+                for message in reply {
+                    message.write(&mut stream, NETWORK)?;
+                }
+            }
+            Ok(served)
+        });
+
+        let client_version =
+            VersionMessage::new(NetAddress::new(Ipv4Addr::LOCALHOST.into(), 18444, 0), 0, 0)?;
+        let mut peer = Peer::new(stream, NETWORK, client_version);
+        peer.handshake()?;
+        Ok((peer, node))
+    }
+
+    #[test]
+    fn syncs_and_tracks_watched_outputs() -> Result<()> {
+        let key = key();
+        let other = Script::op_return(b"other");
+        let genesis = NETWORK.params().genesis;
+
+        // block 1 pays the key, block 3 spends that to the key and someone else
+        let paid = block(&genesis, 1, &key_script(), vec![]);
+        let paid_outpoint = OutPoint::new(paid.transactions[0].txid(), 0);
+        let mut spend = Transaction::new(2, 0);
+        spend.inputs.push(TxIn::new(paid_outpoint));
+        let taproot = Script::p2tr(&output_key(key.point(), None)?.x_only());
+        spend.outputs.push(TxOut::new(30 * COIN, taproot));
+        spend.outputs.push(TxOut::new(20 * COIN, other.clone()));
+        let change = OutPoint::new(spend.txid(), 0);
+
+        let mut blocks = vec![paid];
+        let prev = blocks[0].header;
+        extend(&mut blocks, prev, 2, 1, &other);
+        blocks.push(block(&blocks[1].header, 3, &other, vec![spend]));
+        let prev = blocks[2].header;
+        extend(&mut blocks, prev, 4, 2, &other);
+
+        let path = store_path("sync");
+        let mut client = SpvClient::open(NETWORK.params(), &path)?;
+        client.watch_key(key.point())?;
+        let (peer, node) = serve(blocks.clone(), None)?;
+        let mut peers = [peer];
+        client.sync(&mut peers)?;
+        drop(peers);
+        // only the blocks whose filters match the key were downloaded
+        assert_eq!(node.join().unwrap()?, [1, 3]);
+
+        assert_eq!(client.chain().height(), 5);
+        assert_eq!(client.scanned_height(), 5);
+        assert_eq!(client.outputs().count(), 2);
+        let paid_output = client
+            .outputs()
+            .find(|output| output.outpoint == paid_outpoint);
+        assert_eq!(paid_output.unwrap().spent_height, Some(3));
+        assert_eq!(client.confirmations(&change), Some(3));
+        assert_eq!(client.balance(1), 30 * COIN);
+        assert_eq!(client.balance(4), 0);
+
+        // the outputs found and the scan progress were persisted with the headers
+        let reopened = SpvClient::open(NETWORK.params(), &path)?;
+        assert_eq!(reopened.scanned_height(), 5);
+        assert!(reopened.outputs().eq(client.outputs()));
+
+        // a longer fork from block 2 reorganises the spend away
+        let mut fork = blocks[..2].to_vec();
+        extend(&mut fork, blocks[1].header, 3, 4, &other);
+        let (mut peer, node) = serve(fork.clone(), None)?;
+        assert_eq!(client.sync_headers(&mut peer)?, 1);
+        assert_eq!(client.scanned_height(), 2);
+        client.scan_blocks(&mut peer)?;
+        drop(peer);
+        assert!(node.join().unwrap()?.is_empty());
+
+        assert_eq!(client.confirmations(&change), None);
+        assert_eq!(client.confirmations(&paid_outpoint), Some(6));
+        assert_eq!(client.balance(1), 50 * COIN);
+
+        // the headers of the active chain and the scan of the fork were persisted
+        let reopened = SpvClient::open(NETWORK.params(), &path)?;
+        assert_eq!(reopened.chain().tip(), client.chain().tip());
+        assert_eq!(reopened.chain().tip().header, fork[5].header);
+        assert_eq!(reopened.scanned_height(), 6);
+        assert!(reopened.outputs().eq(client.outputs()));
+        remove_store(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn bans_peers_sending_tampered_blocks() -> Result<()> {
+        let mut blocks = vec![];
+        extend(&mut blocks, NETWORK.params().genesis, 1, 3, &key_script());
+
+        let path = store_path("tampered");
+        let mut client = SpvClient::open(NETWORK.params(), &path)?;
+        client.watch_key(key().point())?;
+        let (mut peer, node) = serve(blocks, Some(Tampered::Block(2)))?;
+        client.sync_headers(&mut peer)?;
+        let err = client.scan_blocks(&mut peer).unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::Banned(BAN_THRESHOLD));
+        assert_eq!(client.scanned_height(), 0);
+        drop(peer);
+        node.join().unwrap()?;
+        remove_store(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn bans_peers_sending_filters_not_matching_their_headers() -> Result<()> {
+        let mut blocks = vec![];
+        extend(&mut blocks, NETWORK.params().genesis, 1, 3, &key_script());
+
+        let path = store_path("filters");
+        let mut client = SpvClient::open(NETWORK.params(), &path)?;
+        client.watch_key(key().point())?;
+        let (mut peer, node) = serve(blocks, Some(Tampered::Filter(2)))?;
+        client.sync_headers(&mut peer)?;
+        let err = client.scan_blocks(&mut peer).unwrap_err();
+        assert_eq!(err.downcast::<P2pError>()?, P2pError::Banned(BAN_THRESHOLD));
+        assert_eq!(client.scanned_height(), 0);
+        drop(peer);
+        // no block was downloaded for the filters received before the tampered one
+        assert!(node.join().unwrap()?.is_empty());
+        remove_store(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn checks_filter_headers_across_peers() -> Result<()> {
+        let mut blocks = vec![];
+        extend(&mut blocks, NETWORK.params().genesis, 1, 1, &key_script());
+        let prev = blocks[0].header;
+        extend(&mut blocks, prev, 2, 2, &Script::op_return(b"other"));
+
+        // a peer that goes away, one hiding the payment and an honest one
+        let path = store_path("peers");
+        let mut client = SpvClient::open(NETWORK.params(), &path)?;
+        client.watch_key(key().point())?;
+        let (gone, gone_node) = serve(blocks.clone(), Some(Tampered::Hangup))?;
+        let (liar, liar_node) = serve(blocks.clone(), Some(Tampered::Hidden(1)))?;
+        let (honest, honest_node) = serve(blocks.clone(), None)?;
+        let mut peers = [gone, liar, honest];
+        client.sync(&mut peers)?;
+
+        assert_eq!(client.scanned_height(), 3);
+        assert_eq!(client.balance(1), 50 * COIN);
+        assert!(!peers[0].is_connected());
+        assert!(!peers[1].is_connected());
+        assert_eq!(peers[1].misbehaviour(), BAN_THRESHOLD);
+        assert!(peers[2].is_connected());
+        drop(peers);
+        assert!(gone_node.join().unwrap()?.is_empty());
+        assert!(liar_node.join().unwrap()?.is_empty());
+        // once to show the liar left out its output, once to scan it
+        assert_eq!(honest_node.join().unwrap()?, [1, 1]);
+        remove_store(&path);
+        Ok(())
+    }
+}
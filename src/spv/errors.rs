@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to syncing a light client
+pub enum SpvError {
+    #[error("header store of {0} bytes does not hold whole headers")]
+    CorruptStore(u64),
+    #[error("stored header at height {0} does not extend the chain")]
+    StoreMismatch(u32),
+    #[error("peer sent block {0}, which was not requested")]
+    UnexpectedBlock(String),
+    #[error("peer sent the filter of block {0}, which was not requested")]
+    UnexpectedFilter(String),
+    #[error("peers disagree on the filter header at height {0}")]
+    FilterHeadersConflict(u32),
+}
@@ -0,0 +1,226 @@
+use {
+    super::{client::WalletOutput, errors::SpvError},
+    crate::{
+        block::header::{BlockHash, BlockHeader, HEADER_SIZE},
+        chain::headers::{Accepted, HeaderChain},
+        filter::basic::FilterHeader,
+        network::params::Params,
+        transaction::tx::{OutPoint, TxOut},
+        utils::encoding::{compact_size, read_array, read_compact_size},
+    },
+    anyhow::{bail, Result},
+    std::{
+        collections::BTreeMap,
+        fs::{self, File, OpenOptions},
+        io::{ErrorKind, Read, Seek, SeekFrom, Write},
+        path::Path,
+    },
+};
+
+/// File holding the headers of the active chain above the genesis block, back to back
+pub struct HeaderStore {
+    file: File,
+    height: u32,
+}
+
+impl HeaderStore {
+    /// Open the store at `path`, creating an empty one if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if !len.is_multiple_of(HEADER_SIZE as u64) {
+            bail!(SpvError::CorruptStore(len));
+        }
+
+        Ok(Self {
+            file,
+            height: (len / HEADER_SIZE as u64) as u32,
+        })
+    }
+
+    /// Height of the last stored header
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Header chain of the network of `params` with the stored headers, validated again
+    pub fn load(&mut self, params: Params) -> Result<HeaderChain> {
+        let mut bytes = vec![];
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut chain = HeaderChain::new(params);
+        for header in bytes.chunks(HEADER_SIZE) {
+            // the headers were not too far in the future when they were stored
+            if chain.accept(BlockHeader::from_bytes(header)?, u32::MAX)? != Accepted::Extended {
+                bail!(SpvError::StoreMismatch(chain.height() + 1));
+            }
+        }
+
+        Ok(chain)
+    }
+
+    /// Keep the headers up to `height`, removing those of blocks disconnected by a reorg
+    pub fn truncate(&mut self, height: u32) -> Result<()> {
+        if height < self.height {
+            self.file.set_len(height as u64 * HEADER_SIZE as u64)?;
+            self.height = height;
+        }
+
+        Ok(())
+    }
+
+    /// Store `headers`, which must extend the stored chain
+    pub fn append(&mut self, headers: &[BlockHeader]) -> Result<()> {
+        let bytes = headers
+            .iter()
+            .flat_map(BlockHeader::serialise)
+            .collect::<Vec<_>>();
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        self.height += headers.len() as u32;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// Progress of scanning the active chain for watched scripts
+pub struct ScanState {
+    /// Hashes of the blocks from genesis up whose filter headers are known, with those headers
+    pub filter_headers: Vec<(BlockHash, FilterHeader)>,
+    /// Height of the last block scanned for watched scripts
+    pub scanned_height: u32,
+    pub outputs: BTreeMap<OutPoint, WalletOutput>,
+}
+
+impl ScanState {
+    /// State saved at `path`, or an empty one if nothing was saved yet
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let reader = &mut bytes.as_slice();
+
+        let mut state = Self::default();
+        for _ in 0..read_compact_size(reader)? {
+            state
+                .filter_headers
+                .push((read_array(reader)?, read_array(reader)?));
+        }
+        state.scanned_height = u32::from_le_bytes(read_array(reader)?);
+        for _ in 0..read_compact_size(reader)? {
+            let outpoint = OutPoint::parse(reader)?;
+            let txout = TxOut::parse(reader)?;
+            let height = u32::from_le_bytes(read_array(reader)?);
+            let spent_height = match read_array::<1>(reader)?[0] {
+                0 => None,
+                _ => Some(u32::from_le_bytes(read_array(reader)?)),
+            };
+            state.outputs.insert(
+                outpoint,
+                WalletOutput {
+                    outpoint,
+                    txout,
+                    height,
+                    spent_height,
+                },
+            );
+        }
+
+        Ok(state)
+    }
+
+    /// Write the state to `path`, replacing the file atomically
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut bytes = compact_size(self.filter_headers.len() as u64);
+        for (hash, filter_header) in &self.filter_headers {
+            bytes.extend(hash);
+            bytes.extend(filter_header);
+        }
+        bytes.extend(self.scanned_height.to_le_bytes());
+        bytes.extend(compact_size(self.outputs.len() as u64));
+        for output in self.outputs.values() {
+            bytes.extend(output.outpoint.serialise());
+            bytes.extend(output.txout.serialise());
+            bytes.extend(output.height.to_le_bytes());
+            match output.spent_height {
+                Some(height) => {
+                    bytes.push(1);
+                    bytes.extend(height.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &bytes)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{chain::errors::ChainError, network::Network},
+        std::fs,
+    };
+
+    #[test]
+    fn stores_and_truncates_headers() -> Result<()> {
+        let params = Network::Regtest.params();
+        let path = std::env::temp_dir().join(format!("store-{}.headers", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut headers = vec![];
+        let mut prev = params.genesis;
+        for _ in 0..3 {
+            let mut header = prev;
+            header.prev_blockhash = prev.hash();
+            header.time += 600;
+            while header.check_pow().is_err() {
+                header.nonce += 1;
+            }
+            headers.push(header);
+            prev = header;
+        }
+
+        let mut store = HeaderStore::open(&path)?;
+        store.append(&headers)?;
+        store.truncate(2)?;
+        let mut store = HeaderStore::open(&path)?;
+        assert_eq!(store.height(), 2);
+        assert_eq!(store.load(params.clone())?.tip().header, headers[1]);
+
+        // a store holding a partial header is refused
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(&[0])?;
+        let err = HeaderStore::open(&path).err().unwrap();
+        assert_eq!(err.downcast::<SpvError>()?, SpvError::CorruptStore(161));
+
+        // as is one whose headers do not chain
+        fs::write(&path, headers[1].serialise())?;
+        let err = HeaderStore::open(&path)?.load(params).unwrap_err();
+        assert!(matches!(
+            err.downcast::<ChainError>()?,
+            ChainError::UnknownParent(_)
+        ));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Transactions and signature hashes
+pub enum TransactionError {
+    #[error("unsupported segwit flag {0}")]
+    InvalidSegwitFlag(u8),
+    #[error("transaction has the segwit marker but no witness data")]
+    EmptyWitness,
+    #[error("input index {0} is out of range for a transaction with {1} inputs")]
+    InputOutOfRange(usize, usize),
+    #[error("invalid sighash type {0:#x}")]
+    InvalidSighashType(u32),
+    #[error("SIGHASH_SINGLE input {0} has no corresponding output")]
+    SingleWithoutOutput(usize),
+    #[error("expected {1} spent outputs, found {0}")]
+    SpentOutputsMismatch(usize, usize),
+    #[error("invalid outpoint `{0}`")]
+    InvalidOutPoint(String),
+}
@@ -0,0 +1,3 @@
+pub mod errors;
+pub mod sighash;
+pub mod tx;
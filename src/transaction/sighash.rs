@@ -0,0 +1,411 @@
+use {
+    super::{
+        errors::TransactionError,
+        tx::{Transaction, TxIn, TxOut},
+    },
+    crate::{
+        script::raw::Script,
+        utils::{
+            encoding::var_bytes,
+            hash::{hash256, sha256, tagged_hash},
+        },
+    },
+    anyhow::{bail, Result},
+};
+
+/// Taproot sighash type committing to everything, with an implied `SIGHASH_ALL` byte
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+
+/// Sign all inputs and outputs
+pub const SIGHASH_ALL: u8 = 0x01;
+
+/// Sign all inputs and no outputs
+pub const SIGHASH_NONE: u8 = 0x02;
+
+/// Sign all inputs and the output with the same index
+pub const SIGHASH_SINGLE: u8 = 0x03;
+
+/// Flag restricting the signed inputs to the one being signed
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// Hash signed in place of the real one when `SIGHASH_SINGLE` has no matching output
+const SIGHASH_SINGLE_BUG: [u8; 32] = {
+    let mut one = [0; 32];
+    one[0] = 1;
+    one
+};
+
+/// Split a sighash type into its base type and whether it has the `SIGHASH_ANYONECANPAY` flag
+fn split_sighash_type(sighash_type: u32) -> (u8, bool) {
+    (
+        (sighash_type & 0x1f) as u8,
+        sighash_type & SIGHASH_ANYONECANPAY as u32 != 0,
+    )
+}
+
+impl Transaction {
+    fn check_input_index(&self, index: usize) -> Result<()> {
+        if index >= self.inputs.len() {
+            bail!(TransactionError::InputOutOfRange(index, self.inputs.len()));
+        }
+
+        Ok(())
+    }
+
+    /// Signature hash of input `index` for pre-segwit scripts
+    ///
+    /// `script_code` is the script being executed, the output script or the P2SH redeem script
+    pub fn legacy_sighash(
+        &self,
+        index: usize,
+        script_code: &Script,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        self.check_input_index(index)?;
+        let (base, anyone_can_pay) = split_sighash_type(sighash_type);
+        if base == SIGHASH_SINGLE && index >= self.outputs.len() {
+            return Ok(SIGHASH_SINGLE_BUG);
+        }
+
+        let mut tx = Transaction::new(self.version, self.lock_time);
+        for (i, input) in self.inputs.iter().enumerate() {
+            if anyone_can_pay && i != index {
+                continue;
+            }
+            let mut input = TxIn {
+                witness: vec![],
+                script_sig: Script::new(),
+                ..input.clone()
+            };
+            if i == index {
+                input.script_sig = script_code.clone();
+            } else if base == SIGHASH_NONE || base == SIGHASH_SINGLE {
+                // other inputs may be updated freely
+                input.sequence = 0;
+            }
+            tx.inputs.push(input);
+        }
+
+        tx.outputs = match base {
+            SIGHASH_NONE => vec![],
+            SIGHASH_SINGLE => (0..=index)
+                .map(|i| match i == index {
+                    true => self.outputs[i].clone(),
+                    false => TxOut::new(u64::MAX, Script::new()),
+                })
+                .collect(),
+            _ => self.outputs.clone(),
+        };
+
+        let mut preimage = tx.serialise_without_witness();
+        preimage.extend(sighash_type.to_le_bytes());
+        Ok(hash256(&preimage))
+    }
+
+    /// Signature hash of input `index` for segwit v0 scripts as specified by BIP143
+    ///
+    /// `script_code` is the witness script, or the P2PKH script of the key for P2WPKH
+    pub fn segwit_v0_sighash(
+        &self,
+        index: usize,
+        script_code: &Script,
+        value: u64,
+        sighash_type: u32,
+    ) -> Result<[u8; 32]> {
+        self.check_input_index(index)?;
+        let (base, anyone_can_pay) = split_sighash_type(sighash_type);
+        let all_outputs = base != SIGHASH_SINGLE && base != SIGHASH_NONE;
+        let input = &self.inputs[index];
+
+        let hash_prevouts = match anyone_can_pay {
+            true => [0; 32],
+            false => hash256(&self.prevouts()),
+        };
+        let hash_sequence = match !anyone_can_pay && all_outputs {
+            true => hash256(&self.sequences()),
+            false => [0; 32],
+        };
+        let hash_outputs = if all_outputs {
+            hash256(&self.serialised_outputs())
+        } else if base == SIGHASH_SINGLE && index < self.outputs.len() {
+            hash256(&self.outputs[index].serialise())
+        } else {
+            [0; 32]
+        };
+
+        let preimage = [
+            self.version.to_le_bytes().as_slice(),
+            &hash_prevouts,
+            &hash_sequence,
+            &input.previous_output.serialise(),
+            &var_bytes(script_code.as_bytes()),
+            &value.to_le_bytes(),
+            &input.sequence.to_le_bytes(),
+            &hash_outputs,
+            &self.lock_time.to_le_bytes(),
+            &sighash_type.to_le_bytes(),
+        ]
+        .concat();
+        Ok(hash256(&preimage))
+    }
+
+    /// Signature hash of input `index` for taproot outputs as specified by BIP341
+    ///
+    /// `spent_outputs` are the outputs spent by every input, in order. `leaf_hash` is given for
+    /// script path spends and `annex` includes its `0x50` prefix
+    pub fn taproot_sighash(
+        &self,
+        index: usize,
+        spent_outputs: &[TxOut],
+        sighash_type: u8,
+        leaf_hash: Option<&[u8; 32]>,
+        annex: Option<&[u8]>,
+    ) -> Result<[u8; 32]> {
+        let leaf = leaf_hash.map(|leaf_hash| (leaf_hash, u32::MAX));
+        self.taproot_sighash_with(index, spent_outputs, sighash_type, leaf, annex)
+    }
+
+    /// Signature hash of input `index` for a tapscript leaf as specified by BIP342
+    ///
+    /// `codesep_pos` is the opcode position of the last executed `OP_CODESEPARATOR`, or
+    /// `u32::MAX` if none was executed
+    pub fn tapscript_sighash(
+        &self,
+        index: usize,
+        spent_outputs: &[TxOut],
+        sighash_type: u8,
+        leaf_hash: &[u8; 32],
+        codesep_pos: u32,
+        annex: Option<&[u8]>,
+    ) -> Result<[u8; 32]> {
+        let leaf = Some((leaf_hash, codesep_pos));
+        self.taproot_sighash_with(index, spent_outputs, sighash_type, leaf, annex)
+    }
+
+    fn taproot_sighash_with(
+        &self,
+        index: usize,
+        spent_outputs: &[TxOut],
+        sighash_type: u8,
+        leaf: Option<(&[u8; 32], u32)>,
+        annex: Option<&[u8]>,
+    ) -> Result<[u8; 32]> {
+        self.check_input_index(index)?;
+        if !matches!(sighash_type, 0x00..=0x03 | 0x81..=0x83) {
+            bail!(TransactionError::InvalidSighashType(sighash_type as u32));
+        }
+        if spent_outputs.len() != self.inputs.len() {
+            bail!(TransactionError::SpentOutputsMismatch(
+                spent_outputs.len(),
+                self.inputs.len()
+            ));
+        }
+        let (base, anyone_can_pay) = split_sighash_type(sighash_type as u32);
+        let input = &self.inputs[index];
+
+        let mut msg = vec![0x00, sighash_type];
+        msg.extend(self.version.to_le_bytes());
+        msg.extend(self.lock_time.to_le_bytes());
+        if !anyone_can_pay {
+            msg.extend(sha256(&self.prevouts()));
+            msg.extend(sha256(
+                &spent_outputs
+                    .iter()
+                    .flat_map(|output| output.value.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            ));
+            msg.extend(sha256(
+                &spent_outputs
+                    .iter()
+                    .flat_map(|output| var_bytes(output.script_pubkey.as_bytes()))
+                    .collect::<Vec<_>>(),
+            ));
+            msg.extend(sha256(&self.sequences()));
+        }
+        if base != SIGHASH_NONE && base != SIGHASH_SINGLE {
+            msg.extend(sha256(&self.serialised_outputs()));
+        }
+
+        let spend_type = u8::from(leaf.is_some()) * 2 + u8::from(annex.is_some());
+        msg.push(spend_type);
+        if anyone_can_pay {
+            msg.extend(input.previous_output.serialise());
+            msg.extend(spent_outputs[index].serialise());
+            msg.extend(input.sequence.to_le_bytes());
+        } else {
+            msg.extend((index as u32).to_le_bytes());
+        }
+        if let Some(annex) = annex {
+            msg.extend(sha256(&var_bytes(annex)));
+        }
+        if base == SIGHASH_SINGLE {
+            let Some(output) = self.outputs.get(index) else {
+                bail!(TransactionError::SingleWithoutOutput(index));
+            };
+            msg.extend(sha256(&output.serialise()));
+        }
+        if let Some((leaf_hash, codesep_pos)) = leaf {
+            // key version 0
+            msg.extend(leaf_hash);
+            msg.push(0x00);
+            msg.extend(codesep_pos.to_le_bytes());
+        }
+
+        Ok(tagged_hash("TapSighash", &msg))
+    }
+
+    fn prevouts(&self) -> Vec<u8> {
+        self.inputs
+            .iter()
+            .flat_map(|input| input.previous_output.serialise())
+            .collect()
+    }
+
+    fn sequences(&self) -> Vec<u8> {
+        self.inputs
+            .iter()
+            .flat_map(|input| input.sequence.to_le_bytes())
+            .collect()
+    }
+
+    fn serialised_outputs(&self) -> Vec<u8> {
+        self.outputs
+            .iter()
+            .flat_map(|output| output.serialise())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            script::{
+                raw::Instruction,
+                taproot::{tap_leaf_hash, TAPSCRIPT_LEAF_VERSION},
+            },
+            secp256k1::{point::Point, signature::Signature},
+            transaction::tx::OutPoint,
+        },
+        ibig::UBig,
+    };
+
+    fn script(hex: &str) -> Result<Script> {
+        Ok(Script::from_bytes(hex::decode(hex)?))
+    }
+
+    /// Outputs serialised with a count prefix
+    fn outputs(hex: &str) -> Result<Vec<TxOut>> {
+        let bytes = hex::decode(hex)?;
+        let mut reader = &bytes[1..];
+        (0..bytes[0]).map(|_| TxOut::parse(&mut reader)).collect()
+    }
+
+    #[test]
+    fn legacy_p2pkh_signature() -> Result<()> {
+        // spends the first output of the transaction funding the first BIP174 test vector
+        let tx: Transaction = "0200000001268171371edff285e937adeea4b37b78000c0566cbb3ad64641713ca42171bf6000000006a473044022070b2245123e6bf474d60c5b50c043d4c691a5d2435f09a34a7662a9dc251790a022001329ca9dacf280bdf30740ec0390422422c81cb45839457aeb76fc12edd95b3012102657d118d3357b8e0f4c2cd46db7b39f6d9c38d9a70abcb9b2de5dc8dbfe4ce31feffffff02d3dff505000000001976a914d0c59903c5bac2868760e90fd521a4665aa7652088ac00e1f5050000000017a9143545e6e33b832c47050f24d3eeb93c9c03948bc787b32e1300".parse()?;
+        let pushes = tx.inputs[0]
+            .script_sig
+            .instructions()
+            .collect::<Result<Vec<_>>>()?;
+        let [Instruction::Push(sig), Instruction::Push(sec)] = pushes.as_slice() else {
+            panic!("expected a P2PKH script sig");
+        };
+        let (sighash_type, der) = sig.split_last().unwrap();
+
+        let z = tx.legacy_sighash(
+            0,
+            &script("76a91485cff1097fd9e008bb34af709c62197b38978a4888ac")?,
+            *sighash_type as u32,
+        )?;
+        assert!(Point::from_sec(sec)?.verify(UBig::from_be_bytes(&z), Signature::from_der(der)?));
+        assert_eq!(Signature::from_der(der)?.der(), der);
+        Ok(())
+    }
+
+    #[test]
+    fn bip143_native_p2wpkh() -> Result<()> {
+        let tx: Transaction = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000".parse()?;
+        let script_code = Script::p2pkh(
+            &hex::decode("1d0f172a0ecb48aee1be1f2687d2963ae33f71a1")?
+                .try_into()
+                .unwrap(),
+        );
+
+        assert_eq!(
+            hex::encode(tx.segwit_v0_sighash(1, &script_code, 600_000_000, SIGHASH_ALL as u32)?),
+            "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bip143_p2sh_p2wsh() -> Result<()> {
+        let tx: Transaction = "010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000".parse()?;
+        let witness_script = script("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae")?;
+
+        assert_eq!(
+            hex::encode(tx.segwit_v0_sighash(
+                0,
+                &witness_script,
+                987_654_321,
+                SIGHASH_ALL as u32
+            )?),
+            "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn taproot_sighashes() -> Result<()> {
+        let tx: Transaction = "020000000189fc651483f9296b906455dd939813bf086b1bbe7c77635e157c8e14ae29062195010000004445b5c7044561320000000000160014331414dbdada7fb578f700f38fb69995fc9b5ab958020000000000001976a914268db0a8104cc6d8afd91233cc8b3d1ace8ac3ef88ac580200000000000017a914ec00dcb368d6a693e11986d265f659d2f59e8be2875802000000000000160014c715799a49a0bae3956df9c17cb4440a673ac0df6f010000".parse()?;
+        let spent = outputs("011bec34000000000022512028055142ea437db73382e991861446040b61dd2185c4891d7daf6893d79f7182")?;
+        let leaf = tap_leaf_hash(
+            &script("20cc4e1107aea1d170c5ff5b6817e1303010049724fb3caa7941792ea9d29b3e2bacab")?,
+            TAPSCRIPT_LEAF_VERSION,
+        );
+
+        assert_eq!(
+            hex::encode(tx.taproot_sighash(0, &spent, SIGHASH_ALL, Some(&leaf), None)?),
+            "d66de5274a60400c7b08c86ba6b7f198f40660079edf53aca89d2a9501317f2e"
+        );
+
+        let tx: Transaction = "0200000001df8123752e8f37d132c4e9f1ff7e4f9b986ade9211267e9ebd5fd22a5e718dec6d01000000ce4023b903cb7b23000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787580200000000000017a914afd0d512a2c5c2b40e25669e9cc460303c325b8b87580200000000000017a914a18b36ea7a094db2f4940fc09edf154e86de7bd787f6020000".parse()?;
+        let spent = outputs("01ea49260000000000225120ab5e9800806bf18cb246edcf5fe63441208fe955a4b5a35bbff65f5db622a010")?;
+        let annex = hex::decode("507b979802e62d397acb29f56743a791894b99372872fc5af06a4f6e8d242d0615cda53062bb20e6ec79756fe39183f0c128adfe85559a8fa042b042c018aa8010143799e44f0893c40e1e")?;
+
+        assert_eq!(
+            hex::encode(tx.taproot_sighash(
+                0,
+                &spent,
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                None,
+                Some(&annex)
+            )?),
+            "3b003000add359a364a156e73e02846782a59d0d95ca8c4638aaad99f2ef915c"
+        );
+        assert!(tx.taproot_sighash(0, &spent, 0x04, None, None).is_err());
+        assert!(tx
+            .taproot_sighash(0, &[], SIGHASH_DEFAULT, None, None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sighash_single_bug() -> Result<()> {
+        let mut tx = Transaction::new(1, 0);
+        tx.inputs = vec![TxIn::new(OutPoint::null()), TxIn::new(OutPoint::null())];
+        tx.outputs = vec![TxOut::new(0, Script::new())];
+
+        assert_eq!(
+            tx.legacy_sighash(1, &Script::new(), SIGHASH_SINGLE as u32)?,
+            SIGHASH_SINGLE_BUG
+        );
+        assert!(tx
+            .legacy_sighash(2, &Script::new(), SIGHASH_ALL as u32)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+use {
+    super::errors::TransactionError,
+    crate::{
+        script::raw::Script,
+        utils::{
+            encoding::{
+                compact_size, read_array, read_compact_size, read_var_bytes, reversed_hex,
+                var_bytes,
+            },
+            errors::EncodingError,
+            hash::hash256,
+        },
+    },
+    anyhow::{bail, Result},
+    std::{
+        fmt::{self, Display, Formatter},
+        io::Read,
+        str::FromStr,
+    },
+};
+
+/// Sequence of an input that opts out of relative timelocks, RBF and lock time
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+
+/// Sequence of an input that enables the lock time without signalling RBF
+pub const SEQUENCE_ENABLE_LOCKTIME_NO_RBF: u32 = 0xfffffffe;
+
+/// Highest sequence of an input signalling replaceability as specified by BIP125
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xfffffffd;
+
+/// Flag of a sequence that has no relative lock time, as specified by BIP68
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// Flag of a sequence whose relative lock time is in units of 512 seconds rather than blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+
+/// Bits of a sequence holding its relative lock time
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
+/// Lock times below are block heights, lock times at or above are unix times
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Weight units per byte of non-witness data
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+/// Transaction id, the double SHA256 of the transaction without witness data
+///
+/// Stored in the byte order it is hashed and serialised in, and displayed reversed
+pub type Txid = [u8; 32];
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// Reference to an output of a previous transaction
+pub struct OutPoint {
+    pub txid: Txid,
+    pub vout: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Transaction input spending `previous_output`
+pub struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Script,
+    pub sequence: u32,
+    /// Witness stack, bottom element first
+    pub witness: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Transaction output locking `value` satoshis with `script_pubkey`
+pub struct TxOut {
+    pub value: u64,
+    pub script_pubkey: Script,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Bitcoin transaction, serialised as specified by BIP144 when it has witness data
+pub struct Transaction {
+    pub version: i32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+/// Read a script prefixed by its length
+pub(crate) fn read_script(reader: &mut impl Read) -> Result<Script> {
+    Ok(Script::from_bytes(read_var_bytes(reader)?))
+}
+
+/// Read a witness stack, a count followed by length prefixed elements
+pub(crate) fn read_witness(reader: &mut impl Read) -> Result<Vec<Vec<u8>>> {
+    let count = read_compact_size(reader)?;
+    (0..count).map(|_| read_var_bytes(reader)).collect()
+}
+
+/// Serialise a witness stack
+pub(crate) fn serialise_witness(witness: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = compact_size(witness.len() as u64);
+    for item in witness {
+        bytes.extend(var_bytes(item));
+    }
+    bytes
+}
+
+impl OutPoint {
+    /// Create a reference to output `vout` of transaction `txid`
+    pub fn new(txid: Txid, vout: u32) -> Self {
+        Self { txid, vout }
+    }
+
+    /// Outpoint spent by coinbase inputs
+    pub fn null() -> Self {
+        Self::new([0; 32], u32::MAX)
+    }
+
+    /// If the outpoint is the one spent by coinbase inputs
+    pub fn is_null(&self) -> bool {
+        *self == Self::null()
+    }
+
+    /// Parse the 36 byte serialisation of an outpoint
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self::new(
+            read_array(reader)?,
+            u32::from_le_bytes(read_array(reader)?),
+        ))
+    }
+
+    /// Serialise the outpoint to 36 bytes
+    pub fn serialise(&self) -> Vec<u8> {
+        [self.txid.as_slice(), &self.vout.to_le_bytes()].concat()
+    }
+}
+
+impl Display for OutPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", reversed_hex(&self.txid), self.vout)
+    }
+}
+
+impl FromStr for OutPoint {
+    type Err = anyhow::Error;
+
+    /// Parse an outpoint written as `txid:vout`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || TransactionError::InvalidOutPoint(s.to_string());
+        let (txid, vout) = s.split_once(':').ok_or_else(invalid)?;
+        let mut txid: Txid = hex::decode(txid)
+            .ok()
+            .and_then(|txid| txid.try_into().ok())
+            .ok_or_else(invalid)?;
+        txid.reverse();
+
+        Ok(Self::new(txid, vout.parse().map_err(|_| invalid())?))
+    }
+}
+
+impl TxIn {
+    /// Create an input spending `previous_output` with an empty script and witness
+    pub fn new(previous_output: OutPoint) -> Self {
+        Self {
+            previous_output,
+            script_sig: Script::new(),
+            sequence: SEQUENCE_FINAL,
+            witness: vec![],
+        }
+    }
+
+    /// Parse an input without its witness, which is serialised separately
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self {
+            previous_output: OutPoint::parse(reader)?,
+            script_sig: read_script(reader)?,
+            sequence: u32::from_le_bytes(read_array(reader)?),
+            witness: vec![],
+        })
+    }
+
+    /// Serialise the input without its witness
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.previous_output.serialise().as_slice(),
+            &var_bytes(self.script_sig.as_bytes()),
+            &self.sequence.to_le_bytes(),
+        ]
+        .concat()
+    }
+}
+
+impl TxOut {
+    /// Create an output paying `value` satoshis to `script_pubkey`
+    pub fn new(value: u64, script_pubkey: Script) -> Self {
+        Self {
+            value,
+            script_pubkey,
+        }
+    }
+
+    /// Parse a serialised output
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Ok(Self::new(
+            u64::from_le_bytes(read_array(reader)?),
+            read_script(reader)?,
+        ))
+    }
+
+    /// Serialise the output
+    pub fn serialise(&self) -> Vec<u8> {
+        [
+            self.value.to_le_bytes().as_slice(),
+            &var_bytes(self.script_pubkey.as_bytes()),
+        ]
+        .concat()
+    }
+}
+
+impl Transaction {
+    /// Create a transaction without inputs or outputs
+    pub fn new(version: i32, lock_time: u32) -> Self {
+        Self {
+            version,
+            inputs: vec![],
+            outputs: vec![],
+            lock_time,
+        }
+    }
+
+    /// Parse a transaction with or without witness data
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        Self::parse_with(reader, true)
+    }
+
+    /// Parse a transaction in the legacy format, where an empty input list is not a segwit marker
+    pub fn parse_without_witness(reader: &mut impl Read) -> Result<Self> {
+        Self::parse_with(reader, false)
+    }
+
+    fn parse_with(reader: &mut impl Read, allow_witness: bool) -> Result<Self> {
+        let version = i32::from_le_bytes(read_array(reader)?);
+        let mut input_count = read_compact_size(reader)?;
+
+        // an empty input list is the segwit marker, followed by the flag
+        let segwit = allow_witness && input_count == 0;
+        if segwit {
+            let [flag] = read_array(reader)?;
+            if flag != 1 {
+                bail!(TransactionError::InvalidSegwitFlag(flag));
+            }
+            input_count = read_compact_size(reader)?;
+        }
+
+        let mut inputs = (0..input_count)
+            .map(|_| TxIn::parse(reader))
+            .collect::<Result<Vec<_>>>()?;
+        let output_count = read_compact_size(reader)?;
+        let outputs = (0..output_count)
+            .map(|_| TxOut::parse(reader))
+            .collect::<Result<Vec<_>>>()?;
+
+        if segwit {
+            for input in inputs.iter_mut() {
+                input.witness = read_witness(reader)?;
+            }
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                bail!(TransactionError::EmptyWitness);
+            }
+        }
+
+        Ok(Self {
+            version,
+            inputs,
+            outputs,
+            lock_time: u32::from_le_bytes(read_array(reader)?),
+        })
+    }
+
+    /// Parse a transaction that must span all of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+        let tx = Self::parse(&mut reader)?;
+        if !reader.is_empty() {
+            bail!(EncodingError::TrailingBytes(reader.len()));
+        }
+
+        Ok(tx)
+    }
+
+    /// Serialise the transaction, in the BIP144 format if it has witness data
+    pub fn serialise(&self) -> Vec<u8> {
+        if !self.has_witness() {
+            return self.serialise_without_witness();
+        }
+
+        let mut bytes = self.version.to_le_bytes().to_vec();
+        bytes.extend([0, 1]);
+        bytes.extend(self.serialise_inputs_and_outputs());
+        for input in &self.inputs {
+            bytes.extend(serialise_witness(&input.witness));
+        }
+        bytes.extend(self.lock_time.to_le_bytes());
+
+        bytes
+    }
+
+    /// Serialise the transaction in the legacy format, which its txid commits to
+    pub fn serialise_without_witness(&self) -> Vec<u8> {
+        [
+            self.version.to_le_bytes().as_slice(),
+            &self.serialise_inputs_and_outputs(),
+            &self.lock_time.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    fn serialise_inputs_and_outputs(&self) -> Vec<u8> {
+        let mut bytes = compact_size(self.inputs.len() as u64);
+        for input in &self.inputs {
+            bytes.extend(input.serialise());
+        }
+        bytes.extend(compact_size(self.outputs.len() as u64));
+        for output in &self.outputs {
+            bytes.extend(output.serialise());
+        }
+
+        bytes
+    }
+
+    /// Transaction id, committing to everything but the witness data
+    pub fn txid(&self) -> Txid {
+        hash256(&self.serialise_without_witness())
+    }
+
+    /// Witness transaction id, equal to the txid when there is no witness data
+    pub fn wtxid(&self) -> Txid {
+        hash256(&self.serialise())
+    }
+
+    /// If any input has witness data
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// If the transaction is a coinbase, the first transaction of a block
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_output.is_null()
+    }
+
+    /// Serialised size in bytes, including witness data
+    pub fn size(&self) -> usize {
+        self.serialise().len()
+    }
+
+    /// Weight as defined by BIP141, witness bytes counting a quarter of other bytes
+    pub fn weight(&self) -> usize {
+        let base = self.serialise_without_witness().len();
+        base * (WITNESS_SCALE_FACTOR - 1) + self.size()
+    }
+
+    /// Virtual size, the weight divided by 4 and rounded up
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    /// Total value of the outputs
+    pub fn output_value(&self) -> u64 {
+        self.outputs.iter().map(|output| output.value).sum()
+    }
+}
+
+impl Display for Transaction {
+    /// Format the serialised transaction as hex
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.serialise()))
+    }
+}
+
+impl FromStr for Transaction {
+    type Err = anyhow::Error;
+
+    /// Parse a hex serialised transaction
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_bytes(&hex::decode(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Segwit transaction funding the first BIP174 test vector
+    const SEGWIT_TX: &str = "0100000000010289a3c71eab4d20e0371bbba4cc698fa295c9463afa2e397f8533ccb62f9567e50100000017160014be18d152a9b012039daf3da7de4f53349eecb985ffffffff86f8aa43a71dff1448893a530a7237ef6b4608bbb2dd2d0171e63aec6a4890b40100000017160014fe3e9ef1a745e974d902c4355943abcb34bd5353ffffffff0200c2eb0b000000001976a91485cff1097fd9e008bb34af709c62197b38978a4888ac72fef84e2c00000017a914339725ba21efd62ac753a9bcd067d6c7a6a39d05870247304402202712be22e0270f394f568311dc7ca9a68970b8025fdd3b240229f07f8a5f3a240220018b38d7dcd314e734c9276bd6fb40f673325bc4baa144c800d2f2f02db2765c012103d2e15674941bad4a996372cb87e1856d3652606d98562fe39c5e9e7e413f210502483045022100d12b852d85dcd961d2f5f4ab660654df6eedcc794c0c33ce5cc309ffb5fce58d022067338a8e0e1725c197fb1a88af59f51e44e4255b20167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4ea169393380734464f84f2ab300000000";
+
+    #[test]
+    fn segwit_round_trip() -> Result<()> {
+        let tx: Transaction = SEGWIT_TX.parse()?;
+
+        assert_eq!(tx.to_string(), SEGWIT_TX);
+        assert_eq!(tx.inputs.len(), 2);
+        assert_eq!(tx.inputs[1].witness.len(), 2);
+        assert_eq!(tx.outputs[1].value, 190_303_501_938);
+        assert_eq!(
+            reversed_hex(&tx.txid()),
+            "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126"
+        );
+        assert_ne!(tx.txid(), tx.wtxid());
+        assert!(tx.vsize() < tx.size());
+        assert_eq!(
+            tx.weight(),
+            tx.serialise_without_witness().len() * 3 + tx.size()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_round_trip() -> Result<()> {
+        let hex = "0200000001ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff0000000000ffffffff010000000000000000036a010000000000";
+        let tx: Transaction = hex.parse()?;
+
+        assert_eq!(tx.to_string(), hex);
+        assert_eq!(
+            reversed_hex(&tx.txid()),
+            "75c5c9665a570569ad77dd1279e6fd4628a093c4dcbf8d41532614044c14c115"
+        );
+        assert_eq!(tx.txid(), tx.wtxid());
+        assert_eq!(tx.weight(), 4 * hex.len() / 2);
+        assert!(!tx.is_coinbase());
+        Ok(())
+    }
+
+    #[test]
+    fn outpoint_strings() -> Result<()> {
+        let s = "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126:1";
+        let outpoint: OutPoint = s.parse()?;
+
+        assert_eq!(outpoint.to_string(), s);
+        assert_eq!(outpoint.txid[0], 0x26);
+        assert!(OutPoint::null().is_null());
+        assert!("f61b:1".parse::<OutPoint>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_transactions() {
+        let truncated = &SEGWIT_TX[..SEGWIT_TX.len() - 2];
+        // segwit marker with a flag other than 1
+        let bad_flag = SEGWIT_TX.replacen("00010289", "00020289", 1);
+        let trailing = format!("{SEGWIT_TX}00");
+        for hex in [
+            truncated,
+            &bad_flag,
+            &trailing,
+            "0100000000010000000000000000",
+        ] {
+            assert!(hex.parse::<Transaction>().is_err(), "{hex}");
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use ibig::{ops::DivRem, ubig, UBig};
+use {
+    super::{errors::Base58Error, hash::hash256},
+    anyhow::{bail, Result},
+    ibig::{ops::DivRem, ubig, UBig},
+};
 
 const ALPHABET: &[u8] = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz".as_bytes();
 
@@ -27,6 +31,48 @@ pub fn encode(data: &[u8]) -> String {
     String::from_utf8(enc).unwrap()
 }
 
+/// Decode a base58 string to a byte array
+pub fn decode(data: &str) -> Result<Vec<u8>> {
+    let mut num = ubig!(0);
+    // every leading '1' stands for a leading zero byte
+    let count = data.chars().take_while(|c| *c == '1').count();
+
+    for c in data.chars() {
+        let Some(digit) = ALPHABET.iter().position(|a| *a as char == c) else {
+            bail!(Base58Error::InvalidCharacter(c));
+        };
+        num = num * 58_usize + digit;
+    }
+
+    let mut dec = vec![0; count];
+    if num > ubig!(0) {
+        dec.extend(num.to_be_bytes());
+    }
+
+    Ok(dec)
+}
+
+/// Encode a byte array to a base58 string with a 4 byte double SHA256 checksum appended
+pub fn encode_check(data: &[u8]) -> String {
+    let checksum = hash256(data);
+    encode(&[data, &checksum[..4]].concat())
+}
+
+/// Decode a base58check string, verifying and stripping its checksum
+pub fn decode_check(data: &str) -> Result<Vec<u8>> {
+    let mut dec = decode(data)?;
+    if dec.len() < 4 {
+        bail!(Base58Error::TooShort(dec.len()));
+    }
+
+    let checksum = dec.split_off(dec.len() - 4);
+    if hash256(&dec)[..4] != checksum[..] {
+        bail!(Base58Error::InvalidChecksum);
+    }
+
+    Ok(dec)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,4 +94,33 @@ mod tests {
         let expected = "11EQJsjkd6JaGwxrjEhfeqPenqHwrBmPQZjJGNSCHBkcF7";
         assert_eq!(encode(&data), expected);
     }
+
+    #[test]
+    fn test_base58_decode() {
+        let data =
+            hex::decode("0000c7207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab6")
+                .unwrap();
+        assert_eq!(
+            decode("11EQJsjkd6JaGwxrjEhfeqPenqHwrBmPQZjJGNSCHBkcF7").unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn test_base58_decode_invalid_char() {
+        assert!(decode("0OIl").is_err());
+    }
+
+    #[test]
+    fn test_base58check_round_trip() {
+        let data = hex::decode("00f54a5851e9372b87810a8e60cdd2e7cfd80b6e31").unwrap();
+        let enc = encode_check(&data);
+        assert_eq!(enc, "1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAs");
+        assert_eq!(decode_check(&enc).unwrap(), data);
+    }
+
+    #[test]
+    fn test_base58check_bad_checksum() {
+        assert!(decode_check("1PMycacnJaSqwwJqjawXBErnLsZ7RkXUAt").is_err());
+    }
 }
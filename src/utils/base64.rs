@@ -0,0 +1,92 @@
+use {
+    super::errors::Base64Error,
+    anyhow::{bail, Result},
+};
+
+const ALPHABET: &[u8] =
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/".as_bytes();
+
+/// Encode a byte array to a padded base64 string
+pub fn encode(data: &[u8]) -> String {
+    let mut enc = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+        // a chunk of n bytes fills n + 1 characters, the rest is padding
+        for i in 0..4 {
+            if i <= chunk.len() {
+                enc.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                enc.push('=');
+            }
+        }
+    }
+
+    enc
+}
+
+/// Decode a padded base64 string to a byte array
+pub fn decode(data: &str) -> Result<Vec<u8>> {
+    if !data.len().is_multiple_of(4) {
+        bail!(Base64Error::Length(data.len()));
+    }
+
+    let mut dec = Vec::with_capacity(data.len() / 4 * 3);
+    let chunks = data.as_bytes().chunks(4).collect::<Vec<_>>();
+    for (n, chunk) in chunks.iter().enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && n != chunks.len() - 1) {
+            bail!(Base64Error::Padding);
+        }
+
+        let mut group = 0_u32;
+        for c in &chunk[..4 - padding] {
+            let Some(digit) = ALPHABET.iter().position(|a| a == c) else {
+                bail!(Base64Error::Character(*c as char));
+            };
+            group = group << 6 | digit as u32;
+        }
+        group <<= 6 * padding;
+
+        // bits left over by the padding must be zero for the encoding to be canonical
+        let bytes = group.to_be_bytes();
+        if bytes[4 - padding..].iter().any(|b| *b != 0) {
+            bail!(Base64Error::Padding);
+        }
+        dec.extend(&bytes[1..4 - padding]);
+    }
+
+    Ok(dec)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rfc4648_vectors() -> Result<()> {
+        for (data, enc) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(data.as_bytes()), enc);
+            assert_eq!(decode(enc)?, data.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_strings() {
+        for s in ["Zg=", "Zg===", "Z===", "Zh==", "Zg==Zm8=", "Zm9*"] {
+            assert!(decode(s).is_err(), "{s}");
+        }
+    }
+}
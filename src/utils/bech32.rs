@@ -0,0 +1,240 @@
+use {
+    super::errors::Bech32Error,
+    anyhow::{bail, Result},
+};
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Generator coefficients of the BCH checksum
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Checksum variant, Bech32 (BIP173) for witness version 0 and Bech32m (BIP350) otherwise
+pub enum Variant {
+    Bech32,
+    Bech32m,
+}
+
+impl Variant {
+    /// Value the checksum polymod must equal
+    fn constant(&self) -> u32 {
+        match self {
+            Self::Bech32 => 1,
+            Self::Bech32m => 0x2bc830a3,
+        }
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk = 1_u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ffffff) << 5 ^ *value as u32;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+/// Expand the human readable part for checksum computation
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let bytes = hrp.as_bytes();
+    bytes
+        .iter()
+        .map(|b| b >> 5)
+        .chain([0])
+        .chain(bytes.iter().map(|b| b & 31))
+        .collect()
+}
+
+/// Encode 5 bit groups `data` with the human readable part `hrp`
+pub fn encode(hrp: &str, data: &[u8], variant: Variant) -> String {
+    let values = [hrp_expand(hrp).as_slice(), data, &[0; 6]].concat();
+    let checksum = polymod(&values) ^ variant.constant();
+
+    let mut enc = format!("{hrp}1");
+    for value in data
+        .iter()
+        .copied()
+        .chain((0..6).map(|i| (checksum >> (5 * (5 - i)) & 31) as u8))
+    {
+        enc.push(CHARSET[value as usize] as char);
+    }
+    enc
+}
+
+/// Decode a bech32 string into its human readable part, 5 bit groups and checksum variant
+pub fn decode(s: &str) -> Result<(String, Vec<u8>, Variant)> {
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        bail!(Bech32Error::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+
+    let Some(pos) = s.rfind('1') else {
+        bail!(Bech32Error::InvalidLength);
+    };
+    if pos == 0 || pos + 7 > s.len() || s.len() > 90 {
+        bail!(Bech32Error::InvalidLength);
+    }
+
+    let (hrp, rest) = (&s[..pos], &s[pos + 1..]);
+    if let Some(c) = hrp.chars().find(|c| !(33..=126).contains(&(*c as u32))) {
+        bail!(Bech32Error::InvalidCharacter(c));
+    }
+
+    let mut data = Vec::with_capacity(rest.len());
+    for c in rest.chars() {
+        let Some(value) = CHARSET.iter().position(|a| *a as char == c) else {
+            bail!(Bech32Error::InvalidCharacter(c));
+        };
+        data.push(value as u8);
+    }
+
+    let variant = match polymod(&[hrp_expand(hrp), data.clone()].concat()) {
+        1 => Variant::Bech32,
+        0x2bc830a3 => Variant::Bech32m,
+        _ => bail!(Bech32Error::InvalidChecksum),
+    };
+    data.truncate(data.len() - 6);
+
+    Ok((hrp.to_string(), data, variant))
+}
+
+/// Regroup `data` from `from` bit groups to `to` bit groups
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>> {
+    let mut acc = 0_u32;
+    let mut bits = 0;
+    let max = (1 << to) - 1;
+    let mut out = vec![];
+
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            bail!(Bech32Error::InvalidPadding);
+        }
+        acc = acc << from | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push((acc >> bits & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            out.push((acc << (to - bits) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        bail!(Bech32Error::InvalidPadding);
+    }
+
+    Ok(out)
+}
+
+/// Encode a segwit address of witness `version` and `program`
+pub fn encode_segwit(hrp: &str, version: u8, program: &[u8]) -> Result<String> {
+    check_program(version, program)?;
+    let variant = if version == 0 {
+        Variant::Bech32
+    } else {
+        Variant::Bech32m
+    };
+    let data = [&[version], convert_bits(program, 8, 5, true)?.as_slice()].concat();
+
+    Ok(encode(hrp, &data, variant))
+}
+
+/// Decode a segwit address into its human readable part, witness version and program
+pub fn decode_segwit(s: &str) -> Result<(String, u8, Vec<u8>)> {
+    let (hrp, data, variant) = decode(s)?;
+    let Some((version, program)) = data.split_first() else {
+        bail!(Bech32Error::InvalidLength);
+    };
+
+    let program = convert_bits(program, 5, 8, false)?;
+    check_program(*version, &program)?;
+    if (*version == 0) != (variant == Variant::Bech32) {
+        bail!(Bech32Error::InvalidVariant(*version));
+    }
+
+    Ok((hrp, *version, program))
+}
+
+/// Check the witness program rules of BIP141
+fn check_program(version: u8, program: &[u8]) -> Result<()> {
+    if version > 16 {
+        bail!(Bech32Error::InvalidWitnessVersion(version));
+    }
+    if !(2..=40).contains(&program.len()) || (version == 0 && ![20, 32].contains(&program.len())) {
+        bail!(Bech32Error::InvalidProgramLength(program.len()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_checksums() -> Result<()> {
+        for (s, variant) in [
+            ("A12UEL5L", Variant::Bech32),
+            (
+                "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+                Variant::Bech32,
+            ),
+            ("A1LQFN3A", Variant::Bech32m),
+            (
+                "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+                Variant::Bech32m,
+            ),
+        ] {
+            assert_eq!(decode(s)?.2, variant, "{s}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_v0_round_trip() -> Result<()> {
+        let program = hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6")?;
+        let addr = encode_segwit("bc", 0, &program)?;
+
+        assert_eq!(addr, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(decode_segwit(&addr)?, ("bc".to_string(), 0, program));
+        Ok(())
+    }
+
+    #[test]
+    fn segwit_v1_round_trip() -> Result<()> {
+        let program =
+            hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
+        let addr = encode_segwit("bc", 1, &program)?;
+
+        assert_eq!(
+            addr,
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+        );
+        assert_eq!(decode_segwit(&addr)?.2, program);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_segwit_addresses() -> Result<()> {
+        for s in [
+            // bech32 checksum for witness version 1
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            // bech32m checksum for witness version 0
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+            // mixed case
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3T4",
+            // invalid program length
+            "bc1rw5uspcuh",
+        ] {
+            assert!(decode_segwit(s).is_err(), "{s}");
+        }
+        Ok(())
+    }
+}
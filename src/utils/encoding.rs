@@ -0,0 +1,112 @@
+use {
+    super::errors::EncodingError,
+    anyhow::{bail, Result},
+    ibig::UBig,
+    std::io::Read,
+};
+
+/// Largest vector a length prefix may announce, matching Bitcoin Core's `MAX_SIZE`
+pub const MAX_VEC_SIZE: usize = 0x0200_0000;
+
+/// Big endian bytes of `num` left padded with zeros to 32 bytes
+///
+/// `num` must fit in 256 bits
+pub fn to_32_bytes(num: &UBig) -> [u8; 32] {
+    let bytes = num.to_be_bytes();
+    let mut padded = [0; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    padded
+}
+
+/// Bitcoin CompactSize encoding of `n`, used for lengths and counts
+pub fn compact_size(n: u64) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => [&[0xfd], (n as u16).to_le_bytes().as_slice()].concat(),
+        0x10000..=0xffff_ffff => [&[0xfe], (n as u32).to_le_bytes().as_slice()].concat(),
+        _ => [&[0xff], n.to_le_bytes().as_slice()].concat(),
+    }
+}
+
+/// Read a CompactSize encoded number, rejecting encodings that are not minimal
+pub fn read_compact_size(reader: &mut impl Read) -> Result<u64> {
+    let [prefix] = read_array(reader)?;
+    let (n, min) = match prefix {
+        0xfd => (u16::from_le_bytes(read_array(reader)?) as u64, 0xfd),
+        0xfe => (u32::from_le_bytes(read_array(reader)?) as u64, 0x10000),
+        0xff => (u64::from_le_bytes(read_array(reader)?), 0x1_0000_0000),
+        n => return Ok(n as u64),
+    };
+    if n < min {
+        bail!(EncodingError::NonCanonicalCompactSize(n));
+    }
+
+    Ok(n)
+}
+
+/// Read exactly `N` bytes
+pub fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read `len` bytes, refusing lengths that could not come from valid data
+pub fn read_bytes(reader: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    if len > MAX_VEC_SIZE as u64 {
+        bail!(EncodingError::OversizedAllocation(len, MAX_VEC_SIZE));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read bytes prefixed by their CompactSize length
+pub fn read_var_bytes(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_compact_size(reader)?;
+    read_bytes(reader, len)
+}
+
+/// Bytes prefixed by their CompactSize length
+pub fn var_bytes(bytes: &[u8]) -> Vec<u8> {
+    [compact_size(bytes.len() as u64).as_slice(), bytes].concat()
+}
+
+/// Hex of `bytes` in reverse order, the way hashes such as txids are displayed
+pub fn reversed_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes.iter().rev().copied().collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, ibig::ubig};
+
+    #[test]
+    fn pads_small_numbers() {
+        let bytes = to_32_bytes(&ubig!(0x0102));
+        assert_eq!(bytes[..30], [0; 30]);
+        assert_eq!(bytes[30..], [1, 2]);
+    }
+
+    #[test]
+    fn compact_size_boundaries() {
+        assert_eq!(compact_size(0xfc), [0xfc]);
+        assert_eq!(compact_size(0xfd), [0xfd, 0xfd, 0x00]);
+        assert_eq!(compact_size(0x10000), [0xfe, 0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(compact_size(1 << 32)[0], 0xff);
+    }
+
+    #[test]
+    fn read_compact_size_round_trip() -> Result<()> {
+        for n in [0, 0xfc, 0xfd, 0xffff, 0x10000, 0xffff_ffff, 1 << 32] {
+            assert_eq!(read_compact_size(&mut compact_size(n).as_slice())?, n);
+        }
+
+        assert!(read_compact_size(&mut [0xfd, 0xfc, 0x00].as_slice()).is_err());
+        assert!(read_compact_size(&mut [0xfe, 0xff, 0xff, 0x00, 0x00].as_slice()).is_err());
+        assert!(read_compact_size(&mut [0xfd, 0x00].as_slice()).is_err());
+        assert!(read_var_bytes(&mut [0xfe, 0xff, 0xff, 0xff, 0xff].as_slice()).is_err());
+        Ok(())
+    }
+}
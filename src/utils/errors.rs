@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Base58 encoding
+pub enum Base58Error {
+    #[error("invalid base58 character `{0}`")]
+    InvalidCharacter(char),
+    #[error("base58check payload is too short ({0} bytes)")]
+    TooShort(usize),
+    #[error("base58check checksum mismatch")]
+    InvalidChecksum,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Bech32 and Bech32m encoding
+pub enum Bech32Error {
+    #[error("bech32 string mixes upper and lower case")]
+    MixedCase,
+    #[error("invalid bech32 character `{0}`")]
+    InvalidCharacter(char),
+    #[error("bech32 string has no separator or an invalid length")]
+    InvalidLength,
+    #[error("bech32 checksum mismatch")]
+    InvalidChecksum,
+    #[error("invalid padding when converting between bit groups")]
+    InvalidPadding,
+    #[error("invalid witness version {0}")]
+    InvalidWitnessVersion(u8),
+    #[error("invalid witness program length {0}")]
+    InvalidProgramLength(usize),
+    #[error("witness version {0} encoded with the wrong checksum variant")]
+    InvalidVariant(u8),
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to Base64 encoding
+pub enum Base64Error {
+    #[error("invalid base64 character `{0}`")]
+    Character(char),
+    #[error("base64 string length {0} is not a multiple of 4")]
+    Length(usize),
+    #[error("invalid base64 padding")]
+    Padding,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to decoding Bitcoin's binary serialisation
+pub enum EncodingError {
+    #[error("CompactSize {0} is not minimally encoded")]
+    NonCanonicalCompactSize(u64),
+    #[error("length {0} exceeds the maximum allocation of {1} bytes")]
+    OversizedAllocation(u64, usize),
+    #[error("{0} unexpected bytes after the end of the data")]
+    TrailingBytes(usize),
+}
@@ -0,0 +1,167 @@
+use {
+    hmac::{Hmac, Mac},
+    ripemd::Ripemd160,
+    sha1::Sha1,
+    sha2::{Digest, Sha256, Sha512},
+};
+
+/// SHA256 of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Double SHA256 of `data`, used for transaction ids, block hashes and checksums
+pub fn hash256(data: &[u8]) -> [u8; 32] {
+    sha256(&sha256(data))
+}
+
+/// SHA1 of `data`, only used by `OP_SHA1`
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+/// RIPEMD160 of `data`
+pub fn ripemd160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(data).into()
+}
+
+/// RIPEMD160 of the SHA256 of `data`, used for public key and script hashes
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    ripemd160(&sha256(data))
+}
+
+/// BIP340 tagged hash of `data`, SHA256(SHA256(tag) || SHA256(tag) || data)
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = sha256(tag.as_bytes());
+    Sha256::new()
+        .chain_update(tag)
+        .chain_update(tag)
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+/// HMAC-SHA512 of `data` keyed with `key`
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// SipHash-2-4 of `data` keyed with `k0` and `k1`, a fast keyed hash used by compact block filters
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f6d6570736575,
+        k1 ^ 0x646f72616e646f6d,
+        k0 ^ 0x6c7967656e657261,
+        k1 ^ 0x7465646279746573,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+
+    let chunks = data.chunks_exact(8);
+    // the last word holds the remaining bytes and the length of the data
+    let mut last = [0; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    for chunk in chunks {
+        compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+    }
+    compress(u64::from_le_bytes(last));
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// 32 bit MurmurHash3 of `data`, as used by BIP37 bloom filters
+pub fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    let mut h = seed;
+    for chunk in chunks {
+        h ^= mix(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0, |k, &byte| (k << 8) | byte as u32);
+        h ^= mix(k);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hash256_empty() {
+        assert_eq!(
+            hex::encode(hash256(b"")),
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456"
+        );
+    }
+
+    #[test]
+    fn hash160_pubkey() {
+        let sec = hex::decode("0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352")
+            .unwrap();
+        assert_eq!(
+            hex::encode(hash160(&sec)),
+            "f54a5851e9372b87810a8e60cdd2e7cfd80b6e31"
+        );
+    }
+
+    #[test]
+    fn siphash24_vectors() {
+        // from the SipHash paper, keyed with 00 01 .. 0f over the messages 00 01 .. len - 1
+        let (k0, k1) = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+        let data = (0..15).collect::<Vec<u8>>();
+        assert_eq!(siphash24(k0, k1, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(k0, k1, &data[..1]), 0x74f839c593dc67fd);
+        assert_eq!(siphash24(k0, k1, &data), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn murmur3_vectors() {
+        // from Bitcoin Core's hash tests
+        assert_eq!(murmur3_32(0, b""), 0);
+        assert_eq!(murmur3_32(0xfba4c795, b""), 0x6a396f08);
+        assert_eq!(murmur3_32(0xffffffff, b""), 0x81f16f39);
+        assert_eq!(murmur3_32(0, &[0x00]), 0x514e28b7);
+        assert_eq!(murmur3_32(0, &[0xff]), 0xfd6cf10d);
+        assert_eq!(murmur3_32(0, &[0x00, 0x11]), 0x16c6b7ab);
+        assert_eq!(murmur3_32(0, &[0x00, 0x11, 0x22]), 0x8eb51c3d);
+        assert_eq!(murmur3_32(0, &[0x00, 0x11, 0x22, 0x33]), 0xb4471bf8);
+        assert_eq!(murmur3_32(0, &[0x00, 0x11, 0x22, 0x33, 0x44]), 0xe2301fa8);
+        assert_eq!(murmur3_32(0xfba4c795, &[0x00]), 0xea3f0b17);
+    }
+}
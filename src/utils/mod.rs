@@ -1 +1,6 @@
 pub mod base58;
+pub mod base64;
+pub mod bech32;
+pub mod encoding;
+pub mod errors;
+pub mod hash;
@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors related to the set of unspent transaction outputs
+pub enum UtxoError {
+    #[error("output {0} is missing or already spent")]
+    MissingCoin(String),
+    #[error("output {0} already exists and is unspent")]
+    DuplicateCoin(String),
+    #[error("undo data holds {0} coins but the block spends {1}")]
+    UndoMismatch(usize, usize),
+    #[error("coin store ends in the middle of a record at offset {0}")]
+    CorruptStore(u64),
+    #[error("invalid coin store record type {0}")]
+    InvalidRecord(u8),
+}
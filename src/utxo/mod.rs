@@ -0,0 +1,4 @@
+pub mod errors;
+pub mod muhash;
+pub mod set;
+pub mod store;
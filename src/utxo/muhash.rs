@@ -0,0 +1,123 @@
+use {
+    crate::utils::hash::sha256,
+    chacha20::{
+        cipher::{KeyIvInit, StreamCipher},
+        ChaCha20,
+    },
+    ibig::{
+        modular::{Modulo, ModuloRing},
+        ubig, UBig,
+    },
+    std::sync::LazyLock,
+};
+
+/// Bytes of a number modulo the MuHash prime
+const NUM3072_SIZE: usize = 384;
+
+/// Ring of numbers modulo the largest 3072 bit safe prime, `2^3072 - 1103717`
+static RING: LazyLock<ModuloRing> =
+    LazyLock::new(|| ModuloRing::new(&((ubig!(1) << 3072) - ubig!(1103717))));
+
+#[derive(Clone)]
+/// Rolling hash of a set, as used by Bitcoin Core for the UTXO set hash
+///
+/// Elements are mapped to numbers modulo a 3072 bit prime, and the hash of the set commits to
+/// their product. Removals multiply a separate denominator, so elements can be added and removed
+/// in any order, at the cost of an inversion when the hash is finalised.
+pub struct MuHash3072 {
+    numerator: Modulo<'static>,
+    denominator: Modulo<'static>,
+}
+
+/// Number modulo the MuHash prime that `data` maps to
+fn to_num3072(data: &[u8]) -> Modulo<'static> {
+    let mut bytes = [0; NUM3072_SIZE];
+    ChaCha20::new(&sha256(data).into(), &[0; 12].into()).apply_keystream(&mut bytes);
+    RING.from(UBig::from_le_bytes(&bytes))
+}
+
+impl Default for MuHash3072 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MuHash3072 {
+    /// Hash of the empty set
+    pub fn new() -> Self {
+        Self {
+            numerator: RING.from(1u8),
+            denominator: RING.from(1u8),
+        }
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        self.numerator *= to_num3072(data);
+    }
+
+    pub fn remove(&mut self, data: &[u8]) {
+        self.denominator *= to_num3072(data);
+    }
+
+    /// Add all elements of `other` and remove those it removes
+    pub fn combine(&mut self, other: &Self) {
+        self.numerator *= &other.numerator;
+        self.denominator *= &other.denominator;
+    }
+
+    /// SHA256 of the product of the elements, serialised in little endian
+    pub fn finalise(&self) -> [u8; 32] {
+        // the denominator is never zero, as the prime does not divide the product of its elements
+        let product = &self.numerator * self.denominator.inverse().unwrap();
+        let mut bytes = product.residue().to_le_bytes();
+        bytes.resize(NUM3072_SIZE, 0);
+
+        sha256(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::encoding::reversed_hex};
+
+    /// Element of 32 bytes, the first of which is `i`
+    fn element(i: u8) -> [u8; 32] {
+        let mut element = [0; 32];
+        element[0] = i;
+        element
+    }
+
+    #[test]
+    fn core_vector() {
+        // from Bitcoin Core's crypto tests
+        let mut muhash = MuHash3072::new();
+        muhash.insert(&element(0));
+        muhash.insert(&element(1));
+        muhash.remove(&element(2));
+        assert_eq!(
+            reversed_hex(&muhash.finalise()),
+            "10d312b100cbd32ada024a6646e40d3482fcff103668d2625f10002a607d5863"
+        );
+    }
+
+    #[test]
+    fn order_independent() {
+        let mut forwards = MuHash3072::new();
+        let mut backwards = MuHash3072::new();
+        for i in 0..4 {
+            forwards.insert(&element(i));
+            backwards.insert(&element(3 - i));
+        }
+        assert_eq!(forwards.finalise(), backwards.finalise());
+
+        // removing an element cancels inserting it
+        let mut other = MuHash3072::new();
+        other.insert(&element(9));
+        other.remove(&element(3));
+        forwards.combine(&other);
+        forwards.remove(&element(9));
+        backwards.remove(&element(3));
+        assert_eq!(forwards.finalise(), backwards.finalise());
+        assert_ne!(forwards.finalise(), MuHash3072::new().finalise());
+    }
+}
@@ -0,0 +1,378 @@
+use {
+    super::{errors::UtxoError, muhash::MuHash3072, store::UtxoStore},
+    crate::{
+        block::body::Block,
+        transaction::tx::{OutPoint, TxOut},
+        utils::encoding::{compact_size, read_array, read_compact_size},
+    },
+    anyhow::{bail, Result},
+    std::{collections::HashMap, io::Read},
+};
+
+/// Confirmations a coinbase output needs before it can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Unspent transaction output, with the height of the block that created it
+pub struct Coin {
+    pub txout: TxOut,
+    pub height: u32,
+    pub is_coinbase: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// Coins spent by the inputs of a block, in order, to restore them if the block is disconnected
+pub struct BlockUndo {
+    pub spent: Vec<Coin>,
+}
+
+/// Read-only source of coins that transactions are validated against
+pub trait UtxoView {
+    /// Unspent coin at `outpoint`, if any
+    fn get_coin(&self, outpoint: &OutPoint) -> Result<Option<Coin>>;
+}
+
+/// Set of unspent transaction outputs, kept in a store, with a rolling hash of its coins
+pub struct UtxoSet<S: UtxoStore> {
+    store: S,
+    muhash: MuHash3072,
+}
+
+impl Coin {
+    /// If the coin can be spent by a transaction in a block at `height`
+    pub fn is_mature(&self, height: u32) -> bool {
+        !self.is_coinbase || height >= self.height.saturating_add(COINBASE_MATURITY)
+    }
+
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let code = u32::from_le_bytes(read_array(reader)?);
+        Ok(Self {
+            txout: TxOut::parse(reader)?,
+            height: code >> 1,
+            is_coinbase: code & 1 == 1,
+        })
+    }
+
+    /// Serialise the coin as its height and coinbase flag, packed as `height * 2 + is_coinbase`,
+    /// followed by the output
+    pub fn serialise(&self) -> Vec<u8> {
+        let code = (self.height << 1) | self.is_coinbase as u32;
+        [code.to_le_bytes().as_slice(), &self.txout.serialise()].concat()
+    }
+}
+
+impl BlockUndo {
+    pub fn parse(reader: &mut impl Read) -> Result<Self> {
+        let count = read_compact_size(reader)?;
+        let spent = (0..count)
+            .map(|_| Coin::parse(reader))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { spent })
+    }
+
+    pub fn serialise(&self) -> Vec<u8> {
+        let mut bytes = compact_size(self.spent.len() as u64);
+        for coin in &self.spent {
+            bytes.extend(coin.serialise());
+        }
+
+        bytes
+    }
+}
+
+impl UtxoView for HashMap<OutPoint, Coin> {
+    fn get_coin(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        Ok(self.get(outpoint).cloned())
+    }
+}
+
+/// Element of the rolling hash for `coin` at `outpoint`, as Bitcoin Core hashes the UTXO set
+fn muhash_element(outpoint: &OutPoint, coin: &Coin) -> Vec<u8> {
+    [outpoint.serialise(), coin.serialise()].concat()
+}
+
+impl<S: UtxoStore> UtxoSet<S> {
+    /// Set of the coins in `store`, hashing them all
+    pub fn new(store: S) -> Result<Self> {
+        let mut muhash = MuHash3072::new();
+        for (outpoint, coin) in store.coins()? {
+            muhash.insert(&muhash_element(&outpoint, &coin));
+        }
+
+        Ok(Self { store, muhash })
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        self.store.get(outpoint)
+    }
+
+    /// Number of coins in the set
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hash committing to every coin of the set, independent of the order they were added in
+    ///
+    /// Equal to the `muhash` Bitcoin Core reports for the same set of coins.
+    pub fn snapshot_hash(&self) -> [u8; 32] {
+        self.muhash.finalise()
+    }
+
+    /// Make the changes to the set durable
+    pub fn flush(&mut self) -> Result<()> {
+        self.store.flush()
+    }
+
+    /// Coin at `outpoint` once `changes` are applied
+    fn lookup(
+        &self,
+        changes: &HashMap<OutPoint, Option<Coin>>,
+        outpoint: &OutPoint,
+    ) -> Result<Option<Coin>> {
+        match changes.get(outpoint) {
+            Some(coin) => Ok(coin.clone()),
+            None => self.store.get(outpoint),
+        }
+    }
+
+    /// Write the final state of each changed outpoint to the store, updating the hash
+    fn apply(&mut self, changes: HashMap<OutPoint, Option<Coin>>) -> Result<()> {
+        for (outpoint, coin) in changes {
+            if let Some(old) = self.store.remove(&outpoint)? {
+                self.muhash.remove(&muhash_element(&outpoint, &old));
+            }
+            if let Some(coin) = coin {
+                self.muhash.insert(&muhash_element(&outpoint, &coin));
+                self.store.insert(outpoint, coin)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Spend the inputs and add the outputs of `block` at `height`, returning the coins it spent
+    ///
+    /// Outputs may be spent by later transactions of the same block. Unspendable outputs are not
+    /// added. A coinbase output replaces an existing coin only if `allow_overwrite`, as in blocks
+    /// that BIP30 is not enforced for. The set is left unchanged if an input is missing or an
+    /// output already exists otherwise.
+    pub fn connect_block(
+        &mut self,
+        block: &Block,
+        height: u32,
+        allow_overwrite: bool,
+    ) -> Result<BlockUndo> {
+        let mut changes = HashMap::new();
+        let mut undo = BlockUndo::default();
+        for tx in &block.transactions {
+            if !tx.is_coinbase() {
+                for input in &tx.inputs {
+                    let outpoint = input.previous_output;
+                    let Some(coin) = self.lookup(&changes, &outpoint)? else {
+                        bail!(UtxoError::MissingCoin(outpoint.to_string()));
+                    };
+                    undo.spent.push(coin);
+                    changes.insert(outpoint, None);
+                }
+            }
+
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                if output.script_pubkey.is_unspendable() {
+                    continue;
+                }
+                let outpoint = OutPoint::new(txid, vout as u32);
+                let overwrite = allow_overwrite && tx.is_coinbase();
+                if !overwrite && self.lookup(&changes, &outpoint)?.is_some() {
+                    bail!(UtxoError::DuplicateCoin(outpoint.to_string()));
+                }
+                let coin = Coin {
+                    txout: output.clone(),
+                    height,
+                    is_coinbase: tx.is_coinbase(),
+                };
+                changes.insert(outpoint, Some(coin));
+            }
+        }
+        self.apply(changes)?;
+
+        Ok(undo)
+    }
+
+    /// Remove the outputs of `block` and restore the coins it spent from `undo`, the undo data
+    /// returned when it was connected
+    ///
+    /// The set is left unchanged if an output of the block is missing or `undo` does not match it.
+    pub fn disconnect_block(&mut self, block: &Block, undo: &BlockUndo) -> Result<()> {
+        let spends = block
+            .transactions
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .map(|tx| tx.inputs.len())
+            .sum::<usize>();
+        if undo.spent.len() != spends {
+            bail!(UtxoError::UndoMismatch(undo.spent.len(), spends));
+        }
+
+        let mut changes = HashMap::new();
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.transactions.iter().rev() {
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                if output.script_pubkey.is_unspendable() {
+                    continue;
+                }
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if self
+                    .lookup(&changes, &outpoint)?
+                    .as_ref()
+                    .map(|coin| &coin.txout)
+                    != Some(output)
+                {
+                    bail!(UtxoError::MissingCoin(outpoint.to_string()));
+                }
+                changes.insert(outpoint, None);
+            }
+
+            if !tx.is_coinbase() {
+                for input in tx.inputs.iter().rev() {
+                    changes.insert(input.previous_output, spent.next().cloned());
+                }
+            }
+        }
+        self.apply(changes)
+    }
+}
+
+impl<S: UtxoStore> UtxoView for UtxoSet<S> {
+    fn get_coin(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        self.get(outpoint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            network::Network,
+            script::raw::Script,
+            transaction::tx::{Transaction, TxIn},
+            utxo::store::MemoryStore,
+        },
+    };
+
+    fn coinbase(height: u32) -> Transaction {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(OutPoint::null()));
+        tx.inputs[0].script_sig = Script::new().push_int(height as i64);
+        tx.outputs
+            .push(TxOut::new(50_0000_0000, Script::p2wpkh(&[1; 20])));
+        tx.outputs.push(TxOut::new(0, Script::op_return(b"data")));
+        tx
+    }
+
+    fn spend(outpoint: OutPoint, value: u64) -> Transaction {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(outpoint));
+        tx.outputs
+            .push(TxOut::new(value, Script::p2tr(&[value as u8; 32])));
+        tx
+    }
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        Block {
+            header: Network::Regtest.params().genesis,
+            transactions,
+        }
+    }
+
+    #[test]
+    fn connects_and_disconnects_blocks() -> Result<()> {
+        let mut utxos = UtxoSet::new(MemoryStore::new())?;
+        let empty = utxos.snapshot_hash();
+        let first = block(vec![coinbase(1)]);
+        let undo = utxos.connect_block(&first, 1, false)?;
+        assert!(undo.spent.is_empty());
+        // the data carrier output is not kept
+        assert_eq!(utxos.len(), 1);
+        let mined = OutPoint::new(first.transactions[0].txid(), 0);
+        let coin = utxos.get(&mined)?.unwrap();
+        assert!(coin.is_coinbase);
+        assert!(!coin.is_mature(100));
+        assert!(coin.is_mature(101));
+        let after_first = utxos.snapshot_hash();
+
+        // a transaction spending an output of an earlier one in the same block
+        let parent = spend(mined, 40_0000_0000);
+        let child = spend(OutPoint::new(parent.txid(), 0), 30_0000_0000);
+        let second = block(vec![coinbase(2), parent, child.clone()]);
+        let undo = utxos.connect_block(&second, 101, false)?;
+        assert_eq!(undo.spent.len(), 2);
+        assert_eq!(undo.spent[0], coin);
+        assert_eq!(BlockUndo::parse(&mut undo.serialise().as_slice())?, undo);
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(utxos.get(&mined)?, None);
+        assert_eq!(
+            utxos.get(&OutPoint::new(child.txid(), 0))?.unwrap().height,
+            101
+        );
+
+        // the hash depends on the coins only
+        let rebuilt = UtxoSet::new(utxos.store().clone())?;
+        assert_eq!(rebuilt.snapshot_hash(), utxos.snapshot_hash());
+
+        utxos.disconnect_block(&second, &undo)?;
+        assert_eq!(utxos.get(&mined)?, Some(coin));
+        assert_eq!(utxos.snapshot_hash(), after_first);
+        utxos.disconnect_block(&first, &BlockUndo::default())?;
+        assert!(utxos.is_empty());
+        assert_eq!(utxos.snapshot_hash(), empty);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_connections_leave_the_set_unchanged() -> Result<()> {
+        let mut utxos = UtxoSet::new(MemoryStore::new())?;
+        let first = block(vec![coinbase(1)]);
+        let undo = utxos.connect_block(&first, 1, false)?;
+        let hash = utxos.snapshot_hash();
+        let mined = OutPoint::new(first.transactions[0].txid(), 0);
+
+        // spending the same output twice
+        let double = block(vec![coinbase(2), spend(mined, 1000), spend(mined, 2000)]);
+        let err = utxos.connect_block(&double, 2, false).unwrap_err();
+        assert_eq!(
+            err.downcast::<UtxoError>()?,
+            UtxoError::MissingCoin(mined.to_string())
+        );
+
+        // an output that already exists
+        let err = utxos.connect_block(&first, 2, false).unwrap_err();
+        assert_eq!(
+            err.downcast::<UtxoError>()?,
+            UtxoError::DuplicateCoin(mined.to_string())
+        );
+        assert_eq!(utxos.snapshot_hash(), hash);
+        assert_eq!(utxos.len(), 1);
+
+        // undo data of another block
+        let second = block(vec![coinbase(2), spend(mined, 1000)]);
+        let err = utxos.disconnect_block(&second, &undo).unwrap_err();
+        assert_eq!(err.downcast::<UtxoError>()?, UtxoError::UndoMismatch(0, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use {
+    super::{errors::UtxoError, set::Coin},
+    crate::{transaction::tx::OutPoint, utils::encoding::read_array},
+    anyhow::{bail, Result},
+    std::{
+        collections::HashMap,
+        fs::{self, File, OpenOptions},
+        io::{BufReader, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Record of a coin added at an outpoint in a file store
+const RECORD_INSERT: u8 = 1;
+
+/// Record of the coin at an outpoint being removed in a file store
+const RECORD_REMOVE: u8 = 2;
+
+/// Bytes of the kind and outpoint a record starts with
+const RECORD_HEADER_SIZE: u64 = 37;
+
+/// Storage of the coins of a UTXO set, keyed by their outpoint
+pub trait UtxoStore {
+    fn get(&self, outpoint: &OutPoint) -> Result<Option<Coin>>;
+
+    /// Add `coin` at `outpoint`, replacing any coin there
+    fn insert(&mut self, outpoint: OutPoint, coin: Coin) -> Result<()>;
+
+    /// Remove the coin at `outpoint`, returning it if there was one
+    fn remove(&mut self, outpoint: &OutPoint) -> Result<Option<Coin>>;
+
+    /// Number of stored coins
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every stored coin, in no particular order
+    fn coins(&self) -> Result<Vec<(OutPoint, Coin)>>;
+
+    /// Make the changes durable
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+/// Store keeping the coins in memory
+pub struct MemoryStore {
+    coins: HashMap<OutPoint, Coin>,
+}
+
+/// Store keeping the coins in an append-only log of insertions and removals
+///
+/// Only the offsets of the coins are kept in memory. Removed coins stay in the log until it is
+/// compacted.
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    /// Offset of the serialised coin at each outpoint
+    index: HashMap<OutPoint, u64>,
+    len: u64,
+}
+
+/// Kind and outpoint at the start of a record
+fn read_record_header(reader: &mut impl Read) -> Result<(u8, OutPoint)> {
+    let [kind] = read_array(reader)?;
+    Ok((kind, OutPoint::parse(reader)?))
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UtxoStore for MemoryStore {
+    fn get(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        Ok(self.coins.get(outpoint).cloned())
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, coin: Coin) -> Result<()> {
+        self.coins.insert(outpoint, coin);
+        Ok(())
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        Ok(self.coins.remove(outpoint))
+    }
+
+    fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    fn coins(&self) -> Result<Vec<(OutPoint, Coin)>> {
+        Ok(self
+            .coins
+            .iter()
+            .map(|(outpoint, coin)| (*outpoint, coin.clone()))
+            .collect())
+    }
+}
+
+impl FileStore {
+    /// Open the store at `path`, creating an empty one if it does not exist, and index its log
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+
+        let mut index = HashMap::new();
+        let mut reader = bytes.as_slice();
+        while !reader.is_empty() {
+            let start = (bytes.len() - reader.len()) as u64;
+            let Ok((kind, outpoint)) = read_record_header(&mut reader) else {
+                bail!(UtxoError::CorruptStore(start));
+            };
+            match kind {
+                RECORD_INSERT => {
+                    let offset = (bytes.len() - reader.len()) as u64;
+                    if Coin::parse(&mut reader).is_err() {
+                        bail!(UtxoError::CorruptStore(start));
+                    }
+                    index.insert(outpoint, offset);
+                }
+                RECORD_REMOVE => {
+                    index.remove(&outpoint);
+                }
+                kind => bail!(UtxoError::InvalidRecord(kind)),
+            }
+        }
+
+        Ok(Self {
+            path,
+            file,
+            index,
+            len: bytes.len() as u64,
+        })
+    }
+
+    /// Size of the log in bytes
+    pub fn log_size(&self) -> u64 {
+        self.len
+    }
+
+    /// Append `record` to the log, returning its offset
+    fn append(&mut self, record: &[u8]) -> Result<u64> {
+        let offset = self.len;
+        self.file.write_all(record)?;
+        self.len += record.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Rewrite the log with only the stored coins, dropping removed ones
+    pub fn compact(&mut self) -> Result<()> {
+        let tmp = self.path.with_extension("compact");
+        let mut bytes = vec![];
+        for (outpoint, coin) in self.coins()? {
+            bytes.push(RECORD_INSERT);
+            bytes.extend(outpoint.serialise());
+            bytes.extend(coin.serialise());
+        }
+        fs::write(&tmp, &bytes)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl UtxoStore for FileStore {
+    fn get(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        let Some(offset) = self.index.get(outpoint) else {
+            return Ok(None);
+        };
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(*offset))?;
+
+        Ok(Some(Coin::parse(&mut BufReader::new(file))?))
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, coin: Coin) -> Result<()> {
+        let record = [
+            [RECORD_INSERT].as_slice(),
+            &outpoint.serialise(),
+            &coin.serialise(),
+        ]
+        .concat();
+        let offset = self.append(&record)? + RECORD_HEADER_SIZE;
+        self.index.insert(outpoint, offset);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        let Some(coin) = self.get(outpoint)? else {
+            return Ok(None);
+        };
+        self.append(&[[RECORD_REMOVE].as_slice(), &outpoint.serialise()].concat())?;
+        self.index.remove(outpoint);
+
+        Ok(Some(coin))
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn coins(&self) -> Result<Vec<(OutPoint, Coin)>> {
+        self.index
+            .keys()
+            .map(|outpoint| Ok((*outpoint, self.get(outpoint)?.unwrap())))
+            .collect()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{script::raw::Script, transaction::tx::TxOut},
+    };
+
+    fn coin(value: u64) -> Coin {
+        Coin {
+            txout: TxOut::new(value, Script::p2wpkh(&[value as u8; 20])),
+            height: value as u32,
+            is_coinbase: value.is_multiple_of(2),
+        }
+    }
+
+    /// Insert and remove coins, leaving those at outpoints 1 and 3
+    fn exercise(store: &mut impl UtxoStore) -> Result<()> {
+        for i in 0..4 {
+            store.insert(OutPoint::new([i as u8; 32], i), coin(i as u64))?;
+        }
+        assert_eq!(store.remove(&OutPoint::new([0; 32], 0))?, Some(coin(0)));
+        assert_eq!(store.remove(&OutPoint::new([0; 32], 0))?, None);
+        store.insert(OutPoint::new([2; 32], 2), coin(20))?;
+        store.remove(&OutPoint::new([2; 32], 2))?;
+        store.insert(OutPoint::new([3; 32], 3), coin(30))?;
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&OutPoint::new([1; 32], 1))?, Some(coin(1)));
+        assert_eq!(store.get(&OutPoint::new([3; 32], 3))?, Some(coin(30)));
+        assert_eq!(store.get(&OutPoint::new([2; 32], 2))?, None);
+        store.flush()
+    }
+
+    #[test]
+    fn memory_store() -> Result<()> {
+        let mut store = MemoryStore::new();
+        exercise(&mut store)?;
+        assert_eq!(store.coins()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn file_store_persists_and_compacts() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("store-{}.coins", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileStore::open(&path)?;
+        exercise(&mut store)?;
+        let size = store.log_size();
+        drop(store);
+
+        let mut store = FileStore::open(&path)?;
+        assert_eq!(store.log_size(), size);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&OutPoint::new([3; 32], 3))?, Some(coin(30)));
+
+        store.compact()?;
+        assert!(store.log_size() < size);
+        let mut coins = FileStore::open(&path)?.coins()?;
+        coins.sort_by_key(|(outpoint, _)| *outpoint);
+        assert_eq!(
+            coins,
+            [
+                (OutPoint::new([1; 32], 1), coin(1)),
+                (OutPoint::new([3; 32], 3), coin(30))
+            ]
+        );
+
+        // a record cut short is refused
+        let size = store.log_size();
+        store.insert(OutPoint::new([4; 32], 4), coin(4))?;
+        drop(store);
+        let bytes = fs::read(&path)?;
+        fs::write(&path, &bytes[..bytes.len() - 1])?;
+        let err = FileStore::open(&path).err().unwrap();
+        assert_eq!(err.downcast::<UtxoError>()?, UtxoError::CorruptStore(size));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,676 @@
+use {
+    super::{
+        errors::ValidationError,
+        tx::{
+            check_sequence_locks, check_transaction, check_tx_inputs, is_final, verify_scripts,
+            COIN,
+        },
+    },
+    crate::{
+        block::body::{Block, MAX_BLOCK_SIGOPS_COST, MAX_BLOCK_WEIGHT},
+        chain::headers::{Accepted, HeaderChain},
+        network::params::Params,
+        script::{
+            interpreter::{
+                SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY, SCRIPT_VERIFY_CHECKSEQUENCEVERIFY,
+                SCRIPT_VERIFY_DERSIG, SCRIPT_VERIFY_NULLDUMMY, SCRIPT_VERIFY_P2SH,
+                SCRIPT_VERIFY_TAPROOT, SCRIPT_VERIFY_WITNESS,
+            },
+            raw::{Instruction, Script},
+        },
+        transaction::tx::{OutPoint, Transaction, WITNESS_SCALE_FACTOR},
+        utils::encoding::reversed_hex,
+        utxo::{
+            set::{BlockUndo, Coin, UtxoSet, UtxoView},
+            store::UtxoStore,
+        },
+    },
+    anyhow::{bail, Result},
+    std::{collections::HashMap, thread},
+};
+
+/// Height from which BIP34 no longer rules out duplicate transactions, as coinbases of earlier
+/// blocks happen to start with a push of a height this high
+const BIP34_IMPLIES_BIP30_LIMIT: u32 = 1_983_702;
+
+/// Header chain with the set of coins at its tip, extended by fully validated blocks
+pub struct ChainState<S: UtxoStore> {
+    chain: HeaderChain,
+    utxos: UtxoSet<S>,
+}
+
+/// Coins of a view with the changes of the transactions of a block validated so far
+struct BlockView<'a, V: UtxoView> {
+    base: &'a V,
+    changes: HashMap<OutPoint, Option<Coin>>,
+}
+
+impl<V: UtxoView> UtxoView for BlockView<'_, V> {
+    fn get_coin(&self, outpoint: &OutPoint) -> Result<Option<Coin>> {
+        match self.changes.get(outpoint) {
+            Some(coin) => Ok(coin.clone()),
+            None => self.base.get_coin(outpoint),
+        }
+    }
+}
+
+/// Satoshis a coinbase at `height` may create on top of the fees, halving every
+/// `subsidy_halving_interval` blocks
+pub fn block_subsidy(height: u32, params: &Params) -> u64 {
+    let halvings = height / params.subsidy_halving_interval;
+    match halvings {
+        0..64 => (50 * COIN) >> halvings,
+        _ => 0,
+    }
+}
+
+/// Flags the scripts of the block `hash` at `height` are verified under
+///
+/// P2SH, segwit and taproot are enforced from the genesis block, apart from the few blocks that
+/// break them before they activated.
+pub fn block_script_flags(params: &Params, height: u32, hash: &[u8; 32]) -> u32 {
+    let mut flags = params
+        .script_flag_exceptions
+        .iter()
+        .find(|(exception, _)| exception == hash)
+        .map_or(
+            SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS | SCRIPT_VERIFY_TAPROOT,
+            |(_, flags)| *flags,
+        );
+    for (activation, flag) in [
+        (params.bip66_height, SCRIPT_VERIFY_DERSIG),
+        (params.bip65_height, SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY),
+        (params.csv_height, SCRIPT_VERIFY_CHECKSEQUENCEVERIFY),
+        (params.segwit_height, SCRIPT_VERIFY_NULLDUMMY),
+    ] {
+        if height >= activation {
+            flags |= flag;
+        }
+    }
+
+    flags
+}
+
+/// Redeem script of a P2SH spend, the last push of its push only script sig
+fn redeem_script(script_sig: &Script) -> Option<Script> {
+    if !script_sig.is_push_only() {
+        return None;
+    }
+    match script_sig.instructions().last()? {
+        Ok(Instruction::Push(bytes)) => Some(Script::from_bytes(bytes.to_vec())),
+        _ => None,
+    }
+}
+
+/// Signature operations of a witness program spent with `witness`
+fn witness_sigops(version: u8, program: &[u8], witness: &[Vec<u8>]) -> usize {
+    match (version, program.len(), witness.last()) {
+        (0, 20, _) => 1,
+        (0, 32, Some(script)) => Script::from_bytes(script.clone()).sigop_count(true),
+        _ => 0,
+    }
+}
+
+/// Signature operations of `tx` spending `coins` under `flags`, in weight units
+///
+/// Signature operations of witness scripts count a quarter of the others.
+pub fn tx_sigop_cost(tx: &Transaction, coins: &[Coin], flags: u32) -> usize {
+    let legacy = tx
+        .inputs
+        .iter()
+        .map(|input| input.script_sig.sigop_count(false))
+        .chain(
+            tx.outputs
+                .iter()
+                .map(|output| output.script_pubkey.sigop_count(false)),
+        )
+        .sum::<usize>();
+    let mut cost = legacy * WITNESS_SCALE_FACTOR;
+    if tx.is_coinbase() {
+        return cost;
+    }
+
+    for (input, coin) in tx.inputs.iter().zip(coins) {
+        let script_pubkey = &coin.txout.script_pubkey;
+        let redeem = match script_pubkey.is_p2sh() {
+            true => redeem_script(&input.script_sig),
+            false => None,
+        };
+        if flags & SCRIPT_VERIFY_P2SH != 0 {
+            if let Some(redeem) = &redeem {
+                cost += redeem.sigop_count(true) * WITNESS_SCALE_FACTOR;
+            }
+        }
+        if flags & SCRIPT_VERIFY_WITNESS != 0 {
+            let program = script_pubkey
+                .witness_version_and_program()
+                .or_else(|| redeem.as_ref()?.witness_version_and_program());
+            if let Some((version, program)) = program {
+                cost += witness_sigops(version, program, &input.witness);
+            }
+        }
+    }
+
+    cost
+}
+
+/// Check the rules a block must follow regardless of the chain it is in
+pub fn check_block(block: &Block) -> Result<()> {
+    if block.transactions.is_empty()
+        || block.stripped_size() * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT
+    {
+        bail!(ValidationError::BadBlockLength);
+    }
+    block.check_merkle_root()?;
+    for tx in &block.transactions {
+        check_transaction(tx)?;
+    }
+    let cost = block.legacy_sigop_cost();
+    if cost > MAX_BLOCK_SIGOPS_COST {
+        bail!(ValidationError::BadBlockSigops(cost, MAX_BLOCK_SIGOPS_COST));
+    }
+
+    Ok(())
+}
+
+/// Verify the scripts of each transaction, with the coins it spends, over as many threads as
+/// the machine has
+fn verify_block_scripts(jobs: &[(&Transaction, Vec<Coin>)], flags: u32) -> Result<()> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunk_size = jobs.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles = jobs
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .try_for_each(|(tx, coins)| verify_scripts(tx, coins, flags))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // the first failure in block order is reported
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })
+}
+
+impl<S: UtxoStore> ChainState<S> {
+    /// State of `chain` whose tip has the coins of `utxos`
+    pub fn new(chain: HeaderChain, utxos: UtxoSet<S>) -> Self {
+        Self { chain, utxos }
+    }
+
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    pub fn utxos(&self) -> &UtxoSet<S> {
+        &self.utxos
+    }
+
+    /// Height of the tip
+    pub fn height(&self) -> u32 {
+        self.chain.height()
+    }
+
+    /// Median time past of the block at `height`, which must not be above the tip
+    fn median_time_past(&self, height: u32) -> u32 {
+        self.chain.median_time_past_at(height).unwrap_or_default()
+    }
+
+    /// Check the rules `block` must follow at `height`, on top of the current tip
+    fn contextual_check_block(&self, block: &Block, height: u32) -> Result<()> {
+        let params = self.chain.params();
+        let version = block.header.version;
+        if (height >= params.bip34_height && version < 2)
+            || (height >= params.bip66_height && version < 3)
+            || (height >= params.bip65_height && version < 4)
+        {
+            bail!(ValidationError::BadVersion(version));
+        }
+
+        // since BIP113 lock times are compared to the median time past
+        let cutoff = match height >= params.csv_height {
+            true => self.median_time_past(height - 1),
+            false => block.header.time,
+        };
+        if !block
+            .transactions
+            .iter()
+            .all(|tx| is_final(tx, height, cutoff))
+        {
+            bail!(ValidationError::NonFinal);
+        }
+
+        if height >= params.bip34_height && block.bip34_height().ok() != Some(height) {
+            bail!(ValidationError::BadCoinbaseHeight(height));
+        }
+
+        if height >= params.segwit_height {
+            block.check_witness_commitment()?;
+        } else if block.transactions.iter().any(Transaction::has_witness) {
+            bail!(ValidationError::UnexpectedWitness);
+        }
+        let weight = block.weight();
+        if weight > MAX_BLOCK_WEIGHT {
+            bail!(ValidationError::BadBlockWeight(weight, MAX_BLOCK_WEIGHT));
+        }
+
+        Ok(())
+    }
+
+    /// Validate `block` on top of the tip and make it the new tip, returning the coins it spent
+    ///
+    /// The header must meet the proof of work and time rules, with `adjusted_time` the network
+    /// adjusted time, and every transaction must be valid against the coins of the tip and the
+    /// transactions before it. Scripts are verified in parallel. The state is left unchanged if
+    /// the block is invalid.
+    pub fn connect_block(&mut self, block: &Block, adjusted_time: u32) -> Result<BlockUndo> {
+        let tip = self.chain.tip().header.hash();
+        if block.header.prev_blockhash != tip {
+            bail!(ValidationError::BadPrevBlock(reversed_hex(&tip)));
+        }
+        let height = self.height() + 1;
+        let hash = block.hash();
+        self.chain.check_header(&block.header, adjusted_time)?;
+        check_block(block)?;
+        self.contextual_check_block(block, height)?;
+
+        let params = self.chain.params();
+        let enforce_bip30 = !params.bip30_exceptions.contains(&(height, hash))
+            && (height < params.bip34_height || height >= BIP34_IMPLIES_BIP30_LIMIT);
+        if enforce_bip30 {
+            for tx in &block.transactions {
+                let txid = tx.txid();
+                for vout in 0..tx.outputs.len() {
+                    let outpoint = OutPoint::new(txid, vout as u32);
+                    if self.utxos.get_coin(&outpoint)?.is_some() {
+                        bail!(ValidationError::Bip30(outpoint.to_string()));
+                    }
+                }
+            }
+        }
+
+        let flags = block_script_flags(params, height, &hash);
+        let check_sequences = height >= params.csv_height;
+        let mut view = BlockView {
+            base: &self.utxos,
+            changes: HashMap::new(),
+        };
+        let mut sigop_cost = 0;
+        let mut fees = 0;
+        let mut jobs = vec![];
+        for tx in &block.transactions {
+            let mut coins = vec![];
+            if !tx.is_coinbase() {
+                let (spent, fee) = check_tx_inputs(tx, &view, height)?;
+                if check_sequences {
+                    check_sequence_locks(tx, &spent, height, |h| self.median_time_past(h))?;
+                }
+                fees += fee;
+                coins = spent;
+                for input in &tx.inputs {
+                    view.changes.insert(input.previous_output, None);
+                }
+            }
+
+            sigop_cost += tx_sigop_cost(tx, &coins, flags);
+            if sigop_cost > MAX_BLOCK_SIGOPS_COST {
+                bail!(ValidationError::BadBlockSigops(
+                    sigop_cost,
+                    MAX_BLOCK_SIGOPS_COST
+                ));
+            }
+
+            let txid = tx.txid();
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                let coin = Coin {
+                    txout: output.clone(),
+                    height,
+                    is_coinbase: tx.is_coinbase(),
+                };
+                view.changes
+                    .insert(OutPoint::new(txid, vout as u32), Some(coin));
+            }
+            if !tx.is_coinbase() {
+                jobs.push((tx, coins));
+            }
+        }
+
+        let reward = block.transactions[0].output_value();
+        let allowed = fees + block_subsidy(height, params);
+        if reward > allowed {
+            bail!(ValidationError::BadCoinbaseAmount(reward, allowed));
+        }
+        verify_block_scripts(&jobs, flags)?;
+
+        // without BIP30 a coinbase replaces an unspent one with the same txid, as in the exceptions
+        let undo = self.utxos.connect_block(block, height, !enforce_bip30)?;
+        let accepted = self.chain.accept(block.header, adjusted_time)?;
+        debug_assert_eq!(accepted, Accepted::Extended);
+
+        Ok(undo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            block::{
+                body::WITNESS_COMMITMENT_HEADER,
+                errors::BlockError,
+                header::{BlockHash, BlockHeader},
+            },
+            network::Network,
+            secp256k1::keys::PrivateKey,
+            transaction::{
+                sighash::SIGHASH_ALL,
+                tx::{TxIn, TxOut},
+            },
+            utils::hash::hash256,
+            utxo::store::MemoryStore,
+        },
+    };
+
+    fn key() -> PrivateKey {
+        PrivateKey::new("c0ffee", 16).unwrap()
+    }
+
+    fn miner_script() -> Script {
+        Script::p2wpkh(&key().point().hash160(true))
+    }
+
+    fn regtest() -> ChainState<MemoryStore> {
+        let chain = HeaderChain::new(Network::Regtest.params());
+        ChainState::new(chain, UtxoSet::new(MemoryStore::new()).unwrap())
+    }
+
+    /// Block on the tip of `state` with `txs` after a coinbase paying `reward` to the miner key
+    fn block(state: &ChainState<MemoryStore>, reward: u64, txs: Vec<Transaction>) -> Block {
+        let height = state.height() + 1;
+        let mut coinbase = Transaction::new(2, 0);
+        coinbase.inputs.push(TxIn::new(OutPoint::null()));
+        coinbase.inputs[0].script_sig = Script::new().push_int(height as i64).push_int(0);
+        coinbase.outputs.push(TxOut::new(reward, miner_script()));
+
+        let mut block = Block {
+            header: BlockHeader {
+                version: 4,
+                prev_blockhash: state.chain().tip().header.hash(),
+                merkle_root: [0; 32],
+                time: state.chain().tip().header.time + 600,
+                bits: state.chain().tip().header.bits,
+                nonce: 0,
+            },
+            transactions: [vec![coinbase], txs].concat(),
+        };
+        if block.transactions.iter().any(Transaction::has_witness) {
+            let commitment = hash256(&[block.compute_witness_root(), [0; 32]].concat());
+            let script = Script::from_bytes([&WITNESS_COMMITMENT_HEADER[..], &commitment].concat());
+            let coinbase = &mut block.transactions[0];
+            coinbase.outputs.push(TxOut::new(0, script));
+            coinbase.inputs[0].witness = vec![vec![0; 32]];
+        }
+
+        mine(block)
+    }
+
+    /// Set the merkle root of `block` and find a nonce meeting its target
+    fn mine(mut block: Block) -> Block {
+        block.header.merkle_root = block.compute_merkle_root().0;
+        block.header.nonce = 0;
+        while block.header.check_pow().is_err() {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    /// Spend of the miner output `outpoint` of `value`, paying `fee`
+    fn spend(outpoint: OutPoint, value: u64, fee: u64) -> Transaction {
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(outpoint));
+        tx.outputs
+            .push(TxOut::new(value - fee, Script::p2tr(&[7; 32])));
+        let hash = key().point().hash160(true);
+        let sighash = tx
+            .segwit_v0_sighash(0, &Script::p2pkh(&hash), value, SIGHASH_ALL as u32)
+            .unwrap();
+        let mut sig = key().sign(&hex::encode(sighash)).unwrap().der();
+        sig.push(SIGHASH_ALL);
+        tx.inputs[0].witness = vec![sig, key().point().sec(true)];
+        tx
+    }
+
+    /// Extend `state` by `count` blocks with only a coinbase
+    fn generate(state: &mut ChainState<MemoryStore>, count: u32) -> Vec<BlockHash> {
+        (0..count)
+            .map(|_| {
+                let subsidy = block_subsidy(state.height() + 1, state.chain().params());
+                let block = block(state, subsidy, vec![]);
+                state.connect_block(&block, block.header.time).unwrap();
+                block.hash()
+            })
+            .collect()
+    }
+
+    fn reason(result: Result<BlockUndo>) -> String {
+        result
+            .unwrap_err()
+            .downcast::<ValidationError>()
+            .unwrap()
+            .reject_reason()
+    }
+
+    #[test]
+    fn subsidy_halves() {
+        let mainnet = Network::Mainnet.params();
+        assert_eq!(block_subsidy(0, &mainnet), 50 * COIN);
+        assert_eq!(block_subsidy(209_999, &mainnet), 50 * COIN);
+        assert_eq!(block_subsidy(210_000, &mainnet), 25 * COIN);
+        assert_eq!(block_subsidy(840_000, &mainnet), 3 * COIN + COIN / 8);
+        assert_eq!(block_subsidy(64 * 210_000, &mainnet), 0);
+
+        let regtest = Network::Regtest.params();
+        assert_eq!(block_subsidy(149, &regtest), 50 * COIN);
+        assert_eq!(block_subsidy(150, &regtest), 25 * COIN);
+    }
+
+    #[test]
+    fn script_flags_follow_activations() {
+        let params = Network::Mainnet.params();
+        let hash = [0; 32];
+        let base = SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS | SCRIPT_VERIFY_TAPROOT;
+        assert_eq!(block_script_flags(&params, 0, &hash), base);
+        assert_eq!(
+            block_script_flags(&params, 363_725, &hash),
+            base | SCRIPT_VERIFY_DERSIG
+        );
+        assert_eq!(
+            block_script_flags(&params, 481_824, &hash),
+            base | SCRIPT_VERIFY_DERSIG
+                | SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY
+                | SCRIPT_VERIFY_CHECKSEQUENCEVERIFY
+                | SCRIPT_VERIFY_NULLDUMMY
+        );
+
+        let (exception, flags) = params.script_flag_exceptions[0];
+        assert_eq!(block_script_flags(&params, 170_060, &exception), flags);
+    }
+
+    /// Outpoint of the miner output created at `height`
+    fn mined_at(state: &ChainState<MemoryStore>, height: u32) -> OutPoint {
+        state
+            .utxos()
+            .store()
+            .coins()
+            .unwrap()
+            .into_iter()
+            .find(|(_, coin)| coin.height == height)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn connects_a_regtest_chain() -> Result<()> {
+        let mut state = regtest();
+        let hashes = generate(&mut state, 101);
+        assert_eq!(state.height(), 101);
+        assert_eq!(state.chain().hash_at(1), Some(hashes[0]));
+        assert_eq!(state.utxos().len(), 101);
+
+        // the coinbase of block 1 has matured and pays a fee to the next coinbase
+        let coinbase = mined_at(&state, 1);
+        let tx = spend(coinbase, 50 * COIN, 1000);
+        let reward = block_subsidy(102, state.chain().params()) + 1000;
+        let next = block(&state, reward, vec![tx.clone()]);
+        let undo = state.connect_block(&next, next.header.time)?;
+
+        assert_eq!(undo.spent.len(), 1);
+        assert_eq!(undo.spent[0].height, 1);
+        assert_eq!(state.height(), 102);
+        assert_eq!(state.chain().tip().header.hash(), next.hash());
+        assert!(state.utxos().get(&coinbase)?.is_none());
+        assert!(state.utxos().get(&OutPoint::new(tx.txid(), 0))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() -> Result<()> {
+        let mut state = regtest();
+        generate(&mut state, 1);
+        let coinbase = mined_at(&state, 1);
+        let subsidy = block_subsidy(2, state.chain().params());
+        let before = state.utxos().snapshot_hash();
+
+        let greedy = block(&state, subsidy + 1, vec![]);
+        assert_eq!(
+            reason(state.connect_block(&greedy, greedy.header.time)),
+            "bad-cb-amount"
+        );
+
+        let immature = block(&state, subsidy, vec![spend(coinbase, 50 * COIN, 0)]);
+        assert_eq!(
+            reason(state.connect_block(&immature, immature.header.time)),
+            "bad-txns-premature-spend-of-coinbase"
+        );
+
+        let mut wrong_height = block(&state, subsidy, vec![]);
+        wrong_height.transactions[0].inputs[0].script_sig = Script::new().push_int(3).push_int(0);
+        let wrong_height = mine(wrong_height);
+        assert_eq!(
+            reason(state.connect_block(&wrong_height, wrong_height.header.time)),
+            "bad-cb-height"
+        );
+
+        let mut old_version = block(&state, subsidy, vec![]);
+        old_version.header.version = 3;
+        let old_version = mine(old_version);
+        assert_eq!(
+            reason(state.connect_block(&old_version, old_version.header.time)),
+            "bad-version(0x00000003)"
+        );
+
+        let mut bad_root = block(&state, subsidy, vec![]);
+        bad_root.header.merkle_root = [1; 32];
+        while bad_root.header.check_pow().is_err() {
+            bad_root.header.nonce += 1;
+        }
+        assert_eq!(
+            state
+                .connect_block(&bad_root, bad_root.header.time)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&BlockError::BadMerkleRoot)
+        );
+
+        let mut orphan = block(&state, subsidy, vec![]);
+        orphan.header.prev_blockhash = [0; 32];
+        assert_eq!(
+            reason(state.connect_block(&orphan, orphan.header.time)),
+            "bad-prevblk"
+        );
+
+        // a spend of an output that does not exist
+        let missing = block(
+            &state,
+            subsidy,
+            vec![spend(OutPoint::new([9; 32], 0), COIN, 0)],
+        );
+        assert_eq!(
+            reason(state.connect_block(&missing, missing.header.time)),
+            "bad-txns-inputs-missingorspent"
+        );
+
+        assert_eq!(state.height(), 1);
+        assert_eq!(state.utxos().snapshot_hash(), before);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_scripts_and_double_spends() -> Result<()> {
+        let mut state = regtest();
+        generate(&mut state, 101);
+        let coinbase = mined_at(&state, 1);
+        let subsidy = block_subsidy(102, state.chain().params());
+
+        let mut forged = spend(coinbase, 50 * COIN, 0);
+        forged.outputs[0].value -= 1;
+        let invalid = block(&state, subsidy, vec![forged]);
+        assert!(reason(state.connect_block(&invalid, invalid.header.time))
+            .starts_with("mandatory-script-verify-flag-failed"));
+
+        // a double spend within the block
+        let first = spend(coinbase, 50 * COIN, 0);
+        let second = spend(coinbase, 50 * COIN, 1);
+        let double = block(&state, subsidy, vec![first.clone(), second]);
+        assert_eq!(
+            reason(state.connect_block(&double, double.header.time)),
+            "bad-txns-inputs-missingorspent"
+        );
+
+        let valid = block(&state, subsidy, vec![first]);
+        state.connect_block(&valid, valid.header.time)?;
+        assert_eq!(state.height(), 102);
+        Ok(())
+    }
+
+    #[test]
+    fn bip30_exceptions_overwrite_duplicate_coinbases() -> Result<()> {
+        // before BIP34 a coinbase need not commit to its height, so it can repeat
+        let mut params = Network::Regtest.params();
+        params.bip34_height = 100;
+        let mut state = ChainState::new(
+            HeaderChain::new(params.clone()),
+            UtxoSet::new(MemoryStore::new())?,
+        );
+        let first = block(&state, 50 * COIN, vec![]);
+        state.connect_block(&first, first.header.time)?;
+        let mut duplicate = block(&state, 50 * COIN, vec![]);
+        duplicate.transactions = first.transactions.clone();
+        let duplicate = mine(duplicate);
+        assert_eq!(
+            reason(state.connect_block(&duplicate, duplicate.header.time)),
+            "bad-txns-BIP30"
+        );
+
+        params.bip30_exceptions.push((2, duplicate.hash()));
+        let mut state =
+            ChainState::new(HeaderChain::new(params), UtxoSet::new(MemoryStore::new())?);
+        state.connect_block(&first, first.header.time)?;
+        state.connect_block(&duplicate, duplicate.header.time)?;
+        let mined = OutPoint::new(first.transactions[0].txid(), 0);
+        assert_eq!(state.height(), 2);
+        assert_eq!(state.utxos().len(), 1);
+        assert_eq!(state.utxos().get(&mined)?.unwrap().height, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors of transactions that break the consensus rules
+pub enum ValidationError {
+    #[error("transaction has no inputs")]
+    NoInputs,
+    #[error("transaction has no outputs")]
+    NoOutputs,
+    #[error("transaction weight without witness {0} is above the limit of {1}")]
+    Oversize(usize, usize),
+    #[error("output value {0} is above the money supply")]
+    OutputValueOutOfRange(u64),
+    #[error("total output value is above the money supply")]
+    OutputTotalOutOfRange,
+    #[error("input {0} is spent twice")]
+    DuplicateInput(String),
+    #[error("coinbase script is {0} bytes, outside 2 to 100")]
+    CoinbaseLength(usize),
+    #[error("input {0} spends the null outpoint")]
+    NullPrevout(usize),
+    #[error("coinbase transactions are only valid in blocks")]
+    Coinbase,
+    #[error("input {0} is missing or already spent")]
+    MissingInput(String),
+    #[error("input {0} spends a coinbase at depth {1}")]
+    PrematureCoinbaseSpend(String, u32),
+    #[error("total input value is above the money supply")]
+    InputValuesOutOfRange,
+    #[error("input value {0} is below output value {1}")]
+    InputsBelowOutputs(u64, u64),
+    #[error("transaction lock time is not reached")]
+    NonFinal,
+    #[error("relative lock time of an input is not reached")]
+    SequenceLocks,
+    #[error("script of input {0} failed: {1}")]
+    ScriptFailed(usize, String),
+    #[error("block does not build on the tip {0}")]
+    BadPrevBlock(String),
+    #[error("block has no transactions or is too large")]
+    BadBlockLength,
+    #[error("block weight {0} is above the limit of {1}")]
+    BadBlockWeight(usize, usize),
+    #[error("block signature operations cost {0}, above the limit of {1}")]
+    BadBlockSigops(usize, usize),
+    #[error("block version {0:#010x} is obsolete at this height")]
+    BadVersion(i32),
+    #[error("coinbase does not start with the block height {0}")]
+    BadCoinbaseHeight(u32),
+    #[error("coinbase pays {0}, above the subsidy and fees of {1}")]
+    BadCoinbaseAmount(u64, u64),
+    #[error("transaction overwrites the unspent output {0}")]
+    Bip30(String),
+    #[error("witness data in a block before segwit")]
+    UnexpectedWitness,
+}
+
+impl ValidationError {
+    /// Reason Bitcoin Core gives for rejecting a transaction with this error
+    pub fn reject_reason(&self) -> String {
+        let reason = match self {
+            Self::NoInputs => "bad-txns-vin-empty",
+            Self::NoOutputs => "bad-txns-vout-empty",
+            Self::Oversize(..) => "bad-txns-oversize",
+            Self::OutputValueOutOfRange(_) => "bad-txns-vout-toolarge",
+            Self::OutputTotalOutOfRange => "bad-txns-txouttotal-toolarge",
+            Self::DuplicateInput(_) => "bad-txns-inputs-duplicate",
+            Self::CoinbaseLength(_) => "bad-cb-length",
+            Self::NullPrevout(_) => "bad-txns-prevout-null",
+            Self::Coinbase => "coinbase",
+            Self::MissingInput(_) => "bad-txns-inputs-missingorspent",
+            Self::PrematureCoinbaseSpend(..) => "bad-txns-premature-spend-of-coinbase",
+            Self::InputValuesOutOfRange => "bad-txns-inputvalues-outofrange",
+            Self::InputsBelowOutputs(..) => "bad-txns-in-belowout",
+            Self::NonFinal => "bad-txns-nonfinal",
+            Self::SequenceLocks => "non-BIP68-final",
+            Self::BadPrevBlock(_) => "bad-prevblk",
+            Self::BadBlockLength => "bad-blk-length",
+            Self::BadBlockWeight(..) => "bad-blk-weight",
+            Self::BadBlockSigops(..) => "bad-blk-sigops",
+            Self::BadVersion(version) => return format!("bad-version({version:#010x})"),
+            Self::BadCoinbaseHeight(_) => "bad-cb-height",
+            Self::BadCoinbaseAmount(..) => "bad-cb-amount",
+            Self::Bip30(_) => "bad-txns-BIP30",
+            Self::UnexpectedWitness => "unexpected-witness",
+            Self::ScriptFailed(_, error) => {
+                return format!("mandatory-script-verify-flag-failed ({error})")
+            }
+        };
+
+        reason.to_string()
+    }
+}
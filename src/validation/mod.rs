@@ -0,0 +1,3 @@
+pub mod chainstate;
+pub mod errors;
+pub mod tx;
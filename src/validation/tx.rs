@@ -0,0 +1,397 @@
+use {
+    super::errors::ValidationError,
+    crate::{
+        block::body::MAX_BLOCK_WEIGHT,
+        script::interpreter::verify_input,
+        transaction::tx::{
+            Transaction, TxOut, LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG,
+            SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG, WITNESS_SCALE_FACTOR,
+        },
+        utxo::set::{Coin, UtxoView},
+    },
+    anyhow::{bail, Result},
+    std::collections::HashSet,
+};
+
+/// Satoshis in a bitcoin
+pub const COIN: u64 = 100_000_000;
+
+/// Most satoshis that can ever exist
+pub const MAX_MONEY: u64 = 21_000_000 * COIN;
+
+/// Seconds each unit of a time based relative lock time stands for, as a power of 2
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// Check the rules a transaction must follow regardless of the chain it is in
+pub fn check_transaction(tx: &Transaction) -> Result<()> {
+    if tx.inputs.is_empty() {
+        bail!(ValidationError::NoInputs);
+    }
+    if tx.outputs.is_empty() {
+        bail!(ValidationError::NoOutputs);
+    }
+    let weight = tx.serialise_without_witness().len() * WITNESS_SCALE_FACTOR;
+    if weight > MAX_BLOCK_WEIGHT {
+        bail!(ValidationError::Oversize(weight, MAX_BLOCK_WEIGHT));
+    }
+
+    let mut total = 0;
+    for output in &tx.outputs {
+        if output.value > MAX_MONEY {
+            bail!(ValidationError::OutputValueOutOfRange(output.value));
+        }
+        total += output.value;
+        if total > MAX_MONEY {
+            bail!(ValidationError::OutputTotalOutOfRange);
+        }
+    }
+
+    let mut spent = HashSet::new();
+    for input in &tx.inputs {
+        if !spent.insert(&input.previous_output) {
+            bail!(ValidationError::DuplicateInput(
+                input.previous_output.to_string()
+            ));
+        }
+    }
+
+    if tx.is_coinbase() {
+        let len = tx.inputs[0].script_sig.len();
+        if !(2..=100).contains(&len) {
+            bail!(ValidationError::CoinbaseLength(len));
+        }
+    } else if let Some(index) = tx
+        .inputs
+        .iter()
+        .position(|input| input.previous_output.is_null())
+    {
+        bail!(ValidationError::NullPrevout(index));
+    }
+
+    Ok(())
+}
+
+/// Look up the coins `tx` spends in `view`, checking they can be spent by a transaction in a
+/// block at `height`
+///
+/// Returns the coins, in the order of the inputs, and the fee of the transaction.
+pub fn check_tx_inputs(
+    tx: &Transaction,
+    view: &impl UtxoView,
+    height: u32,
+) -> Result<(Vec<Coin>, u64)> {
+    let mut coins = vec![];
+    let mut input_value = 0;
+    for input in &tx.inputs {
+        let outpoint = &input.previous_output;
+        let Some(coin) = view.get_coin(outpoint)? else {
+            bail!(ValidationError::MissingInput(outpoint.to_string()));
+        };
+        if !coin.is_mature(height) {
+            bail!(ValidationError::PrematureCoinbaseSpend(
+                outpoint.to_string(),
+                height.saturating_sub(coin.height)
+            ));
+        }
+        input_value += coin.txout.value;
+        if coin.txout.value > MAX_MONEY || input_value > MAX_MONEY {
+            bail!(ValidationError::InputValuesOutOfRange);
+        }
+        coins.push(coin);
+    }
+
+    let output_value = tx.output_value();
+    if input_value < output_value {
+        bail!(ValidationError::InputsBelowOutputs(
+            input_value,
+            output_value
+        ));
+    }
+
+    Ok((coins, input_value - output_value))
+}
+
+/// If the lock time of `tx` allows it in a block at `height` whose lock time cutoff is `time`
+///
+/// Since BIP113 the cutoff is the median time past of the previous block.
+pub fn is_final(tx: &Transaction, height: u32, time: u32) -> bool {
+    if tx.lock_time == 0 {
+        return true;
+    }
+    let cutoff = match tx.lock_time < LOCKTIME_THRESHOLD {
+        true => height,
+        false => time,
+    };
+
+    tx.lock_time < cutoff
+        || tx
+            .inputs
+            .iter()
+            .all(|input| input.sequence == SEQUENCE_FINAL)
+}
+
+/// Check the relative lock times of the inputs of `tx`, spending `coins`, as specified by BIP68
+///
+/// The transaction is in a block at `height`, and `median_time_past` gives the median time past
+/// of the block at each height below it.
+pub fn check_sequence_locks(
+    tx: &Transaction,
+    coins: &[Coin],
+    height: u32,
+    median_time_past: impl Fn(u32) -> u32,
+) -> Result<()> {
+    // relative lock times only apply from version 2
+    if (tx.version as u32) < 2 {
+        return Ok(());
+    }
+
+    // the last height and time at which the transaction is still invalid
+    let (mut min_height, mut min_time) = (-1_i64, -1_i64);
+    for (input, coin) in tx.inputs.iter().zip(coins) {
+        if input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            continue;
+        }
+        let value = (input.sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+        if input.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            // time locks start from the median time past of the block before the coin
+            let coin_time = median_time_past(coin.height.saturating_sub(1)) as i64;
+            min_time = min_time.max(coin_time + (value << SEQUENCE_LOCKTIME_GRANULARITY) - 1);
+        } else {
+            min_height = min_height.max(coin.height as i64 + value - 1);
+        }
+    }
+
+    let tip_time = median_time_past(height.saturating_sub(1)) as i64;
+    if min_height >= height as i64 || min_time >= tip_time {
+        bail!(ValidationError::SequenceLocks);
+    }
+
+    Ok(())
+}
+
+/// Verify the scripts of every input of `tx`, spending `coins`, under `flags`
+pub fn verify_scripts(tx: &Transaction, coins: &[Coin], flags: u32) -> Result<()> {
+    let spent_outputs = coins
+        .iter()
+        .map(|coin| coin.txout.clone())
+        .collect::<Vec<TxOut>>();
+    for index in 0..tx.inputs.len() {
+        if let Err(e) = verify_input(tx, index, &spent_outputs, flags) {
+            bail!(ValidationError::ScriptFailed(index, e.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate `tx` for a block at `height` against the coins of `view`, under the script `flags`
+///
+/// `median_time_past` gives the median time past of the block at each height below `height`.
+/// Coinbase transactions are only valid as part of a block, so they are rejected. Returns the fee
+/// of the transaction.
+pub fn validate_transaction(
+    tx: &Transaction,
+    view: &impl UtxoView,
+    height: u32,
+    median_time_past: impl Fn(u32) -> u32,
+    flags: u32,
+) -> Result<u64> {
+    check_transaction(tx)?;
+    if tx.is_coinbase() {
+        bail!(ValidationError::Coinbase);
+    }
+    if !is_final(tx, height, median_time_past(height.saturating_sub(1))) {
+        bail!(ValidationError::NonFinal);
+    }
+
+    let (coins, fee) = check_tx_inputs(tx, view, height)?;
+    check_sequence_locks(tx, &coins, height, median_time_past)?;
+    verify_scripts(tx, &coins, flags)?;
+
+    Ok(fee)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            script::{interpreter::MANDATORY_SCRIPT_VERIFY_FLAGS, raw::Script},
+            secp256k1::keys::PrivateKey,
+            transaction::tx::{OutPoint, TxIn},
+        },
+        std::collections::HashMap,
+    };
+
+    fn reason(result: Result<impl std::fmt::Debug>) -> String {
+        result
+            .unwrap_err()
+            .downcast::<ValidationError>()
+            .unwrap()
+            .reject_reason()
+    }
+
+    fn key() -> PrivateKey {
+        PrivateKey::new("8b", 16).unwrap()
+    }
+
+    /// View holding a P2PKH coin of `key` at height 1, and a spend of it paying `value`
+    fn spend(value: u64, is_coinbase: bool) -> (HashMap<OutPoint, Coin>, Transaction) {
+        let script_pubkey = Script::p2pkh(&key().point().hash160(true));
+        let outpoint = OutPoint::new([1; 32], 0);
+        let coin = Coin {
+            txout: TxOut::new(50_000, script_pubkey.clone()),
+            height: 1,
+            is_coinbase,
+        };
+
+        let mut tx = Transaction::new(2, 0);
+        tx.inputs.push(TxIn::new(outpoint));
+        tx.outputs.push(TxOut::new(value, Script::op_return(b"")));
+        let z = tx.legacy_sighash(0, &script_pubkey, 1).unwrap();
+        let mut sig = key().sign(&hex::encode(z)).unwrap().der();
+        sig.push(1);
+        tx.inputs[0].script_sig = Script::new()
+            .push_slice(&sig)
+            .push_slice(&key().point().sec(true));
+
+        (HashMap::from([(outpoint, coin)]), tx)
+    }
+
+    fn mtp(height: u32) -> u32 {
+        1_600_000_000 + height * 600
+    }
+
+    #[test]
+    fn context_free_checks() {
+        let (_, tx) = spend(1000, false);
+        check_transaction(&tx).unwrap();
+
+        let mut bad = tx.clone();
+        bad.inputs.clear();
+        assert_eq!(reason(check_transaction(&bad)), "bad-txns-vin-empty");
+
+        let mut bad = tx.clone();
+        bad.outputs.clear();
+        assert_eq!(reason(check_transaction(&bad)), "bad-txns-vout-empty");
+
+        let mut bad = tx.clone();
+        bad.outputs[0].value = MAX_MONEY + 1;
+        assert_eq!(reason(check_transaction(&bad)), "bad-txns-vout-toolarge");
+
+        let mut bad = tx.clone();
+        bad.outputs[0].value = MAX_MONEY;
+        bad.outputs.push(TxOut::new(1, Script::new()));
+        assert_eq!(
+            reason(check_transaction(&bad)),
+            "bad-txns-txouttotal-toolarge"
+        );
+
+        let mut bad = tx.clone();
+        bad.inputs.push(bad.inputs[0].clone());
+        assert_eq!(reason(check_transaction(&bad)), "bad-txns-inputs-duplicate");
+
+        let mut bad = tx.clone();
+        bad.inputs.push(TxIn::new(OutPoint::null()));
+        assert_eq!(reason(check_transaction(&bad)), "bad-txns-prevout-null");
+
+        let mut coinbase = Transaction::new(1, 0);
+        coinbase.inputs.push(TxIn::new(OutPoint::null()));
+        coinbase.outputs.push(TxOut::new(1, Script::new()));
+        assert_eq!(reason(check_transaction(&coinbase)), "bad-cb-length");
+        coinbase.inputs[0].script_sig = Script::new().push_int(1).push_int(100);
+        check_transaction(&coinbase).unwrap();
+    }
+
+    #[test]
+    fn contextual_checks() -> Result<()> {
+        let (view, tx) = spend(40_000, false);
+        let fee = validate_transaction(&tx, &view, 200, mtp, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+        assert_eq!(fee, 10_000);
+
+        let empty = HashMap::<OutPoint, Coin>::new();
+        assert_eq!(
+            reason(validate_transaction(&tx, &empty, 200, mtp, 0)),
+            "bad-txns-inputs-missingorspent"
+        );
+
+        let (view, tx) = spend(40_000, true);
+        assert_eq!(
+            reason(check_tx_inputs(&tx, &view, 100)),
+            "bad-txns-premature-spend-of-coinbase"
+        );
+        check_tx_inputs(&tx, &view, 101)?;
+
+        let (view, tx) = spend(60_000, false);
+        assert_eq!(
+            reason(check_tx_inputs(&tx, &view, 200)),
+            "bad-txns-in-belowout"
+        );
+
+        let (mut view, tx) = spend(40_000, false);
+        view.values_mut().next().unwrap().txout.value = MAX_MONEY + 1;
+        assert_eq!(
+            reason(check_tx_inputs(&tx, &view, 200)),
+            "bad-txns-inputvalues-outofrange"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_times() {
+        let (view, mut tx) = spend(40_000, false);
+        tx.lock_time = 200;
+        tx.inputs[0].sequence = 0;
+        assert!(!is_final(&tx, 200, mtp(199)));
+        assert!(is_final(&tx, 201, mtp(200)));
+        assert_eq!(
+            reason(validate_transaction(&tx, &view, 200, mtp, 0)),
+            "bad-txns-nonfinal"
+        );
+
+        tx.lock_time = mtp(199);
+        assert!(!is_final(&tx, 200, mtp(199)));
+        assert!(is_final(&tx, 201, mtp(200)));
+
+        // a final sequence disables the lock time
+        tx.inputs[0].sequence = SEQUENCE_FINAL;
+        assert!(is_final(&tx, 0, 0));
+    }
+
+    #[test]
+    fn sequence_locks() {
+        let (view, mut tx) = spend(40_000, false);
+        let coins = vec![view.values().next().unwrap().clone()];
+
+        // the coin at height 1 needs 10 confirmations
+        tx.inputs[0].sequence = 10;
+        assert!(check_sequence_locks(&tx, &coins, 10, mtp).is_err());
+        check_sequence_locks(&tx, &coins, 11, mtp).unwrap();
+        assert_eq!(
+            reason(validate_transaction(&tx, &view, 10, mtp, 0)),
+            "non-BIP68-final"
+        );
+
+        // 2 units of 512 seconds after the median time past of the block before the coin
+        tx.inputs[0].sequence = SEQUENCE_LOCKTIME_TYPE_FLAG | 2;
+        assert!(check_sequence_locks(&tx, &coins, 2, mtp).is_err());
+        check_sequence_locks(&tx, &coins, 3, mtp).unwrap();
+
+        tx.inputs[0].sequence = SEQUENCE_LOCKTIME_DISABLE_FLAG | 10;
+        check_sequence_locks(&tx, &coins, 2, mtp).unwrap();
+        tx.version = 1;
+        tx.inputs[0].sequence = 10;
+        check_sequence_locks(&tx, &coins, 2, mtp).unwrap();
+    }
+
+    #[test]
+    fn script_failures() {
+        let (view, mut tx) = spend(40_000, false);
+        tx.outputs[0].value = 39_000;
+        assert_eq!(
+            reason(validate_transaction(&tx, &view, 200, mtp, MANDATORY_SCRIPT_VERIFY_FLAGS)),
+            "mandatory-script-verify-flag-failed (script evaluated without error but finished with a false or empty top stack element)"
+        );
+    }
+}
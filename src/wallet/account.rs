@@ -0,0 +1,1005 @@
+use {
+    super::{builder::TxBuilder, errors::WalletError},
+    crate::{
+        block::{
+            body::Block,
+            header::{BlockHash, BlockHeader},
+            merkle::MerkleBlock,
+        },
+        descriptor::output::Descriptor,
+        filter::basic::BlockFilter,
+        hd::bip32::ExtendedPrivateKey,
+        network::Network,
+        script::{address::Address, raw::Script},
+        transaction::tx::{OutPoint, Transaction, TxOut, Txid},
+        utils::encoding::{
+            compact_size, read_array, read_compact_size, read_var_bytes, reversed_hex, var_bytes,
+        },
+        utxo::set::COINBASE_MATURITY,
+    },
+    anyhow::{bail, Result},
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs::{self, File},
+        path::Path,
+    },
+};
+
+/// Unused addresses derived past the last used one by default
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Magic bytes starting a wallet file
+const WALLET_MAGIC: [u8; 4] = *b"wlt\0";
+
+/// Version of the wallet file format
+const WALLET_VERSION: u8 = 1;
+
+/// Height stored for unconfirmed transactions in a wallet file
+const UNCONFIRMED_HEIGHT: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+/// Chain of addresses of a wallet, handed out to payers or used for change
+pub enum Keychain {
+    External,
+    Internal,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Transaction paying to or spending from the wallet
+pub struct WalletTx {
+    pub tx: Transaction,
+    pub txid: Txid,
+    /// Height of the block confirming the transaction, none while it is unconfirmed
+    pub height: Option<u32>,
+    /// Position of the transaction in its block
+    pub position: u32,
+    pub label: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Unspent output paying an address of the wallet
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    pub keychain: Keychain,
+    /// Derivation index of the address
+    pub index: u32,
+    /// Height of the block that created the output, none while it is unconfirmed
+    pub height: Option<u32>,
+    pub is_coinbase: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// Value of the unspent outputs of a wallet
+pub struct Balance {
+    /// Outputs that can be spent in the next block
+    pub confirmed: u64,
+    /// Outputs of unconfirmed transactions
+    pub unconfirmed: u64,
+    /// Coinbase outputs that have not matured yet
+    pub immature: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+/// Derivation state of a keychain
+struct KeychainState {
+    /// Number of scripts derived and watched
+    derived: u32,
+    /// Number of addresses handed out
+    revealed: u32,
+    last_used: Option<u32>,
+}
+
+/// Wallet of the addresses of a descriptor, following its transactions through scanned blocks
+///
+/// Addresses are derived up to the gap limit past the last one used, so payments to any address
+/// handed out are found. Change goes to the internal descriptor, or the external one if there is
+/// none. Blocks must be applied in order, and a block replacing a scanned one rolls the history
+/// back to its parent.
+pub struct Wallet {
+    network: Network,
+    external: Descriptor,
+    internal: Option<Descriptor>,
+    gap_limit: u32,
+    keychains: BTreeMap<Keychain, KeychainState>,
+    /// Derived scripts with their keychain and index
+    scripts: HashMap<Script, (Keychain, u32)>,
+    transactions: HashMap<Txid, WalletTx>,
+    /// Hashes of the scanned blocks
+    blocks: BTreeMap<u32, BlockHash>,
+}
+
+impl Wallet {
+    /// Wallet on `network` receiving to `external` and taking change to `internal`
+    pub fn new(
+        network: Network,
+        external: Descriptor,
+        internal: Option<Descriptor>,
+        gap_limit: u32,
+    ) -> Result<Self> {
+        let mut keychains = BTreeMap::from([(Keychain::External, KeychainState::default())]);
+        if internal.is_some() {
+            keychains.insert(Keychain::Internal, KeychainState::default());
+        }
+
+        let mut wallet = Self {
+            network,
+            external,
+            internal,
+            gap_limit,
+            keychains,
+            scripts: HashMap::new(),
+            transactions: HashMap::new(),
+            blocks: BTreeMap::new(),
+        };
+        for keychain in wallet.keychains.keys().copied().collect::<Vec<_>>() {
+            wallet.derive_ahead(keychain)?;
+        }
+
+        Ok(wallet)
+    }
+
+    /// BIP84 wallet of the HD keychain of `master`, receiving to `m/84'/coin'/0'/0/*` and taking
+    /// change to `m/84'/coin'/0'/1/*`
+    pub fn from_master_key(
+        network: Network,
+        master: &ExtendedPrivateKey,
+        gap_limit: u32,
+    ) -> Result<Self> {
+        let coin = if network.is_mainnet() { 0 } else { 1 };
+        let external = format!("wpkh({master}/84'/{coin}'/0'/0/*)").parse()?;
+        let internal = format!("wpkh({master}/84'/{coin}'/0'/1/*)").parse()?;
+        Self::new(network, external, Some(internal), gap_limit)
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Descriptor of the addresses of `keychain`
+    pub fn descriptor(&self, keychain: Keychain) -> &Descriptor {
+        match (keychain, &self.internal) {
+            (Keychain::Internal, Some(internal)) => internal,
+            _ => &self.external,
+        }
+    }
+
+    /// Height of the last scanned block, zero if none was
+    pub fn height(&self) -> u32 {
+        self.blocks.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Hash of the scanned block at `height`
+    pub fn block_hash(&self, height: u32) -> Option<BlockHash> {
+        self.blocks.get(&height).copied()
+    }
+
+    /// Keychain change is paid to
+    fn change_keychain(&self) -> Keychain {
+        match self.internal {
+            Some(_) => Keychain::Internal,
+            None => Keychain::External,
+        }
+    }
+
+    /// Derive the scripts of `keychain` up to the gap limit past the last used or revealed one
+    fn derive_ahead(&mut self, keychain: Keychain) -> Result<()> {
+        let state = self.keychains[&keychain];
+        let mut count = state
+            .revealed
+            .max(state.last_used.map_or(0, |index| index + 1));
+        count += self.gap_limit;
+        if !self.descriptor(keychain).is_ranged() {
+            count = 1;
+        }
+
+        for index in state.derived..count {
+            let script = self.descriptor(keychain).script_pubkey(index)?;
+            self.scripts.insert(script, (keychain, index));
+        }
+        let state = self.keychains.get_mut(&keychain).unwrap();
+        state.derived = state.derived.max(count);
+
+        Ok(())
+    }
+
+    /// Address of `keychain` at `index`
+    pub fn peek_address(&self, keychain: Keychain, index: u32) -> Result<Address> {
+        self.descriptor(keychain).address(index, self.network)
+    }
+
+    /// Next unused address of `keychain`, which is handed out and not returned again
+    pub fn next_address(&mut self, keychain: Keychain) -> Result<Address> {
+        let keychain = match self.keychains.contains_key(&keychain) {
+            true => keychain,
+            false => Keychain::External,
+        };
+        let state = self.keychains[&keychain];
+        let index = match self.descriptor(keychain).is_ranged() {
+            true => state
+                .revealed
+                .max(state.last_used.map_or(0, |index| index + 1)),
+            false => 0,
+        };
+        let state = self.keychains.get_mut(&keychain).unwrap();
+        state.revealed = index + 1;
+        self.derive_ahead(keychain)?;
+
+        self.peek_address(keychain, index)
+    }
+
+    /// Keychain and index of the address of `script`, if the wallet derived it
+    pub fn script_index(&self, script: &Script) -> Option<(Keychain, u32)> {
+        self.scripts.get(script).copied()
+    }
+
+    /// Output `outpoint` if it pays the wallet, with the keychain and index of its address
+    fn owned_output(&self, outpoint: &OutPoint) -> Option<(&TxOut, Keychain, u32)> {
+        let txout = self
+            .transactions
+            .get(&outpoint.txid)?
+            .tx
+            .outputs
+            .get(outpoint.vout as usize)?;
+        let (keychain, index) = self.script_index(&txout.script_pubkey)?;
+        Some((txout, keychain, index))
+    }
+
+    /// If `tx` pays the wallet or spends an output that does
+    pub fn is_relevant(&self, tx: &Transaction) -> bool {
+        tx.outputs
+            .iter()
+            .any(|output| self.scripts.contains_key(&output.script_pubkey))
+            || tx
+                .inputs
+                .iter()
+                .any(|input| self.owned_output(&input.previous_output).is_some())
+    }
+
+    /// Remove transaction `txid` and every wallet transaction spending its outputs
+    fn remove_with_descendants(&mut self, txid: Txid) {
+        let mut pending = vec![txid];
+        while let Some(txid) = pending.pop() {
+            if self.transactions.remove(&txid).is_none() {
+                continue;
+            }
+            pending.extend(
+                self.transactions
+                    .values()
+                    .filter(|wtx| {
+                        wtx.tx
+                            .inputs
+                            .iter()
+                            .any(|input| input.previous_output.txid == txid)
+                    })
+                    .map(|wtx| wtx.txid),
+            );
+        }
+    }
+
+    /// Record `tx` if it is relevant, confirmed at `height` and `position`
+    ///
+    /// Addresses it pays count as used. Confirming a transaction removes the unconfirmed ones
+    /// double spending its inputs. Returns if the transaction was relevant.
+    fn insert_tx(&mut self, tx: &Transaction, height: Option<u32>, position: u32) -> Result<bool> {
+        if !self.is_relevant(tx) {
+            return Ok(false);
+        }
+
+        let used = tx
+            .outputs
+            .iter()
+            .filter_map(|output| self.script_index(&output.script_pubkey))
+            .collect::<Vec<_>>();
+        for (keychain, index) in used {
+            let state = self.keychains.get_mut(&keychain).unwrap();
+            state.last_used = state.last_used.max(Some(index));
+            self.derive_ahead(keychain)?;
+        }
+
+        let txid = tx.txid();
+        if height.is_some() {
+            let spent = tx
+                .inputs
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<HashSet<_>>();
+            let conflicts = self
+                .transactions
+                .values()
+                .filter(|wtx| wtx.height.is_none() && wtx.txid != txid)
+                .filter(|wtx| {
+                    wtx.tx
+                        .inputs
+                        .iter()
+                        .any(|input| spent.contains(&input.previous_output))
+                })
+                .map(|wtx| wtx.txid)
+                .collect::<Vec<_>>();
+            for conflict in conflicts {
+                self.remove_with_descendants(conflict);
+            }
+        }
+
+        let wtx = self.transactions.entry(txid).or_insert_with(|| WalletTx {
+            tx: tx.clone(),
+            txid,
+            height: None,
+            position: 0,
+            label: None,
+        });
+        wtx.height = height;
+        wtx.position = position;
+
+        Ok(true)
+    }
+
+    /// Record an unconfirmed transaction, such as one the wallet sent, if it is relevant
+    ///
+    /// Returns if the transaction was relevant and not already known.
+    pub fn add_unconfirmed(&mut self, tx: &Transaction) -> Result<bool> {
+        if self.transactions.contains_key(&tx.txid()) {
+            return Ok(false);
+        }
+        self.insert_tx(tx, None, 0)
+    }
+
+    /// Record the relevant transactions of a block at `height`, with their positions
+    fn connect<'a>(
+        &mut self,
+        header: &BlockHeader,
+        height: u32,
+        txs: impl IntoIterator<Item = (u32, &'a Transaction)>,
+    ) -> Result<()> {
+        let hash = header.hash();
+        match self.blocks.get(&height) {
+            Some(scanned) if *scanned == hash => return Ok(()),
+            Some(_) => self.rollback(height.saturating_sub(1)),
+            None => {}
+        }
+        if let Some(parent) = height
+            .checked_sub(1)
+            .and_then(|height| self.blocks.get(&height))
+        {
+            if *parent != header.prev_blockhash {
+                bail!(WalletError::UnconnectedBlock(height));
+            }
+        }
+
+        // a payment extending the look-ahead may reveal addresses paid earlier in the block, so
+        // it is scanned again until no new script is derived
+        let txs = txs.into_iter().collect::<Vec<_>>();
+        loop {
+            let derived = self.scripts.len();
+            for (position, tx) in &txs {
+                self.insert_tx(tx, Some(height), *position)?;
+            }
+            if self.scripts.len() == derived {
+                break;
+            }
+        }
+        self.blocks.insert(height, hash);
+
+        Ok(())
+    }
+
+    /// Scan `block` at `height` for transactions paying or spending from the wallet
+    ///
+    /// A block replacing a scanned one rolls back to its parent first. A block that does not
+    /// connect to the scanned block below it is rejected, the caller must roll back to the fork.
+    pub fn apply_block(&mut self, block: &Block, height: u32) -> Result<()> {
+        let txs = block.transactions.iter().enumerate();
+        self.connect(
+            &block.header,
+            height,
+            txs.map(|(position, tx)| (position as u32, tx)),
+        )
+    }
+
+    /// Scan the transactions a BIP37 merkle block at `height` matched, checking they are in it
+    pub fn apply_merkle_block(
+        &mut self,
+        merkle_block: &MerkleBlock,
+        txs: &[Transaction],
+        height: u32,
+    ) -> Result<()> {
+        let matches = merkle_block.extract_matches()?;
+        let mut positioned = vec![];
+        for tx in txs {
+            let txid = tx.txid();
+            let Some((_, position)) = matches.iter().find(|(matched, _)| *matched == txid) else {
+                bail!(WalletError::UnmatchedTransaction(reversed_hex(&txid)));
+            };
+            positioned.push((*position, tx));
+        }
+        positioned.sort_by_key(|(position, _)| *position);
+
+        self.connect(&merkle_block.header, height, positioned)
+    }
+
+    /// If the BIP158 filter of the block with `block_hash` may match a wallet transaction, so the
+    /// block should be downloaded and applied
+    pub fn matches_filter(&self, filter: &BlockFilter, block_hash: &BlockHash) -> Result<bool> {
+        filter.match_any(block_hash, self.scripts.keys())
+    }
+
+    /// Forget the blocks above `height`, disconnected by a reorg
+    ///
+    /// Their transactions become unconfirmed, except coinbases, which are removed with the
+    /// transactions spending them.
+    pub fn rollback(&mut self, height: u32) {
+        self.blocks.split_off(&(height + 1));
+
+        let disconnected = self
+            .transactions
+            .values()
+            .filter(|wtx| wtx.height.is_some_and(|confirmed| confirmed > height))
+            .map(|wtx| (wtx.txid, wtx.tx.is_coinbase()))
+            .collect::<Vec<_>>();
+        for (txid, is_coinbase) in disconnected {
+            if is_coinbase {
+                self.remove_with_descendants(txid);
+            } else if let Some(wtx) = self.transactions.get_mut(&txid) {
+                wtx.height = None;
+                wtx.position = 0;
+            }
+        }
+    }
+
+    pub fn transaction(&self, txid: &Txid) -> Option<&WalletTx> {
+        self.transactions.get(txid)
+    }
+
+    /// Wallet transactions in the order they confirmed, followed by the unconfirmed ones
+    pub fn history(&self) -> Vec<&WalletTx> {
+        let mut history = self.transactions.values().collect::<Vec<_>>();
+        history.sort_by_key(|wtx| (wtx.height.unwrap_or(u32::MAX), wtx.position, wtx.txid));
+        history
+    }
+
+    /// Value transaction `txid` paid to the wallet less the value it spent from it
+    pub fn net_amount(&self, txid: &Txid) -> Option<i64> {
+        let wtx = self.transactions.get(txid)?;
+        let received = wtx
+            .tx
+            .outputs
+            .iter()
+            .filter(|output| self.scripts.contains_key(&output.script_pubkey))
+            .map(|output| output.value as i64)
+            .sum::<i64>();
+        let sent = wtx
+            .tx
+            .inputs
+            .iter()
+            .filter_map(|input| self.owned_output(&input.previous_output))
+            .map(|(txout, ..)| txout.value as i64)
+            .sum::<i64>();
+
+        Some(received - sent)
+    }
+
+    /// Label transaction `txid`, replacing its label
+    pub fn set_label(&mut self, txid: &Txid, label: &str) -> Result<()> {
+        let Some(wtx) = self.transactions.get_mut(txid) else {
+            bail!(WalletError::UnknownTransaction(reversed_hex(txid)));
+        };
+        wtx.label = Some(label.to_string());
+
+        Ok(())
+    }
+
+    /// Outputs paying the wallet that no wallet transaction spends
+    pub fn utxos(&self) -> Vec<WalletUtxo> {
+        let spent = self
+            .transactions
+            .values()
+            .flat_map(|wtx| wtx.tx.inputs.iter().map(|input| input.previous_output))
+            .collect::<HashSet<_>>();
+
+        let mut utxos = vec![];
+        for wtx in self.transactions.values() {
+            for (vout, txout) in wtx.tx.outputs.iter().enumerate() {
+                let outpoint = OutPoint::new(wtx.txid, vout as u32);
+                let Some((keychain, index)) = self.script_index(&txout.script_pubkey) else {
+                    continue;
+                };
+                if !spent.contains(&outpoint) {
+                    utxos.push(WalletUtxo {
+                        outpoint,
+                        txout: txout.clone(),
+                        keychain,
+                        index,
+                        height: wtx.height,
+                        is_coinbase: wtx.tx.is_coinbase(),
+                    });
+                }
+            }
+        }
+        utxos.sort_by_key(|utxo| utxo.outpoint);
+
+        utxos
+    }
+
+    /// If `utxo` can be spent in the block after the last scanned one
+    fn is_spendable(&self, utxo: &WalletUtxo) -> bool {
+        utxo.height.is_some_and(|height| {
+            !utxo.is_coinbase || self.height() + 1 >= height + COINBASE_MATURITY
+        })
+    }
+
+    pub fn balance(&self) -> Balance {
+        let mut balance = Balance::default();
+        for utxo in self.utxos() {
+            let value = utxo.txout.value;
+            match utxo.height {
+                None => balance.unconfirmed += value,
+                Some(_) if self.is_spendable(&utxo) => balance.confirmed += value,
+                Some(_) => balance.immature += value,
+            }
+        }
+
+        balance
+    }
+
+    /// Signed transaction paying `recipients` from the confirmed outputs at `fee_rate` satoshis
+    /// per virtual byte, with change to the next change address
+    ///
+    /// The transaction is recorded as unconfirmed, so its inputs are not spent again.
+    pub fn create_transaction(
+        &mut self,
+        recipients: &[(Address, u64)],
+        fee_rate: u64,
+    ) -> Result<Transaction> {
+        let change = self.next_address(self.change_keychain())?;
+        let mut builder = TxBuilder::new(self.network)
+            .fee_rate(fee_rate)
+            .change_address(&change)
+            .current_height(self.height());
+        for (address, value) in recipients {
+            builder = builder.add_recipient(address, *value);
+        }
+        for utxo in self.utxos() {
+            if !self.is_spendable(&utxo) {
+                continue;
+            }
+            builder = builder.add_utxo(utxo.outpoint, utxo.txout);
+            for key in self.descriptor(utxo.keychain).keys() {
+                if let Some(key) = key.derive_private(utxo.index)? {
+                    builder = builder.add_key(key);
+                }
+            }
+        }
+
+        let (tx, _) = builder.build()?;
+        self.add_unconfirmed(&tx)?;
+
+        Ok(tx)
+    }
+
+    /// Write the wallet to `path`, replacing the file atomically
+    ///
+    /// Only the public form of the descriptors is written, so no secrets are stored.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut bytes = [WALLET_MAGIC.as_slice(), &[WALLET_VERSION]].concat();
+        bytes.extend(self.network.magic());
+        bytes.extend(var_bytes(self.external.to_public()?.to_string().as_bytes()));
+        match &self.internal {
+            Some(internal) => {
+                bytes.push(1);
+                bytes.extend(var_bytes(internal.to_public()?.to_string().as_bytes()));
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend(self.gap_limit.to_le_bytes());
+        for state in self.keychains.values() {
+            bytes.extend(state.revealed.to_le_bytes());
+            match state.last_used {
+                Some(index) => {
+                    bytes.push(1);
+                    bytes.extend(index.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        bytes.extend(compact_size(self.blocks.len() as u64));
+        for (height, hash) in &self.blocks {
+            bytes.extend(height.to_le_bytes());
+            bytes.extend(hash);
+        }
+
+        let history = self.history();
+        bytes.extend(compact_size(history.len() as u64));
+        for wtx in history {
+            bytes.extend(var_bytes(&wtx.tx.serialise()));
+            bytes.extend(wtx.height.unwrap_or(UNCONFIRMED_HEIGHT).to_le_bytes());
+            bytes.extend(wtx.position.to_le_bytes());
+            match &wtx.label {
+                Some(label) => {
+                    bytes.push(1);
+                    bytes.extend(var_bytes(label.as_bytes()));
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &bytes)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Wallet saved at `path` for `network`, whose descriptors must be those given or their
+    /// public forms
+    pub fn load(
+        path: impl AsRef<Path>,
+        network: Network,
+        external: Descriptor,
+        internal: Option<Descriptor>,
+    ) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let reader = &mut bytes.as_slice();
+        if read_array::<4>(reader)? != WALLET_MAGIC {
+            bail!(WalletError::StoreMismatch);
+        }
+        let version = read_array::<1>(reader)?[0];
+        if version != WALLET_VERSION {
+            bail!(WalletError::UnsupportedVersion(version));
+        }
+        if read_array::<4>(reader)? != network.magic() {
+            bail!(WalletError::StoreMismatch);
+        }
+        let stored_external = String::from_utf8(read_var_bytes(reader)?)?;
+        let stored_internal = match read_array::<1>(reader)?[0] {
+            0 => None,
+            _ => Some(String::from_utf8(read_var_bytes(reader)?)?),
+        };
+        if stored_external != external.to_public()?.to_string()
+            || stored_internal
+                != internal
+                    .as_ref()
+                    .map(|internal| Ok::<_, anyhow::Error>(internal.to_public()?.to_string()))
+                    .transpose()?
+        {
+            bail!(WalletError::StoreMismatch);
+        }
+
+        let gap_limit = u32::from_le_bytes(read_array(reader)?);
+        let mut wallet = Self::new(network, external, internal, gap_limit)?;
+        for keychain in wallet.keychains.keys().copied().collect::<Vec<_>>() {
+            let revealed = u32::from_le_bytes(read_array(reader)?);
+            let last_used = match read_array::<1>(reader)?[0] {
+                0 => None,
+                _ => Some(u32::from_le_bytes(read_array(reader)?)),
+            };
+            let state = wallet.keychains.get_mut(&keychain).unwrap();
+            state.revealed = revealed;
+            state.last_used = last_used;
+            wallet.derive_ahead(keychain)?;
+        }
+
+        for _ in 0..read_compact_size(reader)? {
+            let height = u32::from_le_bytes(read_array(reader)?);
+            wallet.blocks.insert(height, read_array(reader)?);
+        }
+
+        // restored without checking relevance again, as a transaction spending an unconfirmed
+        // parent stored after it would not look relevant
+        for _ in 0..read_compact_size(reader)? {
+            let tx = Transaction::from_bytes(&read_var_bytes(reader)?)?;
+            let height = u32::from_le_bytes(read_array(reader)?);
+            let position = u32::from_le_bytes(read_array(reader)?);
+            let label = match read_array::<1>(reader)?[0] {
+                0 => None,
+                _ => Some(String::from_utf8(read_var_bytes(reader)?)?),
+            };
+            let txid = tx.txid();
+            let wtx = WalletTx {
+                tx,
+                txid,
+                height: (height != UNCONFIRMED_HEIGHT).then_some(height),
+                position,
+                label,
+            };
+            wallet.transactions.insert(txid, wtx);
+        }
+
+        Ok(wallet)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            chain::headers::HeaderChain,
+            hd::bip39::Mnemonic,
+            mempool::pool::{Mempool, MempoolLimits},
+            mining::assembler::{generate, mine, AssemblerOptions, BlockTemplate},
+            transaction::tx::TxIn,
+            utxo::{set::UtxoSet, store::MemoryStore},
+            validation::{chainstate::ChainState, tx::COIN},
+        },
+    };
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
+
+    fn wallet() -> Wallet {
+        let master = MNEMONIC
+            .parse::<Mnemonic>()
+            .unwrap()
+            .to_master_key(Network::Regtest, "")
+            .unwrap();
+        Wallet::from_master_key(Network::Regtest, &master, 5).unwrap()
+    }
+
+    fn regtest() -> ChainState<MemoryStore> {
+        let chain = HeaderChain::new(Network::Regtest.params());
+        ChainState::new(chain, UtxoSet::new(MemoryStore::new()).unwrap())
+    }
+
+    /// Mine `count` blocks paying `payout` and scan them with `wallet`
+    fn generate_to(
+        state: &mut ChainState<MemoryStore>,
+        mempool: &mut Mempool,
+        wallet: &mut Wallet,
+        payout: &Script,
+        count: u32,
+    ) -> Result<Vec<Block>> {
+        let blocks = generate(state, mempool, payout, count)?;
+        let start = state.height() + 1 - count;
+        for (height, (block, _)) in (start..).zip(&blocks) {
+            wallet.apply_block(block, height)?;
+        }
+        Ok(blocks.into_iter().map(|(block, _)| block).collect())
+    }
+
+    #[test]
+    fn derives_addresses_up_to_the_gap_limit() -> Result<()> {
+        let mut wallet = wallet();
+        // BIP84 test vector of the first receiving address, whose program is the same on regtest
+        let first = wallet.next_address(Keychain::External)?;
+        let vector = "tb1q6rz28mcfaxtmd6v789l9rrlrusdprr9pqcpvkl".parse::<Address>()?;
+        assert_eq!(first.payload(), vector.payload());
+        assert_eq!(
+            wallet.next_address(Keychain::External)?,
+            wallet.peek_address(Keychain::External, 1)?
+        );
+        assert_eq!(wallet.keychains[&Keychain::External].derived, 7);
+
+        // a payment to the last derived address extends the watched range
+        let last = wallet.peek_address(Keychain::External, 6)?.script_pubkey();
+        let mut tx = Transaction::new(2, 0);
+        tx.outputs.push(TxOut::new(COIN, last));
+        assert!(wallet.add_unconfirmed(&tx)?);
+        assert_eq!(wallet.keychains[&Keychain::External].derived, 12);
+        assert_eq!(
+            wallet.next_address(Keychain::External)?,
+            wallet.peek_address(Keychain::External, 7)?
+        );
+        assert_eq!(wallet.balance().unconfirmed, COIN);
+        Ok(())
+    }
+
+    #[test]
+    fn rescans_blocks_revealing_addresses() -> Result<()> {
+        let mut wallet = wallet();
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let other = Script::p2wpkh(&[9; 20]);
+        let (mut block, _) = generate(&mut state, &mut mempool, &other, 1)?.remove(0);
+
+        // the payment to index 8 comes before the one to index 4 bringing it into the look-ahead
+        for index in [8, 4] {
+            let mut tx = Transaction::new(2, 0);
+            tx.inputs
+                .push(TxIn::new(OutPoint::new([index as u8; 32], 0)));
+            let script = wallet
+                .peek_address(Keychain::External, index)?
+                .script_pubkey();
+            tx.outputs.push(TxOut::new(COIN, script));
+            block.transactions.push(tx);
+        }
+        wallet.apply_block(&block, 1)?;
+
+        assert_eq!(wallet.history().len(), 2);
+        assert_eq!(wallet.keychains[&Keychain::External].last_used, Some(8));
+        assert_eq!(wallet.keychains[&Keychain::External].derived, 14);
+        assert_eq!(wallet.balance().confirmed, 2 * COIN);
+        Ok(())
+    }
+
+    #[test]
+    fn tracks_balances_and_spends() -> Result<()> {
+        let mut wallet = wallet();
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let payout = wallet.next_address(Keychain::External)?.script_pubkey();
+        generate_to(&mut state, &mut mempool, &mut wallet, &payout, 101)?;
+
+        let balance = wallet.balance();
+        // the coinbases of the first two blocks can be spent in the next one
+        assert_eq!(balance.confirmed, 100 * COIN);
+        assert_eq!(balance.immature, 99 * 50 * COIN);
+        assert_eq!(wallet.history().len(), 101);
+
+        let recipient = Address::p2tr(&[2; 32], Network::Regtest);
+        let tx = wallet.create_transaction(&[(recipient, 10 * COIN)], 2)?;
+        let txid = tx.txid();
+        let fee = -wallet.net_amount(&txid).unwrap() as u64 - 10 * COIN;
+        wallet.set_label(&txid, "rent")?;
+        assert_eq!(wallet.balance().unconfirmed, 40 * COIN - fee);
+        assert_eq!(wallet.balance().confirmed, 50 * COIN);
+
+        let chain = state.chain();
+        mempool.accept(
+            tx,
+            state.utxos(),
+            state.height() + 1,
+            |height| chain.median_time_past_at(height).unwrap(),
+            0,
+        )?;
+        let other = Script::p2wpkh(&[9; 20]);
+        generate_to(&mut state, &mut mempool, &mut wallet, &other, 1)?;
+
+        let wtx = wallet.transaction(&txid).unwrap();
+        assert_eq!(wtx.height, Some(102));
+        assert_eq!(wtx.label.as_deref(), Some("rent"));
+        let balance = wallet.balance();
+        assert_eq!(balance.confirmed, 100 * COIN + 40 * COIN - fee);
+        assert_eq!(balance.unconfirmed, 0);
+        assert_eq!(wallet.history().last().unwrap().txid, txid);
+        Ok(())
+    }
+
+    #[test]
+    fn rolls_back_reorged_blocks() -> Result<()> {
+        let mut wallet = wallet();
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let payout = wallet.next_address(Keychain::External)?.script_pubkey();
+        let blocks = generate_to(&mut state, &mut mempool, &mut wallet, &payout, 3)?;
+        assert_eq!(wallet.balance().immature, 150 * COIN);
+
+        // a competing block at height 3 replaces the wallet's coinbase
+        let mut fork = regtest();
+        for block in &blocks[..2] {
+            fork.connect_block(block, block.header.time)?;
+        }
+        let other = Script::p2wpkh(&[9; 20]);
+        let template = BlockTemplate::new(&fork, &mempool, &other, 0, &AssemblerOptions::default());
+        let block = mine(template);
+        wallet.apply_block(&block, 3)?;
+        assert_eq!(wallet.height(), 3);
+        assert_eq!(wallet.block_hash(3), Some(block.hash()));
+        assert_eq!(wallet.balance().immature, 100 * COIN);
+        assert_eq!(wallet.history().len(), 2);
+
+        // a block that does not connect must wait for the caller to roll back
+        assert_eq!(
+            wallet
+                .apply_block(&blocks[2], 4)
+                .unwrap_err()
+                .downcast::<WalletError>()?,
+            WalletError::UnconnectedBlock(4)
+        );
+        wallet.rollback(1);
+        assert_eq!(wallet.history().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn scans_merkle_blocks_and_filters() -> Result<()> {
+        let mut wallet = wallet();
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let payout = wallet.next_address(Keychain::External)?.script_pubkey();
+        let (block, _) = generate(&mut state, &mut mempool, &payout, 1)?.remove(0);
+
+        let filter = BlockFilter::from_block(&block, |_| Ok(Script::new()))?;
+        assert!(wallet.matches_filter(&filter, &block.hash())?);
+
+        let coinbase = block.transactions[0].clone();
+        let merkle_block = MerkleBlock::from_block(&block, |txid| *txid == coinbase.txid());
+        let mut unrelated = Transaction::new(2, 0);
+        unrelated.outputs.push(TxOut::new(1, Script::new()));
+        assert_eq!(
+            wallet
+                .apply_merkle_block(&merkle_block, &[unrelated.clone()], 1)
+                .unwrap_err()
+                .downcast::<WalletError>()?,
+            WalletError::UnmatchedTransaction(reversed_hex(&unrelated.txid()))
+        );
+        wallet.apply_merkle_block(&merkle_block, &[coinbase], 1)?;
+        assert_eq!(wallet.balance().immature, 50 * COIN);
+        Ok(())
+    }
+
+    #[test]
+    fn saves_and_loads() -> Result<()> {
+        let mut wallet = wallet();
+        let mut state = regtest();
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let payout = wallet.next_address(Keychain::External)?.script_pubkey();
+        generate_to(&mut state, &mut mempool, &mut wallet, &payout, 101)?;
+        let recipient = Address::p2tr(&[2; 32], Network::Regtest);
+        let tx = wallet.create_transaction(&[(recipient, COIN)], 1)?;
+        wallet.set_label(&tx.txid(), "coffee")?;
+
+        let path = std::env::temp_dir().join(format!("wallet-{}.dat", std::process::id()));
+        wallet.save(&path)?;
+        let bytes = fs::read(&path)?;
+        assert!(!String::from_utf8_lossy(&bytes).contains("tprv"));
+
+        let descriptor = |keychain| wallet.descriptor(keychain).clone();
+        let external = descriptor(Keychain::External);
+        let internal = descriptor(Keychain::Internal);
+        let loaded = Wallet::load(&path, Network::Regtest, external.clone(), Some(internal))?;
+        assert_eq!(loaded.balance(), wallet.balance());
+        assert_eq!(loaded.history(), wallet.history());
+        assert_eq!(loaded.height(), 101);
+        assert_eq!(loaded.keychains[&Keychain::Internal].revealed, 1);
+        assert_eq!(
+            loaded.transaction(&tx.txid()).unwrap().label.as_deref(),
+            Some("coffee")
+        );
+
+        let mismatch = Wallet::load(&path, Network::Regtest, external, None);
+        assert_eq!(
+            mismatch.err().unwrap().downcast::<WalletError>()?,
+            WalletError::StoreMismatch
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn loads_unconfirmed_chains_in_any_order() -> Result<()> {
+        let mut wallet = wallet();
+        let address = wallet.peek_address(Keychain::External, 3)?.script_pubkey();
+        let recipient = Script::p2wpkh(&[9; 20]);
+
+        // a parent paying the wallet, and a child spending all of it without change, whose txid
+        // sorts before the parent's
+        let (parent, child) = (0..)
+            .map(|nonce| {
+                let mut parent = Transaction::new(2, 0);
+                parent.inputs.push(TxIn::new(OutPoint::new([nonce; 32], 0)));
+                parent.outputs.push(TxOut::new(COIN, address.clone()));
+                let mut child = Transaction::new(2, 0);
+                child
+                    .inputs
+                    .push(TxIn::new(OutPoint::new(parent.txid(), 0)));
+                child
+                    .outputs
+                    .push(TxOut::new(COIN - 1_000, recipient.clone()));
+                (parent, child)
+            })
+            .find(|(parent, child)| child.txid() < parent.txid())
+            .unwrap();
+        assert!(wallet.add_unconfirmed(&parent)?);
+        assert!(wallet.add_unconfirmed(&child)?);
+        assert_eq!(wallet.history()[0].txid, child.txid());
+
+        let path = std::env::temp_dir().join(format!("wallet-chain-{}.dat", std::process::id()));
+        wallet.save(&path)?;
+        let descriptor = |keychain| wallet.descriptor(keychain).clone();
+        let loaded = Wallet::load(
+            &path,
+            Network::Regtest,
+            descriptor(Keychain::External),
+            Some(descriptor(Keychain::Internal)),
+        )?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded.history(), wallet.history());
+        assert_eq!(loaded.utxos(), []);
+        assert_eq!(loaded.keychains[&Keychain::External].last_used, Some(3));
+        Ok(())
+    }
+}
@@ -0,0 +1,597 @@
+use {
+    super::{
+        coinselection::{
+            dust_threshold, select_coins, Selection, SelectionParams, WeightedUtxo,
+            DUST_RELAY_TX_FEE,
+        },
+        errors::WalletError,
+    },
+    crate::{
+        network::Network,
+        script::{
+            address::{Address, Payload},
+            raw::Script,
+            taproot::tweak_private_key,
+        },
+        secp256k1::{keys::PrivateKey, signature::MAX_ECDSA_SIG_SIZE},
+        transaction::{
+            sighash::{SIGHASH_ALL, SIGHASH_DEFAULT},
+            tx::{
+                OutPoint, Transaction, TxIn, TxOut, MAX_BIP125_RBF_SEQUENCE,
+                SEQUENCE_ENABLE_LOCKTIME_NO_RBF, SEQUENCE_FINAL, WITNESS_SCALE_FACTOR,
+            },
+        },
+        utils::encoding::compact_size,
+    },
+    anyhow::{bail, Result},
+};
+
+/// Fee rate coins are assumed to be spent at later by default, in satoshis per virtual byte
+pub const DEFAULT_LONG_TERM_FEE_RATE: u64 = 10;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Output scripts the builder can sign for with a single key
+enum SpendKind {
+    P2pkh {
+        compressed: bool,
+    },
+    P2wpkh,
+    /// P2WPKH nested in P2SH
+    P2shP2wpkh,
+    /// Taproot key path of a key without a script tree, as specified by BIP86
+    P2tr,
+}
+
+/// Transaction builder paying recipients from a set of coins and signing with the keys spending
+/// them
+///
+/// Coins are chosen by coin selection at the fee rate, with change paid to the change address or
+/// the P2WPKH address of the first key.
+pub struct TxBuilder {
+    network: Network,
+    recipients: Vec<(Address, u64)>,
+    utxos: Vec<(OutPoint, TxOut)>,
+    keys: Vec<PrivateKey>,
+    /// Satoshis per virtual byte
+    fee_rate: u64,
+    long_term_fee_rate: u64,
+    change_address: Option<Address>,
+    rbf: bool,
+    lock_time: Option<u32>,
+    /// Height of the chain tip, locked to against fee sniping
+    current_height: Option<u32>,
+}
+
+impl SpendKind {
+    /// Kind of spending `script_pubkey` with `key`, if the key can
+    fn with_key(script_pubkey: &Script, key: &PrivateKey) -> Result<Option<Self>> {
+        let bytes = script_pubkey.as_bytes();
+        let hash = key.point().hash160(true);
+        let kind = if script_pubkey.is_p2pkh() {
+            if bytes[3..23] == hash {
+                Some(Self::P2pkh { compressed: true })
+            } else if bytes[3..23] == key.point().hash160(false) {
+                Some(Self::P2pkh { compressed: false })
+            } else {
+                None
+            }
+        } else if script_pubkey.is_p2wpkh() {
+            (bytes[2..] == hash).then_some(Self::P2wpkh)
+        } else if script_pubkey.is_p2sh() {
+            (bytes[2..22] == Script::p2wpkh(&hash).script_hash()).then_some(Self::P2shP2wpkh)
+        } else if script_pubkey.is_p2tr() {
+            let output_key = tweak_private_key(key, None)?.point().x_only();
+            (bytes[2..] == output_key).then_some(Self::P2tr)
+        } else {
+            None
+        };
+
+        Ok(kind)
+    }
+
+    /// Kind of spending `script_pubkey` with some compressed key, assuming P2SH nests P2WPKH
+    fn of(script_pubkey: &Script) -> Option<Self> {
+        if script_pubkey.is_p2pkh() {
+            Some(Self::P2pkh { compressed: true })
+        } else if script_pubkey.is_p2wpkh() {
+            Some(Self::P2wpkh)
+        } else if script_pubkey.is_p2sh() {
+            Some(Self::P2shP2wpkh)
+        } else if script_pubkey.is_p2tr() {
+            Some(Self::P2tr)
+        } else {
+            None
+        }
+    }
+
+    fn is_segwit(&self) -> bool {
+        !matches!(self, Self::P2pkh { .. })
+    }
+
+    /// Largest weight of a signed input, the outpoint, script signature, sequence and witness
+    ///
+    /// In a `segwit` transaction, inputs without a witness still take an empty witness count.
+    fn input_weight(&self, segwit: bool) -> usize {
+        let (script_sig, witness) = match self {
+            Self::P2pkh { compressed } => {
+                let key = if *compressed { 33 } else { 65 };
+                (1 + MAX_ECDSA_SIG_SIZE + 1 + key, usize::from(segwit))
+            }
+            Self::P2wpkh => (0, 1 + 1 + MAX_ECDSA_SIG_SIZE + 1 + 33),
+            Self::P2shP2wpkh => (1 + 22, 1 + 1 + MAX_ECDSA_SIG_SIZE + 1 + 33),
+            Self::P2tr => (0, 1 + 1 + 64),
+        };
+        (32 + 4 + compact_size(script_sig as u64).len() + script_sig + 4) * WITNESS_SCALE_FACTOR
+            + witness
+    }
+
+    fn input_vsize(&self, segwit: bool) -> usize {
+        self.input_weight(segwit).div_ceil(WITNESS_SCALE_FACTOR)
+    }
+}
+
+/// Whether `address` can be paid on `network`, which may share the encoding of testnet addresses
+fn valid_on(address: &Address, network: Network) -> bool {
+    match (address.network(), address.payload()) {
+        (address_network, _) if address_network == network => true,
+        (Network::Testnet, Payload::WitnessProgram { .. }) => network == Network::Signet,
+        (Network::Testnet, _) => matches!(network, Network::Signet | Network::Regtest),
+        _ => false,
+    }
+}
+
+/// Key in `keys` spending `script_pubkey` and how it spends it
+fn find_key<'a>(
+    script_pubkey: &Script,
+    keys: &'a [PrivateKey],
+) -> Result<Option<(&'a PrivateKey, SpendKind)>> {
+    for key in keys {
+        if let Some(kind) = SpendKind::with_key(script_pubkey, key)? {
+            return Ok(Some((key, kind)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Random value below `n`
+fn random_below(n: u32) -> Result<u32> {
+    let mut bytes = [0; 4];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) % n)
+}
+
+/// Sign every input of `tx`, which spends `spent_outputs`, with the key in `keys` for its output
+/// script, using the sighash algorithm of the script type
+///
+/// ECDSA inputs sign with `SIGHASH_ALL` and taproot inputs with `SIGHASH_DEFAULT`.
+pub fn sign_inputs(
+    tx: &mut Transaction,
+    spent_outputs: &[TxOut],
+    keys: &[PrivateKey],
+) -> Result<()> {
+    for (index, spent) in spent_outputs.iter().enumerate() {
+        let Some((key, kind)) = find_key(&spent.script_pubkey, keys)? else {
+            bail!(WalletError::NoSigningKey(spent.script_pubkey.to_string()));
+        };
+
+        let ecdsa = |sighash: [u8; 32]| -> Result<Vec<u8>> {
+            let mut sig = key.sign(&hex::encode(sighash))?.der();
+            sig.push(SIGHASH_ALL);
+            Ok(sig)
+        };
+        let sec = key.point().sec(true);
+        let script_code = Script::p2pkh(&key.point().hash160(true));
+        match kind {
+            SpendKind::P2pkh { compressed } => {
+                let sighash = tx.legacy_sighash(index, &spent.script_pubkey, SIGHASH_ALL as u32)?;
+                tx.inputs[index].script_sig = Script::new()
+                    .push_slice(&ecdsa(sighash)?)
+                    .push_slice(&key.point().sec(compressed));
+            }
+            SpendKind::P2wpkh | SpendKind::P2shP2wpkh => {
+                let sighash =
+                    tx.segwit_v0_sighash(index, &script_code, spent.value, SIGHASH_ALL as u32)?;
+                if kind == SpendKind::P2shP2wpkh {
+                    let redeem_script = Script::p2wpkh(&key.point().hash160(true));
+                    tx.inputs[index].script_sig =
+                        Script::new().push_slice(redeem_script.as_bytes());
+                }
+                tx.inputs[index].witness = vec![ecdsa(sighash)?, sec];
+            }
+            SpendKind::P2tr => {
+                let sighash =
+                    tx.taproot_sighash(index, spent_outputs, SIGHASH_DEFAULT, None, None)?;
+                let mut aux_rand = [0; 32];
+                getrandom::getrandom(&mut aux_rand)?;
+                let sig = tweak_private_key(key, None)?.sign_schnorr(&sighash, &aux_rand)?;
+                tx.inputs[index].witness = vec![sig.to_bytes().to_vec()];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl TxBuilder {
+    /// Create a builder of transactions on `network`, paying 1 satoshi per virtual byte and
+    /// signalling replaceability
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            recipients: vec![],
+            utxos: vec![],
+            keys: vec![],
+            fee_rate: 1,
+            long_term_fee_rate: DEFAULT_LONG_TERM_FEE_RATE,
+            change_address: None,
+            rbf: true,
+            lock_time: None,
+            current_height: None,
+        }
+    }
+
+    /// Pay `value` satoshis to `address`
+    pub fn add_recipient(mut self, address: &Address, value: u64) -> Self {
+        self.recipients.push((address.clone(), value));
+        self
+    }
+
+    /// Add an output the transaction may spend
+    pub fn add_utxo(mut self, outpoint: OutPoint, output: TxOut) -> Self {
+        self.utxos.push((outpoint, output));
+        self
+    }
+
+    /// Add a key to sign with and to derive the change address from
+    pub fn add_key(mut self, key: PrivateKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Set the fee rate in satoshis per virtual byte
+    pub fn fee_rate(mut self, fee_rate: u64) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Set the fee rate in satoshis per virtual byte the coins are expected to be spent at later
+    pub fn long_term_fee_rate(mut self, fee_rate: u64) -> Self {
+        self.long_term_fee_rate = fee_rate;
+        self
+    }
+
+    pub fn change_address(mut self, address: &Address) -> Self {
+        self.change_address = Some(address.clone());
+        self
+    }
+
+    /// Signal replaceability as specified by BIP125, or not
+    pub fn enable_rbf(mut self, rbf: bool) -> Self {
+        self.rbf = rbf;
+        self
+    }
+
+    /// Set the lock time, overriding the one chosen against fee sniping
+    pub fn lock_time(mut self, lock_time: u32) -> Self {
+        self.lock_time = Some(lock_time);
+        self
+    }
+
+    /// Set the height of the chain tip, which the lock time is set to so the transaction cannot
+    /// be mined in a reorganisation of the tip, discouraging fee sniping
+    pub fn current_height(mut self, height: u32) -> Self {
+        self.current_height = Some(height);
+        self
+    }
+
+    /// Address change is paid to
+    fn change_script(&self) -> Result<Script> {
+        match (&self.change_address, self.keys.first()) {
+            (Some(address), _) => Ok(address.script_pubkey()),
+            (None, Some(key)) => Ok(Address::p2wpkh(key.point(), self.network).script_pubkey()),
+            (None, None) => bail!(WalletError::NoChangeAddress),
+        }
+    }
+
+    /// Lock time of the transaction, the tip height as Bitcoin Core sets it against fee sniping,
+    /// sometimes further back for the privacy of transactions that are slow to broadcast
+    fn choose_lock_time(&self) -> Result<u32> {
+        Ok(match (self.lock_time, self.current_height) {
+            (Some(lock_time), _) => lock_time,
+            (None, Some(height)) if random_below(10)? == 0 => {
+                height.saturating_sub(random_below(100)?)
+            }
+            (None, Some(height)) => height,
+            (None, None) => 0,
+        })
+    }
+
+    /// Select coins, add change and sign every input
+    ///
+    /// Returns the signed transaction and the selection funding it.
+    pub fn build(self) -> Result<(Transaction, Selection)> {
+        if self.recipients.is_empty() {
+            bail!(WalletError::NoRecipients);
+        }
+        let mut outputs = vec![];
+        for (address, value) in &self.recipients {
+            if !valid_on(address, self.network) {
+                bail!(WalletError::WrongNetwork(address.to_string()));
+            }
+            let output = TxOut::new(*value, address.script_pubkey());
+            let dust = dust_threshold(&output, DUST_RELAY_TX_FEE);
+            if *value < dust {
+                bail!(WalletError::DustOutput(*value, dust));
+            }
+            outputs.push(output);
+        }
+
+        // only coins the keys can sign for are candidates
+        let mut candidates = vec![];
+        for (outpoint, output) in &self.utxos {
+            if let Some((_, kind)) = find_key(&output.script_pubkey, &self.keys)? {
+                candidates.push((outpoint, output, kind));
+            }
+        }
+
+        let change_script = self.change_script()?;
+        let change = TxOut::new(0, change_script.clone());
+        let Some(change_kind) = SpendKind::of(&change_script) else {
+            bail!(WalletError::UnsupportedScript(change_script.to_string()));
+        };
+        // version, lock time and counts, assuming under 253 inputs
+        let base_size = 4
+            + 4
+            + 1
+            + compact_size(outputs.len() as u64 + 1).len()
+            + outputs
+                .iter()
+                .map(|output| output.serialise().len())
+                .sum::<usize>();
+        // the segwit marker and flag, and the empty witnesses of the other inputs, are only paid
+        // for when a selected input has a witness, so a segwit selection is redone with them
+        let select = |segwit: bool| {
+            let utxos = candidates
+                .iter()
+                .map(|(outpoint, output, kind)| WeightedUtxo {
+                    outpoint: **outpoint,
+                    output: (*output).clone(),
+                    input_vsize: kind.input_vsize(segwit),
+                })
+                .collect::<Vec<_>>();
+            let base_weight = base_size * WITNESS_SCALE_FACTOR + if segwit { 2 } else { 0 };
+            let params = SelectionParams {
+                target: outputs.iter().map(|output| output.value).sum(),
+                base_vsize: base_weight.div_ceil(WITNESS_SCALE_FACTOR),
+                fee_rate: self.fee_rate * 1000,
+                long_term_fee_rate: self.long_term_fee_rate * 1000,
+                change_output_vsize: change.serialise().len(),
+                change_spend_vsize: change_kind.input_vsize(false),
+                dust_threshold: dust_threshold(&change, DUST_RELAY_TX_FEE),
+            };
+            select_coins(&utxos, &params)
+        };
+        let is_segwit = |selection: &Selection| {
+            selection.inputs.iter().any(|utxo| {
+                SpendKind::of(&utxo.output.script_pubkey).is_some_and(|kind| kind.is_segwit())
+            })
+        };
+        let mut selection = select(false)?;
+        if is_segwit(&selection) {
+            selection = select(true)?;
+        }
+
+        // the change goes anywhere among the outputs so it cannot be told apart by position
+        if selection.change > 0 {
+            let position = random_below(outputs.len() as u32 + 1)? as usize;
+            outputs.insert(position, TxOut::new(selection.change, change_script));
+        }
+
+        let lock_time = self.choose_lock_time()?;
+        let sequence = match (self.rbf, lock_time) {
+            (true, _) => MAX_BIP125_RBF_SEQUENCE,
+            (false, 0) => SEQUENCE_FINAL,
+            (false, _) => SEQUENCE_ENABLE_LOCKTIME_NO_RBF,
+        };
+        let mut tx = Transaction::new(2, lock_time);
+        for utxo in &selection.inputs {
+            let mut input = TxIn::new(utxo.outpoint);
+            input.sequence = sequence;
+            tx.inputs.push(input);
+        }
+        tx.outputs = outputs;
+
+        let spent_outputs = selection
+            .inputs
+            .iter()
+            .map(|utxo| utxo.output.clone())
+            .collect::<Vec<_>>();
+        sign_inputs(&mut tx, &spent_outputs, &self.keys)?;
+
+        Ok((tx, selection))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            chain::headers::HeaderChain,
+            mempool::pool::{Mempool, MempoolLimits},
+            mining::assembler::generate,
+            script::interpreter::{verify_input, MANDATORY_SCRIPT_VERIFY_FLAGS},
+            utxo::{set::UtxoSet, store::MemoryStore},
+            validation::{chainstate::ChainState, tx::COIN},
+        },
+    };
+
+    fn key(secret: &str) -> PrivateKey {
+        PrivateKey::new(secret, 16).unwrap()
+    }
+
+    #[test]
+    fn signs_every_script_type() -> Result<()> {
+        let key = key("c0ffee");
+        let network = Network::Regtest;
+        let hash = key.point().hash160(true);
+        let scripts = [
+            Address::p2pkh(key.point(), true, network).script_pubkey(),
+            Address::p2pkh(key.point(), false, network).script_pubkey(),
+            Address::p2wpkh(key.point(), network).script_pubkey(),
+            Address::p2sh(&Script::p2wpkh(&hash), network).script_pubkey(),
+            Script::p2tr(&tweak_private_key(&key, None)?.point().x_only()),
+        ];
+
+        let recipient = Address::p2wpkh(self::key("beef").point(), network);
+        let mut builder = TxBuilder::new(network)
+            .add_recipient(&recipient, 5 * COIN - 10_000)
+            .add_key(key)
+            .fee_rate(5)
+            .long_term_fee_rate(1)
+            .enable_rbf(false);
+        for (i, script) in scripts.iter().enumerate() {
+            builder = builder.add_utxo(
+                OutPoint::new([7; 32], i as u32),
+                TxOut::new(COIN, script.clone()),
+            );
+        }
+        let (tx, selection) = builder.build()?;
+        assert_eq!(tx.inputs.len(), 5);
+        assert_eq!(tx.lock_time, 0);
+        assert!(tx
+            .inputs
+            .iter()
+            .all(|input| input.sequence == SEQUENCE_FINAL));
+        assert_eq!(tx.output_value() + selection.fee, 5 * COIN);
+
+        let spent_outputs = selection
+            .inputs
+            .iter()
+            .map(|utxo| utxo.output.clone())
+            .collect::<Vec<_>>();
+        for index in 0..tx.inputs.len() {
+            verify_input(&tx, index, &spent_outputs, MANDATORY_SCRIPT_VERIFY_FLAGS)?;
+        }
+
+        // the estimate covers the signed size, so the fee rate is at least the one asked for
+        assert!(selection.fee >= 5 * tx.vsize() as u64);
+        assert!(selection.fee <= 5 * (tx.vsize() as u64 + 5));
+        Ok(())
+    }
+
+    #[test]
+    fn sizes_segwit_from_the_selected_inputs() -> Result<()> {
+        let key = key("c0ffee");
+        let network = Network::Regtest;
+        let legacy = Address::p2pkh(key.point(), true, network).script_pubkey();
+        let segwit = Address::p2wpkh(key.point(), network).script_pubkey();
+        let recipient = Address::p2wpkh(self::key("beef").point(), network);
+        let builder = || {
+            TxBuilder::new(network)
+                .add_recipient(&recipient, COIN / 2)
+                .add_key(key.clone())
+                .fee_rate(100)
+                .add_utxo(OutPoint::new([7; 32], 0), TxOut::new(COIN, legacy.clone()))
+        };
+
+        // a witness coin worth less than its input fee is never selected, so adds no marker
+        let (_, alone) = builder().build()?;
+        let (tx, selection) = builder()
+            .add_utxo(OutPoint::new([7; 32], 1), TxOut::new(5000, segwit.clone()))
+            .build()?;
+        assert!(!tx.has_witness());
+        assert_eq!(selection.fee, alone.fee);
+
+        // with a witness input, the legacy one takes an empty witness count
+        let (tx, selection) = builder()
+            .add_recipient(&recipient, COIN)
+            .add_utxo(OutPoint::new([7; 32], 1), TxOut::new(COIN, segwit))
+            .build()?;
+        assert_eq!(tx.inputs.len(), 2);
+        assert!(tx.has_witness() && tx.inputs.iter().any(|input| input.witness.is_empty()));
+        assert!(selection.fee >= 100 * tx.vsize() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn builds_a_transaction_the_mempool_accepts() -> Result<()> {
+        let key = key("c0ffee");
+        let network = Network::Regtest;
+        let address = Address::p2wpkh(key.point(), network);
+        let chain = HeaderChain::new(network.params());
+        let mut state = ChainState::new(chain, UtxoSet::new(MemoryStore::new())?);
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let blocks = generate(&mut state, &mut mempool, &address.script_pubkey(), 101)?;
+
+        let recipient = Address::p2tr(&[2; 32], network);
+        let change = Address::p2wpkh(self::key("beef").point(), network);
+        let coinbase = &blocks[0].0.transactions[0];
+        let (tx, selection) = TxBuilder::new(network)
+            .add_recipient(&recipient, 20 * COIN)
+            .add_utxo(
+                OutPoint::new(coinbase.txid(), 0),
+                coinbase.outputs[0].clone(),
+            )
+            .add_key(key)
+            .fee_rate(2)
+            .change_address(&change)
+            .current_height(state.height())
+            .build()?;
+
+        assert!(tx.lock_time <= state.height() && tx.lock_time + 100 > state.height());
+        assert!(tx
+            .inputs
+            .iter()
+            .all(|input| input.sequence == MAX_BIP125_RBF_SEQUENCE));
+        assert_eq!(selection.change, 30 * COIN - selection.fee);
+        assert!(tx
+            .outputs
+            .iter()
+            .any(|output| output.script_pubkey == change.script_pubkey()
+                && output.value == selection.change));
+
+        let chain = state.chain();
+        mempool.accept(
+            tx,
+            state.utxos(),
+            state.height() + 1,
+            |height| chain.median_time_past_at(height).unwrap(),
+            0,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_recipients() {
+        let key = key("c0ffee");
+        let error = |builder: TxBuilder| {
+            builder
+                .add_key(key.clone())
+                .build()
+                .unwrap_err()
+                .downcast::<WalletError>()
+                .unwrap()
+        };
+        let mainnet = Address::p2wpkh(key.point(), Network::Mainnet);
+        let testnet = Address::p2pkh(key.point(), true, Network::Testnet);
+
+        assert_eq!(
+            error(TxBuilder::new(Network::Regtest)),
+            WalletError::NoRecipients
+        );
+        assert_eq!(
+            error(TxBuilder::new(Network::Regtest).add_recipient(&mainnet, COIN)),
+            WalletError::WrongNetwork(mainnet.to_string())
+        );
+        assert_eq!(
+            error(TxBuilder::new(Network::Regtest).add_recipient(&testnet, 500)),
+            WalletError::DustOutput(500, 546)
+        );
+        assert_eq!(
+            error(TxBuilder::new(Network::Regtest).add_recipient(&testnet, COIN)),
+            WalletError::InsufficientFunds(0, COIN + 44)
+        );
+    }
+}
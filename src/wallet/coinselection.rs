@@ -0,0 +1,568 @@
+use {
+    super::errors::WalletError,
+    crate::transaction::tx::{OutPoint, TxOut},
+    anyhow::{bail, Result},
+};
+
+/// Fee rate outputs must be worth spending at to not be dust, in satoshis per 1000 virtual bytes
+pub const DUST_RELAY_TX_FEE: u64 = 3000;
+
+/// Most branches the Branch and Bound search explores
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// Random subsets the knapsack solver tries
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Coin selection algorithm
+pub enum Algorithm {
+    BranchAndBound,
+    Knapsack,
+    SingleRandomDraw,
+    LargestFirst,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Unspent output the wallet can spend, with the virtual size of the input spending it
+pub struct WeightedUtxo {
+    pub outpoint: OutPoint,
+    pub output: TxOut,
+    /// Virtual size of the signed input, including its share of the witness
+    pub input_vsize: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Amounts and fee rates a selection is made for
+///
+/// Fee rates are in satoshis per 1000 virtual bytes.
+pub struct SelectionParams {
+    /// Value of the recipient outputs
+    pub target: u64,
+    /// Virtual size of the transaction without its inputs and change output
+    pub base_vsize: usize,
+    pub fee_rate: u64,
+    /// Fee rate the coins are expected to be spent at later, which decides whether spending more
+    /// inputs now is wasteful
+    pub long_term_fee_rate: u64,
+    /// Virtual size of a change output
+    pub change_output_vsize: usize,
+    /// Virtual size of the input spending the change output later
+    pub change_spend_vsize: usize,
+    /// Lowest value of a change output, below which the excess is left to the fee
+    pub dust_threshold: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// Coins chosen to fund a transaction and the change left over
+pub struct Selection {
+    pub inputs: Vec<WeightedUtxo>,
+    /// Value of the change output, zero when the excess is too small for one and goes to the fee
+    pub change: u64,
+    /// Fee of the whole transaction
+    pub fee: u64,
+    /// Cost of the selection compared to spending the same coins at the long term fee rate without
+    /// change or excess, lower being better
+    pub waste: i64,
+    pub algorithm: Algorithm,
+}
+
+/// Fee at `fee_rate` satoshis per 1000 virtual bytes for `vsize` virtual bytes, rounded up so a
+/// transaction never pays below the rate
+pub fn fee_at_rate(fee_rate: u64, vsize: usize) -> u64 {
+    (fee_rate * vsize as u64).div_ceil(1000)
+}
+
+/// Value below which `output` costs more to spend than it is worth at `dust_relay_fee`, as
+/// computed by Bitcoin Core
+pub fn dust_threshold(output: &TxOut, dust_relay_fee: u64) -> u64 {
+    if output.script_pubkey.is_unspendable() {
+        return 0;
+    }
+    // outpoint, sequence and a signature with a compressed key, discounted if in the witness
+    let input_size = match output.script_pubkey.witness_version_and_program() {
+        Some(_) => 32 + 4 + 1 + 107 / 4 + 4,
+        None => 32 + 4 + 1 + 107 + 4,
+    };
+    fee_at_rate(dust_relay_fee, output.serialise().len() + input_size)
+}
+
+/// Small xorshift generator seeded from OS randomness, for the randomised algorithms
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let mut seed = [0; 8];
+        getrandom::getrandom(&mut seed).expect("OS randomness is available");
+        Self(u64::from_le_bytes(seed) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, (self.next() % (i as u64 + 1)) as usize);
+        }
+    }
+}
+
+impl WeightedUtxo {
+    /// Fee of spending the output at `fee_rate`
+    pub fn input_fee(&self, fee_rate: u64) -> u64 {
+        fee_at_rate(fee_rate, self.input_vsize)
+    }
+
+    /// Value of the output less the fee of spending it at `fee_rate`, negative if it costs more to
+    /// spend than it is worth
+    pub fn effective_value(&self, fee_rate: u64) -> i64 {
+        self.output.value as i64 - self.input_fee(fee_rate) as i64
+    }
+}
+
+impl SelectionParams {
+    /// Effective value the inputs must cover, the target and the fee of the rest of the
+    /// transaction
+    pub fn selection_target(&self) -> u64 {
+        self.target + fee_at_rate(self.fee_rate, self.base_vsize)
+    }
+
+    /// Fee of adding a change output
+    pub fn change_fee(&self) -> u64 {
+        fee_at_rate(self.fee_rate, self.change_output_vsize)
+    }
+
+    /// Fee of adding a change output now and spending it later
+    pub fn cost_of_change(&self) -> u64 {
+        self.change_fee() + fee_at_rate(self.long_term_fee_rate, self.change_spend_vsize)
+    }
+
+    /// Lowest change worth creating, above both the dust threshold and the fee of spending it
+    pub fn min_change(&self) -> u64 {
+        self.dust_threshold
+            .max(fee_at_rate(self.long_term_fee_rate, self.change_spend_vsize) + 1)
+    }
+
+    /// Waste of spending `input` now rather than at the long term fee rate
+    fn input_waste(&self, input: &WeightedUtxo) -> i64 {
+        input.input_fee(self.fee_rate) as i64 - input.input_fee(self.long_term_fee_rate) as i64
+    }
+}
+
+impl Selection {
+    /// Selection of `inputs`, whose effective value must cover the target, adding change when
+    /// the excess is worth it
+    fn new(inputs: Vec<WeightedUtxo>, params: &SelectionParams, algorithm: Algorithm) -> Self {
+        let value = inputs.iter().map(|input| input.output.value).sum::<u64>();
+        let effective_value = inputs
+            .iter()
+            .map(|input| input.effective_value(params.fee_rate))
+            .sum::<i64>();
+        let excess = (effective_value - params.selection_target() as i64) as u64;
+        let mut waste = inputs
+            .iter()
+            .map(|input| params.input_waste(input))
+            .sum::<i64>();
+
+        let change = if excess >= params.change_fee() + params.min_change() {
+            waste += params.cost_of_change() as i64;
+            excess - params.change_fee()
+        } else {
+            waste += excess as i64;
+            0
+        };
+        let fee = value - params.target - change;
+
+        Self {
+            inputs,
+            change,
+            fee,
+            waste,
+            algorithm,
+        }
+    }
+}
+
+/// Outputs worth spending at the fee rate of `params`
+fn positive(utxos: &[WeightedUtxo], params: &SelectionParams) -> Vec<WeightedUtxo> {
+    utxos
+        .iter()
+        .filter(|utxo| utxo.effective_value(params.fee_rate) > 0)
+        .cloned()
+        .collect()
+}
+
+/// Changeless selection whose excess over the target is below the cost of change, with the least
+/// waste, found by a depth first search as in Bitcoin Core
+pub fn branch_and_bound(utxos: &[WeightedUtxo], params: &SelectionParams) -> Option<Selection> {
+    let mut pool = positive(utxos, params);
+    pool.sort_by_key(|utxo| -utxo.effective_value(params.fee_rate));
+    let values = pool
+        .iter()
+        .map(|utxo| utxo.effective_value(params.fee_rate))
+        .collect::<Vec<_>>();
+    let wastes = pool
+        .iter()
+        .map(|utxo| params.input_waste(utxo))
+        .collect::<Vec<_>>();
+
+    let target = params.selection_target() as i64;
+    let upper_bound = target + params.cost_of_change() as i64;
+    // spending more inputs only adds waste when fees are above the long term rate
+    let high_fees = params.fee_rate > params.long_term_fee_rate;
+    let mut available = values.iter().sum::<i64>();
+    if available < target {
+        return None;
+    }
+
+    let (mut selection, mut value, mut waste) = (Vec::<usize>::new(), 0, 0);
+    let mut best: Option<(Vec<usize>, i64)> = None;
+    let mut index = 0;
+    for _ in 0..BNB_TOTAL_TRIES {
+        let mut backtrack = false;
+        if value + available < target
+            || value > upper_bound
+            || best
+                .as_ref()
+                .is_some_and(|(_, best)| waste > *best && high_fees)
+        {
+            backtrack = true;
+        } else if value >= target {
+            let total = waste + value - target;
+            if best.as_ref().is_none_or(|(_, best)| total <= *best) {
+                best = Some((selection.clone(), total));
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let Some(&last) = selection.last() else {
+                break;
+            };
+            // the outputs skipped after the last included one are available again
+            index -= 1;
+            while index > last {
+                available += values[index];
+                index -= 1;
+            }
+            value -= values[index];
+            waste -= wastes[index];
+            selection.pop();
+        } else {
+            available -= values[index];
+            // excluding an output equal to an excluded one explores the same selections
+            if selection.is_empty()
+                || selection.last() == Some(&(index - 1))
+                || values[index] != values[index - 1]
+                || wastes[index] != wastes[index - 1]
+            {
+                selection.push(index);
+                value += values[index];
+                waste += wastes[index];
+            }
+        }
+        index += 1;
+    }
+
+    let (selection, _) = best?;
+    let inputs = selection.into_iter().map(|i| pool[i].clone()).collect();
+    Some(Selection::new(inputs, params, Algorithm::BranchAndBound))
+}
+
+/// Indices of the subset of `values` closest above `target` found by including them at random,
+/// and its total
+fn approximate_best_subset(rng: &mut Rng, values: &[i64], target: i64) -> (Vec<bool>, i64) {
+    let mut best = vec![true; values.len()];
+    let mut best_total = values.iter().sum::<i64>();
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_total == target {
+            break;
+        }
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached = false;
+        // the first pass includes outputs at random, the second the rest in order
+        for pass in 0..2 {
+            if reached {
+                break;
+            }
+            for (i, value) in values.iter().enumerate() {
+                let include = match pass {
+                    0 => rng.next() & 1 == 1,
+                    _ => !included[i],
+                };
+                if !include {
+                    continue;
+                }
+                total += value;
+                included[i] = true;
+                if total >= target {
+                    reached = true;
+                    if total < best_total {
+                        best_total = total;
+                        best.clone_from(&included);
+                    }
+                    total -= value;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+    (best, best_total)
+}
+
+/// Selection closest above the target plus enough for change, from the smallest output larger
+/// than that or random subsets of the smaller ones, as in Bitcoin Core's knapsack solver
+pub fn knapsack(utxos: &[WeightedUtxo], params: &SelectionParams) -> Option<Selection> {
+    let mut rng = Rng::new();
+    let mut pool = positive(utxos, params);
+    rng.shuffle(&mut pool);
+
+    let target = params.selection_target() as i64;
+    let change_target = (params.change_fee() + params.min_change()) as i64;
+    let mut smaller = vec![];
+    let mut lowest_larger: Option<WeightedUtxo> = None;
+    let mut total_smaller = 0;
+    for utxo in pool {
+        let value = utxo.effective_value(params.fee_rate);
+        if value == target {
+            return Some(Selection::new(vec![utxo], params, Algorithm::Knapsack));
+        } else if value < target + change_target {
+            total_smaller += value;
+            smaller.push(utxo);
+        } else if lowest_larger
+            .as_ref()
+            .is_none_or(|lowest| value < lowest.effective_value(params.fee_rate))
+        {
+            lowest_larger = Some(utxo);
+        }
+    }
+
+    if total_smaller == target {
+        return Some(Selection::new(smaller, params, Algorithm::Knapsack));
+    }
+    if total_smaller < target {
+        return lowest_larger.map(|utxo| Selection::new(vec![utxo], params, Algorithm::Knapsack));
+    }
+
+    smaller.sort_by_key(|utxo| -utxo.effective_value(params.fee_rate));
+    let values = smaller
+        .iter()
+        .map(|utxo| utxo.effective_value(params.fee_rate))
+        .collect::<Vec<_>>();
+    let (mut best, mut best_total) = approximate_best_subset(&mut rng, &values, target);
+    if best_total != target && total_smaller >= target + change_target {
+        (best, best_total) = approximate_best_subset(&mut rng, &values, target + change_target);
+    }
+
+    // a single larger output beats a subset leaving too little for change, or worth more
+    if let Some(lowest) = lowest_larger {
+        if (best_total != target && best_total < target + change_target)
+            || lowest.effective_value(params.fee_rate) <= best_total
+        {
+            return Some(Selection::new(vec![lowest], params, Algorithm::Knapsack));
+        }
+    }
+    let inputs = smaller
+        .into_iter()
+        .zip(best)
+        .filter_map(|(utxo, included)| included.then_some(utxo))
+        .collect();
+    Some(Selection::new(inputs, params, Algorithm::Knapsack))
+}
+
+/// Selection of outputs drawn at random until they cover the target and a change output
+pub fn single_random_draw(utxos: &[WeightedUtxo], params: &SelectionParams) -> Option<Selection> {
+    let mut pool = positive(utxos, params);
+    Rng::new().shuffle(&mut pool);
+    accumulate(pool, params, Algorithm::SingleRandomDraw)
+}
+
+/// Selection of the largest outputs until they cover the target and a change output
+pub fn largest_first(utxos: &[WeightedUtxo], params: &SelectionParams) -> Option<Selection> {
+    let mut pool = positive(utxos, params);
+    pool.sort_by_key(|utxo| -utxo.effective_value(params.fee_rate));
+    accumulate(pool, params, Algorithm::LargestFirst)
+}
+
+/// Selection of outputs of `pool` in order until they cover the target and a change output, or
+/// all of them if they only cover the target
+fn accumulate(
+    pool: Vec<WeightedUtxo>,
+    params: &SelectionParams,
+    algorithm: Algorithm,
+) -> Option<Selection> {
+    let target = params.selection_target() as i64;
+    let with_change = target + (params.change_fee() + params.min_change()) as i64;
+    let (mut inputs, mut value) = (vec![], 0);
+    for utxo in pool {
+        value += utxo.effective_value(params.fee_rate);
+        inputs.push(utxo);
+        if value >= with_change {
+            break;
+        }
+    }
+    (value >= target).then(|| Selection::new(inputs, params, algorithm))
+}
+
+/// Selection with the least waste from those of every algorithm, preferring more inputs on ties
+/// to consolidate outputs while fees are low
+pub fn select_coins(utxos: &[WeightedUtxo], params: &SelectionParams) -> Result<Selection> {
+    let available = utxos
+        .iter()
+        .map(|utxo| utxo.effective_value(params.fee_rate).max(0) as u64)
+        .sum::<u64>();
+    let target = params.selection_target();
+    if available < target {
+        bail!(WalletError::InsufficientFunds(available, target));
+    }
+
+    [
+        branch_and_bound(utxos, params),
+        knapsack(utxos, params),
+        single_random_draw(utxos, params),
+        largest_first(utxos, params),
+    ]
+    .into_iter()
+    .flatten()
+    .min_by_key(|selection| (selection.waste, usize::MAX - selection.inputs.len()))
+    .ok_or_else(|| WalletError::InsufficientFunds(available, target).into())
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::script::raw::Script};
+
+    fn utxo(value: u64, vout: u32) -> WeightedUtxo {
+        WeightedUtxo {
+            outpoint: OutPoint::new([1; 32], vout),
+            output: TxOut::new(value, Script::p2wpkh(&[0; 20])),
+            input_vsize: 68,
+        }
+    }
+
+    fn pool(values: &[u64]) -> Vec<WeightedUtxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| utxo(*value, i as u32))
+            .collect()
+    }
+
+    fn params(target: u64, fee_rate: u64) -> SelectionParams {
+        SelectionParams {
+            target,
+            base_vsize: 0,
+            fee_rate,
+            long_term_fee_rate: 10_000,
+            change_output_vsize: 31,
+            change_spend_vsize: 68,
+            dust_threshold: 294,
+        }
+    }
+
+    fn values(selection: &Selection) -> Vec<u64> {
+        let mut values = selection
+            .inputs
+            .iter()
+            .map(|input| input.output.value)
+            .collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn dust_thresholds() {
+        let p2pkh = TxOut::new(0, Script::p2pkh(&[0; 20]));
+        let p2wpkh = TxOut::new(0, Script::p2wpkh(&[0; 20]));
+        assert_eq!(dust_threshold(&p2pkh, DUST_RELAY_TX_FEE), 546);
+        assert_eq!(dust_threshold(&p2wpkh, DUST_RELAY_TX_FEE), 294);
+        assert_eq!(utxo(10_000, 0).effective_value(5000), 10_000 - 340);
+    }
+
+    #[test]
+    fn branch_and_bound_finds_changeless_solutions() {
+        // at a zero fee rate effective values are the output values
+        let utxos = pool(&[1000, 2000, 3000, 4000, 7000]);
+        let selection = branch_and_bound(&utxos, &params(6000, 0)).unwrap();
+        assert_eq!(values(&selection), vec![1000, 2000, 3000]);
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 0);
+        assert_eq!(selection.algorithm, Algorithm::BranchAndBound);
+
+        // above the long term fee rate fewer inputs waste less
+        let utxos = pool(&[2360, 3360, 4360, 5360, 8360]);
+        let selection = branch_and_bound(&utxos, &params(6000, 20_000)).unwrap();
+        assert_eq!(values(&selection), vec![3360, 5360]);
+
+        // no subset is within the cost of change of the target
+        let utxos = pool(&[4000, 7000]);
+        assert!(branch_and_bound(&utxos, &params(5000, 0)).is_none());
+        assert!(branch_and_bound(&utxos, &params(12_000, 0)).is_none());
+    }
+
+    #[test]
+    fn waste_counts_excess_or_change() {
+        let params = params(50_000, 20_000);
+        let input_waste = 1360 - 680;
+
+        let selection = Selection::new(pool(&[60_000]), &params, Algorithm::LargestFirst);
+        assert_eq!(selection.change, 60_000 - 1360 - 50_000 - 620);
+        assert_eq!(selection.fee, 1360 + 620);
+        assert_eq!(selection.waste, input_waste + 620 + 680);
+
+        // an excess too small for change goes to the fee
+        let selection = Selection::new(pool(&[51_500]), &params, Algorithm::LargestFirst);
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.fee, 1500);
+        assert_eq!(selection.waste, input_waste + 140);
+    }
+
+    #[test]
+    fn randomised_and_greedy_selections_cover_the_target() {
+        let utxos = pool(&[5000, 12_000, 30_000, 45_000, 80_000, 150_000]);
+        let params = params(100_000, 5000);
+        for selection in [
+            knapsack(&utxos, &params).unwrap(),
+            single_random_draw(&utxos, &params).unwrap(),
+            largest_first(&utxos, &params).unwrap(),
+        ] {
+            let value = selection.inputs.iter().map(|input| input.output.value);
+            assert_eq!(
+                value.sum::<u64>(),
+                params.target + selection.fee + selection.change
+            );
+            assert!(selection.change == 0 || selection.change >= params.min_change());
+        }
+        let selection = largest_first(&utxos, &params).unwrap();
+        assert_eq!(values(&selection), vec![150_000]);
+
+        // the smallest output covering the target beats a subset of smaller ones
+        let utxos = pool(&[1000, 2000, 200_000]);
+        let selection = knapsack(&utxos, &params).unwrap();
+        assert_eq!(values(&selection), vec![200_000]);
+    }
+
+    #[test]
+    fn selects_the_least_waste() -> Result<()> {
+        // an exact match avoids the cost of change
+        let utxos = pool(&[30_000, 50_000 + 340, 90_000]);
+        let selection = select_coins(&utxos, &params(50_000, 5000))?;
+        assert_eq!(values(&selection), vec![50_340]);
+        assert_eq!(selection.change, 0);
+
+        // outputs worth less than their fee are never spent
+        let utxos = pool(&[20_000, 300]);
+        assert_eq!(
+            select_coins(&utxos, &params(30_000, 5000))
+                .unwrap_err()
+                .downcast::<WalletError>()?,
+            WalletError::InsufficientFunds(19_660, 30_000)
+        );
+        Ok(())
+    }
+}
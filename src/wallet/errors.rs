@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+/// Errors of building transactions from the coins of a wallet
+pub enum WalletError {
+    #[error("insufficient funds: {0} available after fees, {1} needed")]
+    InsufficientFunds(u64, u64),
+    #[error("transaction has no recipients")]
+    NoRecipients,
+    #[error("address {0} is not for this network")]
+    WrongNetwork(String),
+    #[error("output value {0} is below the dust threshold of {1}")]
+    DustOutput(u64, u64),
+    #[error("no key can spend output script {0}")]
+    NoSigningKey(String),
+    #[error("cannot estimate the size of spending output script {0}")]
+    UnsupportedScript(String),
+    #[error("no change address or key to derive one from")]
+    NoChangeAddress,
+    #[error("block at height {0} does not connect to the scanned blocks")]
+    UnconnectedBlock(u32),
+    #[error("transaction {0} is not in the merkle block")]
+    UnmatchedTransaction(String),
+    #[error("transaction {0} is not in the wallet")]
+    UnknownTransaction(String),
+    #[error("wallet file version {0} is not supported")]
+    UnsupportedVersion(u8),
+    #[error("wallet file is for another network or descriptor")]
+    StoreMismatch,
+}
@@ -0,0 +1,4 @@
+pub mod account;
+pub mod builder;
+pub mod coinselection;
+pub mod errors;